@@ -0,0 +1,58 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+use goko::sketch::RandomProjectionSketch;
+use goko::*;
+use pointcloud::*;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const DIM: usize = 8192;
+const COUNT: usize = 2000;
+
+fn build_tree() -> CoverTreeWriter<DefaultLabeledCloud<L2>> {
+    let mut rng = SmallRng::seed_from_u64(0);
+    let data: Vec<f32> = (0..COUNT * DIM).map(|_| rng.gen::<f32>()).collect();
+    let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, DIM, vec![0; COUNT]);
+    let mut builder = CoverTreeBuilder::new();
+    builder.set_leaf_cutoff(50).set_rng_seed(0);
+    builder.build(Arc::new(point_cloud)).unwrap()
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut ct = build_tree();
+    let sketch = RandomProjectionSketch::new(ct.reader().point_cloud().as_ref(), 64, 0).unwrap();
+    ct.set_sketch(sketch);
+    let reader = ct.reader();
+    let query: Vec<f32> = reader.point_cloud().point(0).unwrap().to_vec();
+
+    c.bench_function("Exact knn 10", |b| {
+        b.iter(|| reader.knn(black_box(&&query[..]), 10))
+    });
+    c.bench_function("Sketched knn 10", |b| {
+        b.iter(|| reader.knn_sketched(black_box(&&query[..]), 10))
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
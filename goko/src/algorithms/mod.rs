@@ -0,0 +1,6 @@
+//! # Algorithms
+//!
+//! Analysis tools that work over whole trees, or sets of trees, rather than single queries.
+
+mod stability;
+pub use stability::*;
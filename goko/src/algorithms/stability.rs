@@ -0,0 +1,295 @@
+//! Bootstrap stability of the clusterings induced by the layers of a tree.
+//!
+//! Each layer of a cover tree partitions the dataset, a point belongs to the node at that scale whose subtree it
+//! ends up in. We build a reference tree on the full dataset and a tree on each bootstrap resample, then compare
+//! the partitions at each scale with the adjusted Rand index. Scales with a high mean index give clusters you can
+//! trust, scales with a low one are mostly noise from the choice of centers.
+
+use crate::covertree::CoverTreeReader;
+use crate::errors::GokoResult;
+use crate::*;
+use hashbrown::HashMap;
+use pointcloud::pc_errors::{PointCloudError, PointCloudResult};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+
+/// The stability of the partition of the dataset at a single scale.
+#[derive(Debug, Clone)]
+pub struct ScaleStability {
+    /// The scale index of the layer this is for
+    pub scale_index: i32,
+    /// The mean adjusted Rand index between the reference partition and the bootstrap partitions
+    pub mean_adjusted_rand_index: f64,
+    /// The variance of the adjusted Rand index over the bootstraps
+    pub variance_adjusted_rand_index: f64,
+}
+
+/// Builds a reference tree on the full point cloud with the builder's parameters, and `n_bootstrap` trees on
+/// resamples (with replacement) of it. Returns the stability of each scale of the reference tree, from the root down.
+///
+/// If the builder has an rng seed the resamples are deterministic.
+pub fn stability<D: PointCloud>(
+    builder: &CoverTreeBuilder,
+    point_cloud: Arc<D>,
+    n_bootstrap: usize,
+) -> GokoResult<Vec<ScaleStability>> {
+    // The readers go blank when their writer is dropped, so we hold onto the writers.
+    let reference_writer = builder.build(Arc::clone(&point_cloud))?;
+    let reference = reference_writer.reader();
+    let scale_indexes: Vec<i32> = reference
+        .layers()
+        .filter(|(_, layer)| !layer.is_empty())
+        .map(|(si, _)| si)
+        .collect();
+
+    let mut rng: SmallRng = match builder.rng_seed {
+        Some(seed) => SmallRng::seed_from_u64(seed),
+        None => SmallRng::from_entropy(),
+    };
+
+    let mut moments: Vec<(f64, f64)> = vec![(0.0, 0.0); scale_indexes.len()];
+    let len = point_cloud.len();
+    for _ in 0..n_bootstrap {
+        let indexes: Vec<usize> = (0..len).map(|_| rng.gen_range(0..len)).collect();
+        let resample = Arc::new(ResampledCloud {
+            indexes,
+            parent: Arc::clone(&point_cloud),
+        });
+        let bootstrap_writer = builder.build(Arc::clone(&resample))?;
+        let bootstrap = bootstrap_writer.reader();
+
+        // Duplicates land in the same node, so we only need one copy of each original point
+        let mut first_occurrence: HashMap<usize, usize> = HashMap::new();
+        for (i, pi) in resample.indexes.iter().enumerate() {
+            first_occurrence.entry(*pi).or_insert(i);
+        }
+        let mut reference_paths = Vec::with_capacity(first_occurrence.len());
+        let mut bootstrap_paths = Vec::with_capacity(first_occurrence.len());
+        for (pi, bi) in first_occurrence.iter() {
+            reference_paths.push(known_addresses(&reference, *pi)?);
+            bootstrap_paths.push(known_addresses(&bootstrap, *bi)?);
+        }
+
+        for (si, (m1, m2)) in scale_indexes.iter().zip(moments.iter_mut()) {
+            let reference_clusters: Vec<usize> = reference_paths
+                .iter()
+                .map(|path| cluster_at_scale(path, *si))
+                .collect();
+            let bootstrap_clusters: Vec<usize> = bootstrap_paths
+                .iter()
+                .map(|path| cluster_at_scale(path, *si))
+                .collect();
+            let ari = adjusted_rand_index(&reference_clusters, &bootstrap_clusters);
+            *m1 += ari;
+            *m2 += ari * ari;
+        }
+    }
+
+    let n = n_bootstrap.max(1) as f64;
+    Ok(scale_indexes
+        .iter()
+        .zip(moments)
+        .map(|(si, (m1, m2))| {
+            let mean = m1 / n;
+            ScaleStability {
+                scale_index: *si,
+                mean_adjusted_rand_index: mean,
+                variance_adjusted_rand_index: (m2 / n - mean * mean).max(0.0),
+            }
+        })
+        .collect())
+}
+
+fn known_addresses<D: PointCloud>(
+    reader: &CoverTreeReader<D>,
+    point_index: usize,
+) -> GokoResult<Vec<NodeAddress>> {
+    Ok(reader
+        .known_path(point_index)?
+        .iter()
+        .map(|(_, addr)| *addr)
+        .collect())
+}
+
+/// The node that covers this path at the given scale, the deepest node on the path at or above the scale.
+/// If the tree's root is below this scale then everything is in the root's cluster.
+fn cluster_at_scale(path: &[NodeAddress], scale_index: i32) -> usize {
+    path.iter()
+        .take_while(|addr| addr.0 >= scale_index)
+        .last()
+        .unwrap_or(&path[0])
+        .1
+}
+
+fn choose_2(n: usize) -> f64 {
+    (n as f64) * (n as f64 - 1.0) / 2.0
+}
+
+/// The adjusted Rand index of two clusterings of the same points, see
+/// <https://en.wikipedia.org/wiki/Rand_index#Adjusted_Rand_index>.
+/// Returns 1.0 if both clusterings are trivial (all singletons, or all one cluster).
+pub fn adjusted_rand_index(x: &[usize], y: &[usize]) -> f64 {
+    assert_eq!(x.len(), y.len());
+    if x.len() < 2 {
+        return 1.0;
+    }
+    let mut contingency: HashMap<(usize, usize), usize> = HashMap::new();
+    let mut x_sums: HashMap<usize, usize> = HashMap::new();
+    let mut y_sums: HashMap<usize, usize> = HashMap::new();
+    for (a, b) in x.iter().zip(y) {
+        *contingency.entry((*a, *b)).or_insert(0) += 1;
+        *x_sums.entry(*a).or_insert(0) += 1;
+        *y_sums.entry(*b).or_insert(0) += 1;
+    }
+    let index: f64 = contingency.values().map(|n| choose_2(*n)).sum();
+    let x_index: f64 = x_sums.values().map(|n| choose_2(*n)).sum();
+    let y_index: f64 = y_sums.values().map(|n| choose_2(*n)).sum();
+    let expected = x_index * y_index / choose_2(x.len());
+    let max_index = 0.5 * (x_index + y_index);
+    if (max_index - expected).abs() < 1.0e-12 {
+        1.0
+    } else {
+        (index - expected) / (max_index - expected)
+    }
+}
+
+/// A resample of a point cloud, the i-th point of this is the `indexes[i]`-th point of the parent.
+#[derive(Debug)]
+struct ResampledCloud<D> {
+    indexes: Vec<usize>,
+    parent: Arc<D>,
+}
+
+impl<D> ResampledCloud<D> {
+    #[inline]
+    fn parent_index(&self, pi: usize) -> PointCloudResult<usize> {
+        match self.indexes.get(pi) {
+            Some(i) => Ok(*i),
+            None => Err(PointCloudError::DataAccessError {
+                index: pi,
+                reason: "index not in resample".to_string(),
+            }),
+        }
+    }
+}
+
+impl<D: PointCloud> PointCloud for ResampledCloud<D> {
+    type Metric = D::Metric;
+    type Point = D::Point;
+    type PointRef<'a>
+        = D::PointRef<'a>
+    where
+        Self: 'a;
+    type Label = D::Label;
+    type LabelSummary = D::LabelSummary;
+    type Metadata = D::Metadata;
+    type MetaSummary = D::MetaSummary;
+
+    fn point<'a, 'b: 'a>(&'b self, pi: usize) -> PointCloudResult<Self::PointRef<'a>> {
+        self.parent.point(self.parent_index(pi)?)
+    }
+
+    fn len(&self) -> usize {
+        self.indexes.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.indexes.is_empty()
+    }
+
+    fn reference_indexes(&self) -> Vec<usize> {
+        (0..self.indexes.len()).collect()
+    }
+
+    fn dim(&self) -> usize {
+        self.parent.dim()
+    }
+
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        self.parent.label(self.parent_index(pn)?)
+    }
+
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        let parent_pns = pns
+            .iter()
+            .map(|pn| self.parent_index(*pn))
+            .collect::<PointCloudResult<Vec<usize>>>()?;
+        self.parent.label_summary(&parent_pns)
+    }
+
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        self.parent.name(self.parent_index(pi)?)
+    }
+
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        let parent_index = self.parent.index(pn)?;
+        self.indexes
+            .iter()
+            .position(|i| *i == parent_index)
+            .ok_or(PointCloudError::UnknownName)
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.indexes
+            .iter()
+            .filter_map(|i| self.parent.name(*i).ok())
+            .collect()
+    }
+
+    fn external_id(&self, pi: usize) -> PointCloudResult<Option<ExternalId>> {
+        self.parent.external_id(self.parent_index(pi)?)
+    }
+
+    fn external_index(&self, id: &ExternalId) -> PointCloudResult<usize> {
+        let parent_index = self.parent.external_index(id)?;
+        self.indexes
+            .iter()
+            .position(|i| *i == parent_index)
+            .ok_or(PointCloudError::UnknownExternalId)
+    }
+
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        self.parent.metadata(self.parent_index(pn)?)
+    }
+
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        let parent_pns = pns
+            .iter()
+            .map(|pn| self.parent_index(*pn))
+            .collect::<PointCloudResult<Vec<usize>>>()?;
+        self.parent.metasummary(&parent_pns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adjusted_rand_index_test() {
+        assert_approx_eq!(adjusted_rand_index(&[0, 0, 1, 1], &[5, 5, 3, 3]), 1.0);
+        assert!(adjusted_rand_index(&[0, 0, 1, 1], &[0, 1, 0, 1]) < 0.0);
+        assert_approx_eq!(adjusted_rand_index(&[0, 0, 0], &[1, 1, 1]), 1.0);
+    }
+
+    #[test]
+    fn stability_test() {
+        let mut data = Vec::with_capacity(40);
+        for i in 0..20 {
+            data.push(i as f32 * 0.001);
+            data.push(10.0 + i as f32 * 0.001);
+        }
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 1).unwrap());
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_rng_seed(0).set_min_res_index(-9);
+        let scores = stability(&builder, point_cloud, 3).unwrap();
+        assert!(!scores.is_empty());
+        for score in scores.iter() {
+            assert!(score.mean_adjusted_rand_index <= 1.0 + 1.0e-8);
+            assert!(score.variance_adjusted_rand_index >= 0.0);
+        }
+        // The top layer is a single cluster
+        assert_approx_eq!(scores[0].mean_adjusted_rand_index, 1.0);
+    }
+}
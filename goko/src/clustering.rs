@@ -0,0 +1,401 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Clustering
+//!
+//! Cuts a tree into disjoint clusters. Each cluster is the subtree of a node, and the clusters cover every point in
+//! the tree exactly once. A routing node's singletons go with its nested child, which shares its center, so they
+//! stay with the points closest to them when the node is cut.
+//!
+//! [`clusters_at_scale`] cuts every branch at the same scale, [`flat_clusters`] splits the widest clusters until
+//! there are enough of them. [`linkage`] exports the whole hierarchy as a dendrogram.
+
+use crate::covertree::CoverTreeReader;
+use crate::*;
+use hashbrown::HashMap;
+
+/// A partition of the points of a tree.
+#[derive(Debug, Clone)]
+pub struct FlatClusters {
+    /// The node each cluster was cut at, cluster `i` is the subtree of `nodes[i]`
+    pub nodes: Vec<NodeAddress>,
+    /// The cluster of each point in the point cloud, `None` for points that aren't in the tree
+    pub assignments: Vec<Option<usize>>,
+}
+
+impl FlatClusters {
+    /// The number of clusters
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// If there are no clusters, only true for an empty tree
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The number of points in each cluster
+    pub fn sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![0; self.nodes.len()];
+        self.assignments
+            .iter()
+            .flatten()
+            .for_each(|c| sizes[*c] += 1);
+        sizes
+    }
+
+    /// The point indexes of a cluster
+    pub fn members(&self, cluster: usize) -> Vec<usize> {
+        self.assignments
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c == Some(cluster))
+            .map(|(pi, _)| pi)
+            .collect()
+    }
+}
+
+/// Cuts the tree at a scale. A point's cluster is the deepest node on its path at or above `scale_index`, the same
+/// partition [`crate::algorithms::stability`] compares. If the root is below the scale there's one cluster.
+pub fn clusters_at_scale<D: PointCloud>(
+    reader: &CoverTreeReader<D>,
+    scale_index: i32,
+) -> FlatClusters {
+    let mut nodes = Vec::new();
+    // The singletons of the routing nodes above the cut, by center, they join the cluster of their nested child
+    let mut pending: Vec<(usize, usize)> = Vec::new();
+    let mut assignments = vec![None; reader.parameters().point_cloud.len()];
+    let mut stack = vec![reader.root_address()];
+    while let Some(address) = stack.pop() {
+        reader.get_node_and(address, |n| {
+            let is_cut = match n.children() {
+                None => true,
+                Some((nested_scale, _)) => nested_scale < scale_index,
+            };
+            if is_cut {
+                let cluster = nodes.len();
+                nodes.push(address);
+                for pi in subtree_points(reader, address) {
+                    assignments[pi] = Some(cluster);
+                }
+            } else if let Some((nested_scale, child_addresses)) = n.children() {
+                pending.extend(n.singletons().iter().map(|pi| (*pi, address.1)));
+                stack.push((nested_scale, address.1));
+                stack.extend(child_addresses);
+            }
+        });
+    }
+    let by_center: HashMap<usize, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(cluster, address)| (address.1, cluster))
+        .collect();
+    for (pi, center) in pending {
+        assignments[pi] = by_center.get(&center).copied();
+    }
+    FlatClusters { nodes, assignments }
+}
+
+/// Cuts the tree into at least `target_k` clusters, if it has enough nodes. Starting from the root, the routing
+/// node with the largest radius is split into its children until there are enough clusters. Ties go to the node
+/// that covers more points. A node splits into all of its children at once, so this can overshoot `target_k`.
+pub fn flat_clusters<D: PointCloud>(reader: &CoverTreeReader<D>, target_k: usize) -> FlatClusters {
+    // Each cluster is a node and the singletons of the split nodes above it on its nested chain
+    let mut clusters: Vec<(NodeAddress, Vec<usize>)> = vec![(reader.root_address(), Vec::new())];
+    while clusters.len() < target_k {
+        let widest = clusters
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (address, _))| {
+                reader
+                    .get_node_and(*address, |n| {
+                        if n.is_leaf() {
+                            None
+                        } else {
+                            Some((n.radius(), n.coverage_count(), i))
+                        }
+                    })
+                    .flatten()
+            })
+            .max_by(|a, b| {
+                a.0.partial_cmp(&b.0)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then(a.1.cmp(&b.1))
+            });
+        let i = match widest {
+            Some((_, _, i)) => i,
+            None => break,
+        };
+        let (address, mut extra) = clusters.swap_remove(i);
+        reader.get_node_and(address, |n| {
+            if let Some((nested_scale, child_addresses)) = n.children() {
+                extra.extend_from_slice(n.singletons());
+                clusters.push(((nested_scale, address.1), extra));
+                clusters.extend(child_addresses.iter().map(|ca| (*ca, Vec::new())));
+            }
+        });
+    }
+
+    let mut assignments = vec![None; reader.parameters().point_cloud.len()];
+    for (cluster, (address, extra)) in clusters.iter().enumerate() {
+        for pi in subtree_points(reader, *address)
+            .into_iter()
+            .chain(extra.iter().copied())
+        {
+            assignments[pi] = Some(cluster);
+        }
+    }
+    FlatClusters {
+        nodes: clusters.into_iter().map(|(address, _)| address).collect(),
+        assignments,
+    }
+}
+
+/// All the points covered by a node.
+pub(crate) fn subtree_points<D: PointCloud>(
+    reader: &CoverTreeReader<D>,
+    address: NodeAddress,
+) -> Vec<usize> {
+    let mut points = Vec::new();
+    let mut stack = vec![address];
+    while let Some(address) = stack.pop() {
+        reader.get_node_and(address, |n| {
+            points.extend_from_slice(n.singletons());
+            match n.children() {
+                None => points.push(address.1),
+                Some((nested_scale, child_addresses)) => {
+                    stack.push((nested_scale, address.1));
+                    stack.extend(child_addresses);
+                }
+            }
+        });
+    }
+    points
+}
+
+/// The tree as a dendrogram, in the format of scipy's `linkage`. The observations are the points in the tree, in the
+/// order of `point_indexes`. Every node merges its singletons, its center (if it's a leaf) and the clusters of its
+/// children at the height of its scale, `b^i`, one pair at a time. Nodes that only have a nested child don't add a
+/// merge. The rows are sorted by height, so the linkage is monotonic.
+#[derive(Debug, Clone)]
+pub struct Linkage {
+    /// The point index of each observation, observation `i` is `point_indexes[i]`
+    pub point_indexes: Vec<usize>,
+    /// The merges, `[a, b, height, count]`. Ids below the number of observations are observations, id `n + k` is
+    /// the cluster made by row `k`.
+    pub rows: Vec<[f64; 4]>,
+}
+
+impl Linkage {
+    /// The dendrogram as a Newick string, with the point indexes as the leaf names and the height differences as the
+    /// branch lengths.
+    pub fn to_newick(&self) -> String {
+        let n = self.point_indexes.len();
+        if n == 0 {
+            return ";".to_string();
+        }
+        // The subtree string and height of each cluster, taken when they're merged into their parent
+        let mut subtrees: Vec<Option<(String, f64)>> = self
+            .point_indexes
+            .iter()
+            .map(|pi| Some((pi.to_string(), 0.0)))
+            .collect();
+        for [a, b, height, _count] in &self.rows {
+            let (a, a_height) = subtrees[*a as usize].take().unwrap();
+            let (b, b_height) = subtrees[*b as usize].take().unwrap();
+            subtrees.push(Some((
+                format!("({}:{},{}:{})", a, height - a_height, b, height - b_height),
+                *height,
+            )));
+        }
+        let (root, _) = subtrees.pop().flatten().unwrap();
+        format!("{};", root)
+    }
+}
+
+/// A member of a merge, before we know how many observations there are.
+#[derive(Debug, Clone, Copy)]
+enum LinkageId {
+    Observation(usize),
+    Merge(usize),
+}
+
+/// Builds the dendrogram of the tree, see [`Linkage`].
+pub fn linkage<D: PointCloud>(reader: &CoverTreeReader<D>) -> Linkage {
+    let mut point_indexes = Vec::new();
+    let mut merges: Vec<(LinkageId, LinkageId, f64, usize)> = Vec::new();
+    link_node(
+        reader,
+        reader.root_address(),
+        &mut point_indexes,
+        &mut merges,
+    );
+
+    // Each merge has a height at least as large as the merges it's made of, and comes after them, so a stable sort
+    // keeps the children before their parents
+    let mut order: Vec<usize> = (0..merges.len()).collect();
+    order.sort_by(|a, b| {
+        merges[*a]
+            .2
+            .partial_cmp(&merges[*b].2)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let mut new_position = vec![0; merges.len()];
+    for (position, k) in order.iter().enumerate() {
+        new_position[*k] = position;
+    }
+    let n = point_indexes.len();
+    let id = |member: LinkageId| match member {
+        LinkageId::Observation(i) => i as f64,
+        LinkageId::Merge(k) => (n + new_position[k]) as f64,
+    };
+    let rows = order
+        .iter()
+        .map(|k| {
+            let (a, b, height, count) = merges[*k];
+            [id(a), id(b), height, count as f64]
+        })
+        .collect();
+    Linkage {
+        point_indexes,
+        rows,
+    }
+}
+
+/// Adds the merges of a node's subtree, returns the cluster of the whole subtree and its size.
+fn link_node<D: PointCloud>(
+    reader: &CoverTreeReader<D>,
+    address: NodeAddress,
+    point_indexes: &mut Vec<usize>,
+    merges: &mut Vec<(LinkageId, LinkageId, f64, usize)>,
+) -> Option<(LinkageId, usize)> {
+    let (mut own_points, children) = reader.get_node_and(address, |n| {
+        let children: Vec<NodeAddress> = match n.children() {
+            None => Vec::new(),
+            Some((nested_scale, child_addresses)) => std::iter::once((nested_scale, address.1))
+                .chain(child_addresses.iter().copied())
+                .collect(),
+        };
+        (n.singletons().to_vec(), children)
+    })?;
+    if children.is_empty() {
+        own_points.push(address.1);
+    }
+    let mut members: Vec<(LinkageId, usize)> = children
+        .iter()
+        .filter_map(|ca| link_node(reader, *ca, point_indexes, merges))
+        .collect();
+    for pi in own_points {
+        members.push((LinkageId::Observation(point_indexes.len()), 1));
+        point_indexes.push(pi);
+    }
+
+    let height = reader.scale(address.0) as f64;
+    let mut members = members.into_iter();
+    let first = members.next()?;
+    Some(members.fold(first, |(a, a_count), (b, b_count)| {
+        merges.push((a, b, height, a_count + b_count));
+        (LinkageId::Merge(merges.len() - 1), a_count + b_count)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    fn assert_partition<D: PointCloud>(reader: &CoverTreeReader<D>, clusters: &FlatClusters) {
+        assert!(clusters.assignments.iter().all(|c| c.is_some()));
+        let sizes = clusters.sizes();
+        for (cluster, address) in clusters.nodes.iter().enumerate() {
+            assert_eq!(sizes[cluster], clusters.members(cluster).len());
+            let coverage = reader
+                .get_node_and(*address, |n| n.coverage_count())
+                .unwrap();
+            assert!(sizes[cluster] >= coverage);
+        }
+        assert_eq!(
+            sizes.iter().sum::<usize>(),
+            reader.parameters().point_cloud.len()
+        );
+    }
+
+    #[test]
+    fn clusters_at_scale_partition_the_tree() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let scales: Vec<i32> = reader.layers().map(|(si, _)| si).collect();
+        let top = clusters_at_scale(&reader, i32::MAX);
+        assert_eq!(top.len(), 1);
+        assert_partition(&reader, &top);
+        let mut last_len = 1;
+        for si in scales {
+            let clusters = clusters_at_scale(&reader, si);
+            assert_partition(&reader, &clusters);
+            assert!(clusters.len() >= last_len);
+            last_len = clusters.len();
+        }
+    }
+
+    #[test]
+    fn flat_clusters_reach_the_target() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let one = flat_clusters(&reader, 1);
+        assert_eq!(one.len(), 1);
+        assert_eq!(one.nodes[0], reader.root_address());
+        assert_partition(&reader, &one);
+
+        let two = flat_clusters(&reader, 2);
+        assert!(two.len() >= 2);
+        assert_partition(&reader, &two);
+
+        let all = flat_clusters(&reader, 100);
+        assert_partition(&reader, &all);
+    }
+
+    #[test]
+    fn linkage_is_a_valid_dendrogram() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let linkage = linkage(&reader);
+        let n = linkage.point_indexes.len();
+        assert_eq!(n, 5);
+        assert_eq!(linkage.rows.len(), n - 1);
+        let mut used = vec![false; 2 * n - 1];
+        let mut last_height = 0.0;
+        for (k, [a, b, height, count]) in linkage.rows.iter().enumerate() {
+            for id in &[*a as usize, *b as usize] {
+                assert!(*id < n + k);
+                assert!(!used[*id]);
+                used[*id] = true;
+            }
+            assert!(*height >= last_height);
+            last_height = *height;
+            assert!(*count >= 2.0);
+        }
+        assert_approx_eq!(linkage.rows[n - 2][3], 5.0);
+
+        let newick = linkage.to_newick();
+        assert!(newick.ends_with(';'));
+        for pi in 0..5 {
+            assert!(newick.contains(&pi.to_string()));
+        }
+    }
+}
@@ -0,0 +1,105 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Distance Accelerators
+//!
+//! The singleton scan at the bottom of a knn query is a batch of distances from the query to a list of points. On
+//! trees with large leaves these batches are big enough that a GPU matrix multiply beats the CPU. Implement
+//! [`DistanceAccelerator`] over your device copy of the data and attach it with
+//! [`crate::CoverTreeWriter::set_accelerator`]. The knn, and so the bulk knn of
+//! [`crate::query_interface::BulkInterface`], hands every batch of at least [`DistanceAccelerator::min_batch_size`]
+//! points to it. The smaller batches and the rest of the tree logic stay on the CPU.
+
+use crate::errors::GokoResult;
+use pointcloud::*;
+use std::fmt::Debug;
+
+/// The default smallest batch that is sent to an accelerator.
+pub const DEFAULT_MIN_BATCH_SIZE: usize = 1024;
+
+/// Computes batches of distances for the tree, usually on a GPU. The distances have to agree with the point cloud's
+/// metric, or the knn stops being exact.
+pub trait DistanceAccelerator<D: PointCloud>: Send + Sync + Debug + 'static {
+    /// The distances from the query point to the points at the indexes, in the order of the indexes.
+    fn distances_to_point(&self, point: &D::Point, indexes: &[usize]) -> GokoResult<Vec<f32>>;
+    /// Batches smaller than this are computed on the CPU, the transfer to the device isn't worth it for them.
+    fn min_batch_size(&self) -> usize {
+        DEFAULT_MIN_BATCH_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::{random_cloud, random_tree_builder};
+    use crate::covertree::*;
+    use crate::query_interface::BulkInterface;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Computes on the CPU, but counts the batches it was given.
+    #[derive(Debug)]
+    struct CountingAccelerator {
+        point_cloud: Arc<DefaultLabeledCloud<L2>>,
+        batches: Arc<AtomicUsize>,
+    }
+
+    impl DistanceAccelerator<DefaultLabeledCloud<L2>> for CountingAccelerator {
+        fn distances_to_point(&self, point: &[f32], indexes: &[usize]) -> GokoResult<Vec<f32>> {
+            self.batches.fetch_add(1, Ordering::SeqCst);
+            Ok(self.point_cloud.distances_to_point(&point, indexes)?)
+        }
+        fn min_batch_size(&self) -> usize {
+            10
+        }
+    }
+
+    #[test]
+    fn accelerated_knn_is_exact() {
+        let point_cloud = Arc::new(random_cloud(2000, 4));
+        let mut builder = random_tree_builder();
+        builder.leaf_cutoff = 50;
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+        let queries: Vec<Vec<f32>> = (0..20)
+            .map(|_| (0..4).map(|_| rng.gen::<f32>()).collect())
+            .collect();
+        let queries: Vec<&[f32]> = queries.iter().map(|q| &q[..]).collect();
+        let expected: Vec<Vec<(f32, usize)>> = BulkInterface::new(tree.reader())
+            .knn(&queries, 5)
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+
+        let batches = Arc::new(AtomicUsize::new(0));
+        tree.set_accelerator(CountingAccelerator {
+            point_cloud,
+            batches: Arc::clone(&batches),
+        });
+        let accelerated: Vec<Vec<(f32, usize)>> = BulkInterface::new(tree.reader())
+            .knn(&queries, 5)
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(accelerated, expected);
+        assert!(batches.load(Ordering::SeqCst) > 0);
+    }
+}
@@ -0,0 +1,176 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Query Budgets
+//!
+//! A knn query opens up every node that could hold a closer point than the ones it has, and on a bad query point in
+//! a high dimensional cloud that can be most of the tree. A [`QueryBudget`] caps the number of nodes the query opens
+//! and the time it runs for. Once either runs out the query stops where it is and returns the neighbors it found,
+//! flagged as truncated. The neighbors of a truncated query are real points, but there may be closer ones.
+
+use super::*;
+use crate::errors::GokoResult;
+use crate::query_tools::KnnQueryHeap;
+use pointcloud::*;
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+use std::time::{Duration, Instant};
+
+/// Limits on the work a single query does. The default has no limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryBudget {
+    /// The most nodes the query opens up, either to look at their children or their singletons
+    pub max_nodes: Option<usize>,
+    /// The longest the query runs for
+    pub max_time: Option<Duration>,
+}
+
+impl QueryBudget {
+    /// If this doesn't limit anything
+    pub fn is_unlimited(&self) -> bool {
+        self.max_nodes.is_none() && self.max_time.is_none()
+    }
+}
+
+/// The result of a query run on a budget.
+#[derive(Debug, Clone)]
+pub struct BudgetedResult<T> {
+    /// What the query found before it stopped
+    pub result: T,
+    /// If the budget ran out before the query finished
+    pub truncated: bool,
+    /// The number of nodes the query opened up
+    pub nodes_visited: usize,
+}
+
+/// Keeps track of what a query has spent.
+#[derive(Debug)]
+pub(crate) struct BudgetMeter {
+    max_nodes: Option<usize>,
+    deadline: Option<Instant>,
+    nodes_visited: usize,
+    exhausted: bool,
+}
+
+impl BudgetMeter {
+    pub(crate) fn new(budget: &QueryBudget) -> BudgetMeter {
+        BudgetMeter {
+            max_nodes: budget.max_nodes,
+            deadline: budget.max_time.map(|t| Instant::now() + t),
+            nodes_visited: 0,
+            exhausted: false,
+        }
+    }
+
+    pub(crate) fn unlimited() -> BudgetMeter {
+        BudgetMeter::new(&QueryBudget::default())
+    }
+
+    /// Spends a node, returns false if the budget has run out and the node shouldn't be opened.
+    pub(crate) fn visit(&mut self) -> bool {
+        if !self.exhausted {
+            let out_of_nodes = self
+                .max_nodes
+                .map(|m| self.nodes_visited >= m)
+                .unwrap_or(false);
+            let out_of_time = self.deadline.map(|d| Instant::now() >= d).unwrap_or(false);
+            self.exhausted = out_of_nodes || out_of_time;
+        }
+        if !self.exhausted {
+            self.nodes_visited += 1;
+        }
+        !self.exhausted
+    }
+
+    fn finish<T>(self, result: T) -> BudgetedResult<T> {
+        BudgetedResult {
+            result,
+            truncated: self.exhausted,
+            nodes_visited: self.nodes_visited,
+        }
+    }
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// The knn of the point, giving up once the budget runs out. See the [module docs](self).
+    pub fn knn_budgeted<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+        budget: &QueryBudget,
+    ) -> GokoResult<BudgetedResult<Vec<(f32, usize)>>> {
+        let mut meter = BudgetMeter::new(budget);
+        let knn = self.knn_with_heap(
+            point,
+            KnnQueryHeap::new(k, self.parameters().scale_base),
+            &mut meter,
+        )?;
+        Ok(meter.finish(knn))
+    }
+
+    /// The routing knn of the point, giving up once the budget runs out. See the [module docs](self).
+    pub fn routing_knn_budgeted<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+        budget: &QueryBudget,
+    ) -> GokoResult<BudgetedResult<Vec<(f32, usize)>>> {
+        let mut meter = BudgetMeter::new(budget);
+        let knn = self.routing_knn_with_meter(point, k, &mut meter)?;
+        Ok(meter.finish(knn))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn unlimited_budget_matches_knn() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let point = [0.1f32];
+        let budgeted = reader
+            .knn_budgeted(&&point[..], 3, &QueryBudget::default())
+            .unwrap();
+        assert!(!budgeted.truncated);
+        assert!(budgeted.nodes_visited > 0);
+        assert_eq!(budgeted.result, reader.knn(&&point[..], 3).unwrap());
+    }
+
+    #[test]
+    fn node_budget_truncates() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let point = [0.1f32];
+        let budget = QueryBudget {
+            max_nodes: Some(1),
+            max_time: None,
+        };
+        let budgeted = reader.knn_budgeted(&&point[..], 5, &budget).unwrap();
+        assert!(budgeted.truncated);
+        assert_eq!(budgeted.nodes_visited, 1);
+
+        let routing = reader
+            .routing_knn_budgeted(&&point[..], 5, &budget)
+            .unwrap();
+        assert!(routing.nodes_visited <= 1);
+    }
+}
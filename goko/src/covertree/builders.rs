@@ -19,22 +19,24 @@
 use super::data_caches::*;
 use super::layer::*;
 use super::node::*;
+use super::progress::*;
+use super::tree::address_stream;
 use super::*;
-use crate::plugins::TreePluginSet;
+use crate::plugins::{PluginAttachments, TreePluginSet};
 use crate::*;
-use pbr::ProgressBar;
-use rand::rngs::SmallRng;
-use rand::SeedableRng;
 use std::cmp::{max, min};
 use std::fs::read_to_string;
 use std::path::Path;
 use std::sync::{atomic, Arc, RwLock};
 use yaml_rust::YamlLoader;
 
-use crossbeam_channel::{unbounded, Receiver, Sender};
-use errors::GokoResult;
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use errors::{GokoError, GokoResult};
+use hashbrown::HashMap;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
 use std::time::Instant;
+use tracing::{info, info_span};
 
 #[derive(Debug)]
 struct BuilderNode {
@@ -43,7 +45,21 @@ struct BuilderNode {
     covered: CoveredData,
 }
 
+/// Runs the job on the build's own pool if it has one, and on the current rayon pool otherwise. The jobs a job spawns
+/// stay on the pool that runs it.
+fn spawn_on<F: FnOnce() + Send + 'static>(pool: Option<&ThreadPool>, job: F) {
+    match pool {
+        Some(pool) => pool.spawn(job),
+        None => rayon::spawn(job),
+    }
+}
+
 type NodeSplitResult<D> = GokoResult<(i32, usize, CoverNode<D>)>;
+/// The bytes the split node held, and the node with its children if it worked.
+type BoundedSplitResult<D> = (
+    usize,
+    GokoResult<(i32, usize, CoverNode<D>, Vec<BuilderNode>)>,
+);
 
 impl BuilderNode {
     fn new<D: PointCloud>(
@@ -58,7 +74,17 @@ impl BuilderNode {
                 CoveredData::FirstCoveredData(FirstCoveredData::new::<D>(&parameters.point_cloud)?)
             }
         };
-        let scale_index = (covered.max_distance()).log(parameters.scale_base).ceil() as i32;
+        // A single point, or a cloud of identical points, has no meaningful scale. Clamp it to the
+        // bottom of the tree so that we build a single leaf.
+        let max_distance = covered.max_distance();
+        let scale_index = if max_distance > 0.0 {
+            max(
+                max_distance.log(parameters.scale_base).ceil() as i32,
+                parameters.min_res_index,
+            )
+        } else {
+            parameters.min_res_index
+        };
         Ok(BuilderNode {
             parent_address: None,
             scale_index,
@@ -75,23 +101,51 @@ impl BuilderNode {
         self,
         parameters: &Arc<CoverTreeParameters<D>>,
         node_sender: &Arc<Sender<NodeSplitResult<D>>>,
+        pool: Option<&ThreadPool>,
     ) {
         let parameters = Arc::clone(parameters);
         let node_sender = Arc::clone(node_sender);
-        rayon::spawn(move || {
+        spawn_on(pool, move || {
             let (si, pi) = self.address();
             match self.split(&parameters) {
                 Ok((new_node, mut new_nodes)) => {
-                    node_sender.send(Ok((si, pi, new_node))).unwrap();
+                    // The builder stops listening if the build was cancelled
+                    if node_sender.send(Ok((si, pi, new_node))).is_err() {
+                        return;
+                    }
                     while let Some(node) = new_nodes.pop() {
-                        node.split_parallel(&parameters, &node_sender);
+                        // Already on the pool the build runs on
+                        node.split_parallel(&parameters, &node_sender, None);
                     }
                 }
-                Err(e) => node_sender.send(Err(e)).unwrap(),
+                Err(e) => {
+                    let _ = node_sender.send(Err(e));
+                }
             };
         });
     }
 
+    /// Splits the node on the pool but hands the children back rather than splitting them too, so the builder
+    /// decides when they're split.
+    fn split_bounded<D: PointCloud>(
+        self,
+        parameters: &Arc<CoverTreeParameters<D>>,
+        node_sender: &Sender<BoundedSplitResult<D>>,
+        pool: Option<&ThreadPool>,
+    ) {
+        let parameters = Arc::clone(parameters);
+        let node_sender = node_sender.clone();
+        spawn_on(pool, move || {
+            let bytes = self.covered.estimated_bytes();
+            let (si, pi) = self.address();
+            let result = self
+                .split(&parameters)
+                .map(|(new_node, new_nodes)| (si, pi, new_node, new_nodes));
+            // The builder stops listening if an earlier split failed
+            let _ = node_sender.send((bytes, result));
+        });
+    }
+
     fn split<D: PointCloud>(
         self,
         parameters: &Arc<CoverTreeParameters<D>>,
@@ -106,6 +160,7 @@ impl BuilderNode {
         */
         let mut new_nodes = if self.covered.len() <= parameters.leaf_cutoff
             || scale_index < parameters.min_res_index
+            || radius == 0.0
         {
             //println!("== This is getting cut down by parameters ==");
             node.insert_singletons(self.covered.into_indexes());
@@ -156,13 +211,14 @@ impl BuilderNode {
         split_scale_index: i32,
         parameters: &Arc<CoverTreeParameters<D>>,
     ) -> GokoResult<Vec<BuilderNode>> {
-        let mut small_rng: SmallRng = match parameters.rng_seed {
-            Some(seed) => SmallRng::seed_from_u64(seed ^ parent_address.1 as u64),
-            None => SmallRng::from_entropy(),
-        };
+        let mut small_rng = parameters.stream_rng(RngDomain::Build, address_stream(parent_address));
         let next_scale = parameters.scale_base.powi(split_scale_index);
-        let (nested_potential, mut splits) =
-            covered.split(next_scale, &parameters.point_cloud, &mut small_rng)?;
+        let (nested_potential, mut splits) = covered.split(
+            next_scale,
+            parameters.label_candidates,
+            &parameters.point_cloud,
+            &mut small_rng,
+        )?;
         let mut new_nodes = Vec::new();
 
         let mut inserts = Vec::new();
@@ -213,10 +269,7 @@ impl BuilderNode {
         split_scale_index: i32,
         parameters: &Arc<CoverTreeParameters<D>>,
     ) -> GokoResult<Vec<BuilderNode>> {
-        let mut small_rng: SmallRng = match parameters.rng_seed {
-            Some(seed) => SmallRng::seed_from_u64(seed ^ parent_address.1 as u64),
-            None => SmallRng::from_entropy(),
-        };
+        let mut small_rng = parameters.stream_rng(RngDomain::Build, address_stream(parent_address));
         let mut new_nodes = Vec::new();
 
         let next_scale = parameters.scale_base.powi(split_scale_index);
@@ -288,16 +341,50 @@ impl BuilderNode {
     }
 }
 
+/// Splits the points of a node of a built tree into a subtree, the same way the build would. The node itself comes
+/// back first, followed by its descendants. If the points don't need splitting that's the only node.
+pub(crate) fn build_subtree<D: PointCloud>(
+    parameters: &Arc<CoverTreeParameters<D>>,
+    parent_address: Option<NodeAddress>,
+    address: NodeAddress,
+    coverage: Vec<usize>,
+) -> GokoResult<Vec<CoverNode<D>>> {
+    let covered = CoveredData::around(
+        parameters.partition_type,
+        address.1,
+        coverage,
+        &parameters.point_cloud,
+    )?;
+    let mut pending = vec![BuilderNode {
+        parent_address,
+        scale_index: address.0,
+        covered,
+    }];
+    let mut nodes = Vec::new();
+    while let Some(builder_node) = pending.pop() {
+        let (node, children) = builder_node.split(parameters)?;
+        nodes.push(node);
+        pending.extend(children);
+    }
+    Ok(nodes)
+}
+
 /// A construction object for a covertree. See [`crate::covertree::CoverTreeParameters`] for docs
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CoverTreeBuilder {
     pub(crate) scale_base: f32,
     pub(crate) leaf_cutoff: usize,
     pub(crate) min_res_index: i32,
     pub(crate) use_singletons: bool,
     pub(crate) partition_type: PartitionType,
+    pub(crate) label_candidates: usize,
     pub(crate) verbosity: u32,
     pub(crate) rng_seed: Option<u64>,
+    pub(crate) memory_budget: Option<usize>,
+    pub(crate) num_threads: Option<usize>,
+    pub(crate) max_in_flight: Option<usize>,
+    pub(crate) progress: Option<ProgressHook>,
+    pub(crate) cancellation: Option<CancellationToken>,
 }
 
 impl Default for CoverTreeBuilder {
@@ -308,8 +395,14 @@ impl Default for CoverTreeBuilder {
             min_res_index: -10,
             use_singletons: true,
             partition_type: PartitionType::Nearest,
+            label_candidates: 1,
             verbosity: 0,
             rng_seed: None,
+            memory_budget: None,
+            num_threads: None,
+            max_in_flight: None,
+            progress: None,
+            cancellation: None,
         }
     }
 }
@@ -323,8 +416,14 @@ impl CoverTreeBuilder {
             min_res_index: -10,
             use_singletons: true,
             partition_type: PartitionType::Nearest,
+            label_candidates: 1,
             verbosity: 0,
             rng_seed: None,
+            memory_budget: None,
+            num_threads: None,
+            max_in_flight: None,
+            progress: None,
+            cancellation: None,
         }
     }
 
@@ -344,8 +443,14 @@ impl CoverTreeBuilder {
             min_res_index: params["min_res_index"].as_i64().unwrap_or(-10) as i32,
             use_singletons: params["use_singletons"].as_bool().unwrap_or(true),
             partition_type,
+            label_candidates: params["label_candidates"].as_i64().unwrap_or(1) as usize,
             verbosity: params["verbosity"].as_i64().unwrap_or(2) as u32,
             rng_seed: params["verbosity"].as_i64().map(|i| i as u64),
+            memory_budget: params["memory_budget"].as_i64().map(|i| i as usize),
+            num_threads: params["num_threads"].as_i64().map(|i| i as usize),
+            max_in_flight: params["max_in_flight"].as_i64().map(|i| i as usize),
+            progress: None,
+            cancellation: None,
         }
     }
 
@@ -370,6 +475,11 @@ impl CoverTreeBuilder {
         self
     }
     /// See [`crate::covertree::CoverTreeParameters`] for docs
+    pub fn set_label_candidates(&mut self, x: usize) -> &mut Self {
+        self.label_candidates = x;
+        self
+    }
+    /// See [`crate::covertree::CoverTreeParameters`] for docs
     pub fn set_verbosity(&mut self, x: u32) -> &mut Self {
         self.verbosity = x;
         self
@@ -379,20 +489,87 @@ impl CoverTreeBuilder {
         self.rng_seed = Some(x);
         self
     }
+    /// Builds in memory budget mode, for machines that can't hold the whole build in RAM. The parallel build
+    /// splits every node it can as soon as it can, so the nodes waiting to be split and their copies of the
+    /// covered indexes pile up. With a budget the nodes are split depth first, and a node is only handed to the
+    /// thread pool if the estimated bytes of the nodes being split stay under `max_resident_bytes`. At least one
+    /// node is always being split, so a budget that's too small builds one node at a time rather than failing.
+    ///
+    /// The estimate only counts the covered index and distance buffers, not the tree itself or the point cloud.
+    /// The tree built is the same as without a budget.
+    pub fn set_memory_budget(&mut self, max_resident_bytes: usize) -> &mut Self {
+        self.memory_budget = Some(max_resident_bytes);
+        self
+    }
+    /// Builds on a pool of its own with this many threads, rather than on the global rayon pool. Two trees built at
+    /// once in one process then don't compete for the same threads, and the build can be kept off some of the cores.
+    pub fn set_num_threads(&mut self, num_threads: usize) -> &mut Self {
+        self.num_threads = Some(num_threads);
+        self
+    }
+    /// Caps the number of nodes being split at once. Without a cap every node is handed to the thread pool as soon as
+    /// its parent is split, so on a machine with many cores the nodes waiting to be split can pile up. With a cap the
+    /// nodes wait with the builder until one of the splits finishes, like they do with
+    /// [`Self::set_memory_budget`]. The tree built is the same as without a cap.
+    pub fn set_max_in_flight(&mut self, max_in_flight: usize) -> &mut Self {
+        self.max_in_flight = Some(max_in_flight.max(1));
+        self
+    }
+    /// Reports the build's progress to the callback, see [`BuildProgress`]. This replaces the progress bar a verbose
+    /// build draws.
+    pub fn set_progress<P: BuildProgress + 'static>(&mut self, progress: P) -> &mut Self {
+        self.progress = Some(ProgressHook(Arc::new(progress)));
+        self
+    }
+    /// Stops the build with [`GokoError::BuildCancelled`] once the token is cancelled. The nodes being split when
+    /// it's cancelled are finished on the pool and thrown away.
+    pub fn set_cancellation(&mut self, token: CancellationToken) -> &mut Self {
+        self.cancellation = Some(token);
+        self
+    }
     /// Pass a point cloud object when ready.
     /// To do, make this point cloud an Arc
     pub fn build<D: PointCloud>(&self, point_cloud: Arc<D>) -> GokoResult<CoverTreeWriter<D>> {
+        if point_cloud.is_empty() {
+            return Err(GokoError::EmptyPointCloud);
+        }
+        let span = info_span!(
+            "build",
+            points = point_cloud.len(),
+            dim = point_cloud.dim(),
+            scale_base = self.scale_base,
+            leaf_cutoff = self.leaf_cutoff,
+            min_res_index = self.min_res_index,
+        );
+        let _build = span.enter();
+        let pool = match self.num_threads {
+            Some(num_threads) => Some(
+                ThreadPoolBuilder::new()
+                    .num_threads(num_threads)
+                    .build()
+                    .map_err(|e| GokoError::ThreadPoolError(e.to_string()))?,
+            ),
+            None => None,
+        };
+        let pool = pool.as_ref();
         let parameters = CoverTreeParameters {
             total_nodes: atomic::AtomicUsize::new(1),
+            epoch: atomic::AtomicU64::new(0),
             scale_base: self.scale_base,
             leaf_cutoff: self.leaf_cutoff,
             min_res_index: self.min_res_index,
             use_singletons: self.use_singletons,
             partition_type: self.partition_type,
+            label_candidates: self.label_candidates,
             point_cloud,
             verbosity: self.verbosity,
             rng_seed: self.rng_seed,
             plugins: RwLock::new(TreePluginSet::new()),
+            plugin_attachments: RwLock::new(PluginAttachments::new()),
+            plugin_sizers: RwLock::new(HashMap::new()),
+            plugin_copiers: RwLock::new(HashMap::new()),
+            sketch: RwLock::new(None),
+            accelerator: RwLock::new(None),
         };
 
         let root = BuilderNode::new(&parameters, self.partition_type)?;
@@ -404,18 +581,17 @@ impl CoverTreeBuilder {
             layers.push(CoverLayerWriter::new(parameters.min_res_index + i as i32));
         }
 
-        let (node_sender, node_receiver): (
-            Sender<NodeSplitResult<D>>,
-            Receiver<NodeSplitResult<D>>,
-        ) = unbounded();
-
-        let node_sender = Arc::new(node_sender);
         let parameters = Arc::new(parameters);
-        root.split_parallel(&parameters, &node_sender);
-        let mut pb = ProgressBar::new(1u64);
-        if parameters.verbosity > 1 {
-            pb.format("╢▌▌░╟");
-        }
+        let progress: Option<Arc<dyn BuildProgress>> = match &self.progress {
+            Some(hook) => Some(Arc::clone(&hook.0)),
+            None if parameters.verbosity > 1 => Some(Arc::new(ProgressBarProgress::new())),
+            None => None,
+        };
+        let cancelled = || {
+            self.cancellation
+                .as_ref()
+                .map_or(false, |token| token.is_cancelled())
+        };
 
         let (_final_addresses_reader, final_addresses) = monomap::new();
 
@@ -424,50 +600,143 @@ impl CoverTreeBuilder {
             layers,
             root_address,
             final_addresses,
+            checkpoint_version: None,
+            plugin_hooks: HashMap::new(),
+            plugin_builders: HashMap::new(),
+            defer_refresh: false,
         };
 
         let mut inserted_nodes: usize = 0;
         let now = Instant::now();
-        loop {
-            if let Ok(res) = node_receiver.recv() {
-                let (scale_index, point_index, new_node) = res.unwrap();
-                for singleton in new_node.singletons() {
-                    cover_tree
-                        .final_addresses
-                        .insert(*singleton, (scale_index, point_index));
-                }
-                if new_node.is_leaf() {
-                    cover_tree
-                        .final_addresses
-                        .insert(point_index, (scale_index, point_index));
-                }
-                unsafe {
-                    cover_tree.insert_raw(scale_index, point_index, new_node);
+        let mut last_report = now;
+        let split_span = info_span!("split_nodes");
+        let split = split_span.enter();
+        let mut insert_node = |scale_index: i32, point_index: usize, new_node: CoverNode<D>| {
+            for singleton in new_node.singletons() {
+                cover_tree
+                    .final_addresses
+                    .insert(*singleton, (scale_index, point_index));
+            }
+            if new_node.is_leaf() {
+                cover_tree
+                    .final_addresses
+                    .insert(point_index, (scale_index, point_index));
+            }
+            unsafe {
+                cover_tree.insert_raw(scale_index, point_index, new_node);
+            }
+            inserted_nodes += 1;
+            if let Some(progress) = &progress {
+                if last_report.elapsed() >= PROGRESS_INTERVAL {
+                    let nodes_created = parameters.total_nodes.load(atomic::Ordering::SeqCst);
+                    progress.on_progress(nodes_created, inserted_nodes, now.elapsed());
+                    last_report = Instant::now();
                 }
-                inserted_nodes += 1;
-                if parameters.verbosity > 1 {
-                    pb.total = parameters.total_nodes.load(atomic::Ordering::SeqCst) as u64;
-                    pb.inc();
+            }
+        };
+        match (self.memory_budget, self.max_in_flight) {
+            (None, None) => {
+                let (node_sender, node_receiver): (
+                    Sender<NodeSplitResult<D>>,
+                    Receiver<NodeSplitResult<D>>,
+                ) = unbounded();
+
+                let node_sender = Arc::new(node_sender);
+                root.split_parallel(&parameters, &node_sender, pool);
+                let mut received_nodes: usize = 0;
+                loop {
+                    if cancelled() {
+                        return Err(GokoError::BuildCancelled);
+                    }
+                    if let Ok(res) = node_receiver.recv() {
+                        let (scale_index, point_index, new_node) = res.unwrap();
+                        insert_node(scale_index, point_index, new_node);
+                        received_nodes += 1;
+                    }
+                    // Stop if there are enough done, and there are no more outstanding parameter references
+                    if received_nodes == parameters.total_nodes.load(atomic::Ordering::SeqCst) {
+                        break;
+                    }
                 }
             }
-            // Stop if there are enough done, and there are no more outstanding parameter references
-            if inserted_nodes == parameters.total_nodes.load(atomic::Ordering::SeqCst) {
-                break;
+            (memory_budget, max_in_flight) => {
+                let max_resident_bytes = memory_budget.unwrap_or(usize::MAX);
+                let max_splitting = max_in_flight.unwrap_or(usize::MAX);
+                let num_threads = pool
+                    .map(|pool| pool.current_num_threads())
+                    .unwrap_or_else(rayon::current_num_threads);
+                // Bounded, so the pool waits on us rather than piling finished nodes up in the channel
+                let (node_sender, node_receiver): (
+                    Sender<BoundedSplitResult<D>>,
+                    Receiver<BoundedSplitResult<D>>,
+                ) = bounded(num_threads);
+
+                let mut pending = vec![root];
+                let mut splitting: usize = 0;
+                let mut splitting_bytes: usize = 0;
+                let mut peak_bytes: usize = 0;
+                loop {
+                    if cancelled() {
+                        return Err(GokoError::BuildCancelled);
+                    }
+                    // Depth first, the newest nodes are the smallest and finishing them frees their buffers
+                    while let Some(node) = pending.pop() {
+                        let bytes = node.covered.estimated_bytes();
+                        if splitting > 0
+                            && (splitting >= max_splitting
+                                || splitting_bytes + bytes > max_resident_bytes)
+                        {
+                            pending.push(node);
+                            break;
+                        }
+                        splitting += 1;
+                        splitting_bytes += bytes;
+                        node.split_bounded(&parameters, &node_sender, pool);
+                    }
+                    if splitting == 0 {
+                        break;
+                    }
+                    peak_bytes = max(peak_bytes, splitting_bytes);
+
+                    let (bytes, res) = node_receiver.recv().unwrap();
+                    splitting -= 1;
+                    splitting_bytes -= bytes;
+                    let (scale_index, point_index, new_node, new_nodes) = res?;
+                    insert_node(scale_index, point_index, new_node);
+                    pending.extend(new_nodes.into_iter().map(|mut node| {
+                        node.covered.shrink_to_fit();
+                        node
+                    }));
+                }
+                info!(
+                    max_resident_bytes = ?memory_budget,
+                    max_in_flight = ?max_in_flight,
+                    peak_bytes,
+                    "Split all nodes within the limits"
+                );
             }
         }
-        if parameters.verbosity > 1 {
-            println!("\nWriting layers...");
-        }
-        cover_tree.refresh();
-        cover_tree.final_addresses.refresh();
-        cover_tree.final_addresses.refresh();
-        if parameters.verbosity > 1 {
-            println!(
-                "Finished building, took {:?} with {} per second",
-                now.elapsed(),
-                (inserted_nodes as f32) / now.elapsed().as_secs_f32()
-            );
+        drop(split);
+        if let Some(progress) = &progress {
+            progress.on_progress(inserted_nodes, inserted_nodes, now.elapsed());
         }
+        info!(
+            nodes = inserted_nodes,
+            elapsed_ms = now.elapsed().as_millis() as u64,
+            "Split all nodes"
+        );
+        info_span!("write_layers").in_scope(|| {
+            cover_tree.refresh();
+            cover_tree.final_addresses.refresh();
+            cover_tree.final_addresses.refresh();
+        });
+        info!(
+            nodes = inserted_nodes,
+            layers = cover_tree.layers.len(),
+            elapsed_ms = now.elapsed().as_millis() as u64,
+            nodes_per_second = (inserted_nodes as f32) / now.elapsed().as_secs_f32(),
+            "Finished building"
+        );
         Ok(cover_tree)
     }
 }
@@ -475,6 +744,8 @@ impl CoverTreeBuilder {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
     use std::{thread, time};
 
     pub fn create_test_parameters(
@@ -484,15 +755,22 @@ mod tests {
         let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, data_dim).unwrap());
         Arc::new(CoverTreeParameters {
             total_nodes: atomic::AtomicUsize::new(1),
+            epoch: atomic::AtomicU64::new(0),
             scale_base: 2.0,
             leaf_cutoff: 0,
             min_res_index: -9,
             use_singletons: true,
             partition_type: PartitionType::Nearest,
+            label_candidates: 1,
             point_cloud,
             verbosity: 0,
             rng_seed: Some(0),
             plugins: RwLock::new(TreePluginSet::new()),
+            plugin_attachments: RwLock::new(PluginAttachments::new()),
+            plugin_sizers: RwLock::new(HashMap::new()),
+            plugin_copiers: RwLock::new(HashMap::new()),
+            sketch: RwLock::new(None),
+            accelerator: RwLock::new(None),
         })
     }
 
@@ -584,7 +862,7 @@ mod tests {
         ) = unbounded();
         let node_sender = Arc::new(node_sender);
 
-        build_node.split_parallel(&test_parameters, &node_sender);
+        build_node.split_parallel(&test_parameters, &node_sender, None);
         thread::sleep(time::Duration::from_millis(100));
         let split_count = test_parameters.total_nodes.load(atomic::Ordering::SeqCst) - 1;
         println!(
@@ -621,7 +899,7 @@ mod tests {
         ) = unbounded();
         let node_sender = Arc::new(node_sender);
 
-        build_node.split_parallel(&test_parameters, &node_sender);
+        build_node.split_parallel(&test_parameters, &node_sender, None);
         thread::sleep(time::Duration::from_millis(100));
         let split_count = test_parameters.total_nodes.load(atomic::Ordering::SeqCst) - 1;
         println!(
@@ -658,7 +936,13 @@ mod tests {
             use_singletons: true,
             verbosity: 0,
             partition_type: PartitionType::First,
+            label_candidates: 1,
             rng_seed: Some(0),
+            memory_budget: None,
+            num_threads: None,
+            max_in_flight: None,
+            progress: None,
+            cancellation: None,
         };
         let tree = builder.build(point_cloud).unwrap();
         let reader = tree.reader();
@@ -702,7 +986,13 @@ mod tests {
             use_singletons: false,
             verbosity: 0,
             partition_type: PartitionType::First,
+            label_candidates: 1,
             rng_seed: Some(0),
+            memory_budget: None,
+            num_threads: None,
+            max_in_flight: None,
+            progress: None,
+            cancellation: None,
         };
         let tree = builder.build(point_cloud).unwrap();
         let reader = tree.reader();
@@ -711,4 +1001,246 @@ mod tests {
         assert!(reader.get_node_and((-2, 2), |n| n.is_leaf()).is_some());
         assert!(reader.no_dangling_refs());
     }
+
+    #[test]
+    fn label_aware_build_condition() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let data: Vec<f32> = (0..1000).map(|_| rng.gen::<f32>()).collect();
+        let labels: Vec<i64> = data.chunks(2).map(|p| (p[0] > 0.5) as i64).collect();
+        let point_cloud = Arc::new(DefaultLabeledCloud::<L2>::new_simple(data, 2, labels));
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_leaf_cutoff(5)
+            .set_min_res_index(-9)
+            .set_label_candidates(8)
+            .set_rng_seed(0);
+        let mut tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+        tree.generate_summaries();
+        let reader = tree.reader();
+        assert!(reader.no_dangling_refs());
+        for pi in 0..point_cloud.len() {
+            let point = point_cloud.point(pi).unwrap();
+            let knn = reader.knn(&point, 1).unwrap();
+            assert_eq!(knn[0].0, 0.0);
+        }
+
+        // The label entropy of the nodes below the root, weighted by their coverage
+        let weighted_entropy = |tree: &CoverTreeWriter<DefaultLabeledCloud<L2>>| {
+            let reader = tree.reader();
+            let (mut entropy, mut coverage) = (0.0, 0.0);
+            for (_, layer) in reader.layers() {
+                layer.for_each_node(|_, n| {
+                    if n.address() != reader.root_address() {
+                        let summary = n.label_summary().unwrap();
+                        let count = n.coverage_count() as f32;
+                        entropy += count * summary.summary().entropy().unwrap();
+                        coverage += count;
+                    }
+                });
+            }
+            entropy / coverage
+        };
+        let mut plain_tree = builder
+            .set_label_candidates(1)
+            .build(Arc::clone(&point_cloud))
+            .unwrap();
+        plain_tree.generate_summaries();
+        assert!(weighted_entropy(&tree) < weighted_entropy(&plain_tree));
+        // Picking pure candidates doesn't break the points up into singletons
+        let singletons = |tree: &CoverTreeWriter<DefaultLabeledCloud<L2>>| {
+            let reader = tree.reader();
+            let mut singletons = 0;
+            for (_, layer) in reader.layers() {
+                layer.for_each_node(|_, n| singletons += n.singletons_len());
+            }
+            singletons
+        };
+        assert!(singletons(&tree) <= 2 * singletons(&plain_tree));
+    }
+
+    #[test]
+    fn memory_budget_build_condition() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let data: Vec<f32> = (0..2000).map(|_| rng.gen::<f32>()).collect();
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 2).unwrap());
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_leaf_cutoff(5)
+            .set_min_res_index(-9)
+            .set_rng_seed(0);
+        let tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+        let reader = tree.reader();
+
+        // Small enough that it's mostly one node at a time
+        builder.set_memory_budget(1024);
+        let budget_tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+        let budget_reader = budget_tree.reader();
+        assert!(budget_reader.no_dangling_refs());
+        assert_eq!(reader.node_count(), budget_reader.node_count());
+        assert_eq!(reader.root_address(), budget_reader.root_address());
+        for pi in 0..point_cloud.len() {
+            let point = point_cloud.point(pi).unwrap();
+            assert_eq!(
+                reader.path(&point).unwrap(),
+                budget_reader.path(&point).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn thread_limits_build_condition() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let data: Vec<f32> = (0..2000).map(|_| rng.gen::<f32>()).collect();
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 2).unwrap());
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_leaf_cutoff(5)
+            .set_min_res_index(-9)
+            .set_rng_seed(0);
+        let tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+        let reader = tree.reader();
+
+        for (num_threads, max_in_flight) in &[(1, 1), (2, 3)] {
+            builder
+                .set_num_threads(*num_threads)
+                .set_max_in_flight(*max_in_flight);
+            let limited_tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+            let limited_reader = limited_tree.reader();
+            assert!(limited_reader.no_dangling_refs());
+            assert_eq!(reader.node_count(), limited_reader.node_count());
+            for pi in 0..point_cloud.len() {
+                let point = point_cloud.point(pi).unwrap();
+                assert_eq!(
+                    reader.path(&point).unwrap(),
+                    limited_reader.path(&point).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn progress_and_cancellation_build_condition() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let data: Vec<f32> = (0..2000).map(|_| rng.gen::<f32>()).collect();
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 2).unwrap());
+        let reports = Arc::new(atomic::AtomicUsize::new(0));
+        let last_inserted = Arc::new(atomic::AtomicUsize::new(0));
+        let mut builder = CoverTreeBuilder::new();
+        {
+            let reports = Arc::clone(&reports);
+            let last_inserted = Arc::clone(&last_inserted);
+            builder
+                .set_leaf_cutoff(5)
+                .set_min_res_index(-9)
+                .set_rng_seed(0)
+                .set_progress(
+                    move |created: usize, inserted: usize, _elapsed: std::time::Duration| {
+                        assert!(inserted <= created);
+                        reports.fetch_add(1, atomic::Ordering::SeqCst);
+                        last_inserted.store(inserted, atomic::Ordering::SeqCst);
+                    },
+                );
+        }
+        let tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+        assert!(reports.load(atomic::Ordering::SeqCst) > 0);
+        assert_eq!(
+            last_inserted.load(atomic::Ordering::SeqCst),
+            tree.reader().node_count()
+        );
+
+        let token = CancellationToken::new();
+        token.cancel();
+        builder.set_cancellation(token.clone());
+        assert!(matches!(
+            builder.build(Arc::clone(&point_cloud)),
+            Err(GokoError::BuildCancelled)
+        ));
+        builder.set_max_in_flight(2);
+        assert!(matches!(
+            builder.build(point_cloud),
+            Err(GokoError::BuildCancelled)
+        ));
+    }
+
+    fn build_tiny_tree(data: Vec<f32>) -> GokoResult<CoverTreeWriter<DefaultCloud<L2>>> {
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 1).unwrap());
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            leaf_cutoff: 0,
+            min_res_index: -9,
+            use_singletons: true,
+            verbosity: 0,
+            partition_type: PartitionType::Nearest,
+            label_candidates: 1,
+            rng_seed: Some(0),
+            memory_budget: None,
+            num_threads: None,
+            max_in_flight: None,
+            progress: None,
+            cancellation: None,
+        };
+        builder.build(point_cloud)
+    }
+
+    #[test]
+    fn empty_cloud_condition() {
+        match build_tiny_tree(vec![]) {
+            Err(GokoError::EmptyPointCloud) => {}
+            _ => panic!("An empty point cloud should not build"),
+        }
+    }
+
+    #[test]
+    fn single_point_condition() {
+        let tree = build_tiny_tree(vec![0.5]).unwrap();
+        let reader = tree.reader();
+        assert_eq!(reader.node_count(), 1);
+        assert_eq!(reader.root_address(), (-9, 0));
+        assert!(reader.get_node_and((-9, 0), |n| n.is_leaf()).unwrap());
+        assert!(reader.no_dangling_refs());
+
+        let knn = reader.knn(&[0.0f32].as_ref(), 3).unwrap();
+        assert_eq!(knn.len(), 1);
+        assert_eq!(knn[0].1, 0);
+        let path = reader.path(&[0.0f32].as_ref()).unwrap();
+        assert_eq!(path.len(), 1);
+        assert_eq!(path[0].1, (-9, 0));
+    }
+
+    #[test]
+    fn two_point_condition() {
+        let tree = build_tiny_tree(vec![0.5, -0.5]).unwrap();
+        let reader = tree.reader();
+        assert!(reader.no_dangling_refs());
+
+        let knn = reader.knn(&[0.4f32].as_ref(), 3).unwrap();
+        assert_eq!(knn.len(), 2);
+        assert_eq!(knn[0].1, 0);
+        assert_eq!(knn[1].1, 1);
+        let path = reader.path(&[0.4f32].as_ref()).unwrap();
+        assert_eq!(path[0].1, reader.root_address());
+    }
+
+    #[test]
+    fn identical_points_condition() {
+        let tree = build_tiny_tree(vec![0.25; 10]).unwrap();
+        let reader = tree.reader();
+        assert_eq!(reader.node_count(), 1);
+        assert!(reader
+            .get_node_and(reader.root_address(), |n| n.is_leaf())
+            .unwrap());
+        assert_eq!(
+            reader
+                .get_node_and(reader.root_address(), |n| n.singletons_len())
+                .unwrap(),
+            9
+        );
+        assert!(reader.no_dangling_refs());
+
+        let knn = reader.knn(&[0.0f32].as_ref(), 5).unwrap();
+        assert_eq!(knn.len(), 5);
+        for (d, _) in knn {
+            assert_approx_eq!(d, 0.25);
+        }
+    }
 }
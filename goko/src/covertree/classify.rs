@@ -0,0 +1,205 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Classification and Regression
+//!
+//! Votes on the label of a query point with the integer labels of the tree's point cloud.
+//! [`CoverTreeReader::classify`] counts the labels of the `k` nearest neighbors, and
+//! [`CoverTreeReader::classify_path`] reads the label summary of the deepest node on the query's path, which is
+//! cheaper but needs [`CoverTreeWriter::generate_summaries`] to have been run.
+//!
+//! For vector labels [`CoverTreeReader::regress`] averages the mean labels cached on the nodes of the query's path
+//! by the [`LabelMeanPlugin`], without reading any labels at query time.
+
+use super::*;
+use crate::errors::GokoResult;
+use crate::plugins::labels::{LabelMeanPlugin, NodeLabelMean};
+use pointcloud::summaries::{CategorySummary, VecSummary};
+use pointcloud::*;
+use std::ops::Deref;
+
+/// The outcome of a vote, the winning label and the share of the votes each label got.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Classification {
+    /// The label with the most votes, ties go to the smaller label. `None` if nothing that voted had a label.
+    pub label: Option<i64>,
+    /// The share of the votes of each label, largest first
+    pub scores: Vec<(i64, f32)>,
+}
+
+impl Classification {
+    fn from_counts(counts: &[(i64, usize)]) -> Classification {
+        let total: usize = counts.iter().map(|(_, c)| c).sum();
+        let mut scores: Vec<(i64, f32)> = counts
+            .iter()
+            .filter(|(_, c)| *c > 0)
+            .map(|(label, c)| (*label, *c as f32 / total as f32))
+            .collect();
+        scores.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.0.cmp(&b.0))
+        });
+        Classification {
+            label: scores.first().map(|(label, _)| *label),
+            scores,
+        }
+    }
+}
+
+impl<D: PointCloud<Label = i64, LabelSummary = CategorySummary>> CoverTreeReader<D> {
+    /// Classifies the point by the labels of its `k` nearest neighbors, one vote each. Unlabeled neighbors don't
+    /// vote.
+    pub fn classify<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+    ) -> GokoResult<Classification> {
+        let neighbors = self.knn(point, k)?;
+        let mut counts: Vec<(i64, usize)> = Vec::new();
+        for (_, pi) in neighbors {
+            if let Some(label) = self.parameters().point_cloud.label(pi)? {
+                match counts.iter_mut().find(|(l, _)| l == label) {
+                    Some((_, c)) => *c += 1,
+                    None => counts.push((*label, 1)),
+                }
+            }
+        }
+        Ok(Classification::from_counts(&counts))
+    }
+
+    /// Classifies the point by the label summary of the deepest node on its path that has one, every point that
+    /// node covers votes. Returns an empty classification if the summaries weren't generated.
+    pub fn classify_path<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+    ) -> GokoResult<Classification> {
+        let path = self.path(point)?;
+        let summary = path
+            .iter()
+            .rev()
+            .find_map(|(_, address)| self.get_node_and(*address, |n| n.label_summary()).flatten());
+        Ok(match summary {
+            Some(summary) => Classification::from_counts(&summary.summary.items),
+            None => Classification::from_counts(&[]),
+        })
+    }
+}
+
+impl<D: PointCloud<LabelSummary = VecSummary>> CoverTreeReader<D> {
+    /// Predicts the vector label of the point with the mean labels of the nodes on its path. Each node is weighted
+    /// by `1/(d + r)`, where `d` is the distance from the point to the node's center and `r` is the node's radius,
+    /// so the small nodes close to the point dominate. Returns `None` if no node on the path has a mean, attach a
+    /// [`LabelMeanPlugin`] first.
+    pub fn regress<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+    ) -> GokoResult<Option<Vec<f32>>> {
+        let mut weighted_sum: Vec<f32> = Vec::new();
+        let mut total_weight = 0.0;
+        for (dist, address) in self.path(point)? {
+            let radius = self.get_node_and(address, |n| n.radius()).unwrap_or(0.0);
+            let weight = 1.0 / (dist + radius + std::f32::EPSILON);
+            self.get_node_plugin_and::<NodeLabelMean, _, _>(address, |label_mean| {
+                if label_mean.count == 0 {
+                    return;
+                }
+                if weighted_sum.is_empty() {
+                    weighted_sum = vec![0.0; label_mean.mean.len()];
+                }
+                weighted_sum
+                    .iter_mut()
+                    .zip(&label_mean.mean)
+                    .for_each(|(s, m)| *s += weight * m);
+                total_weight += weight;
+            });
+        }
+        if total_weight > 0.0 {
+            Ok(Some(
+                weighted_sum.iter().map(|s| s / total_weight).collect(),
+            ))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+    use pointcloud::data_sources::DataRam;
+    use pointcloud::label_sources::VecLabels;
+    use std::sync::Arc;
+
+    #[test]
+    fn classify_votes_with_neighbors() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let near_zeros = reader.classify(&[0.5f32].as_ref(), 3).unwrap();
+        assert_eq!(near_zeros.label, Some(0));
+        assert_eq!(near_zeros.scores, vec![(0, 1.0)]);
+
+        let near_ones = reader.classify(&[-0.5f32].as_ref(), 2).unwrap();
+        assert_eq!(near_ones.label, Some(1));
+    }
+
+    #[test]
+    fn classify_path_needs_summaries() {
+        let mut tree = build_basic_tree();
+        assert_eq!(
+            tree.reader()
+                .classify_path(&[0.5f32].as_ref())
+                .unwrap()
+                .label,
+            None
+        );
+        tree.generate_summaries();
+        let classification = tree.reader().classify_path(&[0.5f32].as_ref()).unwrap();
+        assert_eq!(classification.label, Some(0));
+    }
+
+    #[test]
+    fn regress_averages_the_path() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels: Vec<f32> = data.iter().map(|x| 2.0 * x).collect();
+        let point_cloud = SimpleLabeledCloud::new(
+            DataRam::<L2>::new(data, 1).unwrap(),
+            VecLabels::new(labels, 1, None),
+        );
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_leaf_cutoff(1)
+            .set_min_res_index(-9)
+            .set_rng_seed(0);
+        let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
+        assert_eq!(tree.reader().regress(&[0.49f32].as_ref()).unwrap(), None);
+
+        tree.add_plugin::<LabelMeanPlugin>(LabelMeanPlugin::default());
+        let reader = tree.reader();
+        let root_mean = reader
+            .get_node_plugin_and::<NodeLabelMean, _, _>(reader.root_address(), |m| m.clone())
+            .unwrap();
+        assert_eq!(root_mean.count, 5);
+        assert_approx_eq!(root_mean.mean[0], 2.0 * 0.979 / 5.0);
+
+        let prediction = reader.regress(&[0.49f32].as_ref()).unwrap().unwrap();
+        assert!(prediction[0] > 0.5, "{:?}", prediction);
+    }
+}
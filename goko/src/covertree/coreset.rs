@@ -0,0 +1,130 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Coresets
+//!
+//! [`CoverTreeReader::coreset`] picks a few points that stand in for the whole tree. It starts with the root's center
+//! standing in for every point, and keeps splitting the node that stands in for the most points into its children and
+//! singletons while there's room. Each center is weighted by the coverage of its node, so the weights add up to the
+//! number of points in the tree and the big, spread out nodes are the ones that get split.
+
+use super::*;
+use crate::NodeAddress;
+use pointcloud::*;
+use std::collections::BinaryHeap;
+
+/// Points that stand in for the tree, see the [module docs](self).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coreset {
+    /// The indexes of the points
+    pub indexes: Vec<usize>,
+    /// The number of points of the tree each point stands in for, these add up to the coverage of the root
+    pub weights: Vec<usize>,
+}
+
+impl Coreset {
+    fn push(&mut self, index: usize, weight: usize) {
+        self.indexes.push(index);
+        self.weights.push(weight);
+    }
+
+    /// The number of points in the coreset.
+    pub fn len(&self) -> usize {
+        self.indexes.len()
+    }
+
+    /// If the coreset has no points.
+    pub fn is_empty(&self) -> bool {
+        self.indexes.is_empty()
+    }
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// At most `m` points that stand in for the tree, weighted by how many points they cover. See the
+    /// [module docs](self).
+    pub fn coreset(&self, m: usize) -> Coreset {
+        let mut coreset = Coreset::default();
+        if m == 0 {
+            return coreset;
+        }
+        let root_coverage = self
+            .get_node_and(self.root_address(), |n| n.coverage_count())
+            .unwrap_or(0);
+        // Ordered by the coverage, the nodes that stand in for the most points are split first
+        let mut unsplit: BinaryHeap<(usize, NodeAddress)> = BinaryHeap::new();
+        unsplit.push((root_coverage, self.root_address()));
+        while let Some((coverage, address)) = unsplit.pop() {
+            let split = self.get_node_and(address, |n| {
+                let mut parts: Vec<NodeAddress> = Vec::new();
+                if let Some((nested_scale, child_addresses)) = n.children() {
+                    parts.push((nested_scale, address.1));
+                    parts.extend_from_slice(child_addresses);
+                }
+                (parts, n.singletons().to_vec())
+            });
+            let (children, singletons) = match split {
+                Some(split) => split,
+                None => continue,
+            };
+            let stays = if children.is_empty() { 1 } else { 0 };
+            let parts = children.len() + singletons.len() + stays;
+            if parts <= 1 || coreset.len() + unsplit.len() + parts > m {
+                coreset.push(address.1, coverage);
+                continue;
+            }
+            if stays == 1 {
+                coreset.push(address.1, 1);
+            }
+            for pi in singletons {
+                coreset.push(pi, 1);
+            }
+            for ca in children {
+                if let Some(child_coverage) = self.get_node_and(ca, |n| n.coverage_count()) {
+                    unsplit.push((child_coverage, ca));
+                }
+            }
+        }
+        coreset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn coreset_weights_cover_the_tree() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        for m in 1..8 {
+            let coreset = reader.coreset(m);
+            assert!(coreset.len() <= m);
+            assert!(!coreset.is_empty());
+            assert_eq!(coreset.weights.iter().sum::<usize>(), 5);
+            let mut indexes = coreset.indexes.clone();
+            indexes.sort();
+            indexes.dedup();
+            assert_eq!(indexes.len(), coreset.len());
+        }
+        let everything = reader.coreset(5);
+        assert!(everything.weights.iter().all(|w| *w == 1));
+        assert!(reader.coreset(0).is_empty());
+    }
+}
@@ -17,11 +17,13 @@
 * under the License.
 */
 use crate::errors::GokoResult;
+use crate::PartitionType;
 use pointcloud::*;
 use rand::rngs::SmallRng;
 use rand::seq::SliceRandom;
 use rand::Rng;
 use std::cmp::Ordering;
+use std::mem::size_of;
 use std::sync::Arc;
 
 #[derive(Clone, Debug)]
@@ -31,6 +33,30 @@ pub(crate) enum CoveredData {
 }
 
 impl CoveredData {
+    /// The points of a node of a built tree, so that it can be split again.
+    pub(crate) fn around<D: PointCloud>(
+        partition_type: PartitionType,
+        center_index: usize,
+        coverage: Vec<usize>,
+        point_cloud: &Arc<D>,
+    ) -> GokoResult<CoveredData> {
+        let dists = point_cloud.distances_to_point_index(center_index, &coverage)?;
+        Ok(match partition_type {
+            PartitionType::First => Self::FirstCoveredData(FirstCoveredData {
+                dists,
+                coverage,
+                center_index,
+            }),
+            PartitionType::Nearest => Self::NearestCoveredData(NearestCoveredData {
+                centers: vec![],
+                dists: vec![],
+                point_indexes: coverage,
+                center_index,
+                center_dists: dists,
+            }),
+        })
+    }
+
     pub(crate) fn max_distance(&self) -> f32 {
         match &self {
             Self::FirstCoveredData(a) => a.max_distance(),
@@ -57,6 +83,35 @@ impl CoveredData {
             Self::NearestCoveredData(a) => a.center_index,
         }
     }
+
+    /// The bytes held by the buffers of this cache, by their capacity rather than their length.
+    pub(crate) fn estimated_bytes(&self) -> usize {
+        match &self {
+            Self::FirstCoveredData(a) => {
+                a.coverage.capacity() * size_of::<usize>() + a.dists.capacity() * size_of::<f32>()
+            }
+            Self::NearestCoveredData(a) => {
+                a.point_indexes.capacity() * size_of::<usize>()
+                    + a.center_dists.capacity() * size_of::<f32>()
+                    + a.centers.capacity() * size_of::<usize>()
+                    + a.dists.iter().map(|d| d.capacity()).sum::<usize>() * size_of::<f32>()
+            }
+        }
+    }
+
+    /// Releases the spare capacity of the buffers.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        match self {
+            Self::FirstCoveredData(a) => {
+                a.coverage.shrink_to_fit();
+                a.dists.shrink_to_fit();
+            }
+            Self::NearestCoveredData(a) => {
+                a.point_indexes.shrink_to_fit();
+                a.center_dists.shrink_to_fit();
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -82,23 +137,23 @@ impl UncoveredData {
         let center_index = self.coverage.remove(new_center);
         let dists = point_cloud.distances_to_point_index(center_index, &self.coverage)?;
 
-        let mut close_index = Vec::with_capacity(self.coverage.len());
-        let mut close_dist = Vec::with_capacity(self.coverage.len());
-        let mut far = Vec::new();
-        for (i, d) in self.coverage.iter().zip(&dists) {
-            if *d < radius {
+        let is_close: Vec<bool> = dists.iter().map(|d| *d < radius).collect();
+        let mut close_index = Vec::new();
+        let mut close_dist = Vec::new();
+        for ((i, d), close) in self.coverage.iter().zip(&dists).zip(&is_close) {
+            if *close {
                 close_index.push(*i);
                 close_dist.push(*d);
-            } else {
-                far.push(*i);
             }
         }
+        // The far points stay in our buffer
+        let mut is_close_iter = is_close.iter();
+        self.coverage.retain(|_| !*is_close_iter.next().unwrap());
         let close = FirstCoveredData {
             coverage: close_index,
             dists: close_dist,
             center_index,
         };
-        self.coverage = far;
         Ok(close)
     }
 
@@ -139,25 +194,22 @@ impl FirstCoveredData {
         })
     }
 
-    pub(crate) fn split(self, thresh: f32) -> GokoResult<(FirstCoveredData, UncoveredData)> {
-        let mut close_index = Vec::with_capacity(self.coverage.len());
-        let mut close_dist = Vec::with_capacity(self.coverage.len());
-        let mut far = Vec::new();
-        for (i, d) in self.coverage.iter().zip(&self.dists) {
-            if *d < thresh {
-                close_index.push(*i);
-                close_dist.push(*d);
-            } else {
-                far.push(*i);
-            }
-        }
-        let close = FirstCoveredData {
-            coverage: close_index,
-            dists: close_dist,
-            center_index: self.center_index,
-        };
+    /// The close points are compacted in place, so the covered child reuses our buffers.
+    pub(crate) fn split(mut self, thresh: f32) -> GokoResult<(FirstCoveredData, UncoveredData)> {
+        let is_close: Vec<bool> = self.dists.iter().map(|d| *d < thresh).collect();
+        let far: Vec<usize> = self
+            .coverage
+            .iter()
+            .zip(&is_close)
+            .filter(|(_, close)| !**close)
+            .map(|(i, _)| *i)
+            .collect();
+        let mut is_close_iter = is_close.iter();
+        self.coverage.retain(|_| *is_close_iter.next().unwrap());
+        let mut is_close_iter = is_close.iter();
+        self.dists.retain(|_| *is_close_iter.next().unwrap());
         let new_far = UncoveredData { coverage: far };
-        Ok((close, new_far))
+        Ok((self, new_far))
     }
 
     pub(crate) fn into_indexes(self) -> Vec<usize> {
@@ -168,7 +220,7 @@ impl FirstCoveredData {
         self.dists
             .iter()
             .cloned()
-            .fold(-1. / 0. /* -inf */, f32::max)
+            .fold(0.0, f32::max)
     }
 
     pub(crate) fn len(&self) -> usize {
@@ -204,6 +256,7 @@ impl NearestCoveredData {
     fn cover_thyself<D: PointCloud>(
         &mut self,
         radius: f32,
+        label_candidates: usize,
         point_cloud: &Arc<D>,
         rng: &mut SmallRng,
     ) -> GokoResult<()> {
@@ -217,9 +270,21 @@ impl NearestCoveredData {
                 .filter(|(_, b)| !**b)
                 .map(|(pi, _)| *pi)
                 .collect();
-            let center_index = *uncovered_indexes.choose(rng).unwrap();
-            let new_dists =
-                point_cloud.distances_to_point_index(center_index, &self.point_indexes)?;
+            let (center_index, new_dists) = if label_candidates > 1 {
+                self.purest_candidate(
+                    &uncovered_indexes,
+                    &coverage,
+                    radius,
+                    label_candidates,
+                    point_cloud,
+                    rng,
+                )?
+            } else {
+                let center_index = *uncovered_indexes.choose(rng).unwrap();
+                let new_dists =
+                    point_cloud.distances_to_point_index(center_index, &self.point_indexes)?;
+                (center_index, new_dists)
+            };
             coverage
                 .iter_mut()
                 .zip(&new_dists)
@@ -231,6 +296,52 @@ impl NearestCoveredData {
         Ok(())
     }
 
+    /// Draws some uncovered points and returns the one whose cover has the most purity weighted by size, with its
+    /// distances. A cover of `n` points with label entropy `h` scores `n * exp(-h)`, so a pure cover scores its size
+    /// and a cover split evenly between two labels half of it. A candidate that only covers itself is pure but scores
+    /// 1, so it doesn't win over a large cover that's mostly one label. The summaries without an entropy count as
+    /// pure, so then this picks the largest cover.
+    fn purest_candidate<D: PointCloud>(
+        &self,
+        uncovered_indexes: &[usize],
+        coverage: &[bool],
+        radius: f32,
+        label_candidates: usize,
+        point_cloud: &Arc<D>,
+        rng: &mut SmallRng,
+    ) -> GokoResult<(usize, Vec<f32>)> {
+        let mut best: Option<(f32, usize, usize, Vec<f32>)> = None;
+        for candidate in uncovered_indexes.choose_multiple(rng, label_candidates) {
+            let dists = point_cloud.distances_to_point_index(*candidate, &self.point_indexes)?;
+            let newly_covered: Vec<usize> = self
+                .point_indexes
+                .iter()
+                .zip(&dists)
+                .zip(coverage)
+                .filter(|((_, d), c)| !**c && **d < radius)
+                .map(|((pi, _), _)| *pi)
+                .collect();
+            let entropy = point_cloud
+                .label_summary(&newly_covered)?
+                .summary()
+                .entropy()
+                .unwrap_or(0.0);
+            let score = newly_covered.len() as f32 * (-entropy).exp();
+            let better = match &best {
+                None => true,
+                Some((best_score, best_len, ..)) => {
+                    score > *best_score || (score == *best_score && newly_covered.len() > *best_len)
+                }
+            };
+            if better {
+                best = Some((score, newly_covered.len(), *candidate, dists));
+            }
+        }
+        // There's always an uncovered point to draw when this is called
+        let (_, _, center_index, dists) = best.unwrap();
+        Ok((center_index, dists))
+    }
+
     fn add_point(&mut self, point_index: usize, distance: f32) {
         if point_index != self.center_index {
             self.center_dists.push(distance);
@@ -238,14 +349,9 @@ impl NearestCoveredData {
         }
     }
 
-    fn assign_to_nearest(&self) -> (NearestCoveredData, Vec<NearestCoveredData>) {
-        let mut new_center_coverage = NearestCoveredData {
-            centers: vec![],
-            dists: vec![],
-            point_indexes: Vec::new(),
-            center_index: self.center_index,
-            center_dists: Vec::new(),
-        };
+    /// The points that stay with our center are compacted in place, so the nested child reuses our buffers.
+    fn assign_to_nearest(mut self) -> (NearestCoveredData, Vec<NearestCoveredData>) {
+        let mut stays = Vec::with_capacity(self.point_indexes.len());
         let mut new_coverage: Vec<NearestCoveredData> = self
             .centers
             .iter()
@@ -267,22 +373,36 @@ impl NearestCoveredData {
                 .min_by(|(_di, d), (_ci, c)| d.partial_cmp(c).unwrap_or(Ordering::Equal))
                 .unwrap_or((0, f32::MAX));
             if self.center_dists[i] < d {
-                new_center_coverage.add_point(*pi, self.center_dists[i]);
+                stays.push(true);
             } else {
+                stays.push(false);
                 new_coverage[index].add_point(*pi, d);
             }
         }
 
+        let mut stays_iter = stays.iter();
+        self.point_indexes.retain(|_| *stays_iter.next().unwrap());
+        let mut stays_iter = stays.iter();
+        self.center_dists.retain(|_| *stays_iter.next().unwrap());
+        let new_center_coverage = NearestCoveredData {
+            centers: vec![],
+            dists: vec![],
+            point_indexes: self.point_indexes,
+            center_index: self.center_index,
+            center_dists: self.center_dists,
+        };
+
         (new_center_coverage, new_coverage)
     }
 
     pub(crate) fn split<D: PointCloud>(
         mut self,
         radius: f32,
+        label_candidates: usize,
         point_cloud: &Arc<D>,
         rng: &mut SmallRng,
     ) -> GokoResult<(NearestCoveredData, Vec<NearestCoveredData>)> {
-        self.cover_thyself(radius, point_cloud, rng)?;
+        self.cover_thyself(radius, label_candidates, point_cloud, rng)?;
         Ok(self.assign_to_nearest())
     }
 
@@ -294,7 +414,7 @@ impl NearestCoveredData {
         self.center_dists
             .iter()
             .cloned()
-            .fold(-1. / 0. /* -inf */, f32::max)
+            .fold(0.0, f32::max)
     }
 
     pub(crate) fn len(&self) -> usize {
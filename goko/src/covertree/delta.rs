@@ -0,0 +1,217 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Delta Checkpoints
+//!
+//! Saving a large tree after a handful of insertions rewrites every node. Instead take a full checkpoint once with
+//! [`CoverTreeWriter::save_checkpoint`], then save only the nodes written to since then with
+//! [`CoverTreeWriter::save_delta`]. Each delta is cumulative, it holds every node changed or removed since the full
+//! checkpoint, so you only need to keep the latest one. [`CoverTreeWriter::load_with_deltas`] puts the tree back
+//! together.
+
+use super::layer::*;
+use super::node::*;
+use super::*;
+use crate::errors::{GokoError, GokoResult};
+use crate::tree_file_format::*;
+use crate::NodeAddress;
+use pointcloud::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// What a delta is relative to and what it contains. Store it next to the delta's protobuf.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeltaManifest {
+    /// The version of the full checkpoint this delta applies to
+    pub base_version: u64,
+    /// The epoch of the tree when the delta was taken, see [`CoverTreeParameters::epoch`]
+    pub epoch: u64,
+    /// The root of the tree when the delta was taken
+    pub root_address: NodeAddress,
+    /// The scale indexes of the layers that have nodes in this delta
+    pub changed_layers: Vec<i32>,
+    /// The number of nodes in this delta
+    pub node_count: usize,
+    /// The number of nodes this delta removes
+    #[serde(default)]
+    pub removed_count: usize,
+}
+
+/// The nodes that changed since a full checkpoint. The protobuf has the same layout as a full save, but only the
+/// changed nodes are in its layers. The nodes removed since the checkpoint are in each layer's `removed_indexes`.
+#[derive(Debug, Clone)]
+pub struct TreeDelta {
+    /// What this delta applies to
+    pub manifest: DeltaManifest,
+    /// The changed nodes
+    pub nodes: CoreProto,
+}
+
+impl<D: PointCloud> CoverTreeWriter<D> {
+    /// Saves the whole tree and starts tracking changes against it. Returns the version of this checkpoint, pass it
+    /// to [`CoverTreeWriter::save_delta`].
+    pub fn save_checkpoint(&mut self) -> (u64, CoreProto) {
+        let cover_proto = self.save();
+        self.layers.iter_mut().for_each(|l| l.clear_modified());
+        let version = rand::random::<u64>();
+        self.checkpoint_version = Some(version);
+        (version, cover_proto)
+    }
+
+    /// Saves the nodes written to since the checkpoint with this version. Errors if that isn't the latest full
+    /// checkpoint of this tree.
+    pub fn save_delta(&self, base_version: u64) -> GokoResult<TreeDelta> {
+        if self.checkpoint_version != Some(base_version) {
+            return Err(GokoError::DeltaBaseMismatch);
+        }
+        let mut cover_proto = CoreProto::new();
+        cover_proto.set_root_scale(self.root_address.0);
+        cover_proto.set_root_index(self.root_address.1 as u64);
+        let mut changed_layers = Vec::new();
+        let mut node_count = 0;
+        let mut removed_count = 0;
+        let mut layer_protos = Vec::new();
+        for layer in self.layers.iter().filter(|l| l.modified_len() > 0) {
+            let layer_proto = layer.save_modified();
+            node_count += layer_proto.get_nodes().len();
+            removed_count += layer_proto.get_removed_indexes().len();
+            changed_layers.push(layer_proto.get_scale_index());
+            layer_protos.push(layer_proto);
+        }
+        cover_proto.set_layers(layer_protos.into());
+        Ok(TreeDelta {
+            manifest: DeltaManifest {
+                base_version,
+                epoch: self.epoch(),
+                root_address: self.root_address,
+                changed_layers,
+                node_count,
+                removed_count,
+            },
+            nodes: cover_proto,
+        })
+    }
+
+    /// Loads the full checkpoint and applies the deltas in order. All deltas have to be against the same checkpoint,
+    /// the loaded tree keeps tracking changes against it, so you can keep saving deltas with the same base version.
+    pub fn load_with_deltas(
+        base: &CoreProto,
+        base_version: u64,
+        deltas: &[TreeDelta],
+        point_cloud: Arc<D>,
+    ) -> GokoResult<CoverTreeWriter<D>> {
+        if deltas
+            .iter()
+            .any(|d| d.manifest.base_version != base_version)
+        {
+            return Err(GokoError::DeltaBaseMismatch);
+        }
+        let mut tree = CoverTreeWriter::load(base, point_cloud)?;
+        for delta in deltas {
+            for layer_proto in delta.nodes.get_layers() {
+                let scale_index = layer_proto.get_scale_index();
+                let internal_index = tree.parameters.internal_index(scale_index);
+                while tree.layers.len() <= internal_index {
+                    let new_scale = tree.layers.len() as i32 + tree.parameters.min_res_index - 1;
+                    tree.layers.push(CoverLayerWriter::new(new_scale));
+                }
+                for pi in layer_proto.get_removed_indexes() {
+                    tree.layers[internal_index].remove_raw(*pi as usize);
+                }
+                for node_proto in layer_proto.get_nodes() {
+                    let node = CoverNode::load(node_proto);
+                    tree.layers[internal_index].insert_raw(*node.center_index(), node);
+                }
+            }
+            tree.root_address = delta.manifest.root_address;
+        }
+        tree.refresh();
+        tree.refresh_final_indexes();
+        tree.checkpoint_version = Some(base_version);
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::{build_basic_tree, build_random_tree};
+
+    #[test]
+    fn delta_round_trip() {
+        let mut tree = build_basic_tree();
+        let (version, base) = tree.save_checkpoint();
+        assert_eq!(tree.save_delta(version).unwrap().manifest.node_count, 0);
+        assert!(tree.save_delta(version + 1).is_err());
+
+        let root = tree.root_address;
+        unsafe { tree.update_node(root, |n| n.set_coverage_count(100)) };
+        tree.refresh();
+        let delta = tree.save_delta(version).unwrap();
+        assert_eq!(delta.manifest.node_count, 1);
+        assert_eq!(delta.manifest.changed_layers, vec![root.0]);
+
+        let point_cloud = Arc::clone(&tree.parameters.point_cloud);
+        let loaded =
+            CoverTreeWriter::load_with_deltas(&base, version, &[delta], point_cloud).unwrap();
+        let reader = loaded.reader();
+        assert_eq!(
+            reader.get_node_and(root, |n| n.coverage_count()).unwrap(),
+            100
+        );
+        assert_eq!(reader.node_count(), tree.reader().node_count());
+        assert!(loaded.save_delta(version).is_ok());
+    }
+
+    #[test]
+    fn delta_round_trip_with_removals() {
+        let mut tree = build_random_tree();
+        let (version, base) = tree.save_checkpoint();
+
+        let reader = tree.reader();
+        let mut leaves = Vec::new();
+        for (_si, layer) in reader.layers() {
+            layer.for_each_node(|_pi, n| {
+                if let Some(parent) = n.parent_address() {
+                    if n.is_leaf() && parent.1 != n.address().1 {
+                        leaves.push((n.address(), n.singletons().to_vec()));
+                    }
+                }
+            });
+        }
+        // Emptying a leaf cuts it from the tree
+        let (leaf, singletons) = leaves.pop().unwrap();
+        for pi in singletons {
+            tree.remove_point(pi).unwrap();
+        }
+        tree.remove_point(leaf.1).unwrap();
+        assert!(tree.reader().get_node_and(leaf, |_| ()).is_none());
+
+        let delta = tree.save_delta(version).unwrap();
+        assert_eq!(delta.manifest.removed_count, 1);
+        let point_cloud = Arc::clone(&tree.parameters.point_cloud);
+        let loaded =
+            CoverTreeWriter::load_with_deltas(&base, version, &[delta], point_cloud).unwrap();
+        let reader = loaded.reader();
+        assert!(reader.get_node_and(leaf, |_| ()).is_none());
+        assert!(reader.known_path(leaf.1).is_err());
+        assert!(reader.no_dangling_refs());
+        assert_eq!(reader.node_count(), tree.reader().node_count());
+    }
+}
@@ -0,0 +1,228 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Kernel density estimates
+//!
+//! [`CoverTreeReader::density`] estimates the density of the point cloud at a query point with a gaussian kernel,
+//! without evaluating the kernel at every point. Every point a node covers is within the node's radius of its center,
+//! so the kernel values of the node's points are between the kernel at `d + r` and at `d - r`, where `d` is the
+//! distance from the query to the center. When that range is tight enough the node's points are counted at the
+//! middle of it, otherwise the node is opened up. This is the Barnes-Hut idea with the cover tree's nodes as cells.
+//!
+//! A node is counted at once when its error is small next to its own lower bound, or small next to its share of a
+//! lower bound on the whole sum. The lower bound on the whole sum counts the nodes already estimated at their lower
+//! bounds, not their estimates, so it stays below the exact sum. Both halves of the error budget are bounded by half
+//! the relative error, so the estimate is within the relative error of the exact sum. The nearest children are opened first, so the lower bound
+//! on the whole sum grows quickly and the far nodes are counted at once.
+
+use super::*;
+use crate::errors::GokoResult;
+use crate::NodeAddress;
+use pointcloud::*;
+use std::cmp::Ordering;
+use std::ops::Deref;
+
+/// The relative error of [`CoverTreeReader::density`].
+pub const DEFAULT_DENSITY_ERROR: f64 = 1.0e-2;
+
+/// The gaussian kernel, `exp(-d^2/(2h^2))`.
+fn gaussian_kernel(dist: f32, bandwidth: f32) -> f64 {
+    let scaled = dist as f64 / bandwidth as f64;
+    (-0.5 * scaled * scaled).exp()
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// The mean of the gaussian kernel `exp(-d^2/(2h^2))` between the point and every point in the tree, with `h`
+    /// the bandwidth and `d` the tree's metric. This is within [`DEFAULT_DENSITY_ERROR`] of the exact mean, see
+    /// [`Self::density_within`].
+    ///
+    /// The kernel isn't normalized, as that depends on the dimension the metric works in. For the euclidean metric
+    /// in `n` dimensions divide by `(2 pi h^2)^(n/2)` to get the density.
+    pub fn density<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        bandwidth: f32,
+    ) -> GokoResult<f64> {
+        self.density_within(point, bandwidth, DEFAULT_DENSITY_ERROR)
+    }
+
+    /// Same as [`Self::density`], within a relative error of your choice. A relative error of 0 evaluates the kernel
+    /// at every point that doesn't have the same kernel value as the rest of its node.
+    pub fn density_within<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        bandwidth: f32,
+        relative_error: f64,
+    ) -> GokoResult<f64> {
+        let point_cloud = &self.parameters().point_cloud;
+        let total_count = point_cloud.len();
+        if total_count == 0 {
+            return Ok(0.0);
+        }
+        let node_error = relative_error.max(0.0) / 2.0;
+        let node_bounds = |dist: f32, address: NodeAddress| {
+            self.get_node_and(address, |n| {
+                let count = n.coverage_count() as f64;
+                let lower = count * gaussian_kernel(dist + n.radius(), bandwidth);
+                let upper = count * gaussian_kernel((dist - n.radius()).max(0.0), bandwidth);
+                (count, lower, upper)
+            })
+        };
+
+        let root_center = point_cloud.point(self.root_address().1)?;
+        let root_dist = D::Metric::dist(&root_center, &point);
+        // The estimate of the kernel sum so far, a lower bound on that part of the sum, and the lower bounds of the
+        // nodes on the stack
+        let mut sum = 0.0;
+        let mut sum_lower = 0.0;
+        let mut stacked_lower = node_bounds(root_dist, self.root_address())
+            .map(|(_, lower, _)| lower)
+            .unwrap_or(0.0);
+        let mut unvisited = vec![(root_dist, self.root_address())];
+        while let Some((dist, address)) = unvisited.pop() {
+            let (count, lower, upper) = match node_bounds(dist, address) {
+                Some(bounds) => bounds,
+                None => continue,
+            };
+            stacked_lower -= lower;
+            let whole_lower = sum_lower + stacked_lower + lower;
+            let error = (upper - lower) / 2.0;
+            if error <= node_error * lower
+                || error <= node_error * whole_lower * count / total_count as f64
+            {
+                sum += (upper + lower) / 2.0;
+                sum_lower += lower;
+                continue;
+            }
+            let children: Option<GokoResult<Vec<(f32, NodeAddress)>>> =
+                self.get_node_and(address, |n| {
+                    let singleton_dists = point_cloud.distances_to_point(point, n.singletons())?;
+                    let singleton_sum = singleton_dists
+                        .iter()
+                        .map(|d| gaussian_kernel(*d, bandwidth))
+                        .sum::<f64>();
+                    sum += singleton_sum;
+                    sum_lower += singleton_sum;
+                    match n.children() {
+                        None => {
+                            let center = gaussian_kernel(dist, bandwidth);
+                            sum += center;
+                            sum_lower += center;
+                            Ok(Vec::new())
+                        }
+                        Some((nested_scale, child_addresses)) => {
+                            let centers: Vec<usize> =
+                                child_addresses.iter().map(|ca| ca.1).collect();
+                            let child_dists = point_cloud.distances_to_point(point, &centers)?;
+                            let mut children: Vec<(f32, NodeAddress)> = child_dists
+                                .into_iter()
+                                .zip(child_addresses.iter().cloned())
+                                .collect();
+                            // The nested child shares our center, so we already know its distance
+                            children.push((dist, (nested_scale, address.1)));
+                            Ok(children)
+                        }
+                    }
+                });
+            if let Some(children) = children {
+                let mut children = children?;
+                // The nearest child is popped first
+                children.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+                for (child_dist, child_address) in &children {
+                    stacked_lower += node_bounds(*child_dist, *child_address)
+                        .map(|(_, lower, _)| lower)
+                        .unwrap_or(0.0);
+                }
+                unvisited.extend(children);
+            }
+        }
+        Ok(sum / total_count as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::{build_basic_tree, build_random_tree};
+
+    fn exact_density<D: PointCloud<Point = [f32]>>(
+        reader: &CoverTreeReader<D>,
+        point: &[f32],
+        bandwidth: f32,
+    ) -> f64 {
+        let point_cloud = &reader.parameters().point_cloud;
+        let indexes: Vec<usize> = (0..point_cloud.len()).collect();
+        let dists = point_cloud.distances_to_point(&point, &indexes).unwrap();
+        dists
+            .iter()
+            .map(|d| gaussian_kernel(*d, bandwidth))
+            .sum::<f64>()
+            / indexes.len() as f64
+    }
+
+    #[test]
+    fn density_is_within_the_error() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        for query in &[-0.5, 0.0, 0.45, 2.0] {
+            let point = [*query];
+            for bandwidth in &[0.01, 0.1, 1.0, 10.0] {
+                let exact = exact_density(&reader, &point, *bandwidth);
+                for relative_error in &[0.0, 0.01, 0.5] {
+                    let estimate = reader
+                        .density_within(&&point[..], *bandwidth, *relative_error)
+                        .unwrap();
+                    assert!((estimate - exact).abs() <= relative_error * exact + 1.0e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tight_errors_hold_on_a_larger_tree() {
+        let tree = build_random_tree();
+        let reader = tree.reader();
+        for query in &[[0.5f32, 0.5], [0.1, 0.9], [2.0, -1.0]] {
+            for bandwidth in &[0.05, 0.2, 1.0] {
+                let exact = exact_density(&reader, &query[..], *bandwidth);
+                for relative_error in &[1.0e-3, 1.0e-2, 0.1] {
+                    let estimate = reader
+                        .density_within(&&query[..], *bandwidth, *relative_error)
+                        .unwrap();
+                    assert!(
+                        (estimate - exact).abs() <= relative_error * exact + 1.0e-9,
+                        "query {:?} bandwidth {}: {} != {}",
+                        query,
+                        bandwidth,
+                        estimate,
+                        exact
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wide_bandwidths_count_everything() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let estimate = reader.density(&&[0.0][..], 1.0e6).unwrap();
+        assert_approx_eq!(estimate, 1.0);
+    }
+}
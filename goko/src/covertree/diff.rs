@@ -0,0 +1,211 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Tree Diffs
+//!
+//! Compares two trees built on different snapshots of the same dataset, where a point has the same index in both
+//! snapshots. Nodes are matched by their address, `(scale_index, center_index)`, so a node that's in both trees is
+//! centered on the same point at the same scale. [`CoverTreeReader::diff`] reports the nodes only one of the trees
+//! has, the nodes whose parent changed, and how the fraction of the dataset each shared node covers shifted. Regions
+//! of the space that changed between the snapshots show up as large shifts near the top of the tree.
+
+use super::*;
+use crate::NodeAddress;
+use hashbrown::HashMap;
+use pointcloud::*;
+use serde::{Deserialize, Serialize};
+
+/// A node that is in both trees with a different parent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeMove {
+    /// The address of the node
+    pub address: NodeAddress,
+    /// The parent in the first tree
+    pub old_parent: Option<NodeAddress>,
+    /// The parent in the second tree
+    pub new_parent: Option<NodeAddress>,
+}
+
+/// How the fraction of the dataset a node covers changed between the trees.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeShift {
+    /// The address of the node
+    pub address: NodeAddress,
+    /// The fraction of the first tree's dataset the node covers
+    pub old_fraction: f32,
+    /// The fraction of the second tree's dataset the node covers
+    pub new_fraction: f32,
+}
+
+impl NodeShift {
+    /// The change in the fraction of the dataset the node covers, positive if it grew.
+    pub fn shift(&self) -> f32 {
+        self.new_fraction - self.old_fraction
+    }
+}
+
+/// The differences between two trees, see [`CoverTreeReader::diff`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TreeDiff {
+    /// The nodes only the second tree has, sorted by address
+    pub added: Vec<NodeAddress>,
+    /// The nodes only the first tree has, sorted by address
+    pub removed: Vec<NodeAddress>,
+    /// The nodes in both trees that changed parent, sorted by address
+    pub moved: Vec<NodeMove>,
+    /// The nodes in both trees whose coverage fraction changed, largest shift first
+    pub shifted: Vec<NodeShift>,
+}
+
+impl TreeDiff {
+    /// If the trees have the same structure and coverage.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.moved.is_empty()
+            && self.shifted.is_empty()
+    }
+
+    /// The `n` largest coverage shifts, see [`NodeShift::shift`].
+    pub fn top_shifts(&self, n: usize) -> &[NodeShift] {
+        &self.shifted[..n.min(self.shifted.len())]
+    }
+}
+
+/// The parent and coverage fraction of each node in a tree
+fn node_table<D: PointCloud>(
+    reader: &CoverTreeReader<D>,
+) -> HashMap<NodeAddress, (Option<NodeAddress>, f32)> {
+    let total = reader.parameters().point_cloud.len().max(1) as f32;
+    let mut table = HashMap::new();
+    for (scale_index, layer) in reader.layers() {
+        layer.for_each_node(|center_index, n| {
+            table.insert(
+                (scale_index, *center_index),
+                (n.parent_address(), n.coverage_count() as f32 / total),
+            );
+        });
+    }
+    table
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// The differences between this tree and `other`, with this tree as the old one. See the [module docs](self).
+    pub fn diff<E: PointCloud>(&self, other: &CoverTreeReader<E>) -> TreeDiff {
+        let old_nodes = node_table(self);
+        let new_nodes = node_table(other);
+
+        let mut diff = TreeDiff::default();
+        for (address, (old_parent, old_fraction)) in old_nodes.iter() {
+            match new_nodes.get(address) {
+                Some((new_parent, new_fraction)) => {
+                    if old_parent != new_parent {
+                        diff.moved.push(NodeMove {
+                            address: *address,
+                            old_parent: *old_parent,
+                            new_parent: *new_parent,
+                        });
+                    }
+                    if old_fraction != new_fraction {
+                        diff.shifted.push(NodeShift {
+                            address: *address,
+                            old_fraction: *old_fraction,
+                            new_fraction: *new_fraction,
+                        });
+                    }
+                }
+                None => diff.removed.push(*address),
+            }
+        }
+        diff.added = new_nodes
+            .keys()
+            .filter(|address| !old_nodes.contains_key(*address))
+            .cloned()
+            .collect();
+
+        diff.added.sort_unstable();
+        diff.removed.sort_unstable();
+        diff.moved.sort_unstable_by_key(|m| m.address);
+        diff.shifted.sort_by(|a, b| {
+            b.shift()
+                .abs()
+                .partial_cmp(&a.shift().abs())
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.address.cmp(&b.address))
+        });
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+    use std::sync::Arc;
+
+    #[test]
+    fn a_tree_has_no_diff_with_itself() {
+        let writer = build_basic_tree();
+        let reader = writer.reader();
+        assert!(reader.diff(&reader).is_empty());
+    }
+
+    #[test]
+    fn diff_finds_the_new_points() {
+        let old_tree = build_basic_tree();
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0, -0.3, -0.31];
+        let labels = vec![0, 0, 0, 1, 1, 1, 1];
+        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 1, labels);
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_scale_base(2.0)
+            .set_leaf_cutoff(1)
+            .set_min_res_index(-9)
+            .set_use_singletons(false)
+            .set_rng_seed(0);
+        let new_tree = builder.build(Arc::new(point_cloud)).unwrap();
+
+        let diff = old_tree.reader().diff(&new_tree.reader());
+        assert!(!diff.is_empty());
+        // Without singletons every point is the center of a node, and the old tree has no node on the new points
+        for new_point in &[5, 6] {
+            assert!(
+                diff.added.iter().any(|(_, center)| center == new_point),
+                "point {} is missing from {:?}",
+                new_point,
+                diff.added
+            );
+        }
+        assert!(diff
+            .added
+            .iter()
+            .all(|address| new_tree.reader().get_node_and(*address, |_| ()).is_some()));
+        assert!(diff
+            .removed
+            .iter()
+            .all(|address| old_tree.reader().get_node_and(*address, |_| ()).is_some()));
+        assert!(diff
+            .shifted
+            .windows(2)
+            .all(|w| w[0].shift().abs() >= w[1].shift().abs()));
+        // The root covers everything in both trees
+        let root = old_tree.reader().root_address();
+        assert!(diff.shifted.iter().all(|s| s.address != root));
+    }
+}
@@ -0,0 +1,169 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Structure Export
+//!
+//! Dumps the node hierarchy for visualization. [`CoverTreeReader::to_dot`] writes a GraphViz digraph and
+//! [`CoverTreeReader::to_json_tree`] a nested JSON object, both with the radius, coverage count and label summary of
+//! each node. The nested child of a node is listed with the rest of its children. Big trees are unreadable in either
+//! format, so both take a `max_depth`, the root is at depth 0.
+
+use super::*;
+use crate::errors::GokoResult;
+use crate::NodeAddress;
+use pointcloud::*;
+use serde::Serialize;
+use std::fmt::Write;
+
+/// A node and its subtree, down to the depth it was exported to.
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeStructure<S: Summary> {
+    /// The address of the node
+    pub address: NodeAddress,
+    /// The distance from the center to the furthest point the node covers
+    pub radius: f32,
+    /// The number of points the node covers
+    pub coverage_count: usize,
+    /// The number of points the node owns directly
+    pub singletons_len: usize,
+    /// The summary of the labels of the covered points, if the tree has them
+    pub label_summary: Option<SummaryCounter<S>>,
+    /// The children, nested child first. Empty for leaves and for the nodes at the maximum depth.
+    pub children: Vec<NodeStructure<S>>,
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// The hierarchy under a node, down to `max_depth` below it. `None` if the node doesn't exist.
+    pub fn node_structure(
+        &self,
+        address: NodeAddress,
+        max_depth: usize,
+    ) -> Option<NodeStructure<D::LabelSummary>> {
+        let (mut structure, child_addresses) = self.get_node_and(address, |n| {
+            let structure = NodeStructure {
+                address,
+                radius: n.radius(),
+                coverage_count: n.coverage_count(),
+                singletons_len: n.singletons_len(),
+                label_summary: n.label_summary().map(|s| s.as_ref().clone()),
+                children: Vec::new(),
+            };
+            let child_addresses: Vec<NodeAddress> = match n.children() {
+                Some((nested_scale, child_addresses)) if max_depth > 0 => {
+                    std::iter::once((nested_scale, address.1))
+                        .chain(child_addresses.iter().copied())
+                        .collect()
+                }
+                _ => Vec::new(),
+            };
+            (structure, child_addresses)
+        })?;
+        structure.children = child_addresses
+            .iter()
+            .filter_map(|ca| self.node_structure(*ca, max_depth - 1))
+            .collect();
+        Some(structure)
+    }
+
+    /// The tree as a GraphViz digraph, down to `max_depth` below the root. Each node is labeled with its address,
+    /// radius and coverage count, and the number of labeled points and their entropy if the tree has labels.
+    pub fn to_dot(&self, max_depth: usize) -> String {
+        let mut dot = String::from("digraph covertree {\n    node [shape=box];\n");
+        if let Some(root) = self.node_structure(self.root_address(), max_depth) {
+            let mut stack = vec![&root];
+            while let Some(node) = stack.pop() {
+                let name = dot_name(node.address);
+                let mut label = format!(
+                    "{:?}\\nradius: {}\\ncoverage: {}",
+                    node.address, node.radius, node.coverage_count
+                );
+                if let Some(summary) = &node.label_summary {
+                    write!(label, "\\nlabeled: {}", summary.summary.count()).unwrap();
+                    if let Some(entropy) = summary.summary.entropy() {
+                        write!(label, "\\nentropy: {:.3}", entropy).unwrap();
+                    }
+                }
+                writeln!(dot, "    {} [label=\"{}\"];", name, label).unwrap();
+                for child in node.children.iter() {
+                    writeln!(dot, "    {} -> {};", name, dot_name(child.address)).unwrap();
+                    stack.push(child);
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// The tree as nested JSON objects, down to `max_depth` below the root. See [`NodeStructure`] for the fields.
+    pub fn to_json_tree(&self, max_depth: usize) -> GokoResult<String> {
+        Ok(serde_json::to_string(
+            &self.node_structure(self.root_address(), max_depth),
+        )?)
+    }
+}
+
+/// Node ids in GraphViz can't start with a minus sign unless they're quoted.
+fn dot_name(address: NodeAddress) -> String {
+    format!("\"{}_{}\"", address.0, address.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn structure_matches_the_tree() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let root = reader
+            .node_structure(reader.root_address(), usize::MAX)
+            .unwrap();
+        let mut count = 0;
+        let mut stack = vec![&root];
+        while let Some(node) = stack.pop() {
+            count += 1;
+            let child_coverage: usize = node.children.iter().map(|c| c.coverage_count).sum();
+            if !node.children.is_empty() {
+                assert_eq!(node.coverage_count, child_coverage + node.singletons_len);
+            }
+            assert!(node.label_summary.is_some());
+            stack.extend(node.children.iter());
+        }
+        assert_eq!(count, reader.node_count());
+
+        let top = reader.node_structure(reader.root_address(), 0).unwrap();
+        assert!(top.children.is_empty());
+    }
+
+    #[test]
+    fn dot_and_json_have_every_node() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let dot = reader.to_dot(usize::MAX);
+        assert!(dot.starts_with("digraph"));
+        assert_eq!(dot.matches("[label=").count(), reader.node_count());
+        assert_eq!(dot.matches(" -> ").count(), reader.node_count() - 1);
+
+        let json = reader.to_json_tree(1).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["address"][0], reader.root_address().0);
+        assert!(!value["children"].as_array().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,656 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Flat Trees
+//!
+//! Loading a protobuf tree decodes every node into RAM, which takes minutes for trees with tens of millions of
+//! nodes. The flat format is an offset based layout of the same nodes that can be memory mapped and queried where it
+//! lies. Opening a flat tree only reads the header and the root, the other nodes aren't read until a query touches
+//! them. [`FlatTree::verify`] checks every node record, run it once on a file you don't trust. A [`FlatTree`] is read only, save it from a writer with [`CoverTreeWriter::save_flat`] and open it
+//! with [`FlatTree::open`]. It answers the [`CoverTreeReader::knn`] and [`CoverTreeReader::path`] queries.
+//!
+//! The file is little endian throughout:
+//!
+//! | Section    | Contents |
+//! |------------|----------|
+//! | header     | `GOKOFLAT`, the format version, the tree parameters, the root and the section lengths |
+//! | nodes      | one fixed size record per node, sorted by `(scale_index, center_index)` |
+//! | children   | the child addresses, each node's children are a contiguous run |
+//! | singletons | the singleton indexes, each node's singletons are a contiguous run |
+//!
+//! Nodes are found by a binary search over their records. Plugins are not saved, attach them to a writer instead.
+
+use super::node::*;
+use super::query_tools::KnnQueryHeap;
+use super::*;
+use crate::errors::{GokoError, GokoResult};
+use crate::NodeAddress;
+use memmap2::Mmap;
+use pointcloud::*;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::Write;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 8] = b"GOKOFLAT";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 72;
+const NODE_LEN: usize = 72;
+const CHILD_LEN: usize = 16;
+const SINGLETON_LEN: usize = 8;
+
+const HAS_CHILDREN: u32 = 1;
+const HAS_PARENT: u32 = 2;
+
+const PARTITION_NEAREST: u32 = 0;
+const PARTITION_FIRST: u32 = 1;
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_i32(bytes: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_f32(bytes: &[u8], offset: usize) -> f32 {
+    f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+enum FlatBytes {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl Deref for FlatBytes {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            FlatBytes::Mapped(map) => map,
+            FlatBytes::Owned(bytes) => bytes,
+        }
+    }
+}
+
+/// A read only cover tree over a flat file, see the [module docs](self).
+pub struct FlatTree<D: PointCloud> {
+    bytes: FlatBytes,
+    point_cloud: Arc<D>,
+    scale_base: f32,
+    leaf_cutoff: usize,
+    min_res_index: i32,
+    use_singletons: bool,
+    partition_type: PartitionType,
+    root_address: NodeAddress,
+    node_count: usize,
+    child_count: usize,
+    singleton_count: usize,
+}
+
+/// A node of a [`FlatTree`], read straight out of its record.
+#[derive(Clone, Copy)]
+pub struct FlatNode<'a> {
+    record: &'a [u8],
+    children: &'a [u8],
+    singletons: &'a [u8],
+}
+
+impl<'a> FlatNode<'a> {
+    /// The address of the node
+    pub fn address(&self) -> NodeAddress {
+        (read_i32(self.record, 8), read_u64(self.record, 0) as usize)
+    }
+
+    /// The address of the node's parent, `None` for the root
+    pub fn parent_address(&self) -> Option<NodeAddress> {
+        if read_u32(self.record, 52) & HAS_PARENT != 0 {
+            Some((
+                read_i32(self.record, 56),
+                read_u64(self.record, 64) as usize,
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// The radius of the node's ball
+    pub fn radius(&self) -> f32 {
+        read_f32(self.record, 12)
+    }
+
+    /// The number of points covered by the node
+    pub fn coverage_count(&self) -> usize {
+        read_u64(self.record, 16) as usize
+    }
+
+    /// If the node has no children
+    pub fn is_leaf(&self) -> bool {
+        read_u32(self.record, 52) & HAS_CHILDREN == 0
+    }
+
+    /// The scale index of the nested child and the addresses of the others, like [`CoverNode::children`]
+    pub fn children(&self) -> Option<(i32, Vec<NodeAddress>)> {
+        if self.is_leaf() {
+            return None;
+        }
+        let start = read_u64(self.record, 24) as usize;
+        let len = read_u32(self.record, 32) as usize;
+        let addresses = (start..start + len)
+            .map(|i| {
+                let child = &self.children[i * CHILD_LEN..(i + 1) * CHILD_LEN];
+                (read_i32(child, 0), read_u64(child, 8) as usize)
+            })
+            .collect();
+        Some((read_i32(self.record, 36), addresses))
+    }
+
+    /// The points covered by the node that aren't the center of a child
+    pub fn singletons(&self) -> Vec<usize> {
+        let start = read_u64(self.record, 40) as usize;
+        let len = read_u32(self.record, 48) as usize;
+        (start..start + len)
+            .map(|i| read_u64(self.singletons, i * SINGLETON_LEN) as usize)
+            .collect()
+    }
+}
+
+impl<D: PointCloud> FlatTree<D> {
+    /// Memory maps a flat tree file. The header and the root are checked here and each node record is checked as
+    /// a query reads it, so a malformed or truncated file is never a panic in a query. A node with a malformed record
+    /// is treated as missing, see [`FlatTree::verify`] to find them up front.
+    ///
+    /// The file must not change while the tree is open.
+    pub fn open<P: AsRef<Path>>(path: P, point_cloud: Arc<D>) -> GokoResult<FlatTree<D>> {
+        let file = File::open(path)?;
+        let map = unsafe { Mmap::map(&file)? };
+        FlatTree::new(FlatBytes::Mapped(map), point_cloud)
+    }
+
+    /// Reads a flat tree out of a buffer, for trees that were written to memory.
+    pub fn from_bytes(bytes: Vec<u8>, point_cloud: Arc<D>) -> GokoResult<FlatTree<D>> {
+        FlatTree::new(FlatBytes::Owned(bytes), point_cloud)
+    }
+
+    fn new(bytes: FlatBytes, point_cloud: Arc<D>) -> GokoResult<FlatTree<D>> {
+        if bytes.len() < HEADER_LEN || &bytes[0..8] != MAGIC {
+            return Err(GokoError::InvalidFlatTree("the header is missing"));
+        }
+        if read_u32(&bytes, 8) != VERSION {
+            return Err(GokoError::InvalidFlatTree("unknown format version"));
+        }
+        let node_count = read_u64(&bytes, 48) as usize;
+        let child_count = read_u64(&bytes, 56) as usize;
+        let singleton_count = read_u64(&bytes, 64) as usize;
+        let expected_len = node_count
+            .checked_mul(NODE_LEN)
+            .zip(child_count.checked_mul(CHILD_LEN))
+            .zip(singleton_count.checked_mul(SINGLETON_LEN))
+            .and_then(|((nodes, children), singletons)| {
+                HEADER_LEN
+                    .checked_add(nodes)?
+                    .checked_add(children)?
+                    .checked_add(singletons)
+            });
+        if expected_len != Some(bytes.len()) {
+            return Err(GokoError::InvalidFlatTree(
+                "the file length doesn't match the header",
+            ));
+        }
+        let partition_type = match read_u32(&bytes, 36) {
+            PARTITION_NEAREST => PartitionType::Nearest,
+            PARTITION_FIRST => PartitionType::First,
+            _ => return Err(GokoError::InvalidFlatTree("unknown partition type")),
+        };
+        let tree = FlatTree {
+            scale_base: read_f32(&bytes, 12),
+            min_res_index: read_i32(&bytes, 16),
+            use_singletons: read_u32(&bytes, 20) != 0,
+            partition_type,
+            leaf_cutoff: read_u64(&bytes, 24) as usize,
+            root_address: (read_i32(&bytes, 32), read_u64(&bytes, 40) as usize),
+            node_count,
+            child_count,
+            singleton_count,
+            bytes,
+            point_cloud,
+        };
+        if tree.node(tree.root_address).is_none() {
+            return Err(GokoError::InvalidFlatTree("the root node is missing"));
+        }
+        Ok(tree)
+    }
+
+    /// Checks every node record: that they're sorted, for the binary search, and that their runs of children and
+    /// singletons are in the file. This reads the whole node section, so it's not done on open.
+    pub fn verify(&self) -> GokoResult<()> {
+        let mut previous: Option<NodeAddress> = None;
+        for i in 0..self.node_count {
+            let record = self.record(i);
+            let address = (read_i32(record, 8), read_u64(record, 0) as usize);
+            if previous.map_or(false, |previous| previous >= address) {
+                return Err(GokoError::InvalidFlatTree("the nodes aren't sorted"));
+            }
+            previous = Some(address);
+            self.check_runs(record)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the runs of children and singletons of a record are in the file.
+    fn check_runs(&self, record: &[u8]) -> GokoResult<()> {
+        let in_section = |start: u64, len: u32, count: usize| {
+            let start: Option<usize> = start.try_into().ok();
+            start
+                .and_then(|start| start.checked_add(len as usize))
+                .map_or(false, |end| end <= count)
+        };
+        if !in_section(read_u64(record, 24), read_u32(record, 32), self.child_count) {
+            return Err(GokoError::InvalidFlatTree(
+                "a node's children are past the end of the file",
+            ));
+        }
+        if !in_section(
+            read_u64(record, 40),
+            read_u32(record, 48),
+            self.singleton_count,
+        ) {
+            return Err(GokoError::InvalidFlatTree(
+                "a node's singletons are past the end of the file",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The point cloud the tree is over
+    pub fn point_cloud(&self) -> &Arc<D> {
+        &self.point_cloud
+    }
+
+    /// The address of the root
+    pub fn root_address(&self) -> NodeAddress {
+        self.root_address
+    }
+
+    /// The scale base the tree was built with
+    pub fn scale_base(&self) -> f32 {
+        self.scale_base
+    }
+
+    /// The leaf cutoff the tree was built with
+    pub fn leaf_cutoff(&self) -> usize {
+        self.leaf_cutoff
+    }
+
+    /// The minimum resolution index the tree was built with
+    pub fn min_res_index(&self) -> i32 {
+        self.min_res_index
+    }
+
+    /// If the tree was built with singletons
+    pub fn use_singletons(&self) -> bool {
+        self.use_singletons
+    }
+
+    /// How the tree picks the child a point goes to, see [`CoverTreeReader::path`]
+    pub fn partition_type(&self) -> PartitionType {
+        self.partition_type
+    }
+
+    /// The number of nodes in the tree
+    pub fn node_count(&self) -> usize {
+        self.node_count
+    }
+
+    fn children_bytes(&self) -> &[u8] {
+        let start = HEADER_LEN + self.node_count * NODE_LEN;
+        &self.bytes[start..start + self.child_count * CHILD_LEN]
+    }
+
+    fn singleton_bytes(&self) -> &[u8] {
+        let start = HEADER_LEN + self.node_count * NODE_LEN + self.child_count * CHILD_LEN;
+        &self.bytes[start..start + self.singleton_count * SINGLETON_LEN]
+    }
+
+    fn record(&self, i: usize) -> &[u8] {
+        let start = HEADER_LEN + i * NODE_LEN;
+        &self.bytes[start..start + NODE_LEN]
+    }
+
+    /// Finds the node with a binary search over the node records. This is `None` if the node's record is malformed.
+    pub fn node(&self, address: NodeAddress) -> Option<FlatNode> {
+        let (mut low, mut high) = (0, self.node_count);
+        while low < high {
+            let mid = (low + high) / 2;
+            let record = self.record(mid);
+            let mid_address = (read_i32(record, 8), read_u64(record, 0) as usize);
+            match mid_address.cmp(&address) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => {
+                    return self.check_runs(record).ok().map(|_| FlatNode {
+                        record,
+                        children: self.children_bytes(),
+                        singletons: self.singleton_bytes(),
+                    })
+                }
+            }
+        }
+        None
+    }
+
+    /// The same query as [`CoverTreeReader::knn`], reading the nodes out of the file as it goes.
+    pub fn knn<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let mut query_heap = KnnQueryHeap::new(k, self.scale_base);
+        let dist_to_root = self
+            .point_cloud
+            .distances_to_point(point, &[self.root_address.1])?[0];
+        query_heap.push_nodes(&[self.root_address], &[dist_to_root], None);
+        self.greedy_knn_nodes(point, &mut query_heap)?;
+
+        while let Some((_dist, address)) = query_heap.closest_unvisited_singleton_covering_address()
+        {
+            if let Some(node) = self.node(address) {
+                let singletons = node.singletons();
+                let distances = self.point_cloud.distances_to_point(point, &singletons)?;
+                query_heap.push_outliers(&singletons, &distances);
+            }
+            self.greedy_knn_nodes(point, &mut query_heap)?;
+        }
+        Ok(query_heap.unpack())
+    }
+
+    /// The same query as [`CoverTreeReader::path`], reading the nodes out of the file as it goes.
+    pub fn path<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+    ) -> GokoResult<Vec<(f32, NodeAddress)>> {
+        let root_center = self.point_cloud.point(self.root_address.1)?;
+        let mut current_distance = D::Metric::dist(&root_center, point);
+        let mut current_address = self.root_address;
+        let mut trace = vec![(current_distance, current_address)];
+        while let Some((nested_scale, children)) =
+            self.node(current_address).and_then(|n| n.children())
+        {
+            let next = match self.partition_type {
+                PartitionType::Nearest => nearest_covering_child(
+                    current_address,
+                    nested_scale,
+                    &children,
+                    self.scale_base,
+                    current_distance,
+                    point,
+                    &*self.point_cloud,
+                )?,
+                PartitionType::First => first_covering_child(
+                    current_address,
+                    nested_scale,
+                    &children,
+                    self.scale_base,
+                    current_distance,
+                    point,
+                    &*self.point_cloud,
+                )?,
+            };
+            match next {
+                Some(next) => {
+                    trace.push(next);
+                    current_distance = next.0;
+                    current_address = next.1;
+                }
+                None => break,
+            }
+        }
+        Ok(trace)
+    }
+
+    fn greedy_knn_nodes<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        query_heap: &mut KnnQueryHeap,
+    ) -> GokoResult<()> {
+        while let Some((dist, address)) = query_heap.closest_unvisited_child_covering_address() {
+            let (nested_scale, children) = match self.node(address).and_then(|n| n.children()) {
+                Some(children) => children,
+                None => break,
+            };
+            push_children(
+                address,
+                nested_scale,
+                &children,
+                dist,
+                point,
+                &*self.point_cloud,
+                query_heap,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn write_node_record<W: Write, D: PointCloud>(
+    writer: &mut W,
+    node: &CoverNode<D>,
+    children_start: usize,
+    singletons_start: usize,
+) -> GokoResult<()> {
+    let mut record = [0u8; NODE_LEN];
+    let mut flags = 0;
+    record[0..8].copy_from_slice(&(*node.center_index() as u64).to_le_bytes());
+    record[8..12].copy_from_slice(&node.scale_index().to_le_bytes());
+    record[12..16].copy_from_slice(&node.radius().to_le_bytes());
+    record[16..24].copy_from_slice(&(node.coverage_count() as u64).to_le_bytes());
+    record[24..32].copy_from_slice(&(children_start as u64).to_le_bytes());
+    if let Some((nested_scale, children)) = node.children() {
+        flags |= HAS_CHILDREN;
+        record[32..36].copy_from_slice(&(children.len() as u32).to_le_bytes());
+        record[36..40].copy_from_slice(&nested_scale.to_le_bytes());
+    }
+    record[40..48].copy_from_slice(&(singletons_start as u64).to_le_bytes());
+    record[48..52].copy_from_slice(&(node.singletons_len() as u32).to_le_bytes());
+    if let Some((parent_scale, parent_center)) = node.parent_address() {
+        flags |= HAS_PARENT;
+        record[56..60].copy_from_slice(&parent_scale.to_le_bytes());
+        record[64..72].copy_from_slice(&(parent_center as u64).to_le_bytes());
+    }
+    record[52..56].copy_from_slice(&flags.to_le_bytes());
+    writer.write_all(&record)?;
+    Ok(())
+}
+
+impl<D: PointCloud> CoverTreeWriter<D> {
+    /// Writes the tree in the flat format, see the [module docs](self). This is the published state of the tree,
+    /// like [`CoverTreeWriter::save`].
+    pub fn save_flat<W: Write>(&self, writer: &mut W) -> GokoResult<()> {
+        let reader = self.reader();
+        let mut addresses = Vec::new();
+        for (_, layer) in reader.layers() {
+            layer.for_each_node(|_, n| addresses.push(n.address()));
+        }
+        addresses.sort_unstable();
+
+        let mut children = Vec::new();
+        let mut singletons = Vec::new();
+        let mut records = Vec::with_capacity(addresses.len() * NODE_LEN);
+        for address in &addresses {
+            reader
+                .get_node_and(*address, |n| -> GokoResult<()> {
+                    write_node_record(&mut records, n, children.len(), singletons.len())?;
+                    if let Some((_, child_addresses)) = n.children() {
+                        children.extend_from_slice(child_addresses);
+                    }
+                    singletons.extend_from_slice(n.singletons());
+                    Ok(())
+                })
+                .ok_or(GokoError::IndexNotInTree(address.1))??;
+        }
+
+        let mut header = [0u8; HEADER_LEN];
+        header[0..8].copy_from_slice(MAGIC);
+        header[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        header[12..16].copy_from_slice(&self.parameters.scale_base.to_le_bytes());
+        header[16..20].copy_from_slice(&self.parameters.min_res_index.to_le_bytes());
+        header[20..24].copy_from_slice(&(self.parameters.use_singletons as u32).to_le_bytes());
+        header[24..32].copy_from_slice(&(self.parameters.leaf_cutoff as u64).to_le_bytes());
+        header[32..36].copy_from_slice(&self.root_address.0.to_le_bytes());
+        let partition_type = match self.parameters.partition_type {
+            PartitionType::Nearest => PARTITION_NEAREST,
+            PartitionType::First => PARTITION_FIRST,
+        };
+        header[36..40].copy_from_slice(&partition_type.to_le_bytes());
+        header[40..48].copy_from_slice(&(self.root_address.1 as u64).to_le_bytes());
+        header[48..56].copy_from_slice(&(addresses.len() as u64).to_le_bytes());
+        header[56..64].copy_from_slice(&(children.len() as u64).to_le_bytes());
+        header[64..72].copy_from_slice(&(singletons.len() as u64).to_le_bytes());
+        writer.write_all(&header)?;
+        writer.write_all(&records)?;
+        for (scale_index, center_index) in children {
+            let mut child = [0u8; CHILD_LEN];
+            child[0..4].copy_from_slice(&scale_index.to_le_bytes());
+            child[8..16].copy_from_slice(&(center_index as u64).to_le_bytes());
+            writer.write_all(&child)?;
+        }
+        for pi in singletons {
+            writer.write_all(&(pi as u64).to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn flat_tree_matches_writer() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let mut bytes = Vec::new();
+        tree.save_flat(&mut bytes).unwrap();
+        let flat = FlatTree::from_bytes(bytes, Arc::clone(&tree.parameters.point_cloud)).unwrap();
+
+        assert_eq!(flat.root_address(), reader.root_address());
+        assert_eq!(flat.node_count(), reader.node_count());
+        for (_, layer) in reader.layers() {
+            layer.for_each_node(|_, n| {
+                let flat_node = flat.node(n.address()).unwrap();
+                assert_eq!(flat_node.radius(), n.radius());
+                assert_eq!(flat_node.coverage_count(), n.coverage_count());
+                assert_eq!(flat_node.parent_address(), n.parent_address());
+                assert_eq!(flat_node.singletons(), n.singletons());
+                assert_eq!(
+                    flat_node.children(),
+                    n.children().map(|(si, c)| (si, c.to_vec()))
+                );
+            });
+        }
+        for x in &[-0.5f32, 0.0, 0.3, 0.5] {
+            assert_eq!(
+                flat.knn(&[*x].as_ref(), 3).unwrap(),
+                reader.knn(&[*x].as_ref(), 3).unwrap()
+            );
+            assert_eq!(
+                flat.path(&[*x].as_ref()).unwrap(),
+                reader.path(&[*x].as_ref()).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_flat_trees_are_errors() {
+        let tree = build_basic_tree();
+        let point_cloud = &tree.parameters.point_cloud;
+        let mut bytes = Vec::new();
+        tree.save_flat(&mut bytes).unwrap();
+        let is_invalid = |bytes: Vec<u8>| {
+            matches!(
+                FlatTree::from_bytes(bytes, Arc::clone(point_cloud)).and_then(|flat| flat.verify()),
+                Err(GokoError::InvalidFlatTree(_))
+            )
+        };
+
+        // Node counts that overflow the expected length
+        let mut overflowing = bytes.clone();
+        overflowing[48..56].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(is_invalid(overflowing));
+
+        // A run of singletons past the end of the section
+        let mut long_run = bytes.clone();
+        long_run[HEADER_LEN + 48..HEADER_LEN + 52].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(is_invalid(long_run));
+        let mut far_start = bytes.clone();
+        far_start[HEADER_LEN + 24..HEADER_LEN + 32].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(is_invalid(far_start));
+
+        // The first two records swapped
+        let mut unsorted = bytes.clone();
+        let first: Vec<u8> = unsorted[HEADER_LEN..HEADER_LEN + NODE_LEN].to_vec();
+        unsorted.copy_within(HEADER_LEN + NODE_LEN..HEADER_LEN + 2 * NODE_LEN, HEADER_LEN);
+        unsorted[HEADER_LEN + NODE_LEN..HEADER_LEN + 2 * NODE_LEN].copy_from_slice(&first);
+        assert!(is_invalid(unsorted));
+
+        let mut partition = bytes.clone();
+        partition[36..40].copy_from_slice(&7u32.to_le_bytes());
+        assert!(is_invalid(partition));
+
+        // A malformed record that isn't the root opens, the queries skip it and verify finds it
+        let child_address = tree
+            .reader()
+            .get_node_and(tree.root_address, |n| n.children().unwrap().1[0])
+            .unwrap();
+        let mut bad_child = bytes.clone();
+        let child_record = (0..tree.reader().node_count())
+            .map(|i| HEADER_LEN + i * NODE_LEN)
+            .find(|start| {
+                (
+                    read_i32(&bad_child, start + 8),
+                    read_u64(&bad_child, *start) as usize,
+                ) == child_address
+            })
+            .unwrap();
+        bad_child[child_record + 48..child_record + 52].copy_from_slice(&u32::MAX.to_le_bytes());
+        let flat = FlatTree::from_bytes(bad_child, Arc::clone(point_cloud)).unwrap();
+        assert!(flat.node(child_address).is_none());
+        flat.knn(&[0.0f32].as_ref(), 3).unwrap();
+        flat.path(&[0.0f32].as_ref()).unwrap();
+        assert!(matches!(flat.verify(), Err(GokoError::InvalidFlatTree(_))));
+
+        let flat = FlatTree::from_bytes(bytes, Arc::clone(point_cloud)).unwrap();
+        assert!(flat.verify().is_ok());
+    }
+
+    #[test]
+    fn truncated_flat_tree_is_an_error() {
+        let tree = build_basic_tree();
+        let mut bytes = Vec::new();
+        tree.save_flat(&mut bytes).unwrap();
+        bytes.pop();
+        assert!(FlatTree::from_bytes(bytes, Arc::clone(&tree.parameters.point_cloud)).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Forests
+//!
+//! A single tree depends on the random choice of centers made while building it, so its approximate queries and
+//! drift scores carry some noise from that choice. A [`CoverForest`] builds several trees over the same point cloud,
+//! with different seeds or scale bases, and answers queries with the whole ensemble. The knn results are merged and
+//! the drift is tracked on every tree, reported as the mean and variance across the trees.
+
+use super::*;
+use crate::errors::GokoResult;
+use crate::plugins::discrete::prelude::*;
+use pointcloud::*;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Several cover trees over the same point cloud, see the [module docs](self).
+pub struct CoverForest<D: PointCloud> {
+    trees: Vec<CoverTreeWriter<D>>,
+}
+
+impl<D: PointCloud> CoverForest<D> {
+    /// Builds one tree per seed, the builder's other parameters are shared.
+    pub fn build(
+        builder: &CoverTreeBuilder,
+        point_cloud: Arc<D>,
+        seeds: &[u64],
+    ) -> GokoResult<CoverForest<D>> {
+        let builders: Vec<CoverTreeBuilder> = seeds
+            .iter()
+            .map(|seed| {
+                let mut builder = builder.clone();
+                builder.set_rng_seed(*seed);
+                builder
+            })
+            .collect();
+        CoverForest::build_with(&builders, point_cloud)
+    }
+
+    /// Builds one tree per builder, for forests whose trees differ in more than their seed.
+    pub fn build_with(
+        builders: &[CoverTreeBuilder],
+        point_cloud: Arc<D>,
+    ) -> GokoResult<CoverForest<D>> {
+        let trees = builders
+            .iter()
+            .map(|builder| builder.build(Arc::clone(&point_cloud)))
+            .collect::<GokoResult<Vec<_>>>()?;
+        Ok(CoverForest { trees })
+    }
+
+    /// Wraps trees that were already built. They should all be over the same point cloud.
+    pub fn from_trees(trees: Vec<CoverTreeWriter<D>>) -> CoverForest<D> {
+        CoverForest { trees }
+    }
+
+    /// The trees of the forest
+    pub fn trees(&self) -> &[CoverTreeWriter<D>] {
+        &self.trees
+    }
+
+    /// The trees of the forest, to add plugins or points to them
+    pub fn trees_mut(&mut self) -> &mut [CoverTreeWriter<D>] {
+        &mut self.trees
+    }
+
+    /// The number of trees in the forest
+    pub fn len(&self) -> usize {
+        self.trees.len()
+    }
+
+    /// If the forest has no trees
+    pub fn is_empty(&self) -> bool {
+        self.trees.is_empty()
+    }
+
+    /// A reader for each tree, for the ensemble queries.
+    pub fn reader(&self) -> CoverForestReader<D> {
+        CoverForestReader {
+            readers: self.trees.iter().map(|t| t.reader()).collect(),
+        }
+    }
+}
+
+/// Reads all the trees of a [`CoverForest`] at once.
+#[derive(Clone)]
+pub struct CoverForestReader<D: PointCloud> {
+    readers: Vec<CoverTreeReader<D>>,
+}
+
+/// Merges the knn results of the trees. The trees share a point cloud, so a point found by several trees is at the
+/// same distance in each and only kept once.
+fn merge_knn(results: Vec<Vec<(f32, usize)>>, k: usize) -> Vec<(f32, usize)> {
+    let mut merged: Vec<(f32, usize)> = results.into_iter().flatten().collect();
+    merged.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    merged.dedup_by_key(|(_, pi)| *pi);
+    merged.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    merged.truncate(k);
+    merged
+}
+
+impl<D: PointCloud> CoverForestReader<D> {
+    /// The readers of the trees
+    pub fn readers(&self) -> &[CoverTreeReader<D>] {
+        &self.readers
+    }
+
+    /// The knn of every tree, merged. Each tree's knn is exact, so this is the same as any one of them, it's here
+    /// for completeness.
+    pub fn knn<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let results = self
+            .readers
+            .iter()
+            .map(|r| r.knn(point, k))
+            .collect::<GokoResult<Vec<_>>>()?;
+        Ok(merge_knn(results, k))
+    }
+
+    /// The approximate knn of every tree, see [`CoverTreeReader::approx_knn`], merged. The trees miss different
+    /// points, so the merged result is closer to the true knn than any one tree's.
+    pub fn approx_knn<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+        epsilon: f32,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let results = self
+            .readers
+            .iter()
+            .map(|r| r.approx_knn(point, k, epsilon))
+            .collect::<GokoResult<Vec<_>>>()?;
+        Ok(merge_knn(results, k))
+    }
+
+    /// A drift tracker on every tree, see [`BayesCategoricalTracker`]. The trees need the [`GokoDirichlet`] plugin.
+    pub fn tracker(&self, window_size: usize) -> ForestTracker<D> {
+        ForestTracker {
+            trackers: self
+                .readers
+                .iter()
+                .map(|r| BayesCategoricalTracker::new(window_size, r.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// The drift of a forest, the KL divergence of each tree's tracker and their mean and variance.
+#[derive(Debug, Clone)]
+pub struct ForestDrift {
+    /// The KL divergence of each tree, in the order of the trees
+    pub per_tree: Vec<f64>,
+    /// The mean of the trees' KL divergences
+    pub mean: f64,
+    /// The variance of the trees' KL divergences
+    pub variance: f64,
+}
+
+/// Tracks the same stream of points on every tree of a forest.
+#[derive(Debug)]
+pub struct ForestTracker<D: PointCloud> {
+    trackers: Vec<BayesCategoricalTracker<D>>,
+}
+
+impl<D: PointCloud> ForestTracker<D> {
+    /// Adds the point's path in each tree to that tree's tracker.
+    pub fn add_point<P: Deref<Target = D::Point> + Send + Sync>(
+        &mut self,
+        point: &P,
+    ) -> GokoResult<()> {
+        for tracker in self.trackers.iter_mut() {
+            let path = tracker.reader().path(point)?;
+            tracker.add_path(path);
+        }
+        Ok(())
+    }
+
+    /// The trackers of the trees
+    pub fn trackers(&self) -> &[BayesCategoricalTracker<D>] {
+        &self.trackers
+    }
+
+    /// The trackers of the trees, to set their decay, priors or baselines
+    pub fn trackers_mut(&mut self) -> &mut [BayesCategoricalTracker<D>] {
+        &mut self.trackers
+    }
+
+    /// The KL divergence of each tree, with the mean and variance across the trees.
+    pub fn drift(&self) -> ForestDrift {
+        let per_tree: Vec<f64> = self.trackers.iter().map(|t| t.kl_div()).collect();
+        let count = per_tree.len().max(1) as f64;
+        let mean = per_tree.iter().sum::<f64>() / count;
+        let variance = per_tree
+            .iter()
+            .map(|kl| (kl - mean) * (kl - mean))
+            .sum::<f64>()
+            / count;
+        ForestDrift {
+            per_tree,
+            mean,
+            variance,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    fn build_forest() -> CoverForest<DefaultLabeledCloud<L2>> {
+        let point_cloud = Arc::clone(&build_basic_tree().parameters.point_cloud);
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_leaf_cutoff(1).set_min_res_index(-9);
+        CoverForest::build(&builder, point_cloud, &[0, 1, 2]).unwrap()
+    }
+
+    #[test]
+    fn forest_knn_matches_a_tree() {
+        let forest = build_forest();
+        let reader = forest.reader();
+        assert_eq!(reader.readers().len(), 3);
+        for x in &[-0.5f32, 0.0, 0.3, 0.5] {
+            assert_eq!(
+                reader.knn(&[*x].as_ref(), 3).unwrap(),
+                forest.trees()[0].reader().knn(&[*x].as_ref(), 3).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn forest_drift_covers_every_tree() {
+        let mut forest = build_forest();
+        for tree in forest.trees_mut() {
+            tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        }
+        let mut tracker = forest.reader().tracker(10);
+        for x in &[0.49f32, 0.49, 0.48] {
+            tracker.add_point(&[*x].as_ref()).unwrap();
+        }
+        let drift = tracker.drift();
+        assert_eq!(drift.per_tree.len(), 3);
+        assert!(drift.mean > 0.0);
+        assert!(drift.variance >= 0.0);
+    }
+}
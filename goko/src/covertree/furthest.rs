@@ -0,0 +1,144 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Furthest point and diameter queries
+//!
+//! [`CoverTreeReader::furthest_point`] is the opposite of a 1-NN query. It walks the tree with a
+//! [`FurthestQueryHeap`], opening the node that could cover the furthest point first and dropping the nodes whose
+//! center distance plus radius can't beat the furthest point found. [`CoverTreeReader::approx_diameter`] chains two of
+//! these queries.
+
+use super::query_tools::{FurthestQueryHeap, SingletonQueryHeap};
+use super::*;
+use crate::errors::GokoResult;
+use crate::NodeAddress;
+use pointcloud::*;
+use std::ops::Deref;
+
+/// Two points that are far apart, see [`CoverTreeReader::approx_diameter`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Diameter {
+    /// The distance between the two points
+    pub dist: f32,
+    /// The indexes of the two points
+    pub ends: (usize, usize),
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// The point in the tree that's the furthest from the query point, the distance and its index.
+    pub fn furthest_point<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+    ) -> GokoResult<(f32, usize)> {
+        let point_cloud = &self.parameters().point_cloud;
+        let root_center = point_cloud.point(self.root_address().1)?;
+        let root_dist = D::Metric::dist(&root_center, &point);
+        let root_radius = self
+            .get_node_and(self.root_address(), |n| n.radius())
+            .unwrap_or(0.0);
+        let mut query_heap = FurthestQueryHeap::new();
+        query_heap.push_nodes(&[self.root_address()], &[root_dist], &[root_radius]);
+        while let Some((dist, address)) = query_heap.furthest_unvisited_address() {
+            let children: Option<GokoResult<Vec<(f32, NodeAddress)>>> =
+                self.get_node_and(address, |n| {
+                    let singleton_dists = point_cloud.distances_to_point(point, n.singletons())?;
+                    query_heap.push_outliers(n.singletons(), &singleton_dists);
+                    match n.children() {
+                        None => Ok(Vec::new()),
+                        Some((nested_scale, child_addresses)) => {
+                            let centers: Vec<usize> =
+                                child_addresses.iter().map(|ca| ca.1).collect();
+                            let child_dists = point_cloud.distances_to_point(point, &centers)?;
+                            let mut children: Vec<(f32, NodeAddress)> = child_dists
+                                .into_iter()
+                                .zip(child_addresses.iter().cloned())
+                                .collect();
+                            // The nested child shares our center, so we already know its distance
+                            children.push((dist, (nested_scale, address.1)));
+                            Ok(children)
+                        }
+                    }
+                });
+            if let Some(children) = children {
+                let (dists, addresses): (Vec<f32>, Vec<NodeAddress>) =
+                    children?.into_iter().unzip();
+                let radii: Vec<f32> = addresses
+                    .iter()
+                    .map(|ca| self.get_node_and(*ca, |n| n.radius()).unwrap_or(0.0))
+                    .collect();
+                query_heap.push_nodes(&addresses, &dists, &radii);
+            }
+        }
+        Ok(query_heap
+            .furthest()
+            .unwrap_or((root_dist, self.root_address().1)))
+    }
+
+    /// Same as [`Self::furthest_point`] for a point that's in the point cloud.
+    pub fn furthest_point_index(&self, point_index: usize) -> GokoResult<(f32, usize)> {
+        let point = self.parameters().point_cloud.point(point_index)?;
+        self.furthest_point(&point)
+    }
+
+    /// Two points that are about as far apart as any two points in the tree. This finds the point furthest from the
+    /// root's center, then the point furthest from that. The distance between them is at least half the diameter,
+    /// and usually much closer to it.
+    pub fn approx_diameter(&self) -> GokoResult<Diameter> {
+        let (_, first) = self.furthest_point_index(self.root_address().1)?;
+        let (dist, second) = self.furthest_point_index(first)?;
+        Ok(Diameter {
+            dist,
+            ends: (first, second),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn furthest_point_matches_a_scan() {
+        let basic_tree_data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        for query in &[-1.0, -0.49, 0.0, 0.2, 0.5, 3.0] {
+            let (dist, index) = reader.furthest_point(&&[*query][..]).unwrap();
+            let expected = basic_tree_data
+                .iter()
+                .map(|x: &f32| (x - query).abs())
+                .fold(0.0, f32::max);
+            assert_approx_eq!(dist, expected);
+            assert_approx_eq!((basic_tree_data[index] - query).abs(), expected);
+        }
+    }
+
+    #[test]
+    fn diameter_of_the_basic_tree() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let diameter = reader.approx_diameter().unwrap();
+        // The points are on a line, so the double sweep finds the two ends
+        assert_approx_eq!(diameter.dist, 0.499 + 0.49);
+        let mut ends = [diameter.ends.0, diameter.ends.1];
+        ends.sort();
+        assert_eq!(ends, [0, 3]);
+    }
+}
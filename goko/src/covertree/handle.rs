@@ -0,0 +1,212 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Single Process Handle
+//!
+//! The writer and the readers are built to live on different threads, the readers only see the writer's edits once
+//! it refreshes. An embedder that edits the tree and queries it right away in the same process has to refresh after
+//! every edit, and a refresh swaps every layer. [`CoverTree`] owns a writer and a reader together. With read through
+//! on, the writer leaves its edits pending and the node lookups of the handle apply them on the fly, so a
+//! refresh is only needed before the full queries.
+
+use super::node::CoverNode;
+use super::*;
+use crate::errors::{GokoError, GokoResult};
+use crate::NodeAddress;
+use pointcloud::*;
+
+/// A writer and a reader in one handle, for single process embedders.
+///
+/// By default this behaves like a writer that you query through its reader: every edit is published as it's made.
+/// With [`CoverTree::set_read_through`] the edits stay pending until [`CoverTree::refresh`], and
+/// [`CoverTree::get_node_and`], [`CoverTree::get_node_plugin_and`] and [`CoverTree::known_path`] see them anyway.
+/// Only the few nodes touched since the last refresh pay for this, by replaying their pending edits onto a copy.
+/// The queries on [`CoverTree::reader`], like the knn, see the tree as of the last refresh.
+pub struct CoverTree<D: PointCloud> {
+    writer: CoverTreeWriter<D>,
+    reader: CoverTreeReader<D>,
+}
+
+impl<D: PointCloud> CoverTree<D> {
+    /// Takes ownership of the writer, read through starts off.
+    pub fn new(writer: CoverTreeWriter<D>) -> CoverTree<D> {
+        let reader = writer.reader();
+        CoverTree { writer, reader }
+    }
+
+    /// Turns read through on or off. Turning it off publishes the pending edits.
+    pub fn set_read_through(&mut self, read_through: bool) {
+        self.writer.defer_refresh = read_through;
+        if !read_through {
+            self.refresh();
+        }
+    }
+
+    /// If the edits are left pending and read through by the handle.
+    pub fn read_through(&self) -> bool {
+        self.writer.defer_refresh
+    }
+
+    /// The writer, for edits.
+    pub fn writer(&mut self) -> &mut CoverTreeWriter<D> {
+        &mut self.writer
+    }
+
+    /// The reader, it only sees the edits that were refreshed.
+    pub fn reader(&self) -> &CoverTreeReader<D> {
+        &self.reader
+    }
+
+    /// Publishes the pending edits to the reader, and any other reader of this tree. If an insert put a new root
+    /// above the old one the handle's reader is replaced, see [`CoverTreeWriter::insert_point`].
+    pub fn refresh(&mut self) {
+        self.writer.refresh();
+        if self.reader.root_address() != self.writer.root_address {
+            self.reader = self.writer.reader();
+        }
+    }
+
+    /// Gives the writer back, publishing the pending edits first.
+    pub fn into_writer(mut self) -> CoverTreeWriter<D> {
+        self.set_read_through(false);
+        self.writer
+    }
+
+    /// Read only access to the internals of a node, with the pending edits if read through is on.
+    pub fn get_node_and<F, T>(&self, node_address: NodeAddress, f: F) -> Option<T>
+    where
+        F: FnOnce(&CoverNode<D>) -> T,
+    {
+        if self.read_through() {
+            self.writer.get_node_pending_and(node_address, f)
+        } else {
+            self.reader.get_node_and(node_address, f)
+        }
+    }
+
+    /// Reads the contents of a node's plugin, with the pending edits if read through is on.
+    pub fn get_node_plugin_and<T: Send + Sync + 'static, F, S>(
+        &self,
+        node_address: NodeAddress,
+        transform_fn: F,
+    ) -> Option<S>
+    where
+        F: FnOnce(&T) -> S,
+    {
+        self.get_node_and(node_address, |n| n.get_plugin_and(transform_fn))
+            .flatten()
+    }
+
+    /// The path from the root to the node that owns the point, with the pending edits if read through is on. See
+    /// [`CoverTreeReader::known_path`].
+    pub fn known_path(&self, point_index: usize) -> GokoResult<Vec<(f32, NodeAddress)>> {
+        if !self.read_through() {
+            return self.reader.known_path(point_index);
+        }
+        let mut path = self
+            .writer
+            .known_addresses_pending(point_index)
+            .ok_or(GokoError::IndexNotInTree(point_index))?;
+        path.reverse();
+        let point_indexes: Vec<usize> = path.iter().map(|na| na.1).collect();
+        let dists = self
+            .writer
+            .parameters
+            .point_cloud
+            .distances_to_point_index(point_index, &point_indexes)?;
+        Ok(dists.into_iter().zip(path).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_random_tree;
+
+    #[test]
+    fn read_through_sees_pending_removal() {
+        let mut tree = CoverTree::new(build_random_tree());
+        tree.set_read_through(true);
+
+        let reader = tree.reader().clone();
+        let mut singleton = None;
+        for (_si, layer) in reader.layers() {
+            layer.for_each_node(|_pi, n| {
+                if singleton.is_none() && !n.singletons().is_empty() {
+                    singleton = Some((n.address(), n.singletons()[0], n.coverage_count()));
+                }
+            });
+        }
+        let (owner, pi, coverage) = singleton.unwrap();
+
+        tree.writer().remove_point(pi).unwrap();
+        assert!(tree.known_path(pi).is_err());
+        assert!(tree.reader().known_path(pi).is_ok());
+        assert_eq!(
+            tree.get_node_and(owner, |n| n.coverage_count()),
+            Some(coverage - 1)
+        );
+        assert_eq!(
+            tree.reader().get_node_and(owner, |n| n.coverage_count()),
+            Some(coverage)
+        );
+
+        tree.refresh();
+        assert!(tree.reader().known_path(pi).is_err());
+        assert_eq!(
+            tree.reader().get_node_and(owner, |n| n.coverage_count()),
+            Some(coverage - 1)
+        );
+    }
+
+    #[test]
+    fn read_through_sees_pending_plugin_updates() {
+        use crate::plugins::discrete::prelude::*;
+        let mut writer = build_random_tree();
+        writer.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let mut tree = CoverTree::new(writer);
+        tree.set_read_through(true);
+
+        let reader = tree.reader().clone();
+        let mut singleton = None;
+        for (_si, layer) in reader.layers() {
+            layer.for_each_node(|_pi, n| {
+                if singleton.is_none() && !n.singletons().is_empty() {
+                    singleton = Some((n.address(), n.singletons()[0]));
+                }
+            });
+        }
+        let (owner, pi) = singleton.unwrap();
+        let total = reader
+            .get_node_plugin_and::<Dirichlet, _, _>(owner, |d| d.total())
+            .unwrap();
+
+        tree.writer().remove_point(pi).unwrap();
+        let pending_total = tree
+            .get_node_plugin_and::<Dirichlet, _, _>(owner, |d| d.total())
+            .expect("the pending copy should keep the plugin");
+        assert_approx_eq!(pending_total, total - 1.0);
+        assert_approx_eq!(
+            reader
+                .get_node_plugin_and::<Dirichlet, _, _>(owner, |d| d.total())
+                .unwrap(),
+            total
+        );
+    }
+}
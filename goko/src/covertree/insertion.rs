@@ -0,0 +1,399 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Point Insertion
+//!
+//! Points can be added to a built tree with [`CoverTreeWriter::insert_point`]. The point is routed down the tree
+//! like a [`CoverTreeReader::path`] query and lands in the node where the query would end. The radii and coverage
+//! counts on the way down grow to take it in, and a leaf that grows past the `leaf_cutoff` is split the way the
+//! build would have split it. A point outside the root's cover gets a new root above the old one.
+//!
+//! [`CoverTreeWriter::insert_point`] takes a point that's already in the cloud, one that was taken out with
+//! [`CoverTreeWriter::remove_point`]. New points go through [`CoverTreeWriter::append_point`], which needs a cloud
+//! that can grow behind the readers' backs, like a [`pointcloud::AppendableCloud`]. The nodes made by an insert get
+//! the components of the attached plugins.
+
+use super::builders::build_subtree;
+use super::*;
+use crate::errors::{GokoError, GokoResult};
+use crate::plugins::PluginEvent;
+use crate::NodeAddress;
+use pointcloud::pc_errors::PointCloudError;
+use pointcloud::*;
+use std::sync::{atomic, Arc};
+
+impl<D: PointCloud> CoverTreeWriter<D> {
+    /// Adds a point of the point cloud to the tree and returns the address of the node that owns it. The plugins
+    /// are told about it after it's in, see [`CoverTreeWriter::plugins_on_insert`], and the nodes the insert made
+    /// get their components computed. The insert is published to the readers, unless the refreshes are deferred by
+    /// a [`CoverTree`]. Computing the components of new nodes refreshes the tree even then.
+    ///
+    /// If the point is outside the cover of the root a new root is made above it. Readers made before that keep
+    /// querying from the old root, make a new one with [`CoverTreeWriter::reader`] or refresh the [`CoverTree`].
+    ///
+    /// Errors with [`GokoError::PointAlreadyInTree`] if the point is already in the tree, and with the point
+    /// cloud's error if it doesn't have the point.
+    pub fn insert_point(&mut self, point_index: usize) -> GokoResult<NodeAddress> {
+        if self.final_address_pending(point_index).is_some() {
+            return Err(GokoError::PointAlreadyInTree(point_index));
+        }
+        let point_cloud = Arc::clone(&self.parameters.point_cloud);
+        let point = point_cloud.point(point_index)?;
+        let scale_base = self.parameters.scale_base;
+        let mut new_nodes = Vec::new();
+
+        let root_center = point_cloud.point(self.root_address.1)?;
+        let root_distance = D::Metric::dist(&root_center, &point);
+        if !root_distance.is_finite() {
+            return Err(PointCloudError::data_access(
+                point_index,
+                "The point is an infinite distance from the root".to_string(),
+            )
+            .into());
+        }
+        if root_distance >= scale_base.powi(self.root_address.0) {
+            new_nodes.push(self.promote_root(root_distance)?);
+        }
+
+        let path = self.insertion_path(&point)?;
+        let (distance, owner) = *path.last().unwrap();
+        let (is_leaf, nested_scale, coverage) = self
+            .get_node_pending_and(owner, |n| {
+                (
+                    n.is_leaf(),
+                    n.children().map(|(nested_scale, _)| nested_scale),
+                    n.singletons_len() + 1,
+                )
+            })
+            .ok_or(GokoError::IndexNotInTree(owner.1))?;
+
+        unsafe {
+            for (d, address) in &path[..path.len() - 1] {
+                let d = *d;
+                self.update_node(*address, move |n| {
+                    n.set_coverage_count(n.coverage_count() + 1);
+                    n.set_radius(n.radius().max(d));
+                });
+            }
+        }
+        let final_address = match nested_scale {
+            // A routing node that none of its children cover the point, the point gets its own leaf if the tree
+            // doesn't use singletons
+            Some(nested_scale) if !self.parameters.use_singletons => {
+                let leaf_address = (nested_scale, point_index);
+                unsafe {
+                    self.insert_raw(
+                        nested_scale,
+                        point_index,
+                        CoverNode::new(Some(owner), leaf_address),
+                    );
+                    self.update_node(owner, move |n| {
+                        n.set_radius(n.radius().max(distance));
+                        n.insert_child(leaf_address, 1).unwrap();
+                    });
+                }
+                self.parameters
+                    .total_nodes
+                    .fetch_add(1, atomic::Ordering::SeqCst);
+                new_nodes.push(leaf_address);
+                leaf_address
+            }
+            _ => {
+                unsafe {
+                    self.update_node(owner, move |n| {
+                        n.set_radius(n.radius().max(distance));
+                        n.insert_singleton(point_index);
+                    });
+                }
+                owner
+            }
+        };
+        self.final_addresses.insert(point_index, final_address);
+        // The new nodes are computed with the point in them, so the hooks skip them
+        self.apply_plugin_hooks(point_index, PluginEvent::Insert, &new_nodes)?;
+        if is_leaf && coverage + 1 > self.parameters.leaf_cutoff {
+            new_nodes.extend(self.split_leaf(owner)?);
+        }
+        self.build_plugin_components(&new_nodes);
+        self.publish();
+        self.final_address_pending(point_index)
+            .ok_or(GokoError::IndexNotInTree(point_index))
+    }
+
+    /// Adds the points to the tree in order, see [`CoverTreeWriter::insert_point`]. The inserts are published to the
+    /// readers once at the end. If a point can't be inserted the points before it stay in the tree.
+    pub fn insert_points(&mut self, point_indexes: &[usize]) -> GokoResult<Vec<NodeAddress>> {
+        let defer_refresh = self.defer_refresh;
+        self.defer_refresh = true;
+        let owners: GokoResult<Vec<NodeAddress>> = point_indexes
+            .iter()
+            .map(|pi| self.insert_point(*pi))
+            .collect();
+        self.defer_refresh = defer_refresh;
+        self.publish();
+        owners
+    }
+
+    /// The path the point would be inserted along, as the tree will be after the next refresh.
+    fn insertion_path<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+    ) -> GokoResult<Vec<(f32, NodeAddress)>> {
+        let point_cloud = &self.parameters.point_cloud;
+        let scale_base = self.parameters.scale_base;
+        let root_center = point_cloud.point(self.root_address.1)?;
+        let mut current_distance = D::Metric::dist(&root_center, point);
+        let mut current_address = self.root_address;
+        let mut trace = vec![(current_distance, current_address)];
+        while let Some(nearest) =
+            self.get_node_pending_and(current_address, |n| match self.parameters.partition_type {
+                PartitionType::Nearest => {
+                    n.nearest_covering_child(scale_base, current_distance, point, point_cloud)
+                }
+                PartitionType::First => {
+                    n.first_covering_child(scale_base, current_distance, point, point_cloud)
+                }
+            })
+        {
+            match nearest? {
+                Some(nearest) => {
+                    trace.push(nearest);
+                    current_distance = nearest.0;
+                    current_address = nearest.1;
+                }
+                None => break,
+            }
+        }
+        Ok(trace)
+    }
+
+    /// Puts a new root above the current one, at the first scale that covers a point this far from the center. The
+    /// old root becomes the new root's nested child. Returns the new root's address.
+    fn promote_root(&mut self, distance: f32) -> GokoResult<NodeAddress> {
+        let old_root = self.root_address;
+        let mut scale_index = old_root.0 + 1;
+        while self.parameters.scale_base.powi(scale_index) <= distance {
+            scale_index += 1;
+        }
+        while self.layers.len() <= self.parameters.internal_index(scale_index) {
+            let new_scale = self.layers.len() as i32 + self.parameters.min_res_index - 1;
+            self.layers.push(CoverLayerWriter::new(new_scale));
+            self.parameters
+                .plugin_attachments
+                .write()
+                .unwrap()
+                .add_layer();
+        }
+        let (radius, coverage) = self
+            .get_node_pending_and(old_root, |n| (n.radius(), n.coverage_count()))
+            .ok_or(GokoError::IndexNotInTree(old_root.1))?;
+        let new_root = (scale_index, old_root.1);
+        let mut root = CoverNode::new(None, new_root);
+        root.set_radius(radius);
+        root.insert_nested_child(old_root.0, coverage)?;
+        unsafe {
+            self.insert_raw(new_root.0, new_root.1, root);
+            self.update_node(old_root, move |n| n.set_parent_address(Some(new_root)));
+        }
+        self.parameters
+            .total_nodes
+            .fetch_add(1, atomic::Ordering::SeqCst);
+        self.root_address = new_root;
+        Ok(new_root)
+    }
+
+    /// Replaces a leaf that grew past the leaf cutoff with the subtree the build would have made of its points.
+    /// Returns the addresses of the nodes of the subtree, they replace the leaf and have no plugin components yet.
+    fn split_leaf(&mut self, leaf: NodeAddress) -> GokoResult<Vec<NodeAddress>> {
+        let (parent, coverage) = self
+            .get_node_pending_and(leaf, |n| (n.parent_address(), n.singletons().to_vec()))
+            .ok_or(GokoError::IndexNotInTree(leaf.1))?;
+        let nodes = build_subtree(&self.parameters, parent, leaf, coverage)?;
+        if nodes.len() == 1 {
+            return Ok(Vec::new());
+        }
+        let mut addresses = Vec::with_capacity(nodes.len());
+        unsafe {
+            self.layer(leaf.0).remove_raw(leaf.1);
+            for node in nodes {
+                let address = node.address();
+                for singleton in node.singletons() {
+                    self.final_addresses.insert(*singleton, address);
+                }
+                if node.is_leaf() {
+                    self.final_addresses.insert(address.1, address);
+                }
+                self.insert_raw(address.0, address.1, node);
+                addresses.push(address);
+            }
+        }
+        Ok(addresses)
+    }
+}
+
+impl<D: AppendablePointCloud> CoverTreeWriter<D> {
+    /// Appends the point to the point cloud and adds it to the tree, see [`CoverTreeWriter::insert_point`]. Returns
+    /// the point's new index and the address of the node that owns it.
+    pub fn append_point(&mut self, point: &D::Point) -> GokoResult<(usize, NodeAddress)> {
+        let point_index = self.parameters.point_cloud.append_point(point)?;
+        let address = self.insert_point(point_index)?;
+        Ok((point_index, address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::{random_cloud, random_tree_builder};
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    fn build_random_tree(
+        count: usize,
+        use_singletons: bool,
+    ) -> CoverTreeWriter<AppendableCloud<DefaultLabeledCloud<L2>>> {
+        let point_cloud = AppendableCloud::new(random_cloud(count, 2));
+        let mut builder = random_tree_builder();
+        builder.use_singletons = use_singletons;
+        builder.build(Arc::new(point_cloud)).unwrap()
+    }
+
+    #[test]
+    fn removed_points_can_be_inserted_again() {
+        for use_singletons in &[true, false] {
+            let count = 200;
+            let mut tree = build_random_tree(count, *use_singletons);
+            let removed: Vec<usize> = (0..count)
+                .filter(|pi| tree.remove_point(*pi).is_ok())
+                .collect();
+            assert!(removed.len() > count / 2);
+
+            let owners = tree.insert_points(&removed).unwrap();
+            assert_eq!(owners.len(), removed.len());
+            assert!(tree.validate().unwrap().is_valid());
+
+            let reader = tree.reader();
+            assert!(reader.no_dangling_refs());
+            let root_coverage = reader
+                .get_node_and(reader.root_address(), |n| n.coverage_count())
+                .unwrap();
+            assert_eq!(root_coverage, count);
+            for pi in 0..count {
+                let path = reader.known_path(pi).unwrap();
+                assert!(path.iter().all(|(d, address)| {
+                    reader.get_node_and(*address, |n| n.radius()).unwrap() >= *d
+                }));
+            }
+            let query: Vec<f32> = reader.point_cloud().point(removed[0]).unwrap().to_vec();
+            let knn = reader.knn(&&query[..], 1).unwrap();
+            assert_eq!(knn[0].0, 0.0);
+
+            assert!(matches!(
+                tree.insert_point(removed[0]),
+                Err(GokoError::PointAlreadyInTree(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn appended_points_extend_the_cloud() {
+        use crate::plugins::discrete::prelude::*;
+        for use_singletons in &[true, false] {
+            let count = 200;
+            let mut tree = build_random_tree(count, *use_singletons);
+            tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+            let mut rng = SmallRng::seed_from_u64(1);
+            for i in 0..100 {
+                let point = [rng.gen::<f32>(), rng.gen::<f32>()];
+                let (pi, address) = tree.append_point(&point[..]).unwrap();
+                assert_eq!(pi, count + i);
+                assert_eq!(
+                    tree.reader().known_path(pi).unwrap().last().unwrap().1,
+                    address
+                );
+            }
+            assert_eq!(tree.reader().point_cloud().len(), count + 100);
+            assert!(tree.validate().unwrap().is_valid());
+
+            let reader = tree.reader();
+            assert!(reader.no_dangling_refs());
+            let root_coverage = reader
+                .get_node_and(reader.root_address(), |n| n.coverage_count())
+                .unwrap();
+            assert_eq!(root_coverage, count + 100);
+            for (_si, layer) in reader.layers() {
+                layer.for_each_node(|_pi, n| {
+                    assert!(n.get_plugin_and::<Dirichlet, _, _>(|d| d.total()).is_some());
+                    if !*use_singletons && !n.is_leaf() {
+                        assert!(n.singletons().is_empty());
+                    }
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn far_points_promote_the_root() {
+        use crate::plugins::discrete::prelude::*;
+        for use_singletons in &[true, false] {
+            let count = 200;
+            let mut tree = build_random_tree(count, *use_singletons);
+            tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+            let old_root = tree.reader().root_address();
+
+            let (pi, address) = tree.append_point(&[100.0, 100.0][..]).unwrap();
+            let reader = tree.reader();
+            let root = reader.root_address();
+            assert_eq!(root.1, old_root.1);
+            assert!(root.0 > old_root.0);
+            assert!(reader.scale(root.0) > 100.0);
+            assert!(tree.validate().unwrap().is_valid());
+            assert!(reader.no_dangling_refs());
+
+            let path = reader.known_path(pi).unwrap();
+            assert_eq!(path[0].1, root);
+            assert_eq!(path.last().unwrap().1, address);
+            if *use_singletons {
+                assert_eq!(address, root);
+            } else {
+                assert_eq!(address, (old_root.0, pi));
+            }
+            let (root_coverage, nested) = reader
+                .get_node_and(root, |n| (n.coverage_count(), n.children().unwrap().0))
+                .unwrap();
+            assert_eq!(root_coverage, count + 1);
+            assert_eq!(nested, old_root.0);
+            assert_eq!(
+                reader.get_node_and(old_root, |n| n.parent_address()),
+                Some(Some(root))
+            );
+
+            let (children, _) = reader
+                .get_node_plugin_and::<Dirichlet, _, _>(root, |d| d.prob_vector())
+                .flatten()
+                .expect("the new root should get the plugin");
+            assert!(children.iter().any(|(a, _)| *a == old_root));
+
+            let knn = reader.knn(&&[100.0f32, 100.0][..], 1).unwrap();
+            assert_eq!(knn[0], (0.0, pi));
+            let knn = reader.knn(&&[0.5f32, 0.5][..], 1).unwrap();
+            assert!(knn[0].1 < count);
+        }
+    }
+}
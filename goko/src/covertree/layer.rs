@@ -38,6 +38,7 @@ use pointcloud::*;
 use super::node::*;
 use crate::tree_file_format::*;
 use crate::*;
+use hashbrown::HashSet;
 use std::iter::FromIterator;
 
 /// Actual reader, primarily contains a read head to the hash-map.
@@ -88,6 +89,29 @@ impl<D: PointCloud> CoverLayerReader<D> {
         self.node_reader.for_each(f)
     }
 
+    /// Read only access to all nodes, in order of their center index. This is slower than `for_each_node`,
+    /// but it is the same order on every run so use it for anything that gets written out.
+    pub fn for_each_node_sorted<F>(&self, mut f: F)
+    where
+        F: FnMut(&usize, &CoverNode<D>),
+    {
+        for pi in self.node_center_indexes_sorted() {
+            self.node_reader.get_and(&pi, |n| f(&pi, n));
+        }
+    }
+
+    /// Maps all nodes on the layer, in order of their center index. See `for_each_node_sorted`.
+    pub fn map_nodes_sorted<Map, Target, Collector>(&self, mut f: Map) -> Collector
+    where
+        Map: FnMut(&usize, &CoverNode<D>) -> Target,
+        Collector: FromIterator<Target>,
+    {
+        self.node_center_indexes_sorted()
+            .iter()
+            .filter_map(|pi| self.node_reader.get_and(pi, |n| f(pi, n)))
+            .collect()
+    }
+
     /// Maps all nodes on the layer, useful for collecting statistics.
     pub fn map_nodes<Map, Target, Collector>(&self, f: Map) -> Collector
     where
@@ -114,6 +138,13 @@ impl<D: PointCloud> CoverLayerReader<D> {
         self.node_reader.map_into(|pi, _| *pi)
     }
 
+    /// The center indexes of all nodes on this layer, in ascending order.
+    pub fn node_center_indexes_sorted(&self) -> Vec<usize> {
+        let mut indexes = self.node_center_indexes();
+        indexes.sort_unstable();
+        indexes
+    }
+
     /// Total number of nodes on this layer
     pub fn len(&self) -> usize {
         self.node_reader.len()
@@ -124,6 +155,11 @@ impl<D: PointCloud> CoverLayerReader<D> {
         self.node_reader.is_empty()
     }
 
+    /// The number of nodes the layer's map can hold without reallocating.
+    pub(crate) fn capacity(&self) -> usize {
+        self.node_reader.capacity()
+    }
+
     /// Read only accessor for the scale index.
     pub fn scale_index(&self) -> i32 {
         self.scale_index
@@ -142,6 +178,8 @@ impl<D: PointCloud> CoverLayerReader<D> {
 pub struct CoverLayerWriter<D: PointCloud> {
     scale_index: i32,
     node_writer: MonoWriteHandle<usize, CoverNode<D>>,
+    /// The nodes written to since the last full checkpoint
+    modified: HashSet<usize>,
 }
 
 impl<D: PointCloud> CoverLayerWriter<D> {
@@ -159,6 +197,7 @@ impl<D: PointCloud> CoverLayerWriter<D> {
         CoverLayerWriter {
             scale_index,
             node_writer,
+            modified: HashSet::new(),
         }
     }
 
@@ -166,9 +205,20 @@ impl<D: PointCloud> CoverLayerWriter<D> {
     where
         F: Fn(&mut CoverNode<D>) + 'static + Send + Sync,
     {
+        self.modified.insert(pi);
         self.node_writer.update(pi, update_fn);
     }
 
+    /// Reads a node with the writes that haven't been refreshed yet applied. The writes are applied to a copy made
+    /// with `copy`, pass one that keeps the node's plugin components.
+    pub(crate) fn get_node_pending_and<C, F, T>(&self, pi: usize, copy: C, f: F) -> Option<T>
+    where
+        C: Fn(&CoverNode<D>) -> CoverNode<D>,
+        F: FnOnce(&CoverNode<D>) -> T,
+    {
+        self.node_writer.get_pending_with_and(&pi, copy, f)
+    }
+
     pub(crate) fn load(layer_proto: &LayerProto) -> CoverLayerWriter<D> {
         let scale_index = layer_proto.get_scale_index();
         let (_node_reader, mut node_writer) = monomap::new();
@@ -182,6 +232,7 @@ impl<D: PointCloud> CoverLayerWriter<D> {
         CoverLayerWriter {
             scale_index,
             node_writer,
+            modified: HashSet::new(),
         }
     }
 
@@ -193,19 +244,69 @@ impl<D: PointCloud> CoverLayerWriter<D> {
     pub(crate) fn save(&self) -> LayerProto {
         let mut layer_proto = LayerProto::new();
         let mut node_protos = layer_proto.take_nodes();
-        self.node_writer.for_each(|_pi, node| {
-            node_protos.push(node.save());
-        });
+        // Sorted so that saving the same tree twice gives the same file
+        let mut indexes: Vec<usize> = self.node_writer.map_into(|pi, _| *pi);
+        indexes.sort_unstable();
+        for pi in indexes {
+            self.node_writer.get_and(&pi, |node| {
+                node_protos.push(node.save());
+            });
+        }
         layer_proto.set_nodes(node_protos);
         layer_proto.set_scale_index(self.scale_index);
         layer_proto
     }
 
     pub(crate) fn insert_raw(&mut self, index: usize, node: CoverNode<D>) {
+        self.modified.insert(index);
         self.node_writer.insert(index, node);
     }
 
+    pub(crate) fn remove_raw(&mut self, index: usize) {
+        self.modified.insert(index);
+        self.node_writer.remove(index);
+    }
+
+    /// Saves only the nodes written to since the last call to `clear_modified`. Sorted like `save`. The nodes that
+    /// were removed since then are listed in the proto's `removed_indexes`.
+    pub(crate) fn save_modified(&self) -> LayerProto {
+        let mut layer_proto = LayerProto::new();
+        let mut node_protos = layer_proto.take_nodes();
+        let mut removed = Vec::new();
+        let mut indexes: Vec<usize> = self.modified.iter().cloned().collect();
+        indexes.sort_unstable();
+        for pi in indexes {
+            let saved = self.node_writer.get_and(&pi, |node| {
+                node_protos.push(node.save());
+            });
+            if saved.is_none() {
+                removed.push(pi as u64);
+            }
+        }
+        layer_proto.set_nodes(node_protos);
+        layer_proto.set_removed_indexes(removed);
+        layer_proto.set_scale_index(self.scale_index);
+        layer_proto
+    }
+
+    pub(crate) fn modified_len(&self) -> usize {
+        self.modified.len()
+    }
+
+    pub(crate) fn clear_modified(&mut self) {
+        self.modified.clear();
+    }
+
     pub(crate) fn refresh(&mut self) {
         self.node_writer.refresh();
     }
+
+    /// Replaces the contents of the layer with the nodes, inserted in the order given.
+    pub(crate) fn rebuild(&mut self, nodes: Vec<CoverNode<D>>) {
+        self.node_writer.purge();
+        for node in nodes {
+            self.node_writer.insert(*node.center_index(), node);
+        }
+        self.node_writer.refresh();
+    }
 }
@@ -0,0 +1,178 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Memory reports
+//!
+//! [`CoverTreeReader::memory_report`] adds up the bytes the tree uses, layer by layer, so that you can plan how many
+//! trees fit on a host. The numbers are estimates from the sizes of the types and the capacities of the buffers, the
+//! allocator's own overhead isn't counted. The maps that hold the layers keep two copies of every node so that the
+//! readers never wait on the writer, and the report counts both of them.
+//!
+//! Plugin components are sized with [`NodePlugin::size_hint`](crate::plugins::NodePlugin::size_hint), so a plugin
+//! that owns heap memory but doesn't override it is undercounted. The point cloud isn't part of the report.
+
+use super::*;
+use crate::plugins::PluginSizer;
+use pointcloud::*;
+use serde::{Deserialize, Serialize};
+use std::mem::size_of;
+
+/// The maps keep a copy of their contents for the readers and one for the writer.
+const MAP_COPIES: usize = 2;
+
+/// The bytes of a map's table beyond its values, the keys, a control byte per slot and the empty slots.
+fn table_bytes<K, V>(capacity: usize, len: usize) -> usize {
+    (capacity * (size_of::<(K, V)>() + 1)).saturating_sub(len * size_of::<V>())
+}
+
+/// The bytes used by one layer of the tree, see [`CoverTreeReader::memory_report`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LayerMemory {
+    /// The scale index of the layer
+    pub scale_index: i32,
+    /// The number of nodes on the layer
+    pub node_count: usize,
+    /// The nodes themselves and their child lists
+    pub node_bytes: usize,
+    /// The singleton lists that are too long to fit in the node
+    pub singleton_bytes: usize,
+    /// The components of the attached plugins
+    pub plugin_bytes: usize,
+    /// The layer's map, less the nodes in it
+    pub map_bytes: usize,
+}
+
+impl LayerMemory {
+    /// The bytes used by the layer.
+    pub fn total(&self) -> usize {
+        self.node_bytes + self.singleton_bytes + self.plugin_bytes + self.map_bytes
+    }
+}
+
+/// The bytes used by the components of one plugin, over the whole tree.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PluginMemory {
+    /// The type name of the plugin
+    pub name: String,
+    /// The bytes used by its components
+    pub bytes: usize,
+}
+
+/// The bytes used by the tree, see the [module docs](self).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryReport {
+    /// The layers, starting from the one that holds the root
+    pub layers: Vec<LayerMemory>,
+    /// The attached plugins, these are also counted in the layers' `plugin_bytes`
+    pub plugins: Vec<PluginMemory>,
+    /// The map from each point to the node that owns it
+    pub final_addresses_bytes: usize,
+}
+
+impl MemoryReport {
+    /// The bytes used by the tree.
+    pub fn total(&self) -> usize {
+        self.layers.iter().map(|l| l.total()).sum::<usize>() + self.final_addresses_bytes
+    }
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// The bytes used by the tree, per layer. See the [module docs](self).
+    pub fn memory_report(&self) -> MemoryReport {
+        let sizers: Vec<PluginSizer<D>> = self
+            .parameters()
+            .plugin_sizers
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        let mut plugin_bytes = vec![0; sizers.len()];
+        let layers = self
+            .layers()
+            .map(|(scale_index, layer)| {
+                let mut memory = LayerMemory {
+                    scale_index,
+                    node_count: layer.len(),
+                    ..Default::default()
+                };
+                layer.for_each_node(|_, n| {
+                    memory.node_bytes += size_of::<CoverNode<D>>() + n.children_heap_bytes();
+                    memory.singleton_bytes += n.singletons_heap_bytes();
+                    for ((_, size), bytes) in sizers.iter().zip(plugin_bytes.iter_mut()) {
+                        let component_bytes = size(n) * MAP_COPIES;
+                        memory.plugin_bytes += component_bytes;
+                        *bytes += component_bytes;
+                    }
+                });
+                memory.node_bytes *= MAP_COPIES;
+                memory.singleton_bytes *= MAP_COPIES;
+                memory.map_bytes =
+                    table_bytes::<usize, CoverNode<D>>(layer.capacity(), layer.len()) * MAP_COPIES;
+                memory
+            })
+            .collect();
+        let plugins = sizers
+            .iter()
+            .zip(plugin_bytes)
+            .map(|((name, _), bytes)| PluginMemory {
+                name: name.to_string(),
+                bytes,
+            })
+            .collect();
+        let final_addresses_bytes =
+            table_bytes::<usize, NodeAddress>(self.final_addresses_capacity(), 0) * MAP_COPIES;
+        MemoryReport {
+            layers,
+            plugins,
+            final_addresses_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+    use crate::plugins::discrete::prelude::*;
+
+    #[test]
+    fn memory_report_adds_up() {
+        let mut tree = build_basic_tree();
+        let report = tree.reader().memory_report();
+        assert_eq!(report.layers.len(), tree.reader().len());
+        assert_eq!(
+            report.layers.iter().map(|l| l.node_count).sum::<usize>(),
+            tree.reader().node_count()
+        );
+        assert!(report.plugins.is_empty());
+        assert!(report.layers.iter().all(|l| l.plugin_bytes == 0));
+        assert!(report.total() > 0);
+
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let report = tree.reader().memory_report();
+        assert_eq!(report.plugins.len(), 1);
+        assert!(report.plugins[0].name.contains("GokoDirichlet"));
+        assert!(report.plugins[0].bytes > 0);
+        assert_eq!(
+            report.layers.iter().map(|l| l.plugin_bytes).sum::<usize>(),
+            report.plugins[0].bytes
+        );
+    }
+}
@@ -1,10 +1,53 @@
+pub mod accelerator;
+mod budget;
 pub(crate) mod builders;
+mod classify;
+mod coreset;
 pub(crate) mod data_caches;
+mod delta;
+mod density;
+mod diff;
+mod export;
+mod flat;
+mod forest;
+mod furthest;
+mod handle;
+mod insertion;
 pub mod layer;
+mod memory;
 pub mod node;
+mod plugin_persistence;
+mod progress;
 pub mod query_tools;
+mod removal;
+mod report;
+mod sampling;
+pub mod sketch;
+mod soft_path;
+mod stats;
 
 mod tree;
+mod validation;
+mod warm_start;
 
+pub use budget::{BudgetedResult, QueryBudget};
 pub use builders::CoverTreeBuilder;
+pub use classify::Classification;
+pub use coreset::Coreset;
+pub use delta::{DeltaManifest, TreeDelta};
+pub use density::DEFAULT_DENSITY_ERROR;
+pub use diff::{NodeMove, NodeShift, TreeDiff};
+pub use export::NodeStructure;
+pub use flat::{FlatNode, FlatTree};
+pub use forest::{CoverForest, CoverForestReader, ForestDrift, ForestTracker};
+pub use furthest::Diameter;
+pub use handle::CoverTree;
+pub use memory::{LayerMemory, MemoryReport, PluginMemory};
+pub use plugin_persistence::PluginCodecs;
+pub use progress::{BuildProgress, CancellationToken};
+pub use removal::ExpiryReport;
+pub use report::PathStep;
+pub use soft_path::{SoftNode, SoftPath};
+pub use stats::{BuildParameters, LayerStats, TreeStats};
 pub use tree::*;
+pub use validation::{LoadValidation, ValidationReport, Violation};
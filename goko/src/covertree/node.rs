@@ -20,7 +20,9 @@
 //! # The Node
 //! This is the workhorse of the library. Each node
 //!
+use super::accelerator::DistanceAccelerator;
 use super::query_tools::{RoutingQueryHeap, SingletonQueryHeap};
+use super::sketch::DistanceSketch;
 use crate::errors::{GokoError, GokoResult};
 use crate::plugins::{
     labels::{NodeLabelSummary, NodeMetaSummary},
@@ -28,12 +30,95 @@ use crate::plugins::{
 };
 use crate::tree_file_format::*;
 use crate::NodeAddress;
+use std::cmp::Ordering;
 use std::ops::Deref;
 
 use pointcloud::*;
 use smallvec::SmallVec;
 use std::marker::PhantomData;
 use std::sync::Arc;
+/// A lower bound on the distance from the query to a child, given the distances from the center to both. This is
+/// shaved down a little so rounding can't push it over the real distance.
+#[inline]
+fn triangle_lower_bound(dist_to_center: f32, center_to_child: f32) -> f32 {
+    (dist_to_center - center_to_child).abs() * (1.0 - 1.0e-5)
+}
+
+/// Pushes the nested child and the children of the node at `address` onto the query heap. This is
+/// [`CoverNode::child_knn`] over just the parts of a node that a [`super::FlatNode`] also has.
+pub(crate) fn push_children<
+    D: PointCloud,
+    P: Deref<Target = D::Point> + Send + Sync,
+    T: RoutingQueryHeap,
+>(
+    address: NodeAddress,
+    nested_scale: i32,
+    child_addresses: &[NodeAddress],
+    dist_to_center: f32,
+    point: &P,
+    point_cloud: &D,
+    query_heap: &mut T,
+) -> GokoResult<()> {
+    query_heap.push_nodes(&[(nested_scale, address.1)], &[dist_to_center], None);
+    let children_indexes: Vec<usize> = child_addresses.iter().map(|(_si, pi)| *pi).collect();
+    let distances = point_cloud.distances_to_point(point, &children_indexes[..])?;
+    query_heap.push_nodes(child_addresses, &distances, Some(address));
+    Ok(())
+}
+
+/// [`CoverNode::nearest_covering_child`] over the parts of a node that a [`super::FlatNode`] also has.
+pub(crate) fn nearest_covering_child<D: PointCloud, P: Deref<Target = D::Point> + Send + Sync>(
+    address: NodeAddress,
+    nested_scale: i32,
+    child_addresses: &[NodeAddress],
+    scale_base: f32,
+    dist_to_center: f32,
+    point: &P,
+    point_cloud: &D,
+) -> GokoResult<Option<(f32, NodeAddress)>> {
+    let children_indexes: Vec<usize> = child_addresses.iter().map(|(_si, pi)| *pi).collect();
+    let distances = point_cloud.distances_to_point(point, &children_indexes[..])?;
+    let (min_index, min_dist) = distances
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+        .unwrap_or((0, &std::f32::MAX));
+    if dist_to_center < *min_dist {
+        if dist_to_center < scale_base.powi(nested_scale) {
+            Ok(Some((dist_to_center, (nested_scale, address.1))))
+        } else {
+            Ok(None)
+        }
+    } else if *min_dist < scale_base.powi(child_addresses[min_index].0) {
+        Ok(Some((*min_dist, child_addresses[min_index])))
+    } else {
+        Ok(None)
+    }
+}
+
+/// [`CoverNode::first_covering_child`] over the parts of a node that a [`super::FlatNode`] also has.
+pub(crate) fn first_covering_child<D: PointCloud, P: Deref<Target = D::Point> + Send + Sync>(
+    address: NodeAddress,
+    nested_scale: i32,
+    child_addresses: &[NodeAddress],
+    scale_base: f32,
+    dist_to_center: f32,
+    point: &P,
+    point_cloud: &D,
+) -> GokoResult<Option<(f32, NodeAddress)>> {
+    if dist_to_center < scale_base.powi(nested_scale) {
+        return Ok(Some((dist_to_center, (nested_scale, address.1))));
+    }
+    let children_indexes: Vec<usize> = child_addresses.iter().map(|(_si, pi)| *pi).collect();
+    let distances = point_cloud.distances_to_point(point, &children_indexes[..])?;
+    for (ca, d) in child_addresses.iter().zip(distances) {
+        if d < scale_base.powi(ca.0) {
+            return Ok(Some((d, *ca)));
+        }
+    }
+    Ok(None)
+}
+
 /// The node children. This is a separate struct from the `CoverNode` to use the rust compile time type checking and
 /// `Option` data structure to ensure that all nodes with children are valid and cover their nested child.
 #[derive(Debug, Clone)]
@@ -122,6 +207,30 @@ impl<D: PointCloud> CoverNode<D> {
         self.coverage_count
     }
 
+    /// The bytes of the singleton list that spilled out of the node onto the heap.
+    pub(crate) fn singletons_heap_bytes(&self) -> usize {
+        if self.singles_indexes.spilled() {
+            self.singles_indexes.capacity() * std::mem::size_of::<usize>()
+        } else {
+            0
+        }
+    }
+
+    /// The bytes of the child list that spilled out of the node onto the heap.
+    pub(crate) fn children_heap_bytes(&self) -> usize {
+        match &self.children {
+            Some(children) if children.addresses.spilled() => {
+                children.addresses.capacity() * std::mem::size_of::<NodeAddress>()
+            }
+            _ => 0,
+        }
+    }
+
+    /// Overwrites the coverage count, used when recounting the tree.
+    pub(crate) fn set_coverage_count(&mut self, coverage_count: usize) {
+        self.coverage_count = coverage_count;
+    }
+
     /// Reads the contents of a plugin, due to the nature of the plugin map we have to access it with a
     /// closure.
     pub fn get_plugin_and<T: Send + Sync + 'static, F, S>(&self, transform_fn: F) -> Option<S>
@@ -131,6 +240,16 @@ impl<D: PointCloud> CoverNode<D> {
         self.plugins.get::<T>().map(transform_fn)
     }
 
+    /// Mutates a plugin in place, if it's attached.
+    pub(crate) fn update_plugin<T: Send + Sync + 'static, F>(&mut self, update_fn: F)
+    where
+        F: FnOnce(&mut T),
+    {
+        if let Some(plugin) = self.plugins.get_mut::<T>() {
+            update_fn(plugin)
+        }
+    }
+
     /// Removes all children and returns them to us.
     pub(crate) fn remove_children(&mut self) -> Option<NodeChildren> {
         self.children.take()
@@ -217,6 +336,51 @@ impl<D: PointCloud> CoverNode<D> {
         Ok(())
     }
 
+    /// Performs the singleton scan on the accelerator. Only worth it for batches of the accelerator's minimum size,
+    /// the knn checks that for you.
+    pub fn singleton_knn_accelerated<
+        P: Deref<Target = D::Point> + Send + Sync,
+        T: SingletonQueryHeap,
+    >(
+        &self,
+        point: &P,
+        accelerator: &dyn DistanceAccelerator<D>,
+        query_heap: &mut T,
+    ) -> GokoResult<()> {
+        let distances = accelerator.distances_to_point(point, &self.singles_indexes[..])?;
+        query_heap.push_outliers(&self.singles_indexes[..], &distances[..]);
+        Ok(())
+    }
+
+    /// Performs the singleton scan, skipping the singletons whose sketch shows they can't make it onto the heap.
+    /// The rest are checked in order of their lower bounds, so the heap's maximum distance shrinks as fast as it can.
+    pub fn singleton_knn_sketched<
+        P: Deref<Target = D::Point> + Send + Sync,
+        T: SingletonQueryHeap,
+    >(
+        &self,
+        point: &P,
+        query_sketch: &[f32],
+        sketch: &dyn DistanceSketch<D>,
+        point_cloud: &D,
+        query_heap: &mut T,
+    ) -> GokoResult<()> {
+        let mut bounds: Vec<(f32, usize)> = self
+            .singles_indexes
+            .iter()
+            .map(|pi| (sketch.lower_bound(query_sketch, *pi), *pi))
+            .collect();
+        bounds.sort_unstable_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        for (bound, pi) in bounds {
+            if bound > query_heap.max_dist() {
+                break;
+            }
+            let dist = D::Metric::dist(point, &point_cloud.point(pi)?);
+            query_heap.push_outliers(&[pi], &[dist]);
+        }
+        Ok(())
+    }
+
     /// Performs a brute force knn against the children of the node with a provided query heap. Does nothing if this is a leaf node.
     /// If you have the distance from the query point to this you can pass it to save a distance calculation.
     pub fn child_knn<P: Deref<Target = D::Point> + Send + Sync, T: RoutingQueryHeap>(
@@ -230,15 +394,15 @@ impl<D: PointCloud> CoverNode<D> {
             dist_to_center.unwrap_or(point_cloud.distances_to_point(point, &[self.address.1])?[0]);
 
         if let Some(children) = &self.children {
-            query_heap.push_nodes(
-                &[(children.nested_scale, self.address.1)],
-                &[dist_to_center],
-                None,
-            );
-            let children_indexes: Vec<usize> =
-                children.addresses.iter().map(|(_si, pi)| *pi).collect();
-            let distances = point_cloud.distances_to_point(point, &children_indexes[..])?;
-            query_heap.push_nodes(&children.addresses[..], &distances, Some(self.address));
+            push_children(
+                self.address,
+                children.nested_scale,
+                &children.addresses[..],
+                dist_to_center,
+                point,
+                point_cloud,
+                query_heap,
+            )?;
         }
         Ok(())
     }
@@ -251,43 +415,129 @@ impl<D: PointCloud> CoverNode<D> {
         point: &P,
         point_cloud: &D,
     ) -> GokoResult<Option<(f32, NodeAddress)>> {
+        match &self.children {
+            Some(children) => nearest_covering_child(
+                self.address,
+                children.nested_scale,
+                &children.addresses[..],
+                scale_base,
+                dist_to_center,
+                point,
+                point_cloud,
+            ),
+            None => Ok(None),
+        }
+    }
+
+    /// Gives the child that the point would be inserted into if the
+    /// point just happened to never be picked as a center. This is the first child node that covers
+    /// the point.
+    pub fn first_covering_child<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        scale_base: f32,
+        dist_to_center: f32,
+        point: &P,
+        point_cloud: &D,
+    ) -> GokoResult<Option<(f32, NodeAddress)>> {
+        match &self.children {
+            Some(children) => first_covering_child(
+                self.address,
+                children.nested_scale,
+                &children.addresses[..],
+                scale_base,
+                dist_to_center,
+                point,
+                point_cloud,
+            ),
+            None => Ok(None),
+        }
+    }
+
+    /// Gives every child that covers the point, with the distance to its center. The nested child is first if it
+    /// covers the point. This is empty for a leaf.
+    pub fn covering_children<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        scale_base: f32,
+        dist_to_center: f32,
+        point: &P,
+        point_cloud: &D,
+    ) -> GokoResult<Vec<(f32, NodeAddress)>> {
+        let mut covering = Vec::new();
         if let Some(children) = &self.children {
+            if dist_to_center < scale_base.powi(children.nested_scale) {
+                covering.push((dist_to_center, (children.nested_scale, self.address.1)));
+            }
             let children_indexes: Vec<usize> =
                 children.addresses.iter().map(|(_si, pi)| *pi).collect();
             let distances = point_cloud.distances_to_point(point, &children_indexes[..])?;
-            let (min_index, min_dist) = distances
-                .iter()
-                .enumerate()
-                .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
-                .unwrap_or((0, &std::f32::MAX));
-            if dist_to_center < *min_dist {
-                if dist_to_center < scale_base.powi(children.nested_scale) {
-                    Ok(Some((
-                        dist_to_center,
-                        (children.nested_scale, self.address.1),
-                    )))
-                } else {
-                    Ok(None)
+            for (ca, d) in children.addresses.iter().zip(distances) {
+                if d < scale_base.powi(ca.0) {
+                    covering.push((d, *ca));
+                }
+            }
+        }
+        Ok(covering)
+    }
+
+    /// Same as `nearest_covering_child`, but uses the distances from the center to the children to skip the
+    /// children that the triangle inequality shows can't be the nearest. The answer is the same.
+    pub(crate) fn nearest_covering_child_cached<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        scale_base: f32,
+        dist_to_center: f32,
+        point: &P,
+        point_cloud: &D,
+        child_distances: &[f32],
+    ) -> GokoResult<Option<(f32, NodeAddress)>> {
+        if let Some(children) = &self.children {
+            let mut nearest: Option<(f32, usize)> = None;
+            for (i, (ca, center_dist)) in children.addresses.iter().zip(child_distances).enumerate()
+            {
+                let threshold = nearest
+                    .map(|(d, _)| d)
+                    .unwrap_or(dist_to_center)
+                    .min(dist_to_center);
+                if triangle_lower_bound(dist_to_center, *center_dist) > threshold {
+                    continue;
+                }
+                let d = D::Metric::dist(point, &point_cloud.point(ca.1)?);
+                if nearest.map(|(nd, _)| d < nd).unwrap_or(true) {
+                    nearest = Some((d, i));
+                }
+            }
+            match nearest {
+                Some((min_dist, min_index)) if min_dist <= dist_to_center => {
+                    if min_dist < scale_base.powi(children.addresses[min_index].0) {
+                        Ok(Some((min_dist, children.addresses[min_index])))
+                    } else {
+                        Ok(None)
+                    }
+                }
+                _ => {
+                    if dist_to_center < scale_base.powi(children.nested_scale) {
+                        Ok(Some((
+                            dist_to_center,
+                            (children.nested_scale, self.address.1),
+                        )))
+                    } else {
+                        Ok(None)
+                    }
                 }
-            } else if *min_dist < scale_base.powi(children.addresses[min_index].0) {
-                Ok(Some((*min_dist, children.addresses[min_index])))
-            } else {
-                Ok(None)
             }
         } else {
             Ok(None)
         }
     }
 
-    /// Gives the child that the point would be inserted into if the
-    /// point just happened to never be picked as a center. This is the first child node that covers
-    /// the point.
-    pub fn first_covering_child<P: Deref<Target = D::Point> + Send + Sync>(
+    /// Same as `first_covering_child`, but skips the children that the triangle inequality shows can't cover
+    /// the point. The answer is the same.
+    pub(crate) fn first_covering_child_cached<P: Deref<Target = D::Point> + Send + Sync>(
         &self,
         scale_base: f32,
         dist_to_center: f32,
         point: &P,
         point_cloud: &D,
+        child_distances: &[f32],
     ) -> GokoResult<Option<(f32, NodeAddress)>> {
         if let Some(children) = &self.children {
             if dist_to_center < scale_base.powi(children.nested_scale) {
@@ -296,11 +546,13 @@ impl<D: PointCloud> CoverNode<D> {
                     (children.nested_scale, self.address.1),
                 )));
             }
-            let children_indexes: Vec<usize> =
-                children.addresses.iter().map(|(_si, pi)| *pi).collect();
-            let distances = point_cloud.distances_to_point(point, &children_indexes[..])?;
-            for (ca, d) in children.addresses.iter().zip(distances) {
-                if d < scale_base.powi(ca.0) {
+            for (ca, center_dist) in children.addresses.iter().zip(child_distances) {
+                let radius = scale_base.powi(ca.0);
+                if triangle_lower_bound(dist_to_center, *center_dist) >= radius {
+                    continue;
+                }
+                let d = D::Metric::dist(point, &point_cloud.point(ca.1)?);
+                if d < radius {
                     return Ok(Some((d, *ca)));
                 }
             }
@@ -342,6 +594,44 @@ impl<D: PointCloud> CoverNode<D> {
         }
     }
 
+    /// Removes a routing child from the node, along with the points it covers. Returns `false` if it wasn't one.
+    pub(crate) fn remove_child(&mut self, address: NodeAddress, coverage: usize) -> bool {
+        if let Some(children) = &mut self.children {
+            if let Some(i) = children.addresses.iter().position(|a| *a == address) {
+                children.addresses.remove(i);
+                self.coverage_count = self.coverage_count.saturating_sub(coverage);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Removes a singleton from the node. Returns `false` if it wasn't one.
+    pub(crate) fn remove_singleton(&mut self, pi: usize) -> bool {
+        match self.singles_indexes.iter().position(|s| *s == pi) {
+            Some(i) => {
+                self.singles_indexes.remove(i);
+                self.coverage_count = self.coverage_count.saturating_sub(1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Promotes one of the singletons of a leaf to its center, the old center is dropped. The radius has to be
+    /// recomputed around the new center by the caller.
+    pub(crate) fn recenter(&mut self, center_index: usize, radius: f32) {
+        if self.remove_singleton(center_index) {
+            self.address.1 = center_index;
+            self.radius = radius;
+        }
+    }
+
+    /// Points the node at a new parent, the parent has to list this node as a child.
+    pub(crate) fn set_parent_address(&mut self, parent_address: Option<NodeAddress>) {
+        self.parent_address = parent_address;
+    }
+
     /// Inserts a `vec` of singleton children into the node.
     pub(crate) fn insert_singletons(&mut self, addresses: Vec<usize>) {
         self.coverage_count += addresses.len();
@@ -450,19 +740,20 @@ impl<D: PointCloud> CoverNode<D> {
         proto
     }
 
-    /*
-    /// Brute force verifies that the children are separated by at least the scale provided.
-    /// The scale provided should be b^(s-1) where s is this node's scale index.
-    pub fn check_seperation(&self, scale: f32, point_cloud: &D) -> GokoResult<bool> {
-        let mut nodes = self.singles_indexes.clone();
-        nodes.push(self.address.1);
-        if let Some(children) = &self.children {
-            nodes.extend(children.addresses.iter().map(|(_si, pi)| *pi));
-        }
-        let adj = point_cloud.adjacency_matrix(&nodes)?;
-        Ok(scale > adj.min())
+    /// Brute force verifies that the children are separated by at least the scale provided. The singletons of a
+    /// routing node are children that only cover themselves, so they're checked too. The scale provided should be
+    /// b^(s-1) where s is this node's scale index. Leaves are trivially separated.
+    pub(crate) fn check_seperation(&self, scale: f32, point_cloud: &D) -> GokoResult<bool> {
+        let mut centers: Vec<usize> = match &self.children {
+            Some(children) => children.addresses.iter().map(|(_si, pi)| *pi).collect(),
+            None => return Ok(true),
+        };
+        centers.extend(self.singles_indexes.iter());
+        centers.push(self.address.1);
+        centers.sort_unstable();
+        let adj = point_cloud.adjacency_matrix(&centers)?;
+        Ok(adj.min() >= scale)
     }
-    */
 }
 
 #[cfg(test)]
@@ -470,7 +761,7 @@ mod tests {
     use super::*;
     use std::env;
 
-    use crate::covertree::tests::build_mnist_tree;
+    use crate::covertree::tests::{build_mnist_tree, build_random_tree};
     use crate::query_tools::knn_query_heap::tests::clone_unvisited_nodes;
     use crate::query_tools::query_items::QueryAddress;
     use crate::query_tools::KnnQueryHeap;
@@ -754,4 +1045,29 @@ mod tests {
         assert_eq!(&reconstructed_node.singles_indexes[..], &[1, 2, 3, 4, 5, 6]);
         assert!(reconstructed_node.children.is_none());
     }
+
+    #[test]
+    fn built_nodes_are_separated() {
+        let tree = build_random_tree();
+        let reader = tree.reader();
+        let scale_base = reader.parameters().scale_base;
+        let point_cloud = &reader.parameters().point_cloud;
+        let mut routing_nodes = 0;
+        for (_, layer) in reader.layers() {
+            layer.for_each_node(|_, n| match n.children() {
+                Some((nested_scale, children)) => {
+                    routing_nodes += 1;
+                    assert!(n
+                        .check_seperation(scale_base.powi(nested_scale), point_cloud)
+                        .unwrap());
+                    // Any two centers are closer than that
+                    if !children.is_empty() || n.singletons_len() > 0 {
+                        assert!(!n.check_seperation(f32::MAX, point_cloud).unwrap());
+                    }
+                }
+                None => assert!(n.check_seperation(f32::MAX, point_cloud).unwrap()),
+            });
+        }
+        assert!(routing_nodes > 0);
+    }
 }
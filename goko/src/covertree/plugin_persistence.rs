@@ -0,0 +1,265 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Saving Plugins
+//!
+//! [`CoverTreeWriter::save`] only saves the structure of the tree, so the plugins have to be attached again after a
+//! load, which can take minutes on a large tree. A [`PluginCodecs`] lists the plugins to keep. Their node components
+//! go in each node's `plugins` blob and their tree components in the tree's, encoded with bincode. Pass the same
+//! codecs to [`CoverTreeWriter::load_with_plugins`] to get the tree back with the plugins attached.
+
+use super::node::*;
+use super::*;
+use crate::errors::{GokoError, GokoResult};
+use crate::plugins::*;
+use crate::tree_file_format::*;
+use crate::NodeAddress;
+use pointcloud::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::any::TypeId;
+use std::sync::{atomic, Arc};
+
+/// The encoded components of the plugins, by the name they were registered under
+type PluginBlobs = Vec<(String, Vec<u8>)>;
+
+type SaveTreeFn = Box<dyn Fn(&TreePluginSet) -> GokoResult<Option<Vec<u8>>> + Send + Sync>;
+type SaveNodeFn<D> = Box<dyn Fn(&CoverNode<D>) -> GokoResult<Option<Vec<u8>>> + Send + Sync>;
+type LoadTreeFn<D> = Box<dyn Fn(&mut CoverTreeWriter<D>, &[u8]) -> GokoResult<()> + Send + Sync>;
+type LoadNodeFn<D> =
+    Box<dyn Fn(&mut CoverTreeWriter<D>, NodeAddress, &[u8]) -> GokoResult<()> + Send + Sync>;
+
+struct PluginCodec<D: PointCloud> {
+    name: String,
+    save_tree: SaveTreeFn,
+    save_node: SaveNodeFn<D>,
+    load_tree: LoadTreeFn<D>,
+    load_node: LoadNodeFn<D>,
+}
+
+/// The plugins to save with a tree and load back, see the [module docs](self).
+pub struct PluginCodecs<D: PointCloud> {
+    codecs: Vec<PluginCodec<D>>,
+}
+
+impl<D: PointCloud> Default for PluginCodecs<D> {
+    fn default() -> Self {
+        PluginCodecs { codecs: Vec::new() }
+    }
+}
+
+fn encode<T: Serialize>(value: &T) -> GokoResult<Vec<u8>> {
+    bincode::serialize(value).map_err(|e| GokoError::PluginEncodingError(e.to_string()))
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> GokoResult<T> {
+    bincode::deserialize(bytes).map_err(|e| GokoError::PluginEncodingError(e.to_string()))
+}
+
+impl<D: PointCloud> PluginCodecs<D> {
+    /// No plugins, saving with these is the same as [`CoverTreeWriter::save`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Saves and loads the plugin `P` under `name`. The name is written to the file, so it has to be the same when
+    /// saving and loading, and unique among the registered plugins.
+    pub fn register<P>(mut self, name: &str) -> Self
+    where
+        P: GokoPlugin<D> + Serialize + DeserializeOwned,
+        P::NodeComponent: Serialize + DeserializeOwned,
+    {
+        self.codecs.push(PluginCodec {
+            name: name.to_string(),
+            save_tree: Box::new(|plugins| plugins.get::<P>().map(encode).transpose()),
+            save_node: Box::new(|node| {
+                node.get_plugin_and::<P::NodeComponent, _, _>(encode)
+                    .transpose()
+            }),
+            load_tree: Box::new(|tree, bytes| {
+                let plug_in: P = decode(bytes)?;
+                tree.restore_plugin(plug_in);
+                Ok(())
+            }),
+            load_node: Box::new(|tree, address, bytes| {
+                let component: P::NodeComponent = decode(bytes)?;
+                unsafe { tree.update_node(address, move |n| n.insert_plugin(component.clone())) }
+                Ok(())
+            }),
+        });
+        self
+    }
+
+    fn get(&self, name: &str) -> Option<&PluginCodec<D>> {
+        self.codecs.iter().find(|c| c.name == name)
+    }
+}
+
+impl<D: PointCloud> CoverTreeWriter<D> {
+    /// Same as [`CoverTreeWriter::save`], but the registered plugins are saved with the tree. Plugins that aren't
+    /// attached are skipped.
+    pub fn save_with_plugins(&self, codecs: &PluginCodecs<D>) -> GokoResult<CoreProto> {
+        let mut cover_proto = self.save();
+        let reader = self.reader();
+        for layer_proto in cover_proto.mut_layers().iter_mut() {
+            for node_proto in layer_proto.mut_nodes().iter_mut() {
+                let address = (
+                    node_proto.get_scale_index(),
+                    node_proto.get_center_index() as usize,
+                );
+                let blobs = reader
+                    .get_node_and(address, |n| -> GokoResult<PluginBlobs> {
+                        let mut blobs = Vec::new();
+                        for codec in &codecs.codecs {
+                            if let Some(bytes) = (codec.save_node)(n)? {
+                                blobs.push((codec.name.clone(), bytes));
+                            }
+                        }
+                        Ok(blobs)
+                    })
+                    .transpose()?
+                    .unwrap_or_default();
+                if !blobs.is_empty() {
+                    node_proto.set_plugins(encode(&blobs)?);
+                }
+            }
+        }
+
+        let mut blobs = Vec::new();
+        {
+            let plugins = self.parameters.plugins.read().unwrap();
+            for codec in &codecs.codecs {
+                if let Some(bytes) = (codec.save_tree)(&plugins)? {
+                    blobs.push((codec.name.clone(), bytes));
+                }
+            }
+        }
+        if !blobs.is_empty() {
+            cover_proto.set_plugins(encode(&blobs)?);
+        }
+        Ok(cover_proto)
+    }
+
+    /// Loads a tree saved with [`CoverTreeWriter::save_with_plugins`]. The plugins in the file that are registered in
+    /// `codecs` are attached to the loaded tree, with their hooks, the others are skipped. A file saved without
+    /// plugins loads the same as with [`CoverTreeWriter::load`].
+    pub fn load_with_plugins(
+        cover_proto: &CoreProto,
+        point_cloud: Arc<D>,
+        codecs: &PluginCodecs<D>,
+    ) -> GokoResult<CoverTreeWriter<D>> {
+        let mut tree = CoverTreeWriter::load(cover_proto, point_cloud)?;
+        for layer_proto in cover_proto.get_layers() {
+            for node_proto in layer_proto.get_nodes() {
+                if node_proto.get_plugins().is_empty() {
+                    continue;
+                }
+                let address = (
+                    node_proto.get_scale_index(),
+                    node_proto.get_center_index() as usize,
+                );
+                let blobs: PluginBlobs = decode(node_proto.get_plugins())?;
+                for (name, bytes) in blobs {
+                    if let Some(codec) = codecs.get(&name) {
+                        (codec.load_node)(&mut tree, address, &bytes)?;
+                    }
+                }
+            }
+        }
+        if !cover_proto.get_plugins().is_empty() {
+            let blobs: PluginBlobs = decode(cover_proto.get_plugins())?;
+            for (name, bytes) in blobs {
+                if let Some(codec) = codecs.get(&name) {
+                    (codec.load_tree)(&mut tree, &bytes)?;
+                }
+            }
+        }
+        tree.refresh();
+        Ok(tree)
+    }
+
+    /// Puts a plugin whose node components are already on the nodes back on the tree.
+    fn restore_plugin<P: GokoPlugin<D>>(&mut self, plug_in: P) {
+        {
+            let mut attachments = self.parameters.plugin_attachments.write().unwrap();
+            attachments.begin::<P>(self.layers.len());
+            for layer_index in 0..self.layers.len() {
+                attachments.layer_attached::<P>(layer_index);
+            }
+        }
+        self.plugin_hooks.insert(
+            TypeId::of::<P>(),
+            plugin_hook(plug_in.clone(), Arc::clone(&self.parameters.point_cloud)),
+        );
+        self.plugin_builders
+            .insert(TypeId::of::<P>(), plugin_builder(plug_in.clone()));
+        self.parameters
+            .plugin_sizers
+            .write()
+            .unwrap()
+            .insert(TypeId::of::<P>(), plugin_sizer::<D, P>());
+        self.parameters
+            .plugin_copiers
+            .write()
+            .unwrap()
+            .insert(TypeId::of::<P>(), plugin_copier::<D, P>());
+        self.parameters.plugins.write().unwrap().insert(plug_in);
+        self.parameters.epoch.fetch_add(1, atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+    use crate::plugins::discrete::prelude::*;
+
+    fn dirichlets<D: PointCloud>(tree: &CoverTreeWriter<D>) -> Vec<(NodeAddress, String)> {
+        let reader = tree.reader();
+        let mut components = Vec::new();
+        for (_, layer) in reader.layers() {
+            layer.for_each_node_sorted(|_, n| {
+                if let Some(c) = n.get_plugin_and::<Dirichlet, _, _>(|d| format!("{:?}", d)) {
+                    components.push((n.address(), c));
+                }
+            });
+        }
+        components
+    }
+
+    #[test]
+    fn plugins_survive_a_round_trip() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let codecs = PluginCodecs::new().register::<GokoDirichlet>("dirichlet");
+        let cover_proto = tree.save_with_plugins(&codecs).unwrap();
+
+        let point_cloud = Arc::clone(&tree.parameters.point_cloud);
+        let loaded =
+            CoverTreeWriter::load_with_plugins(&cover_proto, Arc::clone(&point_cloud), &codecs)
+                .unwrap();
+        assert!(loaded.reader().plugin_attached::<GokoDirichlet>());
+        assert!(!dirichlets(&tree).is_empty());
+        assert_eq!(dirichlets(&loaded), dirichlets(&tree));
+
+        let plain = CoverTreeWriter::load(&cover_proto, point_cloud).unwrap();
+        assert!(!plain.reader().plugin_attached::<GokoDirichlet>());
+        assert!(dirichlets(&plain).is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Build Progress
+//!
+//! A build reports how far along it is to a [`BuildProgress`] set with
+//! [`CoverTreeBuilder::set_progress`](crate::CoverTreeBuilder::set_progress), and stops early if the
+//! [`CancellationToken`] set with [`CoverTreeBuilder::set_cancellation`](crate::CoverTreeBuilder::set_cancellation)
+//! is cancelled. Without a progress callback a build with a verbosity above 1 draws a progress bar on stdout.
+
+use pbr::ProgressBar;
+use std::fmt;
+use std::io::Stdout;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often a build reports its progress, at most.
+pub(crate) const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Receives the progress of a build. This is called on the thread that called
+/// [`CoverTreeBuilder::build`](crate::CoverTreeBuilder::build), at most every 100ms and once more when all the nodes
+/// are in, so keep it cheap.
+pub trait BuildProgress: Send + Sync {
+    /// The number of nodes made so far, the number of those that are in the tree, and the time since the nodes
+    /// started splitting. The number of nodes made grows as the nodes are split, so it's not the final node count
+    /// until they're all in.
+    fn on_progress(&self, nodes_created: usize, nodes_inserted: usize, elapsed: Duration);
+}
+
+impl<F: Fn(usize, usize, Duration) + Send + Sync> BuildProgress for F {
+    fn on_progress(&self, nodes_created: usize, nodes_inserted: usize, elapsed: Duration) {
+        self(nodes_created, nodes_inserted, elapsed)
+    }
+}
+
+/// The progress callback of a builder, so the builder stays `Debug` and `Clone`.
+#[derive(Clone)]
+pub(crate) struct ProgressHook(pub(crate) Arc<dyn BuildProgress>);
+
+impl fmt::Debug for ProgressHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ProgressHook")
+    }
+}
+
+/// The progress bar a build draws when it's verbose and doesn't have a callback.
+pub(crate) struct ProgressBarProgress {
+    bar: Mutex<ProgressBar<Stdout>>,
+}
+
+impl ProgressBarProgress {
+    pub(crate) fn new() -> ProgressBarProgress {
+        let mut bar = ProgressBar::new(1u64);
+        bar.format("╢▌▌░╟");
+        ProgressBarProgress {
+            bar: Mutex::new(bar),
+        }
+    }
+}
+
+impl BuildProgress for ProgressBarProgress {
+    fn on_progress(&self, nodes_created: usize, nodes_inserted: usize, _elapsed: Duration) {
+        let mut bar = self.bar.lock().unwrap();
+        bar.total = nodes_created as u64;
+        bar.set(nodes_inserted as u64);
+    }
+}
+
+/// Cancels a build from another thread. Clones share the same flag, so hand a clone to the builder and cancel the
+/// one you kept. A cancelled build stops taking in nodes and errors with
+/// [`GokoError::BuildCancelled`](crate::errors::GokoError::BuildCancelled).
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// A token that isn't cancelled.
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancels every build that has this token or a clone of it.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// If the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,108 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! The distances between the centers of the top nodes and their children. These don't depend on the query, so a
+//! batch of path queries can share them and use the triangle inequality to skip most of the child distance
+//! evaluations on the hottest nodes.
+
+use crate::covertree::CoverTreeReader;
+use crate::errors::GokoResult;
+use crate::NodeAddress;
+use hashbrown::HashMap;
+use pointcloud::*;
+
+/// Center to child distances for the nodes on the top layers of a tree. It's tied to the epoch of the tree it was
+/// built from, the cached path query ignores it once the tree moves on.
+#[derive(Debug, Clone)]
+pub struct ChildDistanceCache {
+    epoch: u64,
+    distances: HashMap<NodeAddress, Vec<f32>>,
+}
+
+impl ChildDistanceCache {
+    /// Computes the distances for the routing nodes on the top `depth` layers, counting from the root's layer.
+    pub fn new<D: PointCloud>(
+        reader: &CoverTreeReader<D>,
+        depth: usize,
+    ) -> GokoResult<ChildDistanceCache> {
+        let epoch = reader.epoch();
+        let root_scale = reader.root_address().0;
+        let point_cloud = reader.point_cloud();
+        let mut distances = HashMap::new();
+        for (si, layer) in reader.layers() {
+            if si > root_scale || si <= root_scale - depth as i32 {
+                continue;
+            }
+            let mut routing_nodes: Vec<(usize, Vec<usize>)> = Vec::new();
+            layer.for_each_node(|pi, n| {
+                if let Some((_nested_scale, child_addresses)) = n.children() {
+                    routing_nodes.push((*pi, child_addresses.iter().map(|(_, ci)| *ci).collect()));
+                }
+            });
+            for (pi, child_indexes) in routing_nodes {
+                let center = point_cloud.point(pi)?;
+                distances.insert(
+                    (si, pi),
+                    point_cloud.distances_to_point(&center, &child_indexes)?,
+                );
+            }
+        }
+        Ok(ChildDistanceCache { epoch, distances })
+    }
+
+    /// The epoch of the tree this was built from.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// The distances from the center of the node to the centers of its children, in the order of the children.
+    pub fn child_distances(&self, address: NodeAddress) -> Option<&[f32]> {
+        self.distances.get(&address).map(|d| &d[..])
+    }
+
+    /// The number of nodes cached.
+    pub fn len(&self) -> usize {
+        self.distances.len()
+    }
+
+    /// If no nodes are cached.
+    pub fn is_empty(&self) -> bool {
+        self.distances.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn cached_path_matches_path() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let cache = ChildDistanceCache::new(&reader, 3).unwrap();
+        assert!(!cache.is_empty());
+        for x in &[0.499f32, 0.49, 0.0, -0.49, 0.25, -1.0, 2.0] {
+            let point: &[f32] = &[*x];
+            let path = reader.path(&point).unwrap();
+            let cached_path = reader.path_cached(&point, &cache).unwrap();
+            assert_eq!(path, cached_path);
+        }
+    }
+}
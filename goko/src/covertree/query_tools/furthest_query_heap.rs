@@ -0,0 +1,159 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Tools and data structures for assisting cover tree queries.
+
+use super::*;
+use crate::NodeAddress;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+
+/// A node that might cover a point further away than the current furthest point.
+#[derive(Clone, Copy, Debug)]
+struct FurthestAddress {
+    max_dist: f32,
+    dist_to_center: f32,
+    address: NodeAddress,
+}
+
+impl PartialEq for FurthestAddress {
+    fn eq(&self, other: &FurthestAddress) -> bool {
+        other.address == self.address
+    }
+}
+
+impl Eq for FurthestAddress {}
+
+impl Ord for FurthestAddress {
+    fn cmp(&self, other: &FurthestAddress) -> Ordering {
+        self.partial_cmp(&other).unwrap_or(Ordering::Less)
+    }
+}
+
+impl PartialOrd for FurthestAddress {
+    fn partial_cmp(&self, other: &FurthestAddress) -> Option<Ordering> {
+        match self
+            .max_dist
+            .partial_cmp(&other.max_dist)
+            .unwrap_or(Ordering::Equal)
+        {
+            Ordering::Equal => other.address.0.partial_cmp(&self.address.0),
+            ordering => Some(ordering),
+        }
+    }
+}
+
+/// The max-heap counterpart of the [`KnnQueryHeap`] for the furthest point from the query. The nodes are ordered by
+/// the distance from the query to their center plus their radius, no point they cover can be further than that.
+/// The node with the largest bound is visited next, and the query is over once that bound can't beat the furthest
+/// point seen.
+///
+/// The centers of the pushed nodes are candidates too, and like the KNN heap a center that's already been seen isn't
+/// counted twice.
+#[derive(Debug, Default)]
+pub struct FurthestQueryHeap {
+    node_heap: BinaryHeap<FurthestAddress>,
+    known_indexes: HashSet<usize>,
+    furthest: Option<(f32, usize)>,
+}
+
+impl SingletonQueryHeap for FurthestQueryHeap {
+    /// Shove a bunch of single points onto the heap
+    fn push_outliers(&mut self, indexes: &[usize], dists: &[f32]) {
+        for (i, d) in indexes.iter().zip(dists) {
+            if self.known_indexes.insert(*i) {
+                self.push_candidate(*i, *d);
+            }
+        }
+    }
+}
+
+impl FurthestQueryHeap {
+    /// Creates an empty heap.
+    pub fn new() -> FurthestQueryHeap {
+        FurthestQueryHeap::default()
+    }
+
+    fn push_candidate(&mut self, index: usize, dist: f32) {
+        if self.furthest.map(|(fd, _)| fd < dist).unwrap_or(true) {
+            self.furthest = Some((dist, index));
+        }
+    }
+
+    /// Pushes nodes with the distances from the query to their centers and their radii. Nodes that can't cover a
+    /// point further than the current furthest are dropped.
+    pub fn push_nodes(&mut self, indexes: &[NodeAddress], dists: &[f32], radii: &[f32]) {
+        for ((address, d), r) in indexes.iter().zip(dists).zip(radii) {
+            if self.known_indexes.insert(address.1) {
+                self.push_candidate(address.1, *d);
+            }
+            let max_dist = d + r;
+            if self.furthest.map(|(fd, _)| fd < max_dist).unwrap_or(true) {
+                self.node_heap.push(FurthestAddress {
+                    max_dist,
+                    dist_to_center: *d,
+                    address: *address,
+                });
+            }
+        }
+    }
+
+    /// Pops the node that could cover the furthest point, if it could beat the current furthest point.
+    pub fn furthest_unvisited_address(&mut self) -> Option<(f32, NodeAddress)> {
+        let node = self.node_heap.pop()?;
+        if self
+            .furthest
+            .map(|(fd, _)| fd < node.max_dist)
+            .unwrap_or(true)
+        {
+            Some((node.dist_to_center, node.address))
+        } else {
+            self.node_heap.clear();
+            None
+        }
+    }
+
+    /// The furthest point seen, the distance and its index.
+    pub fn furthest(&self) -> Option<(f32, usize)> {
+        self.furthest
+    }
+
+    /// The number of nodes left to visit.
+    pub fn node_len(&self) -> usize {
+        self.node_heap.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn far_nodes_come_first() {
+        let mut heap = FurthestQueryHeap::new();
+        heap.push_nodes(&[(0, 1), (0, 3)], &[0.5, 1.0], &[1.0, 0.2]);
+        assert_eq!(heap.furthest(), Some((1.0, 3)));
+        assert_eq!(heap.node_len(), 2);
+        assert_eq!(heap.furthest_unvisited_address(), Some((0.5, (0, 1))));
+        heap.push_outliers(&[5], &[1.4]);
+        // The other node can't cover anything further than 1.2
+        assert_eq!(heap.furthest_unvisited_address(), None);
+        assert_eq!(heap.furthest(), Some((1.4, 5)));
+    }
+}
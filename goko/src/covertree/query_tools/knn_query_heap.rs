@@ -39,6 +39,10 @@ use super::query_items::{QueryAddress, QuerySingleton};
 /// To help with double inserts (easy due to a node's central point's index being repeated througout the tree), we also have a HashSet of visited points.
 /// We reject a node insert if it's central point index is in this hashset.
 ///
+/// For an approximate query there's an `epsilon`, a node is dropped once its minimum distance times `1 + epsilon` reaches
+/// the current max distance. Every point it could hold is then within a factor of `1 + epsilon` of the current KNN set,
+/// so the final `k`th distance is at most `1 + epsilon` times the true one. With `epsilon = 0` the query is exact.
+///
 #[derive(Debug)]
 pub struct KnnQueryHeap {
     child_heap: BinaryHeap<QueryAddress>,
@@ -49,6 +53,7 @@ pub struct KnnQueryHeap {
     dist_heap: BinaryHeap<QuerySingleton>,
     k: usize,
     scale_base: f32,
+    epsilon: f32,
 }
 
 impl RoutingQueryHeap for KnnQueryHeap {
@@ -59,7 +64,7 @@ impl RoutingQueryHeap for KnnQueryHeap {
         dists: &[f32],
         parent_address: Option<NodeAddress>,
     ) {
-        let mut max_dist = self.max_dist();
+        let mut max_dist = self.pruning_dist();
         let mut parent_est_dist_update = 0.0;
         for ((si, pi), d) in indexes.iter().zip(dists) {
             let emd = (d - self.scale_base.powi(*si)).max(0.0);
@@ -84,7 +89,7 @@ impl RoutingQueryHeap for KnnQueryHeap {
             }
             while self.dist_heap.len() > self.k {
                 self.dist_heap.pop();
-                max_dist = self.max_dist();
+                max_dist = self.pruning_dist();
             }
         }
 
@@ -114,6 +119,10 @@ impl SingletonQueryHeap for KnnQueryHeap {
             }
         }
     }
+
+    fn max_dist(&self) -> f32 {
+        KnnQueryHeap::max_dist(self)
+    }
 }
 
 impl KnnQueryHeap {
@@ -128,13 +137,41 @@ impl KnnQueryHeap {
             known_indexes: HashSet::new(),
             k,
             scale_base,
+            epsilon: 0.0,
+        }
+    }
+
+    /// Creates a KNN heap for an approximate query, see the struct docs for what `epsilon` promises.
+    pub fn new_approximate(k: usize, scale_base: f32, epsilon: f32) -> KnnQueryHeap {
+        let mut heap = KnnQueryHeap::new(k, scale_base);
+        heap.epsilon = epsilon.max(0.0);
+        heap
+    }
+
+    /// Nodes whose minimum distance is at least this can't improve the result by more than the `1 + epsilon` factor.
+    /// For an exact heap this is the max distance.
+    fn pruning_dist(&self) -> f32 {
+        let max_dist = self.max_dist();
+        if max_dist == f32::MAX {
+            max_dist
+        } else {
+            max_dist / (1.0 + self.epsilon)
         }
     }
 
+    /// Whether a popped node can be dropped. The exact query relies on the pruning done when nodes are pushed.
+    fn can_skip(&self, node: &QueryAddress) -> bool {
+        self.epsilon > 0.0 && node.min_dist >= self.pruning_dist()
+    }
+
     /// Finds the closest node who could have a child node at least the current kth furthest distance away from the query point.
     /// This pops that node and pushes it onto the singleton heap.
     pub fn closest_unvisited_child_covering_address(&mut self) -> Option<(f32, NodeAddress)> {
         while let Some(mut node_to_visit) = self.child_heap.pop() {
+            if self.can_skip(&node_to_visit) {
+                self.est_min_dist.remove(&node_to_visit.address);
+                continue;
+            }
             if let Some(min_dist_update) = self.est_min_dist.remove(&node_to_visit.address) {
                 if min_dist_update > node_to_visit.min_dist {
                     node_to_visit.min_dist = min_dist_update;
@@ -155,6 +192,10 @@ impl KnnQueryHeap {
     /// This pops the node and sends it to oblivion.
     pub fn closest_unvisited_singleton_covering_address(&mut self) -> Option<(f32, NodeAddress)> {
         while let Some(mut node_to_visit) = self.singleton_heap.pop() {
+            if self.can_skip(&node_to_visit) {
+                self.est_min_dist.remove(&node_to_visit.address);
+                continue;
+            }
             if let Some(min_dist_update) = self.est_min_dist.remove(&node_to_visit.address) {
                 if min_dist_update > node_to_visit.min_dist {
                     node_to_visit.min_dist = min_dist_update;
@@ -233,6 +274,29 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn approximate_heap_drops_far_nodes() {
+        let mut heap = KnnQueryHeap::new_approximate(1, 2.0, 1.0);
+        heap.push_outliers(&[2], &[1.0]);
+        heap.push_nodes(&[(-1, 1), (-1, 3)], &[0.9, 0.5], None);
+        assert_eq!(heap.node_len(), 2);
+        assert_eq!(
+            heap.closest_unvisited_child_covering_address(),
+            Some((0.5, (-1, 3)))
+        );
+        // The other node is at least 0.4 away, which can't beat 0.5 by a factor of 2
+        assert_eq!(heap.closest_unvisited_child_covering_address(), None);
+
+        let mut exact_heap = KnnQueryHeap::new(1, 2.0);
+        exact_heap.push_outliers(&[2], &[1.0]);
+        exact_heap.push_nodes(&[(-1, 1), (-1, 3)], &[0.9, 0.5], None);
+        exact_heap.closest_unvisited_child_covering_address();
+        assert_eq!(
+            exact_heap.closest_unvisited_child_covering_address(),
+            Some((0.9, (-1, 1)))
+        );
+    }
+
     pub fn clone_unvisited_nodes(heap: &KnnQueryHeap) -> Vec<(f32, NodeAddress)> {
         let mut all_nodes: Vec<QueryAddress> = heap.child_heap.iter().cloned().collect();
         all_nodes.extend(heap.singleton_heap.iter().cloned());
@@ -23,6 +23,12 @@ use crate::NodeAddress;
 
 pub(crate) mod query_items;
 
+mod child_distances;
+pub use child_distances::ChildDistanceCache;
+
+pub(crate) mod furthest_query_heap;
+pub use furthest_query_heap::FurthestQueryHeap;
+
 pub(crate) mod knn_query_heap;
 pub use knn_query_heap::KnnQueryHeap;
 pub(crate) mod trace_query_heap;
@@ -45,4 +51,8 @@ pub trait RoutingQueryHeap {
 pub trait SingletonQueryHeap {
     /// Shove a bunch of single points onto the heap
     fn push_outliers(&mut self, indexes: &[usize], dists: &[f32]);
+    /// Points further than this can't make it onto the heap, so a scan can skip them. The default never skips.
+    fn max_dist(&self) -> f32 {
+        std::f32::MAX
+    }
 }
@@ -23,6 +23,7 @@ use super::*;
 use crate::NodeAddress;
 use std::collections::{BinaryHeap, HashMap};
 use std::f32;
+use tracing::trace;
 
 use super::query_items::{QueryAddress, QueryAddressRev};
 
@@ -47,7 +48,7 @@ impl RoutingQueryHeap for MultiscaleQueryHeap {
         for ((si, pi), d) in indexes.iter().zip(dists) {
             let emd = (d - self.scale_base.powi(*si)).max(0.0);
 
-            println!("\t Inserting {:?} into max heap", ((si, pi), d));
+            trace!(address = ?(si, pi), dist = d, "Inserting into max heap");
             let max_heap = self
                 .layer_max_heaps
                 .entry(*si)
@@ -111,11 +112,11 @@ impl MultiscaleQueryHeap {
         self.layer_max_heaps
             .drain()
             .map(|(si, heap)| {
-                let mut v: Vec<(f32, NodeAddress)> = heap
-                    .into_iter_sorted()
+                let v: Vec<(f32, NodeAddress)> = heap
+                    .into_sorted_vec()
+                    .into_iter()
                     .map(|qa| (qa.dist_to_center, qa.address))
                     .collect();
-                v.reverse();
                 (si, v)
             })
             .collect()
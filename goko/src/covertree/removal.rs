@@ -0,0 +1,464 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Point Removal
+//!
+//! Points can be taken out of a built tree with [`CoverTreeWriter::remove_point`]. A point that is a singleton is
+//! dropped from its node, a point that is the center of a leaf hands the leaf over to one of its singletons, and an
+//! empty leaf is cut from its parent. The centers of routing nodes hold up the rest of the tree and are refused,
+//! they go away on the next rebuild. The points stay in the point cloud, they're only removed from the tree.
+//!
+//! For streaming workloads put the cloud in a [`pointcloud::TimestampedCloud`] and age the old points out with
+//! [`CoverTreeWriter::expire_older_than`]. A whole region of the tree can be dropped at once with
+//! [`CoverTreeWriter::prune_subtree`].
+
+use super::*;
+use crate::errors::{GokoError, GokoResult};
+use crate::NodeAddress;
+use pointcloud::*;
+use std::collections::BTreeMap;
+use std::sync::{atomic, Arc};
+use tracing::debug;
+
+/// What an expiry sweep did, see [`CoverTreeWriter::expire_older_than`].
+#[derive(Debug, Clone, Default)]
+pub struct ExpiryReport {
+    /// The number of points removed, keyed by the scale index of the node that owned them
+    pub removed: BTreeMap<i32, usize>,
+    /// The expired points that hold up part of the tree, see [`CoverTreeWriter::remove_point`]. These stay in the
+    /// tree until it's rebuilt
+    pub retained: Vec<usize>,
+}
+
+impl ExpiryReport {
+    /// The total number of points removed
+    pub fn removed_count(&self) -> usize {
+        self.removed.values().sum()
+    }
+}
+
+impl<D: PointCloud> CoverTreeWriter<D> {
+    /// Removes a point from the tree and returns the scale index of the node that owned it. The coverage counts of
+    /// the node's ancestors drop by one, their radii are left alone as they are still an upper bound. The plugins
+    /// are told about the removal first, see [`CoverTreeWriter::plugins_on_remove`]. The removal is published to
+    /// the readers, unless the refreshes are deferred by a [`CoverTree`].
+    ///
+    /// Errors with [`GokoError::PointNotRemovable`] if the point is the center of a routing node or of the root, or
+    /// if it's the center of a leaf that none of its singletons can take over without growing the leaf past its
+    /// scale.
+    pub fn remove_point(&mut self, point_index: usize) -> GokoResult<i32> {
+        let owner = self
+            .final_address_pending(point_index)
+            .ok_or(GokoError::IndexNotInTree(point_index))?;
+        let (parent, is_leaf, radius, singletons) = self
+            .get_node_pending_and(owner, |n| {
+                (
+                    n.parent_address(),
+                    n.is_leaf(),
+                    n.radius(),
+                    n.singletons().to_vec(),
+                )
+            })
+            .ok_or(GokoError::IndexNotInTree(point_index))?;
+        let is_center = owner.1 == point_index;
+        let mut new_center = None;
+        if is_center {
+            match parent {
+                // A nested leaf shares its center with its parent
+                Some(parent) if is_leaf && parent.1 != point_index => {}
+                _ => return Err(GokoError::PointNotRemovable(point_index)),
+            }
+            if !singletons.is_empty() {
+                let max_radius = self.parameters.scale_base.powi(owner.0).max(radius);
+                new_center = Some(
+                    self.closest_center(&singletons, max_radius)?
+                        .ok_or(GokoError::PointNotRemovable(point_index))?,
+                );
+            }
+        }
+        let ancestors = self.ancestors(owner);
+
+        self.plugins_on_remove(point_index)?;
+        unsafe {
+            match (is_center, new_center) {
+                (false, _) => self.update_node(owner, move |n| {
+                    n.remove_singleton(point_index);
+                }),
+                (true, None) => {
+                    self.layer(owner.0).remove_raw(owner.1);
+                    self.update_node(ancestors[0], move |n| {
+                        n.remove_child(owner, 1);
+                    });
+                }
+                (true, Some((center_index, radius))) => {
+                    let new_address =
+                        self.recenter_leaf(ancestors[0], owner, center_index, radius)?;
+                    let coverage = singletons.len() + 1;
+                    self.update_node(ancestors[0], move |n| {
+                        n.remove_child(owner, coverage);
+                        n.insert_child(new_address, coverage - 1).unwrap();
+                    });
+                }
+            }
+            // The parent of a cut or recentered leaf was updated above
+            let skip = if is_center { 1 } else { 0 };
+            for address in ancestors.iter().skip(skip) {
+                self.update_node(*address, |n| {
+                    n.set_coverage_count(n.coverage_count().saturating_sub(1))
+                });
+            }
+        }
+        self.final_addresses.remove(point_index);
+        self.publish();
+        Ok(owner.0)
+    }
+
+    /// Removes a node and everything under it from the tree, and returns the number of points that went with it.
+    /// The node is cut from its parent's children and the coverage counts of its ancestors drop by its coverage,
+    /// their radii are left alone. The plugins are told about each point first, and the removal is published to
+    /// the readers unless the refreshes are deferred by a [`CoverTree`]. The removed nodes go in the next
+    /// [`CoverTreeWriter::save_delta`].
+    ///
+    /// Errors with [`GokoError::PointNotRemovable`] if the node is the root or a nested child, as their center
+    /// holds up the node above them.
+    pub fn prune_subtree(&mut self, address: NodeAddress) -> GokoResult<usize> {
+        let parent = self
+            .get_node_pending_and(address, |n| n.parent_address())
+            .ok_or(GokoError::IndexNotInTree(address.1))?;
+        let parent = match parent {
+            Some(parent) if parent.1 != address.1 => parent,
+            _ => return Err(GokoError::PointNotRemovable(address.1)),
+        };
+
+        let mut nodes = Vec::new();
+        let mut points = Vec::new();
+        let mut unvisited = vec![address];
+        while let Some(current) = unvisited.pop() {
+            self.get_node_pending_and(current, |n| {
+                points.extend_from_slice(n.singletons());
+                match n.children() {
+                    Some((nested_scale, children)) => {
+                        unvisited.push((nested_scale, current.1));
+                        unvisited.extend_from_slice(children);
+                    }
+                    None => points.push(current.1),
+                }
+            });
+            nodes.push(current);
+        }
+        let coverage = points.len();
+        let ancestors = self.ancestors(address);
+
+        // The hooks publish after each point, that waits for the end here
+        let defer_refresh = self.defer_refresh;
+        self.defer_refresh = true;
+        let hooks: GokoResult<()> = points
+            .iter()
+            .map(|pi| self.plugins_on_remove(*pi))
+            .collect();
+        self.defer_refresh = defer_refresh;
+        hooks?;
+        unsafe {
+            for node in &nodes {
+                self.layer(node.0).remove_raw(node.1);
+            }
+            self.update_node(parent, move |n| {
+                n.remove_child(address, coverage);
+            });
+            for ancestor in ancestors.iter().skip(1) {
+                self.update_node(*ancestor, move |n| {
+                    n.set_coverage_count(n.coverage_count().saturating_sub(coverage))
+                });
+            }
+        }
+        for pi in points {
+            self.final_addresses.remove(pi);
+        }
+        self.parameters
+            .total_nodes
+            .fetch_sub(nodes.len(), atomic::Ordering::SeqCst);
+        debug!(
+            ?address,
+            nodes = nodes.len(),
+            points = coverage,
+            "Pruned subtree"
+        );
+        self.publish();
+        Ok(coverage)
+    }
+
+    /// Removes every point whose timestamp is before `timestamp`, see [`PointCloud::timestamp`]. Points without a
+    /// timestamp never expire. The singletons go first, so that a leaf isn't handed over to a point that is about
+    /// to expire as well.
+    pub fn expire_older_than(&mut self, timestamp: u64) -> GokoResult<ExpiryReport> {
+        let point_cloud = Arc::clone(&self.parameters.point_cloud);
+        let mut expired: Vec<(bool, usize)> = Vec::new();
+        for pi in point_cloud.reference_indexes() {
+            if let Some(t) = point_cloud.timestamp(pi)? {
+                if t < timestamp {
+                    if let Some(owner) = self.final_address_pending(pi) {
+                        expired.push((owner.1 == pi, pi));
+                    }
+                }
+            }
+        }
+        expired.sort_unstable();
+
+        let mut report = ExpiryReport::default();
+        for (_is_center, pi) in expired {
+            match self.remove_point(pi) {
+                Ok(scale_index) => *report.removed.entry(scale_index).or_insert(0) += 1,
+                Err(GokoError::PointNotRemovable(pi)) => report.retained.push(pi),
+                Err(e) => return Err(e),
+            }
+        }
+        debug!(
+            removed = report.removed_count(),
+            retained = report.retained.len(),
+            "Expired points"
+        );
+        Ok(report)
+    }
+
+    /// The addresses from the parent of the node up to the root.
+    fn ancestors(&self, address: NodeAddress) -> Vec<NodeAddress> {
+        let mut ancestors = Vec::new();
+        let mut parent = self
+            .get_node_pending_and(address, |n| n.parent_address())
+            .flatten();
+        while let Some(address) = parent {
+            ancestors.push(address);
+            parent = self
+                .get_node_pending_and(address, |n| n.parent_address())
+                .flatten();
+        }
+        ancestors
+    }
+
+    /// The point that covers the others with the smallest radius, and that radius. `None` if that radius is over
+    /// `max_radius`, as the knn prunes the leaf by its scale.
+    fn closest_center(
+        &self,
+        point_indexes: &[usize],
+        max_radius: f32,
+    ) -> GokoResult<Option<(usize, f32)>> {
+        let mut best: Option<(usize, f32)> = None;
+        for pi in point_indexes {
+            let radius = self
+                .parameters
+                .point_cloud
+                .distances_to_point_index(*pi, point_indexes)?
+                .iter()
+                .fold(0.0f32, |a, d| a.max(*d));
+            if radius <= max_radius && best.map(|(_, r)| radius < r).unwrap_or(true) {
+                best = Some((*pi, radius));
+            }
+        }
+        Ok(best)
+    }
+
+    /// Hands a leaf over to one of its singletons, and points the final addresses of its points at the new address.
+    /// The leaf keeps its plugin components, and the parent's components are moved over to the new address.
+    unsafe fn recenter_leaf(
+        &mut self,
+        parent: NodeAddress,
+        leaf: NodeAddress,
+        center_index: usize,
+        radius: f32,
+    ) -> GokoResult<NodeAddress> {
+        let mut node = self
+            .get_node_pending_and(leaf, |n| self.parameters.clone_node(n))
+            .ok_or(GokoError::IndexNotInTree(leaf.1))?;
+        node.recenter(center_index, radius);
+        let new_address = (leaf.0, center_index);
+        let moved: Vec<usize> = node
+            .singletons()
+            .iter()
+            .copied()
+            .chain(std::iter::once(center_index))
+            .collect();
+        for pi in moved.iter() {
+            self.final_addresses.insert(*pi, new_address);
+        }
+        let layer = self.layer(leaf.0);
+        layer.remove_raw(leaf.1);
+        layer.insert_raw(center_index, node);
+        self.plugins_on_rehome(parent, leaf, new_address, moved);
+        Ok(new_address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::{random_cloud, random_tree_builder};
+
+    fn build_timestamped_tree(
+        count: usize,
+    ) -> CoverTreeWriter<TimestampedCloud<DefaultLabeledCloud<L2>>> {
+        let point_cloud =
+            TimestampedCloud::new(random_cloud(count, 2), (0..count as u64).collect());
+        random_tree_builder().build(Arc::new(point_cloud)).unwrap()
+    }
+
+    #[test]
+    fn expire_older_than_removes_points() {
+        let count = 200;
+        let mut tree = build_timestamped_tree(count);
+        let report = tree.expire_older_than(100).unwrap();
+        assert_eq!(report.removed_count() + report.retained.len(), 100);
+        assert!(report.removed_count() > 0);
+
+        let reader = tree.reader();
+        assert!(reader.no_dangling_refs());
+        let root_coverage = reader
+            .get_node_and(reader.root_address(), |n| n.coverage_count())
+            .unwrap();
+        assert_eq!(root_coverage, count - report.removed_count());
+        for pi in 0..count {
+            let in_tree = reader.known_path(pi).is_ok();
+            assert_eq!(in_tree, pi >= 100 || report.retained.contains(&pi));
+        }
+
+        let query: Vec<f32> = reader.point_cloud().point(0).unwrap().to_vec();
+        for (_d, pi) in reader.knn(&&query[..], 10).unwrap() {
+            assert!(pi >= 100 || report.retained.contains(&pi));
+        }
+
+        // Nothing left to expire below 100
+        let report = tree.expire_older_than(100).unwrap();
+        assert_eq!(report.removed_count(), 0);
+    }
+
+    #[test]
+    fn prune_subtree_drops_its_points() {
+        let count = 200;
+        let mut tree = build_timestamped_tree(count);
+        let reader = tree.reader();
+        let root = reader.root_address();
+        assert!(matches!(
+            tree.prune_subtree(root),
+            Err(GokoError::PointNotRemovable(_))
+        ));
+        let (nested_scale, children) = reader
+            .get_node_and(root, |n| n.children().map(|(s, c)| (s, c.to_vec())))
+            .flatten()
+            .unwrap();
+        assert!(tree.prune_subtree((nested_scale, root.1)).is_err());
+
+        let child = children[0];
+        let child_coverage = reader.get_node_and(child, |n| n.coverage_count()).unwrap();
+        let pruned: Vec<usize> = (0..count)
+            .filter(|pi| {
+                reader
+                    .known_path(*pi)
+                    .unwrap()
+                    .iter()
+                    .any(|(_, address)| *address == child)
+            })
+            .collect();
+        assert_eq!(tree.prune_subtree(child).unwrap(), child_coverage);
+        assert_eq!(pruned.len(), child_coverage);
+
+        let reader = tree.reader();
+        assert!(reader.no_dangling_refs());
+        assert!(reader.get_node_and(child, |n| n.coverage_count()).is_none());
+        let root_coverage = reader.get_node_and(root, |n| n.coverage_count()).unwrap();
+        assert_eq!(root_coverage, count - child_coverage);
+        for pi in 0..count {
+            assert_eq!(reader.known_path(pi).is_ok(), !pruned.contains(&pi));
+        }
+        assert!(tree.validate().unwrap().is_valid());
+    }
+
+    #[test]
+    fn recentered_leaf_keeps_plugins() {
+        use crate::plugins::discrete::prelude::*;
+        let mut tree = build_timestamped_tree(200);
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let reader = tree.reader();
+        let mut leaves = Vec::new();
+        for (_si, layer) in reader.layers() {
+            layer.for_each_node(|_pi, n| {
+                if let Some(parent) = n.parent_address() {
+                    if n.is_leaf() && !n.singletons().is_empty() && parent.1 != *n.center_index() {
+                        leaves.push((n.address(), parent, n.coverage_count(), n.singletons()[0]));
+                    }
+                }
+            });
+        }
+        let (leaf, parent, coverage, singleton) = leaves
+            .into_iter()
+            .find(|(leaf, _, _, _)| tree.remove_point(leaf.1).is_ok())
+            .unwrap();
+
+        let reader = tree.reader();
+        let (_, new_address) = *reader.known_path(singleton).unwrap().last().unwrap();
+        assert_ne!(new_address, leaf);
+        let total = reader
+            .get_node_plugin_and::<Dirichlet, _, _>(new_address, |d| d.total())
+            .expect("the recentered leaf should keep its plugin");
+        assert_approx_eq!(total, (coverage - 1) as f64);
+
+        let (children, _) = reader
+            .get_node_plugin_and::<Dirichlet, _, _>(parent, |d| d.prob_vector())
+            .flatten()
+            .unwrap();
+        let share = |address| {
+            children
+                .iter()
+                .find(|(a, _)| *a == address)
+                .map_or(0.0, |(_, p)| *p)
+        };
+        // Only the prior is left under the old address
+        assert!(share(new_address) > share(leaf));
+    }
+
+    #[test]
+    fn pruned_subtrees_are_saved_in_deltas() {
+        let count = 200;
+        let mut tree = build_timestamped_tree(count);
+        let (version, base) = tree.save_checkpoint();
+        let reader = tree.reader();
+        let root = reader.root_address();
+        let child = reader
+            .get_node_and(root, |n| n.children().map(|(_, c)| c[0]))
+            .flatten()
+            .unwrap();
+        let node_count = reader.node_count();
+        let pruned = tree.prune_subtree(child).unwrap();
+
+        let delta = tree.save_delta(version).unwrap();
+        assert!(delta.manifest.removed_count > 0);
+        assert_eq!(
+            node_count - delta.manifest.removed_count,
+            tree.reader().node_count()
+        );
+        let point_cloud = Arc::clone(&tree.parameters.point_cloud);
+        let loaded =
+            CoverTreeWriter::load_with_deltas(&base, version, &[delta], point_cloud).unwrap();
+        let reader = loaded.reader();
+        assert!(reader.no_dangling_refs());
+        assert!(reader.get_node_and(child, |_| ()).is_none());
+        assert_eq!(reader.node_count(), tree.reader().node_count());
+        let root_coverage = reader.get_node_and(root, |n| n.coverage_count()).unwrap();
+        assert_eq!(root_coverage, count - pruned);
+        assert!(loaded.validate().unwrap().is_valid());
+    }
+}
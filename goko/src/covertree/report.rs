@@ -0,0 +1,142 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Path reports
+//!
+//! [`CoverTreeReader::path_report`] walks the same path as [`CoverTreeReader::path`], but reads the coverage and label
+//! summary of each node while it is already looking at the node. Gluing that together from `path` and a
+//! `get_node_label_summary` per node costs an extra lookup for every step.
+
+use super::*;
+use crate::errors::GokoResult;
+use crate::NodeAddress;
+use pointcloud::*;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// A node on the path of a query point, see [`CoverTreeReader::path_report`].
+#[derive(Debug)]
+pub struct PathStep<D: PointCloud> {
+    /// The address of the node
+    pub address: NodeAddress,
+    /// The scale index of the node
+    pub scale_index: i32,
+    /// The distance from the query point to the center of the node
+    pub distance: f32,
+    /// The number of points the node covers
+    pub coverage_count: usize,
+    /// The label summary of the node, `None` if the summaries weren't generated
+    pub label_summary: Option<Arc<SummaryCounter<D::LabelSummary>>>,
+}
+
+impl<D: PointCloud> Clone for PathStep<D> {
+    fn clone(&self) -> Self {
+        PathStep {
+            address: self.address,
+            scale_index: self.scale_index,
+            distance: self.distance,
+            coverage_count: self.coverage_count,
+            label_summary: self.label_summary.clone(),
+        }
+    }
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// The path the point would be inserted along, with the coverage and label summary of each node. The steps are
+    /// the same as [`Self::path`], from the root down.
+    pub fn path_report<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+    ) -> GokoResult<Vec<PathStep<D>>> {
+        let parameters = self.parameters();
+        let root_address = self.root_address();
+        let root_center = parameters.point_cloud.point(root_address.1)?;
+        let mut current_distance = D::Metric::dist(&root_center, &point);
+        let mut current_address = root_address;
+        let mut report = Vec::new();
+        while let Some((step, nearest)) = self.get_node_and(current_address, |n| {
+            let step = PathStep {
+                address: current_address,
+                scale_index: current_address.0,
+                distance: current_distance,
+                coverage_count: n.coverage_count(),
+                label_summary: n.label_summary(),
+            };
+            let nearest = match parameters.partition_type {
+                PartitionType::Nearest => n.nearest_covering_child(
+                    parameters.scale_base,
+                    current_distance,
+                    point,
+                    &parameters.point_cloud,
+                ),
+                PartitionType::First => n.first_covering_child(
+                    parameters.scale_base,
+                    current_distance,
+                    point,
+                    &parameters.point_cloud,
+                ),
+            };
+            (step, nearest)
+        }) {
+            report.push(step);
+            match nearest? {
+                Some((distance, address)) => {
+                    current_distance = distance;
+                    current_address = address;
+                }
+                None => break,
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn path_report_matches_path() {
+        let mut writer = build_basic_tree();
+        writer.generate_summaries();
+        let reader = writer.reader();
+        let point = [0.495f32];
+        let path = reader.path(&point.as_ref()).unwrap();
+        let report = reader.path_report(&point.as_ref()).unwrap();
+        assert_eq!(path.len(), report.len());
+        for ((distance, address), step) in path.iter().zip(&report) {
+            assert_eq!(*address, step.address);
+            assert_eq!(address.0, step.scale_index);
+            assert_approx_eq!(*distance, step.distance);
+            let coverage = reader
+                .get_node_and(*address, |n| n.coverage_count())
+                .unwrap();
+            assert_eq!(coverage, step.coverage_count);
+            let summary = reader.get_node_label_summary(*address).unwrap();
+            assert_eq!(
+                summary.summary.items,
+                step.label_summary.as_ref().unwrap().summary.items
+            );
+        }
+        assert!(report
+            .windows(2)
+            .all(|w| w[0].coverage_count >= w[1].coverage_count));
+    }
+}
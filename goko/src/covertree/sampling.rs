@@ -0,0 +1,210 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Sampling
+//!
+//! A cover tree is a cheap generative model of its data. [`CoverTreeReader::sample`] walks down from the root,
+//! picking a child or a singleton of each node in proportion to how many points it covers, and returns the point it
+//! lands on. When a [`GokoDirichlet`] or [`GokoCategorical`] plugin is attached its node distributions pick the
+//! children instead, so the samples follow what the plugin has seen rather than the training set.
+//! [`CoverTreeReader::sample_synthetic`] lands the same way and then draws a new point from the
+//! [`GokoDiagGaussian`](crate::plugins::gaussians::GokoDiagGaussian) of that node.
+
+use super::*;
+use crate::errors::{GokoError, GokoResult};
+use crate::plugins::discrete::prelude::*;
+use crate::plugins::gaussians::*;
+use crate::NodeAddress;
+use pointcloud::*;
+use rand::Rng;
+
+/// A step of the walk down the tree, either into a child or stopping at the current node. A point is picked if the
+/// walk stopped on a singleton.
+enum Step {
+    Descend(NodeAddress),
+    Land(Option<usize>),
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// Samples the index of a point in the tree, see the [module docs](self). With no plugin attached every point
+    /// is equally likely.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> GokoResult<usize> {
+        match self.sample_landing(rng)? {
+            (_, Some(pi)) => Ok(pi),
+            (address, None) => self
+                .get_node_and(address, |n| {
+                    // The center and the singletons of the node are equally likely
+                    let i = rng.gen_range(0..=n.singletons_len());
+                    if i == 0 {
+                        *n.center_index()
+                    } else {
+                        n.singletons()[i - 1]
+                    }
+                })
+                .ok_or(GokoError::IndexNotInTree(address.1)),
+        }
+    }
+
+    /// Samples a new point from the diagonal Gaussian of the node the walk lands in. Returns `None` if that node
+    /// has no Gaussian, attach a [`GokoDiagGaussian`] first.
+    pub fn sample_synthetic<R: Rng>(&self, rng: &mut R) -> GokoResult<Option<Vec<f32>>> {
+        let (address, _) = self.sample_landing(rng)?;
+        Ok(self
+            .get_node_plugin_and::<DiagGaussian, _, _>(address, |gaussian| {
+                if gaussian.count() > 0 {
+                    Some(gaussian.sample(rng))
+                } else {
+                    None
+                }
+            })
+            .flatten())
+    }
+
+    fn sample_landing<R: Rng>(&self, rng: &mut R) -> GokoResult<(NodeAddress, Option<usize>)> {
+        let use_dirichlet = self.plugin_attached::<GokoDirichlet>();
+        let use_categorical = !use_dirichlet && self.plugin_attached::<GokoCategorical>();
+        let mut address = self.root_address();
+        loop {
+            let step = if use_dirichlet || use_categorical {
+                self.plugin_step(address, use_dirichlet, rng)?
+            } else {
+                self.coverage_step(address, rng)?
+            };
+            match step {
+                Step::Descend(child) => address = child,
+                Step::Land(point) => return Ok((address, point)),
+            }
+        }
+    }
+
+    /// Lets the node's Dirichlet or categorical pick the child. If it picks the singletons, or has seen nothing,
+    /// one of the singletons is picked uniformly.
+    fn plugin_step<R: Rng>(
+        &self,
+        address: NodeAddress,
+        use_dirichlet: bool,
+        rng: &mut R,
+    ) -> GokoResult<Step> {
+        let child = if use_dirichlet {
+            self.get_node_plugin_and::<Dirichlet, _, _>(address, |d| {
+                if d.total() >= 1.0 {
+                    d.sample(rng)
+                } else {
+                    None
+                }
+            })
+        } else {
+            self.get_node_plugin_and::<Categorical, _, _>(address, |c| {
+                if c.total() >= 1.0 {
+                    c.sample(rng)
+                } else {
+                    None
+                }
+            })
+        };
+        if let Some(child) = child.flatten() {
+            return Ok(Step::Descend(child));
+        }
+        self.get_node_and(address, |n| {
+            if n.is_leaf() || n.singletons_len() == 0 {
+                Step::Land(None)
+            } else {
+                Step::Land(Some(n.singletons()[rng.gen_range(0..n.singletons_len())]))
+            }
+        })
+        .ok_or(GokoError::IndexNotInTree(address.1))
+    }
+
+    /// Picks a child or a singleton of the node in proportion to the points each covers.
+    fn coverage_step<R: Rng>(&self, address: NodeAddress, rng: &mut R) -> GokoResult<Step> {
+        let (children, singletons) = self
+            .get_node_and(address, |n| {
+                let children = n.children().map(|(nested_scale, others)| {
+                    let mut children = vec![(nested_scale, address.1)];
+                    children.extend_from_slice(others);
+                    children
+                });
+                (children, n.singletons().to_vec())
+            })
+            .ok_or(GokoError::IndexNotInTree(address.1))?;
+        let children = match children {
+            Some(children) => children,
+            None => return Ok(Step::Land(None)),
+        };
+        let weights: Vec<usize> = children
+            .iter()
+            .map(|child| {
+                self.get_node_and(*child, |n| n.coverage_count())
+                    .unwrap_or(0)
+            })
+            .collect();
+        let total = weights.iter().sum::<usize>() + singletons.len();
+        if total == 0 {
+            return Ok(Step::Land(None));
+        }
+        let mut draw = rng.gen_range(0..total);
+        for (child, weight) in children.iter().zip(weights) {
+            if draw < weight {
+                return Ok(Step::Descend(*child));
+            }
+            draw -= weight;
+        }
+        Ok(Step::Land(Some(singletons[draw])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn samples_cover_the_points() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut counts = vec![0; 5];
+        for _ in 0..2000 {
+            counts[reader.sample(&mut rng).unwrap()] += 1;
+        }
+        // Every point is equally likely, 400 each on average
+        for count in counts {
+            assert!(count > 250 && count < 550, "{}", count);
+        }
+    }
+
+    #[test]
+    fn synthetic_samples_need_gaussians() {
+        let mut tree = build_basic_tree();
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert_eq!(tree.reader().sample_synthetic(&mut rng).unwrap(), None);
+
+        tree.add_plugin::<GokoDiagGaussian>(GokoDiagGaussian::recursive());
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let reader = tree.reader();
+        for _ in 0..100 {
+            assert!(reader.sample(&mut rng).unwrap() < 5);
+            if let Some(point) = reader.sample_synthetic(&mut rng).unwrap() {
+                assert_eq!(point.len(), 1);
+            }
+        }
+    }
+}
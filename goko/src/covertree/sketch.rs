@@ -0,0 +1,184 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Distance Sketches
+//!
+//! For very high dimensional data (a few thousand dimensions and up) the singleton scan at the bottom of a knn query
+//! is dominated by the cost of the metric. A sketch is a small summary of each point that gives a cheap lower bound
+//! on the distance to a query. The scan in [`crate::CoverTreeReader::knn_sketched`] uses it to skip the singletons
+//! that can't beat the current `k`th neighbor, the rest are evaluated exactly so the results are the same as
+//! [`crate::CoverTreeReader::knn`].
+//!
+//! Sketches are swappable, attach one with [`crate::CoverTreeWriter::set_sketch`]. Below about 4096 dimensions the
+//! exact metric is usually cheap enough that the sketch doesn't pay for itself, see the `sketch_bench` benchmark.
+
+use crate::errors::GokoResult;
+use pointcloud::*;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::StandardNormal;
+use rayon::prelude::*;
+use std::fmt::Debug;
+
+/// A per point summary that lower bounds the distance of the point cloud's metric.
+pub trait DistanceSketch<D: PointCloud>: Send + Sync + Debug + 'static {
+    /// Sketches a query point.
+    fn sketch_query(&self, point: &[f32]) -> Vec<f32>;
+    /// A lower bound on the distance between the sketched query and the point at the index. This must never be
+    /// larger than the real distance or the knn stops being exact. Return 0 for points you don't know.
+    fn lower_bound(&self, query_sketch: &[f32], point_index: usize) -> f32;
+}
+
+/// Rounding in the projection can push the bound a hair over the real distance, so we shave it down by this much.
+const BOUND_SLACK: f32 = 1.0e-4;
+
+/// Projects the points onto a random orthonormal basis of a small subspace. An orthogonal projection never increases
+/// the L2 distance, so the distance between the projections is a lower bound on the real one. This is only
+/// implemented for L2 point clouds, the other metrics need their own sketch.
+#[derive(Debug)]
+pub struct RandomProjectionSketch {
+    dim: usize,
+    sketch_dim: usize,
+    /// `sketch_dim` orthonormal rows of length `dim`
+    projection: Vec<f32>,
+    /// `sketch_dim` values per point, in point index order
+    sketches: Vec<f32>,
+}
+
+impl RandomProjectionSketch {
+    /// Sketches every point in the cloud onto `sketch_dim` random directions. The sketch dimension is capped at the
+    /// dimension of the data. A `sketch_dim` of 32 to 64 is a good start, this costs that many floats per point.
+    pub fn new<D: PointCloud<Metric = L2>>(
+        point_cloud: &D,
+        sketch_dim: usize,
+        seed: u64,
+    ) -> GokoResult<RandomProjectionSketch> {
+        let dim = point_cloud.dim();
+        let sketch_dim = sketch_dim.min(dim).max(1);
+        let projection = random_orthonormal_rows(dim, sketch_dim, seed);
+
+        let sketches: Vec<Vec<f32>> = (0..point_cloud.len())
+            .into_par_iter()
+            .map(|pi| {
+                let point = point_cloud.point(pi)?;
+                Ok(project(&projection, dim, point.dense_iter()))
+            })
+            .collect::<GokoResult<Vec<Vec<f32>>>>()?;
+
+        Ok(RandomProjectionSketch {
+            dim,
+            sketch_dim,
+            projection,
+            sketches: sketches.into_iter().flatten().collect(),
+        })
+    }
+
+    /// The number of floats stored per point
+    pub fn sketch_dim(&self) -> usize {
+        self.sketch_dim
+    }
+}
+
+impl<D: PointCloud<Metric = L2>> DistanceSketch<D> for RandomProjectionSketch {
+    fn sketch_query(&self, point: &[f32]) -> Vec<f32> {
+        project(&self.projection, self.dim, point.iter().cloned())
+    }
+
+    fn lower_bound(&self, query_sketch: &[f32], point_index: usize) -> f32 {
+        let start = point_index * self.sketch_dim;
+        match self.sketches.get(start..start + self.sketch_dim) {
+            Some(sketch) => {
+                let sq_dist: f32 = sketch
+                    .iter()
+                    .zip(query_sketch)
+                    .map(|(a, b)| (a - b) * (a - b))
+                    .sum();
+                sq_dist.sqrt() * (1.0 - BOUND_SLACK)
+            }
+            None => 0.0,
+        }
+    }
+}
+
+fn project<I: Iterator<Item = f32>>(projection: &[f32], dim: usize, point: I) -> Vec<f32> {
+    let point: Vec<f32> = point.collect();
+    projection
+        .chunks_exact(dim)
+        .map(|row| row.iter().zip(point.iter()).map(|(r, x)| r * x).sum())
+        .collect()
+}
+
+/// Gram-Schmidt on gaussian rows.
+fn random_orthonormal_rows(dim: usize, count: usize, seed: u64) -> Vec<f32> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut rows: Vec<f32> = Vec::with_capacity(dim * count);
+    while rows.len() < dim * count {
+        let mut row: Vec<f32> = (0..dim).map(|_| rng.sample(StandardNormal)).collect();
+        for prev in rows.chunks_exact(dim) {
+            let dot: f32 = prev.iter().zip(row.iter()).map(|(p, r)| p * r).sum();
+            row.iter_mut().zip(prev).for_each(|(r, p)| *r -= dot * p);
+        }
+        let norm = row.iter().map(|r| r * r).sum::<f32>().sqrt();
+        // A nearly dependent draw loses too much precision, try again
+        if norm > 1.0e-3 {
+            rows.extend(row.iter().map(|r| r / norm));
+        }
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::{random_cloud, random_tree_builder};
+    use crate::covertree::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn sketch_is_lower_bound() {
+        let point_cloud = random_cloud(100, 64);
+        let sketch = RandomProjectionSketch::new(&point_cloud, 8, 0).unwrap();
+        let query = point_cloud.point(0).unwrap();
+        let query_sketch = DistanceSketch::<DefaultLabeledCloud<L2>>::sketch_query(&sketch, &query);
+        let distances = point_cloud
+            .distances_to_point(&query, &(0..100).collect::<Vec<usize>>())
+            .unwrap();
+        for (pi, d) in distances.iter().enumerate() {
+            let bound =
+                DistanceSketch::<DefaultLabeledCloud<L2>>::lower_bound(&sketch, &query_sketch, pi);
+            assert!(bound <= *d, "bound {} is over the distance {}", bound, d);
+        }
+    }
+
+    #[test]
+    fn sketched_knn_is_exact() {
+        let point_cloud = random_cloud(500, 64);
+        let sketch = RandomProjectionSketch::new(&point_cloud, 8, 0).unwrap();
+        let mut builder = random_tree_builder();
+        builder.leaf_cutoff = 10;
+        let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
+        let reader = tree.reader();
+        let query: Vec<f32> = reader.point_cloud().point(3).unwrap().to_vec();
+        let expected = reader.knn(&&query[..], 10).unwrap();
+        assert_eq!(reader.knn_sketched(&&query[..], 10).unwrap(), expected);
+
+        tree.set_sketch(sketch);
+        assert_eq!(reader.knn_sketched(&&query[..], 10).unwrap(), expected);
+    }
+}
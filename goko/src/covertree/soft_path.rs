@@ -0,0 +1,221 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Soft paths
+//!
+//! [`CoverTreeReader::path`] sends a point down to a single child at each node, so a point near the boundary of two
+//! children lands in one of them by a hair. [`CoverTreeReader::soft_path`] splits the point between every child that
+//! covers it instead, with softmax weights on the distances to their centers. The weight that reaches a node is
+//! split again between its covering children, and stays in the node if none of them cover the point.
+//!
+//! The weights of the nodes where the point stops add up to 1. Feed the path to a tracker with
+//! [`crate::plugins::discrete::tracker::BayesCategoricalTracker::add_soft_path`].
+
+use super::*;
+use crate::errors::GokoResult;
+use crate::NodeAddress;
+use pointcloud::*;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::ops::Deref;
+
+/// Children whose share of the point would be smaller than this aren't followed, their weight goes to their
+/// siblings. This keeps a soft path at a high temperature from visiting most of the tree.
+const MIN_SOFT_WEIGHT: f32 = 1.0e-4;
+
+/// A node on a soft path.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SoftNode {
+    /// The address of the node
+    pub address: NodeAddress,
+    /// The node the weight came from, `None` for the root
+    pub parent: Option<NodeAddress>,
+    /// The distance from the point to the node's center
+    pub dist: f32,
+    /// The share of the point that reached the node
+    pub weight: f32,
+    /// If the weight stays in the node, none of its children cover the point
+    pub terminal: bool,
+}
+
+/// The nodes a point was split between, parents before their children. See the module docs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SoftPath {
+    /// The nodes the point reached, the root first
+    pub nodes: Vec<SoftNode>,
+}
+
+impl SoftPath {
+    /// The nodes where the point stopped, their weights add up to 1.
+    pub fn terminals(&self) -> impl Iterator<Item = &SoftNode> {
+        self.nodes.iter().filter(|n| n.terminal)
+    }
+
+    /// The chain from the root to the terminal node with the most weight, in the format of
+    /// [`CoverTreeReader::path`].
+    pub fn heaviest_path(&self) -> Vec<(f32, NodeAddress)> {
+        let heaviest = self
+            .terminals()
+            .max_by(|a, b| a.weight.partial_cmp(&b.weight).unwrap_or(Ordering::Equal));
+        let mut chain = Vec::new();
+        let mut current = heaviest;
+        while let Some(node) = current {
+            chain.push((node.dist, node.address));
+            current = node
+                .parent
+                .and_then(|p| self.nodes.iter().find(|n| n.address == p));
+        }
+        chain.reverse();
+        chain
+    }
+}
+
+/// The softmax of the negative distances over the temperature. A temperature that isn't positive puts all the weight
+/// on the nearest.
+fn softmax_weights(dists: &[f32], temperature: f32) -> Vec<f32> {
+    let min_dist = dists.iter().cloned().fold(std::f32::INFINITY, f32::min);
+    if temperature.is_nan() || temperature <= 0.0 {
+        let nearest = dists.iter().position(|d| *d == min_dist);
+        return (0..dists.len())
+            .map(|i| if Some(i) == nearest { 1.0 } else { 0.0 })
+            .collect();
+    }
+    let exps: Vec<f32> = dists
+        .iter()
+        .map(|d| (-(d - min_dist) / temperature).exp())
+        .collect();
+    let total: f32 = exps.iter().sum();
+    exps.iter().map(|e| e / total).collect()
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// Splits the point between all the children that cover it at each level, instead of picking one like
+    /// [`Self::path`]. A covering child at distance `d` gets a share proportional to `exp(-d/temperature)`, so a
+    /// small temperature is close to the hard path and a large one splits the point evenly. A temperature of 0 puts
+    /// all the weight on the nearest covering child.
+    pub fn soft_path<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        temperature: f32,
+    ) -> GokoResult<SoftPath> {
+        let point_cloud = &self.parameters().point_cloud;
+        let scale_base = self.parameters().scale_base;
+        let root_center = point_cloud.point(self.root_address().1)?;
+        let mut nodes = vec![SoftNode {
+            address: self.root_address(),
+            parent: None,
+            dist: D::Metric::dist(&root_center, &point),
+            weight: 1.0,
+            terminal: true,
+        }];
+        let mut i = 0;
+        while i < nodes.len() {
+            let current = nodes[i];
+            let covering = self
+                .get_node_and(current.address, |n| {
+                    n.covering_children(scale_base, current.dist, point, point_cloud)
+                })
+                .transpose()?
+                .unwrap_or_default();
+            if !covering.is_empty() {
+                let dists: Vec<f32> = covering.iter().map(|(d, _)| *d).collect();
+                let weights = softmax_weights(&dists, temperature);
+                let heaviest = weights.iter().cloned().fold(0.0, f32::max);
+                let kept: Vec<((f32, NodeAddress), f32)> = covering
+                    .into_iter()
+                    .zip(weights)
+                    .filter(|(_, w)| *w == heaviest || current.weight * w >= MIN_SOFT_WEIGHT)
+                    .collect();
+                let kept_total: f32 = kept.iter().map(|(_, w)| w).sum();
+                nodes[i].terminal = false;
+                nodes.extend(kept.into_iter().map(|((dist, address), w)| SoftNode {
+                    address,
+                    parent: Some(current.address),
+                    dist,
+                    weight: current.weight * w / kept_total,
+                    terminal: true,
+                }));
+            }
+            i += 1;
+        }
+        Ok(SoftPath { nodes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn soft_path_weights_add_up() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        for temperature in &[0.01, 0.1, 1.0, 10.0] {
+            let soft_path = reader.soft_path(&&[0.45][..], *temperature).unwrap();
+            let total: f32 = soft_path.terminals().map(|n| n.weight).sum();
+            assert_approx_eq!(total, 1.0);
+            assert_eq!(soft_path.nodes[0].address, reader.root_address());
+            for node in &soft_path.nodes[1..] {
+                let parent = node.parent.unwrap();
+                let children_weight: f32 = soft_path
+                    .nodes
+                    .iter()
+                    .filter(|n| n.parent == Some(parent))
+                    .map(|n| n.weight)
+                    .sum();
+                let parent_weight = soft_path
+                    .nodes
+                    .iter()
+                    .find(|n| n.address == parent)
+                    .unwrap()
+                    .weight;
+                assert_approx_eq!(children_weight, parent_weight);
+            }
+        }
+    }
+
+    #[test]
+    fn cold_soft_path_is_a_chain() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let point = [0.45];
+        let soft_path = reader.soft_path(&&point[..], 0.0).unwrap();
+        assert_eq!(soft_path.terminals().count(), 1);
+        assert!(soft_path.nodes.iter().all(|n| n.weight == 1.0));
+        // All of the point goes down one chain, and it's the hard path
+        assert_eq!(soft_path.heaviest_path().len(), soft_path.nodes.len());
+        assert_eq!(soft_path.heaviest_path(), reader.path(&&point[..]).unwrap());
+    }
+
+    #[test]
+    fn hot_soft_path_splits_evenly() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let soft_path = reader.soft_path(&&[0.0][..], 1.0e6).unwrap();
+        let root_children: Vec<&SoftNode> = soft_path
+            .nodes
+            .iter()
+            .filter(|n| n.parent == Some(reader.root_address()))
+            .collect();
+        for child in &root_children {
+            assert_approx_eq!(child.weight, 1.0 / root_children.len() as f32, 1.0e-3);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Tree statistics
+//!
+//! [`CoverTreeReader::tree_stats`] walks every layer once and sums up the shape of the tree, how many nodes, leaves
+//! and singletons each layer has, how many children the nodes split into and how big they are. It's the traversal
+//! everyone ends up writing to check that a tree's parameters were sensible.
+
+use super::*;
+use pointcloud::*;
+use serde::{Deserialize, Serialize};
+
+/// The parameters a tree was built with, see [`CoverTreeParameters`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BuildParameters {
+    /// See [`CoverTreeParameters::scale_base`]
+    pub scale_base: f32,
+    /// See [`CoverTreeParameters::leaf_cutoff`]
+    pub leaf_cutoff: usize,
+    /// See [`CoverTreeParameters::min_res_index`]
+    pub min_res_index: i32,
+    /// See [`CoverTreeParameters::use_singletons`]
+    pub use_singletons: bool,
+    /// See [`CoverTreeParameters::partition_type`]
+    pub partition_type: PartitionType,
+    /// See [`CoverTreeParameters::rng_seed`]
+    pub rng_seed: Option<u64>,
+}
+
+/// The shape of one layer of the tree, see [`CoverTreeReader::tree_stats`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LayerStats {
+    /// The scale index of the layer
+    pub scale_index: i32,
+    /// The number of nodes on the layer
+    pub node_count: usize,
+    /// The number of those nodes that are leaves
+    pub leaf_count: usize,
+    /// The number of singletons held by the nodes of the layer
+    pub singleton_count: usize,
+    /// The mean radius of the nodes on the layer, 0 if there are none
+    pub mean_radius: f64,
+}
+
+/// The shape of the tree, see the [module docs](self).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TreeStats {
+    /// The number of points the tree covers
+    pub point_count: usize,
+    /// The number of nodes in the tree
+    pub node_count: usize,
+    /// The number of leaves in the tree
+    pub leaf_count: usize,
+    /// The number of singletons in the tree
+    pub singleton_count: usize,
+    /// The mean number of children of the nodes that aren't leaves, counting the nested child
+    pub mean_children: f64,
+    /// The most children any node has, counting the nested child
+    pub max_children: usize,
+    /// The layers, starting from the one that holds the root
+    pub layers: Vec<LayerStats>,
+    /// The parameters the tree was built with
+    pub parameters: BuildParameters,
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// Node, leaf and singleton counts of the tree and its layers. See the [module docs](self).
+    pub fn tree_stats(&self) -> TreeStats {
+        let mut child_total = 0;
+        let mut max_children = 0;
+        let layers: Vec<LayerStats> = self
+            .layers()
+            .map(|(scale_index, layer)| {
+                let mut stats = LayerStats {
+                    scale_index,
+                    node_count: layer.len(),
+                    ..Default::default()
+                };
+                let mut radius_total = 0.0;
+                layer.for_each_node(|_, n| {
+                    stats.singleton_count += n.singletons_len();
+                    radius_total += n.radius() as f64;
+                    match n.children() {
+                        None => stats.leaf_count += 1,
+                        Some((_, child_addresses)) => {
+                            let children = child_addresses.len() + 1;
+                            child_total += children;
+                            max_children = max_children.max(children);
+                        }
+                    }
+                });
+                if stats.node_count > 0 {
+                    stats.mean_radius = radius_total / stats.node_count as f64;
+                }
+                stats
+            })
+            .collect();
+        let node_count: usize = layers.iter().map(|l| l.node_count).sum();
+        let leaf_count: usize = layers.iter().map(|l| l.leaf_count).sum();
+        let parents = node_count - leaf_count;
+        let parameters = &self.parameters();
+        TreeStats {
+            point_count: self
+                .get_node_and(self.root_address(), |n| n.coverage_count())
+                .unwrap_or(0),
+            node_count,
+            leaf_count,
+            singleton_count: layers.iter().map(|l| l.singleton_count).sum(),
+            mean_children: if parents > 0 {
+                child_total as f64 / parents as f64
+            } else {
+                0.0
+            },
+            max_children,
+            layers,
+            parameters: BuildParameters {
+                scale_base: parameters.scale_base,
+                leaf_cutoff: parameters.leaf_cutoff,
+                min_res_index: parameters.min_res_index,
+                use_singletons: parameters.use_singletons,
+                partition_type: parameters.partition_type,
+                rng_seed: parameters.rng_seed,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn stats_of_the_basic_tree() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let stats = reader.tree_stats();
+        assert_eq!(stats.point_count, 5);
+        assert_eq!(stats.node_count, reader.node_count());
+        assert_eq!(stats.layers.len(), reader.len());
+        assert_eq!(stats.layers[0].scale_index, reader.root_address().0);
+        assert!(stats.leaf_count >= 1 && stats.leaf_count < stats.node_count);
+        // Every point is either the center of one leaf or a singleton
+        assert_eq!(stats.leaf_count + stats.singleton_count, 5);
+        assert!(stats.max_children >= 1);
+        assert!(stats.mean_children >= 1.0);
+        assert_eq!(
+            stats.parameters.leaf_cutoff,
+            reader.parameters().leaf_cutoff
+        );
+    }
+}
@@ -40,20 +40,40 @@ use crate::*;
 
 use crate::monomap::{MonoReadHandle, MonoWriteHandle};
 use crate::tree_file_format::*;
+use pointcloud::data_sources::PinCache;
 use std::sync::{atomic, Arc, RwLock};
 
-use super::query_tools::{KnnQueryHeap, RoutingQueryHeap};
-use crate::plugins::{GokoPlugin, TreePluginSet};
+use super::accelerator::DistanceAccelerator;
+use super::budget::BudgetMeter;
+use super::query_tools::{ChildDistanceCache, KnnQueryHeap, RoutingQueryHeap};
+use super::sketch::DistanceSketch;
+use crate::plugins::aggregate::{aggregate_node, Mergeable};
+use crate::plugins::centroids::NodeCentroid;
+use crate::plugins::{
+    plugin_builder, plugin_copier, plugin_hook, plugin_sizer, GokoPlugin, NodePlugin,
+    PluginAttachments, PluginBuilder, PluginCopier, PluginEvent, PluginHook, PluginSizer,
+    PluginStatus, TreePluginSet,
+};
 use errors::{GokoError, GokoResult};
 use serde::{Deserialize, Serialize};
+use std::any::TypeId;
+use std::collections::VecDeque;
 use std::iter::Iterator;
 use std::iter::Rev;
 use std::ops::Deref;
 use std::ops::Range;
 use std::slice::Iter;
+use tracing::trace;
 
 use plugins::labels::*;
 
+use hashbrown::{HashMap, HashSet};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+/// The number of points `recount_coverage` samples when it isn't exact.
+const COVERAGE_SAMPLE_SIZE: usize = 100_000;
+
 /// When 2 spheres overlap under a node, and there is a point in the overlap we have to decide
 /// to which sphere it belongs. As we create the nodes in a particular sequence, we can assign them
 /// to the first to be created or we can assign it to the nearest.
@@ -71,6 +91,9 @@ pub struct CoverTreeParameters<D: PointCloud> {
     /// An atomic that tracks all nodes as they are created across all threads.
     /// This may not reflect what your current reader can see.
     pub total_nodes: atomic::AtomicUsize,
+    /// Bumped every time the writer publishes changes to the readers, so you can tell which version of the tree
+    /// answered a query.
+    pub epoch: atomic::AtomicU64,
     /// See paper or main description, governs the number of children of each node. Higher is more.
     pub scale_base: f32,
     /// If a node covers less than or equal to this number of points, it becomes a leaf.
@@ -81,9 +104,15 @@ pub struct CoverTreeParameters<D: PointCloud> {
     pub use_singletons: bool,
     /// The partition type of the tree
     pub partition_type: PartitionType,
-    /// This should be replaced by a logging solution
+    /// For supervised data. When this is more than 1 the `Nearest` builder draws this many candidates for each new
+    /// center and keeps the one whose covered points have the lowest label entropy, breaking ties by the number of
+    /// points covered. This makes purer nodes, so routing a point down the tree is a better classifier. The labels
+    /// need a summary with an entropy, like [`pointcloud::summaries::CategorySummary`]. This only affects the build.
+    pub label_candidates: usize,
+    /// Above 1 a progress bar is drawn while building. Everything else is logged through `tracing`
     pub verbosity: u32,
-    /// The seed to use for deterministic trees. This is xor-ed with the point index to create a seed for `rand::rngs::SmallRng`.
+    /// The seed to use for deterministic trees. It's hashed with the node being split to seed a `rand::rngs::SmallRng`.
+    /// The queries that sample take their randomness from this too, see [`CoverTreeParameters::stream_rng`].
     ///
     /// Pass in None if you want to use the host os's entropy instead.
     pub rng_seed: Option<u64>,
@@ -91,6 +120,17 @@ pub struct CoverTreeParameters<D: PointCloud> {
     pub point_cloud: Arc<D>,
     /// This is where the base plugins are are stored.
     pub plugins: RwLock<TreePluginSet>,
+    /// How far along attaching each plugin is, so that readers can tell a missing component from one that hasn't
+    /// been made yet.
+    pub plugin_attachments: RwLock<PluginAttachments>,
+    /// Reads the size of each attached plugin's components, for [`CoverTreeReader::memory_report`]
+    pub(crate) plugin_sizers: RwLock<HashMap<TypeId, PluginSizer<D>>>,
+    /// Copies each attached plugin's component from node to node, for [`CoverTreeParameters::clone_node`]
+    pub(crate) plugin_copiers: RwLock<HashMap<TypeId, PluginCopier<D>>>,
+    /// The distance sketch used by `knn_sketched`, if any. See [`crate::sketch`].
+    pub sketch: RwLock<Option<Arc<dyn DistanceSketch<D>>>>,
+    /// The accelerator the knn hands its large distance batches to, if any. See [`crate::accelerator`].
+    pub accelerator: RwLock<Option<Arc<dyn DistanceAccelerator<D>>>>,
 }
 
 impl<D: PointCloud> CoverTreeParameters<D> {
@@ -103,6 +143,66 @@ impl<D: PointCloud> CoverTreeParameters<D> {
             (scale_index - self.min_res_index + 1) as usize
         }
     }
+
+    /// Clones a node along with the components of the attached plugins. The node's own `clone` leaves them behind.
+    pub(crate) fn clone_node(&self, node: &CoverNode<D>) -> CoverNode<D> {
+        let mut copy = node.clone();
+        for copier in self.plugin_copiers.read().unwrap().values() {
+            copier(node, &mut copy);
+        }
+        copy
+    }
+
+    /// A random number generator for one stream of the tree's randomness. With an `rng_seed` the seed, the domain
+    /// and the stream are hashed together, so the same stream gives the same numbers every time, and different
+    /// streams are independent, also when two domains use the same stream numbers. Without a seed this draws from the
+    /// host os's entropy.
+    ///
+    /// The sampling queries and the baselines take their randomness from here, so one seed makes them reproducible.
+    pub fn stream_rng(&self, domain: RngDomain, stream: u64) -> SmallRng {
+        match self.rng_seed {
+            Some(seed) => {
+                let mixed = splitmix64(splitmix64(splitmix64(seed) ^ domain as u64) ^ stream);
+                SmallRng::seed_from_u64(mixed)
+            }
+            None => SmallRng::from_entropy(),
+        }
+    }
+
+    /// The stream of a node in the [`RngDomain::Node`] domain. Use this for randomness that belongs to a node, so
+    /// that it doesn't depend on the order the nodes are visited in.
+    pub fn node_rng(&self, address: NodeAddress) -> SmallRng {
+        self.stream_rng(RngDomain::Node, address_stream(address))
+    }
+}
+
+/// What a stream of the tree's randomness is for, see [`CoverTreeParameters::stream_rng`]. Each of them numbers its
+/// streams from 0, so they're kept apart by this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RngDomain {
+    /// Picking the centers while building, one stream per node that's split
+    Build = 1,
+    /// Sampling the points whose paths are counted for the coverage
+    Coverage = 2,
+    /// Sampling the sequences of a baseline, one stream per sequence
+    Baseline = 3,
+    /// The queries that sample paths, the caller picks the stream
+    Sampling = 4,
+    /// The randomness of a plugin's component, one stream per node
+    Node = 5,
+}
+
+/// The SplitMix64 finalizer, it spreads every bit of the input over the output.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Both parts of the address in one stream number.
+pub(crate) fn address_stream(address: NodeAddress) -> u64 {
+    ((address.0 as u32 as u64) << 32) ^ address.1 as u64
 }
 
 /// Helper struct for iterating thru the reader's of the the layers.
@@ -216,11 +316,21 @@ impl<D: PointCloud> CoverTreeReader<D> {
         self.layers.is_empty()
     }
 
+    /// The number of times the writer has published changes to the readers, see [`CoverTreeParameters::epoch`].
+    pub fn epoch(&self) -> u64 {
+        self.parameters.epoch.load(atomic::Ordering::SeqCst)
+    }
+
     /// If you want to build a new tree with shared parameters, this is helpful.
     pub fn parameters(&self) -> &Arc<CoverTreeParameters<D>> {
         &self.parameters
     }
 
+    /// The number of points the map from a point to its node has room for.
+    pub(crate) fn final_addresses_capacity(&self) -> usize {
+        self.final_addresses.capacity()
+    }
+
     /// This is the total number of nodes in the tree. This queries each layer, so it's not a simple return int.
     pub fn node_count(&self) -> usize {
         self.layers().fold(0, |a, (_si, l)| a + l.len())
@@ -232,6 +342,51 @@ impl<D: PointCloud> CoverTreeReader<D> {
             ..(self.parameters.min_res_index - 1 + self.layers.len() as i32)
     }
 
+    /// The centers of the nodes on the top `layer_count` non-empty layers, root first and without repeats. Every query
+    /// computes distances to these, so they're the points worth keeping in RAM.
+    pub fn top_layer_centers(&self, layer_count: usize) -> Vec<usize> {
+        let mut seen = HashSet::new();
+        let mut centers = Vec::new();
+        for (_si, layer) in self
+            .layers()
+            .filter(|(_si, l)| !l.is_empty())
+            .take(layer_count)
+        {
+            for pi in layer.node_center_indexes_sorted() {
+                if seen.insert(pi) {
+                    centers.push(pi);
+                }
+            }
+        }
+        centers
+    }
+
+    /// Pins the centers of the top `layer_count` layers into the cache of a
+    /// [`PinnedCloud`](pointcloud::data_sources::PinnedCloud), as many as fit in its budget. Returns the number of
+    /// points pinned. Pin again after a large batch of inserts, the top of the tree may have changed.
+    pub fn pin_top_layers(&self, cache: &PinCache, layer_count: usize) -> GokoResult<usize>
+    where
+        D: PointCloud<Point = [f32]>,
+    {
+        let centers = self.top_layer_centers(layer_count);
+        Ok(cache.pin(self.parameters.point_cloud.as_ref(), &centers)?)
+    }
+
+    /// How far attaching the plugin has got. While this isn't [`PluginStatus::Attached`] some nodes are missing
+    /// the plugin's component, so the per-node plugin reads return `None` for them.
+    pub fn plugin_status<P: GokoPlugin<D>>(&self) -> PluginStatus {
+        self.parameters
+            .plugin_attachments
+            .read()
+            .unwrap()
+            .status::<P>()
+    }
+
+    /// If every node has the plugin's component, see [`Self::plugin_status`].
+    pub fn plugin_attached<P: GokoPlugin<D>>(&self) -> bool {
+        self.plugin_status::<P>() == PluginStatus::Attached
+    }
+
     /// Access the stored tree plugin
     pub fn get_plugin_and<T: Send + Sync + 'static, F, S>(&self, transform_fn: F) -> Option<S>
     where
@@ -277,24 +432,99 @@ impl<D: PointCloud> CoverTreeReader<D> {
     ///
     /// See `query_tools::KnnQueryHeap` for the pair of heaps and mechanisms for tracking the minimum distance and the current knn set.
     /// See the `nodes::CoverNode::singleton_knn` and `nodes::CoverNode::child_knn` for the brute force node based knn.
+    ///
+    /// If the tree has a distance accelerator, the singleton scans that are at least its minimum batch size run on it.
     pub fn knn<P: Deref<Target = D::Point> + Send + Sync>(
         &self,
         point: &P,
         k: usize,
     ) -> GokoResult<Vec<(f32, usize)>> {
+        self.knn_with_heap(
+            point,
+            KnnQueryHeap::new(k, self.parameters.scale_base),
+            &mut BudgetMeter::unlimited(),
+        )
+    }
+
+    /// Same as knn, but gives up on nodes that can't improve the result by more than a factor of `1 + epsilon`. The
+    /// `k`th distance returned is at most `1 + epsilon` times the true `k`th distance, and far fewer nodes are opened
+    /// up on high dimensional data. With `epsilon = 0` this is `knn`.
+    pub fn approx_knn<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+        epsilon: f32,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        self.knn_with_heap(
+            point,
+            KnnQueryHeap::new_approximate(k, self.parameters.scale_base, epsilon),
+            &mut BudgetMeter::unlimited(),
+        )
+    }
+
+    /// The knn loop, it stops opening nodes once the meter runs out.
+    pub(crate) fn knn_with_heap<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        mut query_heap: KnnQueryHeap,
+        meter: &mut BudgetMeter,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let accelerator = self.parameters.accelerator.read().unwrap().clone();
+
+        let root_center = self.parameters.point_cloud.point(self.root_address.1)?;
+        let dist_to_root = D::Metric::dist(&root_center, &point);
+        query_heap.push_nodes(&[self.root_address], &[dist_to_root], None);
+        self.greedy_knn_nodes(point, &mut query_heap, meter);
+
+        while let Some((_dist, address)) = query_heap.closest_unvisited_singleton_covering_address()
+        {
+            if !meter.visit() {
+                break;
+            }
+            self.get_node_and(address, |n| match &accelerator {
+                Some(acc) if n.singletons_len() >= acc.min_batch_size() => {
+                    n.singleton_knn_accelerated(point, acc.as_ref(), &mut query_heap)
+                }
+                _ => n.singleton_knn(point, &self.parameters.point_cloud, &mut query_heap),
+            });
+            self.greedy_knn_nodes(point, &mut query_heap, meter);
+        }
+
+        Ok(query_heap.unpack())
+    }
+
+    /// Same as knn, but the singleton scans use the tree's distance sketch to skip points that can't be in the
+    /// result. The answer is the same, this only saves metric evaluations. If there's no sketch this is just `knn`.
+    pub fn knn_sketched<P: Deref<Target = D::Point> + PointRef + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let sketch = match self.parameters.sketch.read().unwrap().clone() {
+            Some(sketch) => sketch,
+            None => return self.knn(point, k),
+        };
+        let query_sketch = sketch.sketch_query(&point.dense());
         let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+        let mut meter = BudgetMeter::unlimited();
 
         let root_center = self.parameters.point_cloud.point(self.root_address.1)?;
         let dist_to_root = D::Metric::dist(&root_center, &point);
         query_heap.push_nodes(&[self.root_address], &[dist_to_root], None);
-        self.greedy_knn_nodes(point, &mut query_heap);
+        self.greedy_knn_nodes(point, &mut query_heap, &mut meter);
 
         while let Some((_dist, address)) = query_heap.closest_unvisited_singleton_covering_address()
         {
             self.get_node_and(address, |n| {
-                n.singleton_knn(point, &self.parameters.point_cloud, &mut query_heap)
+                n.singleton_knn_sketched(
+                    point,
+                    &query_sketch,
+                    sketch.as_ref(),
+                    &self.parameters.point_cloud,
+                    &mut query_heap,
+                )
             });
-            self.greedy_knn_nodes(point, &mut query_heap);
+            self.greedy_knn_nodes(point, &mut query_heap, &mut meter);
         }
 
         Ok(query_heap.unpack())
@@ -305,15 +535,24 @@ impl<D: PointCloud> CoverTreeReader<D> {
         &self,
         point: &P,
         k: usize,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        self.routing_knn_with_meter(point, k, &mut BudgetMeter::unlimited())
+    }
+
+    pub(crate) fn routing_knn_with_meter<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+        meter: &mut BudgetMeter,
     ) -> GokoResult<Vec<(f32, usize)>> {
         let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
 
         let root_center = self.parameters.point_cloud.point(self.root_address.1)?;
         let dist_to_root = D::Metric::dist(&root_center, &point);
         query_heap.push_nodes(&[self.root_address], &[dist_to_root], None);
-        self.greedy_knn_nodes(point, &mut query_heap);
+        self.greedy_knn_nodes(point, &mut query_heap, meter);
 
-        while self.greedy_knn_nodes(point, &mut query_heap) {}
+        while self.greedy_knn_nodes(point, &mut query_heap, meter) {}
         Ok(query_heap.unpack())
     }
 
@@ -321,6 +560,7 @@ impl<D: PointCloud> CoverTreeReader<D> {
         &self,
         point: &P,
         query_heap: &mut KnnQueryHeap,
+        meter: &mut BudgetMeter,
     ) -> bool {
         let mut did_something = false;
         while let Some((dist, nearest_address)) =
@@ -329,6 +569,7 @@ impl<D: PointCloud> CoverTreeReader<D> {
             if self
                 .get_node_and(nearest_address, |n| n.is_leaf())
                 .unwrap_or(true)
+                || !meter.visit()
             {
                 break;
             } else {
@@ -341,6 +582,128 @@ impl<D: PointCloud> CoverTreeReader<D> {
         did_something
     }
 
+    /// The number of points within `radius` of the query, inclusive. This doesn't collect the points, a node whose
+    /// ball is entirely inside the query ball adds its coverage count in one step, and a node whose ball misses the
+    /// query ball is skipped. Only the nodes on the boundary of the query ball are opened up.
+    ///
+    /// The node balls come from the radii recorded on the nodes. Insertions grow them and removals leave them be,
+    /// so they stay upper bounds. To get the points themselves use [`CoverTreeReader::range_query`].
+    pub fn count_within<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        radius: f32,
+    ) -> GokoResult<usize> {
+        self.count_within_until(point, radius, usize::MAX)
+    }
+
+    /// The points within `radius` of the query, inclusive, with their distances and sorted by distance. A node
+    /// whose ball misses the query ball is skipped with everything under it, see [`CoverTreeReader::count_within`]
+    /// for when only the count is needed.
+    pub fn range_query<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        radius: f32,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let point_cloud = &self.parameters.point_cloud;
+        let root_center = point_cloud.point(self.root_address.1)?;
+        let mut unvisited = vec![(D::Metric::dist(&root_center, &point), self.root_address)];
+        let mut within = Vec::new();
+        while let Some((dist, address)) = unvisited.pop() {
+            let result = self.get_node_and(address, |n| -> GokoResult<()> {
+                if dist > radius + n.radius() {
+                    return Ok(());
+                }
+                let singleton_dists = point_cloud.distances_to_point(point, n.singletons())?;
+                within.extend(
+                    singleton_dists
+                        .into_iter()
+                        .zip(n.singletons().iter().cloned())
+                        .filter(|(d, _)| *d <= radius),
+                );
+                match n.children() {
+                    None => {
+                        if dist <= radius {
+                            within.push((dist, address.1));
+                        }
+                    }
+                    Some((nested_scale, child_addresses)) => {
+                        unvisited.push((dist, (nested_scale, address.1)));
+                        let centers: Vec<usize> = child_addresses.iter().map(|ca| ca.1).collect();
+                        let child_dists = point_cloud.distances_to_point(point, &centers)?;
+                        unvisited
+                            .extend(child_dists.into_iter().zip(child_addresses.iter().cloned()));
+                    }
+                }
+                Ok(())
+            });
+            if let Some(result) = result {
+                result?;
+            }
+        }
+        within.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        Ok(within)
+    }
+
+    /// Whether there's any point within `radius` of the query, inclusive. Stops at the first node or point that
+    /// establishes it, see [`CoverTreeReader::count_within`].
+    pub fn exists_within<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        radius: f32,
+    ) -> GokoResult<bool> {
+        Ok(self.count_within_until(point, radius, 1)? > 0)
+    }
+
+    /// Counts the points within the radius, stopping as soon as the count reaches the limit. The count returned
+    /// is at least the limit in that case, as whole nodes are counted at once.
+    fn count_within_until<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        radius: f32,
+        limit: usize,
+    ) -> GokoResult<usize> {
+        let point_cloud = &self.parameters.point_cloud;
+        let root_center = point_cloud.point(self.root_address.1)?;
+        let mut unvisited = vec![(D::Metric::dist(&root_center, &point), self.root_address)];
+        let mut count = 0;
+        while let Some((dist, address)) = unvisited.pop() {
+            let result = self.get_node_and(address, |n| -> GokoResult<()> {
+                if dist + n.radius() <= radius {
+                    count += n.coverage_count();
+                    return Ok(());
+                }
+                if dist > radius + n.radius() {
+                    return Ok(());
+                }
+                let singleton_dists = point_cloud.distances_to_point(point, n.singletons())?;
+                count += singleton_dists.iter().filter(|d| **d <= radius).count();
+                match n.children() {
+                    None => {
+                        if dist <= radius {
+                            count += 1;
+                        }
+                    }
+                    Some((nested_scale, child_addresses)) => {
+                        // The nested child shares our center, so we already know its distance
+                        unvisited.push((dist, (nested_scale, address.1)));
+                        let centers: Vec<usize> = child_addresses.iter().map(|ca| ca.1).collect();
+                        let child_dists = point_cloud.distances_to_point(point, &centers)?;
+                        unvisited
+                            .extend(child_dists.into_iter().zip(child_addresses.iter().cloned()));
+                    }
+                }
+                Ok(())
+            });
+            if let Some(result) = result {
+                result?;
+            }
+            if count >= limit {
+                break;
+            }
+        }
+        Ok(count)
+    }
+
     /// # Dry Insert Query
     pub fn path<P: Deref<Target = D::Point> + Send + Sync>(
         &self,
@@ -377,6 +740,60 @@ impl<D: PointCloud> CoverTreeReader<D> {
         Ok(trace)
     }
 
+    /// Same as `path`, but the nodes in the cache use their center to child distances to skip children that can't be
+    /// picked. The path is the same. If the cache is from an older epoch of the tree this is just `path`.
+    pub fn path_cached<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        cache: &ChildDistanceCache,
+    ) -> GokoResult<Vec<(f32, NodeAddress)>> {
+        if cache.epoch() != self.epoch() {
+            return self.path(point);
+        }
+        let root_center = self.parameters.point_cloud.point(self.root_address.1)?;
+        let mut current_distance = D::Metric::dist(&root_center, &point);
+        let mut current_address = self.root_address;
+        let mut trace = vec![(current_distance, current_address)];
+        while let Some(nearest) = self.get_node_and(current_address, |n| {
+            let scale_base = self.parameters.scale_base;
+            let point_cloud = &self.parameters.point_cloud;
+            match (
+                self.parameters.partition_type,
+                cache.child_distances(current_address),
+            ) {
+                (PartitionType::Nearest, Some(cd)) => n.nearest_covering_child_cached(
+                    scale_base,
+                    current_distance,
+                    point,
+                    point_cloud,
+                    cd,
+                ),
+                (PartitionType::Nearest, None) => {
+                    n.nearest_covering_child(scale_base, current_distance, point, point_cloud)
+                }
+                (PartitionType::First, Some(cd)) => n.first_covering_child_cached(
+                    scale_base,
+                    current_distance,
+                    point,
+                    point_cloud,
+                    cd,
+                ),
+                (PartitionType::First, None) => {
+                    n.first_covering_child(scale_base, current_distance, point, point_cloud)
+                }
+            }
+        }) {
+            if let Some(nearest) = nearest? {
+                trace.push(nearest);
+                current_distance = nearest.0;
+                current_address = nearest.1;
+            } else {
+                break;
+            }
+        }
+        Ok(trace)
+    }
+
     ///
     pub fn known_path(&self, point_index: usize) -> GokoResult<Vec<(f32, NodeAddress)>> {
         self.final_addresses
@@ -399,6 +816,62 @@ impl<D: PointCloud> CoverTreeReader<D> {
             .ok_or(GokoError::IndexNotInTree(point_index))
     }
 
+    /// The addresses of the nodes on the path to the node that owns the point, without the distances.
+    fn known_addresses(&self, point_index: usize) -> Option<Vec<NodeAddress>> {
+        self.final_addresses.get_and(&point_index, |addr| {
+            let mut path = Vec::with_capacity((self.root_address().0 - addr.0) as usize);
+            let mut parent = Some(*addr);
+            while let Some(addr) = parent {
+                path.push(addr);
+                parent = self.get_node_and(addr, |n| n.parent_address()).flatten();
+            }
+            path
+        })
+    }
+
+    /// Adds up node level counts, like the evidence of a tracker, at the ancestors of the nodes at `target_scale`.
+    /// Paths can skip scales, so a node's ancestor is the shallowest node on its path to the root whose scale index
+    /// is at most `target_scale`. Nodes at or above the target scale keep their own counts and addresses that
+    /// aren't in the tree are skipped.
+    ///
+    /// This follows the parent addresses up, each node is only visited once however many counts go through it.
+    pub fn rollup<I>(&self, addresses_and_counts: I, target_scale: i32) -> HashMap<NodeAddress, f64>
+    where
+        I: IntoIterator<Item = (NodeAddress, f64)>,
+    {
+        let mut ancestors: HashMap<NodeAddress, Option<NodeAddress>> = HashMap::new();
+        let mut rolled_up = HashMap::new();
+        let mut walked = Vec::new();
+        for (address, count) in addresses_and_counts {
+            let mut current = address;
+            let ancestor = loop {
+                if let Some(ancestor) = ancestors.get(&current) {
+                    break *ancestor;
+                }
+                walked.push(current);
+                let parent = if current.0 <= self.root_address.0 {
+                    self.get_node_and(current, |n| n.parent_address())
+                } else {
+                    None
+                };
+                match parent {
+                    None => break None,
+                    Some(Some(parent)) if current.0 < target_scale && parent.0 <= target_scale => {
+                        current = parent
+                    }
+                    Some(_) => break Some(current),
+                }
+            };
+            for node in walked.drain(..) {
+                ancestors.insert(node, ancestor);
+            }
+            if let Some(ancestor) = ancestor {
+                *rolled_up.entry(ancestor).or_insert(0.0) += count;
+            }
+        }
+        rolled_up
+    }
+
     ///Computes the fractal dimension of a node
     pub fn node_fractal_dim(&self, node_address: NodeAddress) -> f32 {
         let count: f32 = self
@@ -440,13 +913,28 @@ impl<D: PointCloud> CoverTreeReader<D> {
         weighted_count.log(self.parameters.scale_base)
     }
 
+    /// The mean of the points under a node. `None` if the node doesn't exist or doesn't have a centroid, attach a
+    /// [`CentroidPlugin`](crate::plugins::centroids::CentroidPlugin) first.
+    pub fn node_centroid(&self, node_address: NodeAddress) -> Option<Vec<f32>> {
+        self.get_node_plugin_and::<NodeCentroid, _, _>(node_address, |p| p.centroid())
+            .flatten()
+    }
+
+    /// The index of the point closest to the node's centroid, see [`crate::plugins::centroids`]. `None` if the
+    /// centroid plugin wasn't attached with medoids.
+    pub fn node_medoid(&self, node_address: NodeAddress) -> Option<usize> {
+        self.get_node_plugin_and::<NodeCentroid, _, _>(node_address, |p| p.medoid())
+            .flatten()
+    }
+
     ///Computes the fractal dimension of a layer
     pub fn layer_fractal_dim(&self, scale_index: i32) -> f32 {
         let parent_layer = self.layer(scale_index);
         let parent_count = parent_layer.len() as f32;
         let mut child_count: f32 = 0.0;
-        parent_layer
-            .for_each_node(|_, n| child_count += (n.singletons_len() + n.children_len()) as f32);
+        parent_layer.for_each_node_sorted(|_, n| {
+            child_count += (n.singletons_len() + n.children_len()) as f32
+        });
         child_count.log(self.parameters.scale_base) - parent_count.log(self.parameters.scale_base)
     }
 
@@ -457,7 +945,7 @@ impl<D: PointCloud> CoverTreeReader<D> {
         let mut parent_coverage_counts: Vec<usize> = Vec::new();
         let mut child_coverage_counts: Vec<usize> = Vec::new();
         let mut singletons_count: f32 = 0.0;
-        parent_layer.for_each_node(|center_index, n| {
+        parent_layer.for_each_node_sorted(|center_index, n| {
             parent_coverage_counts.push(n.coverage_count());
 
             singletons_count += n.singletons().len() as f32;
@@ -497,15 +985,10 @@ impl<D: PointCloud> CoverTreeReader<D> {
     pub(crate) fn no_dangling_refs(&self) -> bool {
         let mut refs_to_check = vec![self.root_address];
         while let Some(node_addr) = refs_to_check.pop() {
-            println!("checking {:?}", node_addr);
-            println!("refs_to_check: {:?}", refs_to_check);
+            trace!(?node_addr, remaining = refs_to_check.len(), "Checking node");
             let node_exists = self.get_node_and(node_addr, |n| {
                 if let Some((nested_scale, other_children)) = n.children() {
-                    println!(
-                        "Pushing: {:?}, {:?}",
-                        (nested_scale, other_children),
-                        other_children
-                    );
+                    trace!(nested_scale, ?other_children, "Pushing children");
                     refs_to_check.push((nested_scale, node_addr.1));
                     refs_to_check.extend(&other_children[..]);
                 }
@@ -524,6 +1007,15 @@ pub struct CoverTreeWriter<D: PointCloud> {
     pub(crate) layers: Vec<CoverLayerWriter<D>>,
     pub(crate) root_address: NodeAddress,
     pub(crate) final_addresses: MonoWriteHandle<usize, NodeAddress>,
+    /// The version of the last full checkpoint, see [`CoverTreeWriter::save_checkpoint`]
+    pub(crate) checkpoint_version: Option<u64>,
+    /// The lifecycle hooks of the attached plugins, keyed by the type of the plugin
+    pub(crate) plugin_hooks: HashMap<TypeId, PluginHook<D>>,
+    /// Computes the components of the attached plugins for the nodes made by an insert, keyed by the type of the
+    /// plugin
+    pub(crate) plugin_builders: HashMap<TypeId, PluginBuilder<D>>,
+    /// Leaves the incremental edits pending instead of refreshing after each one, see [`CoverTree`]
+    pub(crate) defer_refresh: bool,
 }
 
 impl<D: PointCloud> CoverTreeWriter<D> {
@@ -537,21 +1029,285 @@ impl<D: PointCloud> CoverTreeWriter<D> {
         self.add_plugin::<LabelSummaryPlugin>(LabelSummaryPlugin::default())
     }
 
+    /// Attaches the plugin to every node of the tree. This runs from the bottom layer up and publishes each layer as
+    /// it's done, so readers can see a partially attached plugin for a while. Check
+    /// [`CoverTreeReader::plugin_status`] before relying on the components being there.
     ///
+    /// The nodes of a layer are computed in parallel on rayon's thread pool, see [`GokoPlugin::node_component`].
     pub fn add_plugin<P: GokoPlugin<D>>(&mut self, plug_in: P) {
+        self.parameters
+            .plugin_attachments
+            .write()
+            .unwrap()
+            .begin::<P>(self.layers.len());
         P::prepare_tree(&plug_in, self);
-        let reader = self.reader();
-        for layer in self.layers.iter_mut() {
-            layer.reader().for_each_node(|pi, n| {
-                if let Some(node_component) = P::node_component(&plug_in, n, &reader) {
-                    unsafe {
-                        layer.update_node(*pi, move |n| n.insert_plugin(node_component.clone()))
+        let parameters = Arc::clone(&self.parameters);
+        self.attach_bottom_up(
+            |n, reader| P::node_component(&plug_in, n, reader),
+            |layer_index| {
+                parameters
+                    .plugin_attachments
+                    .write()
+                    .unwrap()
+                    .layer_attached::<P>(layer_index)
+            },
+        );
+        self.plugin_hooks.insert(
+            TypeId::of::<P>(),
+            plugin_hook(plug_in.clone(), Arc::clone(&self.parameters.point_cloud)),
+        );
+        self.plugin_builders
+            .insert(TypeId::of::<P>(), plugin_builder(plug_in.clone()));
+        self.parameters
+            .plugin_sizers
+            .write()
+            .unwrap()
+            .insert(TypeId::of::<P>(), plugin_sizer::<D, P>());
+        self.parameters
+            .plugin_copiers
+            .write()
+            .unwrap()
+            .insert(TypeId::of::<P>(), plugin_copier::<D, P>());
+        self.parameters.plugins.write().unwrap().insert(plug_in);
+        self.parameters.epoch.fetch_add(1, atomic::Ordering::SeqCst);
+    }
+
+    /// Updates the attached plugins after the point was added to the tree, see [`GokoPlugin::on_insert`]. The hooks
+    /// run on every node of the point's path and the change is published to the readers, unless the refreshes are
+    /// deferred by a [`CoverTree`].
+    pub fn plugins_on_insert(&mut self, point_index: usize) -> GokoResult<()> {
+        self.run_plugin_hooks(point_index, PluginEvent::Insert)
+    }
+
+    /// Updates the attached plugins before the point is removed from the tree, see [`GokoPlugin::on_remove`]. The
+    /// point has to still be in the tree so that we can find its path.
+    pub fn plugins_on_remove(&mut self, point_index: usize) -> GokoResult<()> {
+        self.run_plugin_hooks(point_index, PluginEvent::Remove)
+    }
+
+    /// Moves the points of a child that changed address over to its new address in the parent's plugin components.
+    /// Each point is removed under the old address and inserted under the new one.
+    pub(crate) fn plugins_on_rehome(
+        &mut self,
+        parent: NodeAddress,
+        from: NodeAddress,
+        to: NodeAddress,
+        point_indexes: Vec<usize>,
+    ) {
+        if self.plugin_hooks.is_empty() {
+            return;
+        }
+        let hooks: Vec<PluginHook<D>> = self.plugin_hooks.values().cloned().collect();
+        unsafe {
+            self.update_node(parent, move |n| {
+                for pi in point_indexes.iter() {
+                    for h in hooks.iter() {
+                        h(n, *pi, Some(from), PluginEvent::Remove);
+                        h(n, *pi, Some(to), PluginEvent::Insert);
                     }
                 }
-            });
+            })
+        }
+    }
+
+    fn run_plugin_hooks(&mut self, point_index: usize, event: PluginEvent) -> GokoResult<()> {
+        if self.plugin_hooks.is_empty() {
+            return Ok(());
+        }
+        self.apply_plugin_hooks(point_index, event, &[])?;
+        self.publish();
+        Ok(())
+    }
+
+    /// Runs the hooks on the point's path without publishing. The nodes in `skip` are left alone, they're the new
+    /// nodes of an insert that get their components from [`CoverTreeWriter::build_plugin_components`].
+    pub(crate) fn apply_plugin_hooks(
+        &mut self,
+        point_index: usize,
+        event: PluginEvent,
+        skip: &[NodeAddress],
+    ) -> GokoResult<()> {
+        if self.plugin_hooks.is_empty() {
+            return Ok(());
+        }
+        // The path runs from the node that owns the point up to the root
+        let path = self
+            .known_addresses_pending(point_index)
+            .ok_or(GokoError::IndexNotInTree(point_index))?;
+        let hooks: Arc<Vec<PluginHook<D>>> =
+            Arc::new(self.plugin_hooks.values().cloned().collect());
+        for (i, address) in path.iter().enumerate() {
+            if skip.contains(address) {
+                continue;
+            }
+            let child = if i == 0 { None } else { Some(path[i - 1]) };
+            let hooks = Arc::clone(&hooks);
+            unsafe {
+                self.update_node(*address, move |n| {
+                    hooks.iter().for_each(|h| h(n, point_index, child, event))
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// Computes the components of the attached plugins for nodes made after the plugins were attached. The nodes
+    /// are done from the bottom layer up and each layer is refreshed before the one above it, as the components are
+    /// computed from the readers' view of the tree. This refreshes even when the refreshes are deferred by a
+    /// [`CoverTree`], the components of the top layer are left to the caller to publish.
+    pub(crate) fn build_plugin_components(&mut self, addresses: &[NodeAddress]) {
+        if self.plugin_builders.is_empty() || addresses.is_empty() {
+            return;
+        }
+        let builders: Vec<PluginBuilder<D>> = self.plugin_builders.values().cloned().collect();
+        let mut scale_indexes: Vec<i32> = addresses.iter().map(|a| a.0).collect();
+        scale_indexes.sort_unstable();
+        scale_indexes.dedup();
+        for scale_index in scale_indexes {
+            self.refresh();
+            let reader = self.reader();
+            for address in addresses.iter().filter(|a| a.0 == scale_index) {
+                for builder in builders.iter() {
+                    if let Some(insert) = builder(&reader, *address) {
+                        unsafe { self.update_node(*address, move |n| insert(n)) }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Attaches a node component that is computed bottom up, see [`crate::plugins::aggregate`]. The nodes of each
+    /// layer are computed in parallel once the layers below them are done.
+    pub fn add_aggregate_plugin<P: Mergeable<D>>(&mut self) {
+        self.attach_bottom_up(aggregate_node::<D, P>, |_| {});
+        self.parameters.epoch.fetch_add(1, atomic::Ordering::SeqCst);
+    }
+
+    /// Inserts the component `build` makes for each node, from the bottom layer up. The nodes of a layer are built
+    /// in parallel from the readers' view of the tree, and the layer is refreshed before the one above it so a node
+    /// can build on its children's components. `layer_done` gets the index of each layer once it's refreshed.
+    fn attach_bottom_up<C, F, L>(&mut self, build: F, mut layer_done: L)
+    where
+        C: NodePlugin<D> + Clone + 'static,
+        F: Fn(&CoverNode<D>, &CoverTreeReader<D>) -> Option<C> + Sync,
+        L: FnMut(usize),
+    {
+        let reader = self.reader();
+        for (layer_index, layer) in self.layers.iter_mut().enumerate() {
+            let scale_index = layer.reader().scale_index();
+            let components: Vec<(usize, C)> = layer
+                .reader()
+                .node_center_indexes()
+                .into_par_iter()
+                .map_with(reader.clone(), |reader, pi| {
+                    let reader: &CoverTreeReader<D> = reader;
+                    reader
+                        .get_node_and((scale_index, pi), |n| build(n, reader))
+                        .flatten()
+                        .map(|component| (pi, component))
+                })
+                .flatten()
+                .collect();
+            for (pi, component) in components {
+                unsafe { layer.update_node(pi, move |n| n.insert_plugin(component.clone())) }
+            }
+            layer.refresh();
+            layer_done(layer_index);
+        }
+    }
+
+    /// Swaps in a distance sketch for `knn_sketched`, see [`crate::sketch`].
+    pub fn set_sketch<S: DistanceSketch<D>>(&mut self, sketch: S) {
+        *self.parameters.sketch.write().unwrap() = Some(Arc::new(sketch));
+    }
+
+    /// Removes the distance sketch, `knn_sketched` falls back to the plain scan.
+    pub fn clear_sketch(&mut self) {
+        *self.parameters.sketch.write().unwrap() = None;
+    }
+
+    /// Swaps in a distance accelerator for the knn's large singleton scans, see [`crate::accelerator`].
+    pub fn set_accelerator<A: DistanceAccelerator<D>>(&mut self, accelerator: A) {
+        *self.parameters.accelerator.write().unwrap() = Some(Arc::new(accelerator));
+    }
+
+    /// Removes the distance accelerator, every distance is computed on the CPU again.
+    pub fn clear_accelerator(&mut self) {
+        *self.parameters.accelerator.write().unwrap() = None;
+    }
+
+    /// Recomputes the coverage counts of the nodes, these drift after many insertions and removals.
+    ///
+    /// The exact recount is a bottom up pass over the whole tree, the nodes of each layer are counted in parallel.
+    /// The approximate recount walks the paths of a uniform sample of the points and scales the number of samples
+    /// that pass through each node up to the size of the dataset. Only the nodes that a sample passes through are
+    /// updated, these are the large nodes that dominate the fractal dimension and the coverage weightings. If the
+    /// dataset isn't larger than the sample this does an exact recount.
+    pub fn recount_coverage(&mut self, exact: bool) {
+        if exact || self.parameters.point_cloud.len() <= COVERAGE_SAMPLE_SIZE {
+            self.recount_coverage_exact();
+        } else {
+            self.recount_coverage_sampled(COVERAGE_SAMPLE_SIZE);
+        }
+        self.parameters.epoch.fetch_add(1, atomic::Ordering::SeqCst);
+    }
+
+    fn recount_coverage_exact(&mut self) {
+        let reader = self.reader();
+        for layer in self.layers.iter_mut() {
+            let scale_index = layer.reader().scale_index();
+            let counts: Vec<(usize, usize)> = layer
+                .reader()
+                .node_center_indexes()
+                .into_par_iter()
+                .map_with(reader.clone(), |reader, pi| {
+                    let reader: &CoverTreeReader<D> = reader;
+                    reader.get_node_and((scale_index, pi), |n| {
+                        let mut count = n.singletons_len();
+                        match n.children() {
+                            None => count += 1,
+                            Some((nested_scale, child_addresses)) => {
+                                let nested_address = (nested_scale, pi);
+                                for ca in std::iter::once(&nested_address).chain(child_addresses) {
+                                    count += reader
+                                        .get_node_and(*ca, |c| c.coverage_count())
+                                        .unwrap_or(0);
+                                }
+                            }
+                        }
+                        (pi, count)
+                    })
+                })
+                .flatten()
+                .collect();
+            for (pi, count) in counts {
+                unsafe { layer.update_node(pi, move |n| n.set_coverage_count(count)) }
+            }
             layer.refresh()
         }
-        self.parameters.plugins.write().unwrap().insert(plug_in);
+    }
+
+    fn recount_coverage_sampled(&mut self, sample_size: usize) {
+        let len = self.parameters.point_cloud.len();
+        let mut rng = self.parameters.stream_rng(RngDomain::Coverage, 0);
+        let sample: Vec<usize> = (0..sample_size).map(|_| rng.gen_range(0..len)).collect();
+        let reader = self.reader();
+        let paths: Vec<Vec<NodeAddress>> = sample
+            .into_par_iter()
+            .map_with(reader, |reader, pi| reader.known_addresses(pi))
+            .flatten()
+            .collect();
+
+        let mut hits: HashMap<NodeAddress, usize> = HashMap::new();
+        for address in paths.iter().flatten() {
+            *hits.entry(*address).or_insert(0) += 1;
+        }
+        let scale = len as f64 / sample_size as f64;
+        for (address, count) in hits {
+            let estimate = ((count as f64 * scale).round() as usize).max(1);
+            unsafe { self.update_node(address, move |n| n.set_coverage_count(estimate)) }
+        }
+        self.layers.iter_mut().for_each(|l| l.refresh());
     }
 
     /// Provides a reference to a `CoverLayerWriter`. Do not use, unless you're going to leave the tree in a *valid* state.
@@ -566,6 +1322,188 @@ impl<D: PointCloud> CoverTreeWriter<D> {
         self.layers[self.parameters.internal_index(address.0)].update_node(address.1, update_fn);
     }
 
+    /// Reads a node as it will be after the next refresh, the edits of this writer that the readers can't see yet
+    /// are applied to a copy. The copy keeps the node's plugin components.
+    pub(crate) fn get_node_pending_and<F, T>(&self, address: NodeAddress, f: F) -> Option<T>
+    where
+        F: FnOnce(&CoverNode<D>) -> T,
+    {
+        let parameters = &self.parameters;
+        self.layers[parameters.internal_index(address.0)].get_node_pending_and(
+            address.1,
+            |n| parameters.clone_node(n),
+            f,
+        )
+    }
+
+    /// The address of the node that owns the point, as it will be after the next refresh.
+    pub(crate) fn final_address_pending(&self, point_index: usize) -> Option<NodeAddress> {
+        self.final_addresses
+            .get_pending_and(&point_index, |address| *address)
+    }
+
+    /// The addresses from the node that owns the point up to the root, as they will be after the next refresh.
+    pub(crate) fn known_addresses_pending(&self, point_index: usize) -> Option<Vec<NodeAddress>> {
+        let mut path = vec![self.final_address_pending(point_index)?];
+        while let Some(parent) = self
+            .get_node_pending_and(*path.last().unwrap(), |n| n.parent_address())
+            .flatten()
+        {
+            path.push(parent);
+        }
+        Some(path)
+    }
+
+    /// Refreshes after an incremental edit, unless a [`CoverTree`] asked for the refreshes to be deferred.
+    pub(crate) fn publish(&mut self) {
+        if !self.defer_refresh {
+            self.refresh();
+        }
+    }
+
+    /// Rebuilds the layers so that the nodes near the root are the cheapest to look up.
+    ///
+    /// Each layer is a hash map keyed by the center index, so the position of a node is set by the hash and not
+    /// by us. What we do control is who wins a collision: a node inserted first gets its home slot and the ones
+    /// inserted after it probe further. This purges every layer and re-inserts the nodes in breadth first order of
+    /// the tree, so the nodes every query passes through resolve in a single probe, and the tombstones left by
+    /// removals are cleared out. Run it offline, after the tree is built and before it's served.
+    ///
+    /// The nodes are copied with the components of the attached plugins, so the plugins stay attached.
+    pub fn optimize_layout(&mut self) {
+        let reader = self.reader();
+        let mut layer_nodes: Vec<Vec<CoverNode<D>>> =
+            self.layers.iter().map(|_| Vec::new()).collect();
+        let mut queue: VecDeque<NodeAddress> = VecDeque::new();
+        queue.push_back(self.root_address);
+        while let Some(address) = queue.pop_front() {
+            if let Some(node) = reader.get_node_and(address, |n| self.parameters.clone_node(n)) {
+                if let Some((nested_scale, child_addresses)) = node.children() {
+                    queue.push_back((nested_scale, address.1));
+                    queue.extend(child_addresses.iter().cloned());
+                }
+                layer_nodes[self.parameters.internal_index(address.0)].push(node);
+            }
+        }
+        for (layer, nodes) in self.layers.iter_mut().zip(layer_nodes) {
+            layer.rebuild(nodes);
+        }
+        self.parameters.epoch.fetch_add(1, atomic::Ordering::SeqCst);
+    }
+
+    /// Moves the subtree rooted at `subtree_root` under `new_parent`, as one of its routing children.
+    ///
+    /// The new parent has to be a routing node at a higher scale than the subtree root, and it can't be inside the
+    /// subtree. The subtree root can't be a nested child, as its parent would lose its center. Unless `force` is
+    /// set the new parent also has to cover the subtree root, its center has to be within `scale_base^scale_index`
+    /// of the subtree root's center. Forcing it leaves a tree whose queries may miss points in the subtree.
+    ///
+    /// The coverage counts of both ancestor chains are updated, and the radii of the new ancestors are grown to
+    /// contain the subtree. Nodes are addressed by their scale and center, so no address changes and the final
+    /// addresses of the points stay valid. Plugins are not updated, reattach them afterwards.
+    ///
+    /// The move is published to the readers, unless the refreshes are deferred by a [`CoverTree`].
+    pub fn reparent(
+        &mut self,
+        subtree_root: NodeAddress,
+        new_parent: NodeAddress,
+        force: bool,
+    ) -> GokoResult<()> {
+        let (old_parent, coverage, subtree_radius) = self
+            .get_node_pending_and(subtree_root, |n| {
+                (n.parent_address(), n.coverage_count(), n.radius())
+            })
+            .ok_or(GokoError::InvalidReparent(
+                "the subtree root isn't in the tree",
+            ))?;
+        let old_parent =
+            old_parent.ok_or(GokoError::InvalidReparent("the subtree root is the root"))?;
+        if old_parent == new_parent {
+            return Ok(());
+        }
+        if old_parent.1 == subtree_root.1 {
+            return Err(GokoError::InvalidReparent(
+                "the subtree root is a nested child",
+            ));
+        }
+        let parent_is_leaf = self
+            .get_node_pending_and(new_parent, |n| n.is_leaf())
+            .ok_or(GokoError::InvalidReparent(
+                "the new parent isn't in the tree",
+            ))?;
+        if parent_is_leaf {
+            return Err(GokoError::InvalidReparent("the new parent is a leaf"));
+        }
+        if new_parent.0 <= subtree_root.0 {
+            return Err(GokoError::InvalidReparent(
+                "the new parent isn't at a higher scale than the subtree root",
+            ));
+        }
+
+        let mut new_ancestors = vec![new_parent];
+        while let Some(parent) = self
+            .get_node_pending_and(*new_ancestors.last().unwrap(), |n| n.parent_address())
+            .flatten()
+        {
+            new_ancestors.push(parent);
+        }
+        if new_ancestors.contains(&subtree_root) {
+            return Err(GokoError::InvalidReparent(
+                "the new parent is inside the subtree",
+            ));
+        }
+
+        let point_cloud = &self.parameters.point_cloud;
+        let ancestor_centers: Vec<usize> = new_ancestors.iter().map(|a| a.1).collect();
+        let ancestor_dists =
+            point_cloud.distances_to_point_index(subtree_root.1, &ancestor_centers)?;
+        if !force && ancestor_dists[0] > self.parameters.scale_base.powi(new_parent.0) {
+            return Err(GokoError::InvalidReparent(
+                "the new parent doesn't cover the subtree root",
+            ));
+        }
+
+        let mut old_ancestors = vec![old_parent];
+        while let Some(parent) = self
+            .get_node_pending_and(*old_ancestors.last().unwrap(), |n| n.parent_address())
+            .flatten()
+        {
+            old_ancestors.push(parent);
+        }
+
+        unsafe {
+            self.update_node(old_parent, move |n| {
+                n.remove_child(subtree_root, coverage);
+            });
+            for address in old_ancestors.iter().skip(1) {
+                self.update_node(*address, move |n| {
+                    n.set_coverage_count(n.coverage_count().saturating_sub(coverage))
+                });
+            }
+            self.update_node(new_parent, move |n| {
+                n.insert_child(subtree_root, coverage).unwrap();
+            });
+            for address in new_ancestors.iter().skip(1) {
+                self.update_node(*address, move |n| {
+                    n.set_coverage_count(n.coverage_count() + coverage)
+                });
+            }
+            for (address, dist) in new_ancestors.iter().zip(ancestor_dists) {
+                let radius = dist + subtree_radius;
+                self.update_node(*address, move |n| {
+                    if n.radius() < radius {
+                        n.set_radius(radius)
+                    }
+                });
+            }
+            self.update_node(subtree_root, move |n| {
+                n.set_parent_address(Some(new_parent))
+            });
+        }
+        self.publish();
+        Ok(())
+    }
+
     /// Creates a reader for queries.
     pub fn reader(&self) -> CoverTreeReader<D> {
         CoverTreeReader {
@@ -586,6 +1524,7 @@ impl<D: PointCloud> CoverTreeWriter<D> {
     }
 
     /// Loads a tree from a protobuf. There's a `load_tree` in `utils` that handles loading from a path to a protobuf file.
+    /// Trees saved by older versions can break the covering invariants, see [`CoverTreeWriter::load_validated`].
     pub fn load(cover_proto: &CoreProto, point_cloud: Arc<D>) -> GokoResult<CoverTreeWriter<D>> {
         let partition_type = if cover_proto.partition_type == "first" {
             PartitionType::First
@@ -595,6 +1534,7 @@ impl<D: PointCloud> CoverTreeWriter<D> {
 
         let parameters = Arc::new(CoverTreeParameters {
             total_nodes: atomic::AtomicUsize::new(0),
+            epoch: atomic::AtomicU64::new(0),
             use_singletons: cover_proto.use_singletons,
             scale_base: cover_proto.scale_base as f32,
             leaf_cutoff: cover_proto.cutoff as usize,
@@ -602,7 +1542,13 @@ impl<D: PointCloud> CoverTreeWriter<D> {
             point_cloud,
             verbosity: 2,
             partition_type,
+            label_candidates: 1,
             plugins: RwLock::new(TreePluginSet::new()),
+            plugin_attachments: RwLock::new(PluginAttachments::new()),
+            plugin_sizers: RwLock::new(HashMap::new()),
+            plugin_copiers: RwLock::new(HashMap::new()),
+            sketch: RwLock::new(None),
+            accelerator: RwLock::new(None),
             rng_seed: None,
         });
         let root_address = (
@@ -622,6 +1568,10 @@ impl<D: PointCloud> CoverTreeWriter<D> {
             layers,
             root_address,
             final_addresses,
+            checkpoint_version: None,
+            plugin_hooks: HashMap::new(),
+            plugin_builders: HashMap::new(),
+            defer_refresh: false,
         };
 
         tree.refresh_final_indexes();
@@ -684,7 +1634,14 @@ impl<D: PointCloud> CoverTreeWriter<D> {
     /// Swaps the maps on each layer so that any `CoverTreeReaders` see the updated tree.
     /// Only call once you have a valid tree.
     pub fn refresh(&mut self) {
+        self.final_addresses.flush();
         self.layers.iter_mut().rev().for_each(|l| l.refresh());
+        self.parameters.epoch.fetch_add(1, atomic::Ordering::SeqCst);
+    }
+
+    /// The number of times this writer has published changes to the readers, see [`CoverTreeParameters::epoch`].
+    pub fn epoch(&self) -> u64 {
+        self.parameters.epoch.load(atomic::Ordering::SeqCst)
     }
 }
 
@@ -693,6 +1650,8 @@ pub(crate) mod tests {
     use super::*;
 
     use crate::utils::cover_tree_from_labeled_yaml;
+    use pointcloud::data_sources::{DataRam, PinnedCloud};
+    use pointcloud::label_sources::{SmallIntLabels, VecLabels};
     use std::path::Path;
 
     pub(crate) fn build_mnist_tree() -> CoverTreeWriter<DefaultLabeledCloud<L2>> {
@@ -716,12 +1675,66 @@ pub(crate) mod tests {
             min_res_index: -9,
             use_singletons: true,
             partition_type: PartitionType::Nearest,
+            label_candidates: 1,
             verbosity: 0,
             rng_seed: Some(0),
+            memory_budget: None,
+            num_threads: None,
+            max_in_flight: None,
+            progress: None,
+            cancellation: None,
         };
         builder.build(Arc::new(point_cloud)).unwrap()
     }
 
+    /// The builder of the random test trees.
+    pub(crate) fn random_tree_builder() -> CoverTreeBuilder {
+        CoverTreeBuilder {
+            scale_base: 2.0,
+            leaf_cutoff: 5,
+            min_res_index: -10,
+            use_singletons: true,
+            partition_type: PartitionType::Nearest,
+            label_candidates: 1,
+            verbosity: 0,
+            rng_seed: Some(0),
+            memory_budget: None,
+            num_threads: None,
+            max_in_flight: None,
+            progress: None,
+            cancellation: None,
+        }
+    }
+
+    /// `count` points drawn uniformly from the unit cube in `dim` dimensions, all labeled 0.
+    pub(crate) fn random_cloud(count: usize, dim: usize) -> DefaultLabeledCloud<L2> {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let data: Vec<f32> = (0..count * dim).map(|_| rng.gen::<f32>()).collect();
+        DefaultLabeledCloud::<L2>::new_simple(data, dim, vec![0; count])
+    }
+
+    /// A tree on 200 random points in the unit square.
+    pub(crate) fn build_random_tree() -> CoverTreeWriter<DefaultLabeledCloud<L2>> {
+        random_tree_builder()
+            .build(Arc::new(random_cloud(200, 2)))
+            .unwrap()
+    }
+
+    #[test]
+    fn rng_streams_are_independent() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let parameters = reader.parameters();
+        let draw = |domain: RngDomain, stream: u64| -> Vec<u64> {
+            let mut rng = parameters.stream_rng(domain, stream);
+            (0..4).map(|_| rng.gen::<u64>()).collect()
+        };
+        assert_eq!(draw(RngDomain::Baseline, 3), draw(RngDomain::Baseline, 3));
+        assert_ne!(draw(RngDomain::Baseline, 3), draw(RngDomain::Baseline, 2));
+        assert_ne!(draw(RngDomain::Baseline, 3), draw(RngDomain::Sampling, 3));
+        assert_ne!(draw(RngDomain::Build, 0), draw(RngDomain::Coverage, 0));
+    }
+
     #[test]
     fn len_is_num_layers() {
         let tree = build_basic_tree();
@@ -763,8 +1776,14 @@ pub(crate) mod tests {
             min_res_index: -9,
             use_singletons: false,
             partition_type: PartitionType::Nearest,
+            label_candidates: 1,
             verbosity: 0,
             rng_seed: Some(0),
+            memory_budget: None,
+            num_threads: None,
+            max_in_flight: None,
+            progress: None,
+            cancellation: None,
         };
         let tree = builder.build(Arc::new(point_cloud)).unwrap();
         let reader = tree.reader();
@@ -787,7 +1806,11 @@ pub(crate) mod tests {
                 .1
         );
 
-        reader.greedy_knn_nodes(&point.as_ref(), &mut query_heap);
+        reader.greedy_knn_nodes(
+            &point.as_ref(),
+            &mut query_heap,
+            &mut BudgetMeter::unlimited(),
+        );
         println!("{:#?}", query_heap);
         println!(
             "{:#?}",
@@ -843,6 +1866,31 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn rollup_matches_known_paths() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let paths: Vec<Vec<(f32, NodeAddress)>> =
+            (0..5).map(|pi| reader.known_path(pi).unwrap()).collect();
+        for target_scale in reader.scale_range() {
+            let mut expected: HashMap<NodeAddress, f64> = HashMap::new();
+            for path in &paths {
+                let ancestor = path
+                    .iter()
+                    .map(|(_, address)| *address)
+                    .find(|address| address.0 <= target_scale)
+                    .unwrap_or_else(|| path.last().unwrap().1);
+                *expected.entry(ancestor).or_insert(0.0) += 1.0;
+            }
+            let leaves = paths.iter().map(|path| (path.last().unwrap().1, 1.0));
+            let rolled_up = reader.rollup(leaves, target_scale);
+            assert_eq!(rolled_up, expected, "target scale {}", target_scale);
+        }
+        // Unknown nodes are skipped
+        let above_root = (reader.root_address().0 + 1, 0);
+        assert!(reader.rollup(vec![(above_root, 1.0)], 0).is_empty());
+    }
+
     #[test]
     fn knn_singletons_on() {
         println!("2 nearest neighbors of 0.0 are 0.48 and 0.0");
@@ -854,6 +1902,20 @@ pub(crate) mod tests {
         assert!(zero_nbrs[1].1 == 2);
     }
 
+    #[test]
+    fn approx_knn_stays_within_epsilon() {
+        let writer = build_basic_tree();
+        let reader = writer.reader();
+        for query in &[0.0f32, 0.1, 0.3, -0.6, 2.0] {
+            let exact = reader.knn(&[*query].as_ref(), 2).unwrap();
+            let zero = reader.approx_knn(&[*query].as_ref(), 2, 0.0).unwrap();
+            assert_eq!(exact, zero);
+            let approx = reader.approx_knn(&[*query].as_ref(), 2, 1.0).unwrap();
+            assert_eq!(approx.len(), 2);
+            assert!(approx[1].0 <= 2.0 * exact[1].0 + 1e-6, "query {}", query);
+        }
+    }
+
     #[test]
     fn label_summary() {
         let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
@@ -866,8 +1928,14 @@ pub(crate) mod tests {
             min_res_index: -9,
             use_singletons: false,
             partition_type: PartitionType::Nearest,
+            label_candidates: 1,
             verbosity: 0,
             rng_seed: Some(0),
+            memory_budget: None,
+            num_threads: None,
+            max_in_flight: None,
+            progress: None,
+            cancellation: None,
         };
         let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
         tree.generate_summaries();
@@ -885,6 +1953,48 @@ pub(crate) mod tests {
         assert_eq!(l.errors, 0);
     }
 
+    #[test]
+    fn vec_label_summary() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels: Vec<f32> = data.iter().flat_map(|x| vec![*x, 1.0]).collect();
+
+        let point_cloud = SimpleLabeledCloud::new(
+            DataRam::<L2>::new(data.clone(), 1).unwrap(),
+            VecLabels::new(labels, 2, None),
+        );
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_leaf_cutoff(1)
+            .set_min_res_index(-9)
+            .set_rng_seed(0);
+        let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
+        tree.generate_summaries();
+        let reader = tree.reader();
+
+        // Every node counts each covered point once, including the first one added to an empty summary
+        for (_, layer) in reader.layers() {
+            layer.for_each_node(|_, n| {
+                let summary = n.label_summary().unwrap();
+                assert_eq!(summary.summary.count, n.coverage_count());
+                assert_eq!(summary.summary.moment1.len(), 2);
+                assert_approx_eq!(summary.summary.moment1[1], n.coverage_count() as f32);
+            });
+        }
+
+        let l = reader
+            .get_node_label_summary(reader.root_address())
+            .unwrap();
+        assert_eq!(l.summary.count, 5);
+        assert_approx_eq!(l.summary.moment1[0], data.iter().sum::<f32>());
+        assert_approx_eq!(
+            l.summary.moment2[0],
+            data.iter().map(|x| x * x).sum::<f32>()
+        );
+        assert_approx_eq!(l.summary.moment2[1], 5.0);
+        assert_eq!(l.nones, 0);
+        assert_eq!(l.errors, 0);
+    }
+
     #[test]
     fn knn_singletons_off() {
         let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
@@ -897,8 +2007,14 @@ pub(crate) mod tests {
             min_res_index: -9,
             use_singletons: false,
             partition_type: PartitionType::Nearest,
+            label_candidates: 1,
             verbosity: 0,
             rng_seed: Some(0),
+            memory_budget: None,
+            num_threads: None,
+            max_in_flight: None,
+            progress: None,
+            cancellation: None,
         };
         let tree = builder.build(Arc::new(point_cloud)).unwrap();
         let reader = tree.reader();
@@ -922,8 +2038,14 @@ pub(crate) mod tests {
             min_res_index: -9,
             use_singletons: false,
             partition_type: PartitionType::Nearest,
+            label_candidates: 1,
             verbosity: 0,
             rng_seed: Some(0),
+            memory_budget: None,
+            num_threads: None,
+            max_in_flight: None,
+            progress: None,
+            cancellation: None,
         };
         let tree = builder.build(Arc::clone(&point_cloud)).unwrap();
         let reader = tree.reader();
@@ -954,4 +2076,267 @@ pub(crate) mod tests {
             })
         }
     }
+
+    #[test]
+    fn epoch_bumps_on_refresh() {
+        let mut tree = build_basic_tree();
+        let reader = tree.reader();
+        let built_epoch = reader.epoch();
+        tree.refresh();
+        assert_eq!(reader.epoch(), built_epoch + 1);
+        tree.generate_summaries();
+        assert_eq!(tree.epoch(), built_epoch + 2);
+        assert_eq!(reader.epoch(), built_epoch + 2);
+    }
+
+    #[test]
+    fn recount_coverage_exact_and_sampled() {
+        let mut tree = build_basic_tree();
+        let reader = tree.reader();
+        let root = reader.root_address();
+        let mut counts: Vec<(NodeAddress, usize)> = Vec::new();
+        for (si, layer) in reader.layers() {
+            layer.for_each_node(|pi, n| counts.push(((si, *pi), n.coverage_count())));
+        }
+
+        for (address, _) in counts.iter() {
+            unsafe { tree.update_node(*address, |n| n.set_coverage_count(0)) };
+        }
+        tree.refresh();
+        tree.recount_coverage(true);
+        for (address, count) in counts.iter() {
+            assert_eq!(
+                reader
+                    .get_node_and(*address, |n| n.coverage_count())
+                    .unwrap(),
+                *count
+            );
+        }
+
+        unsafe { tree.update_node(root, |n| n.set_coverage_count(0)) };
+        tree.refresh();
+        tree.recount_coverage_sampled(50);
+        assert_eq!(
+            reader.get_node_and(root, |n| n.coverage_count()).unwrap(),
+            reader.parameters().point_cloud.len()
+        );
+    }
+
+    #[test]
+    fn optimize_layout_keeps_nodes() {
+        let mut tree = build_basic_tree();
+        let reader = tree.reader();
+        let mut nodes: Vec<(NodeAddress, usize, Option<Vec<NodeAddress>>)> = Vec::new();
+        for (si, layer) in reader.layers() {
+            layer.for_each_node(|pi, n| {
+                nodes.push((
+                    (si, *pi),
+                    n.coverage_count(),
+                    n.children().map(|(_, c)| c.to_vec()),
+                ))
+            });
+        }
+        let epoch = tree.epoch();
+        tree.optimize_layout();
+        assert!(tree.epoch() > epoch);
+
+        let mut node_count = 0;
+        for (_si, layer) in reader.layers() {
+            node_count += layer.len();
+        }
+        assert_eq!(node_count, nodes.len());
+        for (address, count, children) in nodes.iter() {
+            reader
+                .get_node_and(*address, |n| {
+                    assert_eq!(n.coverage_count(), *count);
+                    assert_eq!(n.children().map(|(_, c)| c.to_vec()), *children);
+                })
+                .unwrap();
+        }
+        assert!(reader.known_path(0).is_ok());
+    }
+
+    #[test]
+    fn optimize_layout_keeps_plugins() {
+        use crate::plugins::discrete::prelude::*;
+        use crate::plugins::gaussians::*;
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        tree.add_plugin::<GokoDiagGaussian>(GokoDiagGaussian::recursive());
+        let reader = tree.reader();
+        let root = reader.root_address();
+        let total = reader
+            .get_node_plugin_and::<Dirichlet, _, _>(root, |d| d.total())
+            .unwrap();
+        tree.optimize_layout();
+
+        assert!(reader.plugin_attached::<GokoDirichlet>());
+        assert!(reader.plugin_attached::<GokoDiagGaussian>());
+        for (_si, layer) in reader.layers() {
+            layer.for_each_node(|_pi, n| {
+                assert!(n.get_plugin_and::<Dirichlet, _, _>(|_| ()).is_some());
+                assert!(n.get_plugin_and::<DiagGaussian, _, _>(|_| ()).is_some());
+            });
+        }
+        assert_approx_eq!(
+            reader
+                .get_node_plugin_and::<Dirichlet, _, _>(root, |d| d.total())
+                .unwrap(),
+            total
+        );
+        assert_eq!(
+            reader
+                .get_node_plugin_and::<DiagGaussian, _, _>(root, |g| g.count())
+                .unwrap(),
+            5
+        );
+    }
+
+    #[test]
+    fn sorted_layer_iteration() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        for (_si, layer) in reader.layers() {
+            let indexes: Vec<usize> = layer.map_nodes_sorted(|pi, _| *pi);
+            let mut expected = layer.node_center_indexes();
+            expected.sort_unstable();
+            assert_eq!(indexes, expected);
+
+            let mut visited = Vec::new();
+            layer.for_each_node_sorted(|pi, _| visited.push(*pi));
+            assert_eq!(visited, expected);
+        }
+
+        let proto = tree.save();
+        assert_eq!(proto.get_layers(), tree.save().get_layers());
+        for layer_proto in proto.get_layers() {
+            let indexes: Vec<u64> = layer_proto
+                .get_nodes()
+                .iter()
+                .map(|n| n.get_center_index())
+                .collect();
+            assert!(indexes.windows(2).all(|w| w[0] < w[1]));
+        }
+    }
+
+    #[test]
+    fn count_within_matches_brute_force() {
+        let data = [0.499f32, 0.49, 0.48, -0.49, 0.0];
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        for query in &[0.0f32, 0.1, 0.3, -0.6, 2.0] {
+            for radius in &[0.0f32, 0.05, 0.2, 0.5, 1.0, 10.0] {
+                let expected = data
+                    .iter()
+                    .filter(|x| (*x - query).abs() <= *radius)
+                    .count();
+                let count = reader.count_within(&[*query].as_ref(), *radius).unwrap();
+                assert_eq!(count, expected, "query {} radius {}", query, radius);
+                let exists = reader.exists_within(&[*query].as_ref(), *radius).unwrap();
+                assert_eq!(exists, expected > 0, "query {} radius {}", query, radius);
+            }
+        }
+    }
+
+    #[test]
+    fn range_query_matches_brute_force() {
+        let data = [0.499f32, 0.49, 0.48, -0.49, 0.0];
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        for query in &[0.0f32, 0.1, 0.3, -0.6, 2.0] {
+            for radius in &[0.0f32, 0.05, 0.2, 0.5, 1.0, 10.0] {
+                let mut expected: Vec<usize> = data
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, x)| (*x - query).abs() <= *radius)
+                    .map(|(i, _)| i)
+                    .collect();
+                expected.sort();
+                let found = reader.range_query(&[*query].as_ref(), *radius).unwrap();
+                for window in found.windows(2) {
+                    assert!(window[0].0 <= window[1].0);
+                }
+                let mut indexes: Vec<usize> = found.iter().map(|(_, i)| *i).collect();
+                indexes.sort();
+                assert_eq!(indexes, expected, "query {} radius {}", query, radius);
+            }
+        }
+    }
+
+    #[test]
+    fn reparent_moves_subtree() {
+        let mut tree = build_random_tree();
+        let reader = tree.reader();
+        let root = reader.root_address();
+
+        // A node that isn't a nested child and whose parent isn't the root
+        let mut subtree = None;
+        for (_si, layer) in reader.layers() {
+            layer.for_each_node(|pi, n| {
+                if let Some(parent) = n.parent_address() {
+                    if parent != root && parent.1 != *pi && subtree.is_none() {
+                        subtree = Some((n.address(), parent, n.coverage_count()));
+                    }
+                }
+            });
+        }
+        let (subtree_root, old_parent, coverage) = subtree.unwrap();
+        let old_parent_coverage = reader
+            .get_node_and(old_parent, |n| n.coverage_count())
+            .unwrap();
+
+        assert!(tree.reparent(root, old_parent, false).is_err());
+        let nested_scale = reader
+            .get_node_and(old_parent, |n| n.children().unwrap().0)
+            .unwrap();
+        assert!(tree
+            .reparent((nested_scale, old_parent.1), root, false)
+            .is_err());
+        assert!(tree.reparent(old_parent, subtree_root, true).is_err());
+
+        tree.reparent(subtree_root, root, false).unwrap();
+        assert!(reader.no_dangling_refs());
+        assert_eq!(
+            reader.get_node_and(subtree_root, |n| n.parent_address()),
+            Some(Some(root))
+        );
+        assert_eq!(
+            reader.get_node_and(old_parent, |n| n.coverage_count()),
+            Some(old_parent_coverage - coverage)
+        );
+        assert_eq!(reader.get_node_and(root, |n| n.coverage_count()), Some(200));
+        assert!(reader
+            .get_node_and(root, |n| n.children().unwrap().1.contains(&subtree_root))
+            .unwrap());
+        assert_eq!(
+            reader.count_within(&[0.5f32, 0.5].as_ref(), 10.0).unwrap(),
+            200
+        );
+    }
+
+    #[test]
+    fn pinned_top_layers_are_hit() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let data: Vec<f32> = (0..400).map(|_| rng.gen::<f32>()).collect();
+        let labels = SmallIntLabels::new(vec![0; 200], None);
+        let pinned = PinnedCloud::new(DataRam::<L2>::new(data.clone(), 2).unwrap(), 1 << 20);
+        let cache = pinned.cache();
+        let point_cloud = SimpleLabeledCloud::new(pinned, labels);
+        let builder = random_tree_builder();
+        let tree = builder.build(Arc::new(point_cloud)).unwrap();
+        let reader = tree.reader();
+
+        let centers = reader.top_layer_centers(3);
+        assert_eq!(centers[0], reader.root_address().1);
+        assert_eq!(reader.pin_top_layers(&cache, 3).unwrap(), centers.len());
+
+        let before = cache.stats();
+        let query = [0.5f32, 0.5];
+        let knn = reader.knn(&query.as_ref(), 5).unwrap();
+        assert!(cache.stats().hits > before.hits);
+
+        let plain = DefaultLabeledCloud::<L2>::new_simple(data, 2, vec![0; 200]);
+        let plain_tree = builder.build(Arc::new(plain)).unwrap();
+        assert_eq!(plain_tree.reader().knn(&query.as_ref(), 5).unwrap(), knn);
+    }
 }
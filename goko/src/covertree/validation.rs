@@ -0,0 +1,395 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Load Validation
+//!
+//! Trees saved by older versions of goko can break invariants that the current queries rely on. A child can sit
+//! outside the covering radius of its parent, or a node can have a radius smaller than the distance to some of the
+//! points under it. Queries on such a tree don't fail, they prune nodes they shouldn't and silently return worse
+//! results.
+//!
+//! [`CoverTreeWriter::load_validated`] loads a tree and checks it before it's served. The check walks the tree down
+//! from the root and computes the distance from every point to the centers of the nodes above it, so it costs about
+//! as much as a pass of `known_path` over the whole dataset. A repair moves the stray children under a node that
+//! covers them and grows the radii that are too small.
+
+use super::*;
+use crate::errors::{GokoError, GokoResult};
+use crate::tree_file_format::*;
+use crate::NodeAddress;
+use hashbrown::HashMap;
+use pointcloud::*;
+use rayon::prelude::*;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Radii are recomputed with the same metric, but not necessarily in the same order, so we allow a little rounding.
+const RADIUS_TOLERANCE: f32 = 1.0e-5;
+
+/// What [`CoverTreeWriter::load_validated`] does with the loaded tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadValidation {
+    /// Don't check the tree, same as [`CoverTreeWriter::load`]
+    Skip,
+    /// Check the tree and report the violations, but leave it as it is
+    Report,
+    /// Check the tree and repair what can be repaired, see [`CoverTreeWriter::repair`]
+    Repair,
+}
+
+/// An invariant that a node of the tree breaks.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// A child address that doesn't reference a node in the tree. The points under it are lost
+    DanglingChild {
+        /// The node that lists the child
+        parent: NodeAddress,
+        /// The missing child
+        child: NodeAddress,
+    },
+    /// A node whose stored parent isn't the node that lists it as a child
+    WrongParent {
+        /// The node
+        node: NodeAddress,
+        /// The parent the node has stored
+        stored: Option<NodeAddress>,
+        /// The node that lists it as a child
+        actual: NodeAddress,
+    },
+    /// A child whose center is further than `scale_base^scale_index` from the center of its parent
+    StrayChild {
+        /// The parent whose scale doesn't cover the child
+        parent: NodeAddress,
+        /// The stray child
+        child: NodeAddress,
+        /// The distance between the centers
+        distance: f32,
+    },
+    /// A node whose radius is smaller than the distance from its center to one of the points under it
+    RadiusTooSmall {
+        /// The node
+        node: NodeAddress,
+        /// The stored radius
+        radius: f32,
+        /// The distance to the furthest point under the node
+        required: f32,
+    },
+}
+
+/// The result of a validation pass, see [`CoverTreeWriter::validate`].
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    /// The number of nodes reachable from the root
+    pub nodes_checked: usize,
+    /// The violations found, in breadth first order with the radius violations last
+    pub violations: Vec<Violation>,
+    /// The violations that are still in the tree after a repair. Empty if the tree wasn't repaired
+    pub unrepaired: Vec<Violation>,
+}
+
+impl ValidationReport {
+    /// If the tree didn't break any invariants
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl<D: PointCloud> CoverTreeWriter<D> {
+    /// Loads a tree from a protobuf, like [`CoverTreeWriter::load`], and validates it before it's handed out. The
+    /// report is empty if the validation is skipped. A violation is logged as a warning, the report has the details.
+    pub fn load_validated(
+        cover_proto: &CoreProto,
+        point_cloud: Arc<D>,
+        validation: LoadValidation,
+    ) -> GokoResult<(CoverTreeWriter<D>, ValidationReport)> {
+        let mut tree = CoverTreeWriter::load(cover_proto, point_cloud)?;
+        let report = match validation {
+            LoadValidation::Skip => ValidationReport::default(),
+            LoadValidation::Report => tree.validate()?,
+            LoadValidation::Repair => tree.repair()?,
+        };
+        if !report.is_valid() {
+            warn!(
+                violations = report.violations.len(),
+                unrepaired = report.unrepaired.len(),
+                "Loaded tree breaks the covering invariants"
+            );
+        }
+        Ok((tree, report))
+    }
+
+    /// Checks the published tree against the covering invariants, see [`Violation`]. The tree is walked through the
+    /// child lists, so the stored parent addresses don't need to be right.
+    pub fn validate(&self) -> GokoResult<ValidationReport> {
+        let reader = self.reader();
+        let point_cloud = &self.parameters.point_cloud;
+        let mut report = ValidationReport::default();
+        let mut parents: HashMap<NodeAddress, NodeAddress> = HashMap::new();
+        let mut radii: Vec<(NodeAddress, f32)> = Vec::new();
+        // The points each node holds directly, its singletons and its center if it's a leaf
+        let mut owned: Vec<(NodeAddress, usize)> = Vec::new();
+
+        let mut unvisited = VecDeque::new();
+        unvisited.push_back(self.root_address);
+        while let Some(address) = unvisited.pop_front() {
+            let (stored_parent, radius, singletons, children) =
+                match reader.get_node_and(address, |n| {
+                    (
+                        n.parent_address(),
+                        n.radius(),
+                        n.singletons().to_vec(),
+                        n.children().map(|(ns, cs)| (ns, cs.to_vec())),
+                    )
+                }) {
+                    Some(node) => node,
+                    None => continue,
+                };
+            report.nodes_checked += 1;
+            radii.push((address, radius));
+            if let Some(actual) = parents.get(&address) {
+                if stored_parent != Some(*actual) {
+                    report.violations.push(Violation::WrongParent {
+                        node: address,
+                        stored: stored_parent,
+                        actual: *actual,
+                    });
+                }
+            }
+            owned.extend(singletons.into_iter().map(|pi| (address, pi)));
+
+            match children {
+                None => owned.push((address, address.1)),
+                Some((nested_scale, child_addresses)) => {
+                    let child_centers: Vec<usize> = child_addresses.iter().map(|c| c.1).collect();
+                    let distances =
+                        point_cloud.distances_to_point_index(address.1, &child_centers)?;
+                    let cover = self.parameters.scale_base.powi(address.0);
+                    let children = std::iter::once(((nested_scale, address.1), 0.0))
+                        .chain(child_addresses.into_iter().zip(distances));
+                    for (child, distance) in children {
+                        if reader.get_node_and(child, |_| ()).is_none() {
+                            report.violations.push(Violation::DanglingChild {
+                                parent: address,
+                                child,
+                            });
+                            continue;
+                        }
+                        if distance > cover {
+                            report.violations.push(Violation::StrayChild {
+                                parent: address,
+                                child,
+                                distance,
+                            });
+                        }
+                        parents.insert(child, address);
+                        unvisited.push_back(child);
+                    }
+                }
+            }
+        }
+
+        let paths = owned
+            .par_iter()
+            .map(|(owner, pi)| {
+                let mut path = vec![*owner];
+                while let Some(parent) = parents.get(path.last().unwrap()) {
+                    path.push(*parent);
+                }
+                let centers: Vec<usize> = path.iter().map(|a| a.1).collect();
+                let distances = point_cloud.distances_to_point_index(*pi, &centers)?;
+                Ok(path.into_iter().zip(distances).collect())
+            })
+            .collect::<GokoResult<Vec<Vec<(NodeAddress, f32)>>>>()?;
+        let mut required: HashMap<NodeAddress, f32> = HashMap::new();
+        for (address, distance) in paths.into_iter().flatten() {
+            let r = required.entry(address).or_insert(0.0);
+            *r = r.max(distance);
+        }
+        for (node, radius) in radii {
+            let required = required.get(&node).cloned().unwrap_or(0.0);
+            if required - radius > RADIUS_TOLERANCE * required.max(1.0) {
+                report.violations.push(Violation::RadiusTooSmall {
+                    node,
+                    radius,
+                    required,
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    /// Validates the tree and repairs it. The stored parents are corrected, each stray child is moved under the
+    /// nearest routing node that covers it, starting at the scale of its old parent and going up, and the radii
+    /// that are too small are grown to the furthest point. Dangling children can't be repaired, the points under them
+    /// are gone.
+    ///
+    /// The returned report has the violations of the tree as it was, and the ones left after the repair. Plugins are
+    /// not updated, attach them after the repair. The repair is published to the readers.
+    pub fn repair(&mut self) -> GokoResult<ValidationReport> {
+        let mut report = self.validate()?;
+        if report.is_valid() {
+            return Ok(report);
+        }
+        let mut strays = Vec::new();
+        for violation in report.violations.iter() {
+            match violation {
+                Violation::WrongParent { node, actual, .. } => {
+                    let actual = *actual;
+                    unsafe { self.update_node(*node, move |n| n.set_parent_address(Some(actual))) }
+                }
+                Violation::StrayChild { parent, child, .. } => strays.push((*parent, *child)),
+                // The radii are redone once the strays have moved
+                Violation::RadiusTooSmall { .. } | Violation::DanglingChild { .. } => {}
+            }
+        }
+        self.refresh();
+        for (parent, child) in strays {
+            self.adopt_stray(parent, child)?;
+        }
+
+        let remaining = self.validate()?;
+        for violation in remaining.violations.iter() {
+            if let Violation::RadiusTooSmall { node, required, .. } = violation {
+                let required = *required;
+                unsafe { self.update_node(*node, move |n| n.set_radius(required)) }
+            }
+        }
+        self.refresh();
+        report.unrepaired = remaining
+            .violations
+            .into_iter()
+            .filter(|v| !matches!(v, Violation::RadiusTooSmall { .. }))
+            .collect();
+        Ok(report)
+    }
+
+    /// Moves a stray child under the nearest routing node that covers it, at the lowest scale possible. If there
+    /// isn't one the child stays where it is, the next validation reports it.
+    fn adopt_stray(&mut self, parent: NodeAddress, child: NodeAddress) -> GokoResult<()> {
+        let reader = self.reader();
+        for scale_index in parent.0..=self.root_address.0 {
+            let candidates: Vec<usize> = reader
+                .layer(scale_index)
+                .node_center_indexes()
+                .into_iter()
+                .filter(|pi| {
+                    reader
+                        .get_node_and((scale_index, *pi), |n| !n.is_leaf())
+                        .unwrap_or(false)
+                })
+                .collect();
+            if candidates.is_empty() {
+                continue;
+            }
+            let distances = self
+                .parameters
+                .point_cloud
+                .distances_to_point_index(child.1, &candidates)?;
+            let cover = self.parameters.scale_base.powi(scale_index);
+            let nearest = candidates
+                .iter()
+                .zip(distances)
+                .filter(|(_, d)| *d <= cover)
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+            if let Some((pi, _)) = nearest {
+                // A tree that's broken enough to refuse the move keeps the stray
+                return match self.reparent(child, (scale_index, *pi), false) {
+                    Ok(()) | Err(GokoError::InvalidReparent(_)) => Ok(()),
+                    Err(e) => Err(e),
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::{random_cloud, random_tree_builder};
+
+    #[test]
+    fn repairs_broken_tree() {
+        let point_cloud = Arc::new(random_cloud(200, 2));
+        let mut tree = random_tree_builder()
+            .build(Arc::clone(&point_cloud))
+            .unwrap();
+        assert!(tree.validate().unwrap().is_valid());
+        let query = [0.5f32, 0.5];
+        let expected = tree.reader().knn(&query.as_ref(), 10).unwrap();
+
+        // Force a subtree under a routing node that doesn't cover it
+        let reader = tree.reader();
+        let root = reader.root_address();
+        let mut stray = None;
+        for (_si, layer) in reader.layers() {
+            layer.for_each_node(|pi, n| {
+                if let Some(parent) = n.parent_address() {
+                    if parent != root && parent.1 != *pi && stray.is_none() {
+                        stray = Some(n.address());
+                    }
+                }
+            });
+        }
+        let stray = stray.unwrap();
+        let mut far_parent = None;
+        for (si, layer) in reader.layers().filter(|(si, _)| *si > stray.0) {
+            let cover = builder.scale_base.powi(si);
+            layer.for_each_node(|pi, n| {
+                let distance = point_cloud
+                    .distances_to_point_index(stray.1, &[*pi])
+                    .unwrap()[0];
+                if !n.is_leaf() && distance > cover && far_parent.is_none() {
+                    far_parent = Some(n.address());
+                }
+            });
+        }
+        tree.reparent(stray, far_parent.unwrap(), true).unwrap();
+        unsafe { tree.update_node(root, |n| n.set_radius(0.0)) };
+        tree.refresh();
+
+        let proto = tree.save();
+        let (_tree, report) = CoverTreeWriter::load_validated(
+            &proto,
+            Arc::clone(&point_cloud),
+            LoadValidation::Report,
+        )
+        .unwrap();
+        assert!(report
+            .violations
+            .iter()
+            .any(|v| matches!(v, Violation::StrayChild { child, .. } if *child == stray)));
+        assert!(report
+            .violations
+            .iter()
+            .any(|v| matches!(v, Violation::RadiusTooSmall { node, .. } if *node == root)));
+        assert!(report.unrepaired.is_empty());
+
+        let (repaired, report) =
+            CoverTreeWriter::load_validated(&proto, point_cloud, LoadValidation::Repair).unwrap();
+        assert!(!report.is_valid());
+        assert!(report.unrepaired.is_empty());
+        assert!(repaired.validate().unwrap().is_valid());
+        assert_eq!(
+            repaired.reader().knn(&query.as_ref(), 10).unwrap(),
+            expected
+        );
+    }
+}
@@ -0,0 +1,171 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Warm Starts
+//!
+//! Rebuilding a tree on a new version of a dataset gives new node addresses, so the trackers, baselines and priors
+//! made on the old tree don't line up with the new one. [`CoverTreeBuilder::build_warm`] keeps the nodes of an
+//! existing tree instead. Every node of the old tree is in the new one at the same address, with the same parent and
+//! children. The points of the new point cloud are then inserted like [`CoverTreeWriter::insert_point`] does, so
+//! they land in the existing nodes and only leaves that grow past the `leaf_cutoff` are split into new nodes.
+//!
+//! The nodes are centered on point indexes, so the new point cloud has to keep the centers of the old tree at the
+//! same indexes. A dataset that only has points appended to it does this.
+
+use super::layer::*;
+use super::*;
+use crate::errors::GokoResult;
+use crate::monomap;
+use crate::plugins::{PluginAttachments, TreePluginSet};
+use crate::tree_file_format::*;
+use hashbrown::HashMap;
+use pointcloud::*;
+use std::sync::{atomic, Arc, RwLock};
+use tracing::info_span;
+
+impl CoverTreeBuilder {
+    /// Builds a tree on `point_cloud` with the nodes of `base`, see the [module docs](self). The scale base, leaf
+    /// cutoff, minimum resolution, singleton use and partition type come from `base`, as its node addresses only mean
+    /// the same thing with them. The rest of the settings come from this builder.
+    ///
+    /// Errors with the point cloud's error if it doesn't have one of the centers of `base`.
+    pub fn build_warm<D: PointCloud, E: PointCloud>(
+        &self,
+        base: &CoverTreeReader<E>,
+        point_cloud: Arc<D>,
+    ) -> GokoResult<CoverTreeWriter<D>> {
+        let span = info_span!(
+            "build_warm",
+            points = point_cloud.len(),
+            base_nodes = base.node_count(),
+        );
+        let _build = span.enter();
+        let base_parameters = base.parameters();
+        let parameters = Arc::new(CoverTreeParameters {
+            total_nodes: atomic::AtomicUsize::new(base.node_count()),
+            epoch: atomic::AtomicU64::new(0),
+            scale_base: base_parameters.scale_base,
+            leaf_cutoff: base_parameters.leaf_cutoff,
+            min_res_index: base_parameters.min_res_index,
+            use_singletons: base_parameters.use_singletons,
+            partition_type: base_parameters.partition_type,
+            label_candidates: self.label_candidates,
+            point_cloud,
+            verbosity: self.verbosity,
+            rng_seed: self.rng_seed,
+            plugins: RwLock::new(TreePluginSet::new()),
+            plugin_attachments: RwLock::new(PluginAttachments::new()),
+            plugin_sizers: RwLock::new(HashMap::new()),
+            plugin_copiers: RwLock::new(HashMap::new()),
+            sketch: RwLock::new(None),
+            accelerator: RwLock::new(None),
+        });
+
+        // The old tree's nodes without the old points, only the centers stay in the tree
+        let mut layers = Vec::with_capacity(base.len());
+        for (scale_index, layer) in base.layers() {
+            let mut node_protos = Vec::with_capacity(layer.len());
+            let mut center_indexes = layer.node_center_indexes();
+            center_indexes.sort_unstable();
+            for center_index in center_indexes {
+                parameters.point_cloud.point(center_index)?;
+                layer.get_node_and(center_index, |n| {
+                    let mut node_proto = n.save();
+                    node_proto.clear_outlier_point_indexes();
+                    node_proto.set_coverage_count(1);
+                    node_protos.push(node_proto);
+                });
+            }
+            let mut layer_proto = LayerProto::new();
+            layer_proto.set_scale_index(scale_index);
+            layer_proto.set_nodes(protobuf::RepeatedField::from_vec(node_protos));
+            layers.push(CoverLayerWriter::load(&layer_proto));
+        }
+        // The reader's layers start at the top
+        layers.reverse();
+
+        let (_final_addresses_reader, final_addresses) = monomap::new();
+        let mut tree = CoverTreeWriter {
+            parameters,
+            layers,
+            root_address: base.root_address(),
+            final_addresses,
+            checkpoint_version: None,
+            plugin_hooks: HashMap::new(),
+            plugin_builders: HashMap::new(),
+            defer_refresh: false,
+        };
+        tree.refresh_final_indexes();
+
+        let new_points: Vec<usize> = (0..tree.parameters.point_cloud.len())
+            .filter(|pi| tree.final_address_pending(*pi).is_none())
+            .collect();
+        tree.insert_points(&new_points)?;
+        tree.recount_coverage(true);
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn warm_start_keeps_the_nodes() {
+        let base = build_basic_tree();
+        let base_reader = base.reader();
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0, -0.3, 0.25, 0.495];
+        let labels = vec![0, 0, 0, 1, 1, 1, 0, 0];
+        let point_cloud = Arc::new(DefaultLabeledCloud::<L2>::new_simple(data, 1, labels));
+        let tree = CoverTreeBuilder::new()
+            .build_warm(&base_reader, point_cloud)
+            .unwrap();
+        let reader = tree.reader();
+
+        assert_eq!(reader.root_address(), base_reader.root_address());
+        for (scale_index, layer) in base_reader.layers() {
+            layer.for_each_node(|center_index, n| {
+                let address = (scale_index, *center_index);
+                let parent = reader.get_node_and(address, |m| m.parent_address());
+                assert_eq!(parent, Some(n.parent_address()));
+            });
+        }
+        for pi in 0..8 {
+            assert!(reader.known_path(pi).is_ok());
+        }
+        let root_coverage = reader
+            .get_node_and(reader.root_address(), |n| n.coverage_count())
+            .unwrap();
+        assert_eq!(root_coverage, 8);
+    }
+
+    #[test]
+    fn warm_start_needs_the_centers() {
+        let base = build_basic_tree();
+        let point_cloud = Arc::new(DefaultLabeledCloud::<L2>::new_simple(
+            vec![0.499, 0.49],
+            1,
+            vec![0, 0],
+        ));
+        assert!(CoverTreeBuilder::new()
+            .build_warm(&base.reader(), point_cloud)
+            .is_err());
+    }
+}
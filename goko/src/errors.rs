@@ -47,6 +47,32 @@ pub enum GokoError {
     DoubleNest,
     /// Inserted a node before you changed it from a leaf node into a normal node. Insert the nested child first.
     InsertBeforeNest,
+    /// Attempted to build a tree on a point cloud with no points in it
+    EmptyPointCloud,
+    /// A delta checkpoint doesn't apply to the given full checkpoint
+    DeltaBaseMismatch,
+    /// A reparent was refused, the reason is attached
+    InvalidReparent(&'static str),
+    /// The point holds up part of the tree, it can't be removed without a rebuild
+    PointNotRemovable(usize),
+    /// The point is already in the tree, it can't be inserted again
+    PointAlreadyInTree(usize),
+    /// A plugin's saved state couldn't be encoded or decoded, the reason is attached
+    PluginEncodingError(String),
+    /// A flat tree file is malformed or truncated, the reason is attached
+    InvalidFlatTree(&'static str),
+    /// A saved tree doesn't describe a tree, the reason is attached
+    InvalidTreeFile(&'static str),
+    /// The thread pool for a build couldn't be made, the reason is attached
+    ThreadPoolError(String),
+    /// The build was cancelled with its cancellation token
+    BuildCancelled,
+    /// Error when writing or reading JSON
+    JsonError(serde_json::Error),
+    /// A baseline can't be trained with the given settings, the reason is attached
+    InvalidBaseline(&'static str),
+    /// A Dirichlet prior strategy has a parameter that doesn't make a prior, the reason is attached
+    InvalidPrior(&'static str),
 }
 
 impl fmt::Display for GokoError {
@@ -70,6 +96,39 @@ impl fmt::Display for GokoError {
                 f,
                 "Inserted a node into a node that does not have a nested child"
             ),
+            GokoError::EmptyPointCloud => write!(
+                f,
+                "Attempted to build a tree on a point cloud with no points in it"
+            ),
+            GokoError::DeltaBaseMismatch => write!(
+                f,
+                "The delta checkpoint doesn't apply to the given full checkpoint"
+            ),
+            GokoError::InvalidReparent(reason) => {
+                write!(f, "Unable to reparent the subtree, {}", reason)
+            }
+            GokoError::PointNotRemovable(pi) => write!(
+                f,
+                "The point {} holds up part of the tree and can't be removed",
+                pi
+            ),
+            GokoError::PointAlreadyInTree(pi) => {
+                write!(f, "The point {} is already in the tree", pi)
+            }
+            GokoError::PluginEncodingError(ref reason) => {
+                write!(f, "Unable to save or load a plugin, {}", reason)
+            }
+            GokoError::InvalidFlatTree(reason) => write!(f, "Unable to read the flat tree, {}", reason),
+            GokoError::InvalidTreeFile(reason) => write!(f, "Unable to read the tree, {}", reason),
+            GokoError::ThreadPoolError(ref reason) => {
+                write!(f, "Unable to make the build's thread pool, {}", reason)
+            }
+            GokoError::BuildCancelled => write!(f, "The build was cancelled"),
+            GokoError::JsonError(ref e) => write!(f, "{}", e),
+            GokoError::InvalidBaseline(reason) => {
+                write!(f, "Unable to train the baseline, {}", reason)
+            }
+            GokoError::InvalidPrior(reason) => write!(f, "Unable to build the prior, {}", reason),
         }
     }
 }
@@ -93,6 +152,25 @@ impl Error for GokoError {
             GokoError::InvalidProbDistro => {
                 "The probability distribution you are trying to sample from is invalid, probably because it was infered from 0 points."
             }
+            GokoError::EmptyPointCloud => {
+                "Attempted to build a tree on a point cloud with no points in it"
+            }
+            GokoError::DeltaBaseMismatch => {
+                "The delta checkpoint doesn't apply to the given full checkpoint"
+            }
+            GokoError::InvalidReparent(..) => "Unable to reparent the subtree",
+            GokoError::PointNotRemovable(..) => {
+                "The point holds up part of the tree and can't be removed"
+            }
+            GokoError::PointAlreadyInTree(..) => "The point is already in the tree",
+            GokoError::PluginEncodingError(..) => "Unable to save or load a plugin",
+            GokoError::InvalidFlatTree(..) => "Unable to read the flat tree",
+            GokoError::InvalidTreeFile(..) => "Unable to read the tree",
+            GokoError::ThreadPoolError(..) => "Unable to make the build's thread pool",
+            GokoError::BuildCancelled => "The build was cancelled",
+            GokoError::JsonError(..) => "Unable to write or read JSON",
+            GokoError::InvalidBaseline(..) => "Unable to train the baseline",
+            GokoError::InvalidPrior(..) => "Unable to build the prior",
         }
     }
 
@@ -105,6 +183,19 @@ impl Error for GokoError {
             GokoError::DoubleNest => None,
             GokoError::InsertBeforeNest => None,
             GokoError::InvalidProbDistro => None,
+            GokoError::EmptyPointCloud => None,
+            GokoError::DeltaBaseMismatch => None,
+            GokoError::InvalidReparent(..) => None,
+            GokoError::PointNotRemovable(..) => None,
+            GokoError::PointAlreadyInTree(..) => None,
+            GokoError::PluginEncodingError(..) => None,
+            GokoError::InvalidFlatTree(..) => None,
+            GokoError::InvalidTreeFile(..) => None,
+            GokoError::ThreadPoolError(..) => None,
+            GokoError::BuildCancelled => None,
+            GokoError::JsonError(ref e) => Some(e),
+            GokoError::InvalidBaseline(..) => None,
+            GokoError::InvalidPrior(..) => None,
         }
     }
 }
@@ -126,3 +217,9 @@ impl From<io::Error> for GokoError {
         GokoError::IoError(err)
     }
 }
+
+impl From<serde_json::Error> for GokoError {
+    fn from(err: serde_json::Error) -> Self {
+        GokoError::JsonError(err)
+    }
+}
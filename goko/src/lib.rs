@@ -21,8 +21,6 @@
 #![deny(warnings)]
 #![warn(missing_docs)]
 #![doc(test(attr(allow(unused_variables), deny(warnings))))]
-#![feature(binary_heap_into_iter_sorted)]
-#![feature(associated_type_defaults)]
 
 //! # Goko
 //! This is an lock-free efficient implementation of a covertree for data science. The traditional
@@ -75,6 +73,10 @@ pub mod utils;
 
 pub mod plugins;
 
+pub mod algorithms;
+
+pub mod clustering;
+
 /// The data structure explicitly seperates the covertree by layer, and the addressing schema for nodes
 /// is a pair for the layer index and the center point index of that node.
 pub type NodeAddress = (i32, usize);
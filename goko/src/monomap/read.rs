@@ -200,6 +200,11 @@ where
         self.with_handle(|inner| inner.data.len()).unwrap_or(0)
     }
 
+    /// Returns the number of keys the map can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.with_handle(|inner| inner.data.capacity()).unwrap_or(0)
+    }
+
     /// Returns true if the map contains no elements.
     pub fn is_empty(&self) -> bool {
         self.with_handle(|inner| inner.data.is_empty())
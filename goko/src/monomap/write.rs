@@ -259,6 +259,50 @@ where
         &self.oplog[self.swap_index..]
     }
 
+    /// Reads the value as the readers will see it after the next refresh. The pending operations on the key are
+    /// replayed onto a copy of the published value, so this costs a scan of the pending operations and, for a key
+    /// that was written to, a clone.
+    pub fn get_pending_and<F, T>(&self, key: &K, then: F) -> Option<T>
+    where
+        F: FnOnce(&V) -> T,
+    {
+        self.get_pending_with_and(key, V::clone, then)
+    }
+
+    /// Same as [`MonoWriteHandle::get_pending_and`], with the copy of the published value made by `copy`. Use this
+    /// for values whose `clone` leaves part of them behind.
+    pub fn get_pending_with_and<C, F, T>(&self, key: &K, copy: C, then: F) -> Option<T>
+    where
+        C: Fn(&V) -> V,
+        F: FnOnce(&V) -> T,
+    {
+        let pending = self.pending();
+        let touched = pending.iter().any(|op| match op {
+            MonoOperation::Insert(k, _)
+            | MonoOperation::Update(k, _)
+            | MonoOperation::Remove(k) => k == key,
+            MonoOperation::Purge => true,
+        });
+        if !touched {
+            return self.get_and(key, then);
+        }
+        let mut value = self.get_and(key, |v| copy(v));
+        for op in pending {
+            match op {
+                MonoOperation::Insert(k, v) if k == key => value = Some(copy(v)),
+                MonoOperation::Update(k, updater) if k == key => {
+                    if let Some(v) = value.as_mut() {
+                        updater.eval(v);
+                    }
+                }
+                MonoOperation::Remove(k) if k == key => value = None,
+                MonoOperation::Purge => value = None,
+                _ => {}
+            }
+        }
+        value.as_ref().map(then)
+    }
+
     /// Refresh as necessary to ensure that all operations are visible to readers.
     ///
     /// `MonoWriteHandle::refresh` will *always* wait for old readers to depart and swap the maps.
@@ -0,0 +1,87 @@
+//! # Bottom up aggregation
+//!
+//! Most node plugins are a fold over the points a node covers. The label summaries, the coverage indexes and the
+//! gaussians all compute a value for the singletons of the node (and the center if it's a leaf), then combine it
+//! with the values of the children. If your node component can do those two things implement [`Mergeable`] and
+//! attach it with [`crate::CoverTreeWriter::add_aggregate_plugin`], which handles the recursion. Each layer is
+//! computed in parallel once the layers below it are done.
+
+use super::*;
+use crate::covertree::node::CoverNode;
+use crate::covertree::CoverTreeReader;
+
+/// A node component that can be computed from a set of points and merged with the components of other nodes.
+pub trait Mergeable<D: PointCloud>: NodePlugin<D> + Clone + 'static {
+    /// The value for the points the node owns directly. This is never called with an empty slice.
+    fn from_points(point_indexes: &[usize], point_cloud: &D) -> Option<Self>;
+    /// Folds the value of a child into this one.
+    fn merge(&mut self, other: &Self);
+}
+
+/// The aggregate value of a node, assuming the values of its children are already attached.
+pub(crate) fn aggregate_node<D: PointCloud, P: Mergeable<D>>(
+    my_node: &CoverNode<D>,
+    my_tree: &CoverTreeReader<D>,
+) -> Option<P> {
+    let mut own_points = my_node.singletons().to_vec();
+    if my_node.is_leaf() {
+        own_points.push(*my_node.center_index());
+    }
+    let mut value = if own_points.is_empty() {
+        None
+    } else {
+        P::from_points(&own_points, &my_tree.parameters().point_cloud)
+    };
+
+    if let Some((nested_scale, child_addresses)) = my_node.children() {
+        let nested_address = (nested_scale, *my_node.center_index());
+        for ca in std::iter::once(&nested_address).chain(child_addresses) {
+            my_tree.get_node_plugin_and::<P, _, _>(*ca, |p| match value.as_mut() {
+                Some(v) => v.merge(p),
+                None => value = Some(p.clone()),
+            });
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[derive(Debug, Clone)]
+    struct PointCount {
+        count: usize,
+    }
+
+    impl<D: PointCloud> NodePlugin<D> for PointCount {}
+
+    impl<D: PointCloud> Mergeable<D> for PointCount {
+        fn from_points(point_indexes: &[usize], _point_cloud: &D) -> Option<Self> {
+            Some(PointCount {
+                count: point_indexes.len(),
+            })
+        }
+        fn merge(&mut self, other: &Self) {
+            self.count += other.count;
+        }
+    }
+
+    #[test]
+    fn aggregate_matches_coverage() {
+        let mut tree = build_basic_tree();
+        tree.add_aggregate_plugin::<PointCount>();
+        let reader = tree.reader();
+        let root_count = reader
+            .get_node_plugin_and::<PointCount, _, _>(reader.root_address(), |p| p.count)
+            .unwrap();
+        assert_eq!(root_count, reader.parameters().point_cloud.len());
+        for (_si, layer) in reader.layers() {
+            layer.for_each_node(|_pi, n| {
+                let count = n.get_plugin_and::<PointCount, _, _>(|p| p.count).unwrap();
+                assert_eq!(count, n.coverage_count());
+            });
+        }
+    }
+}
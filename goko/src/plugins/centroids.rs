@@ -0,0 +1,240 @@
+//! # Centroids
+//!
+//! Keeps the mean of the points each node covers, and optionally a medoid, a covered point close to that mean. These
+//! are computed bottom up like the recursive gaussians, a node sums its own points and the sums of its children. The
+//! medoid is picked from the node's own points and the medoids of its children, whichever is closest to the node's
+//! centroid, so it's an approximation that costs a handful of distance calls per node rather than a scan of the
+//! coverage. The distances to the centroid are euclidean, whatever the tree's metric is.
+//!
+//! Read them with [`CoverTreeReader::node_centroid`] and [`CoverTreeReader::node_medoid`].
+
+use super::*;
+use crate::covertree::node::CoverNode;
+use crate::covertree::CoverTreeReader;
+use pointcloud::PointRef;
+
+/// The sum and count of the points a node covers, and the medoid if the plugin keeps one.
+#[derive(Debug, Clone, Default)]
+pub struct NodeCentroid {
+    moment1: Vec<f32>,
+    count: usize,
+    medoid: Option<usize>,
+}
+
+impl NodeCentroid {
+    /// The mean of the covered points, `None` if the node covers no points.
+    pub fn centroid(&self) -> Option<Vec<f32>> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(
+                self.moment1
+                    .iter()
+                    .map(|x| x / (self.count as f32))
+                    .collect(),
+            )
+        }
+    }
+
+    /// The index of the covered point closest to the centroid that was found, see the module docs.
+    pub fn medoid(&self) -> Option<usize> {
+        self.medoid
+    }
+
+    /// The number of points the centroid is over.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    fn add_point<T: PointRef>(&mut self, point: &T) {
+        if self.moment1.is_empty() {
+            self.moment1 = point.dense_iter().collect();
+        } else {
+            self.moment1
+                .iter_mut()
+                .zip(point.dense_iter())
+                .for_each(|(m, x)| *m += x);
+        }
+        self.count += 1;
+    }
+
+    fn remove_point<T: PointRef>(&mut self, point: &T) {
+        self.moment1
+            .iter_mut()
+            .zip(point.dense_iter())
+            .for_each(|(m, x)| *m -= x);
+        self.count = self.count.saturating_sub(1);
+    }
+
+    fn merge(&mut self, other: &NodeCentroid) {
+        if self.moment1.is_empty() {
+            self.moment1 = other.moment1.clone();
+        } else {
+            self.moment1
+                .iter_mut()
+                .zip(&other.moment1)
+                .for_each(|(m, x)| *m += x);
+        }
+        self.count += other.count;
+    }
+
+    /// Sets the medoid to the candidate closest to the current centroid.
+    fn pick_medoid<D: PointCloud>(&mut self, candidates: &[usize], point_cloud: &D) {
+        let centroid = match self.centroid() {
+            Some(centroid) => centroid,
+            None => return,
+        };
+        let mut best: Option<(f32, usize)> = None;
+        for pi in candidates {
+            if let Ok(point) = point_cloud.point(*pi) {
+                let dist: f32 = point
+                    .dense_iter()
+                    .zip(&centroid)
+                    .map(|(x, c)| (x - c) * (x - c))
+                    .sum();
+                if best.map(|(d, _)| dist < d).unwrap_or(true) {
+                    best = Some((dist, *pi));
+                }
+            }
+        }
+        self.medoid = best.map(|(_, pi)| pi);
+    }
+}
+
+impl<D: PointCloud> NodePlugin<D> for NodeCentroid {
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<Self>() + self.moment1.capacity() * std::mem::size_of::<f32>()
+    }
+}
+
+/// Attaches a [`NodeCentroid`] to every node.
+#[derive(Debug, Clone, Default)]
+pub struct CentroidPlugin {
+    /// If the nodes keep a medoid as well as the centroid
+    pub medoid: bool,
+}
+
+impl CentroidPlugin {
+    /// Keeps just the centroids.
+    pub fn new() -> CentroidPlugin {
+        CentroidPlugin { medoid: false }
+    }
+
+    /// Keeps the centroids and the medoids.
+    pub fn with_medoid() -> CentroidPlugin {
+        CentroidPlugin { medoid: true }
+    }
+}
+
+impl<D: PointCloud> GokoPlugin<D> for CentroidPlugin {
+    type NodeComponent = NodeCentroid;
+    fn node_component(
+        parameters: &Self,
+        my_node: &CoverNode<D>,
+        my_tree: &CoverTreeReader<D>,
+    ) -> Option<Self::NodeComponent> {
+        let point_cloud = &my_tree.parameters().point_cloud;
+        let mut candidates: Vec<usize> = my_node.singletons().to_vec();
+        if my_node.is_leaf() {
+            candidates.push(*my_node.center_index());
+        }
+        let mut my_centroid = NodeCentroid::default();
+        for pi in &candidates {
+            my_centroid.add_point(&point_cloud.point(*pi).ok()?);
+        }
+        if let Some((nested_scale, child_addresses)) = my_node.children() {
+            let nested_address = (nested_scale, *my_node.center_index());
+            for ca in std::iter::once(&nested_address).chain(child_addresses) {
+                my_tree.get_node_plugin_and::<Self::NodeComponent, _, _>(*ca, |p| {
+                    my_centroid.merge(p);
+                    candidates.extend(p.medoid);
+                })?;
+            }
+        }
+        if parameters.medoid {
+            my_centroid.pick_medoid(&candidates, point_cloud.as_ref());
+        }
+        Some(my_centroid)
+    }
+
+    /// The new point replaces the medoid if it's closer to the updated centroid.
+    fn on_insert(
+        parameters: &Self,
+        node_component: &mut Self::NodeComponent,
+        point_index: usize,
+        _child: Option<NodeAddress>,
+        point_cloud: &D,
+    ) {
+        if let Ok(point) = point_cloud.point(point_index) {
+            node_component.add_point(&point);
+        }
+        if parameters.medoid {
+            let mut candidates = vec![point_index];
+            candidates.extend(node_component.medoid);
+            node_component.pick_medoid(&candidates, point_cloud);
+        }
+    }
+
+    /// If the removed point was the medoid the node is left without one, reattach the plugin to pick a new one.
+    fn on_remove(
+        _parameters: &Self,
+        node_component: &mut Self::NodeComponent,
+        point_index: usize,
+        _child: Option<NodeAddress>,
+        point_cloud: &D,
+    ) {
+        if let Ok(point) = point_cloud.point(point_index) {
+            node_component.remove_point(&point);
+        }
+        if node_component.medoid == Some(point_index) {
+            node_component.medoid = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn centroids_match_the_coverage() {
+        let basic_tree_data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<CentroidPlugin>(CentroidPlugin::with_medoid());
+        let reader = tree.reader();
+        let root_centroid = reader.node_centroid(reader.root_address()).unwrap();
+        let mean = basic_tree_data.iter().sum::<f32>() / basic_tree_data.len() as f32;
+        assert_approx_eq!(root_centroid[0], mean);
+        let root_medoid = reader.node_medoid(reader.root_address()).unwrap();
+        assert!(root_medoid < basic_tree_data.len());
+
+        for (_si, layer) in reader.layers() {
+            layer.for_each_node(|_pi, n| {
+                n.get_plugin_and::<NodeCentroid, _, _>(|p| {
+                    assert_eq!(p.count(), n.coverage_count());
+                    assert!(p.medoid().is_some());
+                })
+                .unwrap();
+            });
+        }
+    }
+
+    #[test]
+    fn centroids_take_in_inserts() {
+        let mut tree = build_basic_tree();
+        let parameters = CentroidPlugin::with_medoid();
+        tree.add_plugin::<CentroidPlugin>(parameters.clone());
+        let reader = tree.reader();
+        let point_cloud = &reader.parameters().point_cloud;
+        let mut centroid = reader
+            .get_node_plugin_and::<NodeCentroid, _, _>(reader.root_address(), |p| p.clone())
+            .unwrap();
+        GokoPlugin::on_remove(&parameters, &mut centroid, 3, None, point_cloud.as_ref());
+        assert_eq!(centroid.count(), 4);
+        assert_approx_eq!(centroid.centroid().unwrap()[0], (0.499 + 0.49 + 0.48) / 4.0);
+        GokoPlugin::on_insert(&parameters, &mut centroid, 2, None, point_cloud.as_ref());
+        assert_eq!(centroid.count(), 5);
+        assert_eq!(centroid.medoid(), Some(2));
+    }
+}
@@ -3,8 +3,9 @@
 use crate::plugins::discrete::tracker::*;
 use crate::*;
 use rand::prelude::*;
-use rand::thread_rng;
-use rayon::iter::repeatn;
+use serde::{Deserialize, Serialize};
+use statrs::function::erf::erf;
+use std::sync::atomic::{self, AtomicUsize};
 
 /// Trains a baseline by sampling randomly from the training set (used to create the tree)
 /// This baseline is _not_ realistic.
@@ -37,7 +38,8 @@ impl DirichletBaseline {
     pub fn set_sequence_len(&mut self, sequence_len: usize) {
         self.sequence_len = sequence_len;
     }
-    /// Sets a new count of sequences to train over, default 100. Stats for each sequence are returned.
+    /// Sets a new count of sequences to train over, default 8. Stats for each sequence are returned. Training with
+    /// no sequences is an error.
     pub fn set_num_sequences(&mut self, num_sequences: usize) {
         self.num_sequences = num_sequences;
     }
@@ -49,16 +51,36 @@ impl DirichletBaseline {
     pub fn set_observation_weight(&mut self, observation_weight: f64) {
         self.observation_weight = observation_weight;
     }
-    /// Samples at the following rate, then interpolates for sequence lengths between the following.
+    /// Samples at the following rate, then interpolates for sequence lengths between the following. A rate of 0 is an
+    /// error when training.
     pub fn set_sample_rate(&mut self, sample_rate: usize) {
         self.sample_rate = sample_rate;
     }
 
-    /// Trains the sequences up.
+    /// Trains the sequences up. Each sequence is drawn from its own stream of the tree's rng, so this is
+    /// reproducible if the tree has an `rng_seed`.
     pub fn train<D: PointCloud>(
         &self,
         reader: CoverTreeReader<D>,
     ) -> GokoResult<KLDivergenceBaseline> {
+        self.train_with_progress(reader, &AtomicUsize::new(0))
+    }
+
+    /// Trains the sequences up like [`DirichletBaseline::train`], adding one to `progress` as each sequence is
+    /// finished. Another thread can watch it to see how far along the training is.
+    pub fn train_with_progress<D: PointCloud>(
+        &self,
+        reader: CoverTreeReader<D>,
+        progress: &AtomicUsize,
+    ) -> GokoResult<KLDivergenceBaseline> {
+        if self.num_sequences == 0 {
+            return Err(GokoError::InvalidBaseline(
+                "there are no sequences to train on",
+            ));
+        }
+        if self.sample_rate == 0 {
+            return Err(GokoError::InvalidBaseline("the sample rate is 0"));
+        }
         let point_indexes = reader.point_cloud().reference_indexes();
         let sequence_len = if self.sequence_len == 0 {
             point_indexes.len()
@@ -66,14 +88,18 @@ impl DirichletBaseline {
             self.sequence_len
         };
 
-        let results: Vec<Vec<KLDivergenceStats>> = repeatn(reader, self.num_sequences)
-            .map(|reader| {
+        let results: Vec<Vec<KLDivergenceStats>> = (0..self.num_sequences)
+            .into_par_iter()
+            .map_with(reader, |reader, sequence| {
+                let mut rng = reader
+                    .parameters()
+                    .stream_rng(RngDomain::Baseline, sequence as u64);
                 let mut tracker = BayesCategoricalTracker::new(
                     0,
-                    reader,
+                    reader.clone(),
                 );
-                (&point_indexes[..])
-                    .choose_multiple(&mut thread_rng(), sequence_len)
+                let stats: Vec<KLDivergenceStats> = (&point_indexes[..])
+                    .choose_multiple(&mut rng, sequence_len)
                     .enumerate()
                     .filter_map(|(i, pi)| {
                         tracker.add_path(tracker.reader().known_path(*pi).unwrap());
@@ -83,7 +109,9 @@ impl DirichletBaseline {
                             None
                         }
                     })
-                    .collect()
+                    .collect();
+                progress.fetch_add(1, atomic::Ordering::Relaxed);
+                stats
             })
             .collect();
         let len = results[0].len();
@@ -108,7 +136,7 @@ impl DirichletBaseline {
 }
 
 /// Tracks the non-zero (all KL divergences above 1e-10)
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct KLDivergenceBaselineStats {
     /// The maximum non-zero KL divergence
     pub max: (f64, f64),
@@ -178,6 +206,7 @@ impl KLDivergenceBaselineStats {
 }
 
 /// Computing the KL div of each node's prior and posterior is expensive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KLDivergenceBaseline {
     /// The number of sequences we're have the stats from
     pub num_sequences: usize,
@@ -188,13 +217,16 @@ pub struct KLDivergenceBaseline {
 }
 
 impl KLDivergenceBaseline {
-    /// Gets the stats object that stores an approximate mean and variance of the samples.
+    /// Gets the stats object that stores an approximate mean and variance of the samples. Past the last sampled
+    /// length this extrapolates from the last two samples, or gives the last one if only one was taken.
     pub fn stats(&self, i: usize) -> KLDivergenceBaselineStats {
         match self.sequence_len.binary_search(&i) {
             Ok(index) => self.stats[index].to_mean_var(self.num_sequences as f64),
             Err(index) => {
                 if index == 0 {
                     KLDivergenceBaselineStats::default()
+                } else if index == 1 && self.sequence_len.len() == 1 {
+                    self.stats[0].to_mean_var(self.num_sequences as f64)
                 } else if index == self.sequence_len.len() {
                     let stats1 = self.stats[index - 2].to_mean_var(self.num_sequences as f64);
                     let stats2 = self.stats[index - 1].to_mean_var(self.num_sequences as f64);
@@ -211,4 +243,108 @@ impl KLDivergenceBaseline {
             }
         }
     }
+
+    /// Scores the stats of a tracker against the baseline at the same sequence length, see [`KLDivergenceScores`].
+    pub fn score(&self, stats: &KLDivergenceStats) -> KLDivergenceScores {
+        let baseline = self.stats(stats.sequence_len);
+        KLDivergenceScores {
+            max: FieldScore::new(stats.max, baseline.max),
+            min: FieldScore::new(stats.min, baseline.min),
+            nz_count: FieldScore::new(stats.nz_count as f64, baseline.nz_count),
+            moment1_nz: FieldScore::new(stats.moment1_nz, baseline.moment1_nz),
+            moment2_nz: FieldScore::new(stats.moment2_nz, baseline.moment2_nz),
+            sequence_len: stats.sequence_len,
+        }
+    }
+}
+
+/// Where one field of a tracker's stats falls in the baseline's distribution of that field.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FieldScore {
+    /// The number of baseline standard deviations the value is above the baseline mean
+    pub z_score: f64,
+    /// The percentage of baseline sequences expected to have a smaller value, assuming the field is normally
+    /// distributed over the baseline's sequences
+    pub percentile: f64,
+}
+
+impl FieldScore {
+    /// Scores a value against a `(mean, variance)` pair. A field with no variance in the baseline scores 0 and the
+    /// 50th percentile, there's nothing to rank it against.
+    pub fn new(value: f64, (mean, var): (f64, f64)) -> FieldScore {
+        let std_dev = var.max(0.0).sqrt();
+        let z_score = if std_dev > 1.0e-12 {
+            (value - mean) / std_dev
+        } else {
+            0.0
+        };
+        FieldScore {
+            z_score,
+            percentile: 50.0 * (1.0 + erf(z_score / std::f64::consts::SQRT_2)),
+        }
+    }
+}
+
+/// The stats of a tracker normalized against a [`KLDivergenceBaseline`]. The raw KL divergences depend on the tree
+/// and the length of the sequence, these are comparable across both. A z score of 3 or a percentile near 100 on
+/// `moment1_nz` means the queries are drifting away from the baseline.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct KLDivergenceScores {
+    /// The score of the maximum non-zero KL divergence
+    pub max: FieldScore,
+    /// The score of the minimum non-zero KL divergence
+    pub min: FieldScore,
+    /// The score of the number of nodes that have a non-zero divergence
+    pub nz_count: FieldScore,
+    /// The score of the first moment
+    pub moment1_nz: FieldScore,
+    /// The score of the second moment
+    pub moment2_nz: FieldScore,
+    /// The sequence length the baseline was read at
+    pub sequence_len: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+    use crate::plugins::discrete::prelude::*;
+
+    #[test]
+    fn field_score_ranks_against_baseline() {
+        let at_mean = FieldScore::new(2.0, (2.0, 4.0));
+        assert_approx_eq!(at_mean.z_score, 0.0);
+        assert_approx_eq!(at_mean.percentile, 50.0);
+
+        let above = FieldScore::new(4.0, (2.0, 4.0));
+        assert_approx_eq!(above.z_score, 1.0);
+        assert_approx_eq!(above.percentile, 84.134474, 1.0e-5);
+
+        let flat = FieldScore::new(4.0, (2.0, 0.0));
+        assert_approx_eq!(flat.z_score, 0.0);
+        assert_approx_eq!(flat.percentile, 50.0);
+    }
+
+    #[test]
+    fn sparse_samples_score() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        // The default rate of 100 samples once over the 5 points
+        let mut trainer = DirichletBaseline::default();
+        trainer.set_num_sequences(3);
+        let baseline = trainer.train(tree.reader()).unwrap();
+        assert_eq!(baseline.sequence_len, vec![1]);
+
+        let mut tracker = BayesCategoricalTracker::new(0, tree.reader());
+        for pi in 0..4 {
+            tracker.add_path(tree.reader().known_path(pi).unwrap());
+        }
+        let score = baseline.score(&tracker.kl_div_stats());
+        assert_eq!(score.sequence_len, 4);
+        let last = baseline.stats(1);
+        assert_approx_eq!(baseline.stats(4).moment1_nz.0, last.moment1_nz.0);
+
+        trainer.set_num_sequences(0);
+        assert!(trainer.train(tree.reader()).is_err());
+    }
 }
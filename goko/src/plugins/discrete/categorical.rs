@@ -9,9 +9,10 @@ use crate::plugins::*;
 
 use rand::distributions::{Distribution, Uniform};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 /// Simple probability density function for where things go by count
 /// Stored as a flat vector in the order of the node addresses.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Categorical {
     pub(crate) child_counts: Vec<(NodeAddress, f64)>,
     pub(crate) singleton_count: f64,
@@ -58,6 +59,13 @@ impl Categorical {
         self.add_child_pop(None, other.singleton_count);
     }
 
+    pub(crate) fn scale(&mut self, factor: f64) {
+        for (_, c) in self.child_counts.iter_mut() {
+            *c *= factor;
+        }
+        self.singleton_count *= factor;
+    }
+
     pub(crate) fn add_child_pop(&mut self, loc: Option<NodeAddress>, count: f64) {
         match loc {
             Some(ca) => match self.child_counts.binary_search_by_key(&ca, |&(a, _)| a) {
@@ -149,7 +157,12 @@ impl Categorical {
     }
 }
 
-impl<D: PointCloud> NodePlugin<D> for Categorical {}
+impl<D: PointCloud> NodePlugin<D> for Categorical {
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.child_counts.capacity() * std::mem::size_of::<(NodeAddress, f64)>()
+    }
+}
 
 /// Zero sized type that can be passed around. Equivilant to `()`
 #[derive(Debug, Clone)]
@@ -11,22 +11,31 @@
 //! attackers has been proven.
 
 use crate::covertree::node::CoverNode;
-use crate::covertree::CoverTreeReader;
+use crate::covertree::{CoverTreeReader, RngDomain};
 use crate::plugins::*;
 
 use rand::prelude::*;
 use statrs::function::gamma::{digamma, ln_gamma};
 
 use rand::distributions::{Distribution, Uniform};
+use serde::{de, Deserialize, Deserializer, Serialize};
 
 use super::categorical::*;
 
 /// Simple probability density function for where things go by count
 ///
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Dirichlet {
     child_counts: Vec<(NodeAddress, f64)>,
     singleton_count: f64,
+    // What a point added to or removed from the tree is worth, see `PriorStrategy::point_weight`
+    point_weight: f64,
+}
+
+impl Default for Dirichlet {
+    fn default() -> Dirichlet {
+        Dirichlet::new()
+    }
 }
 
 impl Dirichlet {
@@ -35,6 +44,7 @@ impl Dirichlet {
         Dirichlet {
             child_counts: Vec::new(),
             singleton_count: 0.0,
+            point_weight: 1.0,
         }
     }
     /// Multiplies all parameters by this weight
@@ -92,6 +102,12 @@ impl Dirichlet {
         }
     }
 
+    fn has_child(&self, ca: &NodeAddress) -> bool {
+        self.child_counts
+            .binary_search_by_key(ca, |&(a, _)| a)
+            .is_ok()
+    }
+
     fn remove_child_pop(&mut self, loc: Option<NodeAddress>, count: f64) {
         match loc {
             Some(ca) => {
@@ -180,11 +196,14 @@ impl Dirichlet {
         Some(ax.ln() - self.total().ln())
     }
 
-    /// Samples from the expected PDF of the Dirichlet distribution
+    /// Samples from the expected PDF of the Dirichlet distribution. The parameters don't have to be whole counts,
+    /// see [`PriorStrategy`]. Returns `None` for the singletons, or if the parameters are all 0.
     pub fn sample<R: Rng>(&self, rng: &mut R) -> Option<NodeAddress> {
-        let sum = self.total() as usize;
-        let uniform = Uniform::from(0..sum);
-        let sample = uniform.sample(rng) as f64;
+        let total = self.total();
+        if !(total.is_finite() && total > 0.0) {
+            return None;
+        }
+        let sample = Uniform::new(0.0, total).sample(rng);
 
         let mut count = 0.0;
         for (a, c) in &self.child_counts {
@@ -233,47 +252,218 @@ impl Dirichlet {
     }
 }
 
-impl<D: PointCloud> NodePlugin<D> for Dirichlet {}
+impl<D: PointCloud> NodePlugin<D> for Dirichlet {
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.child_counts.capacity() * std::mem::size_of::<(NodeAddress, f64)>()
+    }
+}
+
+/// How [`GokoDirichlet`] turns the points a node covers into the node's prior. The prior has a parameter for each
+/// child and one for the singletons. The larger the parameters, the more queries it takes to move the posterior, so a
+/// heavy prior is slower to report drift and raises fewer false alarms.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PriorStrategy {
+    /// Each child's parameter is the number of points it covers, and the singletons' is the number of singletons.
+    /// A leaf counts its center as a singleton.
+    Counts,
+    /// Every child and the singletons get the same parameter, no matter how many points they cover. This only keeps
+    /// the shape of the tree, a query landing in a sparse child is as likely as one landing in a dense child.
+    Uniform {
+        /// The parameter of each child and of the singletons
+        weight: f64,
+    },
+    /// The counts plus a pseudo-count. The small children, and the singletons of a node that has none, aren't as
+    /// unlikely as the counts alone make them.
+    PseudoCount {
+        /// Added to each child's count and to the singletons' count
+        pseudo_count: f64,
+    },
+    /// The counts times `scale_base^(exponent * scale_index)` of the node. With a positive exponent the coarse nodes
+    /// near the root get confident priors and the fine nodes weak ones, so drift shows up at the fine scales first.
+    /// A negative exponent does the opposite.
+    ScaleWeighted {
+        /// The power of the scale base, per scale index
+        exponent: f64,
+    },
+}
+
+impl Default for PriorStrategy {
+    fn default() -> PriorStrategy {
+        PriorStrategy::Counts
+    }
+}
+
+impl PriorStrategy {
+    /// Errors if a parameter is infinite or NaN, or if a weight or pseudo-count isn't positive. A zero weight would
+    /// give the children no mass at all.
+    pub fn check(&self) -> GokoResult<()> {
+        match self {
+            PriorStrategy::Counts => Ok(()),
+            PriorStrategy::Uniform { weight } => {
+                if weight.is_finite() && *weight > 0.0 {
+                    Ok(())
+                } else {
+                    Err(GokoError::InvalidPrior(
+                        "the weight isn't a positive number",
+                    ))
+                }
+            }
+            PriorStrategy::PseudoCount { pseudo_count } => {
+                if pseudo_count.is_finite() && *pseudo_count > 0.0 {
+                    Ok(())
+                } else {
+                    Err(GokoError::InvalidPrior(
+                        "the pseudo-count isn't a positive number",
+                    ))
+                }
+            }
+            PriorStrategy::ScaleWeighted { exponent } => {
+                if exponent.is_finite() {
+                    Ok(())
+                } else {
+                    Err(GokoError::InvalidPrior("the exponent isn't finite"))
+                }
+            }
+        }
+    }
+
+    /// The parameter of a child or of the singletons that doesn't depend on the points it covers.
+    fn base(&self) -> f64 {
+        match self {
+            PriorStrategy::Uniform { weight } => *weight,
+            PriorStrategy::PseudoCount { pseudo_count } => *pseudo_count,
+            PriorStrategy::Counts | PriorStrategy::ScaleWeighted { .. } => 0.0,
+        }
+    }
+
+    /// What each point a child or the singletons cover adds to its parameter.
+    fn point_weight(&self, scale_index: i32, scale_base: f32) -> f64 {
+        match self {
+            PriorStrategy::Counts | PriorStrategy::PseudoCount { .. } => 1.0,
+            PriorStrategy::Uniform { .. } => 0.0,
+            PriorStrategy::ScaleWeighted { exponent } => {
+                (scale_base as f64).powf(exponent * scale_index as f64)
+            }
+        }
+    }
+}
 
 /// Stores the log probabilities for each node in the tree.
 ///
-/// This is the probability that when you sample from the tree you end up at a particular node.
-#[derive(Debug, Clone, Default)]
+/// This is the probability that when you sample from the tree you end up at a particular node. The `prior` picks
+/// how the points under each node become its Dirichlet prior, the default uses the counts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GokoDirichlet {
-    // probability that you'd pass thru this node.
-//pub cond_ln_probs: HashMap<NodeAddress,f64>,
+    /// How the prior of each node is built from the points it covers. A saved strategy is checked when it's loaded.
+    #[serde(default, deserialize_with = "checked_prior")]
+    pub prior: PriorStrategy,
+}
+
+fn checked_prior<'de, De: Deserializer<'de>>(deserializer: De) -> Result<PriorStrategy, De::Error> {
+    let prior = PriorStrategy::deserialize(deserializer)?;
+    prior.check().map_err(de::Error::custom)?;
+    Ok(prior)
+}
+
+impl GokoDirichlet {
+    /// A plugin that builds the priors with this strategy. Errors if the strategy's parameters don't make a prior,
+    /// see [`PriorStrategy::check`].
+    pub fn with_prior(prior: PriorStrategy) -> GokoResult<GokoDirichlet> {
+        prior.check()?;
+        Ok(GokoDirichlet { prior })
+    }
+
+    /// Samples a path down the tree, picking each child with the probability the Dirichlet of its parent gives it.
+    /// The walk ends when it picks a singleton or reaches a leaf, so the last address is the node the sample landed
+    /// in. The randomness comes from `stream` of the tree's rng, see [`crate::CoverTreeParameters::stream_rng`], so
+    /// with an `rng_seed` the same stream always gives the same path.
+    ///
+    /// Returns an empty path if the tree doesn't have this plugin.
+    pub fn marginal_sample<D: PointCloud>(
+        reader: &CoverTreeReader<D>,
+        stream: u64,
+    ) -> Vec<NodeAddress> {
+        let mut rng = reader.parameters().stream_rng(RngDomain::Sampling, stream);
+        let mut path = Vec::new();
+        let mut current = Some(reader.root_address());
+        while let Some(address) = current {
+            current = reader
+                .get_node_plugin_and::<Dirichlet, _, _>(address, |dirichlet| {
+                    path.push(address);
+                    if dirichlet.total() >= 1.0 {
+                        dirichlet.sample(&mut rng)
+                    } else {
+                        None
+                    }
+                })
+                .flatten();
+        }
+        path
+    }
 }
 
 /// Parent trait that make this all work. Ideally this should be included in the `TreePlugin` but rust doesn't like it.
 impl<D: PointCloud> GokoPlugin<D> for GokoDirichlet {
     type NodeComponent = Dirichlet;
     fn node_component(
-        _parameters: &Self,
+        parameters: &Self,
         my_node: &CoverNode<D>,
         my_tree: &CoverTreeReader<D>,
     ) -> Option<Self::NodeComponent> {
+        let base = parameters.prior.base();
+        let point_weight = parameters
+            .prior
+            .point_weight(*my_node.scale_index(), my_tree.parameters().scale_base);
         let mut bucket = Dirichlet::new();
+        bucket.point_weight = point_weight;
 
-        // If we're a routing node then grab the childen's values
+        // If we're a routing node then grab the childen's coverage
         if let Some((nested_scale, child_addresses)) = my_node.children() {
-            my_tree.get_node_plugin_and::<Self::NodeComponent, _, _>(
-                (nested_scale, *my_node.center_index()),
-                |p| {
-                    bucket.add_child_pop(Some((nested_scale, *my_node.center_index())), p.total());
-                },
-            );
-            for ca in child_addresses {
-                my_tree.get_node_plugin_and::<Self::NodeComponent, _, _>(*ca, |p| {
-                    bucket.add_child_pop(Some(*ca), p.total());
-                });
+            let nested_address = (nested_scale, *my_node.center_index());
+            for ca in std::iter::once(&nested_address).chain(child_addresses) {
+                if let Some(count) = my_tree.get_node_and(*ca, |n| n.coverage_count()) {
+                    bucket.add_child_pop(Some(*ca), base + point_weight * count as f64);
+                }
             }
-            bucket.add_child_pop(None, my_node.singletons_len() as f64);
+            bucket.add_child_pop(None, base + point_weight * my_node.singletons_len() as f64);
         } else {
-            bucket.add_child_pop(None, (my_node.singletons_len() + 1) as f64);
+            bucket.add_child_pop(
+                None,
+                base + point_weight * (my_node.singletons_len() + 1) as f64,
+            );
         }
         Some(bucket)
     }
 
+    fn on_insert(
+        parameters: &Self,
+        node_component: &mut Self::NodeComponent,
+        _point_index: usize,
+        child: Option<NodeAddress>,
+        _point_cloud: &D,
+    ) {
+        // A child that's new to the node gets the part of the prior that doesn't come from its points
+        if let Some(ca) = child {
+            if !node_component.has_child(&ca) {
+                node_component.add_child_pop(child, parameters.prior.base());
+            }
+        }
+        let point_weight = node_component.point_weight;
+        node_component.add_child_pop(child, point_weight);
+    }
+
+    fn on_remove(
+        _parameters: &Self,
+        node_component: &mut Self::NodeComponent,
+        _point_index: usize,
+        child: Option<NodeAddress>,
+        _point_cloud: &D,
+    ) {
+        let point_weight = node_component.point_weight;
+        node_component.remove_child_pop(child, point_weight);
+    }
+
     /*
     fn tree_component(parameters: &mut Self, my_tree: &mut CoverTreeWriter<D>) {
         let mut unvisited_nodes = vec![my_tree.root_address];
@@ -296,7 +486,8 @@ impl<D: PointCloud> GokoPlugin<D> for GokoDirichlet {
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
-    //use crate::covertree::tests::build_basic_tree;
+    use crate::covertree::tests::build_basic_tree;
+    use rand::rngs::SmallRng;
 
     #[test]
     fn dirichlet_sanity_test() {
@@ -429,4 +620,133 @@ pub(crate) mod tests {
         }).unwrap());
     }
     */
+
+    #[test]
+    fn marginal_sample_is_reproducible() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let reader = tree.reader();
+        for stream in 0..10 {
+            let path = GokoDirichlet::marginal_sample(&reader, stream);
+            assert_eq!(path[0], reader.root_address());
+            assert_eq!(path, GokoDirichlet::marginal_sample(&reader, stream));
+        }
+    }
+
+    #[test]
+    fn sample_follows_the_parameters() {
+        let priors = [
+            PriorStrategy::Counts,
+            PriorStrategy::Uniform { weight: 0.25 },
+            PriorStrategy::PseudoCount { pseudo_count: 0.5 },
+            PriorStrategy::ScaleWeighted { exponent: 1.0 },
+            PriorStrategy::ScaleWeighted { exponent: -1.0 },
+        ];
+        let mut rng = SmallRng::seed_from_u64(0);
+        for prior in &priors {
+            let mut tree = build_basic_tree();
+            tree.add_plugin::<GokoDirichlet>(GokoDirichlet::with_prior(*prior).unwrap());
+            let reader = tree.reader();
+            let dirichlet = reader
+                .get_node_plugin_and::<Dirichlet, _, _>(reader.root_address(), |d| d.clone())
+                .unwrap();
+
+            let draws = 20000;
+            let mut singletons = 0;
+            let mut children = vec![0; dirichlet.child_counts.len()];
+            for _ in 0..draws {
+                match dirichlet.sample(&mut rng) {
+                    Some(ca) => {
+                        let i = dirichlet
+                            .child_counts
+                            .iter()
+                            .position(|(a, _)| *a == ca)
+                            .unwrap();
+                        children[i] += 1;
+                    }
+                    None => singletons += 1,
+                }
+            }
+            for ((ca, _), count) in dirichlet.child_counts.iter().zip(&children) {
+                let expected = dirichlet.ln_pdf(Some(ca)).unwrap().exp();
+                assert_approx_eq!(*count as f64 / draws as f64, expected, 0.02);
+            }
+            let expected = dirichlet.singleton_count / dirichlet.total();
+            assert_approx_eq!(singletons as f64 / draws as f64, expected, 0.02);
+        }
+        assert!(Dirichlet::new().sample(&mut rng).is_none());
+    }
+
+    #[test]
+    fn prior_strategies() {
+        let root_prior = |prior: PriorStrategy| {
+            let mut tree = build_basic_tree();
+            tree.add_plugin::<GokoDirichlet>(GokoDirichlet::with_prior(prior).unwrap());
+            let reader = tree.reader();
+            let root = reader.root_address();
+            let coverage = reader.get_node_and(root, |n| n.coverage_count()).unwrap();
+            let dirichlet = reader
+                .get_node_plugin_and::<Dirichlet, _, _>(root, |d| d.clone())
+                .unwrap();
+            (coverage, dirichlet, root.0)
+        };
+
+        let (coverage, counts, _) = root_prior(PriorStrategy::Counts);
+        assert_approx_eq!(counts.total(), coverage as f64);
+
+        let (_, uniform, _) = root_prior(PriorStrategy::Uniform { weight: 2.0 });
+        let categories = uniform.child_counts.len() + 1;
+        assert_approx_eq!(uniform.total(), 2.0 * categories as f64);
+        assert!(uniform.child_counts.iter().all(|(_, c)| *c == 2.0));
+
+        let (_, pseudo, _) = root_prior(PriorStrategy::PseudoCount { pseudo_count: 0.5 });
+        assert_approx_eq!(pseudo.total(), coverage as f64 + 0.5 * categories as f64);
+        assert!(pseudo.ln_pdf(None).unwrap().is_finite());
+
+        let (_, scaled, scale_index) = root_prior(PriorStrategy::ScaleWeighted { exponent: 1.0 });
+        let weight = 2.0f64.powi(scale_index);
+        assert_approx_eq!(scaled.total(), weight * coverage as f64);
+        for ((ca, c), (_, s)) in counts.child_counts.iter().zip(&scaled.child_counts) {
+            assert_approx_eq!(*s, weight * c, 1.0e-9);
+            assert_approx_eq!(
+                scaled.ln_pdf(Some(ca)).unwrap(),
+                counts.ln_pdf(Some(ca)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn priors_are_checked() {
+        for prior in &[
+            PriorStrategy::Uniform { weight: 0.0 },
+            PriorStrategy::Uniform { weight: -1.0 },
+            PriorStrategy::Uniform { weight: f64::NAN },
+            PriorStrategy::PseudoCount { pseudo_count: 0.0 },
+            PriorStrategy::PseudoCount {
+                pseudo_count: f64::INFINITY,
+            },
+            PriorStrategy::ScaleWeighted {
+                exponent: f64::NEG_INFINITY,
+            },
+        ] {
+            assert!(matches!(
+                GokoDirichlet::with_prior(*prior),
+                Err(GokoError::InvalidPrior(..))
+            ));
+        }
+        assert!(GokoDirichlet::with_prior(PriorStrategy::ScaleWeighted { exponent: 0.0 }).is_ok());
+
+        let loaded: GokoDirichlet = serde_json::from_str("{}").unwrap();
+        assert_eq!(loaded.prior, PriorStrategy::Counts);
+        let loaded: GokoDirichlet =
+            serde_json::from_str(r#"{"prior": {"PseudoCount": {"pseudo_count": 0.5}}}"#).unwrap();
+        assert_eq!(
+            loaded.prior,
+            PriorStrategy::PseudoCount { pseudo_count: 0.5 }
+        );
+        assert!(serde_json::from_str::<GokoDirichlet>(
+            r#"{"prior": {"Uniform": {"weight": -2.0}}}"#
+        )
+        .is_err());
+    }
 }
@@ -0,0 +1,216 @@
+//! Tracks the labels of a stream of points as they land in the tree.
+//!
+//! The [`super::tracker::BayesCategoricalTracker`] only sees where the points go, so it can only detect covariate drift.
+//! If the incoming points come with a label (true, or predicted by some upstream model) we can also track the
+//! label distribution on each node of the path and compare it to the label summary the node was trained with.
+//! A change here is concept drift, the same region of space is being labeled differently.
+
+use crate::covertree::CoverTreeReader;
+use crate::plugins::*;
+use hashbrown::HashMap;
+use pointcloud::summaries::CategorySummary;
+
+use super::tracker::{CoverageWeighting, KLDivergenceStats};
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Windowed per-node label distributions for a stream of labeled points.
+///
+/// This needs the label summaries on the tree, see [`crate::CoverTreeWriter::generate_summaries`].
+pub struct LabelStreamTracker<D: PointCloud<LabelSummary = CategorySummary>> {
+    running_labels: HashMap<NodeAddress, CategorySummary>,
+    sequence_queue: VecDeque<(Vec<(f32, NodeAddress)>, i64)>,
+    sequence_count: usize,
+    window_size: usize,
+    reader: CoverTreeReader<D>,
+}
+
+impl<D: PointCloud<LabelSummary = CategorySummary>> fmt::Debug for LabelStreamTracker<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "LabelStreamTracker {{ sequence_queue: {:?}, window_size: {}, running_labels: {:?}}}",
+            self.sequence_queue, self.window_size, self.running_labels,
+        )
+    }
+}
+
+impl<D: PointCloud<LabelSummary = CategorySummary>> LabelStreamTracker<D> {
+    /// Creates a new blank tracker with a window of `window_size`, input 0 for unlimited.
+    pub fn new(window_size: usize, reader: CoverTreeReader<D>) -> LabelStreamTracker<D> {
+        LabelStreamTracker {
+            running_labels: HashMap::new(),
+            sequence_queue: VecDeque::new(),
+            sequence_count: 0,
+            window_size,
+            reader,
+        }
+    }
+
+    /// Adds a path along with the label of the point that produced it.
+    pub fn add_labeled_path(&mut self, trace: Vec<(f32, NodeAddress)>, label: i64) {
+        for (_, address) in trace.iter() {
+            self.running_labels
+                .entry(*address)
+                .or_default()
+                .add(&label);
+        }
+        self.sequence_count += 1;
+        if self.window_size != 0 {
+            self.sequence_queue.push_back((trace, label));
+
+            if self.sequence_queue.len() > self.window_size {
+                let (oldest, oldest_label) = self.sequence_queue.pop_front().unwrap();
+                self.remove_labeled_path(&oldest, oldest_label);
+            }
+        }
+    }
+
+    fn remove_labeled_path(&mut self, trace: &[(f32, NodeAddress)], label: i64) {
+        for (_, address) in trace.iter() {
+            let mut now_empty = false;
+            if let Some(summary) = self.running_labels.get_mut(address) {
+                for (stored_label, count) in summary.items.iter_mut() {
+                    if *stored_label == label {
+                        *count -= 1;
+                        break;
+                    }
+                }
+                summary.items.retain(|(_, count)| *count > 0);
+                now_empty = summary.items.is_empty();
+            }
+            if now_empty {
+                self.running_labels.remove(address);
+            }
+        }
+    }
+
+    /// The running label distributions
+    pub fn running_labels(&self) -> &HashMap<NodeAddress, CategorySummary> {
+        &self.running_labels
+    }
+
+    /// The lenght of the sequence
+    pub fn sequence_len(&self) -> usize {
+        if self.sequence_queue.is_empty() {
+            self.sequence_count
+        } else {
+            self.sequence_queue.len()
+        }
+    }
+
+    /// The KL divergence between the windowed label distribution of a node and the training label distribution
+    /// of that node, KL(window || training). The training distribution is smoothed with a pseudo-count of 1 for
+    /// every label seen in either distribution, so this is always defined.
+    ///
+    /// Returns None if the node doesn't exist, has no label summary, or the window hasn't touched it.
+    pub fn node_label_kl(&self, address: NodeAddress) -> Option<f64> {
+        let window = self.running_labels.get(&address)?;
+        let training = self.reader.get_node_label_summary(address)?;
+        label_kl_divergence(window, &training.summary)
+    }
+
+    /// Gives the per-node label KL divergence, with the node address
+    pub fn all_node_label_kl(&self) -> Vec<(f64, NodeAddress)> {
+        self.running_labels
+            .keys()
+            .filter_map(|address| self.node_label_kl(*address).map(|kl| (kl, *address)))
+            .collect()
+    }
+
+    /// A set of stats for the label divergences of the sequence, same format as the covariate drift stats.
+    pub fn label_kl_div_stats(&self) -> KLDivergenceStats {
+        self.label_kl_div_stats_weighted(CoverageWeighting::default())
+    }
+
+    /// A set of stats for the label divergences of the sequence, with the weighted fields computed with the given weighting.
+    pub fn label_kl_div_stats_weighted(&self, weighting: CoverageWeighting) -> KLDivergenceStats {
+        KLDivergenceStats::from_node_kls(
+            &self.all_node_label_kl(),
+            &self.reader,
+            weighting,
+            self.sequence_len(),
+        )
+    }
+
+    /// Easy access to the cover tree read head associated to this tracker
+    pub fn reader(&self) -> &CoverTreeReader<D> {
+        &self.reader
+    }
+}
+
+/// KL(window || training) where the training distribution gets a pseudo-count of 1 on the union of the supports.
+fn label_kl_divergence(window: &CategorySummary, training: &CategorySummary) -> Option<f64> {
+    let window_total = window.items.iter().map(|(_, c)| *c).sum::<usize>() as f64;
+    if window_total == 0.0 {
+        return None;
+    }
+    let mut support: Vec<i64> = window.items.iter().map(|(l, _)| *l).collect();
+    for (l, _) in training.items.iter() {
+        if !support.contains(l) {
+            support.push(*l);
+        }
+    }
+    let training_total = training.items.iter().map(|(_, c)| *c).sum::<usize>() as f64
+        + support.len() as f64;
+    let mut sum = 0.0;
+    for (label, count) in window.items.iter() {
+        let training_count = training
+            .items
+            .iter()
+            .find(|(l, _)| l == label)
+            .map(|(_, c)| *c as f64)
+            .unwrap_or(0.0)
+            + 1.0;
+        let p = *count as f64 / window_total;
+        sum += p * (p.ln() - (training_count / training_total).ln());
+    }
+    // for floating point errors, sometimes this is -0.000000001
+    if sum < 0.0 {
+        Some(0.0)
+    } else {
+        Some(sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn label_tracker_window_test() {
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        let reader = tree.reader();
+        let root = reader.root_address();
+        let mut tracker = LabelStreamTracker::new(2, reader);
+
+        tracker.add_labeled_path(vec![(0.0, root)], 0);
+        tracker.add_labeled_path(vec![(0.0, root)], 0);
+        let matching_kl = tracker.node_label_kl(root).unwrap();
+        tracker.add_labeled_path(vec![(0.0, root)], 1);
+        tracker.add_labeled_path(vec![(0.0, root)], 1);
+        let flipped_kl = tracker.node_label_kl(root).unwrap();
+        assert_eq!(tracker.sequence_len(), 2);
+        assert_eq!(tracker.running_labels().get(&root).unwrap().items.len(), 1);
+        assert!(matching_kl < flipped_kl);
+
+        let stats = tracker.label_kl_div_stats();
+        assert_eq!(stats.nz_count, 1);
+        assert_approx_eq!(stats.max, flipped_kl);
+    }
+
+    #[test]
+    fn label_tracker_unknown_label_test() {
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        let reader = tree.reader();
+        let root = reader.root_address();
+        let mut tracker = LabelStreamTracker::new(0, reader);
+        tracker.add_labeled_path(vec![(0.0, root)], 7);
+        assert!(tracker.node_label_kl(root).unwrap() > 0.0);
+        assert_eq!(tracker.node_label_kl((root.0 - 1, root.1 + 100)), None);
+    }
+}
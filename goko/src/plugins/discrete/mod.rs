@@ -13,7 +13,10 @@
 pub mod baseline;
 pub mod categorical;
 pub mod dirichlet;
+pub mod label_tracker;
+pub mod priors;
 pub mod tracker;
+pub mod visitors;
 
 #[allow(unused_imports)]
 pub mod prelude {
@@ -21,5 +24,8 @@ pub mod prelude {
     pub use super::baseline::*;
     pub use super::categorical::*;
     pub use super::dirichlet::*;
+    pub use super::label_tracker::*;
+    pub use super::priors::*;
     pub use super::tracker::*;
+    pub use super::visitors::*;
 }
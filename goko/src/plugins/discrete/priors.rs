@@ -0,0 +1,151 @@
+//! # Historical Priors
+//!
+//! By default a [`BayesCategoricalTracker`](super::tracker::BayesCategoricalTracker) measures drift against the
+//! Dirichlet priors of [`GokoDirichlet`](super::dirichlet::GokoDirichlet), which come from the training set. In
+//! production the queries rarely look like the training set, so a tracker fed normal traffic reports drift from the
+//! start. These priors are built from a log of past queries instead, so the tracker measures drift against the
+//! traffic you usually get.
+//!
+//! The priors are half open. A node the log visited gets a prior from the log, with a little of the training prior
+//! mixed in so a child the log never went to isn't impossible. A node the log never visited keeps its training prior.
+
+use crate::covertree::CoverTreeReader;
+use crate::errors::GokoResult;
+use crate::plugins::*;
+use hashbrown::HashMap;
+
+use super::categorical::*;
+use super::dirichlet::*;
+
+/// Dirichlet priors for the nodes of a tree built from a historical query log, see the module docs.
+#[derive(Debug, Clone, Default)]
+pub struct HistoricalPriors {
+    priors: HashMap<NodeAddress, Dirichlet>,
+    path_count: usize,
+}
+
+impl HistoricalPriors {
+    /// Builds the priors from the paths of past queries, root first as returned by [`CoverTreeReader::path`].
+    ///
+    /// `smoothing` is the mass of the training prior mixed into each node the log visited. It should be positive,
+    /// otherwise a query that goes somewhere the log never did has an undefined KL divergence. The tree needs a
+    /// [`GokoDirichlet`] plugin for the training prior, without it only the log is used.
+    pub fn from_paths<D, I, P>(
+        reader: &CoverTreeReader<D>,
+        paths: I,
+        smoothing: f64,
+    ) -> HistoricalPriors
+    where
+        D: PointCloud,
+        I: IntoIterator<Item = P>,
+        P: AsRef<[(f32, NodeAddress)]>,
+    {
+        let mut counts: HashMap<NodeAddress, Categorical> = HashMap::new();
+        let mut path_count = 0;
+        for path in paths {
+            let path = path.as_ref();
+            if let Some((_, last)) = path.last() {
+                for (parent, child) in path.iter().zip(path.iter().skip(1)) {
+                    counts
+                        .entry(parent.1)
+                        .or_default()
+                        .add_child_pop(Some(child.1), 1.0);
+                }
+                counts.entry(*last).or_default().add_child_pop(None, 1.0);
+                path_count += 1;
+            }
+        }
+
+        let priors = counts
+            .into_iter()
+            .map(|(address, mut evidence)| {
+                let training = reader
+                    .get_node_plugin_and::<Dirichlet, _, _>(address, |p| p.prob_vector())
+                    .flatten();
+                if let Some((child_probs, singleton_prob)) = training {
+                    for (child, prob) in child_probs {
+                        evidence.add_child_pop(Some(child), smoothing * prob);
+                    }
+                    evidence.add_child_pop(None, smoothing * singleton_prob);
+                }
+                let mut prior = Dirichlet::new();
+                prior.add_evidence(&evidence);
+                (address, prior)
+            })
+            .collect();
+        HistoricalPriors { priors, path_count }
+    }
+
+    /// Builds the priors from the indexes of past queries that were added to the tree. Their known paths are used,
+    /// see [`CoverTreeReader::known_path`].
+    pub fn from_point_indexes<D: PointCloud>(
+        reader: &CoverTreeReader<D>,
+        point_indexes: &[usize],
+        smoothing: f64,
+    ) -> GokoResult<HistoricalPriors> {
+        let paths = point_indexes
+            .iter()
+            .map(|pi| reader.known_path(*pi))
+            .collect::<GokoResult<Vec<Vec<(f32, NodeAddress)>>>>()?;
+        Ok(HistoricalPriors::from_paths(reader, paths, smoothing))
+    }
+
+    /// The prior of a node, `None` if the log never visited it.
+    pub fn get(&self, address: &NodeAddress) -> Option<&Dirichlet> {
+        self.priors.get(address)
+    }
+
+    /// The number of paths in the log
+    pub fn path_count(&self) -> usize {
+        self.path_count
+    }
+
+    /// The number of nodes the log visited
+    pub fn node_count(&self) -> usize {
+        self.priors.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+    use crate::plugins::discrete::tracker::BayesCategoricalTracker;
+    use std::sync::Arc;
+
+    #[test]
+    fn log_priors_replace_training_priors() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let reader = tree.reader();
+        let log: Vec<Vec<(f32, NodeAddress)>> =
+            (0..20).map(|_| reader.known_path(0).unwrap()).collect();
+        let priors = HistoricalPriors::from_paths(&reader, &log, 1.0);
+        assert_eq!(priors.path_count(), 20);
+        assert_eq!(priors.node_count(), log[0].len());
+        let root = reader.root_address();
+        assert_approx_eq!(priors.get(&root).unwrap().total(), 21.0);
+
+        // Traffic that looks like the log barely moves the tracker, the same traffic is a surprise to the training
+        // priors
+        let priors = Arc::new(priors);
+        let mut tracker = BayesCategoricalTracker::new(0, reader.clone());
+        let mut log_tracker = BayesCategoricalTracker::new(0, reader.clone());
+        log_tracker.set_priors(Arc::clone(&priors));
+        for path in log.iter().take(5) {
+            tracker.add_path(path.clone());
+            log_tracker.add_path(path.clone());
+        }
+        let training_kl = tracker.kl_div_stats().moment1_nz;
+        let log_kl = log_tracker.kl_div_stats().moment1_nz;
+        assert!(
+            log_kl < training_kl,
+            "{} is not under {}",
+            log_kl,
+            training_kl
+        );
+
+        log_tracker.clear_priors();
+        assert_approx_eq!(log_tracker.kl_div_stats().moment1_nz, training_kl);
+    }
+}
@@ -1,18 +1,25 @@
 //! See the paper for how this works
 
-use crate::covertree::CoverTreeReader;
+use crate::covertree::{CoverTreeReader, SoftPath};
 use crate::plugins::*;
 use hashbrown::HashMap;
 
+use super::baseline::{KLDivergenceBaseline, KLDivergenceScores};
 use super::categorical::*;
 use super::dirichlet::*;
+use super::priors::HistoricalPriors;
+use super::visitors::{NodeVisitors, UniqueVisitors};
+use statrs::distribution::{ChiSquared, Univariate};
 use statrs::function::gamma::{digamma, ln_gamma};
 
 use serde::{Deserialize, Serialize};
 
 use std::fmt;
 
-use std::collections::VecDeque;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::Arc;
+use tracing::warn;
 
 /// Computes a frequentist KL divergence calculation on each node the sequence touches.
 pub struct BayesCategoricalTracker<D: PointCloud> {
@@ -20,9 +27,106 @@ pub struct BayesCategoricalTracker<D: PointCloud> {
     sequence_queue: VecDeque<Vec<(f32, NodeAddress)>>,
     sequence_count: usize,
     window_size: usize,
+    decay: Option<f64>,
+    // The decayed evidence is the stored evidence times this, so that decaying doesn't touch every node
+    decay_scale: f64,
+    // What each path in the window added to the stored evidence, taken back out when the path is evicted
+    path_weights: VecDeque<f64>,
+    // The soft paths in the window, `None` for the hard ones. The sequence queue has their heaviest chain
+    soft_paths: VecDeque<Option<SoftPath>>,
+    // The stored weight of all the paths that went in and weren't evicted
+    stored_weight: f64,
+    // The evidence isn't whole counts of paths, as a soft path or the decay went into it. Cleared by a reset
+    weighted: bool,
+    priors: Option<Arc<HistoricalPriors>>,
+    baseline: Option<Arc<KLDivergenceBaseline>>,
+    visitors: Option<UniqueVisitors>,
+    drift: Option<DriftIndex>,
     reader: CoverTreeReader<D>,
 }
 
+/// The decay scale is folded into the stored evidence before it gets this small, so the stored values don't overflow.
+const MIN_DECAY_SCALE: f64 = 1.0e-100;
+
+/// Every node a soft path touched, in the format of a trace.
+fn soft_trace(soft_path: &SoftPath) -> Vec<(f32, NodeAddress)> {
+    soft_path
+        .nodes
+        .iter()
+        .map(|node| (node.dist, node.address))
+        .collect()
+}
+
+/// The non-zero per node KL divergences of a tracker, kept up to date as paths come and go so that the most drifted
+/// nodes can be read off without computing the divergence of every node. See
+/// [`BayesCategoricalTracker::top_drift`].
+#[derive(Debug, Default)]
+struct DriftIndex {
+    kls: HashMap<NodeAddress, f64>,
+    // Keyed on the bits of the divergence, these sort like the divergence itself as they're all positive
+    ordered: BTreeSet<(u64, NodeAddress)>,
+    // Set when the divergences can't be updated one path at a time, the priors changed or weren't attached
+    stale: bool,
+}
+
+impl DriftIndex {
+    fn set(&mut self, address: NodeAddress, kl: Option<f64>) {
+        if let Some(old) = self.kls.remove(&address) {
+            self.ordered.remove(&(old.to_bits(), address));
+        }
+        if let Some(kl) = kl.filter(|kl| kl.is_finite() && *kl > 1.0e-10) {
+            self.kls.insert(address, kl);
+            self.ordered.insert((kl.to_bits(), address));
+        }
+    }
+
+    fn top(&self, n: usize) -> Vec<(f64, NodeAddress)> {
+        self.ordered
+            .iter()
+            .rev()
+            .take(n)
+            .map(|(kl, address)| (f64::from_bits(*kl), *address))
+            .collect()
+    }
+
+    fn clear(&mut self) {
+        self.kls.clear();
+        self.ordered.clear();
+    }
+}
+
+/// What a [`BayesCategoricalTracker`] keeps besides the paths in its window, see
+/// [`BayesCategoricalTracker::state`]. Replaying the window doesn't give back the evidence of an unlimited window, the
+/// sequence count or the decay, this does.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackerState {
+    running_evidence: Vec<(NodeAddress, Categorical)>,
+    sequence_count: usize,
+    decay: Option<f64>,
+    decay_scale: f64,
+    path_weights: Vec<f64>,
+    soft_paths: Vec<Option<SoftPath>>,
+    stored_weight: f64,
+    #[serde(default)]
+    weighted: bool,
+}
+
+impl TrackerState {
+    /// The decay factor of the tracker, if its evidence decays.
+    pub fn decay(&self) -> Option<f64> {
+        self.decay
+    }
+
+    /// Every node the evidence is on, including the children it observed.
+    pub fn addresses(&self) -> impl Iterator<Item = NodeAddress> + '_ {
+        self.running_evidence
+            .iter()
+            .flat_map(|(address, evidence)| {
+                std::iter::once(*address).chain(evidence.child_counts.iter().map(|(ca, _)| *ca))
+            })
+    }
+}
+
 impl<D: PointCloud> fmt::Debug for BayesCategoricalTracker<D> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -35,46 +139,63 @@ impl<D: PointCloud> fmt::Debug for BayesCategoricalTracker<D> {
 
 impl<D: PointCloud> BayesCategoricalTracker<D> {
     /// Creates a new blank thing with capacity `size`, input 0 for unlimited.
-    pub fn new(
-        window_size: usize,
-        reader: CoverTreeReader<D>,
-    ) -> BayesCategoricalTracker<D> {
+    pub fn new(window_size: usize, reader: CoverTreeReader<D>) -> BayesCategoricalTracker<D> {
         BayesCategoricalTracker {
             running_evidence: HashMap::new(),
             sequence_queue: VecDeque::new(),
             sequence_count: 0,
             window_size,
+            decay: None,
+            decay_scale: 1.0,
+            path_weights: VecDeque::new(),
+            soft_paths: VecDeque::new(),
+            stored_weight: 0.0,
+            weighted: false,
+            priors: None,
+            baseline: None,
+            visitors: None,
+            drift: None,
             reader,
         }
     }
 
     /// Appends a tracker to this one,
     pub fn append(mut self, other: &Self) -> Self {
+        self.rescale();
         for (k, v) in other.running_evidence.iter() {
+            let v = other.decayed(v);
             self.running_evidence
                 .entry(*k)
-                .and_modify(|e| e.merge(v))
-                .or_insert_with(|| v.clone());
+                .and_modify(|e| e.merge(&v))
+                .or_insert_with(|| v.into_owned());
         }
         self.sequence_queue
             .extend(other.sequence_queue.iter().cloned());
+        self.path_weights
+            .extend(other.path_weights.iter().map(|w| w * other.decay_scale));
+        self.soft_paths.extend(other.soft_paths.iter().cloned());
+        self.stored_weight += other.stored_weight * other.decay_scale;
+        self.weighted |= other.weighted;
         self.sequence_count += other.sequence_count;
+        if let (Some(mine), Some(theirs)) = (self.visitors.as_mut(), other.visitors.as_ref()) {
+            mine.merge(theirs);
+        }
+        self.invalidate_drift();
         self
     }
 
-    fn get_distro(&self, address: NodeAddress) -> Dirichlet {
+    fn get_distro(&self, address: NodeAddress) -> Option<Dirichlet> {
         let mut prob = self
             .reader
-            .get_node_plugin_and::<Dirichlet, _, _>(address, |p| p.clone())
-            .unwrap();
+            .get_node_plugin_and::<Dirichlet, _, _>(address, |p| p.clone())?;
         let total = prob.total();
         if total > self.window_size as f64 {
             prob.weight((total.ln() * self.window_size as f64) / total)
         }
-        prob
+        Some(prob)
     }
 
-    fn add_trace_to_pdfs(&mut self, trace: &[(f32, NodeAddress)]) {
+    fn add_trace_to_pdfs(&mut self, trace: &[(f32, NodeAddress)], weight: f64) {
         let parent_address_iter = trace.iter().map(|(_, ca)| ca);
         let mut child_address_iter = trace.iter().map(|(_, ca)| ca);
         child_address_iter.next();
@@ -82,41 +203,155 @@ impl<D: PointCloud> BayesCategoricalTracker<D> {
             self.running_evidence
                 .entry(*parent)
                 .or_default()
-                .add_child_pop(Some(*child), 1.0);
+                .add_child_pop(Some(*child), weight);
         }
         let last = trace.last().unwrap().1;
         self.running_evidence
             .entry(last)
             .or_default()
-            .add_child_pop(None, 1.0);
+            .add_child_pop(None, weight);
     }
 
-    fn remove_trace_from_pdfs(&mut self, trace: &[(f32, NodeAddress)]) {
+    fn remove_trace_from_pdfs(&mut self, trace: &[(f32, NodeAddress)], weight: f64) {
         let parent_address_iter = trace.iter().map(|(_, ca)| ca);
         let mut child_address_iter = trace.iter().map(|(_, ca)| ca);
         child_address_iter.next();
         for (parent, child) in parent_address_iter.zip(child_address_iter) {
             let parent_evidence = self.running_evidence.get_mut(parent).unwrap();
-            parent_evidence.remove_child_pop(Some(*child), 1.0);
+            parent_evidence.remove_child_pop(Some(*child), weight);
         }
         let last = trace.last().unwrap().1;
         self.running_evidence
             .get_mut(&last)
             .unwrap()
-            .remove_child_pop(None, 1.0);
+            .remove_child_pop(None, weight);
+    }
+
+    fn add_soft_path_to_pdfs(&mut self, soft_path: &SoftPath, weight: f64) {
+        for node in &soft_path.nodes {
+            let node_weight = weight * node.weight as f64;
+            if let Some(parent) = node.parent {
+                self.running_evidence
+                    .entry(parent)
+                    .or_default()
+                    .add_child_pop(Some(node.address), node_weight);
+            }
+            if node.terminal {
+                self.running_evidence
+                    .entry(node.address)
+                    .or_default()
+                    .add_child_pop(None, node_weight);
+            }
+        }
+    }
+
+    fn remove_soft_path_from_pdfs(&mut self, soft_path: &SoftPath, weight: f64) {
+        for node in &soft_path.nodes {
+            let node_weight = weight * node.weight as f64;
+            if let Some(parent) = node.parent {
+                if let Some(parent_evidence) = self.running_evidence.get_mut(&parent) {
+                    parent_evidence.remove_child_pop(Some(node.address), node_weight);
+                }
+            }
+            if node.terminal {
+                if let Some(evidence) = self.running_evidence.get_mut(&node.address) {
+                    evidence.remove_child_pop(None, node_weight);
+                }
+            }
+        }
+    }
+
+    /// The evidence with the decay applied, see [`Self::set_decay`].
+    fn decayed<'a>(&self, evidence: &'a Categorical) -> Cow<'a, Categorical> {
+        if self.decay_scale == 1.0 {
+            Cow::Borrowed(evidence)
+        } else {
+            let mut evidence = evidence.clone();
+            evidence.scale(self.decay_scale);
+            Cow::Owned(evidence)
+        }
+    }
+
+    /// Folds the decay scale into the stored evidence.
+    fn rescale(&mut self) {
+        let scale = self.decay_scale;
+        if scale == 1.0 {
+            return;
+        }
+        for evidence in self.running_evidence.values_mut() {
+            evidence.scale(scale);
+        }
+        for weight in self.path_weights.iter_mut() {
+            *weight *= scale;
+        }
+        self.stored_weight *= scale;
+        self.decay_scale = 1.0;
+    }
+
+    /// Decays the evidence by a step and gives the weight the next path is stored with.
+    fn next_path_weight(&mut self) -> f64 {
+        match self.decay {
+            Some(gamma) => {
+                if gamma < 1.0 {
+                    self.weighted = true;
+                }
+                self.decay_scale *= gamma;
+                if self.decay_scale < MIN_DECAY_SCALE {
+                    self.rescale();
+                }
+                1.0 / self.decay_scale
+            }
+            None => 1.0,
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        if let (Some(oldest), Some(weight)) = (
+            self.sequence_queue.pop_front(),
+            self.path_weights.pop_front(),
+        ) {
+            match self.soft_paths.pop_front().flatten() {
+                Some(soft_path) => {
+                    self.remove_soft_path_from_pdfs(&soft_path, weight);
+                    self.update_drift(&soft_trace(&soft_path));
+                }
+                None => {
+                    self.remove_trace_from_pdfs(&oldest, weight);
+                    self.update_drift(&oldest);
+                }
+            }
+            self.stored_weight -= weight;
+        }
+    }
+
+    /// The total weight of the evidence, the number of paths unless the evidence decays.
+    fn evidence_total(&self) -> f64 {
+        self.stored_weight * self.decay_scale
+    }
+
+    /// Runs the function on the prior of the node, the historical one if there is one and the tree's otherwise.
+    fn prior_and<F, T>(&self, address: NodeAddress, f: F) -> Option<T>
+    where
+        F: FnOnce(&Dirichlet) -> T,
+    {
+        match self.priors.as_ref().and_then(|p| p.get(&address)) {
+            Some(prior) => Some(f(prior)),
+            None => self
+                .reader
+                .get_node_plugin_and::<Dirichlet, _, _>(address, f),
+        }
     }
 
     /// Gives the probability vector for this
     pub fn prob_vector(&self, na: NodeAddress) -> Option<(Vec<(NodeAddress, f64)>, f64)> {
-        self.reader
-            .get_node_plugin_and::<Dirichlet, _, _>(na, |p| {
-                let mut dir = p.clone();
-                if let Some(e) = self.running_evidence.get(&na) {
-                    dir.add_evidence(e)
-                }
-                dir.prob_vector()
-            })
-            .flatten()
+        self.prior_and(na, |p| {
+            let mut dir = p.clone();
+            if let Some(e) = self.running_evidence.get(&na) {
+                dir.add_evidence(&self.decayed(e))
+            }
+            dir.prob_vector()
+        })
+        .flatten()
     }
 
     /// Gives the probability vector for this
@@ -127,25 +362,281 @@ impl<D: PointCloud> BayesCategoricalTracker<D> {
             .flatten()
     }
 
-    /// Adds an element to the trace
+    /// The posterior KL divergence of a node, `None` if nothing went through it.
+    fn node_kl(&self, address: NodeAddress) -> Option<f64> {
+        let evidence = self.decayed(self.running_evidence.get(&address)?);
+        self.prior_and(address, |p| p.posterior_kl_divergence(&evidence))
+            .flatten()
+    }
+
+    /// Refreshes the divergences of the nodes on the trace in the drift index, if there is one.
+    fn update_drift(&mut self, trace: &[(f32, NodeAddress)]) {
+        if self.decay.is_some() {
+            // Every node's evidence moved, not just the ones on the trace
+            self.invalidate_drift();
+            return;
+        }
+        match self.drift.as_ref() {
+            Some(drift) if !drift.stale => {
+                if !self.priors_attached() {
+                    self.invalidate_drift();
+                    return;
+                }
+            }
+            _ => return,
+        }
+        let kls: Vec<(NodeAddress, Option<f64>)> = trace
+            .iter()
+            .map(|(_, address)| (*address, self.node_kl(*address)))
+            .collect();
+        if let Some(drift) = self.drift.as_mut() {
+            for (address, kl) in kls {
+                drift.set(address, kl);
+            }
+        }
+    }
+
+    fn invalidate_drift(&mut self) {
+        if let Some(drift) = self.drift.as_mut() {
+            drift.stale = true;
+        }
+    }
+
+    /// Adds an element to the trace. If the window is full the oldest path is evicted, and its observations are
+    /// removed from the nodes it touched. If the evidence decays the older paths are discounted first.
     pub fn add_path(&mut self, trace: Vec<(f32, NodeAddress)>) {
-        self.add_trace_to_pdfs(&trace);
+        let weight = self.next_path_weight();
+        self.add_trace_to_pdfs(&trace, weight);
+        self.update_drift(&trace);
         self.sequence_count += 1;
+        self.stored_weight += weight;
         if self.window_size != 0 {
             self.sequence_queue.push_back(trace);
+            self.path_weights.push_back(weight);
+            self.soft_paths.push_back(None);
+
+            if self.sequence_queue.len() > self.window_size {
+                self.evict_oldest();
+            }
+        }
+    }
+
+    /// Adds a point that was split between several nodes, see [`CoverTreeReader::soft_path`]. Each node gets the
+    /// fraction of an observation that reached it, a parent observes each child by the weight that went into it.
+    /// A soft path takes up one spot in the window like any other path.
+    ///
+    /// The sequence queue only has the heaviest chain of the soft path, see [`SoftPath::heaviest_path`].
+    /// Anything rebuilt from the queue, like a restored snapshot, sees that chain as a hard path.
+    pub fn add_soft_path(&mut self, soft_path: SoftPath) {
+        self.weighted = true;
+        let weight = self.next_path_weight();
+        self.add_soft_path_to_pdfs(&soft_path, weight);
+        self.update_drift(&soft_trace(&soft_path));
+        self.sequence_count += 1;
+        self.stored_weight += weight;
+        if self.window_size != 0 {
+            self.sequence_queue.push_back(soft_path.heaviest_path());
+            self.path_weights.push_back(weight);
+            self.soft_paths.push_back(Some(soft_path));
 
             if self.sequence_queue.len() > self.window_size {
-                let oldest = self.sequence_queue.pop_front().unwrap();
-                self.remove_trace_from_pdfs(&oldest);
+                self.evict_oldest();
             }
         }
     }
 
-    /// The running categorical distributions
+    /// Adds an element to the trace, recording a visit by the query id to every node on the path if unique visitors
+    /// are enabled, see [`Self::enable_unique_visitors`].
+    pub fn add_path_with_id(&mut self, trace: Vec<(f32, NodeAddress)>, query_id: u64) {
+        if let Some(visitors) = self.visitors.as_mut() {
+            for (_, address) in &trace {
+                visitors.visit(*address, query_id);
+            }
+        }
+        self.add_path(trace);
+    }
+
+    /// Starts estimating the distinct queries that go through each node, with a sketch of this precision per node.
+    /// Only the paths added with [`Self::add_path_with_id`] count. The counts aren't windowed, see
+    /// [`crate::plugins::discrete::visitors`].
+    pub fn enable_unique_visitors(&mut self, precision: u8) {
+        if self.visitors.is_none() {
+            self.visitors = Some(UniqueVisitors::new(precision));
+        }
+    }
+
+    /// Stops counting unique visitors and drops the sketches.
+    pub fn disable_unique_visitors(&mut self) {
+        self.visitors = None;
+    }
+
+    /// The visits to every node, `None` unless unique visitors are enabled.
+    pub fn node_visitors(&self) -> Option<Vec<NodeVisitors>> {
+        self.visitors.as_ref().map(|v| v.nodes())
+    }
+
+    /// The visits to a node, `None` unless unique visitors are enabled and a query went through the node.
+    pub fn unique_visitors(&self, address: NodeAddress) -> Option<NodeVisitors> {
+        self.visitors.as_ref().and_then(|v| v.get(address))
+    }
+
+    /// Changes the size of the window, 0 for unlimited. Shrinking the window drops the oldest paths until it fits.
+    /// An unlimited window doesn't keep its paths, so going from unlimited to a limited window starts the
+    /// tracker over.
+    pub fn set_window_size(&mut self, window_size: usize) {
+        if self.window_size == 0 && window_size != 0 {
+            self.reset();
+        }
+        self.window_size = window_size;
+        if window_size != 0 {
+            while self.sequence_queue.len() > window_size {
+                self.evict_oldest();
+            }
+        }
+    }
+
+    /// Decays the evidence, each new path multiplies the evidence of the paths before it by `gamma`, so the drift
+    /// reflects the recent traffic. A path that went in `n` paths ago counts for `gamma^n`. This works with or
+    /// without a window. Each path still only touches the nodes on it, but [`Self::top_drift`] has to compute every
+    /// node's divergence again as they all move.
+    ///
+    /// `gamma` is clamped to `(0, 1]`, with 1 the same as no decay.
+    pub fn set_decay(&mut self, gamma: f64) {
+        self.rescale();
+        self.decay = Some(gamma.max(f64::MIN_POSITIVE).min(1.0));
+        self.invalidate_drift();
+    }
+
+    /// Stops decaying the evidence, the evidence already decayed stays that way.
+    pub fn clear_decay(&mut self) {
+        self.rescale();
+        self.decay = None;
+        self.invalidate_drift();
+    }
+
+    /// The decay factor, if the evidence decays.
+    pub fn decay(&self) -> Option<f64> {
+        self.decay
+    }
+
+    /// Measures the drift against priors built from a query log instead of the training set, see
+    /// [`HistoricalPriors`]. The per node divergences and the stats built on them use the new priors, [`Self::kl_div`]
+    /// stays against the training coverage. The evidence is kept.
+    pub fn set_priors(&mut self, priors: Arc<HistoricalPriors>) {
+        self.priors = Some(priors);
+        self.invalidate_drift();
+    }
+
+    /// Goes back to the training priors.
+    pub fn clear_priors(&mut self) {
+        self.priors = None;
+        self.invalidate_drift();
+    }
+
+    /// The historical priors, if the tracker has them.
+    pub fn priors(&self) -> Option<&Arc<HistoricalPriors>> {
+        self.priors.as_ref()
+    }
+
+    /// Attaches a baseline to score the stats against, see [`Self::kl_div_scores`].
+    pub fn set_baseline(&mut self, baseline: Arc<KLDivergenceBaseline>) {
+        self.baseline = Some(baseline);
+    }
+
+    /// Drops the baseline.
+    pub fn clear_baseline(&mut self) {
+        self.baseline = None;
+    }
+
+    /// The attached baseline, if there is one.
+    pub fn baseline(&self) -> Option<&Arc<KLDivergenceBaseline>> {
+        self.baseline.as_ref()
+    }
+
+    /// Clears all the evidence and unique visitors, the window size and decay are kept.
+    pub fn reset(&mut self) {
+        self.running_evidence.clear();
+        self.sequence_queue.clear();
+        self.path_weights.clear();
+        self.soft_paths.clear();
+        self.sequence_count = 0;
+        self.stored_weight = 0.0;
+        self.decay_scale = 1.0;
+        self.weighted = false;
+        if let Some(visitors) = self.visitors.as_mut() {
+            visitors.clear();
+        }
+        if let Some(drift) = self.drift.as_mut() {
+            drift.clear();
+        }
+    }
+
+    /// The state that goes with the paths in the window, see [`Self::restore_state`].
+    pub fn state(&self) -> TrackerState {
+        TrackerState {
+            running_evidence: self
+                .running_evidence
+                .iter()
+                .map(|(address, evidence)| (*address, evidence.clone()))
+                .collect(),
+            sequence_count: self.sequence_count,
+            decay: self.decay,
+            decay_scale: self.decay_scale,
+            path_weights: self.path_weights.iter().cloned().collect(),
+            soft_paths: self.soft_paths.iter().cloned().collect(),
+            stored_weight: self.stored_weight,
+            weighted: self.weighted,
+        }
+    }
+
+    /// Puts the tracker back the way it was when the state was taken, with the paths of its window then, see
+    /// [`Self::sequence_queue`]. The window size, visitors, priors and baseline are this tracker's. Returns false, and
+    /// leaves the tracker alone, if the paths don't go with the state or don't fit in the window.
+    pub fn restore_state(
+        &mut self,
+        sequence_queue: Vec<Vec<(f32, NodeAddress)>>,
+        state: TrackerState,
+    ) -> bool {
+        let fits = if self.window_size == 0 {
+            sequence_queue.is_empty()
+        } else {
+            sequence_queue.len() <= self.window_size
+        };
+        if !fits
+            || state.path_weights.len() != sequence_queue.len()
+            || state.soft_paths.len() != sequence_queue.len()
+        {
+            return false;
+        }
+        self.running_evidence = state.running_evidence.into_iter().collect();
+        self.sequence_queue = sequence_queue.into();
+        self.sequence_count = state.sequence_count;
+        self.decay = state.decay;
+        self.decay_scale = state.decay_scale;
+        self.path_weights = state.path_weights.into();
+        self.soft_paths = state.soft_paths.into();
+        self.stored_weight = state.stored_weight;
+        self.weighted = state.weighted;
+        self.invalidate_drift();
+        true
+    }
+
+    /// The running categorical distributions. If the evidence decays these are stored undecayed, they're all off by
+    /// the same factor.
     pub fn running_evidence(&self) -> &HashMap<NodeAddress, Categorical> {
         &self.running_evidence
     }
 
+    /// The paths currently in the window, oldest first. This is empty if the window is unlimited.
+    pub fn sequence_queue(&self) -> &VecDeque<Vec<(f32, NodeAddress)>> {
+        &self.sequence_queue
+    }
+
+    /// The size of the window, 0 for unlimited.
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
     /// The lenght of the sequence
     pub fn sequence_len(&self) -> usize {
         if self.sequence_queue.is_empty() {
@@ -155,72 +646,151 @@ impl<D: PointCloud> BayesCategoricalTracker<D> {
         }
     }
 
-    /// Gives the per-node KL divergence, with the node address
+    /// If the priors of every node are there. They're missing while [`GokoDirichlet`] is still being attached to the
+    /// tree, see [`CoverTreeReader::plugin_status`]. Historical priors don't need the plugin.
+    pub fn priors_attached(&self) -> bool {
+        self.priors.is_some() || self.reader.plugin_attached::<GokoDirichlet>()
+    }
+
+    /// Gives the per-node KL divergence, with the node address. This is empty until the priors are attached, see
+    /// [`Self::try_all_node_kl`].
     pub fn all_node_kl(&self) -> Vec<(f64, NodeAddress)> {
-        self.running_evidence()
+        self.try_all_node_kl().unwrap_or_default()
+    }
+
+    /// Gives the per-node KL divergence, with the node address, or `None` if the priors aren't attached yet.
+    pub fn try_all_node_kl(&self) -> Option<Vec<(f64, NodeAddress)>> {
+        if !self.priors_attached() {
+            return None;
+        }
+        let node_kls = self
+            .running_evidence()
             .iter()
             .filter_map(|(address, sequence_pdf)| {
+                let sequence_pdf = self.decayed(sequence_pdf);
                 let kl_option = self
-                    .reader
-                    .get_node_plugin_and::<Dirichlet, _, _>(*address, |p| {
-                        p.posterior_kl_divergence(sequence_pdf).unwrap()
-                    })
+                    .prior_and(*address, |p| p.posterior_kl_divergence(&sequence_pdf))
+                    .flatten()
                     .map(|kl| (kl, *address));
                 if let None = kl_option {
-                    println!("Unable to find node at {:?}", address);
+                    warn!(?address, "Unable to find node");
                 }
                 kl_option
             })
-            .collect()
+            .collect();
+        Some(node_kls)
     }
 
-    /// A set of stats for the sequence that are helpful.
-    pub fn kl_div_stats(&self) -> KLDivergenceStats {
-        let mut max = f64::MIN;
-        let mut min = f64::MAX;
-        let mut nz_count = 0;
-        let mut moment1_nz = 0.0;
-        let mut moment2_nz = 0.0;
-        self.all_node_kl().iter().for_each(|(kl, _address)| {
-            if *kl > 1.0e-10 {
-                moment1_nz += kl;
-                moment2_nz += kl * kl;
-                if max < *kl {
-                    max = *kl;
-                }
-                if *kl < min {
-                    min = *kl;
-                }
+    /// Starts keeping the per node divergences up to date as paths are added and dropped, so that
+    /// [`Self::top_drift`] doesn't have to compute all of them. Each path then costs a divergence per node on it
+    /// and the one that falls out of the window.
+    pub fn enable_top_drift(&mut self) {
+        if self.drift.is_none() {
+            self.drift = Some(DriftIndex {
+                stale: true,
+                ..DriftIndex::default()
+            });
+        }
+    }
 
-                nz_count += 1;
+    /// Stops keeping the per node divergences.
+    pub fn disable_top_drift(&mut self) {
+        self.drift = None;
+    }
+
+    /// The `n` nodes with the largest KL divergence, largest first. Only nodes with a non-zero divergence are
+    /// returned. This is `None` unless [`Self::enable_top_drift`] was called and the priors are attached. The first
+    /// call after enabling it, or after the priors change, computes the divergence of every node once.
+    pub fn top_drift(&mut self, n: usize) -> Option<Vec<(f64, NodeAddress)>> {
+        let stale = self.drift.as_ref()?.stale;
+        if stale {
+            let node_kls = self.try_all_node_kl()?;
+            let drift = self.drift.as_mut()?;
+            drift.clear();
+            for (kl, address) in node_kls {
+                drift.set(address, Some(kl));
             }
-        });
-        KLDivergenceStats {
-            max,
-            min,
-            nz_count,
-            moment1_nz,
-            moment2_nz,
-            sequence_len: self.sequence_len(),
+            drift.stale = false;
+        } else if !self.priors_attached() {
+            return None;
         }
+        self.drift.as_ref().map(|drift| drift.top(n))
+    }
+
+    /// The KL divergence of the nodes added up at their ancestors at `target_scale`, largest first, see
+    /// [`CoverTreeReader::rollup`]. A region that drifted shows up as one large value instead of being spread over
+    /// many small nodes. This is `None` if the priors aren't attached yet.
+    pub fn rollup_kl(&self, target_scale: i32) -> Option<Vec<(f64, NodeAddress)>> {
+        let node_kls = self.try_all_node_kl()?;
+        let mut rolled_up: Vec<(f64, NodeAddress)> = self
+            .reader
+            .rollup(
+                node_kls.into_iter().map(|(kl, address)| (address, kl)),
+                target_scale,
+            )
+            .into_iter()
+            .map(|(address, kl)| (kl, address))
+            .collect();
+        rolled_up.sort_by(|a, b| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        Some(rolled_up)
+    }
+
+    /// A set of stats for the sequence that are helpful. The weighted stats use [`CoverageWeighting::Fraction`].
+    pub fn kl_div_stats(&self) -> KLDivergenceStats {
+        self.kl_div_stats_weighted(CoverageWeighting::default())
+    }
+
+    /// A set of stats for the sequence, with the weighted fields computed with the given weighting. Until the priors
+    /// are attached this falls back to the structure only stats, the sequence length with no divergences.
+    pub fn kl_div_stats_weighted(&self, weighting: CoverageWeighting) -> KLDivergenceStats {
+        KLDivergenceStats::from_node_kls(
+            &self.all_node_kl(),
+            &self.reader,
+            weighting,
+            self.sequence_len(),
+        )
+    }
+
+    /// The stats of the sequence, or `None` if the priors aren't attached yet. See [`Self::kl_div_stats_weighted`].
+    pub fn try_kl_div_stats_weighted(
+        &self,
+        weighting: CoverageWeighting,
+    ) -> Option<KLDivergenceStats> {
+        self.try_all_node_kl().map(|node_kls| {
+            KLDivergenceStats::from_node_kls(
+                &node_kls,
+                &self.reader,
+                weighting,
+                self.sequence_len(),
+            )
+        })
+    }
+
+    /// The stats of the sequence as z scores and percentiles of the attached baseline, `None` without a baseline or
+    /// while the priors are being attached.
+    pub fn kl_div_scores(&self) -> Option<KLDivergenceScores> {
+        let baseline = self.baseline.as_ref()?;
+        self.try_kl_div_stats_weighted(CoverageWeighting::default())
+            .map(|stats| baseline.score(&stats))
     }
 
     /// The KL Divergence between the prior and posterior of the whole tree.
     pub fn kl_div(&self) -> f64 {
         let prior_total =
             (self.reader.parameters().point_cloud.len() + self.reader.node_count()) as f64;
-        let posterior_total = prior_total + self.sequence_len() as f64;
+        let posterior_total = prior_total + self.evidence_total();
         let mut prior_total_lng = 0.0;
         let mut posterior_total_lng = 0.0;
         let mut digamma_portion = 0.0;
         for (addr, evidence) in self.running_evidence.iter() {
-            if evidence.singleton_count > 0.0 {
+            let singleton_count = evidence.singleton_count * self.decay_scale;
+            if singleton_count > 0.0 {
                 self.reader.get_node_and(*addr, |n| {
                     let prior = n.singletons_len() as f64 + 1.0;
                     prior_total_lng += ln_gamma(prior);
-                    posterior_total_lng += ln_gamma(evidence.singleton_count + prior);
-                    digamma_portion += evidence.singleton_count
-                        * (digamma(evidence.singleton_count + prior) - digamma(posterior_total));
+                    posterior_total_lng += ln_gamma(singleton_count + prior);
+                    digamma_portion += singleton_count
+                        * (digamma(singleton_count + prior) - digamma(posterior_total));
                 });
             }
         }
@@ -236,6 +806,112 @@ impl<D: PointCloud> BayesCategoricalTracker<D> {
         }
     }
 
+    /// The Akaike information criterion of the sequence under the tree's marginal model, `2k - 2 ln L`. `L` is the
+    /// likelihood of every step the sequence took through a node under the expected categorical of that node's prior,
+    /// and `k` is the number of nodes the sequence touched. This grows as the sequence wanders into parts of the tree
+    /// the prior considers unlikely.
+    pub fn marginal_aic(&self) -> f64 {
+        let mut ln_likelihood = 0.0;
+        let mut touched = 0;
+        for (addr, evidence) in self.running_evidence.iter() {
+            let evidence = self.decayed(evidence);
+            if evidence.total() <= 0.0 {
+                continue;
+            }
+            touched += 1;
+            let node_ln_likelihood = self.prior_and(*addr, |p| {
+                let singletons = if evidence.singleton_count > 0.0 {
+                    evidence.singleton_count * p.ln_pdf(None).unwrap_or(0.0)
+                } else {
+                    0.0
+                };
+                evidence
+                    .child_counts
+                    .iter()
+                    .filter(|(_, c)| *c > 0.0)
+                    .map(|(ca, c)| c * p.ln_pdf(Some(ca)).unwrap_or(0.0))
+                    .fold(singletons, |a, x| a + x)
+            });
+            ln_likelihood += node_ln_likelihood.unwrap_or(0.0);
+        }
+        2.0 * touched as f64 - 2.0 * ln_likelihood
+    }
+
+    /// Pearson's chi-squared and the G-test of the sequence against the tree's marginal model, with their p-values.
+    /// Until the priors are attached this is the stats of an empty sequence, see [`Self::try_goodness_of_fit`].
+    pub fn goodness_of_fit(&self) -> GoodnessOfFitStats {
+        self.try_goodness_of_fit().unwrap_or_else(|| {
+            GoodnessOfFitStats::from_tests(0.0, 0.0, 0.0, 0, self.sequence_len(), true)
+        })
+    }
+
+    /// Pearson's chi-squared and the G-test of the sequence against the tree's marginal model, or `None` if the
+    /// priors aren't attached yet.
+    ///
+    /// Each node the sequence went through is a test of where the sequence went next against the expected
+    /// categorical of the node's prior, and the statistics and degrees of freedom of the nodes are added up. A child
+    /// the prior gives no mass to can't be tested and is left out, a [`PriorStrategy::PseudoCount`] prior gives every
+    /// child some mass. The p-values assume the steps are independent, which they are for paths that are independent.
+    ///
+    /// The tests are for whole counts. Once a soft path or the decay went into the evidence the counts are fractions
+    /// and the statistics aren't chi-squared distributed, so the p-values are `None` until the tracker is reset, see
+    /// [`Self::reset`]. The statistics are still there to compare to each other.
+    pub fn try_goodness_of_fit(&self) -> Option<GoodnessOfFitStats> {
+        if !self.priors_attached() {
+            return None;
+        }
+        let mut chi_squared = 0.0;
+        let mut g_statistic = 0.0;
+        let mut degrees_of_freedom = 0.0;
+        let mut node_count = 0;
+        for (addr, evidence) in self.running_evidence.iter() {
+            let evidence = self.decayed(evidence);
+            let observed_total = evidence.total();
+            if observed_total <= 0.0 {
+                continue;
+            }
+            let probs = self.prior_and(*addr, |p| p.prob_vector()).flatten();
+            let (child_probs, singleton_prob) = match probs {
+                Some(probs) => probs,
+                None => continue,
+            };
+            let observed = |loc: Option<&NodeAddress>| match loc {
+                Some(ca) => evidence
+                    .child_counts
+                    .binary_search_by_key(&ca, |(a, _)| a)
+                    .map(|i| evidence.child_counts[i].1)
+                    .unwrap_or(0.0),
+                None => evidence.singleton_count,
+            };
+            let categories = child_probs
+                .iter()
+                .map(|(ca, p)| (observed(Some(ca)), *p))
+                .chain(std::iter::once((observed(None), singleton_prob)))
+                .filter(|(_, p)| *p > 0.0);
+            let mut category_count = 0;
+            for (o, p) in categories {
+                let e = observed_total * p;
+                chi_squared += (o - e) * (o - e) / e;
+                if o > 0.0 {
+                    g_statistic += 2.0 * o * (o / e).ln();
+                }
+                category_count += 1;
+            }
+            if category_count > 1 {
+                degrees_of_freedom += (category_count - 1) as f64;
+                node_count += 1;
+            }
+        }
+        Some(GoodnessOfFitStats::from_tests(
+            chi_squared,
+            g_statistic,
+            degrees_of_freedom,
+            node_count,
+            self.sequence_len(),
+            !self.weighted,
+        ))
+    }
+
     /// A set of stats for the sequence that are helpful.
     pub fn fractal_dim_stats(&self) -> FractalDimStats {
         let mut layer_totals: Vec<u64> = vec![0; self.reader.len()];
@@ -269,6 +945,32 @@ impl<D: PointCloud> BayesCategoricalTracker<D> {
     }
 }
 
+/// How each node's KL divergence is weighted in the weighted stats. Raw per-node stats let the many small
+/// nodes at the bottom of the tree dominate, weighting by coverage counters this.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum CoverageWeighting {
+    /// The fraction of the dataset the node covers
+    Fraction,
+    /// `ln(1 + coverage) / ln(1 + dataset size)`, this is gentler on the smaller nodes
+    LnFraction,
+}
+
+impl Default for CoverageWeighting {
+    fn default() -> Self {
+        CoverageWeighting::Fraction
+    }
+}
+
+impl CoverageWeighting {
+    /// The weight of a node that covers `coverage` of the `total` points.
+    pub fn weight(&self, coverage: usize, total: usize) -> f64 {
+        match self {
+            CoverageWeighting::Fraction => coverage as f64 / total as f64,
+            CoverageWeighting::LnFraction => (coverage as f64).ln_1p() / (total as f64).ln_1p(),
+        }
+    }
+}
+
 /// Tracks the non-zero KL div (all KL divergences above 1e-10)
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KLDivergenceStats {
@@ -282,16 +984,135 @@ pub struct KLDivergenceStats {
     pub moment1_nz: f64,
     /// The second moment, use this with the `nz_count` and first moment to get the variance
     pub moment2_nz: f64,
-    /// The number of sequence elements that went into calculating this stat. This is not the total lenght
-    /// We can drop old sequence elements
+    /// The first moment, with each node's divergence weighted by it's coverage
+    pub weighted_moment1_nz: f64,
+    /// The second moment, with each node's divergence weighted by it's coverage
+    pub weighted_moment2_nz: f64,
+    /// The sum of the weights of the nodes that have a non-zero divergence, use this with the weighted moments to get the weighted mean
+    pub weight_nz: f64,
+    /// The number of sequence elements that went into calculating this stat. For a windowed tracker this is at most
+    /// the window size, as the oldest elements are dropped
     pub sequence_len: usize,
 }
 
+impl KLDivergenceStats {
+    pub(crate) fn from_node_kls<D: PointCloud>(
+        node_kls: &[(f64, NodeAddress)],
+        reader: &CoverTreeReader<D>,
+        weighting: CoverageWeighting,
+        sequence_len: usize,
+    ) -> KLDivergenceStats {
+        let total = reader.parameters().point_cloud.len();
+        let mut max = f64::MIN;
+        let mut min = f64::MAX;
+        let mut nz_count = 0;
+        let mut moment1_nz = 0.0;
+        let mut moment2_nz = 0.0;
+        let mut weighted_moment1_nz = 0.0;
+        let mut weighted_moment2_nz = 0.0;
+        let mut weight_nz = 0.0;
+        node_kls.iter().for_each(|(kl, address)| {
+            if *kl > 1.0e-10 {
+                moment1_nz += kl;
+                moment2_nz += kl * kl;
+                if max < *kl {
+                    max = *kl;
+                }
+                if *kl < min {
+                    min = *kl;
+                }
+                nz_count += 1;
+
+                let coverage = reader
+                    .get_node_and(*address, |n| n.coverage_count())
+                    .unwrap_or(0);
+                let weight = weighting.weight(coverage, total);
+                weighted_moment1_nz += weight * kl;
+                weighted_moment2_nz += weight * kl * kl;
+                weight_nz += weight;
+            }
+        });
+        KLDivergenceStats {
+            max,
+            min,
+            nz_count,
+            moment1_nz,
+            moment2_nz,
+            weighted_moment1_nz,
+            weighted_moment2_nz,
+            weight_nz,
+            sequence_len,
+        }
+    }
+}
+
+/// Goodness-of-fit tests of the sequence against the tree's marginal model, see
+/// [`BayesCategoricalTracker::goodness_of_fit`]. Unlike the KL divergence these come with p-values, the chance that a
+/// sequence drawn from the training distribution fits as badly as this one. A small p-value is drift.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GoodnessOfFitStats {
+    /// Pearson's chi-squared statistic, added up over the nodes
+    pub chi_squared: f64,
+    /// The p-value of the chi-squared statistic, `None` if the evidence isn't whole counts, see
+    /// [`BayesCategoricalTracker::try_goodness_of_fit`]
+    pub chi_squared_p_value: Option<f64>,
+    /// The G-test statistic, twice the log-likelihood ratio, added up over the nodes
+    pub g_statistic: f64,
+    /// The p-value of the G-test statistic, `None` if the evidence isn't whole counts
+    pub g_test_p_value: Option<f64>,
+    /// The degrees of freedom of both tests, one less than the number of children of each node that was tested
+    pub degrees_of_freedom: f64,
+    /// The number of nodes that were tested
+    pub node_count: u64,
+    /// The number of sequence elements that went into calculating this stat. For a windowed tracker this is at most
+    /// the window size, as the oldest elements are dropped
+    pub sequence_len: usize,
+}
+
+impl GoodnessOfFitStats {
+    fn from_tests(
+        chi_squared: f64,
+        g_statistic: f64,
+        degrees_of_freedom: f64,
+        node_count: u64,
+        sequence_len: usize,
+        whole_counts: bool,
+    ) -> GoodnessOfFitStats {
+        let p_value = |statistic| {
+            if whole_counts {
+                Some(chi_squared_p_value(statistic, degrees_of_freedom))
+            } else {
+                None
+            }
+        };
+        GoodnessOfFitStats {
+            chi_squared,
+            chi_squared_p_value: p_value(chi_squared),
+            g_statistic,
+            g_test_p_value: p_value(g_statistic),
+            degrees_of_freedom,
+            node_count,
+            sequence_len,
+        }
+    }
+}
+
+/// The chance that a chi-squared variable with these degrees of freedom is at least the statistic.
+fn chi_squared_p_value(statistic: f64, degrees_of_freedom: f64) -> f64 {
+    if degrees_of_freedom <= 0.0 || statistic <= 0.0 {
+        return 1.0;
+    }
+    match ChiSquared::new(degrees_of_freedom) {
+        Ok(dist) => (1.0 - dist.cdf(statistic)).max(0.0),
+        Err(_) => 1.0,
+    }
+}
+
 /// Stats that let you compute the fractal dim of the query dataset wrt the base covertree
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FractalDimStats {
-    /// The number of sequence elements that went into calculating this stat. This is not the total lenght
-    /// We can drop old sequence elements
+    /// The number of sequence elements that went into calculating this stat. For a windowed tracker this is at most
+    /// the window size, as the oldest elements are dropped
     pub sequence_len: usize,
     /// The number of nodes per layer this sequence touches
     pub layer_totals: Vec<u64>,
@@ -303,6 +1124,7 @@ pub struct FractalDimStats {
 pub(crate) mod tests {
     use super::*;
     use crate::covertree::tests::build_basic_tree;
+    use crate::plugins::discrete::visitors::DEFAULT_VISITOR_PRECISION;
 
     #[test]
     fn dirichlet_tree_probs_test() {
@@ -368,4 +1190,430 @@ pub(crate) mod tests {
         println!("Merge KL Div: {}", tracker1.kl_div());
         assert_approx_eq!(tracker.kl_div(), tracker1.kl_div());
     }
+
+    #[test]
+    fn dirichlet_tree_resize_test() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let long_path = vec![
+            (0.0, (-1, 4)),
+            (0.0, (-2, 2)),
+            (0.0, (-5, 2)),
+            (0.0, (-6, 2)),
+        ];
+        let mut tracker = BayesCategoricalTracker::new(3, tree.reader());
+        tracker.add_path(long_path.clone());
+        tracker.add_path(vec![(0.0, (-1, 4))]);
+        tracker.add_path(vec![(0.0, (-1, 4))]);
+
+        let mut expected = BayesCategoricalTracker::new(1, tree.reader());
+        expected.add_path(vec![(0.0, (-1, 4))]);
+        tracker.set_window_size(1);
+        assert_eq!(tracker.sequence_len(), 1);
+        assert_approx_eq!(tracker.kl_div(), expected.kl_div());
+
+        tracker.set_window_size(5);
+        tracker.add_path(long_path);
+        assert_eq!(tracker.sequence_len(), 2);
+
+        tracker.reset();
+        assert_eq!(tracker.sequence_len(), 0);
+        assert_eq!(tracker.window_size(), 5);
+        assert_approx_eq!(tracker.kl_div(), 0.0);
+    }
+
+    #[test]
+    fn window_evicts_oldest_path() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let long_path = vec![
+            (0.0, (-1, 4)),
+            (0.0, (-2, 2)),
+            (0.0, (-5, 2)),
+            (0.0, (-6, 2)),
+        ];
+        let short_path = vec![(0.0, (-1, 4))];
+        let mut tracker = BayesCategoricalTracker::new(2, tree.reader());
+        tracker.add_path(long_path.clone());
+        tracker.add_path(short_path.clone());
+        tracker.add_path(short_path.clone());
+
+        let mut expected = BayesCategoricalTracker::new(2, tree.reader());
+        expected.add_path(short_path.clone());
+        expected.add_path(short_path);
+        assert_eq!(tracker.sequence_len(), 2);
+        assert_eq!(
+            tracker.sequence_queue().front(),
+            expected.sequence_queue().front()
+        );
+        assert_approx_eq!(tracker.kl_div(), expected.kl_div());
+        for (address, _) in &long_path[1..] {
+            let evidence = tracker
+                .running_evidence()
+                .get(address)
+                .map(|e| e.total())
+                .unwrap_or(0.0);
+            assert_approx_eq!(evidence, 0.0);
+        }
+    }
+
+    #[test]
+    fn decayed_evidence_forgets_old_paths() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let long_path = vec![
+            (0.0, (-1, 4)),
+            (0.0, (-2, 2)),
+            (0.0, (-5, 2)),
+            (0.0, (-6, 2)),
+        ];
+        let short_path = vec![(0.0, (-1, 4))];
+
+        let mut undecayed = BayesCategoricalTracker::new(0, tree.reader());
+        undecayed.set_decay(1.0);
+        let mut expected = BayesCategoricalTracker::new(0, tree.reader());
+        undecayed.add_path(long_path.clone());
+        expected.add_path(long_path.clone());
+        undecayed.add_path(short_path.clone());
+        expected.add_path(short_path.clone());
+        assert_approx_eq!(undecayed.kl_div(), expected.kl_div());
+
+        // The long path is down to 0.5^60 and the short paths add up to 2
+        let mut halving = BayesCategoricalTracker::new(0, tree.reader());
+        halving.set_decay(0.5);
+        halving.add_path(long_path.clone());
+        for _ in 0..60 {
+            halving.add_path(short_path.clone());
+        }
+        let mut expected = BayesCategoricalTracker::new(0, tree.reader());
+        expected.add_path(short_path.clone());
+        expected.add_path(short_path.clone());
+        assert_approx_eq!(halving.kl_div(), expected.kl_div());
+
+        // Far enough along that the stored evidence gets rescaled
+        let mut fast = BayesCategoricalTracker::new(0, tree.reader());
+        fast.set_decay(0.01);
+        let mut slow = BayesCategoricalTracker::new(0, tree.reader());
+        slow.set_decay(0.01);
+        fast.add_path(long_path.clone());
+        slow.add_path(long_path);
+        for _ in 0..60 {
+            fast.add_path(short_path.clone());
+        }
+        for _ in 0..10 {
+            slow.add_path(short_path.clone());
+        }
+        assert_approx_eq!(fast.kl_div(), slow.kl_div());
+    }
+
+    #[test]
+    fn dirichlet_tree_weighted_stats_test() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let mut tracker = BayesCategoricalTracker::new(0, tree.reader());
+        tracker.add_path(vec![
+            (0.0, (-1, 4)),
+            (0.0, (-2, 2)),
+            (0.0, (-5, 2)),
+            (0.0, (-6, 2)),
+        ]);
+        let stats = tracker.kl_div_stats();
+        let ln_stats = tracker.kl_div_stats_weighted(CoverageWeighting::LnFraction);
+        assert_eq!(stats.nz_count, ln_stats.nz_count);
+        assert_approx_eq!(stats.moment1_nz, ln_stats.moment1_nz);
+        // The weights are at most 1, and the ln weights are larger than the fraction ones
+        assert!(stats.weighted_moment1_nz <= stats.moment1_nz + 1.0e-8);
+        assert!(stats.weight_nz <= ln_stats.weight_nz + 1.0e-8);
+        assert!(ln_stats.weight_nz <= stats.nz_count as f64 + 1.0e-8);
+    }
+
+    #[test]
+    fn stats_fall_back_while_attaching() {
+        let mut tree = build_basic_tree();
+        let path = vec![
+            (0.0, (-1, 4)),
+            (0.0, (-2, 2)),
+            (0.0, (-5, 2)),
+            (0.0, (-6, 2)),
+        ];
+        let mut tracker = BayesCategoricalTracker::new(0, tree.reader());
+        tracker.add_path(path.clone());
+        assert_eq!(
+            tracker.reader().plugin_status::<GokoDirichlet>(),
+            PluginStatus::Detached
+        );
+        assert!(tracker.try_all_node_kl().is_none());
+        let stats = tracker.kl_div_stats();
+        assert_eq!(stats.sequence_len, 1);
+        assert_eq!(stats.nz_count, 0);
+
+        let layer_count = tracker.reader().len();
+        tracker
+            .reader()
+            .parameters()
+            .plugin_attachments
+            .write()
+            .unwrap()
+            .begin::<GokoDirichlet>(layer_count);
+        assert_eq!(
+            tracker.reader().plugin_status::<GokoDirichlet>(),
+            PluginStatus::Attaching {
+                layers_attached: 0,
+                layer_count
+            }
+        );
+        assert!(tracker
+            .try_kl_div_stats_weighted(CoverageWeighting::Fraction)
+            .is_none());
+
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        assert!(tracker.priors_attached());
+        assert!(tracker.kl_div_stats().nz_count > 0);
+    }
+
+    #[test]
+    fn unique_visitors_follow_the_path() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let path = vec![(0.0, (-1, 4)), (0.0, (-2, 2))];
+        let mut tracker = BayesCategoricalTracker::new(2, tree.reader());
+        tracker.add_path_with_id(path.clone(), 0);
+        assert!(tracker.node_visitors().is_none());
+
+        tracker.enable_unique_visitors(DEFAULT_VISITOR_PRECISION);
+        for i in 0..6 {
+            tracker.add_path_with_id(path.clone(), i % 2);
+        }
+        tracker.add_path(vec![(0.0, (-1, 4))]);
+        let root = tracker.unique_visitors((-1, 4)).unwrap();
+        assert_eq!(root.count, 6);
+        assert_approx_eq!(root.distinct, 2.0, 0.1);
+        assert_eq!(tracker.node_visitors().unwrap().len(), 2);
+
+        tracker.reset();
+        assert!(tracker.unique_visitors((-1, 4)).is_none());
+    }
+
+    #[test]
+    fn top_drift_matches_all_node_kl() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let long_path = vec![
+            (0.0, (-1, 4)),
+            (0.0, (-2, 2)),
+            (0.0, (-5, 2)),
+            (0.0, (-6, 2)),
+        ];
+        let mut tracker = BayesCategoricalTracker::new(2, tree.reader());
+        assert!(tracker.top_drift(3).is_none());
+        tracker.enable_top_drift();
+        tracker.add_path(long_path.clone());
+
+        let expected = |tracker: &BayesCategoricalTracker<_>| {
+            let mut node_kls: Vec<(f64, NodeAddress)> = tracker
+                .all_node_kl()
+                .into_iter()
+                .filter(|(kl, _)| *kl > 1.0e-10)
+                .collect();
+            node_kls.sort_by(|a, b| b.partial_cmp(a).unwrap());
+            node_kls
+        };
+        assert_eq!(tracker.top_drift(10).unwrap(), expected(&tracker));
+
+        // These are applied one path at a time, the long path falls out of the window on the last one
+        tracker.add_path(vec![(0.0, (-1, 4))]);
+        tracker.add_path(vec![(0.0, (-1, 4)), (0.0, (-2, 2))]);
+        let top = tracker.top_drift(10).unwrap();
+        assert_eq!(top, expected(&tracker));
+        assert!(top.iter().all(|(_, address)| *address != (-6, 2)));
+        assert_eq!(tracker.top_drift(1).unwrap(), top[..1].to_vec());
+
+        tracker.reset();
+        assert!(tracker.top_drift(10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rollup_kl_adds_up_to_the_root() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let mut tracker = BayesCategoricalTracker::new(0, tree.reader());
+        tracker.add_path(vec![
+            (0.0, (-1, 4)),
+            (0.0, (-2, 2)),
+            (0.0, (-5, 2)),
+            (0.0, (-6, 2)),
+        ]);
+        let root = tree.reader().root_address();
+        let total: f64 = tracker.all_node_kl().iter().map(|(kl, _)| kl).sum();
+        let rolled_up = tracker.rollup_kl(root.0).unwrap();
+        assert_eq!(rolled_up.len(), 1);
+        assert_eq!(rolled_up[0].1, root);
+        assert_approx_eq!(rolled_up[0].0, total);
+
+        let finest = tracker
+            .rollup_kl(tree.reader().scale_range().start)
+            .unwrap();
+        let finest_total: f64 = finest.iter().map(|(kl, _)| kl).sum();
+        assert_approx_eq!(finest_total, total);
+        assert!(finest.windows(2).all(|w| w[0].0 >= w[1].0));
+    }
+
+    #[test]
+    fn marginal_aic_counts_the_touched_nodes() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let mut tracker = BayesCategoricalTracker::new(0, tree.reader());
+        assert_approx_eq!(tracker.marginal_aic(), 0.0);
+        tracker.add_path(vec![
+            (0.0, (-1, 4)),
+            (0.0, (-2, 2)),
+            (0.0, (-5, 2)),
+            (0.0, (-6, 2)),
+        ]);
+        let touched = tracker
+            .running_evidence()
+            .values()
+            .filter(|e| e.total() > 0.0)
+            .count();
+        assert!(touched > 0);
+        assert!(tracker.marginal_aic() >= 2.0 * touched as f64);
+    }
+
+    #[test]
+    fn goodness_of_fit_of_a_skewed_sequence() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let reader = tree.reader();
+        let mut tracker = BayesCategoricalTracker::new(0, reader.clone());
+        let empty = tracker.goodness_of_fit();
+        assert_approx_eq!(empty.chi_squared, 0.0);
+        assert_eq!(empty.chi_squared_p_value, Some(1.0));
+        assert_eq!(empty.node_count, 0);
+
+        // The same point over and over again is not what the training set looks like
+        let path = reader.path(&&[0.499f32][..]).unwrap();
+        for _ in 0..50 {
+            tracker.add_path(path.clone());
+        }
+        let stats = tracker.goodness_of_fit();
+        assert_eq!(stats.sequence_len, 50);
+        assert!(stats.node_count > 0);
+        assert!(stats.degrees_of_freedom >= stats.node_count as f64);
+        assert!(stats.chi_squared > 0.0);
+        assert!(stats.g_statistic > 0.0);
+        assert!(stats.chi_squared_p_value.unwrap() < 0.01);
+        assert!(stats.g_test_p_value.unwrap() < 0.01);
+    }
+
+    #[test]
+    fn goodness_of_fit_has_no_p_values_for_weighted_evidence() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let reader = tree.reader();
+        let path = reader.path(&&[0.499f32][..]).unwrap();
+
+        let mut decayed = BayesCategoricalTracker::new(0, reader.clone());
+        decayed.set_decay(0.9);
+        for _ in 0..50 {
+            decayed.add_path(path.clone());
+        }
+        let stats = decayed.goodness_of_fit();
+        assert!(stats.chi_squared > 0.0);
+        assert!(stats.chi_squared_p_value.is_none());
+        assert!(stats.g_test_p_value.is_none());
+        // The weights are gone with the evidence
+        decayed.reset();
+        decayed.clear_decay();
+        decayed.add_path(path.clone());
+        assert!(decayed.goodness_of_fit().chi_squared_p_value.is_some());
+
+        let mut soft = BayesCategoricalTracker::new(0, reader.clone());
+        soft.add_soft_path(reader.soft_path(&&[0.499f32][..], 1.0).unwrap());
+        assert!(soft.goodness_of_fit().chi_squared_p_value.is_none());
+
+        // A decay of 1 doesn't weigh anything
+        let mut undecayed = BayesCategoricalTracker::new(0, reader);
+        undecayed.set_decay(1.0);
+        undecayed.add_path(path);
+        assert!(undecayed.goodness_of_fit().g_test_p_value.is_some());
+    }
+
+    #[test]
+    fn chi_squared_p_values() {
+        // The 95th percentile of a chi-squared with 1 and 10 degrees of freedom
+        assert_approx_eq!(chi_squared_p_value(3.841459, 1.0), 0.05, 1.0e-5);
+        assert_approx_eq!(chi_squared_p_value(18.307038, 10.0), 0.05, 1.0e-5);
+        assert_approx_eq!(chi_squared_p_value(0.0, 3.0), 1.0);
+        assert_approx_eq!(chi_squared_p_value(5.0, 0.0), 1.0);
+    }
+
+    #[test]
+    fn restored_state_matches_the_tracker() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let reader = tree.reader();
+        let paths: Vec<Vec<(f32, NodeAddress)>> = [0.45f32, 0.1, 0.9, 0.3, 0.7]
+            .iter()
+            .map(|x| reader.path(&&[*x][..]).unwrap())
+            .collect();
+
+        for window_size in &[0, 3] {
+            let mut tracker = BayesCategoricalTracker::new(*window_size, tree.reader());
+            tracker.set_decay(0.9);
+            for path in &paths {
+                tracker.add_path(path.clone());
+            }
+            tracker.add_soft_path(reader.soft_path(&&[0.45f32][..], 1.0).unwrap());
+
+            let queue: Vec<_> = tracker.sequence_queue().iter().cloned().collect();
+            let state: TrackerState =
+                serde_json::from_str(&serde_json::to_string(&tracker.state()).unwrap()).unwrap();
+            let mut restored = BayesCategoricalTracker::new(*window_size, tree.reader());
+            assert!(restored.restore_state(queue, state));
+            assert_eq!(restored.decay(), Some(0.9));
+            assert_eq!(restored.sequence_len(), tracker.sequence_len());
+            assert_approx_eq!(restored.kl_div(), tracker.kl_div());
+
+            // Both keep going the same way, the soft path is evicted from the windowed ones
+            for path in &paths {
+                tracker.add_path(path.clone());
+                restored.add_path(path.clone());
+            }
+            assert_eq!(restored.sequence_len(), tracker.sequence_len());
+            assert_approx_eq!(restored.kl_div(), tracker.kl_div());
+        }
+
+        let mut tracker = BayesCategoricalTracker::new(3, tree.reader());
+        tracker.add_path(paths[0].clone());
+        let state = tracker.state();
+        let mut restored = BayesCategoricalTracker::new(3, tree.reader());
+        assert!(!restored.restore_state(Vec::new(), state));
+        assert_eq!(restored.sequence_len(), 0);
+    }
+
+    #[test]
+    fn soft_paths_leave_the_window() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let reader = tree.reader();
+        let point = [0.45];
+        let mut tracker = BayesCategoricalTracker::new(1, tree.reader());
+        tracker.add_soft_path(reader.soft_path(&&point[..], 1.0).unwrap());
+        let root_total = tracker.running_evidence()[&reader.root_address()].total();
+        assert_approx_eq!(root_total, 1.0, 1.0e-5);
+        assert_eq!(tracker.sequence_len(), 1);
+
+        let hard_path = reader.path(&&point[..]).unwrap();
+        tracker.add_path(hard_path.clone());
+        let mut expected = BayesCategoricalTracker::new(1, tree.reader());
+        expected.add_path(hard_path);
+        for (address, evidence) in tracker.running_evidence() {
+            let expected_total = expected
+                .running_evidence()
+                .get(address)
+                .map(|e| e.total())
+                .unwrap_or(0.0);
+            assert_approx_eq!(evidence.total(), expected_total, 1.0e-5);
+        }
+    }
 }
@@ -0,0 +1,219 @@
+//! # Unique Visitors
+//!
+//! The evidence of a [`BayesCategoricalTracker`](super::tracker::BayesCategoricalTracker) counts how many queries
+//! went through each node, but a single client hammering one region looks the same as many clients visiting it.
+//! This keeps a [`HyperLogLog`] sketch per node, keyed by an id the caller gives each query, so that the tracker
+//! can estimate how many distinct queries went through each node as well.
+//!
+//! A sketch can't forget an id, so the counts here aren't windowed. They cover every query since the unique
+//! visitors were enabled or the tracker was last reset.
+
+use crate::NodeAddress;
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+/// The precision the trackers use by default, 1KB per visited node for about a 3% error.
+pub const DEFAULT_VISITOR_PRECISION: u8 = 10;
+
+/// A HyperLogLog distinct count sketch. It has `2^precision` one byte registers, the standard error of the estimate
+/// is about `1.04 / sqrt(2^precision)`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// An empty sketch. The precision is clamped to between 4 and 16.
+    pub fn new(precision: u8) -> HyperLogLog {
+        let precision = precision.max(4).min(16);
+        HyperLogLog {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    /// Adds an id to the sketch. The id is mixed before it's used, so sequential ids are fine.
+    pub fn insert(&mut self, id: u64) {
+        let hash = mix(id);
+        let index = (hash >> (64 - self.precision)) as usize;
+        // The guard bit caps the run of zeros, so the rank fits in the remaining bits
+        let rest = (hash << self.precision) | (1 << (self.precision - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if self.registers[index] < rank {
+            self.registers[index] = rank;
+        }
+    }
+
+    /// The estimated number of distinct ids added to the sketch.
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let mut inverse_sum = 0.0;
+        let mut zeros = 0;
+        for register in &self.registers {
+            inverse_sum += 2.0f64.powi(-(*register as i32));
+            if *register == 0 {
+                zeros += 1;
+            }
+        }
+        let raw = alpha * m * m / inverse_sum;
+        // Small counts are better estimated by how many registers are still empty
+        if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+
+    /// Folds another sketch into this one, as if its ids were added here. Both need the same precision.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        if self.precision == other.precision {
+            for (mine, theirs) in self.registers.iter_mut().zip(other.registers.iter()) {
+                if *mine < *theirs {
+                    *mine = *theirs;
+                }
+            }
+        }
+    }
+}
+
+/// The splitmix64 finalizer.
+fn mix(id: u64) -> u64 {
+    let mut z = id.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// The visits to a node.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NodeVisitors {
+    /// The node
+    pub address: NodeAddress,
+    /// The number of queries that went through the node
+    pub count: u64,
+    /// The estimated number of distinct query ids among them
+    pub distinct: f64,
+}
+
+#[derive(Debug, Clone)]
+struct NodeSketch {
+    count: u64,
+    sketch: HyperLogLog,
+}
+
+/// The per node sketches of a tracker, see the module docs.
+#[derive(Debug, Clone)]
+pub struct UniqueVisitors {
+    precision: u8,
+    nodes: HashMap<NodeAddress, NodeSketch>,
+}
+
+impl UniqueVisitors {
+    /// No visits yet, each node visited gets a sketch with this precision.
+    pub fn new(precision: u8) -> UniqueVisitors {
+        UniqueVisitors {
+            precision,
+            nodes: HashMap::new(),
+        }
+    }
+
+    /// Records a visit to the node by the query with this id.
+    pub fn visit(&mut self, address: NodeAddress, id: u64) {
+        let precision = self.precision;
+        let node = self.nodes.entry(address).or_insert_with(|| NodeSketch {
+            count: 0,
+            sketch: HyperLogLog::new(precision),
+        });
+        node.count += 1;
+        node.sketch.insert(id);
+    }
+
+    /// The visits to the node, `None` if nothing went through it.
+    pub fn get(&self, address: NodeAddress) -> Option<NodeVisitors> {
+        self.nodes.get(&address).map(|node| NodeVisitors {
+            address,
+            count: node.count,
+            distinct: node.sketch.estimate(),
+        })
+    }
+
+    /// The visits to every node that was visited, sorted by address.
+    pub fn nodes(&self) -> Vec<NodeVisitors> {
+        let mut nodes: Vec<NodeVisitors> = self
+            .nodes
+            .iter()
+            .map(|(address, node)| NodeVisitors {
+                address: *address,
+                count: node.count,
+                distinct: node.sketch.estimate(),
+            })
+            .collect();
+        nodes.sort_by_key(|n| n.address);
+        nodes
+    }
+
+    /// Folds the visits of another set of sketches into this one.
+    pub fn merge(&mut self, other: &UniqueVisitors) {
+        for (address, theirs) in other.nodes.iter() {
+            match self.nodes.get_mut(address) {
+                Some(mine) => {
+                    mine.count += theirs.count;
+                    mine.sketch.merge(&theirs.sketch);
+                }
+                None => {
+                    self.nodes.insert(*address, theirs.clone());
+                }
+            }
+        }
+    }
+
+    /// Forgets every visit.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hyperloglog_estimates_distinct_ids() {
+        let mut sketch = HyperLogLog::new(DEFAULT_VISITOR_PRECISION);
+        for i in 0..10_000u64 {
+            sketch.insert(i % 2_000);
+        }
+        let estimate = sketch.estimate();
+        assert!((estimate - 2_000.0).abs() < 200.0, "estimate {}", estimate);
+
+        let mut small = HyperLogLog::new(DEFAULT_VISITOR_PRECISION);
+        small.insert(7);
+        small.insert(7);
+        small.insert(8);
+        assert!((small.estimate() - 2.0).abs() < 0.1);
+
+        small.merge(&sketch);
+        assert!((small.estimate() - 2_000.0).abs() < 200.0);
+    }
+
+    #[test]
+    fn visitors_count_per_node() {
+        let mut visitors = UniqueVisitors::new(DEFAULT_VISITOR_PRECISION);
+        for i in 0..30 {
+            visitors.visit((0, 0), i % 3);
+        }
+        visitors.visit((-1, 2), 1);
+        let root = visitors.get((0, 0)).unwrap();
+        assert_eq!(root.count, 30);
+        assert!((root.distinct - 3.0).abs() < 0.1);
+        assert_eq!(visitors.nodes().len(), 2);
+        assert!(visitors.get((-2, 2)).is_none());
+    }
+}
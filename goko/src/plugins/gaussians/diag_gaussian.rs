@@ -3,17 +3,35 @@
 //! This computes a coordinate bound multivariate Gaussian. This can be thought of as a rough
 //! simulation of the data underling a node. However we can chose the scale from which we
 //! simulate the data, down to the individual point, so this can be arbitrarily accurate.
+//!
+//! A tracker tells you which nodes drifted. [`DiagGaussian::drift_attribution`] tells you which features drove it,
+//! by comparing the recent queries that went through a drifting node to the node's gaussian one dimension at a time.
 
 use super::*;
 use crate::covertree::node::CoverNode;
 use crate::covertree::CoverTreeReader;
+use serde::{Deserialize, Serialize};
 
 use rand::prelude::*;
 use rand_distr::StandardNormal;
 use std::f32::consts::PI;
 
+/// How far the queries in one dimension are from a node's gaussian, see [`DiagGaussian::drift_attribution`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DimensionDrift {
+    /// The index of the dimension
+    pub dimension: usize,
+    /// The z-score of the mean of the queries, `(query_mean - mean) / sqrt(var / query_count)`. This is infinite if
+    /// the node has no variance in this dimension and the queries moved off its mean.
+    pub z_score: f32,
+    /// The node's mean in this dimension
+    pub mean: f32,
+    /// The mean of the queries in this dimension
+    pub query_mean: f32,
+}
+
 /// Node component, coded in such a way that it can be efficiently, recursively computed.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DiagGaussian {
     /// First Moment
     pub moment1: Vec<f32>,
@@ -134,7 +152,7 @@ impl DiagGaussian {
                 .iter_mut()
                 .zip(point.dense_iter())
                 .for_each(|(m, p)| *m -= p * p);
-            self.count += 1;
+            self.count -= 1;
         }
     }
 
@@ -171,12 +189,92 @@ impl DiagGaussian {
     pub fn count(&self) -> usize {
         self.count
     }
+
+    /// The z-score of each coordinate of the point against this gaussian, `(x - mean) / sqrt(var)`. Returns `None`
+    /// if the point's length isn't this gaussian's dimension.
+    pub fn z_scores<T: PointRef>(&self, point: &T) -> Option<Vec<f32>> {
+        if point.dense_iter().count() != self.dim() {
+            return None;
+        }
+        Some(
+            point
+                .dense_iter()
+                .zip(self.mean().iter().zip(self.var()))
+                .map(|(x, (u, v))| z_score(x - u, v))
+                .collect(),
+        )
+    }
+
+    /// The `k` dimensions the points are the furthest off in, largest absolute z-score first. The points should be
+    /// recent queries that went through the node this gaussian belongs to. Each dimension's z-score is that of the
+    /// points' mean, so it's measured in standard errors and a few points far off in one feature stand out as
+    /// much as many points a little off. Returns nothing if there are no points or the gaussian is empty, and `None`
+    /// if a point's length isn't this gaussian's dimension.
+    pub fn drift_attribution<T: PointRef>(
+        &self,
+        points: &[T],
+        k: usize,
+    ) -> Option<Vec<DimensionDrift>> {
+        if points.iter().any(|p| p.dense_iter().count() != self.dim()) {
+            return None;
+        }
+        if points.is_empty() || self.count == 0 {
+            return Some(Vec::new());
+        }
+        let mut query_moment1 = vec![0.0f32; self.dim()];
+        for point in points {
+            query_moment1
+                .iter_mut()
+                .zip(point.dense_iter())
+                .for_each(|(m, x)| *m += x);
+        }
+        let query_count = points.len() as f32;
+        let mut drifts: Vec<DimensionDrift> = query_moment1
+            .iter()
+            .zip(self.mean().iter().zip(self.var()))
+            .enumerate()
+            .map(|(dimension, (m, (mean, var)))| {
+                let query_mean = m / query_count;
+                DimensionDrift {
+                    dimension,
+                    z_score: z_score(query_mean - mean, var / query_count),
+                    mean: *mean,
+                    query_mean,
+                }
+            })
+            .collect();
+        drifts.sort_by(|a, b| {
+            b.z_score
+                .abs()
+                .partial_cmp(&a.z_score.abs())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        drifts.truncate(k);
+        Some(drifts)
+    }
+}
+
+/// The difference over the standard deviation. With no variance any difference is infinitely unlikely, and the
+/// variance can come out a little negative from rounding.
+fn z_score(diff: f32, var: f32) -> f32 {
+    if var > 0.0 {
+        diff / var.sqrt()
+    } else if diff == 0.0 {
+        0.0
+    } else {
+        diff.signum() * f32::INFINITY
+    }
 }
 
-impl<D: PointCloud> NodePlugin<D> for DiagGaussian {}
+impl<D: PointCloud> NodePlugin<D> for DiagGaussian {
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + (self.moment1.capacity() + self.moment2.capacity()) * std::mem::size_of::<f32>()
+    }
+}
 
 /// Zero sized type that can be passed around. Equivilant to `()`
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GokoDiagGaussian {
     recursive: bool,
 }
@@ -191,6 +289,25 @@ impl GokoDiagGaussian {
     pub fn singletons() -> Self {
         Self { recursive: false }
     }
+
+    /// The `k` dimensions the points are the furthest off in at a node, see [`DiagGaussian::drift_attribution`].
+    /// Pass a node from [`BayesCategoricalTracker::top_drift`](crate::plugins::discrete::tracker::BayesCategoricalTracker::top_drift)
+    /// and the recent queries that went through it to see which features drove the drift. Use this with the
+    /// recursive gaussians, so the node's gaussian covers everything under it.
+    ///
+    /// Returns `None` if the tree doesn't have this plugin, or if a point's length isn't the tree's dimension.
+    pub fn drift_attribution<D: PointCloud, T: PointRef>(
+        reader: &CoverTreeReader<D>,
+        address: NodeAddress,
+        points: &[T],
+        k: usize,
+    ) -> Option<Vec<DimensionDrift>> {
+        reader
+            .get_node_plugin_and::<DiagGaussian, _, _>(address, |gaussian| {
+                gaussian.drift_attribution(points, k)
+            })
+            .flatten()
+    }
 }
 
 impl<D: PointCloud> GokoPlugin<D> for GokoDiagGaussian {
@@ -242,6 +359,34 @@ impl<D: PointCloud> GokoPlugin<D> for GokoDiagGaussian {
         }
         Some(my_dg)
     }
+
+    fn on_insert(
+        parameters: &Self,
+        node_component: &mut Self::NodeComponent,
+        point_index: usize,
+        child: Option<NodeAddress>,
+        point_cloud: &D,
+    ) {
+        if parameters.recursive || child.is_none() {
+            if let Ok(point) = point_cloud.point(point_index) {
+                node_component.add_point(&point);
+            }
+        }
+    }
+
+    fn on_remove(
+        parameters: &Self,
+        node_component: &mut Self::NodeComponent,
+        point_index: usize,
+        child: Option<NodeAddress>,
+        point_cloud: &D,
+    ) {
+        if parameters.recursive || child.is_none() {
+            if let Ok(point) = point_cloud.point(point_index) {
+                node_component.remove_point(&point);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -339,6 +484,64 @@ pub(crate) mod tests {
         });
     }
 
+    #[test]
+    fn drift_attribution_finds_the_shifted_dimension() {
+        let mut gaussian = DiagGaussian::new(3);
+        for x in &[
+            [0.0f32, 1.0, 5.0],
+            [1.0, 2.0, 5.0],
+            [0.5, 1.5, 5.0],
+            [0.5, 1.5, 5.0],
+        ] {
+            gaussian.add_point(&&x[..]);
+        }
+        let queries: Vec<&[f32]> = vec![&[0.5, 3.0, 5.0], &[0.75, 3.5, 5.0]];
+        let drifts = gaussian.drift_attribution(&queries, 2).unwrap();
+        assert_eq!(drifts.len(), 2);
+        assert_eq!(drifts[0].dimension, 1);
+        assert_approx_eq!(drifts[0].query_mean, 3.25);
+        assert_approx_eq!(drifts[0].mean, 1.5);
+        // The variance is 0.125, so the standard error of 2 queries is 0.25
+        assert_approx_eq!(drifts[0].z_score, 7.0);
+        assert_eq!(drifts[1].dimension, 0);
+        assert_approx_eq!(drifts[1].z_score, 0.5);
+        assert!(gaussian
+            .drift_attribution::<&[f32]>(&[], 2)
+            .unwrap()
+            .is_empty());
+
+        // The third dimension never moved, so any shift in it is infinitely unlikely
+        let drifts = gaussian
+            .drift_attribution(&[&[0.5f32, 1.5, 6.0][..]], 1)
+            .unwrap();
+        assert_eq!(drifts[0].dimension, 2);
+        assert!(drifts[0].z_score.is_infinite());
+        assert_eq!(
+            gaussian.z_scores(&&[0.5f32, 1.5, 5.0][..]),
+            Some(vec![0.0, 0.0, 0.0])
+        );
+
+        // A point of the wrong length doesn't line up with the dimensions
+        assert_eq!(gaussian.z_scores(&&[0.5f32, 1.5][..]), None);
+        assert_eq!(gaussian.z_scores(&&[0.5f32, 1.5, 5.0, 1.0][..]), None);
+        let queries: Vec<&[f32]> = vec![&[0.5, 3.0, 5.0], &[0.75, 3.5]];
+        assert!(gaussian.drift_attribution(&queries, 2).is_none());
+    }
+
+    #[test]
+    fn drift_attribution_on_the_tree() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDiagGaussian>(GokoDiagGaussian::recursive());
+        let reader = tree.reader();
+        let queries: Vec<&[f32]> = vec![&[2.0], &[2.5]];
+        let drifts =
+            GokoDiagGaussian::drift_attribution(&reader, reader.root_address(), &queries, 3)
+                .unwrap();
+        assert_eq!(drifts.len(), 1);
+        assert_eq!(drifts[0].dimension, 0);
+        assert!(drifts[0].z_score > 0.0);
+    }
+
     #[test]
     fn diag_gaussian_sanity_check() {
         let mut ct = build_basic_tree();
@@ -0,0 +1,584 @@
+//! # Full Covariance Gaussian
+//!
+//! The [`DiagGaussian`] treats each coordinate as independent, which badly misfits data with correlated features.
+//! This keeps the whole covariance matrix of the points under a node instead. That's `d^2` numbers per node and a
+//! `d^3` factorization of the covariance, so it's only for low dimensional data, see [`MAX_FULL_GAUSSIAN_DIM`]. The
+//! factorization is done the first time a density, divergence or sample needs it and kept until a point is added,
+//! removed or merged in, so repeated queries against a node only pay the `d^2` solves.
+//!
+//! The mean and the sum of the outer products of the deviations from the mean are kept in `f64` and updated with
+//! Welford's method as points come and go, and with Chan's method when two gaussians are merged. These don't subtract
+//! two large sums from each other like the raw moments of the diagonal gaussian do, so the covariance stays accurate
+//! for data that's far from the origin.
+
+use super::*;
+use crate::covertree::node::CoverNode;
+use crate::covertree::CoverTreeReader;
+use serde::{Deserialize, Serialize};
+
+use rand::prelude::*;
+use rand_distr::StandardNormal;
+use std::f64::consts::PI;
+use std::sync::OnceLock;
+
+/// The largest dimension [`GokoFullGaussian`] builds gaussians for. Past this the nodes don't get a component.
+pub const MAX_FULL_GAUSSIAN_DIM: usize = 32;
+
+/// Node component, a gaussian with a full covariance matrix that can be updated a point at a time and merged.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FullGaussian {
+    mean: Vec<f64>,
+    // The sum of the outer products of the deviations from the mean, row major
+    comoment: Vec<f64>,
+    count: usize,
+    // The cholesky factor of the covariance and whether it's full rank, cleared whenever the gaussian changes
+    #[serde(skip)]
+    factor: OnceLock<(Vec<f64>, bool)>,
+}
+
+/// The lower triangular cholesky factor of a positive semi-definite matrix, row major. A pivot that's zero up to
+/// rounding leaves its column at zero, the factor still multiplies out to the matrix but it's not invertible, and the
+/// flag is false.
+fn cholesky(matrix: &[f64], dim: usize) -> (Vec<f64>, bool) {
+    let mut factor = vec![0.0; dim * dim];
+    let mut full_rank = true;
+    let scale = (0..dim)
+        .map(|i| matrix[i * dim + i].abs())
+        .fold(0.0, f64::max);
+    let tolerance = scale * 1.0e-12;
+    for j in 0..dim {
+        let pivot = matrix[j * dim + j]
+            - (0..j)
+                .map(|k| factor[j * dim + k] * factor[j * dim + k])
+                .sum::<f64>();
+        if pivot <= tolerance {
+            full_rank = false;
+            continue;
+        }
+        let pivot = pivot.sqrt();
+        factor[j * dim + j] = pivot;
+        for i in (j + 1)..dim {
+            let dot = (0..j)
+                .map(|k| factor[i * dim + k] * factor[j * dim + k])
+                .sum::<f64>();
+            factor[i * dim + j] = (matrix[i * dim + j] - dot) / pivot;
+        }
+    }
+    (factor, full_rank)
+}
+
+/// Solves `factor * y = x` for a full rank lower triangular factor.
+fn forward_solve(factor: &[f64], dim: usize, x: &[f64]) -> Vec<f64> {
+    let mut y: Vec<f64> = Vec::with_capacity(dim);
+    for (row, xi) in factor.chunks(dim.max(1)).zip(x) {
+        let i = y.len();
+        let dot = row.iter().zip(&y).map(|(l, yk)| l * yk).sum::<f64>();
+        y.push((xi - dot) / row[i]);
+    }
+    y
+}
+
+/// The log determinant of the matrix a full rank cholesky factor came from.
+fn ln_det(factor: &[f64], dim: usize) -> f64 {
+    2.0 * (0..dim).map(|i| factor[i * dim + i].ln()).sum::<f64>()
+}
+
+impl ContinousDistribution for FullGaussian {
+    /// The log density at the point, `None` if the covariance is singular. A node with fewer points than dimensions
+    /// always has a singular covariance.
+    fn ln_pdf<T: PointRef>(&self, point: &T) -> Option<f64> {
+        let dim = self.dim();
+        if self.count == 0 || point.dense_iter().count() != dim {
+            return None;
+        }
+        let (factor, full_rank) = self.factor();
+        if !full_rank {
+            return None;
+        }
+        let diff: Vec<f64> = point
+            .dense_iter()
+            .zip(&self.mean)
+            .map(|(x, u)| x as f64 - u)
+            .collect();
+        let mah_dist = forward_solve(factor, dim, &diff)
+            .iter()
+            .map(|y| y * y)
+            .sum::<f64>();
+        Some(-0.5 * (mah_dist + ln_det(factor, dim) + (dim as f64) * (2.0 * PI).ln()))
+    }
+
+    /// Samples the mean plus the cholesky factor of the covariance times a standard normal vector. This works for
+    /// singular covariances too, the samples stay in the subspace the points are in.
+    fn sample<R: Rng>(&self, rng: &mut R) -> Vec<f32> {
+        let dim = self.dim();
+        let (factor, _) = self.factor();
+        let normal: Vec<f64> = StandardNormal.sample_iter(rng).take(dim).collect();
+        (0..dim)
+            .map(|i| {
+                let offset = (0..=i)
+                    .map(|k| factor[i * dim + k] * normal[k])
+                    .sum::<f64>();
+                (self.mean[i] + offset) as f32
+            })
+            .collect()
+    }
+
+    /// `KL(self || other)`, `None` if either covariance is singular or the dimensions don't match.
+    fn kl_divergence(&self, other: &FullGaussian) -> Option<f64> {
+        let dim = self.dim();
+        if other.dim() != dim || self.count == 0 || other.count == 0 {
+            return None;
+        }
+        let (my_factor, my_full_rank) = self.factor();
+        let (other_factor, other_full_rank) = other.factor();
+        if !my_full_rank || !other_full_rank {
+            return None;
+        }
+        // The trace of other^-1 self is the squared Frobenius norm of other_factor^-1 my_factor
+        let mut trace = 0.0;
+        for j in 0..dim {
+            let column: Vec<f64> = (0..dim).map(|i| my_factor[i * dim + j]).collect();
+            trace += forward_solve(other_factor, dim, &column)
+                .iter()
+                .map(|y| y * y)
+                .sum::<f64>();
+        }
+        let diff: Vec<f64> = other
+            .mean
+            .iter()
+            .zip(&self.mean)
+            .map(|(u, x)| u - x)
+            .collect();
+        let mah_dist = forward_solve(other_factor, dim, &diff)
+            .iter()
+            .map(|y| y * y)
+            .sum::<f64>();
+        let kld = (trace + mah_dist - dim as f64 + ln_det(other_factor, dim)
+            - ln_det(my_factor, dim))
+            / 2.0;
+        // for floating point errors, sometimes this is -0.000000001
+        Some(kld.max(0.0))
+    }
+}
+
+impl FullGaussian {
+    /// Creates a new empty gaussian
+    pub fn new(dim: usize) -> FullGaussian {
+        FullGaussian {
+            mean: vec![0.0; dim],
+            comoment: vec![0.0; dim * dim],
+            count: 0,
+            factor: OnceLock::new(),
+        }
+    }
+
+    /// The cholesky factor of the covariance and whether it's full rank, factored on the first call after a change.
+    fn factor(&self) -> (&[f64], bool) {
+        let (factor, full_rank) = self
+            .factor
+            .get_or_init(|| cholesky(&self.covariance(), self.dim()));
+        (factor, *full_rank)
+    }
+
+    /// Dimension for this
+    pub fn dim(&self) -> usize {
+        self.mean.len()
+    }
+
+    /// Adds `weight` times the outer product of the vector to the comoment.
+    fn add_outer(&mut self, v: &[f64], weight: f64) {
+        let dim = self.dim();
+        for (row, vi) in self.comoment.chunks_mut(dim.max(1)).zip(v) {
+            for (m, vj) in row.iter_mut().zip(v) {
+                *m += weight * vi * vj;
+            }
+        }
+    }
+
+    /// adds a point to the gaussian
+    pub fn add_point<T: PointRef>(&mut self, point: &T) {
+        self.factor = OnceLock::new();
+        self.count += 1;
+        let n = self.count as f64;
+        let delta: Vec<f64> = point
+            .dense_iter()
+            .zip(&self.mean)
+            .map(|(x, u)| x as f64 - u)
+            .collect();
+        self.mean
+            .iter_mut()
+            .zip(&delta)
+            .for_each(|(u, d)| *u += d / n);
+        self.add_outer(&delta, (n - 1.0) / n);
+    }
+
+    /// removes a point from the gaussian, the point should be one that was added
+    pub fn remove_point<T: PointRef>(&mut self, point: &T) {
+        match self.count {
+            0 => {}
+            1 => *self = FullGaussian::new(self.dim()),
+            count => {
+                let n = count as f64;
+                // The mean without the point, and the point's deviation from it
+                let delta: Vec<f64> = point
+                    .dense_iter()
+                    .zip(&self.mean)
+                    .map(|(x, u)| (x as f64 - u) * n / (n - 1.0))
+                    .collect();
+                self.mean
+                    .iter_mut()
+                    .zip(&delta)
+                    .for_each(|(u, d)| *u -= d / n);
+                self.add_outer(&delta, -(n - 1.0) / n);
+                self.count -= 1;
+                self.factor = OnceLock::new();
+            }
+        }
+    }
+
+    /// Merges two gaussians together
+    pub fn merge(&mut self, other: &FullGaussian) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other.clone();
+            return;
+        }
+        self.factor = OnceLock::new();
+        let my_count = self.count as f64;
+        let other_count = other.count as f64;
+        let n = my_count + other_count;
+        let delta: Vec<f64> = other
+            .mean
+            .iter()
+            .zip(&self.mean)
+            .map(|(o, u)| o - u)
+            .collect();
+        self.mean
+            .iter_mut()
+            .zip(&delta)
+            .for_each(|(u, d)| *u += d * other_count / n);
+        self.comoment
+            .iter_mut()
+            .zip(&other.comoment)
+            .for_each(|(m, o)| *m += o);
+        self.add_outer(&delta, my_count * other_count / n);
+        self.count += other.count;
+    }
+
+    /// The mean
+    pub fn mean(&self) -> Vec<f64> {
+        self.mean.clone()
+    }
+
+    /// The covariance, the comoment over the count, row major
+    pub fn covariance(&self) -> Vec<f64> {
+        if self.count > 0 {
+            let n = self.count as f64;
+            self.comoment.iter().map(|m| m / n).collect()
+        } else {
+            vec![0.0; self.comoment.len()]
+        }
+    }
+
+    /// The number of points in this gaussian
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl<D: PointCloud> NodePlugin<D> for FullGaussian {
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + (self.mean.capacity()
+                + self.comoment.capacity()
+                + self.factor.get().map_or(0, |(f, _)| f.capacity()))
+                * std::mem::size_of::<f64>()
+    }
+}
+
+/// Builds a [`FullGaussian`] on each node, the same way [`GokoDiagGaussian`] builds the diagonal ones. The point cloud
+/// can have at most [`MAX_FULL_GAUSSIAN_DIM`] dimensions, otherwise the nodes get no component.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GokoFullGaussian {
+    recursive: bool,
+}
+
+impl GokoFullGaussian {
+    /// Sets this up to build the gaussians recursively, so the gaussian for a node is for the total cover space.
+    pub fn recursive() -> Self {
+        Self { recursive: true }
+    }
+
+    /// Produces a gaussian off of just the singletons attached to the node, not the total cover space
+    pub fn singletons() -> Self {
+        Self { recursive: false }
+    }
+}
+
+impl<D: PointCloud> GokoPlugin<D> for GokoFullGaussian {
+    type NodeComponent = FullGaussian;
+    fn node_component(
+        parameters: &Self,
+        my_node: &CoverNode<D>,
+        my_tree: &CoverTreeReader<D>,
+    ) -> Option<Self::NodeComponent> {
+        let point_cloud = &my_tree.parameters().point_cloud;
+        let dim = point_cloud.dim();
+        if dim > MAX_FULL_GAUSSIAN_DIM {
+            return None;
+        }
+        let mut my_fg = FullGaussian::new(dim);
+        for pi in my_node.singletons() {
+            if let Ok(point) = point_cloud.point(*pi) {
+                my_fg.add_point(&point);
+            }
+        }
+        // If we're a routing node then grab the childen's values
+        if let Some((nested_scale, child_addresses)) = my_node.children() {
+            if parameters.recursive {
+                my_tree.get_node_plugin_and::<Self::NodeComponent, _, _>(
+                    (nested_scale, *my_node.center_index()),
+                    |p| {
+                        my_fg.merge(p);
+                    },
+                );
+                for ca in child_addresses {
+                    my_tree.get_node_plugin_and::<Self::NodeComponent, _, _>(*ca, |p| {
+                        my_fg.merge(p);
+                    });
+                }
+            }
+        } else if let Ok(point) = point_cloud.point(*my_node.center_index()) {
+            my_fg.add_point(&point);
+        }
+        Some(my_fg)
+    }
+
+    fn on_insert(
+        parameters: &Self,
+        node_component: &mut Self::NodeComponent,
+        point_index: usize,
+        child: Option<NodeAddress>,
+        point_cloud: &D,
+    ) {
+        if parameters.recursive || child.is_none() {
+            if let Ok(point) = point_cloud.point(point_index) {
+                node_component.add_point(&point);
+            }
+        }
+    }
+
+    fn on_remove(
+        parameters: &Self,
+        node_component: &mut Self::NodeComponent,
+        point_index: usize,
+        child: Option<NodeAddress>,
+        point_cloud: &D,
+    ) {
+        if parameters.recursive || child.is_none() {
+            if let Ok(point) = point_cloud.point(point_index) {
+                node_component.remove_point(&point);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+    use rand::rngs::SmallRng;
+
+    // Correlated points far from the origin, so the raw moments would lose most of their precision
+    fn correlated_points() -> Vec<[f32; 2]> {
+        (0..20)
+            .map(|i| {
+                let t = i as f32 / 4.0;
+                [1000.0 + t, 2000.0 + 2.0 * t + (i % 3) as f32 * 0.1]
+            })
+            .collect()
+    }
+
+    fn batch_gaussian(points: &[[f32; 2]]) -> (Vec<f64>, Vec<f64>) {
+        let n = points.len() as f64;
+        let mean: Vec<f64> = (0..2)
+            .map(|i| points.iter().map(|p| p[i] as f64).sum::<f64>() / n)
+            .collect();
+        let mut covariance = vec![0.0; 4];
+        for p in points {
+            for (i, (pi, ui)) in p.iter().zip(&mean).enumerate() {
+                for (j, (pj, uj)) in p.iter().zip(&mean).enumerate() {
+                    covariance[i * 2 + j] += (*pi as f64 - ui) * (*pj as f64 - uj) / n;
+                }
+            }
+        }
+        (mean, covariance)
+    }
+
+    fn assert_close(a: &[f64], b: &[f64]) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b) {
+            assert_approx_eq!(x, y, 1.0e-6);
+        }
+    }
+
+    #[test]
+    fn incremental_updates_match_the_batch() {
+        let points = correlated_points();
+        let mut gaussian = FullGaussian::new(2);
+        points.iter().for_each(|p| gaussian.add_point(&&p[..]));
+        let (mean, covariance) = batch_gaussian(&points);
+        assert_eq!(gaussian.count(), 20);
+        assert_close(&gaussian.mean(), &mean);
+        assert_close(&gaussian.covariance(), &covariance);
+
+        points[15..]
+            .iter()
+            .for_each(|p| gaussian.remove_point(&&p[..]));
+        let (mean, covariance) = batch_gaussian(&points[..15]);
+        assert_close(&gaussian.mean(), &mean);
+        assert_close(&gaussian.covariance(), &covariance);
+
+        let mut first = FullGaussian::new(2);
+        let mut second = FullGaussian::new(2);
+        points[..7].iter().for_each(|p| first.add_point(&&p[..]));
+        points[7..].iter().for_each(|p| second.add_point(&&p[..]));
+        first.merge(&second);
+        let (mean, covariance) = batch_gaussian(&points);
+        assert_close(&first.mean(), &mean);
+        assert_close(&first.covariance(), &covariance);
+    }
+
+    #[test]
+    fn ln_pdf_and_kl_of_known_gaussians() {
+        // Four points with mean 0 and covariance [[1, 0.5], [0.5, 1]]
+        let mut gaussian = FullGaussian::new(2);
+        let a = (1.5f32).sqrt();
+        let b = (0.5f32).sqrt();
+        for p in &[[a, a], [-a, -a], [b, -b], [-b, b]] {
+            gaussian.add_point(&&p[..]);
+        }
+        assert_close(&gaussian.covariance(), &[1.0, 0.5, 0.5, 1.0]);
+        let ln_det = 0.75f64.ln();
+        let expected = -0.5 * (ln_det + 2.0 * (2.0 * PI).ln());
+        assert_approx_eq!(
+            gaussian.ln_pdf(&&[0.0f32, 0.0][..]).unwrap(),
+            expected,
+            1.0e-6
+        );
+        // (1, 1) is 4/3 away in the mahalanobis norm squared
+        let expected = -0.5 * (4.0 / 3.0 + ln_det + 2.0 * (2.0 * PI).ln());
+        assert_approx_eq!(
+            gaussian.ln_pdf(&&[1.0f32, 1.0][..]).unwrap(),
+            expected,
+            1.0e-6
+        );
+
+        // The point has to have the gaussian's dimension, not just start with it
+        assert!(gaussian.ln_pdf(&&[1.0f32][..]).is_none());
+        assert!(gaussian.ln_pdf(&&[1.0f32, 1.0, 0.0][..]).is_none());
+
+        assert_approx_eq!(gaussian.kl_divergence(&gaussian).unwrap(), 0.0, 1.0e-9);
+        // Against the standard normal, KL = (tr - d - ln det) / 2
+        let mut standard = FullGaussian::new(2);
+        for p in &[[1.0f32, 0.0], [-1.0, 0.0], [0.0, 1.0], [0.0, -1.0]] {
+            standard.add_point(&&p[..]);
+        }
+        let standard_covariance = standard.covariance();
+        assert_close(&standard_covariance, &[0.5, 0.0, 0.0, 0.5]);
+        let expected = (2.0 / 0.5 - 2.0 + 2.0 * 0.5f64.ln() - ln_det) / 2.0;
+        assert_approx_eq!(gaussian.kl_divergence(&standard).unwrap(), expected, 1.0e-6);
+
+        // A single point has no covariance
+        let mut single = FullGaussian::new(2);
+        single.add_point(&&[1.0f32, 1.0][..]);
+        assert!(single.ln_pdf(&&[1.0f32, 1.0][..]).is_none());
+        assert!(gaussian.kl_divergence(&single).is_none());
+        assert_eq!(
+            single.sample(&mut SmallRng::seed_from_u64(0)),
+            vec![1.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn changes_clear_the_cached_factor() {
+        let points = correlated_points();
+        let fresh = |points: &[[f32; 2]]| {
+            let mut gaussian = FullGaussian::new(2);
+            points.iter().for_each(|p| gaussian.add_point(&&p[..]));
+            gaussian
+        };
+        let query = [1002.0f32, 2004.0];
+        let mut gaussian = fresh(&points[..10]);
+        let mut other = fresh(&points[10..]);
+        let before = gaussian.ln_pdf(&&query[..]).unwrap();
+        assert!(gaussian.kl_divergence(&other).is_some());
+
+        gaussian.add_point(&&points[10][..]);
+        let expected = fresh(&points[..11]);
+        assert_ne!(gaussian.ln_pdf(&&query[..]).unwrap(), before);
+        assert_approx_eq!(
+            gaussian.ln_pdf(&&query[..]).unwrap(),
+            expected.ln_pdf(&&query[..]).unwrap(),
+            1.0e-6
+        );
+
+        gaussian.remove_point(&&points[10][..]);
+        assert_approx_eq!(gaussian.ln_pdf(&&query[..]).unwrap(), before, 1.0e-6);
+
+        other.remove_point(&&points[19][..]);
+        gaussian.merge(&other);
+        let expected = fresh(&points[..19]);
+        assert_approx_eq!(
+            gaussian.ln_pdf(&&query[..]).unwrap(),
+            expected.ln_pdf(&&query[..]).unwrap(),
+            1.0e-6
+        );
+    }
+
+    #[test]
+    fn samples_have_the_covariance() {
+        let points = correlated_points();
+        let mut gaussian = FullGaussian::new(2);
+        points.iter().for_each(|p| gaussian.add_point(&&p[..]));
+        let mut rng = SmallRng::seed_from_u64(0);
+        let samples: Vec<[f32; 2]> = (0..20000)
+            .map(|_| {
+                let s = gaussian.sample(&mut rng);
+                [s[0], s[1]]
+            })
+            .collect();
+        let (mean, covariance) = batch_gaussian(&samples);
+        let expected = gaussian.covariance();
+        for (x, y) in mean.iter().zip(gaussian.mean()) {
+            assert!((x - y).abs() < 0.1);
+        }
+        for (x, y) in covariance.iter().zip(&expected) {
+            assert!((x - y).abs() < 0.05 * expected[0].abs().max(expected[3].abs()));
+        }
+    }
+
+    #[test]
+    fn recursive_full_gaussian_covers_the_tree() {
+        let basic_tree_data = vec![0.499f64, 0.49, 0.48, -0.49, 0.0];
+        let n = basic_tree_data.len() as f64;
+        let mean = basic_tree_data.iter().sum::<f64>() / n;
+        let var = basic_tree_data
+            .iter()
+            .map(|x| (x - mean) * (x - mean))
+            .sum::<f64>()
+            / n;
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoFullGaussian>(GokoFullGaussian::recursive());
+        let reader = tree.reader();
+        reader
+            .get_node_plugin_and::<FullGaussian, _, _>(reader.root_address(), |p| {
+                assert_eq!(p.count(), 5);
+                assert_approx_eq!(p.mean()[0], mean, 1.0e-6);
+                assert_approx_eq!(p.covariance()[0], var, 1.0e-6);
+            })
+            .unwrap();
+    }
+}
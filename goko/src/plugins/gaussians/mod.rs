@@ -9,6 +9,8 @@ use std::fmt::Debug;
 
 mod diag_gaussian;
 pub use diag_gaussian::*;
+mod full_gaussian;
+pub use full_gaussian::*;
 
 /*
 There's an issue with rust-numpy and ndarray causing the linear algebra package for ndarray to fail.
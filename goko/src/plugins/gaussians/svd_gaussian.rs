@@ -44,7 +44,12 @@ impl SvdGaussian {
     }
 }
 
-impl<D: PointCloud> NodePlugin<D> for SvdGaussian {}
+impl<D: PointCloud> NodePlugin<D> for SvdGaussian {
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + (self.mean.len() + self.vt.len() + self.singular_vals.len()) * std::mem::size_of::<f32>()
+    }
+}
 
 /// Zero sized type that can be passed around. Equivilant to `()`
 #[derive(Debug, Clone)]
@@ -4,6 +4,7 @@ use super::*;
 use crate::covertree::node::CoverNode;
 use crate::covertree::CoverTreeReader;
 //use pointcloud::*;
+use pointcloud::summaries::VecSummary;
 use std::sync::Arc;
 
 /// Wrapper around the summary found in the point cloud
@@ -63,6 +64,17 @@ impl<D: PointCloud> GokoPlugin<D> for LabelSummaryPlugin {
             summary: Arc::new(bucket),
         })
     }
+
+    /// Summaries can't forget a label, so there's no `on_remove`. Reattach the plugin after removing points.
+    fn on_insert(
+        _parameters: &Self,
+        node_component: &mut Self::NodeComponent,
+        point_index: usize,
+        _child: Option<NodeAddress>,
+        point_cloud: &D,
+    ) {
+        Arc::make_mut(&mut node_component.summary).add(point_cloud.label(point_index));
+    }
 }
 
 /// Wrapper around the summary found in the point cloud
@@ -122,4 +134,90 @@ impl<D: PointCloud> GokoPlugin<D> for MetaSummaryPlugin {
             summary: Arc::new(bucket),
         })
     }
+
+    /// Summaries can't forget a value, so there's no `on_remove`. Reattach the plugin after removing points.
+    fn on_insert(
+        _parameters: &Self,
+        node_component: &mut Self::NodeComponent,
+        point_index: usize,
+        _child: Option<NodeAddress>,
+        point_cloud: &D,
+    ) {
+        Arc::make_mut(&mut node_component.summary).add(point_cloud.metadata(point_index));
+    }
+}
+
+/// The mean of the vector labels a node covers, for [`CoverTreeReader::regress`].
+#[derive(Debug, Clone, Default)]
+pub struct NodeLabelMean {
+    /// The mean label, empty if the node covers no labeled points
+    pub mean: Vec<f32>,
+    /// The number of labeled points the node covers
+    pub count: usize,
+}
+
+impl NodeLabelMean {
+    fn merge(&mut self, other: &NodeLabelMean) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other.clone();
+            return;
+        }
+        let total = (self.count + other.count) as f32;
+        let self_weight = self.count as f32 / total;
+        let other_weight = other.count as f32 / total;
+        self.mean
+            .iter_mut()
+            .zip(&other.mean)
+            .for_each(|(m, o)| *m = *m * self_weight + o * other_weight);
+        self.count += other.count;
+    }
+}
+
+impl<D: PointCloud> NodePlugin<D> for NodeLabelMean {}
+
+/// Caches the mean of the vector labels under each node, so that regression doesn't read the labels at query time.
+#[derive(Debug, Clone, Default)]
+pub struct LabelMeanPlugin {}
+
+impl<D: PointCloud<LabelSummary = VecSummary>> GokoPlugin<D> for LabelMeanPlugin {
+    type NodeComponent = NodeLabelMean;
+    fn node_component(
+        _parameters: &Self,
+        my_node: &CoverNode<D>,
+        my_tree: &CoverTreeReader<D>,
+    ) -> Option<Self::NodeComponent> {
+        let point_cloud = &my_tree.parameters().point_cloud;
+        let mut bucket = point_cloud
+            .label_summary(my_node.singletons())
+            .ok()?
+            .summary;
+        if my_node.is_leaf() {
+            if let Ok(Some(label)) = point_cloud.label(*my_node.center_index()) {
+                bucket.add(label);
+            }
+        }
+        let mut component = NodeLabelMean {
+            mean: bucket
+                .moment1
+                .iter()
+                .map(|m| m / bucket.count as f32)
+                .collect(),
+            count: bucket.count,
+        };
+        // If we're a routing node then grab the childen's values
+        if let Some((nested_scale, child_addresses)) = my_node.children() {
+            my_tree.get_node_plugin_and::<Self::NodeComponent, _, _>(
+                (nested_scale, *my_node.center_index()),
+                |p| component.merge(p),
+            );
+            for ca in child_addresses {
+                my_tree
+                    .get_node_plugin_and::<Self::NodeComponent, _, _>(*ca, |p| component.merge(p));
+            }
+        }
+        Some(component)
+    }
 }
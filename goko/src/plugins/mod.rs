@@ -8,20 +8,33 @@
 //! plugin for the child nodes.
 //!
 //! None of this is parallelized. We need to move to Tokio to take advantage of the async computation there to || it.
+//! The exception is plugins that are a simple fold over the points, see [`aggregate`].
 
 use crate::covertree::node::CoverNode;
 use crate::covertree::CoverTreeReader;
 use crate::*;
+use hashbrown::HashMap;
+use std::any::TypeId;
 use std::fmt::Debug;
+use std::sync::Arc;
 use type_map::concurrent::TypeMap;
 
+pub mod aggregate;
+pub mod centroids;
 pub mod discrete;
 pub mod gaussians;
 pub mod labels;
+pub mod reservoir;
 pub mod utils;
 
 /// Mockup for the plugin interface attached to the node. These are meant to be functions that Goko uses to maintain the plugin.
-pub trait NodePlugin<D: PointCloud>: Send + Sync + Debug {}
+pub trait NodePlugin<D: PointCloud>: Send + Sync + Debug {
+    /// The bytes this component uses, including what it owns on the heap. The default only counts the component
+    /// itself, override it if the component holds a `Vec` or the like. See [`CoverTreeReader::memory_report`].
+    fn size_hint(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+}
 
 /// Parent trait that make this all work. Ideally this should be included in the `TreePlugin` but rust doesn't like it.
 pub trait GokoPlugin<D: PointCloud>: Send + Sync + Debug + Clone + 'static {
@@ -29,17 +42,184 @@ pub trait GokoPlugin<D: PointCloud>: Send + Sync + Debug + Clone + 'static {
     type NodeComponent: NodePlugin<D> + Clone + 'static;
     /// This is called just before we build the tree to prepare it for the upcomming plugin creations.
     fn prepare_tree(_parameters: &Self, _my_tree: &mut CoverTreeWriter<D>) {}
-    /// The function that actually builds the node components.
+    /// The function that actually builds the node components. This is called on all the nodes of a layer at once,
+    /// from rayon's thread pool, so it must not rely on the order the nodes are visited in. The layers are done
+    /// from the bottom up and each is published before the next starts, so the components of the node's children
+    /// can be read from the tree, but the components of the other nodes in the same layer can't.
     fn node_component(
         parameters: &Self,
         my_node: &CoverNode<D>,
         my_node: &CoverTreeReader<D>,
     ) -> Option<Self::NodeComponent>;
+    /// Called by the writer after a point is added to the tree, once for each node on the path to where the point
+    /// landed. The `child` is the next node on that path, it's `None` on the node that owns the point. The default
+    /// does nothing, so the component goes stale until the plugin is attached again.
+    fn on_insert(
+        _parameters: &Self,
+        _node_component: &mut Self::NodeComponent,
+        _point_index: usize,
+        _child: Option<NodeAddress>,
+        _point_cloud: &D,
+    ) {
+    }
+    /// Called by the writer before a point is removed from the tree, the same way as [`GokoPlugin::on_insert`].
+    fn on_remove(
+        _parameters: &Self,
+        _node_component: &mut Self::NodeComponent,
+        _point_index: usize,
+        _child: Option<NodeAddress>,
+        _point_cloud: &D,
+    ) {
+    }
 }
 
 pub(crate) type NodePluginSet = TypeMap;
 pub(crate) type TreePluginSet = TypeMap;
 
+/// The name of an attached plugin and a function that reads the size of its component off a node, with the plugin's
+/// type erased so the tree can keep a list of them.
+pub(crate) type PluginSizer<D> = (&'static str, fn(&CoverNode<D>) -> usize);
+
+pub(crate) fn plugin_sizer<D: PointCloud, P: GokoPlugin<D>>() -> PluginSizer<D> {
+    (std::any::type_name::<P>(), |node| {
+        node.get_plugin_and(|component: &P::NodeComponent| NodePlugin::<D>::size_hint(component))
+            .unwrap_or(0)
+    })
+}
+
+/// Copies the component of an attached plugin from one node to another, with the plugin's type erased. A node's
+/// `clone` can't copy its components as it doesn't know their types, see [`crate::CoverTreeParameters::clone_node`].
+pub(crate) type PluginCopier<D> = fn(&CoverNode<D>, &mut CoverNode<D>);
+
+pub(crate) fn plugin_copier<D: PointCloud, P: GokoPlugin<D>>() -> PluginCopier<D> {
+    |from, to| {
+        if let Some(component) = from.get_plugin_and(|c: &P::NodeComponent| c.clone()) {
+            to.insert_plugin(component);
+        }
+    }
+}
+
+/// Computes the component of an attached plugin for a node that was made after the plugin was attached, with the
+/// plugin's type erased. The node and its children have to be visible to the reader. Returns the update that puts
+/// the component on the node, `None` if the plugin has no component for it.
+pub(crate) type PluginBuilder<D> =
+    Arc<dyn Fn(&CoverTreeReader<D>, NodeAddress) -> Option<PluginInsert<D>> + Send + Sync>;
+
+/// Puts a computed component on a node, see [`PluginBuilder`].
+pub(crate) type PluginInsert<D> = Box<dyn Fn(&mut CoverNode<D>) + Send + Sync>;
+
+pub(crate) fn plugin_builder<D: PointCloud, P: GokoPlugin<D>>(parameters: P) -> PluginBuilder<D> {
+    Arc::new(
+        move |reader: &CoverTreeReader<D>, address: NodeAddress| -> Option<PluginInsert<D>> {
+            let component = reader
+                .get_node_and(address, |n| P::node_component(&parameters, n, reader))
+                .flatten()?;
+            Some(Box::new(move |node: &mut CoverNode<D>| {
+                node.insert_plugin(component.clone())
+            }))
+        },
+    )
+}
+
+/// How far attaching a plugin has got, see [`CoverTreeReader::plugin_status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluginStatus {
+    /// The plugin was never added to the tree
+    Detached,
+    /// `add_plugin` is running. The layers are attached from the bottom up and published one at a time, so the
+    /// nodes of the layers that aren't done yet don't have a component.
+    Attaching {
+        /// The number of layers whose nodes have their components
+        layers_attached: usize,
+        /// The number of layers in the tree
+        layer_count: usize,
+    },
+    /// Every node has its component
+    Attached,
+}
+
+/// The per-layer progress flags of the plugins that have been added to the tree, keyed by the plugin's type.
+#[derive(Debug, Default)]
+pub struct PluginAttachments {
+    layers: HashMap<TypeId, Vec<bool>>,
+}
+
+impl PluginAttachments {
+    /// An empty set, no plugin is attached.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn begin<P: 'static>(&mut self, layer_count: usize) {
+        self.layers.insert(TypeId::of::<P>(), vec![false; layer_count]);
+    }
+
+    pub(crate) fn layer_attached<P: 'static>(&mut self, layer_index: usize) {
+        if let Some(flags) = self.layers.get_mut(&TypeId::of::<P>()) {
+            flags[layer_index] = true;
+        }
+    }
+
+    /// Adds a layer to the top of the tree. Its nodes get the components of the plugins when they're made.
+    pub(crate) fn add_layer(&mut self) {
+        self.layers.values_mut().for_each(|flags| flags.push(true));
+    }
+
+    /// The status of the plugin.
+    pub fn status<P: 'static>(&self) -> PluginStatus {
+        match self.layers.get(&TypeId::of::<P>()) {
+            None => PluginStatus::Detached,
+            Some(flags) => {
+                let layers_attached = flags.iter().filter(|f| **f).count();
+                if layers_attached == flags.len() {
+                    PluginStatus::Attached
+                } else {
+                    PluginStatus::Attaching {
+                        layers_attached,
+                        layer_count: flags.len(),
+                    }
+                }
+            }
+        }
+    }
+
+    /// If the nodes of the layer have the plugin's component. `layer_index` is the index of the layer in the tree's
+    /// list, see [`crate::CoverTreeParameters::internal_index`].
+    pub fn is_layer_attached<P: 'static>(&self, layer_index: usize) -> bool {
+        self.layers
+            .get(&TypeId::of::<P>())
+            .and_then(|flags| flags.get(layer_index).cloned())
+            .unwrap_or(false)
+    }
+}
+
+/// Which of the lifecycle hooks to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PluginEvent {
+    Insert,
+    Remove,
+}
+
+/// The lifecycle hooks of an attached plugin with the plugin's type erased, so the writer can keep a list of them.
+pub(crate) type PluginHook<D> =
+    Arc<dyn Fn(&mut CoverNode<D>, usize, Option<NodeAddress>, PluginEvent) + Send + Sync>;
+
+pub(crate) fn plugin_hook<D: PointCloud, P: GokoPlugin<D>>(
+    parameters: P,
+    point_cloud: Arc<D>,
+) -> PluginHook<D> {
+    Arc::new(move |node, point_index, child, event| {
+        node.update_plugin::<P::NodeComponent, _>(|component| match event {
+            PluginEvent::Insert => {
+                P::on_insert(&parameters, component, point_index, child, &point_cloud)
+            }
+            PluginEvent::Remove => {
+                P::on_remove(&parameters, component, point_index, child, &point_cloud)
+            }
+        })
+    })
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -118,4 +298,42 @@ pub(crate) mod tests {
             });
         }
     }
+
+    #[test]
+    fn lifecycle_hooks_track_points() {
+        use crate::plugins::discrete::prelude::*;
+        use crate::plugins::gaussians::*;
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        tree.add_plugin::<GokoDiagGaussian>(GokoDiagGaussian::recursive());
+        let reader = tree.reader();
+        let root = reader.root_address();
+        let total = reader
+            .get_node_plugin_and::<Dirichlet, _, _>(root, |p| p.total())
+            .unwrap();
+        let count = reader
+            .get_node_plugin_and::<DiagGaussian, _, _>(root, |p| p.count())
+            .unwrap();
+
+        tree.plugins_on_remove(2).unwrap();
+        assert_eq!(
+            reader.get_node_plugin_and::<Dirichlet, _, _>(root, |p| p.total()),
+            Some(total - 1.0)
+        );
+        assert_eq!(
+            reader.get_node_plugin_and::<DiagGaussian, _, _>(root, |p| p.count()),
+            Some(count - 1)
+        );
+
+        tree.plugins_on_insert(2).unwrap();
+        assert_eq!(
+            reader.get_node_plugin_and::<Dirichlet, _, _>(root, |p| p.total()),
+            Some(total)
+        );
+        assert_eq!(
+            reader.get_node_plugin_and::<DiagGaussian, _, _>(root, |p| p.count()),
+            Some(count)
+        );
+        assert!(tree.plugins_on_insert(100).is_err());
+    }
 }
@@ -0,0 +1,205 @@
+//! # Reservoir Samples
+//!
+//! Keeps a uniform sample of the points each node covers, for when you need representative points of a node rather
+//! than just how many points it covers. A leaf samples its center and singletons directly. A routing node merges the
+//! samples of its children, drawing from each child in proportion to the number of points it covers, so the merged
+//! sample is uniform over the whole node. Inserted points are taken in with reservoir sampling.
+
+use super::*;
+use crate::covertree::node::CoverNode;
+use crate::covertree::CoverTreeReader;
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+
+/// A uniform sample of at most `size` of the points a node covers.
+#[derive(Debug, Clone)]
+pub struct NodeReservoir {
+    samples: Vec<usize>,
+    population: usize,
+    rng: SmallRng,
+}
+
+impl NodeReservoir {
+    /// The sampled point indexes, in no particular order.
+    pub fn samples(&self) -> &[usize] {
+        &self.samples
+    }
+
+    /// The number of points the sample was drawn from.
+    pub fn population(&self) -> usize {
+        self.population
+    }
+}
+
+impl<D: PointCloud> NodePlugin<D> for NodeReservoir {
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<Self>() + self.samples.capacity() * std::mem::size_of::<usize>()
+    }
+}
+
+/// Attaches a [`NodeReservoir`] of `size` points to every node. The samples are drawn from the tree's node streams,
+/// see [`crate::CoverTreeParameters::node_rng`], so they're reproducible if the tree has an `rng_seed`.
+#[derive(Debug, Clone)]
+pub struct ReservoirSample {
+    /// The most points kept for each node
+    pub size: usize,
+}
+
+impl ReservoirSample {
+    /// Keeps `size` points for each node.
+    pub fn new(size: usize) -> ReservoirSample {
+        ReservoirSample { size }
+    }
+}
+
+impl<D: PointCloud> GokoPlugin<D> for ReservoirSample {
+    type NodeComponent = NodeReservoir;
+    fn node_component(
+        parameters: &Self,
+        my_node: &CoverNode<D>,
+        my_tree: &CoverTreeReader<D>,
+    ) -> Option<Self::NodeComponent> {
+        let mut rng = my_tree.parameters().node_rng(my_node.address());
+
+        // Each source is a sample in random order and the number of points it was drawn from
+        let mut sources: Vec<(Vec<usize>, usize)> = Vec::new();
+        let mut direct: Vec<usize> = my_node.singletons().to_vec();
+        match my_node.children() {
+            None => direct.push(*my_node.center_index()),
+            Some((nested_scale, child_addresses)) => {
+                let nested_address = (nested_scale, *my_node.center_index());
+                for ca in std::iter::once(&nested_address).chain(child_addresses) {
+                    my_tree.get_node_plugin_and::<Self::NodeComponent, _, _>(*ca, |p| {
+                        let mut samples = p.samples.clone();
+                        samples.shuffle(&mut rng);
+                        sources.push((samples, p.population));
+                    })?;
+                }
+            }
+        }
+        direct.shuffle(&mut rng);
+        let direct_len = direct.len();
+        sources.push((direct, direct_len));
+
+        let mut remaining: Vec<usize> = sources.iter().map(|(_, population)| *population).collect();
+        let population: usize = remaining.iter().sum();
+        let mut taken = vec![0; sources.len()];
+        let mut samples = Vec::with_capacity(parameters.size.min(population));
+        // Drawing without replacement, a source is picked in proportion to the points of it that haven't been drawn
+        while samples.len() < parameters.size.min(population) {
+            let mut draw = rng.gen_range(0..remaining.iter().sum::<usize>());
+            let source = remaining
+                .iter()
+                .position(|r| {
+                    if draw < *r {
+                        true
+                    } else {
+                        draw -= r;
+                        false
+                    }
+                })
+                .unwrap();
+            samples.push(sources[source].0[taken[source]]);
+            taken[source] += 1;
+            remaining[source] -= 1;
+        }
+        Some(NodeReservoir {
+            samples,
+            population,
+            rng,
+        })
+    }
+
+    fn on_insert(
+        parameters: &Self,
+        node_component: &mut Self::NodeComponent,
+        point_index: usize,
+        _child: Option<NodeAddress>,
+        _point_cloud: &D,
+    ) {
+        node_component.population += 1;
+        if node_component.samples.len() < parameters.size {
+            node_component.samples.push(point_index);
+        } else {
+            let j = node_component.rng.gen_range(0..node_component.population);
+            if j < parameters.size {
+                node_component.samples[j] = point_index;
+            }
+        }
+    }
+
+    /// The sample stays uniform over the remaining points, but it's short a point if the removed point was in it.
+    /// Reattach the plugin to fill the samples back up.
+    fn on_remove(
+        _parameters: &Self,
+        node_component: &mut Self::NodeComponent,
+        point_index: usize,
+        _child: Option<NodeAddress>,
+        _point_cloud: &D,
+    ) {
+        node_component.population = node_component.population.saturating_sub(1);
+        node_component.samples.retain(|pi| *pi != point_index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn reservoirs_sample_the_coverage() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<ReservoirSample>(ReservoirSample::new(2));
+        let reader = tree.reader();
+        for (_si, layer) in reader.layers() {
+            layer.for_each_node(|_pi, n| {
+                n.get_plugin_and::<NodeReservoir, _, _>(|p| {
+                    assert_eq!(p.population(), n.coverage_count());
+                    assert_eq!(p.samples().len(), n.coverage_count().min(2));
+                    let mut samples = p.samples().to_vec();
+                    samples.sort_unstable();
+                    samples.dedup();
+                    assert_eq!(samples.len(), p.samples().len());
+                    if n.is_leaf() && n.singletons_len() == 0 {
+                        assert_eq!(p.samples(), &[*n.center_index()]);
+                    }
+                })
+                .unwrap();
+            });
+        }
+        let root_samples = reader
+            .get_node_plugin_and::<NodeReservoir, _, _>(reader.root_address(), |p| {
+                p.samples().to_vec()
+            })
+            .unwrap();
+        assert!(root_samples.iter().all(|pi| *pi < 5));
+    }
+
+    #[test]
+    fn reservoirs_take_in_inserts() {
+        let mut tree = build_basic_tree();
+        let parameters = ReservoirSample::new(2);
+        tree.add_plugin::<ReservoirSample>(parameters.clone());
+        let reader = tree.reader();
+        let point_cloud = &reader.parameters().point_cloud;
+        let mut reservoir = reader
+            .get_node_plugin_and::<NodeReservoir, _, _>(reader.root_address(), |p| p.clone())
+            .unwrap();
+        for pi in 5..100 {
+            GokoPlugin::on_insert(&parameters, &mut reservoir, pi, None, point_cloud.as_ref());
+        }
+        assert_eq!(reservoir.population(), 100);
+        assert_eq!(reservoir.samples().len(), 2);
+        let removed = reservoir.samples()[0];
+        GokoPlugin::on_remove(
+            &parameters,
+            &mut reservoir,
+            removed,
+            None,
+            point_cloud.as_ref(),
+        );
+        assert_eq!(reservoir.population(), 99);
+        assert_eq!(reservoir.samples().len(), 1);
+    }
+}
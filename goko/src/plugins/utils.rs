@@ -12,7 +12,11 @@ pub struct CoverageIndexes {
     pis: Arc<Vec<usize>>,
 }
 
-impl<D: PointCloud> NodePlugin<D> for CoverageIndexes {}
+impl<D: PointCloud> NodePlugin<D> for CoverageIndexes {
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<Self>() + self.pis.capacity() * std::mem::size_of::<usize>()
+    }
+}
 
 impl CoverageIndexes {
     /// Returns all point indexes that the node covers
@@ -0,0 +1,147 @@
+//! The k nearest neighbor graph of a whole point cloud, the input UMAP and HDBSCAN style pipelines start from.
+
+use super::BulkInterface;
+use crate::*;
+use std::io::Write;
+
+/// The number of points whose neighbors are held in memory at once when the graph is written out.
+const WRITE_CHUNK_LEN: usize = 1 << 14;
+
+/// A k nearest neighbor graph in compressed sparse row form. The neighbors of point `i` are
+/// `indices[offsets[i]..offsets[i + 1]]`, nearest first, and `distances` lines up with `indices`. A point isn't its
+/// own neighbor.
+#[derive(Debug, Clone, Default)]
+pub struct KnnGraph {
+    /// Where each point's row starts, there's one more of these than there are points
+    pub offsets: Vec<usize>,
+    /// The neighbor indexes of all the rows, one after the other
+    pub indices: Vec<usize>,
+    /// The distance to each neighbor
+    pub distances: Vec<f32>,
+}
+
+impl KnnGraph {
+    /// The number of points in the graph
+    pub fn len(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    /// If the graph has no points
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `(distance, index)` pairs of a point's neighbors
+    pub fn neighbors(&self, point_index: usize) -> impl Iterator<Item = (f32, usize)> + '_ {
+        let range = self.offsets[point_index]..self.offsets[point_index + 1];
+        self.distances[range.clone()]
+            .iter()
+            .copied()
+            .zip(self.indices[range].iter().copied())
+    }
+
+    fn push_row(&mut self, row: &[(f32, usize)]) {
+        for (d, i) in row {
+            self.distances.push(*d);
+            self.indices.push(*i);
+        }
+        self.offsets.push(self.indices.len());
+    }
+}
+
+/// The `k` nearest neighbors of a point in the tree's cloud, not counting the point itself.
+fn neighbor_row<D: PointCloud>(
+    reader: &CoverTreeReader<D>,
+    point_index: usize,
+    k: usize,
+) -> GokoResult<Vec<(f32, usize)>> {
+    let point = reader.parameters().point_cloud.point(point_index)?;
+    let mut row = reader.knn(&point, k + 1)?;
+    row.retain(|(_, i)| *i != point_index);
+    row.truncate(k);
+    Ok(row)
+}
+
+impl<D: PointCloud> BulkInterface<D> {
+    /// The `k` nearest neighbors of every point in the point cloud, computed in parallel. Points that were removed
+    /// from the tree still get a row, but they aren't anyone's neighbor.
+    pub fn knn_graph(&self, k: usize) -> GokoResult<KnnGraph> {
+        let indexes: Vec<usize> = (0..self.reader.parameters().point_cloud.len()).collect();
+        let rows = self.index_map_with_reader(&indexes, |reader, i| neighbor_row(reader, i, k));
+        let mut graph = KnnGraph::default();
+        graph.offsets.push(0);
+        for row in rows {
+            graph.push_row(&row?);
+        }
+        Ok(graph)
+    }
+
+    /// Writes the `k` nearest neighbors of every point straight to `writer`, without holding the whole graph in
+    /// memory. Each point gets a fixed width row of `k` little endian `(u64 index, f32 distance)` pairs in point
+    /// order, nearest first. Rows with fewer than `k` neighbors are padded with `(u64::MAX, f32::INFINITY)`.
+    pub fn write_knn_graph<W: Write>(&self, k: usize, writer: &mut W) -> GokoResult<()> {
+        let indexes: Vec<usize> = (0..self.reader.parameters().point_cloud.len()).collect();
+        let mut buffer = Vec::with_capacity(12 * k * WRITE_CHUNK_LEN.min(indexes.len()));
+        for chunk in indexes.chunks(WRITE_CHUNK_LEN) {
+            let rows = self.index_map_with_reader(chunk, |reader, i| neighbor_row(reader, i, k));
+            buffer.clear();
+            for row in rows {
+                let row = row?;
+                let padding = std::iter::repeat((std::f32::INFINITY, u64::MAX));
+                let pairs = row.into_iter().map(|(d, i)| (d, i as u64));
+                for (d, i) in pairs.chain(padding).take(k) {
+                    buffer.extend_from_slice(&i.to_le_bytes());
+                    buffer.extend_from_slice(&d.to_le_bytes());
+                }
+            }
+            writer.write_all(&buffer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn knn_graph_matches_knn() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let interface = BulkInterface::new(tree.reader());
+        let graph = interface.knn_graph(2).unwrap();
+        assert_eq!(graph.len(), 5);
+        let cloud = reader.point_cloud();
+        for pi in 0..5 {
+            let neighbors: Vec<(f32, usize)> = graph.neighbors(pi).collect();
+            assert_eq!(neighbors.len(), 2);
+            assert!(neighbors.iter().all(|(_, i)| *i != pi));
+            let expected: Vec<(f32, usize)> = reader
+                .knn(&cloud.point(pi).unwrap(), 3)
+                .unwrap()
+                .into_iter()
+                .filter(|(_, i)| *i != pi)
+                .take(2)
+                .collect();
+            for ((d1, i1), (d2, i2)) in neighbors.iter().zip(expected) {
+                assert_approx_eq!(*d1, d2);
+                assert_eq!(*i1, i2);
+            }
+        }
+    }
+
+    #[test]
+    fn written_knn_graph_is_padded() {
+        let tree = build_basic_tree();
+        let interface = BulkInterface::new(tree.reader());
+        let mut bytes = Vec::new();
+        interface.write_knn_graph(5, &mut bytes).unwrap();
+        assert_eq!(bytes.len(), 5 * 5 * 12);
+        // Every point has 4 neighbors, so the last pair of each row is padding
+        let last_pair = &bytes[4 * 12..5 * 12];
+        assert_eq!(&last_pair[..8], &u64::MAX.to_le_bytes());
+        assert_eq!(&last_pair[8..], &std::f32::INFINITY.to_le_bytes());
+    }
+}
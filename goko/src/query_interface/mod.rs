@@ -1,20 +1,45 @@
 //! Interfacees that simplify bulk queries
 
 //use crossbeam_channel::unbounded;
+use crate::query_tools::ChildDistanceCache;
 use crate::*;
 use ndarray::ArrayView2;
-use rayon::iter::repeatn;
+use pointcloud::summaries::{CategorySummary, VecSummary};
 use std::ops::Deref;
 
+mod knn_graph;
+pub use knn_graph::KnnGraph;
+
+/// The number of chunks each rayon worker gets, more of them balance the load better but split the work more.
+const CHUNKS_PER_THREAD: usize = 4;
+
 /// Inteface for bulk queries. Handles cloning the readers for you
 pub struct BulkInterface<D: PointCloud> {
     reader: CoverTreeReader<D>,
+    child_distances: Option<ChildDistanceCache>,
 }
 
 impl<D: PointCloud> BulkInterface<D> {
     /// Creates a new one.
     pub fn new(reader: CoverTreeReader<D>) -> Self {
-        BulkInterface { reader }
+        BulkInterface {
+            reader,
+            child_distances: None,
+        }
+    }
+
+    /// Caches the center to child distances of the nodes on the top `depth` layers. The bulk path queries share
+    /// them to skip most of the distance evaluations on the nodes every query passes through. Two or three layers is
+    /// usually enough. See [`ChildDistanceCache`].
+    pub fn cache_child_distances(&mut self, depth: usize) -> GokoResult<()> {
+        self.child_distances = Some(ChildDistanceCache::new(&self.reader, depth)?);
+        Ok(())
+    }
+
+    /// The smallest chunk of a bulk query a rayon worker takes. The readers are cloned when the work is split, not per
+    /// point, so this keeps the clones to a few per worker however many layers the tree has.
+    fn min_chunk_len(&self, len: usize) -> usize {
+        (len / (rayon::current_num_threads() * CHUNKS_PER_THREAD)).max(1)
     }
 
     /// Applies the passed in fn to the passed in indexes and collects the result in a vector. Core function for this struct.
@@ -23,19 +48,11 @@ impl<D: PointCloud> BulkInterface<D> {
         F: Fn(&CoverTreeReader<D>, usize) -> T + Send + Sync,
         T: Send + Sync,
     {
-        let indexes_iter = point_indexes.par_chunks(100);
-        let reader_copies = indexes_iter.len();
-        let mut chunked_results: Vec<Vec<T>> = indexes_iter
-            .zip(repeatn(self.reader.clone(), reader_copies))
-            .map(|(chunk_indexes, reader)| chunk_indexes.iter().map(|p| f(&reader, *p)).collect())
-            .collect();
-        chunked_results
-            .drain(..)
-            .reduce(|mut a, mut x| {
-                a.extend(x.drain(..));
-                a
-            })
-            .unwrap()
+        point_indexes
+            .par_iter()
+            .with_min_len(self.min_chunk_len(point_indexes.len()))
+            .map_with(self.reader.clone(), |reader, p| f(reader, *p))
+            .collect()
     }
 
     /// Applies the passed in fn to the passed in indexes and collects the result in a vector. Core function for this struct.
@@ -48,19 +65,11 @@ impl<D: PointCloud> BulkInterface<D> {
         F: Fn(&CoverTreeReader<D>, &P) -> T + Send + Sync,
         T: Send + Sync,
     {
-        let point_iter = points.par_chunks(100);
-        let reader_copies = point_iter.len();
-        let mut chunked_results: Vec<Vec<T>> = point_iter
-            .zip(repeatn(self.reader.clone(), reader_copies))
-            .map(|(chunk_points, reader)| chunk_points.iter().map(|p| f(&reader, p)).collect())
-            .collect();
-        chunked_results
-            .drain(..)
-            .reduce(|mut a, mut x| {
-                a.extend(x.drain(..));
-                a
-            })
-            .unwrap()
+        points
+            .par_iter()
+            .with_min_len(self.min_chunk_len(points.len()))
+            .map_with(self.reader.clone(), |reader, p| f(reader, p))
+            .collect()
     }
 
     /// Bulk known path
@@ -82,7 +91,20 @@ impl<D: PointCloud> BulkInterface<D> {
         &self,
         points: &[P],
     ) -> Vec<GokoResult<Vec<(f32, NodeAddress)>>> {
-        self.point_map_with_reader(points, |reader, p| reader.path(p))
+        match &self.child_distances {
+            Some(cache) => {
+                self.point_map_with_reader(points, |reader, p| reader.path_cached(p, cache))
+            }
+            None => self.point_map_with_reader(points, |reader, p| reader.path(p)),
+        }
+    }
+
+    /// Bulk path report
+    pub fn path_report<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        points: &[P],
+    ) -> Vec<GokoResult<Vec<PathStep<D>>>> {
+        self.point_map_with_reader(points, |reader, p| reader.path_report(p))
     }
 
     /// Bulk knn
@@ -111,26 +133,42 @@ impl<D: PointCloud<Point = [f32]>> BulkInterface<D> {
         F: Fn(&CoverTreeReader<D>, &&[f32]) -> T + Send + Sync,
         T: Send + Sync,
     {
-        let indexes: Vec<usize> = (0..points.nrows()).collect();
-        let point_iter = indexes.par_chunks(100);
-        let reader_copies = point_iter.len();
-
-        let mut chunked_results: Vec<Vec<T>> = point_iter
-            .zip(repeatn(self.reader.clone(), reader_copies))
-            .map(|(chunk_points, reader)| {
-                chunk_points
-                    .iter()
-                    .map(|i| f(&reader, &points.row(*i).as_slice().unwrap()))
-                    .collect()
-            })
-            .collect();
-        chunked_results
-            .drain(..)
-            .reduce(|mut a, mut x| {
-                a.extend(x.drain(..));
-                a
+        (0..points.nrows())
+            .into_par_iter()
+            .with_min_len(self.min_chunk_len(points.nrows()))
+            .map_with(self.reader.clone(), |reader, i| {
+                f(reader, &points.row(i).as_slice().unwrap())
             })
-            .unwrap()
+            .collect()
+    }
+}
+
+impl<D: PointCloud<Label = i64, LabelSummary = CategorySummary>> BulkInterface<D> {
+    /// Bulk classify, see [`CoverTreeReader::classify`]
+    pub fn classify<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        points: &[P],
+        k: usize,
+    ) -> Vec<GokoResult<Classification>> {
+        self.point_map_with_reader(points, |reader, p| reader.classify(p, k))
+    }
+
+    /// Bulk classify by the label summaries on the paths, see [`CoverTreeReader::classify_path`]
+    pub fn classify_path<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        points: &[P],
+    ) -> Vec<GokoResult<Classification>> {
+        self.point_map_with_reader(points, |reader, p| reader.classify_path(p))
+    }
+}
+
+impl<D: PointCloud<LabelSummary = VecSummary>> BulkInterface<D> {
+    /// Bulk regress, see [`CoverTreeReader::regress`]
+    pub fn regress<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        points: &[P],
+    ) -> Vec<GokoResult<Option<Vec<f32>>>> {
+        self.point_map_with_reader(points, |reader, p| reader.regress(p))
     }
 }
 
@@ -139,7 +177,21 @@ pub(crate) mod tests {
     use super::*;
     use std::env;
 
-    use crate::covertree::tests::build_mnist_tree;
+    use crate::covertree::tests::{build_basic_tree, build_mnist_tree};
+
+    #[test]
+    fn bulk_known_path_keeps_order() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let interface = BulkInterface::new(tree.reader());
+        assert!(interface.known_path(&[]).is_empty());
+
+        let indexes: Vec<usize> = (0..5).rev().collect();
+        let paths = interface.known_path(&indexes);
+        for (i, path) in indexes.iter().zip(paths) {
+            assert_eq!(path.unwrap(), reader.known_path(*i).unwrap());
+        }
+    }
 
     #[test]
     fn bulk_path() {
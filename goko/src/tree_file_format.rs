@@ -39,6 +39,7 @@ pub struct NodeProto {
     pub outlier_point_indexes: ::std::vec::Vec<u64>,
     pub outlier_summary_json: ::std::string::String,
     pub radius: f32,
+    pub plugins: ::std::vec::Vec<u8>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -301,6 +302,32 @@ impl NodeProto {
     pub fn set_radius(&mut self, v: f32) {
         self.radius = v;
     }
+
+    // bytes plugins = 14;
+
+
+    pub fn get_plugins(&self) -> &[u8] {
+        &self.plugins
+    }
+    pub fn clear_plugins(&mut self) {
+        self.plugins.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_plugins(&mut self, v: ::std::vec::Vec<u8>) {
+        self.plugins = v;
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_plugins(&mut self) -> &mut ::std::vec::Vec<u8> {
+        &mut self.plugins
+    }
+
+    // Take field
+    pub fn take_plugins(&mut self) -> ::std::vec::Vec<u8> {
+        ::std::mem::replace(&mut self.plugins, ::std::vec::Vec::new())
+    }
 }
 
 impl ::protobuf::Message for NodeProto {
@@ -383,6 +410,9 @@ impl ::protobuf::Message for NodeProto {
                     let tmp = is.read_float()?;
                     self.radius = tmp;
                 },
+                14 => {
+                    ::protobuf::rt::read_singular_proto3_bytes_into(wire_type, is, &mut self.plugins)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -434,6 +464,9 @@ impl ::protobuf::Message for NodeProto {
         if self.radius != 0. {
             my_size += 5;
         }
+        if !self.plugins.is_empty() {
+            my_size += ::protobuf::rt::bytes_size(14, &self.plugins);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -479,6 +512,9 @@ impl ::protobuf::Message for NodeProto {
         if self.radius != 0. {
             os.write_float(13, self.radius)?;
         }
+        if !self.plugins.is_empty() {
+            os.write_bytes(14, &self.plugins)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -582,6 +618,11 @@ impl ::protobuf::Message for NodeProto {
                 |m: &NodeProto| { &m.radius },
                 |m: &mut NodeProto| { &mut m.radius },
             ));
+            fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
+                "plugins",
+                |m: &NodeProto| { &m.plugins },
+                |m: &mut NodeProto| { &mut m.plugins },
+            ));
             ::protobuf::reflect::MessageDescriptor::new_pb_name::<NodeProto>(
                 "NodeProto",
                 fields,
@@ -611,6 +652,7 @@ impl ::protobuf::Clear for NodeProto {
         self.outlier_point_indexes.clear();
         self.outlier_summary_json.clear();
         self.radius = 0.;
+        self.plugins.clear();
         self.unknown_fields.clear();
     }
 }
@@ -632,6 +674,7 @@ pub struct LayerProto {
     // message fields
     pub scale_index: i32,
     pub nodes: ::protobuf::RepeatedField<NodeProto>,
+    pub removed_indexes: ::std::vec::Vec<u64>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -687,6 +730,31 @@ impl LayerProto {
     pub fn take_nodes(&mut self) -> ::protobuf::RepeatedField<NodeProto> {
         ::std::mem::replace(&mut self.nodes, ::protobuf::RepeatedField::new())
     }
+
+    // repeated uint64 removed_indexes = 3;
+
+
+    pub fn get_removed_indexes(&self) -> &[u64] {
+        &self.removed_indexes
+    }
+    pub fn clear_removed_indexes(&mut self) {
+        self.removed_indexes.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_removed_indexes(&mut self, v: ::std::vec::Vec<u64>) {
+        self.removed_indexes = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_removed_indexes(&mut self) -> &mut ::std::vec::Vec<u64> {
+        &mut self.removed_indexes
+    }
+
+    // Take field
+    pub fn take_removed_indexes(&mut self) -> ::std::vec::Vec<u64> {
+        ::std::mem::replace(&mut self.removed_indexes, ::std::vec::Vec::new())
+    }
 }
 
 impl ::protobuf::Message for LayerProto {
@@ -713,6 +781,9 @@ impl ::protobuf::Message for LayerProto {
                 2 => {
                     ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.nodes)?;
                 },
+                3 => {
+                    ::protobuf::rt::read_repeated_uint64_into(wire_type, is, &mut self.removed_indexes)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -732,6 +803,9 @@ impl ::protobuf::Message for LayerProto {
             let len = value.compute_size();
             my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
         };
+        for value in &self.removed_indexes {
+            my_size += ::protobuf::rt::value_size(3, *value, ::protobuf::wire_format::WireTypeVarint);
+        };
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -746,6 +820,9 @@ impl ::protobuf::Message for LayerProto {
             os.write_raw_varint32(v.get_cached_size())?;
             v.write_to_with_cached_sizes(os)?;
         };
+        for v in &self.removed_indexes {
+            os.write_uint64(3, *v)?;
+        };
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -794,6 +871,11 @@ impl ::protobuf::Message for LayerProto {
                 |m: &LayerProto| { &m.nodes },
                 |m: &mut LayerProto| { &mut m.nodes },
             ));
+            fields.push(::protobuf::reflect::accessor::make_vec_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                "removed_indexes",
+                |m: &LayerProto| { &m.removed_indexes },
+                |m: &mut LayerProto| { &mut m.removed_indexes },
+            ));
             ::protobuf::reflect::MessageDescriptor::new_pb_name::<LayerProto>(
                 "LayerProto",
                 fields,
@@ -812,6 +894,7 @@ impl ::protobuf::Clear for LayerProto {
     fn clear(&mut self) {
         self.scale_index = 0;
         self.nodes.clear();
+        self.removed_indexes.clear();
         self.unknown_fields.clear();
     }
 }
@@ -842,6 +925,7 @@ pub struct CoreProto {
     pub root_index: u64,
     pub layers: ::protobuf::RepeatedField<LayerProto>,
     pub name_map: ::std::collections::HashMap<::std::string::String, u64>,
+    pub plugins: ::std::vec::Vec<u8>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -1053,6 +1137,32 @@ impl CoreProto {
     pub fn take_name_map(&mut self) -> ::std::collections::HashMap<::std::string::String, u64> {
         ::std::mem::replace(&mut self.name_map, ::std::collections::HashMap::new())
     }
+
+    // bytes plugins = 13;
+
+
+    pub fn get_plugins(&self) -> &[u8] {
+        &self.plugins
+    }
+    pub fn clear_plugins(&mut self) {
+        self.plugins.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_plugins(&mut self, v: ::std::vec::Vec<u8>) {
+        self.plugins = v;
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_plugins(&mut self) -> &mut ::std::vec::Vec<u8> {
+        &mut self.plugins
+    }
+
+    // Take field
+    pub fn take_plugins(&mut self) -> ::std::vec::Vec<u8> {
+        ::std::mem::replace(&mut self.plugins, ::std::vec::Vec::new())
+    }
 }
 
 impl ::protobuf::Message for CoreProto {
@@ -1134,6 +1244,9 @@ impl ::protobuf::Message for CoreProto {
                 12 => {
                     ::protobuf::rt::read_map_into::<::protobuf::types::ProtobufTypeString, ::protobuf::types::ProtobufTypeUint64>(wire_type, is, &mut self.name_map)?;
                 },
+                13 => {
+                    ::protobuf::rt::read_singular_proto3_bytes_into(wire_type, is, &mut self.plugins)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -1178,6 +1291,9 @@ impl ::protobuf::Message for CoreProto {
             my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
         };
         my_size += ::protobuf::rt::compute_map_size::<::protobuf::types::ProtobufTypeString, ::protobuf::types::ProtobufTypeUint64>(12, &self.name_map);
+        if !self.plugins.is_empty() {
+            my_size += ::protobuf::rt::bytes_size(13, &self.plugins);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -1217,6 +1333,9 @@ impl ::protobuf::Message for CoreProto {
             v.write_to_with_cached_sizes(os)?;
         };
         ::protobuf::rt::write_map_with_cached_sizes::<::protobuf::types::ProtobufTypeString, ::protobuf::types::ProtobufTypeUint64>(12, &self.name_map, os)?;
+        if !self.plugins.is_empty() {
+            os.write_bytes(13, &self.plugins)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -1310,6 +1429,11 @@ impl ::protobuf::Message for CoreProto {
                 |m: &CoreProto| { &m.name_map },
                 |m: &mut CoreProto| { &mut m.name_map },
             ));
+            fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
+                "plugins",
+                |m: &CoreProto| { &m.plugins },
+                |m: &mut CoreProto| { &mut m.plugins },
+            ));
             ::protobuf::reflect::MessageDescriptor::new_pb_name::<CoreProto>(
                 "CoreProto",
                 fields,
@@ -1337,6 +1461,7 @@ impl ::protobuf::Clear for CoreProto {
         self.root_index = 0;
         self.layers.clear();
         self.name_map.clear();
+        self.plugins.clear();
         self.unknown_fields.clear();
     }
 }
@@ -1354,31 +1479,34 @@ impl ::protobuf::reflect::ProtobufValue for CoreProto {
 }
 
 static file_descriptor_proto_data: &'static [u8] = b"\
-    \n\x16tree_file_format.proto\x12\tCoverTree\"\x99\x04\n\tNodeProto\x12%\
-    \n\x0ecoverage_count\x18\x01\x20\x01(\x04R\rcoverageCount\x12!\n\x0ccent\
-    er_index\x18\x02\x20\x01(\x04R\x0bcenterIndex\x12\x12\n\x04name\x18\x03\
-    \x20\x01(\tR\x04name\x12\x1f\n\x0bscale_index\x18\x04\x20\x01(\x05R\nsca\
-    leIndex\x12.\n\x13parent_center_index\x18\x05\x20\x01(\x04R\x11parentCen\
-    terIndex\x12,\n\x12parent_scale_index\x18\x06\x20\x01(\x05R\x10parentSca\
-    leIndex\x12\x17\n\x07is_leaf\x18\x07\x20\x01(\x08R\x06isLeaf\x124\n\x16c\
-    hildren_point_indexes\x18\x08\x20\x03(\x04R\x14childrenPointIndexes\x124\
-    \n\x16children_scale_indexes\x18\t\x20\x03(\x05R\x14childrenScaleIndexes\
-    \x12,\n\x12nested_scale_index\x18\n\x20\x01(\x05R\x10nestedScaleIndex\
-    \x122\n\x15outlier_point_indexes\x18\x0b\x20\x03(\x04R\x13outlierPointIn\
-    dexes\x120\n\x14outlier_summary_json\x18\x0c\x20\x01(\tR\x12outlierSumma\
-    ryJson\x12\x16\n\x06radius\x18\r\x20\x01(\x02R\x06radius\"Y\n\nLayerProt\
-    o\x12\x1f\n\x0bscale_index\x18\x01\x20\x01(\x05R\nscaleIndex\x12*\n\x05n\
-    odes\x18\x02\x20\x03(\x0b2\x14.CoverTree.NodeProtoR\x05nodes\"\xbf\x03\n\
-    \tCoreProto\x12%\n\x0euse_singletons\x18\x01\x20\x01(\x08R\ruseSingleton\
-    s\x12\x1d\n\nscale_base\x18\x02\x20\x01(\x02R\tscaleBase\x12\x16\n\x06cu\
-    toff\x18\x03\x20\x01(\x04R\x06cutoff\x12\x1e\n\nresolution\x18\x04\x20\
-    \x01(\x11R\nresolution\x12%\n\x0epartition_type\x18\x05\x20\x01(\tR\rpar\
-    titionType\x12\x10\n\x03dim\x18\x07\x20\x01(\x04R\x03dim\x12\x14\n\x05co\
-    unt\x18\x08\x20\x01(\x04R\x05count\x12\x1d\n\nroot_scale\x18\t\x20\x01(\
-    \x05R\trootScale\x12\x1d\n\nroot_index\x18\n\x20\x01(\x04R\trootIndex\
-    \x12-\n\x06layers\x18\x0b\x20\x03(\x0b2\x15.CoverTree.LayerProtoR\x06lay\
-    ers\x12<\n\x08name_map\x18\x0c\x20\x03(\x0b2!.CoverTree.CoreProto.NameMa\
-    pEntryR\x07nameMap\x1a:\n\x0cNameMapEntry\x12\x10\n\x03key\x18\x01\x20\
+    \n\x16tree_file_format.proto\x12\tCoverTree\"\xb3\x04\n\tNodeProto\x12%\
+    \n\x0ecoverage_count\x18\x01\x20\x01(\x04R\rcoverageCount\x12!\n\x0ccen\
+    ter_index\x18\x02\x20\x01(\x04R\x0bcenterIndex\x12\x12\n\x04name\x18\
+    \x03\x20\x01(\tR\x04name\x12\x1f\n\x0bscale_index\x18\x04\x20\x01(\x05R\
+    \nscaleIndex\x12.\n\x13parent_center_index\x18\x05\x20\x01(\x04R\x11par\
+    entCenterIndex\x12,\n\x12parent_scale_index\x18\x06\x20\x01(\x05R\x10pa\
+    rentScaleIndex\x12\x17\n\x07is_leaf\x18\x07\x20\x01(\x08R\x06isLeaf\x12\
+    4\n\x16children_point_indexes\x18\x08\x20\x03(\x04R\x14childrenPointInd\
+    exes\x124\n\x16children_scale_indexes\x18\t\x20\x03(\x05R\x14childrenSc\
+    aleIndexes\x12,\n\x12nested_scale_index\x18\n\x20\x01(\x05R\x10nestedSc\
+    aleIndex\x122\n\x15outlier_point_indexes\x18\x0b\x20\x03(\x04R\x13outli\
+    erPointIndexes\x120\n\x14outlier_summary_json\x18\x0c\x20\x01(\tR\x12ou\
+    tlierSummaryJson\x12\x16\n\x06radius\x18\r\x20\x01(\x02R\x06radius\x12\
+    \x18\n\x07plugins\x18\x0e\x20\x01(\x0cR\x07plugins\"\x82\x01\n\nLayerPr\
+    oto\x12\x1f\n\x0bscale_index\x18\x01\x20\x01(\x05R\nscaleIndex\x12*\n\
+    \x05nodes\x18\x02\x20\x03(\x0b2\x14.CoverTree.NodeProtoR\x05nodes\x12'\
+    \n\x0fremoved_indexes\x18\x03\x20\x03(\x04R\x0eremovedIndexes\"\xd9\x03\
+    \n\tCoreProto\x12%\n\x0euse_singletons\x18\x01\x20\x01(\x08R\ruseSingle\
+    tons\x12\x1d\n\nscale_base\x18\x02\x20\x01(\x02R\tscaleBase\x12\x16\n\
+    \x06cutoff\x18\x03\x20\x01(\x04R\x06cutoff\x12\x1e\n\nresolution\x18\
+    \x04\x20\x01(\x11R\nresolution\x12%\n\x0epartition_type\x18\x05\x20\x01\
+    (\tR\rpartitionType\x12\x10\n\x03dim\x18\x07\x20\x01(\x04R\x03dim\x12\
+    \x14\n\x05count\x18\x08\x20\x01(\x04R\x05count\x12\x1d\n\nroot_scale\
+    \x18\t\x20\x01(\x05R\trootScale\x12\x1d\n\nroot_index\x18\n\x20\x01(\
+    \x04R\trootIndex\x12-\n\x06layers\x18\x0b\x20\x03(\x0b2\x15.CoverTree.L\
+    ayerProtoR\x06layers\x12<\n\x08name_map\x18\x0c\x20\x03(\x0b2!.CoverTre\
+    e.CoreProto.NameMapEntryR\x07nameMap\x12\x18\n\x07plugins\x18\r\x20\x01\
+    (\x0cR\x07plugins\x1a:\n\x0cNameMapEntry\x12\x10\n\x03key\x18\x01\x20\
     \x01(\tR\x03key\x12\x14\n\x05value\x18\x02\x20\x01(\x04R\x05value:\x028\
     \x01b\x06proto3\
 ";
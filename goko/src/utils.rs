@@ -21,18 +21,25 @@
 
 use crate::errors::{GokoError, GokoResult};
 use crate::tree_file_format::*;
-use protobuf::{CodedInputStream, CodedOutputStream, Message};
+use protobuf::{CodedInputStream, CodedOutputStream, Message, RepeatedField};
 use std::fs::File;
 use std::fs::{read_to_string, remove_file, OpenOptions};
+use std::io::{BufWriter, Write};
 use std::path::Path;
 use std::sync::Arc;
+use tracing::info;
 use yaml_rust::YamlLoader;
 
 use crate::builders::CoverTreeBuilder;
+use crate::clustering::{linkage, Linkage};
 
-use crate::CoverTreeWriter;
+use crate::{
+    Coreset, CoverTreeReader, CoverTreeWriter, LoadValidation, PluginCodecs, TreeDiff,
+    ValidationReport,
+};
 
-use pointcloud::loaders::{labeled_ram_from_yaml, ram_from_yaml};
+use pointcloud::loaders::{labeled_ram_from_yaml, named_labeled_ram_from_yaml, ram_from_yaml};
+use pointcloud::name_sources::NameIndex;
 use pointcloud::*;
 
 /// Given a yaml file on disk, it builds a covertree.
@@ -69,9 +76,59 @@ pub fn cover_tree_from_labeled_yaml<P: AsRef<Path>>(
     }
 
     let builder = CoverTreeBuilder::from_yaml(&path);
-    println!(
-        "Loaded dataset, building a cover tree with scale base {}, leaf_cutoff {}, min_res_index {}, and use_singletons {}",
-        &builder.scale_base, &builder.min_res_index, &builder.min_res_index, &builder.use_singletons
+    info!(
+        scale_base = builder.scale_base,
+        leaf_cutoff = builder.leaf_cutoff,
+        min_res_index = builder.min_res_index,
+        use_singletons = builder.use_singletons,
+        "Loaded dataset, building a cover tree"
+    );
+    Ok(builder.build(Arc::new(point_cloud))?)
+}
+
+/// Given a yaml file on disk, it builds a covertree whose points are named by a column of a CSV. The knn and known
+/// path by name queries of a server look the points up by these names.
+///
+/// ```yaml
+/// ---
+/// leaf_cutoff: 5
+/// min_res_index: -10
+/// scale_base: 1.3
+/// data_path: DATAMEMMAPs
+/// labels_path: LABELS_CSV
+/// names_path: NAMES_CSV
+/// count: NUMBER_OF_DATA_POINTS
+/// data_dim: 784
+/// labels_index: 3
+/// names_index: 0
+/// ```
+pub fn cover_tree_from_named_yaml<P: AsRef<Path>>(
+    path: P,
+) -> GokoResult<CoverTreeWriter<SimpleNamedCloud<DefaultLabeledCloud<L2>, NameIndex>>> {
+    let config = read_to_string(&path).expect("Unable to read config file");
+
+    let params_files = YamlLoader::load_from_str(&config).unwrap();
+    let params = &params_files[0];
+
+    let point_cloud = named_labeled_ram_from_yaml::<_, L2>(&path)?;
+    if let Some(count) = params["count"].as_i64() {
+        if count as usize != point_cloud.len() {
+            panic!(
+                "We expected {:?} points, but the file has {:?} points at dim {:?}",
+                count,
+                point_cloud.len(),
+                point_cloud.dim()
+            );
+        }
+    }
+
+    let builder = CoverTreeBuilder::from_yaml(&path);
+    info!(
+        scale_base = builder.scale_base,
+        leaf_cutoff = builder.leaf_cutoff,
+        min_res_index = builder.min_res_index,
+        use_singletons = builder.use_singletons,
+        "Loaded dataset, building a cover tree"
     );
     Ok(builder.build(Arc::new(point_cloud))?)
 }
@@ -108,9 +165,12 @@ pub fn cover_tree_from_yaml<P: AsRef<Path>>(
         }
     }
     let builder = CoverTreeBuilder::from_yaml(&path);
-    println!(
-        "Loaded dataset, building a cover tree with scale base {}, leaf_cutoff {}, min_res_index {}, and use_singletons {}",
-        &builder.scale_base, &builder.min_res_index, &builder.min_res_index, &builder.use_singletons
+    info!(
+        scale_base = builder.scale_base,
+        leaf_cutoff = builder.leaf_cutoff,
+        min_res_index = builder.min_res_index,
+        use_singletons = builder.use_singletons,
+        "Loaded dataset, building a cover tree"
     );
     Ok(builder.build(Arc::new(point_cloud))?)
 }
@@ -120,26 +180,124 @@ pub fn load_tree<P: AsRef<Path>, D: PointCloud>(
     tree_path: P,
     point_cloud: Arc<D>,
 ) -> GokoResult<CoverTreeWriter<D>> {
-    let tree_path_ref: &Path = tree_path.as_ref();
-    println!("\nLoading tree from : {}", tree_path_ref.to_string_lossy());
+    let cover_proto = read_tree_proto(tree_path)?;
+    CoverTreeWriter::load(&cover_proto, point_cloud)
+}
 
-    if !tree_path_ref.exists() {
-        let tree_path_str = match tree_path_ref.to_str() {
-            Some(expr) => expr,
-            None => panic!("Unicode error with the tree path"),
-        };
-        panic!("{} does not exist\n", tree_path_str);
+/// Loads a tree saved with [`save_tree_with_plugins`], attaching the saved plugins that are in `codecs`. See
+/// [`CoverTreeWriter::load_with_plugins`].
+pub fn load_tree_with_plugins<P: AsRef<Path>, D: PointCloud>(
+    tree_path: P,
+    point_cloud: Arc<D>,
+    codecs: &PluginCodecs<D>,
+) -> GokoResult<CoverTreeWriter<D>> {
+    let cover_proto = read_tree_proto(tree_path)?;
+    CoverTreeWriter::load_with_plugins(&cover_proto, point_cloud, codecs)
+}
+
+/// Migrates a tree saved by an older version to the current format, including the trees saved by `grandma`, the
+/// crate goko started as. The tree is loaded, checked and repaired with [`LoadValidation::Repair`], then saved to
+/// `new_tree_path`. The node addresses are still `(scale_index, center_index)` pairs, so they carry over as they are.
+///
+/// The old schema has the same field numbers as [`CoreProto`], the fields added since are empty in an old file. What
+/// the old files leave out is filled in before the tree is loaded: the layers are sorted by scale with the empty ones
+/// added back, and if the root address isn't saved it's the one node of the top layer.
+///
+/// The report lists what was wrong with the old tree. If it has unrepaired violations the new tree is still
+/// written, but its queries over the broken nodes can miss points.
+pub fn migrate_tree<P: AsRef<Path>, Q: AsRef<Path>, D: PointCloud>(
+    old_tree_path: P,
+    new_tree_path: Q,
+    point_cloud: Arc<D>,
+) -> GokoResult<ValidationReport> {
+    let mut cover_proto = read_tree_proto(old_tree_path)?;
+    upgrade_legacy_proto(&mut cover_proto)?;
+    let (tree, report) =
+        CoverTreeWriter::load_validated(&cover_proto, point_cloud, LoadValidation::Repair)?;
+    info!(
+        violations = report.violations.len(),
+        unrepaired = report.unrepaired.len(),
+        "Migrated tree"
+    );
+    save_tree(new_tree_path, &tree)?;
+    Ok(report)
+}
+
+/// Puts an old tree file in the shape [`CoverTreeWriter::load`] reads, which finds the layers by their position. See
+/// [`migrate_tree`].
+fn upgrade_legacy_proto(cover_proto: &mut CoreProto) -> GokoResult<()> {
+    let bottom_scale = cover_proto.get_resolution() - 1;
+    let mut saved_layers = cover_proto.take_layers().into_vec();
+    saved_layers.sort_by_key(|l| l.get_scale_index());
+    if saved_layers
+        .first()
+        .map_or(false, |l| l.get_scale_index() < bottom_scale)
+    {
+        return Err(GokoError::InvalidTreeFile(
+            "a layer is below the minimum resolution",
+        ));
+    }
+    if saved_layers
+        .windows(2)
+        .any(|w| w[0].get_scale_index() == w[1].get_scale_index())
+    {
+        return Err(GokoError::InvalidTreeFile("a layer is saved twice"));
     }
+    let top_scale = saved_layers
+        .last()
+        .map_or(bottom_scale, |l| l.get_scale_index());
 
-    let mut cover_proto = CoreProto::new();
+    let mut saved_layers = saved_layers.into_iter().peekable();
+    let mut layers = Vec::new();
+    for scale_index in bottom_scale..=top_scale {
+        match saved_layers.peek() {
+            Some(layer) if layer.get_scale_index() == scale_index => {
+                layers.extend(saved_layers.next());
+            }
+            _ => {
+                let mut layer = LayerProto::new();
+                layer.set_scale_index(scale_index);
+                layers.push(layer);
+            }
+        }
+    }
 
-    let mut file = File::open(&tree_path_ref).map_err(GokoError::from)?;
-    let mut cis = CodedInputStream::new(&mut file);
-    if let Err(e) = cover_proto.merge_from(&mut cis) {
-        panic!("Proto buff was unable to read {:#?}", e)
+    let root_scale = cover_proto.get_root_scale();
+    let root_index = cover_proto.get_root_index();
+    let root_saved = layers.iter().any(|l| {
+        l.get_scale_index() == root_scale
+            && l.get_nodes()
+                .iter()
+                .any(|n| n.get_center_index() == root_index)
+    });
+    if !root_saved {
+        let top_nodes = layers
+            .iter()
+            .rev()
+            .map(|l| l.get_nodes())
+            .find(|nodes| !nodes.is_empty())
+            .ok_or(GokoError::InvalidTreeFile("there are no nodes"))?;
+        if top_nodes.len() != 1 {
+            return Err(GokoError::InvalidTreeFile(
+                "the root isn't saved and the top layer has several nodes",
+            ));
+        }
+        cover_proto.set_root_scale(top_nodes[0].get_scale_index());
+        cover_proto.set_root_index(top_nodes[0].get_center_index());
     }
+    cover_proto.set_layers(RepeatedField::from_vec(layers));
+    Ok(())
+}
 
-    CoverTreeWriter::load(&cover_proto, point_cloud)
+fn read_tree_proto<P: AsRef<Path>>(tree_path: P) -> GokoResult<CoreProto> {
+    let tree_path_ref: &Path = tree_path.as_ref();
+    info!(path = %tree_path_ref.to_string_lossy(), "Loading tree");
+
+    let mut cover_proto = CoreProto::new();
+    let mut file = File::open(&tree_path_ref).map_err(GokoError::from)?;
+    let mut cis = CodedInputStream::new(&mut file);
+    cover_proto.merge_from(&mut cis).map_err(GokoError::from)?;
+    Ok(cover_proto)
 }
 
 /// Helper function that handles the file I/O and protobuf encoding for you.
@@ -147,20 +305,31 @@ pub fn save_tree<P: AsRef<Path>, D: PointCloud>(
     tree_path: P,
     cover_tree: &CoverTreeWriter<D>,
 ) -> GokoResult<()> {
+    write_tree_proto(tree_path, &cover_tree.save())
+}
+
+/// Saves the tree with the plugins registered in `codecs`, see [`CoverTreeWriter::save_with_plugins`].
+pub fn save_tree_with_plugins<P: AsRef<Path>, D: PointCloud>(
+    tree_path: P,
+    cover_tree: &CoverTreeWriter<D>,
+    codecs: &PluginCodecs<D>,
+) -> GokoResult<()> {
+    write_tree_proto(tree_path, &cover_tree.save_with_plugins(codecs)?)
+}
+
+fn write_tree_proto<P: AsRef<Path>>(tree_path: P, cover_proto: &CoreProto) -> GokoResult<()> {
     let tree_path_ref: &Path = tree_path.as_ref();
 
-    println!("Saving tree to : {}", tree_path_ref.to_string_lossy());
+    info!(path = %tree_path_ref.to_string_lossy(), "Saving tree");
     if tree_path_ref.exists() {
         let tree_path_str = match tree_path_ref.to_str() {
             Some(expr) => expr,
             None => panic!("Unicode error with the tree path"),
         };
-        println!("\t \t {:?} exists, removing", tree_path_str);
+        info!(path = tree_path_str, "Tree file exists, removing");
         remove_file(&tree_path).map_err(GokoError::from)?;
     }
 
-    let cover_proto = cover_tree.save();
-
     let mut core_file = OpenOptions::new()
         .read(true)
         .write(true)
@@ -173,3 +342,142 @@ pub fn save_tree<P: AsRef<Path>, D: PointCloud>(
     cos.flush().map_err(GokoError::from)?;
     Ok(())
 }
+
+/// Writes the tree in the flat format that [`FlatTree::open`](crate::FlatTree::open) memory maps, replacing the
+/// file if it exists.
+pub fn save_flat_tree<P: AsRef<Path>, D: PointCloud>(
+    tree_path: P,
+    cover_tree: &CoverTreeWriter<D>,
+) -> GokoResult<()> {
+    let tree_path_ref: &Path = tree_path.as_ref();
+    info!(path = %tree_path_ref.to_string_lossy(), "Saving flat tree");
+    let mut writer = BufWriter::new(File::create(tree_path_ref)?);
+    cover_tree.save_flat(&mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// The nodes added, removed and moved between two trees built on snapshots of the same dataset, and how the coverage
+/// of the shared nodes shifted. See [`CoverTreeReader::diff`].
+pub fn tree_diff<D: PointCloud, E: PointCloud>(
+    a: &CoverTreeReader<D>,
+    b: &CoverTreeReader<E>,
+) -> TreeDiff {
+    a.diff(b)
+}
+
+/// At most `m` points of the tree with the number of points each stands in for, for sketching the dataset. The
+/// node centers are taken from the top layers down, see [`CoverTreeReader::coreset`].
+pub fn coreset<D: PointCloud>(reader: &CoverTreeReader<D>, m: usize) -> Coreset {
+    reader.coreset(m)
+}
+
+/// The tree as a scipy linkage matrix, for dendrogram tools. See [`Linkage`].
+pub fn linkage_matrix<D: PointCloud>(reader: &CoverTreeReader<D>) -> Linkage {
+    linkage(reader)
+}
+
+/// Writes the tree's dendrogram as a Newick string, replacing the file if it exists. See [`Linkage::to_newick`].
+pub fn save_newick<P: AsRef<Path>, D: PointCloud>(
+    path: P,
+    reader: &CoverTreeReader<D>,
+) -> GokoResult<()> {
+    let path_ref: &Path = path.as_ref();
+    info!(path = %path_ref.to_string_lossy(), "Saving newick dendrogram");
+    let mut writer = BufWriter::new(File::create(path_ref)?);
+    writer.write_all(linkage(reader).to_newick().as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn migrates_a_legacy_tree() {
+        let tree = build_basic_tree();
+        let point_cloud = Arc::clone(&tree.reader().parameters().point_cloud);
+        let dir = std::env::temp_dir();
+        let old_path = dir.join(format!("goko_legacy_{}.tree", std::process::id()));
+        let new_path = dir.join(format!("goko_migrated_{}.tree", std::process::id()));
+
+        // The old files don't have the root address, and only have the layers with nodes in them
+        let mut legacy = tree.save();
+        legacy.clear_root_scale();
+        legacy.clear_root_index();
+        legacy.clear_name_map();
+        let mut layers = legacy.take_layers().into_vec();
+        layers.retain(|l| !l.get_nodes().is_empty());
+        layers.reverse();
+        legacy.set_layers(RepeatedField::from_vec(layers));
+        let mut file = File::create(&old_path).unwrap();
+        let mut cos = CodedOutputStream::new(&mut file);
+        legacy.write_to(&mut cos).unwrap();
+        cos.flush().unwrap();
+        drop(cos);
+
+        let report = migrate_tree(&old_path, &new_path, Arc::clone(&point_cloud)).unwrap();
+        assert!(report.unrepaired.is_empty());
+        let migrated = load_tree(&new_path, Arc::clone(&point_cloud)).unwrap();
+        let (reader, migrated_reader) = (tree.reader(), migrated.reader());
+        assert_eq!(migrated_reader.root_address(), reader.root_address());
+        assert_eq!(migrated_reader.len(), reader.len());
+        assert_eq!(migrated_reader.node_count(), reader.node_count());
+        for pi in 0..point_cloud.len() {
+            let point = point_cloud.point(pi).unwrap();
+            assert_eq!(
+                migrated_reader.path(&point).unwrap(),
+                reader.path(&point).unwrap()
+            );
+        }
+        remove_file(&old_path).ok();
+        remove_file(&new_path).ok();
+    }
+
+    #[test]
+    fn migrates_an_old_format_file() {
+        // A tree over the points of the basic tree, written in the old format. It has no root address, name map or
+        // plugins, and only the layers with nodes in them, out of order. The root (0, 4) has the nested child
+        // (-2, 4), the child (-2, 0) and the singleton 3. The node (-2, 0) has the nested child (-6, 0), which has
+        // the singleton 1, and the singleton 2.
+        let point_cloud = Arc::clone(&build_basic_tree().reader().parameters().point_cloud);
+        let old_path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/legacy_basic.tree");
+        let new_path =
+            std::env::temp_dir().join(format!("goko_fixture_{}.tree", std::process::id()));
+
+        let report = migrate_tree(&old_path, &new_path, Arc::clone(&point_cloud)).unwrap();
+        assert!(report.violations.is_empty());
+        let migrated = load_tree(&new_path, Arc::clone(&point_cloud)).unwrap();
+        let reader = migrated.reader();
+        assert_eq!(reader.root_address(), (0, 4));
+        assert_eq!(reader.node_count(), 4);
+        assert_eq!(
+            reader.get_node_and((-6, 0), |n| n.coverage_count()),
+            Some(2)
+        );
+        for pi in 0..point_cloud.len() {
+            let point = point_cloud.point(pi).unwrap();
+            assert_eq!(reader.knn(&point, 1).unwrap(), vec![(0.0, pi)]);
+        }
+        remove_file(&new_path).ok();
+    }
+
+    #[test]
+    fn unreadable_trees_are_errors() {
+        let point_cloud = Arc::clone(&build_basic_tree().reader().parameters().point_cloud);
+        let path = std::env::temp_dir().join(format!("goko_garbage_{}.tree", std::process::id()));
+        assert!(matches!(
+            load_tree(&path, Arc::clone(&point_cloud)),
+            Err(GokoError::IoError(_))
+        ));
+        std::fs::write(&path, [0xffu8; 16]).unwrap();
+        assert!(matches!(
+            load_tree(&path, point_cloud),
+            Err(GokoError::ProtobufError(_))
+        ));
+        remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,253 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Clouds that take new points after a tree is built on them.
+//!
+//! A tree shares its cloud with every reader, so a cloud that grows has to do it through a shared reference. Wrap
+//! the cloud in an [`AppendableCloud`] and the points appended to it get the indexes after the end of the wrapped
+//! cloud.
+
+use crate::base_traits::*;
+use crate::external_ids::ExternalId;
+use crate::pc_errors::{PointCloudError, PointCloudResult};
+use std::ops::Deref;
+use std::sync::{Arc, RwLock};
+
+/// A cloud that points can be added to through a shared reference.
+pub trait AppendablePointCloud: PointCloud {
+    /// Adds the point to the end of the cloud and returns its index.
+    fn append_point(&self, point: &Self::Point) -> PointCloudResult<usize>;
+}
+
+/// Wraps a cloud and keeps the points appended to it in RAM. The appended points have no label, metadata or name.
+#[derive(Debug)]
+pub struct AppendableCloud<D> {
+    data: D,
+    appended: RwLock<Vec<Arc<[f32]>>>,
+}
+
+impl<D: PointCloud<Point = [f32]>> AppendableCloud<D> {
+    /// Wraps the cloud, nothing is appended yet.
+    pub fn new(data: D) -> Self {
+        AppendableCloud {
+            data,
+            appended: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// The wrapped cloud.
+    pub fn data(&self) -> &D {
+        &self.data
+    }
+
+    /// The number of points appended to the wrapped cloud.
+    pub fn appended_len(&self) -> usize {
+        self.appended.read().unwrap().len()
+    }
+
+    fn appended_index(&self, pi: usize) -> Option<usize> {
+        pi.checked_sub(self.data.len())
+    }
+
+    /// Splits the indexes into the ones of the wrapped cloud and the count of appended ones.
+    fn split_indexes(&self, pns: &[usize]) -> (Vec<usize>, usize) {
+        let data_len = self.data.len();
+        let data_indexes: Vec<usize> = pns.iter().copied().filter(|pi| *pi < data_len).collect();
+        let appended = pns.len() - data_indexes.len();
+        (data_indexes, appended)
+    }
+}
+
+impl<D: PointCloud<Point = [f32]>> AppendablePointCloud for AppendableCloud<D> {
+    fn append_point(&self, point: &[f32]) -> PointCloudResult<usize> {
+        let mut appended = self.appended.write().unwrap();
+        let pi = self.data.len() + appended.len();
+        if point.len() != self.data.dim() {
+            return Err(PointCloudError::data_access(
+                pi,
+                format!(
+                    "Appended a point of dimension {} to a cloud of dimension {}",
+                    point.len(),
+                    self.data.dim()
+                ),
+            ));
+        }
+        appended.push(Arc::from(point));
+        Ok(pi)
+    }
+}
+
+/// A point of an [`AppendableCloud`], either from the wrapped cloud or one of the appended points.
+pub enum AppendedRef<R> {
+    /// A point of the wrapped cloud
+    Data(R),
+    /// An appended point
+    Appended(Arc<[f32]>),
+}
+
+impl<R: Deref<Target = [f32]>> Deref for AppendedRef<R> {
+    type Target = [f32];
+    fn deref(&self) -> &[f32] {
+        match self {
+            AppendedRef::Data(r) => r.deref(),
+            AppendedRef::Appended(p) => &p[..],
+        }
+    }
+}
+
+impl<R: Deref<Target = [f32]> + Send + Sync> PointRef for AppendedRef<R> {
+    type DenseIter = std::vec::IntoIter<f32>;
+    fn dense(&self) -> Vec<f32> {
+        self.deref().to_vec()
+    }
+    fn dense_iter(&self) -> Self::DenseIter {
+        self.dense().into_iter()
+    }
+}
+
+impl<D: PointCloud<Point = [f32]>> PointCloud for AppendableCloud<D> {
+    type Metric = D::Metric;
+    type Point = [f32];
+    type PointRef<'a>
+        = AppendedRef<D::PointRef<'a>>
+    where
+        Self: 'a;
+    type Metadata = D::Metadata;
+    type MetaSummary = D::MetaSummary;
+
+    type Label = D::Label;
+    type LabelSummary = D::LabelSummary;
+
+    #[inline]
+    fn dim(&self) -> usize {
+        self.data.dim()
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len() + self.appended_len()
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn reference_indexes(&self) -> Vec<usize> {
+        let mut indexes = self.data.reference_indexes();
+        indexes.extend(self.data.len()..self.len());
+        indexes
+    }
+    fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<Self::PointRef<'a>> {
+        match self.appended_index(i) {
+            None => Ok(AppendedRef::Data(self.data.point(i)?)),
+            Some(ai) => self
+                .appended
+                .read()
+                .unwrap()
+                .get(ai)
+                .map(|p| AppendedRef::Appended(Arc::clone(p)))
+                .ok_or_else(|| {
+                    PointCloudError::data_access(i, "Index past the end of the cloud".to_string())
+                }),
+        }
+    }
+
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        match self.appended_index(pn) {
+            None => self.data.metadata(pn),
+            Some(_) => Ok(None),
+        }
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        let (data_indexes, appended) = self.split_indexes(pns);
+        let mut summary = self.data.metasummary(&data_indexes)?;
+        summary.nones += appended;
+        Ok(summary)
+    }
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        match self.appended_index(pn) {
+            None => self.data.label(pn),
+            Some(_) => Ok(None),
+        }
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        let (data_indexes, appended) = self.split_indexes(pns);
+        let mut summary = self.data.label_summary(&data_indexes)?;
+        summary.nones += appended;
+        Ok(summary)
+    }
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        match self.appended_index(pi) {
+            None => self.data.name(pi),
+            Some(_) => Err(PointCloudError::UnknownName),
+        }
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        self.data.index(pn)
+    }
+    fn names(&self) -> Vec<String> {
+        self.data.names()
+    }
+    fn timestamp(&self, pi: usize) -> PointCloudResult<Option<u64>> {
+        match self.appended_index(pi) {
+            None => self.data.timestamp(pi),
+            Some(_) => Ok(None),
+        }
+    }
+    fn external_id(&self, pi: usize) -> PointCloudResult<Option<ExternalId>> {
+        match self.appended_index(pi) {
+            None => self.data.external_id(pi),
+            Some(_) => Ok(None),
+        }
+    }
+    fn external_index(&self, id: &ExternalId) -> PointCloudResult<usize> {
+        self.data.external_index(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DefaultLabeledCloud;
+
+    #[test]
+    fn appended_points_follow_the_wrapped_cloud() {
+        let data: Vec<f32> = (0..20).map(|i| i as f32).collect();
+        let labels: Vec<i64> = (0..10).map(|i| i % 3).collect();
+        let cloud = AppendableCloud::new(DefaultLabeledCloud::<crate::L2>::new_simple(
+            data, 2, labels,
+        ));
+        assert_eq!(cloud.len(), 10);
+        assert_eq!(cloud.append_point(&[100.0, 101.0]).unwrap(), 10);
+        assert_eq!(cloud.append_point(&[102.0, 103.0]).unwrap(), 11);
+        assert!(cloud.append_point(&[1.0]).is_err());
+        assert_eq!(cloud.len(), 12);
+        assert_eq!(cloud.appended_len(), 2);
+        assert_eq!(cloud.reference_indexes(), (0..12).collect::<Vec<usize>>());
+
+        assert_eq!(&*cloud.point(3).unwrap(), &[6.0, 7.0]);
+        assert_eq!(&*cloud.point(11).unwrap(), &[102.0, 103.0]);
+        assert!(cloud.point(12).is_err());
+        assert_eq!(cloud.label(3).unwrap(), cloud.data().label(3).unwrap());
+        assert!(cloud.label(10).unwrap().is_none());
+
+        let summary = cloud.label_summary(&[0, 1, 10, 11]).unwrap();
+        let expected = cloud.data().label_summary(&[0, 1]).unwrap();
+        assert_eq!(summary.summary.items, expected.summary.items);
+        assert_eq!(summary.nones, expected.nones + 2);
+    }
+}
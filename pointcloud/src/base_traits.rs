@@ -6,8 +6,13 @@ use std::fmt::Debug;
 
 use std::ops::Deref;
 
+use crate::external_ids::ExternalId;
+use crate::metrics::{BATCH, DISTANCE_BLOCK};
 use crate::pc_errors::*;
+use crate::view::PointCloudView;
 use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+use std::sync::Arc;
 
 /// A trait to ensure that we can create matrices and statiscial vectors from your point reference.
 ///
@@ -31,6 +36,24 @@ pub trait PointRef: Send + Sync {
 pub trait Metric<T: ?Sized>: Send + Sync + 'static {
     /// Distance calculator. Optimize the hell out of this if you're implementing it.
     fn dist(x: &T, y: &T) -> f32;
+    /// The distances from `x` to each of the `ys`. [`PointCloud::distances_to_point`] hands these over
+    /// [`DISTANCE_BLOCK`](crate::metrics::DISTANCE_BLOCK) at a time, override it if comparing a point to several at
+    /// once is faster.
+    fn dists(x: &T, ys: &[&T], dists: &mut [f32]) {
+        for (d, y) in dists.iter_mut().zip(ys) {
+            *d = Self::dist(x, y);
+        }
+    }
+    /// The distances between each of the `xs` and each of the `ys`, row `k` of `dists` is for `xs[k]`.
+    /// [`PointCloud::partial_distance_matrix`] fills its blocks with this.
+    fn dist_block(xs: &[&T], ys: &[&T], dists: &mut [f32]) {
+        if ys.is_empty() {
+            return;
+        }
+        for (row, x) in dists.chunks_mut(ys.len()).zip(xs) {
+            Self::dists(x, ys, row);
+        }
+    }
     // Implemented, but the system that uses this isn't yet.
     //fn norm(x: &RawSparse<f32, u32>) -> f32
 }
@@ -38,10 +61,39 @@ pub trait Metric<T: ?Sized>: Send + Sync + 'static {
 use ndarray::{Array1, Array2};
 
 #[inline]
-fn chunk(data_dim: usize) -> usize {
+pub(crate) fn chunk(data_dim: usize) -> usize {
     min(300000 / data_dim, 100)
 }
 
+/// Fills in the distances from `x` to the points at the indexes, a block of points at a time.
+fn batched_distances<P, T>(
+    point_cloud: &P,
+    x: &T,
+    indexes: &[usize],
+    dists: &mut [f32],
+) -> PointCloudResult<()>
+where
+    P: PointCloud + ?Sized,
+    T: Deref<Target = P::Point>,
+{
+    for (batch, batch_dists) in indexes
+        .chunks(DISTANCE_BLOCK)
+        .zip(dists.chunks_mut(DISTANCE_BLOCK))
+    {
+        let points = batch
+            .iter()
+            .map(|i| point_cloud.point(*i))
+            .collect::<PointCloudResult<SmallVec<[P::PointRef<'_>; BATCH]>>>()?;
+        let ys: SmallVec<[&P::Point; BATCH]> = points.iter().map(|y| y.deref()).collect();
+        P::Metric::dists(x.deref(), &ys, batch_dists);
+    }
+    Ok(())
+}
+
+fn is_sorted(indexes: &[usize]) -> bool {
+    indexes.windows(2).all(|w| w[0] <= w[1])
+}
+
 /// Base trait for a point cloud
 pub trait PointCloud: Send + Sync + 'static {
     /// The derefrenced, raw point. Think [f32]
@@ -49,7 +101,9 @@ pub trait PointCloud: Send + Sync + 'static {
     /// A reference to a point. Think &'a [f32]
     ///
     /// It might be better to move this to the `point` function, but this way we can rely on it for other things.
-    type PointRef<'a>: Deref<Target = Self::Point> + PointRef;
+    type PointRef<'a>: Deref<Target = Self::Point> + PointRef
+    where
+        Self: 'a;
     /// The metric this pointcloud is bound to. Think L2
     type Metric: Metric<Self::Point>;
     /// The label type.
@@ -79,6 +133,20 @@ pub trait PointCloud: Send + Sync + 'static {
     fn index(&self, pn: &str) -> PointCloudResult<usize>;
     /// Gather's all valid known names
     fn names(&self) -> Vec<String>;
+    /// When the point arrived, in whatever unit the cloud was given. `None` if the cloud doesn't keep timestamps,
+    /// see [`TimestampedCloud`].
+    fn timestamp(&self, _pi: usize) -> PointCloudResult<Option<u64>> {
+        Ok(None)
+    }
+    /// The id of the point outside of the cloud, one that survives the data being re-ingested in another order.
+    /// `None` if the cloud doesn't keep them, see [`crate::external_ids::ExternalIdCloud`].
+    fn external_id(&self, _pi: usize) -> PointCloudResult<Option<ExternalId>> {
+        Ok(None)
+    }
+    /// Converts an external id to an index you can use
+    fn external_index(&self, _id: &ExternalId) -> PointCloudResult<usize> {
+        Err(PointCloudError::UnknownExternalId)
+    }
 
     /// The number of samples this cloud covers
     fn len(&self) -> usize;
@@ -109,60 +177,94 @@ pub trait PointCloud: Send + Sync + 'static {
         Ok(Array2::from_shape_vec((indexes.len(), dim), data).unwrap())
     }
 
-    /*
-    /// The main distance function. This paralizes if there are more than 100 points.
-    fn partial_adjacency_matrix(
-        &self,
-        is: &[usize],
-        js: &[usize],
-    ) -> PointCloudResult<AdjMatrix> {
-        if !is.is_sorted() || !js.is_sorted() {
-            return Err(PointCloudError::NotSorted);
-        }
+    /// A view of the points at the indexes, as a cloud indexed `0..len`. Takes a range or any list of indexes, the
+    /// underlying buffers are shared rather than copied. See [`PointCloudView`].
+    fn view<I: IntoIterator<Item = usize>>(
+        self: Arc<Self>,
+        indexes: I,
+    ) -> PointCloudResult<PointCloudView<Self>>
+    where
+        Self: Sized,
+    {
+        PointCloudView::new(self, indexes)
+    }
 
-        let mut vals: Vec<f32> = Vec::new();
-        let mut indexes: Vec<(usize, usize)> = Vec::new();
-        for i in is.iter() {
-            let x = self.point(*i)?;
-            for j in js.iter() {
-                if i < j && !indexes.contains(&(*i, *j)) {
-                    let y = self.point(*j)?;
-                    vals.push(Self::Metric::dist(&x,&y));
-                    indexes.push((*i, *j));
-                } else if j < i && !indexes.contains(&(*j, *i)) {
-                    let y = self.point(*j)?;
-                    vals.push(Self::Metric::dist(&x,&y));
-                    indexes.push((*j, *i));
-                }
-            }
+    /// The distances between every point of `is` and every point of `js`, row `k` is for `is[k]`. The rows are
+    /// computed in parallel blocks, and each block walks the columns a block at a time so the points it reads stay in
+    /// cache.
+    fn partial_distance_matrix(&self, is: &[usize], js: &[usize]) -> PointCloudResult<Array2<f32>> {
+        let mut dists = Array2::<f32>::zeros((is.len(), js.len()));
+        if is.is_empty() || js.is_empty() {
+            return Ok(dists);
         }
-        Ok(AdjMatrix { vals, indexes })
+        let block = chunk(self.dim()).max(1);
+        let error: Mutex<Result<(), PointCloudError>> = Mutex::new(Ok(()));
+        dists
+            .as_slice_mut()
+            .unwrap()
+            .par_chunks_mut(js.len() * block)
+            .zip(is.par_chunks(block))
+            .for_each(|(block_dists, block_is)| {
+                let fill_block = || -> PointCloudResult<()> {
+                    let x_points = block_is
+                        .iter()
+                        .map(|i| self.point(*i))
+                        .collect::<PointCloudResult<Vec<_>>>()?;
+                    let xs: Vec<&Self::Point> = x_points.iter().map(|x| x.deref()).collect();
+                    let mut tile = Vec::new();
+                    for (column_offset, block_js) in js.chunks(block).enumerate() {
+                        let column_offset = column_offset * block;
+                        let y_points = block_js
+                            .iter()
+                            .map(|j| self.point(*j))
+                            .collect::<PointCloudResult<Vec<_>>>()?;
+                        let ys: Vec<&Self::Point> = y_points.iter().map(|y| y.deref()).collect();
+                        tile.resize(xs.len() * ys.len(), 0.0);
+                        Self::Metric::dist_block(&xs, &ys, &mut tile);
+                        for (row, tile_row) in tile.chunks(ys.len()).enumerate() {
+                            let start = row * js.len() + column_offset;
+                            block_dists[start..start + ys.len()].copy_from_slice(tile_row);
+                        }
+                    }
+                    Ok(())
+                };
+                if let Err(e) = fill_block() {
+                    *error.lock().unwrap() = Err(e);
+                }
+            });
+        (error.into_inner().unwrap())?;
+        Ok(dists)
+    }
+
+    /// The dense, symmetric matrix of the distances between the points at the indexes, in the order given.
+    fn distance_matrix(&self, indexes: &[usize]) -> PointCloudResult<Array2<f32>> {
+        self.partial_distance_matrix(indexes, indexes)
     }
 
-    /// Returns a sparse adj matrix for the given points.
-    fn adjacency_matrix(&self, mut indexes: &[usize]) -> PointCloudResult<AdjMatrix> {
-        if !indexes.is_sorted() {
+    /// Returns a sparse adj matrix for the given points, each pair once with the smaller index first. The indexes
+    /// have to be sorted, duplicates are dropped.
+    fn adjacency_matrix(&self, indexes: &[usize]) -> PointCloudResult<AdjMatrix> {
+        if !is_sorted(indexes) {
             return Err(PointCloudError::NotSorted);
         }
+        let mut unique = indexes.to_vec();
+        unique.dedup();
+        let dists = self.distance_matrix(&unique)?;
 
-        let capacity = indexes.len() * (indexes.len() - 1) / 2;
+        let capacity = unique.len() * unique.len().saturating_sub(1) / 2;
         let mut vals = Vec::with_capacity(capacity);
         let mut ret_indexes = Vec::with_capacity(capacity);
-        while indexes.len() > 1 {
-            let i = indexes[0];
-            indexes = &indexes[1..];
-            let distances = self.distances_to_point_index(i, &indexes)?;
-            indexes.iter().zip(distances).for_each(|(j, d)| {
-                ret_indexes.push((i, *j));
-                vals.push(d);
-            });
+        for (k, i) in unique.iter().enumerate() {
+            for (l, j) in unique.iter().enumerate().skip(k + 1) {
+                ret_indexes.push((*i, *j));
+                vals.push(dists[[k, l]]);
+            }
         }
         Ok(AdjMatrix {
             vals,
             indexes: ret_indexes,
         })
     }
-    */
 
     /*
     /// The main distance function. This paralizes if there are more than 100 points.
@@ -234,25 +336,16 @@ pub trait PointCloud: Send + Sync + 'static {
             dist_iter
                 .zip(indexes_iter)
                 .for_each(|(chunk_dists, chunk_indexes)| {
-                    for (d, i) in chunk_dists.iter_mut().zip(chunk_indexes) {
-                        match self.point(*i).map(|y| Self::Metric::dist(&x, &y)) {
-                            Ok(dist) => *d = dist,
-                            Err(e) => {
-                                *error.lock().unwrap() = Err(e);
-                            }
-                        }
+                    if let Err(e) = batched_distances(self, x, chunk_indexes, chunk_dists) {
+                        *error.lock().unwrap() = Err(e);
                     }
                 });
             (error.into_inner().unwrap())?;
             Ok(dists)
         } else {
-            indexes
-                .iter()
-                .map(|i| {
-                    let y = self.point(*i)?;
-                    Ok(Self::Metric::dist(&x, &y))
-                })
-                .collect()
+            let mut dists: Vec<f32> = vec![f32::default(); indexes.len()];
+            batched_distances(self, x, indexes, &mut dists)?;
+            Ok(dists)
         }
     }
 
@@ -346,6 +439,11 @@ pub trait Summary: Serialize + Clone + Debug + Default + Send + Sync + 'static {
     fn combine(&mut self, other: &Self);
     /// The number of elements this summary covers
     fn count(&self) -> usize;
+    /// The Shannon entropy (in nats) of the summarized values, for the summaries of categorical values. `None` for the
+    /// rest.
+    fn entropy(&self) -> Option<f32> {
+        None
+    }
 }
 
 impl Summary for () {
@@ -447,7 +545,10 @@ impl<D: PointCloud, L: LabelSet> PointCloud for SimpleLabeledCloud<D, L> {
     /// Underlying metric this point cloud uses
     type Metric = D::Metric;
     type Point = D::Point;
-    type PointRef<'a> = D::PointRef<'a>;
+    type PointRef<'a>
+        = D::PointRef<'a>
+    where
+        Self: 'a;
     type Metadata = D::Metadata;
     type MetaSummary = D::MetaSummary;
 
@@ -505,6 +606,15 @@ impl<D: PointCloud, L: LabelSet> PointCloud for SimpleLabeledCloud<D, L> {
     fn names(&self) -> Vec<String> {
         self.data.names()
     }
+    fn timestamp(&self, pi: usize) -> PointCloudResult<Option<u64>> {
+        self.data.timestamp(pi)
+    }
+    fn external_id(&self, pi: usize) -> PointCloudResult<Option<ExternalId>> {
+        self.data.external_id(pi)
+    }
+    fn external_index(&self, id: &ExternalId) -> PointCloudResult<usize> {
+        self.data.external_index(id)
+    }
 }
 
 /// Enables the points in the underlying cloud to be named with strings.
@@ -541,7 +651,10 @@ impl<D: PointCloud, N: NamedSet> PointCloud for SimpleNamedCloud<D, N> {
     /// Underlying metric this point cloud uses
     type Metric = D::Metric;
     type Point = D::Point;
-    type PointRef<'a> = D::PointRef<'a>;
+    type PointRef<'a>
+        = D::PointRef<'a>
+    where
+        Self: 'a;
     type Metadata = D::Metadata;
     type MetaSummary = D::MetaSummary;
 
@@ -599,6 +712,100 @@ impl<D: PointCloud, N: NamedSet> PointCloud for SimpleNamedCloud<D, N> {
     fn names(&self) -> Vec<String> {
         self.names.names()
     }
+    fn timestamp(&self, pi: usize) -> PointCloudResult<Option<u64>> {
+        self.data.timestamp(pi)
+    }
+    fn external_id(&self, pi: usize) -> PointCloudResult<Option<ExternalId>> {
+        self.data.external_id(pi)
+    }
+    fn external_index(&self, id: &ExternalId) -> PointCloudResult<usize> {
+        self.data.external_index(id)
+    }
+}
+
+/// Attaches a timestamp to every point of the underlying cloud, so that old points can be aged out of a tree.
+#[derive(Debug)]
+pub struct TimestampedCloud<D> {
+    data: D,
+    timestamps: Vec<u64>,
+}
+
+impl<D: PointCloud> TimestampedCloud<D> {
+    /// Creates a new one, there has to be a timestamp per point
+    pub fn new(data: D, timestamps: Vec<u64>) -> Self {
+        assert_eq!(timestamps.len(), data.len());
+        TimestampedCloud { data, timestamps }
+    }
+}
+
+impl<D: PointCloud> PointCloud for TimestampedCloud<D> {
+    /// Underlying metric this point cloud uses
+    type Metric = D::Metric;
+    type Point = D::Point;
+    type PointRef<'a>
+        = D::PointRef<'a>
+    where
+        Self: 'a;
+    type Metadata = D::Metadata;
+    type MetaSummary = D::MetaSummary;
+
+    type Label = D::Label;
+    type LabelSummary = D::LabelSummary;
+
+    #[inline]
+    fn dim(&self) -> usize {
+        self.data.dim()
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    #[inline]
+    fn reference_indexes(&self) -> Vec<usize> {
+        self.data.reference_indexes()
+    }
+    #[inline]
+    fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<Self::PointRef<'a>> {
+        self.data.point(i)
+    }
+
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        self.data.metadata(pn)
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        self.data.metasummary(pns)
+    }
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        self.data.label(pn)
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        self.data.label_summary(pns)
+    }
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        self.data.name(pi)
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        self.data.index(pn)
+    }
+    fn names(&self) -> Vec<String> {
+        self.data.names()
+    }
+    fn timestamp(&self, pi: usize) -> PointCloudResult<Option<u64>> {
+        match self.timestamps.get(pi) {
+            Some(t) => Ok(Some(*t)),
+            None => Err(PointCloudError::data_access(pi, "no timestamp".to_string())),
+        }
+    }
+    fn external_id(&self, pi: usize) -> PointCloudResult<Option<ExternalId>> {
+        self.data.external_id(pi)
+    }
+    fn external_index(&self, id: &ExternalId) -> PointCloudResult<usize> {
+        self.data.external_index(id)
+    }
 }
 
 /// Allows for expensive metadata, this is identical to the label trait, but enables slower update
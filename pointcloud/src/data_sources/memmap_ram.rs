@@ -121,7 +121,10 @@ macro_rules! make_point_cloud {
         impl<M: Metric<[f32]>> PointCloud for $name<M> {
             type Metric = M;
             type Point = [f32];
-            type PointRef<'a> = &'a [f32];
+            type PointRef<'a>
+                = &'a [f32]
+            where
+                Self: 'a;
             type LabelSummary = ();
             type Label = ();
             type MetaSummary = ();
@@ -274,7 +277,6 @@ pub mod tests {
         }
     }
 
-    /*
     #[test]
     fn adjacency_correct() {
         let pc = build_ram_fixed_test(10, 5);
@@ -282,7 +284,6 @@ pub mod tests {
         let indexes: [usize; 5] = [1, 3, 5, 7, 9];
 
         let adj = pc.adjacency_matrix(&indexes).unwrap();
-        println!("{:?}", adj);
         for val1 in &indexes {
             for val2 in &indexes {
                 let diff = *val1 as f32 - *val2 as f32;
@@ -293,7 +294,42 @@ pub mod tests {
             }
         }
     }
-    */
+
+    #[test]
+    fn adjacency_needs_sorted_indexes() {
+        let pc = build_ram_fixed_test(10, 5);
+
+        assert!(matches!(
+            pc.adjacency_matrix(&[3, 1, 3]),
+            Err(PointCloudError::NotSorted)
+        ));
+        let adj = pc.adjacency_matrix(&[1, 3, 3, 5]).unwrap();
+        assert_eq!(adj.indexes, vec![(1, 3), (1, 5), (3, 5)]);
+    }
+
+    #[test]
+    fn distance_matrix_correct() {
+        let pc = build_ram_fixed_test(10, 5);
+
+        let is: [usize; 3] = [9, 0, 4];
+        let js: [usize; 2] = [1, 4];
+        let dists = pc.partial_distance_matrix(&is, &js).unwrap();
+        assert_eq!(dists.shape(), &[3, 2]);
+        for (k, i) in is.iter().enumerate() {
+            for (l, j) in js.iter().enumerate() {
+                let diff = *i as f32 - *j as f32;
+                assert_approx_eq!((5.0 * diff * diff).sqrt(), dists[[k, l]]);
+            }
+        }
+
+        let square = pc.distance_matrix(&is).unwrap();
+        for k in 0..3 {
+            assert_approx_eq!(square[[k, k]], 0.0);
+            for l in 0..3 {
+                assert_approx_eq!(square[[k, l]], square[[l, k]]);
+            }
+        }
+    }
 
     #[test]
     fn distance_correct() {
@@ -18,13 +18,18 @@
 */
 
 //! Some data sources and a trait to dimension and uniformly reference the data contained.
-//! The only currently supported are memmaps and ram blobs.
+//! The only currently supported are memmaps and ram blobs, and sliding windows over a signal. A [`PinnedCloud`] keeps
+//! a RAM copy of the hot points of a memmap.
 
 mod memmap_ram;
+mod pinned;
+mod sliding_window;
 mod sparse_ram;
 
 #[allow(dead_code)]
-mod memmapf32;
+pub(crate) mod memmapf32;
 
 #[doc(hidden)]
 pub use memmap_ram::*;
+pub use pinned::{PinCache, PinStats, PinnedCloud, PinnedRef};
+pub use sliding_window::SlidingWindows;
@@ -0,0 +1,399 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! A RAM copy of the hot points of a memmapped cloud.
+
+use crate::base_traits::*;
+use crate::external_ids::ExternalId;
+use crate::metrics::{BATCH, DISTANCE_BLOCK};
+use crate::pc_errors::{PointCloudError, PointCloudResult};
+use fxhash::FxBuildHasher;
+use hashbrown::HashMap;
+use rayon::prelude::*;
+use smallvec::SmallVec;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// The copied points, `dim` values per point at the offset in `offsets`.
+#[derive(Debug, Default)]
+struct PinnedPoints {
+    offsets: HashMap<usize, usize, FxBuildHasher>,
+    values: Vec<f32>,
+}
+
+/// The pinned set of a [`PinnedCloud`]. This is shared, so you can keep a handle on it after the cloud is wrapped in
+/// labels and handed to a tree.
+#[derive(Debug)]
+pub struct PinCache {
+    budget_bytes: usize,
+    pinned: RwLock<Arc<PinnedPoints>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// How the pin cache is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PinStats {
+    /// The number of pinned points
+    pub pinned: usize,
+    /// The memory the pinned points take up
+    pub bytes: usize,
+    /// The most memory the pinned points may take up
+    pub budget_bytes: usize,
+    /// Point reads served from the copy
+    pub hits: u64,
+    /// Point reads that went to the underlying cloud
+    pub misses: u64,
+}
+
+impl PinCache {
+    /// Copies the points at the indexes into RAM, replacing the pinned set. The points are taken in order until the
+    /// budget runs out, so put the hottest first. Returns the number of points pinned.
+    pub fn pin<D: PointCloud<Point = [f32]>>(
+        &self,
+        point_cloud: &D,
+        indexes: &[usize],
+    ) -> PointCloudResult<usize> {
+        let max_points =
+            self.budget_bytes / (point_cloud.dim() * std::mem::size_of::<f32>()).max(1);
+        let mut pinned = PinnedPoints::default();
+        for pi in indexes {
+            if pinned.offsets.len() >= max_points {
+                break;
+            }
+            if !pinned.offsets.contains_key(pi) {
+                let point = point_cloud.point(*pi)?;
+                pinned.offsets.insert(*pi, pinned.values.len());
+                pinned.values.extend_from_slice(&point);
+            }
+        }
+        let count = pinned.offsets.len();
+        *self.pinned.write().unwrap() = Arc::new(pinned);
+        Ok(count)
+    }
+
+    /// Drops the pinned points, every read goes to the underlying cloud again.
+    pub fn unpin(&self) {
+        *self.pinned.write().unwrap() = Arc::new(PinnedPoints::default());
+    }
+
+    /// The pinned set as it is now, a later `pin` or `unpin` doesn't change it.
+    fn snapshot(&self) -> Arc<PinnedPoints> {
+        Arc::clone(&self.pinned.read().unwrap())
+    }
+
+    fn count(&self, hits: usize, misses: usize) {
+        self.hits.fetch_add(hits as u64, Ordering::Relaxed);
+        self.misses.fetch_add(misses as u64, Ordering::Relaxed);
+    }
+
+    /// The size of the pinned set and the hit counts since the cache was made.
+    pub fn stats(&self) -> PinStats {
+        let pinned = self.pinned.read().unwrap();
+        PinStats {
+            pinned: pinned.offsets.len(),
+            bytes: pinned.values.len() * std::mem::size_of::<f32>(),
+            budget_bytes: self.budget_bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Wraps a cloud, usually a [`crate::data_sources::DataMemmap`], and serves the points you pin from a RAM copy. The
+/// reads are transparent, so a tree built on this uses the copy for every distance to a pinned point. Pin the
+/// centers of the top layers of the tree, they're on the path of every query.
+#[derive(Debug)]
+pub struct PinnedCloud<D> {
+    data: D,
+    cache: Arc<PinCache>,
+}
+
+impl<D: PointCloud<Point = [f32]>> PinnedCloud<D> {
+    /// Wraps the cloud with an empty pin cache that can hold up to `budget_bytes` of points.
+    pub fn new(data: D, budget_bytes: usize) -> Self {
+        PinnedCloud {
+            data,
+            cache: Arc::new(PinCache {
+                budget_bytes,
+                pinned: RwLock::new(Arc::new(PinnedPoints::default())),
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// A handle on the pin cache.
+    pub fn cache(&self) -> Arc<PinCache> {
+        Arc::clone(&self.cache)
+    }
+
+    /// Fills in the distances from `x` to the points at the indexes, reading the pinned ones out of `pinned`.
+    /// Returns the number of pinned points read.
+    fn batched_distances(
+        &self,
+        pinned: &PinnedPoints,
+        x: &[f32],
+        indexes: &[usize],
+        dists: &mut [f32],
+    ) -> PointCloudResult<usize> {
+        let dim = self.data.dim();
+        let mut hits = 0;
+        for (batch, batch_dists) in indexes
+            .chunks(DISTANCE_BLOCK)
+            .zip(dists.chunks_mut(DISTANCE_BLOCK))
+        {
+            let points = batch
+                .iter()
+                .map(|i| match pinned.offsets.get(i) {
+                    Some(start) => {
+                        hits += 1;
+                        Ok(BatchPoint::Pinned(&pinned.values[*start..*start + dim]))
+                    }
+                    None => self.data.point(*i).map(BatchPoint::Data),
+                })
+                .collect::<PointCloudResult<SmallVec<[BatchPoint<D::PointRef<'_>>; BATCH]>>>()?;
+            let ys: SmallVec<[&[f32]; BATCH]> = points.iter().map(|y| y.deref()).collect();
+            D::Metric::dists(x, &ys, batch_dists);
+        }
+        Ok(hits)
+    }
+}
+
+/// A point read by a batch of distances, the batch holds the snapshot of the pinned set so the pinned points are
+/// borrowed from it.
+enum BatchPoint<'a, R> {
+    Pinned(&'a [f32]),
+    Data(R),
+}
+
+impl<'a, R: Deref<Target = [f32]>> Deref for BatchPoint<'a, R> {
+    type Target = [f32];
+    fn deref(&self) -> &[f32] {
+        match self {
+            BatchPoint::Pinned(p) => p,
+            BatchPoint::Data(r) => r.deref(),
+        }
+    }
+}
+
+/// A point of a [`PinnedCloud`], either from the RAM copy or from the underlying cloud.
+pub struct PinnedRef<R> {
+    inner: PinnedRefInner<R>,
+}
+
+enum PinnedRefInner<R> {
+    /// Holds on to the copy, so the point stays readable if it's unpinned in the meantime
+    Pinned {
+        points: Arc<PinnedPoints>,
+        start: usize,
+        end: usize,
+    },
+    Data(R),
+}
+
+impl<R: Deref<Target = [f32]>> Deref for PinnedRef<R> {
+    type Target = [f32];
+    fn deref(&self) -> &[f32] {
+        match &self.inner {
+            PinnedRefInner::Pinned { points, start, end } => &points.values[*start..*end],
+            PinnedRefInner::Data(r) => r.deref(),
+        }
+    }
+}
+
+impl<R: Deref<Target = [f32]> + Send + Sync> PointRef for PinnedRef<R> {
+    type DenseIter = std::vec::IntoIter<f32>;
+    fn dense(&self) -> Vec<f32> {
+        self.deref().to_vec()
+    }
+    fn dense_iter(&self) -> Self::DenseIter {
+        self.dense().into_iter()
+    }
+}
+
+impl<D: PointCloud<Point = [f32]>> PointCloud for PinnedCloud<D> {
+    type Metric = D::Metric;
+    type Point = [f32];
+    type PointRef<'a>
+        = PinnedRef<D::PointRef<'a>>
+    where
+        Self: 'a;
+    type Metadata = D::Metadata;
+    type MetaSummary = D::MetaSummary;
+
+    type Label = D::Label;
+    type LabelSummary = D::LabelSummary;
+
+    #[inline]
+    fn dim(&self) -> usize {
+        self.data.dim()
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    #[inline]
+    fn reference_indexes(&self) -> Vec<usize> {
+        self.data.reference_indexes()
+    }
+    fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<Self::PointRef<'a>> {
+        let pinned = self.cache.snapshot();
+        if let Some(start) = pinned.offsets.get(&i).cloned() {
+            self.cache.hits.fetch_add(1, Ordering::Relaxed);
+            Ok(PinnedRef {
+                inner: PinnedRefInner::Pinned {
+                    points: pinned,
+                    start,
+                    end: start + self.data.dim(),
+                },
+            })
+        } else {
+            self.cache.misses.fetch_add(1, Ordering::Relaxed);
+            Ok(PinnedRef {
+                inner: PinnedRefInner::Data(self.data.point(i)?),
+            })
+        }
+    }
+
+    /// Takes one snapshot of the pinned set for all of the indexes, rather than one per point read.
+    fn distances_to_point<T: Deref<Target = Self::Point> + Send + Sync>(
+        &self,
+        x: &T,
+        indexes: &[usize],
+    ) -> PointCloudResult<Vec<f32>> {
+        let pinned = self.cache.snapshot();
+        let mut dists: Vec<f32> = vec![f32::default(); indexes.len()];
+        let chunk = chunk(self.dim());
+        let hits = if indexes.len() > chunk * 3 {
+            let error: Mutex<Result<(), PointCloudError>> = Mutex::new(Ok(()));
+            let hits = dists
+                .par_chunks_mut(chunk)
+                .zip(indexes.par_chunks(chunk))
+                .map(|(chunk_dists, chunk_indexes)| {
+                    match self.batched_distances(&pinned, x.deref(), chunk_indexes, chunk_dists) {
+                        Ok(hits) => hits,
+                        Err(e) => {
+                            *error.lock().unwrap() = Err(e);
+                            0
+                        }
+                    }
+                })
+                .sum::<usize>();
+            (error.into_inner().unwrap())?;
+            hits
+        } else {
+            self.batched_distances(&pinned, x.deref(), indexes, &mut dists)?
+        };
+        self.cache.count(hits, indexes.len() - hits);
+        Ok(dists)
+    }
+
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        self.data.metadata(pn)
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        self.data.metasummary(pns)
+    }
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        self.data.label(pn)
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        self.data.label_summary(pns)
+    }
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        self.data.name(pi)
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        self.data.index(pn)
+    }
+    fn names(&self) -> Vec<String> {
+        self.data.names()
+    }
+    fn timestamp(&self, pi: usize) -> PointCloudResult<Option<u64>> {
+        self.data.timestamp(pi)
+    }
+    fn external_id(&self, pi: usize) -> PointCloudResult<Option<ExternalId>> {
+        self.data.external_id(pi)
+    }
+    fn external_index(&self, id: &ExternalId) -> PointCloudResult<usize> {
+        self.data.external_index(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_sources::DataRam;
+    use crate::L2;
+
+    #[test]
+    fn pinned_points_match() {
+        let data: Vec<f32> = (0..40).map(|i| i as f32).collect();
+        let cloud = PinnedCloud::new(DataRam::<L2>::new(data, 4).unwrap(), 2 * 4 * 4);
+        let cache = cloud.cache();
+        assert_eq!(cache.pin(&cloud, &[3, 7, 9]).unwrap(), 2);
+        for pi in 0..10 {
+            let expected: Vec<f32> = (4 * pi..4 * pi + 4).map(|i| i as f32).collect();
+            assert_eq!(&*cloud.point(pi).unwrap(), &expected[..]);
+        }
+        let stats = cache.stats();
+        assert_eq!(stats.pinned, 2);
+        assert_eq!(stats.bytes, 32);
+        assert_eq!(stats.hits, 2);
+        // The pin itself read 2 points
+        assert_eq!(stats.misses, 10);
+
+        cache.unpin();
+        cloud.point(3).unwrap();
+        assert_eq!(cache.stats().hits, 2);
+    }
+
+    #[test]
+    fn pinned_distances_match() {
+        let data: Vec<f32> = (0..4000).map(|i| (i % 17) as f32).collect();
+        let ram = DataRam::<L2>::new(data.clone(), 4).unwrap();
+        let cloud = PinnedCloud::new(DataRam::<L2>::new(data, 4).unwrap(), 100 * 4 * 4);
+        let cache = cloud.cache();
+        let pinned: Vec<usize> = (0..1000).step_by(10).collect();
+        assert_eq!(cache.pin(&cloud, &pinned).unwrap(), 100);
+        let before = cache.stats();
+
+        let x = [1.0f32, 2.0, 3.0, 4.0];
+        let indexes: Vec<usize> = (0..1000).collect();
+        assert_eq!(
+            cloud.distances_to_point(&&x[..], &indexes).unwrap(),
+            ram.distances_to_point(&&x[..], &indexes).unwrap()
+        );
+        let short = &indexes[..20];
+        assert_eq!(
+            cloud.distances_to_point(&&x[..], short).unwrap(),
+            ram.distances_to_point(&&x[..], short).unwrap()
+        );
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits - before.hits, 102);
+        assert_eq!(stats.misses - before.misses, 918);
+    }
+}
@@ -0,0 +1,167 @@
+//! Sliding windows over a long 1-D signal, exposed as points.
+
+use crate::pc_errors::{ParsingError, PointCloudError, PointCloudResult};
+use std::marker::PhantomData;
+
+use crate::base_traits::*;
+use crate::metrics::*;
+
+/// Treats every window of length `dim` of a signal as a point, without copying the windows out.
+/// The `i`th point is `signal[i*stride..i*stride + dim]`.
+///
+/// This is meant for telemetry, you can build a tree over the windows of the historical signal and track
+/// the windows of the live one.
+#[derive(Debug)]
+pub struct SlidingWindows<M = L2> {
+    name: String,
+    signal: Vec<f32>,
+    dim: usize,
+    stride: usize,
+    metric: PhantomData<M>,
+}
+
+impl<M> SlidingWindows<M> {
+    /// Creates the windows with a stride of 1, so every offset is a point.
+    pub fn new(signal: Vec<f32>, dim: usize) -> PointCloudResult<SlidingWindows<M>> {
+        SlidingWindows::with_stride(signal, dim, 1)
+    }
+
+    /// Creates the windows with the given stride, the offset between consecutive windows.
+    pub fn with_stride(
+        signal: Vec<f32>,
+        dim: usize,
+        stride: usize,
+    ) -> PointCloudResult<SlidingWindows<M>> {
+        if dim == 0 || stride == 0 {
+            return Err(ParsingError::RegularParsingError(
+                "Sliding windows need a non-zero window length and stride",
+            )
+            .into());
+        }
+        Ok(SlidingWindows {
+            name: "SlidingWindows".to_string(),
+            signal,
+            dim,
+            stride,
+            metric: PhantomData,
+        })
+    }
+
+    /// The offset into the signal that the window starts at
+    pub fn window_start(&self, pi: usize) -> usize {
+        pi * self.stride
+    }
+
+    /// The offset between consecutive windows
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// The underlying signal
+    pub fn signal(&self) -> &[f32] {
+        &self.signal
+    }
+}
+
+impl<M: Metric<[f32]>> PointCloud for SlidingWindows<M> {
+    type Metric = M;
+    type Point = [f32];
+    type PointRef<'a>
+        = &'a [f32]
+    where
+        Self: 'a;
+    type LabelSummary = ();
+    type Label = ();
+    type MetaSummary = ();
+    type Metadata = ();
+
+    fn metadata(&self, _pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        Ok(None)
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        Ok(SummaryCounter {
+            summary: (),
+            nones: pns.len(),
+            errors: 0,
+        })
+    }
+    fn label(&self, _pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        Ok(None)
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        Ok(SummaryCounter {
+            summary: (),
+            nones: pns.len(),
+            errors: 0,
+        })
+    }
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        Ok(pi.to_string())
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        pn.parse::<usize>().map_err(|_| {
+            ParsingError::RegularParsingError("Unable to parse your str into an usize").into()
+        })
+    }
+    fn names(&self) -> Vec<String> {
+        (0..self.len()).map(|i| i.to_string()).collect()
+    }
+
+    #[inline]
+    fn dim(&self) -> usize {
+        self.dim
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        if self.signal.len() < self.dim {
+            0
+        } else {
+            (self.signal.len() - self.dim) / self.stride + 1
+        }
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    #[inline]
+    fn reference_indexes(&self) -> Vec<usize> {
+        (0..self.len()).collect()
+    }
+    #[inline]
+    fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<&'a [f32]> {
+        let start = self.window_start(i);
+        match self.signal.get(start..(start + self.dim)) {
+            None => Err(PointCloudError::data_access(i, self.name.clone())),
+            Some(x) => Ok(x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_correct() {
+        let signal: Vec<f32> = (0..10).map(|i| i as f32).collect();
+        let pc = SlidingWindows::<L2>::new(signal, 3).unwrap();
+        assert_eq!(pc.len(), 8);
+        assert_eq!(pc.point(0).unwrap(), &[0.0, 1.0, 2.0]);
+        assert_eq!(pc.point(7).unwrap(), &[7.0, 8.0, 9.0]);
+        assert!(pc.point(8).is_err());
+        let dists = pc.distances_to_point_index(0, &[1]).unwrap();
+        assert_approx_eq!(dists[0], 3.0f32.sqrt());
+    }
+
+    #[test]
+    fn strided_windows_correct() {
+        let signal: Vec<f32> = (0..10).map(|i| i as f32).collect();
+        let pc = SlidingWindows::<L2>::with_stride(signal, 4, 3).unwrap();
+        assert_eq!(pc.len(), 3);
+        assert_eq!(pc.point(2).unwrap(), &[6.0, 7.0, 8.0, 9.0]);
+
+        let short = SlidingWindows::<L2>::new(vec![1.0, 2.0], 3).unwrap();
+        assert!(short.is_empty());
+        assert!(SlidingWindows::<L2>::with_stride(vec![1.0], 1, 0).is_err());
+    }
+}
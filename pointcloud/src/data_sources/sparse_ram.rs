@@ -44,7 +44,10 @@ impl<M> PointCloud for SparseDataRam<f32, u32, M>
 where
     M: Metric<RawSparse<f32, u32>>,
 {
-    type PointRef<'a> = SparseRef<'a, f32, u32>;
+    type PointRef<'a>
+        = SparseRef<'a, f32, u32>
+    where
+        Self: 'a;
     type Point = RawSparse<f32, u32>;
     type Metric = L2;
     type LabelSummary = ();
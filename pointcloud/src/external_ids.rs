@@ -0,0 +1,258 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Ids for the points that don't depend on their position in the cloud.
+//!
+//! A point index is its position in the cloud, so re-ingesting the data in a different order shifts every index and
+//! anything saved against the old ones, trackers or a tree, no longer lines up. Wrap the cloud in an
+//! [`ExternalIdCloud`] with an id per point, say a row id from the source database, and the queries can report and
+//! look points up by that id instead.
+
+use crate::base_traits::*;
+use crate::pc_errors::*;
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The id of a point outside of the cloud. Numeric ids serialize as numbers and the rest as strings.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ExternalId {
+    /// A numeric id, like a row id
+    Int(u64),
+    /// Any other id, like a hash or a uuid
+    Str(String),
+}
+
+impl ExternalId {
+    /// Reads an id from a string, like a query string or a CSV column. Anything that parses as a `u64` is numeric.
+    pub fn parse(id: &str) -> ExternalId {
+        match id.parse::<u64>() {
+            Ok(i) => ExternalId::Int(i),
+            Err(_) => ExternalId::Str(id.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ExternalId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExternalId::Int(i) => write!(f, "{}", i),
+            ExternalId::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<u64> for ExternalId {
+    fn from(i: u64) -> ExternalId {
+        ExternalId::Int(i)
+    }
+}
+
+impl From<String> for ExternalId {
+    fn from(s: String) -> ExternalId {
+        ExternalId::Str(s)
+    }
+}
+
+impl From<&str> for ExternalId {
+    fn from(s: &str) -> ExternalId {
+        ExternalId::Str(s.to_string())
+    }
+}
+
+/// A bidirectional map between the external ids of the points and their indexes.
+#[derive(Debug, Clone, Default)]
+pub struct ExternalIdIndex {
+    ids: Vec<ExternalId>,
+    indexes: HashMap<ExternalId, usize>,
+}
+
+impl ExternalIdIndex {
+    /// Creates the index, the id of point `i` is `ids[i]`. Errors if an id is used twice.
+    pub fn new(ids: Vec<ExternalId>) -> PointCloudResult<ExternalIdIndex> {
+        let mut indexes = HashMap::with_capacity(ids.len());
+        for (i, id) in ids.iter().enumerate() {
+            if indexes.insert(id.clone(), i).is_some() {
+                return Err(ParsingError::RegularParsingError(
+                    "An external id is used more than once",
+                )
+                .into());
+            }
+        }
+        Ok(ExternalIdIndex { ids, indexes })
+    }
+
+    /// The number of ids
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// If there are no ids
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// The id of the point at the index
+    pub fn id(&self, pi: usize) -> PointCloudResult<&ExternalId> {
+        self.ids
+            .get(pi)
+            .ok_or_else(|| PointCloudError::data_access(pi, "external id index".to_string()))
+    }
+
+    /// The index of the point with the id
+    pub fn index(&self, id: &ExternalId) -> PointCloudResult<usize> {
+        self.indexes
+            .get(id)
+            .cloned()
+            .ok_or(PointCloudError::UnknownExternalId)
+    }
+}
+
+/// Attaches an external id to every point of the underlying cloud, see the module docs.
+#[derive(Debug)]
+pub struct ExternalIdCloud<D> {
+    data: D,
+    ids: ExternalIdIndex,
+}
+
+impl<D: PointCloud> ExternalIdCloud<D> {
+    /// Creates a new one, there has to be an id per point and no id can be used twice.
+    pub fn new(data: D, ids: Vec<ExternalId>) -> PointCloudResult<Self> {
+        if ids.len() != data.len() {
+            return Err(ParsingError::RegularParsingError(
+                "There has to be an external id per point",
+            )
+            .into());
+        }
+        Ok(ExternalIdCloud {
+            data,
+            ids: ExternalIdIndex::new(ids)?,
+        })
+    }
+
+    /// The underlying cloud
+    pub fn data(&self) -> &D {
+        &self.data
+    }
+}
+
+impl<D: PointCloud> PointCloud for ExternalIdCloud<D> {
+    type Metric = D::Metric;
+    type Point = D::Point;
+    type PointRef<'a>
+        = D::PointRef<'a>
+    where
+        Self: 'a;
+    type Metadata = D::Metadata;
+    type MetaSummary = D::MetaSummary;
+
+    type Label = D::Label;
+    type LabelSummary = D::LabelSummary;
+
+    #[inline]
+    fn dim(&self) -> usize {
+        self.data.dim()
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    #[inline]
+    fn reference_indexes(&self) -> Vec<usize> {
+        self.data.reference_indexes()
+    }
+    #[inline]
+    fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<Self::PointRef<'a>> {
+        self.data.point(i)
+    }
+
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        self.data.metadata(pn)
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        self.data.metasummary(pns)
+    }
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        self.data.label(pn)
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        self.data.label_summary(pns)
+    }
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        self.data.name(pi)
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        self.data.index(pn)
+    }
+    fn names(&self) -> Vec<String> {
+        self.data.names()
+    }
+    fn timestamp(&self, pi: usize) -> PointCloudResult<Option<u64>> {
+        self.data.timestamp(pi)
+    }
+    fn external_id(&self, pi: usize) -> PointCloudResult<Option<ExternalId>> {
+        self.ids.id(pi).map(|id| Some(id.clone()))
+    }
+    fn external_index(&self, id: &ExternalId) -> PointCloudResult<usize> {
+        self.ids.index(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DefaultLabeledCloud;
+    use std::sync::Arc;
+
+    #[test]
+    fn external_ids_round_trip() {
+        let data: Vec<f32> = (0..10).map(|i| i as f32).collect();
+        let labels: Vec<i64> = (0..10).collect();
+        let ids: Vec<ExternalId> = (0..10u64).map(|i| ExternalId::Int(100 + i)).collect();
+        let cloud = Arc::new(
+            ExternalIdCloud::new(
+                DefaultLabeledCloud::<crate::L2>::new_simple(data, 1, labels),
+                ids,
+            )
+            .unwrap(),
+        );
+        assert_eq!(cloud.external_id(3).unwrap(), Some(ExternalId::Int(103)));
+        assert_eq!(cloud.external_index(&ExternalId::parse("107")).unwrap(), 7);
+        assert!(cloud.external_index(&ExternalId::from("107")).is_err());
+        assert!(cloud.external_id(10).is_err());
+
+        // The ids follow the points into a view, the indexes don't
+        let view = Arc::clone(&cloud).view(vec![7, 3]).unwrap();
+        assert_eq!(view.external_id(0).unwrap(), Some(ExternalId::Int(107)));
+        assert_eq!(view.external_index(&ExternalId::Int(103)).unwrap(), 1);
+        assert!(view.external_index(&ExternalId::Int(100)).is_err());
+
+        let plain = DefaultLabeledCloud::<crate::L2>::new_simple(vec![0.0], 1, vec![0]);
+        assert_eq!(plain.external_id(0).unwrap(), None);
+
+        let duplicated = vec![ExternalId::from("a"), ExternalId::from("a")];
+        let two = DefaultLabeledCloud::<crate::L2>::new_simple(vec![0.0, 1.0], 1, vec![0, 1]);
+        assert!(ExternalIdCloud::new(two, duplicated).is_err());
+    }
+}
@@ -3,6 +3,7 @@
 use crate::pc_errors::{PointCloudError, PointCloudResult};
 
 use crate::base_traits::*;
+use crate::external_ids::ExternalId;
 
 use fxhash::FxBuildHasher;
 use hashbrown::HashMap;
@@ -11,6 +12,7 @@ use hashbrown::HashMap;
 #[derive(Debug)]
 pub struct HashGluedCloud<D> {
     addresses: HashMap<usize, (usize, usize), FxBuildHasher>,
+    reverse: HashMap<(usize, usize), usize, FxBuildHasher>,
     data_sources: Vec<D>,
 }
 
@@ -26,12 +28,23 @@ impl<D: PointCloud> HashGluedCloud<D> {
             }
         }
         HashGluedCloud {
+            reverse: reverse_addresses(&addresses),
             addresses,
             data_sources,
         }
     }
 }
 
+/// The glued index of each address, so a lookup in one of the data sources maps back without a scan.
+fn reverse_addresses(
+    addresses: &HashMap<usize, (usize, usize), FxBuildHasher>,
+) -> HashMap<(usize, usize), usize, FxBuildHasher> {
+    addresses
+        .iter()
+        .map(|(pi, address)| (*address, *pi))
+        .collect()
+}
+
 impl<D> HashGluedCloud<D> {
     /// Remaps the indexes, treats the first element of the pair as the old index, and the second as the new index
     pub fn reindex(&mut self, new_indexes: &[(usize, usize)]) -> PointCloudResult<()> {
@@ -50,6 +63,7 @@ impl<D> HashGluedCloud<D> {
                 }
             }
         }
+        self.reverse = reverse_addresses(&new_addresses);
         self.addresses = new_addresses;
         Ok(())
     }
@@ -79,7 +93,10 @@ impl<D> HashGluedCloud<D> {
 impl<D: PointCloud> PointCloud for HashGluedCloud<D> {
     type Metric = D::Metric;
     type Point = D::Point;
-    type PointRef<'a> = D::PointRef<'a>;
+    type PointRef<'a>
+        = D::PointRef<'a>
+    where
+        Self: 'a;
     type Label = D::Label;
     type LabelSummary = D::LabelSummary;
     type Metadata = D::Metadata;
@@ -148,6 +165,28 @@ impl<D: PointCloud> PointCloud for HashGluedCloud<D> {
             .collect()
     }
 
+    fn timestamp(&self, pi: usize) -> PointCloudResult<Option<u64>> {
+        let (i, j) = self.get_address(pi)?;
+        self.data_sources[i].timestamp(j)
+    }
+
+    fn external_id(&self, pi: usize) -> PointCloudResult<Option<ExternalId>> {
+        let (i, j) = self.get_address(pi)?;
+        self.data_sources[i].external_id(j)
+    }
+    fn external_index(&self, id: &ExternalId) -> PointCloudResult<usize> {
+        for (i, data_source) in self.data_sources.iter().enumerate() {
+            if let Ok(j) = data_source.external_index(id) {
+                return self
+                    .reverse
+                    .get(&(i, j))
+                    .cloned()
+                    .ok_or(PointCloudError::UnknownExternalId);
+            }
+        }
+        Err(PointCloudError::UnknownExternalId)
+    }
+
     fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
         let (i, j) = self.get_address(pn)?;
         self.data_sources[i].metadata(j)
@@ -167,6 +206,7 @@ mod tests {
     use super::*;
     use crate::data_sources::tests::*;
     use crate::data_sources::*;
+    use crate::external_ids::ExternalIdCloud;
     use crate::label_sources::*;
 
     pub fn build_glue_random_labeled_test(
@@ -265,6 +305,28 @@ mod tests {
         assert_eq!(label_summary.summary.items[0], (1, 5));
     }
 
+    #[test]
+    fn external_index_correct() {
+        let mut pc = HashGluedCloud::new(
+            (0..5u64)
+                .map(|i| {
+                    let ids = vec![ExternalId::Int(10 * i), ExternalId::Int(10 * i + 1)];
+                    ExternalIdCloud::new(build_ram_fixed_test(2, 3), ids).unwrap()
+                })
+                .collect(),
+        );
+        for pi in 0..10 {
+            let id = pc.external_id(pi).unwrap().unwrap();
+            assert_eq!(pc.external_index(&id).unwrap(), pi);
+        }
+        assert!(pc.external_index(&ExternalId::Int(2)).is_err());
+
+        let reversed: Vec<(usize, usize)> = (0..10).map(|pi| (pi, 9 - pi)).collect();
+        pc.reindex(&reversed).unwrap();
+        assert_eq!(pc.external_index(&ExternalId::Int(0)).unwrap(), 9);
+        assert_eq!(pc.external_index(&ExternalId::Int(41)).unwrap(), 0);
+    }
+
     #[test]
     fn distance_correct() {
         let pc = build_glue_fixed_test(5, 2, 3);
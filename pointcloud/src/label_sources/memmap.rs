@@ -0,0 +1,230 @@
+//! Label sets that stay on disk. These read flat files with the same layout as the data memmaps, no header and
+//! the labels packed one after another, so the label for point `i` lives at `i * dim`.
+
+use crate::base_traits::*;
+use crate::data_sources::memmapf32::Mmapf32;
+use crate::pc_errors::*;
+use crate::summaries::*;
+
+use super::{SmallIntLabels, VecLabels};
+
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::slice;
+
+fn open_memmap(path: &Path) -> PointCloudResult<Mmapf32> {
+    let file = OpenOptions::new().read(true).open(&path)?;
+    unsafe { Mmapf32::map(&file).map_err(PointCloudError::from) }
+}
+
+/// The memmapped equivalent of [`super::SmallIntLabels`]. The file is a flat array of native endian `i64`s,
+/// one per point. Negative labels are treated as missing, this takes the place of the mask.
+#[derive(Debug)]
+pub struct MemmapSmallIntLabels {
+    name: String,
+    data: Mmapf32,
+}
+
+impl MemmapSmallIntLabels {
+    /// Opens the labels at the path. The name is the path.
+    pub fn new(path: &Path) -> PointCloudResult<MemmapSmallIntLabels> {
+        let name = path.to_string_lossy().to_string();
+        let data = open_memmap(path)?;
+        if data.len() % 2 != 0 {
+            return Err(ParsingError::RegularParsingError(
+                "The label file is not a whole number of i64s",
+            )
+            .into());
+        }
+        Ok(MemmapSmallIntLabels { name, data })
+    }
+
+    #[inline]
+    fn labels(&self) -> &[i64] {
+        // The map is page aligned and a whole number of i64s long
+        unsafe { slice::from_raw_parts(self.data.as_ptr() as *const i64, self.data.len() / 2) }
+    }
+
+    /// Reads the labels into ram.
+    pub fn convert_to_ram(&self) -> SmallIntLabels {
+        let labels = self.labels().to_vec();
+        let mask = labels.iter().map(|l| *l >= 0).collect();
+        SmallIntLabels::new(labels, Some(mask))
+    }
+}
+
+impl LabelSet for MemmapSmallIntLabels {
+    type Label = i64;
+    type LabelSummary = CategorySummary;
+
+    fn len(&self) -> usize {
+        self.labels().len()
+    }
+    fn is_empty(&self) -> bool {
+        self.labels().is_empty()
+    }
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&i64>> {
+        match self.labels().get(pn) {
+            Some(l) if *l >= 0 => Ok(Some(l)),
+            Some(_) => Ok(None),
+            None => Err(PointCloudError::data_access(pn, self.name.clone())),
+        }
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        let mut summary = CategorySummary::default();
+        let mut nones = 0;
+        let mut errors = 0;
+        let labels = self.labels();
+        for i in pns {
+            match labels.get(*i) {
+                Some(l) if *l >= 0 => summary.add(l),
+                Some(_) => nones += 1,
+                None => errors += 1,
+            }
+        }
+        Ok(SummaryCounter {
+            summary,
+            nones,
+            errors,
+        })
+    }
+}
+
+/// The memmapped equivalent of [`super::VecLabels`]. The file is a flat array of `f32`s, `label_dim` per point.
+/// A label with a `NaN` in it is treated as missing.
+#[derive(Debug)]
+pub struct MemmapVecLabels {
+    name: String,
+    data: Mmapf32,
+    label_dim: usize,
+}
+
+impl MemmapVecLabels {
+    /// Opens the labels at the path. The name is the path.
+    pub fn new(label_dim: usize, path: &Path) -> PointCloudResult<MemmapVecLabels> {
+        let name = path.to_string_lossy().to_string();
+        let data = open_memmap(path)?;
+        if label_dim == 0 || data.len() % label_dim != 0 {
+            return Err(ParsingError::RegularParsingError(
+                "The label file is not a whole number of labels",
+            )
+            .into());
+        }
+        Ok(MemmapVecLabels {
+            name,
+            data,
+            label_dim,
+        })
+    }
+
+    /// The dimension of the vectors this labelset contains
+    pub fn dim(&self) -> usize {
+        self.label_dim
+    }
+
+    #[inline]
+    fn raw_label(&self, pn: usize) -> Option<&[f32]> {
+        self.data.get(self.label_dim * pn..self.label_dim * (pn + 1))
+    }
+
+    /// Reads the labels into ram. Missing labels are masked.
+    pub fn convert_to_ram(&self) -> VecLabels {
+        let mask = (0..self.len())
+            .map(|i| !self.raw_label(i).unwrap().iter().any(|x| x.is_nan()))
+            .collect();
+        VecLabels::new(self.data.to_vec(), self.label_dim, Some(mask))
+    }
+}
+
+impl LabelSet for MemmapVecLabels {
+    type Label = [f32];
+    type LabelSummary = VecSummary;
+
+    fn len(&self) -> usize {
+        self.data.len() / self.label_dim
+    }
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&[f32]>> {
+        match self.raw_label(pn) {
+            Some(l) if !l.iter().any(|x| x.is_nan()) => Ok(Some(l)),
+            Some(_) => Ok(None),
+            None => Err(PointCloudError::data_access(pn, self.name.clone())),
+        }
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        let mut summary = VecSummary::default();
+        let mut nones = 0;
+        let mut errors = 0;
+        for i in pns {
+            match self.raw_label(*i) {
+                Some(l) if !l.iter().any(|x| x.is_nan()) => summary.add(l),
+                Some(_) => nones += 1,
+                None => errors += 1,
+            }
+        }
+        Ok(SummaryCounter {
+            summary,
+            nones,
+            errors,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    #[test]
+    fn memmap_int_labels_correct() {
+        let dir = TempDir::new("memmap_int_labels").unwrap();
+        let path = dir.path().join("labels.i64");
+        let labels: Vec<i64> = vec![0, 1, 1, -1, 2];
+        let mut file = File::create(&path).unwrap();
+        for l in labels.iter() {
+            file.write_all(&l.to_ne_bytes()).unwrap();
+        }
+        drop(file);
+
+        let memmap = MemmapSmallIntLabels::new(&path).unwrap();
+        assert_eq!(memmap.len(), 5);
+        assert_eq!(memmap.label(1).unwrap(), Some(&1));
+        assert_eq!(memmap.label(3).unwrap(), None);
+        assert!(memmap.label(5).is_err());
+
+        let summary = memmap.label_summary(&[0, 1, 2, 3]).unwrap();
+        assert_eq!(summary.nones, 1);
+        let ram_summary = memmap
+            .convert_to_ram()
+            .label_summary(&[0, 1, 2, 3])
+            .unwrap();
+        assert_eq!(summary.summary.items, ram_summary.summary.items);
+        assert_eq!(ram_summary.nones, 1);
+    }
+
+    #[test]
+    fn memmap_vec_labels_correct() {
+        let dir = TempDir::new("memmap_vec_labels").unwrap();
+        let path = dir.path().join("labels.f32");
+        let labels: Vec<f32> = vec![0.0, 1.0, 1.0, 0.0, std::f32::NAN, 0.0];
+        let mut file = File::create(&path).unwrap();
+        for l in labels.iter() {
+            file.write_all(&l.to_ne_bytes()).unwrap();
+        }
+        drop(file);
+
+        let memmap = MemmapVecLabels::new(2, &path).unwrap();
+        assert_eq!(memmap.len(), 3);
+        assert_eq!(memmap.label(1).unwrap(), Some(&[1.0f32, 0.0][..]));
+        assert_eq!(memmap.label(2).unwrap(), None);
+
+        let summary = memmap.label_summary(&[0, 1, 2]).unwrap();
+        assert_eq!(summary.nones, 1);
+        assert_eq!(summary.summary.count, 2);
+        assert!(MemmapVecLabels::new(4, &path).is_err());
+    }
+}
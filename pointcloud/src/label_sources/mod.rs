@@ -4,6 +4,9 @@ use crate::base_traits::*;
 use crate::pc_errors::*;
 use crate::summaries::*;
 
+mod memmap;
+pub use memmap::{MemmapSmallIntLabels, MemmapVecLabels};
+
 /// Labels for a small number of categories, using ints
 #[derive(Debug)]
 pub struct SmallIntLabels {
@@ -23,9 +23,6 @@
 //#![deny(warnings)]
 #![warn(missing_docs)]
 #![allow(clippy::cast_ptr_alignment)]
-#![feature(result_flattening)]
-#![feature(is_sorted)]
-#![feature(generic_associated_types)]
 
 #[cfg(test)]
 #[macro_use]
@@ -44,7 +41,20 @@ pub mod data_sources;
 
 pub mod glued_data_cloud;
 
+pub mod view;
+#[doc(inline)]
+pub use view::PointCloudView;
+
+pub mod external_ids;
+#[doc(inline)]
+pub use external_ids::{ExternalId, ExternalIdCloud};
+
+pub mod appendable;
+#[doc(inline)]
+pub use appendable::{AppendableCloud, AppendablePointCloud};
+
 pub mod label_sources;
+pub mod name_sources;
 pub mod summaries;
 
 pub mod loaders;
@@ -52,7 +62,7 @@ pub mod loaders;
 use data_sources::DataRam;
 use label_sources::SmallIntLabels;
 
-pub use metrics::L2;
+pub use metrics::{Cosine, L2};
 
 /// A sensible default for an labeled cloud
 pub type DefaultLabeledCloud<M = L2> = SimpleLabeledCloud<DataRam<M>, SmallIntLabels>;
@@ -0,0 +1,370 @@
+//! Loaders for parquet and arrow IPC files. The points are a list column of floats, either a fixed size list or a
+//! plain list whose rows all have the same length. The labels are either an integer column or another list column.
+//! Null labels are masked, null points and null values inside a point or a label are an error.
+
+use arrow::array::{
+    Array, ArrayRef, FixedSizeListArray, Float32Array, Float64Array, Int32Array, Int64Array,
+    ListArray,
+};
+use arrow::ipc::reader::FileReader;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+use parquet::file::reader::SerializedFileReader;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::base_traits::*;
+use crate::data_sources::DataRam;
+use crate::label_sources::*;
+use crate::pc_errors::*;
+
+/// The number of rows the parquet reader decodes at a time
+const PARQUET_BATCH_SIZE: usize = 8192;
+
+/// Opens a parquet file and reads the points in `data_column`.
+pub fn open_parquet<M: Metric<[f32]>, P: AsRef<Path>>(
+    path: &P,
+    data_column: &str,
+) -> PointCloudResult<DataRam<M>> {
+    let batches = read_parquet_batches(path.as_ref())?;
+    dense_column(path.as_ref(), &batches, data_column)
+}
+
+/// Opens a parquet file and reads the points in `data_column` with the integer labels in `label_column`.
+pub fn open_labeled_parquet<M: Metric<[f32]>, P: AsRef<Path>>(
+    path: &P,
+    data_column: &str,
+    label_column: &str,
+) -> PointCloudResult<SimpleLabeledCloud<DataRam<M>, SmallIntLabels>> {
+    let batches = read_parquet_batches(path.as_ref())?;
+    Ok(SimpleLabeledCloud::new(
+        dense_column(path.as_ref(), &batches, data_column)?,
+        int_label_column(path.as_ref(), &batches, label_column)?,
+    ))
+}
+
+/// Opens a parquet file and reads the points in `data_column` with the vector labels in `label_column`.
+pub fn open_vec_labeled_parquet<M: Metric<[f32]>, P: AsRef<Path>>(
+    path: &P,
+    data_column: &str,
+    label_column: &str,
+) -> PointCloudResult<SimpleLabeledCloud<DataRam<M>, VecLabels>> {
+    let batches = read_parquet_batches(path.as_ref())?;
+    Ok(SimpleLabeledCloud::new(
+        dense_column(path.as_ref(), &batches, data_column)?,
+        vec_label_column(path.as_ref(), &batches, label_column)?,
+    ))
+}
+
+/// Opens an arrow IPC file and reads the points in `data_column`.
+pub fn open_arrow<M: Metric<[f32]>, P: AsRef<Path>>(
+    path: &P,
+    data_column: &str,
+) -> PointCloudResult<DataRam<M>> {
+    let batches = read_arrow_batches(path.as_ref())?;
+    dense_column(path.as_ref(), &batches, data_column)
+}
+
+/// Opens an arrow IPC file and reads the points in `data_column` with the integer labels in `label_column`.
+pub fn open_labeled_arrow<M: Metric<[f32]>, P: AsRef<Path>>(
+    path: &P,
+    data_column: &str,
+    label_column: &str,
+) -> PointCloudResult<SimpleLabeledCloud<DataRam<M>, SmallIntLabels>> {
+    let batches = read_arrow_batches(path.as_ref())?;
+    Ok(SimpleLabeledCloud::new(
+        dense_column(path.as_ref(), &batches, data_column)?,
+        int_label_column(path.as_ref(), &batches, label_column)?,
+    ))
+}
+
+/// Opens an arrow IPC file and reads the points in `data_column` with the vector labels in `label_column`.
+pub fn open_vec_labeled_arrow<M: Metric<[f32]>, P: AsRef<Path>>(
+    path: &P,
+    data_column: &str,
+    label_column: &str,
+) -> PointCloudResult<SimpleLabeledCloud<DataRam<M>, VecLabels>> {
+    let batches = read_arrow_batches(path.as_ref())?;
+    Ok(SimpleLabeledCloud::new(
+        dense_column(path.as_ref(), &batches, data_column)?,
+        vec_label_column(path.as_ref(), &batches, label_column)?,
+    ))
+}
+
+fn columnar_error<E: ToString>(path: &Path, column: &str, reason: E) -> PointCloudError {
+    PointCloudError::ParsingError(ParsingError::ColumnarReadError {
+        file_name: path.to_string_lossy().to_string(),
+        column: column.to_string(),
+        reason: reason.to_string(),
+    })
+}
+
+fn read_parquet_batches(path: &Path) -> PointCloudResult<Vec<RecordBatch>> {
+    let file = File::open(path)?;
+    let file_reader = SerializedFileReader::new(file).map_err(|e| columnar_error(path, "", e))?;
+    let mut arrow_reader = ParquetFileArrowReader::new(Arc::new(file_reader));
+    arrow_reader
+        .get_record_reader(PARQUET_BATCH_SIZE)
+        .map_err(|e| columnar_error(path, "", e))?
+        .map(|batch| batch.map_err(|e| columnar_error(path, "", e)))
+        .collect()
+}
+
+fn read_arrow_batches(path: &Path) -> PointCloudResult<Vec<RecordBatch>> {
+    let file = File::open(path)?;
+    FileReader::try_new(file)
+        .map_err(|e| columnar_error(path, "", e))?
+        .map(|batch| batch.map_err(|e| columnar_error(path, "", e)))
+        .collect()
+}
+
+/// The column with the name out of each batch
+fn columns<'a>(
+    path: &Path,
+    batches: &'a [RecordBatch],
+    column: &str,
+) -> PointCloudResult<Vec<&'a ArrayRef>> {
+    batches
+        .iter()
+        .map(|batch| {
+            let index = batch
+                .schema()
+                .index_of(column)
+                .map_err(|e| columnar_error(path, column, e))?;
+            Ok(batch.column(index))
+        })
+        .collect()
+}
+
+/// Appends the values of the row at index `row`, a null among them is an error as there's no value to give it.
+fn push_floats(
+    path: &Path,
+    column: &str,
+    row: usize,
+    values: &ArrayRef,
+    out: &mut Vec<f32>,
+) -> PointCloudResult<()> {
+    if values.null_count() > 0 {
+        return Err(columnar_error(
+            path,
+            column,
+            format!("the row {} has a null value", row),
+        ));
+    }
+    if let Some(floats) = values.as_any().downcast_ref::<Float32Array>() {
+        out.extend_from_slice(floats.values());
+    } else if let Some(doubles) = values.as_any().downcast_ref::<Float64Array>() {
+        out.extend(doubles.values().iter().map(|x| *x as f32));
+    } else {
+        return Err(columnar_error(
+            path,
+            column,
+            format!("expected f32 or f64 values, found {:?}", values.data_type()),
+        ));
+    }
+    Ok(())
+}
+
+/// Reads a list column into a flat buffer, with the row length and which rows weren't null.
+fn list_column(
+    path: &Path,
+    batches: &[RecordBatch],
+    column: &str,
+) -> PointCloudResult<(Vec<f32>, usize, Vec<bool>)> {
+    let mut values = Vec::new();
+    let mut present = Vec::new();
+    let mut dim: Option<usize> = None;
+    for array in columns(path, batches, column)? {
+        let rows: Vec<Option<ArrayRef>> =
+            if let Some(list) = array.as_any().downcast_ref::<FixedSizeListArray>() {
+                (0..list.len())
+                    .map(|i| {
+                        if list.is_null(i) {
+                            None
+                        } else {
+                            Some(list.value(i))
+                        }
+                    })
+                    .collect()
+            } else if let Some(list) = array.as_any().downcast_ref::<ListArray>() {
+                (0..list.len())
+                    .map(|i| {
+                        if list.is_null(i) {
+                            None
+                        } else {
+                            Some(list.value(i))
+                        }
+                    })
+                    .collect()
+            } else {
+                return Err(columnar_error(
+                    path,
+                    column,
+                    format!("expected a list column, found {:?}", array.data_type()),
+                ));
+            };
+        for row in rows {
+            match row {
+                Some(row) => {
+                    let row_dim = *dim.get_or_insert(row.len());
+                    if row.len() != row_dim {
+                        return Err(columnar_error(
+                            path,
+                            column,
+                            format!("a row has length {}, expected {}", row.len(), row_dim),
+                        ));
+                    }
+                    push_floats(path, column, present.len(), &row, &mut values)?;
+                    present.push(true);
+                }
+                None => present.push(false),
+            }
+        }
+    }
+    let dim = dim.ok_or_else(|| columnar_error(path, column, "the column has no values"))?;
+    // The null rows are zeros, so the rows stay aligned
+    if present.iter().any(|p| !p) {
+        let mut padded = Vec::with_capacity(present.len() * dim);
+        let mut rows = values.chunks_exact(dim);
+        for p in &present {
+            if *p {
+                padded.extend_from_slice(rows.next().unwrap());
+            } else {
+                padded.extend(std::iter::repeat(0.0).take(dim));
+            }
+        }
+        values = padded;
+    }
+    Ok((values, dim, present))
+}
+
+fn dense_column<M: Metric<[f32]>>(
+    path: &Path,
+    batches: &[RecordBatch],
+    column: &str,
+) -> PointCloudResult<DataRam<M>> {
+    let (values, dim, present) = list_column(path, batches, column)?;
+    if let Some(row) = present.iter().position(|p| !p) {
+        return Err(columnar_error(
+            path,
+            column,
+            format!("the point at row {} is null", row),
+        ));
+    }
+    DataRam::new(values, dim)
+}
+
+fn vec_label_column(
+    path: &Path,
+    batches: &[RecordBatch],
+    column: &str,
+) -> PointCloudResult<VecLabels> {
+    let (values, dim, present) = list_column(path, batches, column)?;
+    let mask = if present.iter().all(|p| *p) {
+        None
+    } else {
+        Some(present)
+    };
+    Ok(VecLabels::new(values, dim, mask))
+}
+
+fn int_label_column(
+    path: &Path,
+    batches: &[RecordBatch],
+    column: &str,
+) -> PointCloudResult<SmallIntLabels> {
+    let mut labels = Vec::new();
+    let mut mask = Vec::new();
+    for array in columns(path, batches, column)? {
+        if let Some(ints) = array.as_any().downcast_ref::<Int64Array>() {
+            for i in 0..ints.len() {
+                labels.push(ints.value(i));
+                mask.push(!ints.is_null(i));
+            }
+        } else if let Some(ints) = array.as_any().downcast_ref::<Int32Array>() {
+            for i in 0..ints.len() {
+                labels.push(ints.value(i) as i64);
+                mask.push(!ints.is_null(i));
+            }
+        } else {
+            return Err(columnar_error(
+                path,
+                column,
+                format!("expected i32 or i64 labels, found {:?}", array.data_type()),
+            ));
+        }
+    }
+    let mask = if mask.iter().all(|m| *m) {
+        None
+    } else {
+        Some(mask)
+    };
+    Ok(SmallIntLabels::new(labels, mask))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::L2;
+    use arrow::datatypes::{DataType, Field, Float32Type, Schema};
+    use arrow::ipc::writer::FileWriter;
+    use tempdir::TempDir;
+
+    fn write_arrow(path: &Path, points: ListArray, labels: Int64Array) {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("points", points.data_type().clone(), true),
+            Field::new("labels", DataType::Int64, true),
+        ]));
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(points), Arc::new(labels)]).unwrap();
+        let mut writer = FileWriter::try_new(File::create(path).unwrap(), &schema).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn arrow_points_and_labels() {
+        let dir = TempDir::new("columnar").unwrap();
+        let path = dir.path().join("points.arrow");
+        let points = ListArray::from_iter_primitive::<Float32Type, _, _>(vec![
+            Some(vec![Some(0.0), Some(1.0)]),
+            Some(vec![Some(2.0), Some(3.0)]),
+            Some(vec![Some(4.0), Some(5.0)]),
+        ]);
+        let labels = Int64Array::from(vec![Some(1), None, Some(2)]);
+        write_arrow(&path, points, labels);
+
+        let cloud = open_labeled_arrow::<L2, _>(&path, "points", "labels").unwrap();
+        assert_eq!(cloud.len(), 3);
+        assert_eq!(cloud.dim(), 2);
+        assert_eq!(&*cloud.point(2).unwrap(), &[4.0, 5.0]);
+        assert_eq!(cloud.label(0).unwrap(), Some(&1));
+        assert_eq!(cloud.label(1).unwrap(), None);
+    }
+
+    #[test]
+    fn null_values_are_an_error() {
+        let dir = TempDir::new("columnar").unwrap();
+        let path = dir.path().join("points.arrow");
+        let points = ListArray::from_iter_primitive::<Float32Type, _, _>(vec![
+            Some(vec![Some(0.0), Some(1.0)]),
+            Some(vec![Some(2.0), None]),
+        ]);
+        write_arrow(&path, points, Int64Array::from(vec![1, 2]));
+        assert!(open_arrow::<L2, _>(&path, "points").is_err());
+    }
+
+    #[test]
+    fn null_points_are_an_error() {
+        let dir = TempDir::new("columnar").unwrap();
+        let path = dir.path().join("points.arrow");
+        let points = ListArray::from_iter_primitive::<Float32Type, _, _>(vec![
+            Some(vec![Some(0.0), Some(1.0)]),
+            None,
+        ]);
+        write_arrow(&path, points, Int64Array::from(vec![1, 2]));
+        assert!(open_arrow::<L2, _>(&path, "points").is_err());
+        assert!(open_vec_labeled_arrow::<L2, _>(&path, "points", "points").is_err());
+    }
+}
@@ -5,7 +5,9 @@ use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 
+use crate::external_ids::ExternalId;
 use crate::label_sources::*;
+use crate::name_sources::NameIndex;
 
 /// Opens a CSV and reads a single column from it as a integer label. Negative labels are treated as unlabeled and are masked.
 pub fn open_int_csv<P: AsRef<Path> + std::fmt::Debug>(
@@ -68,3 +70,112 @@ fn read_csv<P: AsRef<Path> + std::fmt::Debug, R: Read>(
         Ok(SmallIntLabels::new(labels, None))
     }
 }
+
+/// Opens a CSV and reads a single column from it as the names of the points. Rows without that column are named
+/// with their index, like the data sources do.
+pub fn open_name_csv<P: AsRef<Path> + std::fmt::Debug>(
+    path: &P,
+    index: usize,
+) -> PointCloudResult<NameIndex> {
+    if !path.as_ref().exists() {
+        panic!("CSV file {:?} does not exist", path);
+    }
+
+    match File::open(&path) {
+        Ok(file) => {
+            if path.as_ref().extension().unwrap() == "gz" {
+                read_name_csv(index, Reader::from_reader(GzDecoder::new(file)))
+            } else {
+                read_name_csv(index, Reader::from_reader(file))
+            }
+        }
+        Err(e) => panic!("Unable to open csv file {:#?}", e),
+    }
+}
+
+fn read_name_csv<R: Read>(index: usize, mut rdr: Reader<R>) -> PointCloudResult<NameIndex> {
+    let mut names = Vec::new();
+    for result in rdr.records() {
+        let record = result.expect("Unable to read a record from the name CSV");
+        match record.get(index) {
+            Some(name) => names.push(name.to_string()),
+            None => names.push(names.len().to_string()),
+        }
+    }
+    NameIndex::new(names)
+}
+
+/// Opens a CSV and reads a single column from it as the external ids of the points, see
+/// [`crate::external_ids`]. Every row needs the column.
+pub fn open_external_id_csv<P: AsRef<Path> + std::fmt::Debug>(
+    path: &P,
+    index: usize,
+) -> PointCloudResult<Vec<ExternalId>> {
+    if !path.as_ref().exists() {
+        panic!("CSV file {:?} does not exist", path);
+    }
+
+    match File::open(&path) {
+        Ok(file) => {
+            if path.as_ref().extension().unwrap() == "gz" {
+                read_external_id_csv(index, path, Reader::from_reader(GzDecoder::new(file)))
+            } else {
+                read_external_id_csv(index, path, Reader::from_reader(file))
+            }
+        }
+        Err(e) => panic!("Unable to open csv file {:#?}", e),
+    }
+}
+
+fn read_external_id_csv<P: AsRef<Path> + std::fmt::Debug, R: Read>(
+    index: usize,
+    path: &P,
+    mut rdr: Reader<R>,
+) -> PointCloudResult<Vec<ExternalId>> {
+    let mut ids = Vec::new();
+    for result in rdr.records() {
+        let record = result.expect("Unable to read a record from the external id CSV");
+        match record.get(index) {
+            Some(id) => ids.push(ExternalId::parse(id)),
+            None => {
+                return Err(PointCloudError::ParsingError(ParsingError::CSVReadError {
+                    file_name: path.as_ref().to_string_lossy().to_string(),
+                    line_number: record.position().unwrap().line() as usize,
+                    key: format!("No external id in {:?}", record),
+                }))
+            }
+        }
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_traits::NamedSet;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    #[test]
+    fn names_are_read_from_a_column() {
+        let dir = TempDir::new("name_csv").unwrap();
+        let path = dir.path().join("names.csv");
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "id,name").unwrap();
+        writeln!(file, "0,first point").unwrap();
+        writeln!(file, "1,\"a, b & 50%\"").unwrap();
+        drop(file);
+
+        let names = open_name_csv(&path, 1).unwrap();
+        assert_eq!(names.len(), 2);
+        assert_eq!(names.name(0).unwrap(), "first point");
+        assert_eq!(names.index("a, b & 50%").unwrap(), 1);
+
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "name").unwrap();
+        writeln!(file, "a").unwrap();
+        writeln!(file, "a").unwrap();
+        drop(file);
+        assert!(open_name_csv(&path, 0).is_err());
+    }
+}
@@ -12,6 +12,10 @@ mod yaml_loaders;
 pub use yaml_loaders::*;
 mod csv_loaders;
 pub use csv_loaders::*;
+#[cfg(feature = "columnar")]
+mod columnar_loaders;
+#[cfg(feature = "columnar")]
+pub use columnar_loaders::*;
 
 /// Opens a set of memmaps of both data and labels
 pub fn open_labeled_memmaps<M: Metric<[f32]>>(
@@ -3,10 +3,11 @@ use std::cmp::Ordering;
 use std::fs;
 use yaml_rust::YamlLoader;
 
-use log::{info, trace};
+use tracing::{info, trace};
 
 use super::*;
 use crate::metrics::L2;
+use crate::name_sources::NameIndex;
 use crate::DefaultLabeledCloud;
 
 /// Given a yaml file on disk, it builds a point cloud. Minimal example below.
@@ -27,6 +28,33 @@ pub fn labeled_ram_from_yaml<P: AsRef<Path>, M: Metric<[f32]>>(
     Ok(SimpleLabeledCloud::new(data_set, label_set))
 }
 
+/// Given a yaml file on disk, it builds a point cloud whose points are named by a column of a CSV. Minimal example
+/// below, `names_index` defaults to the first column.
+/// ```yaml
+/// ---
+/// data_path: DATAMEMMAP
+/// labels_path: LABELS_CSV
+/// names_path: NAMES_CSV
+/// names_index: 0
+/// count: NUMBER_OF_DATA_POINTS
+/// data_dim: 784
+/// label_csv_index: 2
+/// ```
+pub fn named_labeled_ram_from_yaml<P: AsRef<Path>, M: Metric<[f32]>>(
+    path: P,
+) -> PointCloudResult<SimpleNamedCloud<DefaultLabeledCloud<M>, NameIndex>> {
+    let name_set = names_from_yaml(&path)?;
+    let point_cloud = labeled_ram_from_yaml(&path)?;
+    if name_set.len() != point_cloud.len() {
+        return Err(ParsingError::RegularParsingError(
+            "The names CSV doesn't have a row per point",
+        )
+        .into());
+    }
+
+    Ok(SimpleNamedCloud::new(point_cloud, name_set))
+}
+
 /// Given a yaml file on disk, it builds a point cloud. Minimal example below.
 /// ```yaml
 /// ---
@@ -171,6 +199,36 @@ pub fn labels_from_yaml<P: AsRef<Path>>(path: P) -> PointCloudResult<SmallIntLab
         .unwrap())
 }
 
+/// Given a yaml file on disk, it reads the names of the points from a CSV with [`open_name_csv`]. The files
+/// matched by `names_path` are read in order and their names are concatenated. Minimal example below.
+/// ```yaml
+/// ---
+/// names_path: NAMES_CSV
+/// names_index: 0
+/// ```
+pub fn names_from_yaml<P: AsRef<Path>>(path: P) -> PointCloudResult<NameIndex> {
+    info!("Opening names yaml with path {:?}", &path.as_ref());
+    let config = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("Unable to read config file {:?}", &path.as_ref()));
+    let path: &Path = path.as_ref();
+    let params_files = &YamlLoader::load_from_str(&config).unwrap()[0];
+
+    let names_path = &get_file_list(
+        params_files["names_path"]
+            .as_str()
+            .expect("Unable to read the 'names_path'"),
+        path,
+    );
+    let names_index = params_files["names_index"].as_i64().unwrap_or(0) as usize;
+
+    let mut names = Vec::new();
+    for path in names_path {
+        info!("Opening name file with path {:?}", path);
+        names.extend(open_name_csv(&path, names_index)?.names());
+    }
+    NameIndex::new(names)
+}
+
 fn get_file_list(files_reg: &str, yaml_path: &Path) -> Vec<PathBuf> {
     let options = MatchOptions {
         case_sensitive: false,
@@ -0,0 +1,205 @@
+//! f32 implementations of the cosine metric.
+
+#[cfg(not(feature = "simd"))]
+use super::lane_sum;
+use super::{sq_l2_norm_f32, Cosine};
+use crate::base_traits::Metric;
+use crate::points::*;
+#[cfg(feature = "simd")]
+use packed_simd::*;
+use std::ops::Deref;
+
+impl Metric<[f32]> for Cosine {
+    fn dist(x: &[f32], y: &[f32]) -> f32 {
+        angle(
+            dot_dense_f32(x.deref(), y.deref()),
+            sq_l2_norm_f32(x),
+            sq_l2_norm_f32(y),
+        )
+    }
+}
+
+impl<'a> Metric<RawSparse<f32, u32>> for Cosine {
+    fn dist(x: &RawSparse<f32, u32>, y: &RawSparse<f32, u32>) -> f32 {
+        sparse_angle(x.indexes(), x.values(), y.indexes(), y.values())
+    }
+}
+
+impl<'a> Metric<RawSparse<f32, u16>> for Cosine {
+    fn dist(x: &RawSparse<f32, u16>, y: &RawSparse<f32, u16>) -> f32 {
+        sparse_angle(x.indexes(), x.values(), y.indexes(), y.values())
+    }
+}
+
+impl<'a> Metric<RawSparse<f32, u8>> for Cosine {
+    fn dist(x: &RawSparse<f32, u8>, y: &RawSparse<f32, u8>) -> f32 {
+        sparse_angle(x.indexes(), x.values(), y.indexes(), y.values())
+    }
+}
+
+/// The angle between two vectors from their dot product and squared norms. A zero vector is at a right angle to
+/// everything but another zero vector.
+///
+/// The cosine and its arccosine are taken in f64. The arccosine is steep near a cosine of 1, so an f32 cosine would
+/// put a vector about `3.5e-4` from itself. The dot product and norms are still f32 sums, so nearly parallel vectors
+/// are only as far apart as those sums can tell.
+#[inline]
+pub fn angle(dot: f32, sq_norm_x: f32, sq_norm_y: f32) -> f32 {
+    if sq_norm_x == 0.0 || sq_norm_y == 0.0 {
+        if sq_norm_x == sq_norm_y {
+            0.0
+        } else {
+            std::f32::consts::FRAC_PI_2
+        }
+    } else {
+        let cos = dot as f64 / (sq_norm_x as f64 * sq_norm_y as f64).sqrt();
+        cos.max(-1.0).min(1.0).acos() as f32
+    }
+}
+
+fn sparse_angle<S: Ord>(x_ind: &[S], x_val: &[f32], y_ind: &[S], y_val: &[f32]) -> f32 {
+    angle(
+        dot_sparse_f32_f32(x_ind, x_val, y_ind, y_val),
+        sq_l2_norm_f32(x_val),
+        sq_l2_norm_f32(y_val),
+    )
+}
+
+/// basic sparse function
+pub fn dot_sparse_f32_f32<S>(x_ind: &[S], x_val: &[f32], y_ind: &[S], y_val: &[f32]) -> f32
+where
+    S: Ord,
+{
+    let mut total = 0.0;
+    let mut x_iter = x_ind.iter().zip(x_val);
+    let mut y_iter = y_ind.iter().zip(y_val);
+    let mut x_tr = x_iter.next();
+    let mut y_tr = y_iter.next();
+    while let (Some((xi, xv)), Some((yi, yv))) = (x_tr, y_tr) {
+        if xi < yi {
+            x_tr = x_iter.next();
+        } else if yi < xi {
+            y_tr = y_iter.next();
+        } else {
+            total += *xv * *yv;
+            x_tr = x_iter.next();
+            y_tr = y_iter.next();
+        }
+    }
+    total
+}
+
+dispatched! {
+    ///
+    #[cfg(not(feature = "simd"))]
+    #[inline]
+    pub fn dot_dense_f32(x: &[f32], y: &[f32]) -> f32 {
+        lane_sum(x, y, |xi, yi| xi * yi)
+    }
+}
+
+///
+#[cfg(feature = "simd")]
+#[inline]
+pub fn dot_dense_f32(mut x: &[f32], mut y: &[f32]) -> f32 {
+    let mut d_acc_16 = f32x16::splat(0.0);
+    while y.len() > 16 {
+        let x_simd = f32x16::from_slice_unaligned(x);
+        let y_simd = f32x16::from_slice_unaligned(y);
+        d_acc_16 += x_simd * y_simd;
+        y = &y[16..];
+        x = &x[16..];
+    }
+    let mut d_acc_8 = f32x8::splat(0.0);
+    if y.len() > 8 {
+        let x_simd = f32x8::from_slice_unaligned(x);
+        let y_simd = f32x8::from_slice_unaligned(y);
+        d_acc_8 += x_simd * y_simd;
+        y = &y[8..];
+        x = &x[8..];
+    }
+    let leftover = y
+        .iter()
+        .zip(x)
+        .map(|(xi, yi)| xi * yi)
+        .fold(0.0, |acc, y| acc + y);
+    leftover + d_acc_8.sum() + d_acc_16.sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_traits::PointCloud;
+    use crate::metrics::BATCH;
+    use crate::DefaultCloud;
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    fn assert_close(d: f32, exact: f32) {
+        assert!((d - exact).abs() < 1.0e-6, "{} != {}", d, exact);
+    }
+
+    #[test]
+    fn known_angles() {
+        assert_close(Cosine::dist(&[1.0, 0.0], &[0.0, 1.0]), FRAC_PI_2);
+        assert_close(Cosine::dist(&[1.0, 0.0], &[3.0, 0.0]), 0.0);
+        assert_close(Cosine::dist(&[1.0, 0.0], &[-2.0, 0.0]), PI);
+        assert_close(Cosine::dist(&[1.0, 1.0], &[1.0, 0.0]), PI / 4.0);
+        // The dot product of a vector with itself is its squared norm, and the f64 cosine of that is exactly 1
+        let x = [0.1, 0.2, 0.3, 0.4, 0.5];
+        assert_eq!(Cosine::dist(&x, &x), 0.0);
+    }
+
+    #[test]
+    fn zero_vectors() {
+        let zero = [0.0; 20];
+        let x: Vec<f32> = (0..20).map(|i| i as f32 - 5.5).collect();
+        assert_eq!(Cosine::dist(&zero, &zero), 0.0);
+        assert_eq!(Cosine::dist(&zero, &x), FRAC_PI_2);
+        assert_eq!(Cosine::dist(&x, &zero), FRAC_PI_2);
+        assert_eq!(sparse_angle::<u32>(&[], &[], &[], &[]), 0.0);
+        assert_eq!(sparse_angle::<u32>(&[], &[], &[3], &[1.0]), FRAC_PI_2);
+    }
+
+    #[test]
+    fn sparse_matches_dense() {
+        let x_ind: Vec<u32> = vec![0, 3, 4, 9];
+        let x_val = vec![1.0, -2.0, 0.5, 3.0];
+        let y_ind: Vec<u32> = vec![1, 3, 9];
+        let y_val = vec![4.0, 1.0, -1.5];
+        let dense = |ind: &[u32], val: &[f32]| {
+            let mut dense = vec![0.0; 10];
+            for (i, v) in ind.iter().zip(val) {
+                dense[*i as usize] = *v;
+            }
+            dense
+        };
+        assert_close(
+            sparse_angle(&x_ind, &x_val, &y_ind, &y_val),
+            Cosine::dist(&dense(&x_ind, &x_val), &dense(&y_ind, &y_val)),
+        );
+    }
+
+    #[test]
+    fn batches_match_dist() {
+        let dim = 37;
+        let count = 5 * BATCH + 3;
+        let mut data: Vec<f32> = (0..count * dim)
+            .map(|i| ((i * 7919) % 113) as f32 - 56.0)
+            .collect();
+        // A zero vector in the middle of a batch
+        for c in &mut data[3 * dim..4 * dim] {
+            *c = 0.0;
+        }
+        let cloud = DefaultCloud::<Cosine>::new(data, dim).unwrap();
+        let indexes: Vec<usize> = (0..count).collect();
+        for x in &[0, 3] {
+            let point = cloud.point(*x).unwrap();
+            let dists = cloud.distances_to_point(&point, &indexes).unwrap();
+            for (i, d) in indexes.iter().zip(&dists) {
+                let y = cloud.point(*i).unwrap();
+                assert_close(*d, Cosine::dist(&point, &y));
+            }
+            assert_eq!(dists[3], if *x == 3 { 0.0 } else { FRAC_PI_2 });
+        }
+    }
+}
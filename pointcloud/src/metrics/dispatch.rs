@@ -0,0 +1,145 @@
+//! Runtime dispatch for the portable dense kernels.
+//!
+//! Without the `simd` feature the dense kernels are plain loops that the compiler vectorizes, but only for the
+//! instruction sets the crate is compiled for, which on x86_64 is SSE2 unless you pass `target-cpu`. The
+//! `dispatched!` macro compiles a kernel again for AVX2, and for AVX-512 with the `avx512` feature, and picks the
+//! widest one the CPU has the first time a kernel runs. Enabling `avx512f` on a function needs Rust 1.89, so that
+//! feature is off by default. NEON is part of the aarch64 baseline, so there the portable kernel already uses it.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// The instruction set the dense kernels run with, see [`kernel_level`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelLevel {
+    /// Whatever the crate was compiled for
+    Portable,
+    /// AVX2 and FMA on x86_64
+    Avx2,
+    /// AVX-512F on x86_64, only picked with the `avx512` feature
+    Avx512,
+    /// NEON on aarch64, the same code as `Portable`
+    Neon,
+}
+
+impl KernelLevel {
+    fn code(self) -> u8 {
+        match self {
+            KernelLevel::Portable => 1,
+            KernelLevel::Avx2 => 2,
+            KernelLevel::Avx512 => 3,
+            KernelLevel::Neon => 4,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<KernelLevel> {
+        match code {
+            1 => Some(KernelLevel::Portable),
+            2 => Some(KernelLevel::Avx2),
+            3 => Some(KernelLevel::Avx512),
+            4 => Some(KernelLevel::Neon),
+            _ => None,
+        }
+    }
+}
+
+static KERNEL_LEVEL: AtomicU8 = AtomicU8::new(0);
+
+#[cfg(test)]
+thread_local! {
+    static FORCED_KERNEL_LEVEL: std::cell::Cell<Option<KernelLevel>> = std::cell::Cell::new(None);
+}
+
+/// Runs `f` with the dense kernels of this level on the current thread, so that tests can check each of them.
+#[cfg(test)]
+pub(crate) fn with_kernel_level<T>(level: KernelLevel, f: impl FnOnce() -> T) -> T {
+    let previous = FORCED_KERNEL_LEVEL.with(|forced| forced.replace(Some(level)));
+    let result = f();
+    FORCED_KERNEL_LEVEL.with(|forced| forced.set(previous));
+    result
+}
+
+/// The levels this CPU can run, the portable one first.
+#[cfg(test)]
+pub(crate) fn supported_kernel_levels() -> Vec<KernelLevel> {
+    let mut levels = vec![KernelLevel::Portable];
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+            levels.push(KernelLevel::Avx2);
+        }
+        if cfg!(feature = "avx512") && is_x86_feature_detected!("avx512f") {
+            levels.push(KernelLevel::Avx512);
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    levels.push(KernelLevel::Neon);
+    levels
+}
+
+#[cfg(target_arch = "x86_64")]
+fn detect_kernel_level() -> KernelLevel {
+    if cfg!(feature = "avx512") && is_x86_feature_detected!("avx512f") {
+        KernelLevel::Avx512
+    } else if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+        KernelLevel::Avx2
+    } else {
+        KernelLevel::Portable
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn detect_kernel_level() -> KernelLevel {
+    KernelLevel::Neon
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn detect_kernel_level() -> KernelLevel {
+    KernelLevel::Portable
+}
+
+/// The widest instruction set the dense kernels use on this CPU. It's detected once and cached.
+pub fn kernel_level() -> KernelLevel {
+    #[cfg(test)]
+    {
+        if let Some(level) = FORCED_KERNEL_LEVEL.with(|forced| forced.get()) {
+            return level;
+        }
+    }
+    match KernelLevel::from_code(KERNEL_LEVEL.load(Ordering::Relaxed)) {
+        Some(level) => level,
+        None => {
+            let level = detect_kernel_level();
+            KERNEL_LEVEL.store(level.code(), Ordering::Relaxed);
+            level
+        }
+    }
+}
+
+/// Defines a kernel that's compiled once for the crate's target and once more for each x86_64 instruction set in
+/// [`KernelLevel`], and runs the one [`kernel_level`] picks. The body is the same for all of them, write it so that
+/// it vectorizes, see [`super::lane_sum`].
+macro_rules! dispatched {
+    ($(#[$meta:meta])* pub fn $name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)? $body:block) => {
+        $(#[$meta])*
+        pub fn $name($($arg: $ty),*) $(-> $ret)? {
+            #[cfg(target_arch = "x86_64")]
+            {
+                #[cfg(feature = "avx512")]
+                #[target_feature(enable = "avx512f")]
+                unsafe fn avx512($($arg: $ty),*) $(-> $ret)? $body
+
+                #[target_feature(enable = "avx2,fma")]
+                unsafe fn avx2($($arg: $ty),*) $(-> $ret)? $body
+
+                // Safe as the CPU has the features, that's what the level says
+                match $crate::metrics::kernel_level() {
+                    #[cfg(feature = "avx512")]
+                    $crate::metrics::KernelLevel::Avx512 => return unsafe { avx512($($arg),*) },
+                    $crate::metrics::KernelLevel::Avx2 => return unsafe { avx2($($arg),*) },
+                    _ => {}
+                }
+            }
+            $body
+        }
+    };
+}
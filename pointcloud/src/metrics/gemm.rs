@@ -0,0 +1,160 @@
+//! L2 distances through a matrix product.
+//!
+//! The squared distance is `|x|^2 + |y|^2 - 2 x.y`, so a block of squared distances is the norms of the rows and
+//! columns plus `-2` times the product of the two blocks of points. `matrixmultiply` computes that product with a
+//! cache blocked kernel, which beats comparing the points one pair at a time once there are enough of them to pay for
+//! packing them into contiguous matrices, see [`GEMM_MIN_POINTS`].
+//!
+//! This loses precision for points that are close together relative to their norms, as the distance is the
+//! difference of much larger numbers. Those are the distances a nearest neighbor query cares about, so the ones below
+//! [`GEMM_EXACT_BELOW`] of the norms are recomputed one pair at a time. The distance from a point to itself comes out
+//! as exactly 0.
+
+use super::{sq_l2_dense_f32, sq_l2_dense_f32_batch, sq_l2_norm_f32};
+
+/// [`L2`](super::L2) uses the matrix product to compare a point to at least this many points, and for blocks of at
+/// least the square of this many distances.
+pub const GEMM_MIN_POINTS: usize = 64;
+
+/// A squared distance from the matrix product that's less than this fraction of the squared norms of its two points
+/// is recomputed exactly. Below it the cancellation costs more than a couple of digits of the distance.
+pub const GEMM_EXACT_BELOW: f32 = 1.0e-2;
+
+/// Copies the points into a row major matrix, one point per row.
+fn pack(points: &[&[f32]], dim: usize) -> Vec<f32> {
+    let mut packed = Vec::with_capacity(points.len() * dim);
+    for p in points {
+        packed.extend_from_slice(&p[..dim]);
+    }
+    packed
+}
+
+/// The squared L2 distances between each of the `xs` and each of the `ys`, row `k` of `dists` is for `xs[k]`. The
+/// matrix product needs the points to all have the same dimension, if they don't the rows are computed with the
+/// batched kernel instead, which handles ragged lengths.
+pub fn sq_l2_dense_f32_gemm(xs: &[&[f32]], ys: &[&[f32]], dists: &mut [f32]) {
+    let m = xs.len();
+    let n = ys.len();
+    if m == 0 || n == 0 {
+        return;
+    }
+    let dim = xs[0].len();
+    assert!(dists.len() >= m * n);
+    if xs.iter().chain(ys).any(|p| p.len() != dim) {
+        for (row, x) in dists.chunks_mut(n).zip(xs) {
+            sq_l2_dense_f32_batch(x, ys, row);
+        }
+        return;
+    }
+    let packed_xs = pack(xs, dim);
+    let packed_ys = pack(ys, dim);
+    // dists = -2 xs ys^T, ys is row major so its transpose has a row stride of 1
+    unsafe {
+        matrixmultiply::sgemm(
+            m,
+            dim,
+            n,
+            -2.0,
+            packed_xs.as_ptr(),
+            dim as isize,
+            1,
+            packed_ys.as_ptr(),
+            1,
+            dim as isize,
+            0.0,
+            dists.as_mut_ptr(),
+            n as isize,
+            1,
+        );
+    }
+    let y_norms: Vec<f32> = ys.iter().map(|y| sq_l2_norm_f32(y)).collect();
+    for (row, x) in dists.chunks_mut(n).zip(xs) {
+        let x_norm = sq_l2_norm_f32(x);
+        for ((d, y_norm), y) in row.iter_mut().zip(&y_norms).zip(ys) {
+            let norms = x_norm + y_norm;
+            *d += norms;
+            if *d < GEMM_EXACT_BELOW * norms {
+                *d = sq_l2_dense_f32(x, y);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_traits::Metric;
+    use crate::L2;
+
+    /// Points on a small grid, far from the origin so that their norms dwarf the distances between them.
+    fn far_points(count: usize, dim: usize, offset: f32) -> Vec<Vec<f32>> {
+        (0..count)
+            .map(|i| {
+                (0..dim)
+                    .map(|j| offset + ((i * 7 + j * 3) % 11) as f32 * 0.01)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gemm_matches_the_exact_kernel_at_large_norms() {
+        for offset in &[0.0f32, 100.0, 1.0e4] {
+            let points = far_points(80, 37, *offset);
+            let refs: Vec<&[f32]> = points.iter().map(|p| &p[..]).collect();
+            let mut dists = vec![0.0; refs.len() * refs.len()];
+            sq_l2_dense_f32_gemm(&refs, &refs, &mut dists);
+            for (i, x) in refs.iter().enumerate() {
+                for (j, y) in refs.iter().enumerate() {
+                    let exact = sq_l2_dense_f32(x, y);
+                    let d = dists[i * refs.len() + j];
+                    if i == j {
+                        assert_eq!(d, 0.0);
+                    }
+                    assert!(
+                        (d - exact).abs() <= 1.0e-3 * exact.max(1.0e-3),
+                        "offset {}: {} != {}",
+                        offset,
+                        d,
+                        exact
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn l2_dists_match_dist_at_large_norms() {
+        let points = far_points(GEMM_MIN_POINTS + 9, 20, 1.0e4);
+        let refs: Vec<&[f32]> = points.iter().map(|p| &p[..]).collect();
+        let mut dists = vec![0.0; refs.len()];
+        L2::dists(refs[0], &refs, &mut dists);
+        for (y, d) in refs.iter().zip(&dists) {
+            let exact = L2::dist(refs[0], y);
+            assert!((d - exact).abs() <= 1.0e-3 * exact.max(1.0e-3));
+        }
+    }
+
+    #[test]
+    fn ragged_lengths_fall_back() {
+        let points = far_points(GEMM_MIN_POINTS + 9, 20, 1.0);
+        let refs: Vec<&[f32]> = points.iter().map(|p| &p[..]).collect();
+        let mut dists = vec![0.0; refs.len()];
+        for x in &[vec![0.5f32; 25], vec![0.5f32; 7]] {
+            L2::dists(x, &refs, &mut dists);
+            for (y, d) in refs.iter().zip(&dists) {
+                assert!((d - L2::dist(x, y)).abs() <= 1.0e-4);
+            }
+        }
+
+        let mut ragged = refs.clone();
+        ragged[3] = &refs[3][..5];
+        let mut block = vec![0.0; ragged.len() * ragged.len()];
+        L2::dist_block(&ragged, &ragged, &mut block);
+        for (i, x) in ragged.iter().enumerate() {
+            for (j, y) in ragged.iter().enumerate() {
+                assert!((block[i * ragged.len() + j] - L2::dist(x, y)).abs() <= 1.0e-4);
+            }
+        }
+    }
+}
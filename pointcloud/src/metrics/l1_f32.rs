@@ -1,8 +1,11 @@
 //! f32 implementations of the L1 metric.
 
 use super::L1;
+#[cfg(not(feature = "simd"))]
+use super::{batch_lane_sum, lane_sum, lane_sum_single};
 use crate::base_traits::Metric;
 use crate::points::*;
+#[cfg(feature = "simd")]
 use packed_simd::*;
 use std::ops::Deref;
 
@@ -10,6 +13,11 @@ impl Metric<[f32]> for L1 {
     fn dist(x: &[f32], y: &[f32]) -> f32 {
         l1_dense_f32(x.deref(), y.deref()).sqrt()
     }
+
+    fn dists(x: &[f32], ys: &[&[f32]], dists: &mut [f32]) {
+        l1_dense_f32_batch(x, ys, dists);
+        dists.iter_mut().for_each(|d| *d = d.sqrt());
+    }
 }
 
 impl<'a> Metric<RawSparse<f32, u32>> for L1 {
@@ -30,7 +38,33 @@ impl<'a> Metric<RawSparse<f32, u8>> for L1 {
     }
 }
 
+dispatched! {
+    ///
+    #[cfg(not(feature = "simd"))]
+    #[inline]
+    pub fn l1_dense_f32(x: &[f32], y: &[f32]) -> f32 {
+        lane_sum(x, y, |xi, yi| (xi - yi).abs())
+    }
+}
+
+dispatched! {
+    /// The L1 norms of the differences between `x` and each of the `ys`, see [`super::BATCH`].
+    #[cfg(not(feature = "simd"))]
+    pub fn l1_dense_f32_batch(x: &[f32], ys: &[&[f32]], dists: &mut [f32]) {
+        batch_lane_sum(x, ys, dists, |xi, yi| (xi - yi).abs())
+    }
+}
+
+/// The L1 norms of the differences between `x` and each of the `ys`.
+#[cfg(feature = "simd")]
+pub fn l1_dense_f32_batch(x: &[f32], ys: &[&[f32]], dists: &mut [f32]) {
+    for (d, y) in dists.iter_mut().zip(ys) {
+        *d = l1_dense_f32(x, y);
+    }
+}
+
 ///
+#[cfg(feature = "simd")]
 pub fn l1_dense_f32(mut x: &[f32], mut y: &[f32]) -> f32 {
     let mut d_acc_16 = f32x16::splat(0.0);
     while y.len() > 16 {
@@ -58,7 +92,17 @@ pub fn l1_dense_f32(mut x: &[f32], mut y: &[f32]) -> f32 {
     leftover + d_acc_8.sum() + d_acc_16.sum()
 }
 
+dispatched! {
+    ///
+    #[cfg(not(feature = "simd"))]
+    #[inline]
+    pub fn l1_norm_f32(x: &[f32]) -> f32 {
+        lane_sum_single(x, |xi| xi.abs())
+    }
+}
+
 ///
+#[cfg(feature = "simd")]
 #[inline]
 pub fn l1_norm_f32(mut x: &[f32]) -> f32 {
     let mut d_acc_16 = f32x16::splat(0.0);
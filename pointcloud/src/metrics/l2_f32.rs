@@ -1,8 +1,13 @@
 //! f32 implementations of the L1 metric.
 
 use super::L2;
+#[cfg(not(feature = "simd"))]
+use super::{batch_lane_sum, lane_sum, lane_sum_single};
+#[cfg(feature = "gemm")]
+use super::{sq_l2_dense_f32_gemm, GEMM_MIN_POINTS};
 use crate::base_traits::Metric;
 use crate::points::*;
+#[cfg(feature = "simd")]
 use packed_simd::*;
 use std::ops::Deref;
 
@@ -10,6 +15,31 @@ impl Metric<[f32]> for L2 {
     fn dist(x: &[f32], y: &[f32]) -> f32 {
         sq_l2_dense_f32(x.deref(), y.deref()).sqrt()
     }
+
+    fn dists(x: &[f32], ys: &[&[f32]], dists: &mut [f32]) {
+        #[cfg(feature = "gemm")]
+        {
+            if ys.len() >= GEMM_MIN_POINTS {
+                sq_l2_dense_f32_gemm(&[x], ys, dists);
+                dists.iter_mut().for_each(|d| *d = d.sqrt());
+                return;
+            }
+        }
+        sq_l2_dense_f32_batch(x, ys, dists);
+        dists.iter_mut().for_each(|d| *d = d.sqrt());
+    }
+
+    #[cfg(feature = "gemm")]
+    fn dist_block(xs: &[&[f32]], ys: &[&[f32]], dists: &mut [f32]) {
+        if xs.len() * ys.len() >= GEMM_MIN_POINTS * GEMM_MIN_POINTS {
+            sq_l2_dense_f32_gemm(xs, ys, dists);
+            dists.iter_mut().for_each(|d| *d = d.sqrt());
+        } else {
+            for (row, x) in dists.chunks_mut(ys.len().max(1)).zip(xs) {
+                Self::dists(x, ys, row);
+            }
+        }
+    }
 }
 
 impl<'a> Metric<RawSparse<f32, u32>> for L2 {
@@ -82,7 +112,33 @@ where
     }
 }
 
+dispatched! {
+    ///
+    #[cfg(not(feature = "simd"))]
+    #[inline]
+    pub fn sq_l2_dense_f32(x: &[f32], y: &[f32]) -> f32 {
+        lane_sum(x, y, |xi, yi| (xi - yi) * (xi - yi))
+    }
+}
+
+dispatched! {
+    /// The squared distances from `x` to each of the `ys`, see [`super::BATCH`].
+    #[cfg(not(feature = "simd"))]
+    pub fn sq_l2_dense_f32_batch(x: &[f32], ys: &[&[f32]], dists: &mut [f32]) {
+        batch_lane_sum(x, ys, dists, |xi, yi| (xi - yi) * (xi - yi))
+    }
+}
+
+/// The squared distances from `x` to each of the `ys`.
+#[cfg(feature = "simd")]
+pub fn sq_l2_dense_f32_batch(x: &[f32], ys: &[&[f32]], dists: &mut [f32]) {
+    for (d, y) in dists.iter_mut().zip(ys) {
+        *d = sq_l2_dense_f32(x, y);
+    }
+}
+
 ///
+#[cfg(feature = "simd")]
 #[inline]
 pub fn sq_l2_dense_f32(mut x: &[f32], mut y: &[f32]) -> f32 {
     let mut d_acc_16 = f32x16::splat(0.0);
@@ -111,7 +167,17 @@ pub fn sq_l2_dense_f32(mut x: &[f32], mut y: &[f32]) -> f32 {
     leftover + d_acc_8.sum() + d_acc_16.sum()
 }
 
+dispatched! {
+    ///
+    #[cfg(not(feature = "simd"))]
+    #[inline]
+    pub fn sq_l2_norm_f32(x: &[f32]) -> f32 {
+        lane_sum_single(x, |xi| xi * xi)
+    }
+}
+
 ///
+#[cfg(feature = "simd")]
 #[inline]
 pub fn sq_l2_norm_f32(mut x: &[f32]) -> f32 {
     let mut d_acc_16 = f32x16::splat(0.0);
@@ -1,14 +1,27 @@
 //! Various implementations of the L2 metric for types that can be easily converted to f32.
 
 use super::L2;
+#[cfg(not(feature = "simd"))]
+use super::{lane_sum, lane_sum_single};
 use crate::base_traits::Metric;
 use crate::points::*;
+#[cfg(feature = "simd")]
 use packed_simd::*;
 use std::ops::Deref;
 
 macro_rules! make_l2_distance {
     ($base:ident, $simd_16_base:ident, $simd_8_base:ident, $sparse_base:ident, $dist_base:ident, $norm_base:ident) => {
         ///
+        #[cfg(not(feature = "simd"))]
+        #[inline]
+        pub fn $dist_base(x: &[$base], y: &[$base]) -> f32 {
+            lane_sum(x, y, |xi, yi| {
+                (xi as f32 - yi as f32) * (xi as f32 - yi as f32)
+            })
+        }
+
+        ///
+        #[cfg(feature = "simd")]
         #[inline]
         pub fn $dist_base(mut x: &[$base], mut y: &[$base]) -> f32 {
             let mut d_acc_16 = f32x16::splat(0.0);
@@ -42,6 +55,14 @@ macro_rules! make_l2_distance {
         }
 
         ///
+        #[cfg(not(feature = "simd"))]
+        #[inline]
+        pub fn $norm_base(x: &[$base]) -> f32 {
+            lane_sum_single(x, |xi| (xi as f32) * (xi as f32))
+        }
+
+        ///
+        #[cfg(feature = "simd")]
         #[inline]
         pub fn $norm_base(mut x: &[$base]) -> f32 {
             let mut d_acc_16 = f32x16::splat(0.0);
@@ -1,5 +1,16 @@
 //! Metrics.
+//!
+//! The dense kernels use `packed_simd` when the `simd` feature is on, which needs a nightly compiler. Without it they
+//! keep the same 16 lanes of partial sums in plain arrays, which the compiler vectorizes on stable, and they're
+//! compiled for the wider instruction sets the CPU might have as well, see [`dispatch`].
+//!
+//! The metrics on `[f32]` also compare a point to [`BATCH`] points at once in [`Metric::dists`](crate::Metric::dists),
+//! so that the chunk of the query point being read stays in registers across the batch. With the `gemm` feature the
+//! L2 metric computes large blocks of distances with a matrix product instead, see the `gemm` module.
 
+#[macro_use]
+pub mod dispatch;
+pub use dispatch::{kernel_level, KernelLevel};
 pub mod l2_misc;
 pub use l2_misc::*;
 pub mod l1_misc;
@@ -8,9 +19,245 @@ pub mod l2_f32;
 pub use l2_f32::*;
 pub mod l1_f32;
 pub use l1_f32::*;
+pub mod cosine_f32;
+pub use cosine_f32::*;
+#[cfg(feature = "gemm")]
+pub mod gemm;
+#[cfg(feature = "gemm")]
+pub use gemm::*;
 
 #[derive(Debug)]
 /// L2 distance trait.
 pub struct L2 {}
 /// L1 distance trait
 pub struct L1 {}
+#[derive(Debug)]
+/// Cosine distance trait. This is the angle between the vectors in radians rather than one minus their cosine
+/// similarity, as the angle satisfies the triangle inequality the cover tree relies on.
+pub struct Cosine {}
+
+/// The number of points the batched kernels compare to the query point at once.
+pub const BATCH: usize = 8;
+
+/// The number of points [`PointCloud::distances_to_point`](crate::PointCloud::distances_to_point) hands to
+/// [`Metric::dists`](crate::Metric::dists) at once.
+#[cfg(not(feature = "gemm"))]
+pub const DISTANCE_BLOCK: usize = BATCH;
+
+/// The number of points [`PointCloud::distances_to_point`](crate::PointCloud::distances_to_point) hands to
+/// [`Metric::dists`](crate::Metric::dists) at once, enough for a matrix product to pay off.
+#[cfg(feature = "gemm")]
+pub const DISTANCE_BLOCK: usize = 256;
+
+/// The number of partial sums the portable kernels keep.
+#[cfg(not(feature = "simd"))]
+const LANES: usize = 16;
+
+/// Sums `f` over the pairs of coordinates in `LANES` partial sums, so that the loop vectorizes.
+#[cfg(not(feature = "simd"))]
+#[inline(always)]
+pub(crate) fn lane_sum<T: Copy, F: Fn(T, T) -> f32>(x: &[T], y: &[T], f: F) -> f32 {
+    // The remainders have to start at the same coordinate, so both go to the shorter length
+    let len = x.len().min(y.len());
+    let (x, y) = (&x[..len], &y[..len]);
+    let mut acc = [0.0f32; LANES];
+    let x_chunks = x.chunks_exact(LANES);
+    let y_chunks = y.chunks_exact(LANES);
+    let leftover: f32 = x_chunks
+        .remainder()
+        .iter()
+        .zip(y_chunks.remainder())
+        .map(|(xi, yi)| f(*xi, *yi))
+        .sum();
+    for (xc, yc) in x_chunks.zip(y_chunks) {
+        for ((a, xi), yi) in acc.iter_mut().zip(xc).zip(yc) {
+            *a += f(*xi, *yi);
+        }
+    }
+    leftover + acc.iter().sum::<f32>()
+}
+
+/// Sums `f` over the coordinates in `LANES` partial sums, so that the loop vectorizes.
+#[cfg(not(feature = "simd"))]
+#[inline(always)]
+pub(crate) fn lane_sum_single<T: Copy, F: Fn(T) -> f32>(x: &[T], f: F) -> f32 {
+    let mut acc = [0.0f32; LANES];
+    let x_chunks = x.chunks_exact(LANES);
+    let leftover: f32 = x_chunks.remainder().iter().map(|xi| f(*xi)).sum();
+    for xc in x_chunks {
+        for (a, xi) in acc.iter_mut().zip(xc) {
+            *a += f(*xi);
+        }
+    }
+    leftover + acc.iter().sum::<f32>()
+}
+
+/// Sums `f` over the pairs of coordinates of `x` and each of the `ys`, [`BATCH`] of the `ys` at a time. The partial
+/// sums are the same as [`lane_sum`]'s, so the results are too, also when the `ys` have different lengths.
+#[cfg(not(feature = "simd"))]
+#[inline(always)]
+pub(crate) fn batch_lane_sum<F: Fn(f32, f32) -> f32>(
+    x: &[f32],
+    ys: &[&[f32]],
+    dists: &mut [f32],
+    f: F,
+) {
+    for (batch, batch_dists) in ys.chunks(BATCH).zip(dists.chunks_mut(BATCH)) {
+        let len = batch.iter().fold(x.len(), |len, y| len.min(y.len()));
+        let chunked_len = len - len % LANES;
+        let mut acc = [[0.0f32; LANES]; BATCH];
+        for start in (0..chunked_len).step_by(LANES) {
+            let xc = &x[start..start + LANES];
+            for (lanes, y) in acc.iter_mut().zip(batch) {
+                let yc = &y[start..start + LANES];
+                for ((a, xi), yi) in lanes.iter_mut().zip(xc).zip(yc) {
+                    *a += f(*xi, *yi);
+                }
+            }
+        }
+        for ((d, lanes), y) in batch_dists.iter_mut().zip(acc.iter_mut()).zip(batch) {
+            // The points longer than the shortest one in the batch finish their own chunks
+            let y_len = x.len().min(y.len());
+            let y_chunked_len = y_len - y_len % LANES;
+            for start in (chunked_len..y_chunked_len).step_by(LANES) {
+                let xc = &x[start..start + LANES];
+                let yc = &y[start..start + LANES];
+                for ((a, xi), yi) in lanes.iter_mut().zip(xc).zip(yc) {
+                    *a += f(*xi, *yi);
+                }
+            }
+            let leftover: f32 = x[y_chunked_len..y_len]
+                .iter()
+                .zip(&y[y_chunked_len..y_len])
+                .map(|(xi, yi)| f(*xi, *yi))
+                .sum();
+            *d = leftover + lanes.iter().sum::<f32>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dispatch::{supported_kernel_levels, with_kernel_level};
+    use super::*;
+    use crate::base_traits::Metric;
+
+    /// Lengths around the multiples of the lanes and of the batch.
+    const LENGTHS: &[usize] = &[0, 1, 7, 8, 9, 15, 16, 17, 31, 33, 100];
+
+    fn point(len: usize, seed: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| ((i * 13 + seed * 7) % 23) as f32 * 0.25 - 2.5)
+            .collect()
+    }
+
+    fn assert_close(d: f32, exact: f32) {
+        assert!(
+            (d - exact).abs() <= 1.0e-5 * exact.abs().max(1.0),
+            "{} != {}",
+            d,
+            exact
+        );
+    }
+
+    /// Compares `M::dists` to `M::dist` for batches of each count, with all the points of length `len` or, when
+    /// `ragged`, of lengths cycling through [`LENGTHS`].
+    fn check_dists<M: Metric<[f32]>>(len: usize, ragged: bool) {
+        for count in &[1, BATCH - 1, BATCH, BATCH + 1, 2 * BATCH + 3] {
+            let x = point(len, 0);
+            let ys: Vec<Vec<f32>> = (0..*count)
+                .map(|i| {
+                    let y_len = if ragged {
+                        LENGTHS[(len + i) % LENGTHS.len()]
+                    } else {
+                        len
+                    };
+                    point(y_len, i + 1)
+                })
+                .collect();
+            let refs: Vec<&[f32]> = ys.iter().map(|y| &y[..]).collect();
+            let mut dists = vec![0.0; refs.len()];
+            M::dists(&x, &refs, &mut dists);
+            for (y, d) in refs.iter().zip(&dists) {
+                assert_close(*d, M::dist(&x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn dists_match_dist() {
+        for level in supported_kernel_levels() {
+            with_kernel_level(level, || {
+                for len in LENGTHS {
+                    check_dists::<L2>(*len, false);
+                    check_dists::<L1>(*len, false);
+                    check_dists::<Cosine>(*len, false);
+                }
+            });
+        }
+    }
+
+    #[cfg(not(feature = "simd"))]
+    #[test]
+    fn dists_match_dist_on_ragged_lengths() {
+        for level in supported_kernel_levels() {
+            with_kernel_level(level, || {
+                for len in LENGTHS {
+                    check_dists::<L2>(*len, true);
+                    check_dists::<L1>(*len, true);
+                    check_dists::<Cosine>(*len, true);
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn kernel_levels_agree() {
+        for len in LENGTHS {
+            let x = point(*len, 0);
+            let y = point(*len, 1);
+            let portable = with_kernel_level(KernelLevel::Portable, || {
+                (
+                    sq_l2_dense_f32(&x, &y),
+                    l1_dense_f32(&x, &y),
+                    dot_dense_f32(&x, &y),
+                    sq_l2_norm_f32(&x),
+                )
+            });
+            for level in supported_kernel_levels() {
+                let dispatched = with_kernel_level(level, || {
+                    (
+                        sq_l2_dense_f32(&x, &y),
+                        l1_dense_f32(&x, &y),
+                        dot_dense_f32(&x, &y),
+                        sq_l2_norm_f32(&x),
+                    )
+                });
+                assert_close(dispatched.0, portable.0);
+                assert_close(dispatched.1, portable.1);
+                assert_close(dispatched.2, portable.2);
+                assert_close(dispatched.3, portable.3);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "simd"))]
+    #[test]
+    fn batch_lane_sum_matches_lane_sum() {
+        let f = |xi: f32, yi: f32| (xi - yi) * (xi - yi);
+        for len in LENGTHS {
+            let x = point(*len, 0);
+            for count in &[1, BATCH - 1, BATCH, BATCH + 1, 2 * BATCH + 3] {
+                let ys: Vec<Vec<f32>> = (0..*count)
+                    .map(|i| point(LENGTHS[(len + i) % LENGTHS.len()], i + 1))
+                    .collect();
+                let refs: Vec<&[f32]> = ys.iter().map(|y| &y[..]).collect();
+                let mut dists = vec![0.0; refs.len()];
+                batch_lane_sum(&x, &refs, &mut dists, f);
+                for (y, d) in refs.iter().zip(&dists) {
+                    assert_eq!(*d, lane_sum(&x, y, f));
+                }
+            }
+        }
+    }
+}
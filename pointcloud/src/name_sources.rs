@@ -0,0 +1,107 @@
+//! Name sets to glue onto the data sources with [`crate::SimpleNamedCloud`].
+//!
+//! The data sources name their points with the string of their index. If your points have real names, say a hash
+//! or a row id from a CSV, load them into a [`NameIndex`] and you can go from a name to the index and back.
+
+use crate::base_traits::*;
+use crate::pc_errors::*;
+use hashbrown::HashMap;
+
+/// A bidirectional map between the names of the points and their indexes. A point has one name, but can have any
+/// number of aliases that also resolve to it.
+#[derive(Debug, Clone, Default)]
+pub struct NameIndex {
+    names: Vec<String>,
+    indexes: HashMap<String, usize>,
+}
+
+impl NameIndex {
+    /// Creates the index, the name of point `i` is `names[i]`. Errors if a name is used twice.
+    pub fn new(names: Vec<String>) -> PointCloudResult<NameIndex> {
+        let mut indexes = HashMap::with_capacity(names.len());
+        for (i, name) in names.iter().enumerate() {
+            if indexes.insert(name.clone(), i).is_some() {
+                return Err(ParsingError::RegularParsingError(
+                    "A point name is used more than once",
+                )
+                .into());
+            }
+        }
+        Ok(NameIndex { names, indexes })
+    }
+
+    /// Gives the point a new name. The old name and the aliases of the point still resolve to it.
+    pub fn rename(&mut self, pi: usize, name: &str) -> PointCloudResult<()> {
+        self.alias(pi, name)?;
+        self.names[pi] = name.to_string();
+        Ok(())
+    }
+
+    /// Adds another name that resolves to the point. Errors if the name already belongs to another point.
+    pub fn alias(&mut self, pi: usize, alias: &str) -> PointCloudResult<()> {
+        if pi >= self.names.len() {
+            return Err(PointCloudError::data_access(pi, "name index".to_string()));
+        }
+        match self.indexes.get(alias) {
+            Some(i) if *i != pi => Err(ParsingError::RegularParsingError(
+                "The name already belongs to another point",
+            )
+            .into()),
+            _ => {
+                self.indexes.insert(alias.to_string(), pi);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl NamedSet for NameIndex {
+    fn len(&self) -> usize {
+        self.names.len()
+    }
+    fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        self.names
+            .get(pi)
+            .cloned()
+            .ok_or_else(|| PointCloudError::data_access(pi, "name index".to_string()))
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        self.indexes
+            .get(pn)
+            .cloned()
+            .ok_or(PointCloudError::UnknownName)
+    }
+    fn names(&self) -> Vec<String> {
+        self.names.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_index_round_trip() {
+        let mut names =
+            NameIndex::new(vec!["a".to_string(), "b".to_string(), "c".to_string()]).unwrap();
+        assert_eq!(names.index("b").unwrap(), 1);
+        assert_eq!(names.name(2).unwrap(), "c");
+        assert!(names.index("d").is_err());
+
+        names.alias(0, "first").unwrap();
+        assert_eq!(names.index("first").unwrap(), 0);
+        assert_eq!(names.name(0).unwrap(), "a");
+        assert!(names.alias(1, "first").is_err());
+
+        names.rename(1, "second").unwrap();
+        assert_eq!(names.name(1).unwrap(), "second");
+        assert_eq!(names.index("second").unwrap(), 1);
+        assert_eq!(names.index("b").unwrap(), 1);
+        assert!(names.rename(5, "sixth").is_err());
+
+        assert!(NameIndex::new(vec!["a".to_string(), "a".to_string()]).is_err());
+    }
+}
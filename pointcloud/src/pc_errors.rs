@@ -42,6 +42,8 @@ pub enum PointCloudError {
     NotSorted,
     /// Most common error, the given point name isn't present in the training data
     UnknownName,
+    /// The given external id isn't in the cloud, or the cloud doesn't have external ids
+    UnknownExternalId,
     /// IO error when opening files
     IoError(io::Error),
     /// Parsing error when loading a CSV file
@@ -65,6 +67,9 @@ impl fmt::Display for PointCloudError {
             PointCloudError::UnknownName => {
                 write!(f, "there was an issue grabbing a name from the known names")
             }
+            PointCloudError::UnknownExternalId => {
+                write!(f, "the external id is not one of the known ids")
+            }
             PointCloudError::NodeNestingError { .. } => {
                 write!(f, "There is a temporary node in a working tree")
             }
@@ -90,6 +95,7 @@ impl Error for PointCloudError {
             PointCloudError::UnknownName => {
                 "there was an issue grabbing a name from the known names"
             }
+            PointCloudError::UnknownExternalId => "the external id is not one of the known ids",
             PointCloudError::NodeNestingError { .. } => {
                 "There is a temporary node in a working tree"
             }
@@ -106,6 +112,7 @@ impl Error for PointCloudError {
             PointCloudError::ParsingError(ref e) => Some(e),
             PointCloudError::DataAccessError { .. } => None,
             PointCloudError::UnknownName => None,
+            PointCloudError::UnknownExternalId => None,
             PointCloudError::NodeNestingError { .. } => None,
             PointCloudError::MetricError { .. } => None,
             PointCloudError::NotSorted { .. } => None,
@@ -170,6 +177,15 @@ pub enum ParsingError {
         /// The column name that was messed up
         key: String,
     },
+    /// An error reading a parquet or arrow file
+    ColumnarReadError {
+        /// The file the error occored in
+        file_name: String,
+        /// The column that was messed up, empty if the file itself couldn't be read
+        column: String,
+        /// What went wrong
+        reason: String,
+    },
     /// Something else happened parsing a string
     RegularParsingError(&'static str),
 }
@@ -187,6 +203,7 @@ impl Error for ParsingError {
             ParsingError::MalformedYamlError { .. } => "there is a error reading a yaml entry",
             ParsingError::MissingYamlError { .. } => "not all message fields set",
             ParsingError::CSVReadError { .. } => "issue reading a CSV entry",
+            ParsingError::ColumnarReadError { .. } => "issue reading a parquet or arrow column",
             ParsingError::RegularParsingError(..) => "Error parsing a string",
         }
     }
@@ -196,6 +213,7 @@ impl Error for ParsingError {
             ParsingError::MalformedYamlError { .. } => None,
             ParsingError::MissingYamlError { .. } => None,
             ParsingError::CSVReadError { .. } => None,
+            ParsingError::ColumnarReadError { .. } => None,
             ParsingError::RegularParsingError(..) => None,
         }
     }
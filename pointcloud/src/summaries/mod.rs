@@ -10,6 +10,21 @@ use crate::base_traits::*;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
+/// The entropy of the distribution given by some category counts, 0 for an empty one.
+fn counts_entropy<I: Iterator<Item = usize> + Clone>(counts: I) -> f32 {
+    let total: usize = counts.clone().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    counts
+        .filter(|c| *c > 0)
+        .map(|c| {
+            let p = c as f32 / total as f32;
+            -p * p.ln()
+        })
+        .sum()
+}
+
 /// A summary for a small number of categories.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CategorySummary {
@@ -60,6 +75,10 @@ impl Summary for CategorySummary {
     fn count(&self) -> usize {
         self.items.iter().map(|(_a, b)| b).sum()
     }
+
+    fn entropy(&self) -> Option<f32> {
+        Some(counts_entropy(self.items.iter().map(|(_a, b)| *b)))
+    }
 }
 
 /// Summary of vectors
@@ -94,10 +113,17 @@ impl Summary for VecSummary {
             }
         } else {
             self.moment1.extend(val);
-            self.moment2.extend(val.iter().map(|x| x * x))
+            self.moment2.extend(val.iter().map(|x| x * x));
+            self.count += 1;
         }
     }
     fn combine(&mut self, other: &VecSummary) {
+        if self.moment1.is_empty() {
+            self.moment1 = other.moment1.clone();
+            self.moment2 = other.moment2.clone();
+            self.count += other.count;
+            return;
+        }
         self.moment1
             .iter_mut()
             .zip(&other.moment1)
@@ -204,4 +230,8 @@ impl Summary for StringSummary {
     fn count(&self) -> usize {
         self.items.values().sum()
     }
+
+    fn entropy(&self) -> Option<f32> {
+        Some(counts_entropy(self.items.values().cloned()))
+    }
 }
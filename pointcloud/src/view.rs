@@ -0,0 +1,194 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Subsets of a cloud that share its buffers.
+
+use crate::base_traits::*;
+use crate::external_ids::ExternalId;
+use crate::pc_errors::{PointCloudError, PointCloudResult};
+use fxhash::FxBuildHasher;
+use hashbrown::HashMap;
+use std::sync::Arc;
+
+/// A cloud over a subset of the points of another cloud, see [`PointCloud::view`]. The points of the view are indexed
+/// `0..len` in the order they were given, and every access is remapped to the underlying cloud, so nothing is copied.
+/// Build trees on a view for sub-sampled trees, cross-validation folds, or to pull out the points of a subtree.
+#[derive(Debug)]
+pub struct PointCloudView<D> {
+    data: Arc<D>,
+    indexes: Vec<usize>,
+    reverse: HashMap<usize, usize, FxBuildHasher>,
+}
+
+impl<D: PointCloud> PointCloudView<D> {
+    /// Views the points of `data` at `indexes`. Errors if an index is past the end of the cloud.
+    pub fn new<I: IntoIterator<Item = usize>>(data: Arc<D>, indexes: I) -> PointCloudResult<Self> {
+        let indexes: Vec<usize> = indexes.into_iter().collect();
+        let mut reverse =
+            HashMap::with_capacity_and_hasher(indexes.len(), FxBuildHasher::default());
+        for (i, pi) in indexes.iter().enumerate() {
+            if *pi >= data.len() {
+                return Err(PointCloudError::data_access(
+                    *pi,
+                    format!(
+                        "Viewed index past the end of a cloud of {} points",
+                        data.len()
+                    ),
+                ));
+            }
+            reverse.entry(*pi).or_insert(i);
+        }
+        Ok(PointCloudView {
+            data,
+            indexes,
+            reverse,
+        })
+    }
+
+    /// The cloud this is a view of.
+    pub fn parent(&self) -> &Arc<D> {
+        &self.data
+    }
+
+    /// The index in the parent cloud of a point of the view.
+    pub fn parent_index(&self, i: usize) -> PointCloudResult<usize> {
+        self.indexes.get(i).copied().ok_or_else(|| {
+            PointCloudError::data_access(
+                i,
+                format!(
+                    "Index past the end of a view of {} points",
+                    self.indexes.len()
+                ),
+            )
+        })
+    }
+
+    /// The index in the view of a point of the parent cloud, `None` if it isn't in the view.
+    pub fn view_index(&self, pi: usize) -> Option<usize> {
+        self.reverse.get(&pi).copied()
+    }
+
+    fn parent_indexes(&self, pns: &[usize]) -> PointCloudResult<Vec<usize>> {
+        pns.iter().map(|i| self.parent_index(*i)).collect()
+    }
+}
+
+impl<D: PointCloud> PointCloud for PointCloudView<D> {
+    type Metric = D::Metric;
+    type Point = D::Point;
+    type PointRef<'a>
+        = D::PointRef<'a>
+    where
+        Self: 'a;
+    type Metadata = D::Metadata;
+    type MetaSummary = D::MetaSummary;
+
+    type Label = D::Label;
+    type LabelSummary = D::LabelSummary;
+
+    #[inline]
+    fn dim(&self) -> usize {
+        self.data.dim()
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        self.indexes.len()
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.indexes.is_empty()
+    }
+    #[inline]
+    fn reference_indexes(&self) -> Vec<usize> {
+        (0..self.indexes.len()).collect()
+    }
+    fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<Self::PointRef<'a>> {
+        self.data.point(self.parent_index(i)?)
+    }
+
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        self.data.metadata(self.parent_index(pn)?)
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        self.data.metasummary(&self.parent_indexes(pns)?)
+    }
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        self.data.label(self.parent_index(pn)?)
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        self.data.label_summary(&self.parent_indexes(pns)?)
+    }
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        self.data.name(self.parent_index(pi)?)
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        let pi = self.data.index(pn)?;
+        self.view_index(pi).ok_or(PointCloudError::UnknownName)
+    }
+    fn names(&self) -> Vec<String> {
+        self.indexes
+            .iter()
+            .filter_map(|pi| self.data.name(*pi).ok())
+            .collect()
+    }
+    fn timestamp(&self, pi: usize) -> PointCloudResult<Option<u64>> {
+        self.data.timestamp(self.parent_index(pi)?)
+    }
+    fn external_id(&self, pi: usize) -> PointCloudResult<Option<ExternalId>> {
+        self.data.external_id(self.parent_index(pi)?)
+    }
+    fn external_index(&self, id: &ExternalId) -> PointCloudResult<usize> {
+        let pi = self.data.external_index(id)?;
+        self.view_index(pi)
+            .ok_or(PointCloudError::UnknownExternalId)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DefaultLabeledCloud;
+
+    #[test]
+    fn view_remaps_indexes() {
+        let data: Vec<f32> = (0..20).map(|i| i as f32).collect();
+        let labels: Vec<i64> = (0..10).map(|i| i % 3).collect();
+        let cloud = Arc::new(DefaultLabeledCloud::<crate::L2>::new_simple(
+            data, 2, labels,
+        ));
+
+        let view = Arc::clone(&cloud).view(vec![7, 2, 5]).unwrap();
+        assert_eq!(view.len(), 3);
+        assert_eq!(view.reference_indexes(), vec![0, 1, 2]);
+        assert_eq!(&*view.point(0).unwrap(), &[14.0, 15.0]);
+        assert_eq!(&*view.point(1).unwrap(), &[4.0, 5.0]);
+        assert_eq!(view.label(2).unwrap(), cloud.label(5).unwrap());
+        assert_eq!(view.view_index(2), Some(1));
+        assert_eq!(view.view_index(3), None);
+        assert!(view.point(3).is_err());
+
+        let summary = view.label_summary(&[0, 1, 2]).unwrap();
+        let expected = cloud.label_summary(&[7, 2, 5]).unwrap();
+        assert_eq!(summary.summary.items, expected.summary.items);
+
+        let range = Arc::clone(&cloud).view(4..8).unwrap();
+        assert_eq!(&*range.point(0).unwrap(), &[8.0, 9.0]);
+        assert!(Arc::clone(&cloud).view(vec![10]).is_err());
+    }
+}
@@ -19,6 +19,8 @@
 
 use pyo3::prelude::*;
 
+#[macro_use]
+pub mod metric;
 pub mod layer;
 pub mod node;
 pub mod plugins;
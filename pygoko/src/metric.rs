@@ -0,0 +1,109 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! The metrics a tree can be built with. A python class can't be generic over the metric, so the writers and
+//! readers are enums with a variant per metric, and `dispatch!` runs the same code on whichever variant it's given.
+
+use goko::plugins::discrete::prelude::BayesCategoricalTracker;
+use goko::*;
+use pointcloud::*;
+use pyo3::prelude::*;
+
+/// Matches on a [`PyWriter`], [`PyReader`] or [`PyTracker`] and runs the body with `$name` bound to the value inside
+/// the variant, so the body is compiled once per metric.
+macro_rules! dispatch {
+    ($kind:ident, $value:expr, $name:ident => $body:expr) => {
+        match $value {
+            $crate::metric::$kind::L2($name) => $body,
+            $crate::metric::$kind::Cosine($name) => $body,
+        }
+    };
+}
+
+/// The metric of a tree, see `CoverTree.set_metric`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetricKind {
+    /// The euclidean distance
+    L2,
+    /// The angle between the points, see [`pointcloud::Cosine`]
+    Cosine,
+}
+
+impl MetricKind {
+    /// Parses the name python passes in, `"l2"` or `"cosine"`.
+    pub fn parse(name: &str) -> PyResult<MetricKind> {
+        match name.to_lowercase().as_str() {
+            "l2" | "euclidean" => Ok(MetricKind::L2),
+            "cosine" | "angular" => Ok(MetricKind::Cosine),
+            _ => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "unknown metric {}, expected l2 or cosine",
+                name
+            ))),
+        }
+    }
+
+    /// The name `parse` takes back.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MetricKind::L2 => "l2",
+            MetricKind::Cosine => "cosine",
+        }
+    }
+}
+
+/// A tree on one of the metrics.
+pub enum PyWriter {
+    L2(CoverTreeWriter<DefaultLabeledCloud<L2>>),
+    Cosine(CoverTreeWriter<DefaultLabeledCloud<Cosine>>),
+}
+
+impl PyWriter {
+    pub fn reader(&self) -> PyReader {
+        match self {
+            PyWriter::L2(writer) => PyReader::L2(writer.reader()),
+            PyWriter::Cosine(writer) => PyReader::Cosine(writer.reader()),
+        }
+    }
+
+    pub fn metric(&self) -> MetricKind {
+        match self {
+            PyWriter::L2(_) => MetricKind::L2,
+            PyWriter::Cosine(_) => MetricKind::Cosine,
+        }
+    }
+}
+
+/// A reader of a tree on one of the metrics.
+#[derive(Clone)]
+pub enum PyReader {
+    L2(CoverTreeReader<DefaultLabeledCloud<L2>>),
+    Cosine(CoverTreeReader<DefaultLabeledCloud<Cosine>>),
+}
+
+/// A tracker and the reader of the tree it tracks.
+pub struct Tracked<D: PointCloud> {
+    pub hkl: BayesCategoricalTracker<D>,
+    pub tree: CoverTreeReader<D>,
+}
+
+/// A tracker on a tree on one of the metrics.
+pub enum PyTracker {
+    L2(Tracked<DefaultLabeledCloud<L2>>),
+    Cosine(Tracked<DefaultLabeledCloud<Cosine>>),
+}
@@ -8,15 +8,15 @@ use goko::plugins::discrete::prelude::*;
 use goko::plugins::gaussians::*;
 use goko::*;
 use pointcloud::*;
-use std::sync::Arc;
 
 use pyo3::types::PyDict;
 
+use crate::metric::*;
+
 #[pyclass(unsendable)]
 pub struct IterLayerNode {
-    pub parameters: Arc<CoverTreeParameters<DefaultLabeledCloud<L2>>>,
     pub addresses: Vec<NodeAddress>,
-    pub tree: CoverTreeReader<DefaultLabeledCloud<L2>>,
+    pub tree: PyReader,
     pub index: usize,
 }
 
@@ -27,7 +27,6 @@ impl std::iter::Iterator for IterLayerNode {
             let index = self.index;
             self.index += 1;
             Some(PyNode {
-                parameters: Arc::clone(&self.parameters),
                 address: self.addresses[index],
                 tree: self.tree.clone(),
             })
@@ -49,9 +48,8 @@ impl PyIterProtocol for IterLayerNode {
 
 #[pyclass(unsendable)]
 pub struct PyNode {
-    pub parameters: Arc<CoverTreeParameters<DefaultLabeledCloud<L2>>>,
     pub address: NodeAddress,
-    pub tree: CoverTreeReader<DefaultLabeledCloud<L2>>,
+    pub tree: PyReader,
 }
 
 #[pymethods]
@@ -61,22 +59,21 @@ impl PyNode {
     }
 
     pub fn is_leaf(&self) -> bool {
-        self.tree
-            .get_node_and(self.address, |n| n.is_leaf())
-            .unwrap()
+        dispatch!(PyReader, &self.tree, tree => {
+            tree.get_node_and(self.address, |n| n.is_leaf()).unwrap()
+        })
     }
 
     pub fn coverage_count(&self) -> usize {
-        self.tree
-            .get_node_and(self.address, |n| n.coverage_count())
-            .unwrap()
+        dispatch!(PyReader, &self.tree, tree => {
+            tree.get_node_and(self.address, |n| n.coverage_count()).unwrap()
+        })
     }
 
     pub fn children(&self) -> Vec<PyNode> {
         self.children_addresses()
             .iter()
             .map(|address| PyNode {
-                parameters: Arc::clone(&self.parameters),
                 address: *address,
                 tree: self.tree.clone(),
             })
@@ -84,14 +81,15 @@ impl PyNode {
     }
 
     pub fn children_probs(&self) -> Option<(Vec<((i32, usize), f64)>, f64)> {
-        self.tree
-            .get_node_plugin_and(self.address, |p: &Dirichlet| p.prob_vector())
-            .flatten()
+        dispatch!(PyReader, &self.tree, tree => {
+            tree.get_node_plugin_and(self.address, |p: &Dirichlet| p.prob_vector())
+                .flatten()
+        })
     }
 
     pub fn children_addresses(&self) -> Vec<(i32, usize)> {
-        self.tree
-            .get_node_and(self.address, |n| {
+        dispatch!(PyReader, &self.tree, tree => {
+            tree.get_node_and(self.address, |n| {
                 n.children().map(|(nested_scale, children)| {
                     let mut py_nodes: Vec<(i32, usize)> = Vec::from(children);
                     py_nodes.push((nested_scale, *n.center_index()));
@@ -100,32 +98,37 @@ impl PyNode {
             })
             .flatten()
             .unwrap_or(vec![])
+        })
     }
 
     pub fn fractal_dim(&self) -> f32 {
-        self.tree.node_fractal_dim(self.address)
+        dispatch!(PyReader, &self.tree, tree => tree.node_fractal_dim(self.address))
     }
 
     pub fn weighted_fractal_dim(&self) -> f32 {
-        self.tree.node_weighted_fractal_dim(self.address)
+        dispatch!(PyReader, &self.tree, tree => tree.node_weighted_fractal_dim(self.address))
     }
 
     pub fn singletons(&self) -> PyResult<Py<PyArray2<f32>>> {
-        let dim = self.parameters.point_cloud.dim();
         let len = self.coverage_count() as usize;
-        let mut ret_matrix = Vec::with_capacity(len * dim);
-        self.tree.get_node_and(self.address, |n| {
-            n.singletons().iter().for_each(|pi| {
-                if let Ok(p) = self.parameters.point_cloud.point(*pi) {
-                    ret_matrix.extend(p.dense_iter());
+        let (ret_matrix, dim) = dispatch!(PyReader, &self.tree, tree => {
+            let point_cloud = &tree.parameters().point_cloud;
+            let dim = point_cloud.dim();
+            let mut ret_matrix = Vec::with_capacity(len * dim);
+            tree.get_node_and(self.address, |n| {
+                n.singletons().iter().for_each(|pi| {
+                    if let Ok(p) = point_cloud.point(*pi) {
+                        ret_matrix.extend(p.dense_iter());
+                    }
+                });
+
+                if n.is_leaf() {
+                    if let Ok(p) = point_cloud.point(*n.center_index()) {
+                        ret_matrix.extend(p.dense_iter());
+                    }
                 }
             });
-
-            if n.is_leaf() {
-                if let Ok(p) = self.parameters.point_cloud.point(*n.center_index()) {
-                    ret_matrix.extend(p.dense_iter());
-                }
-            }
+            (ret_matrix, dim)
         });
 
         let ret_matrix = Array2::from_shape_vec((len, dim), ret_matrix).unwrap();
@@ -135,34 +138,35 @@ impl PyNode {
     }
 
     pub fn singletons_indexes(&self) -> Vec<usize> {
-        self.tree
-            .get_node_and(self.address, |n| Vec::from(n.singletons()))
-            .unwrap_or(vec![])
+        dispatch!(PyReader, &self.tree, tree => {
+            tree.get_node_and(self.address, |n| Vec::from(n.singletons()))
+                .unwrap_or(vec![])
+        })
     }
 
     pub fn cover_mean(&self) -> PyResult<Option<Py<PyArray1<f32>>>> {
-        let dim = self.parameters.point_cloud.dim();
+        let dim = self.dim();
         let gil = pyo3::Python::acquire_gil();
         let py = gil.python();
-        let mean = self
-            .tree
-            .get_node_plugin_and::<DiagGaussian, _, _>(self.address, |p| p.mean())
-            .map(|m| {
-                Array1::from_shape_vec((dim,), m)
-                    .unwrap()
-                    .into_pyarray(py)
-                    .to_owned()
-            });
+        let mean = dispatch!(PyReader, &self.tree, tree => {
+            tree.get_node_plugin_and::<DiagGaussian, _, _>(self.address, |p| p.mean())
+        })
+        .map(|m| {
+            Array1::from_shape_vec((dim,), m)
+                .unwrap()
+                .into_pyarray(py)
+                .to_owned()
+        });
 
         Ok(mean)
     }
 
     pub fn cover_diag_var(&self) -> PyResult<Py<PyArray1<f32>>> {
-        let dim = self.parameters.point_cloud.dim();
-        let var = self
-            .tree
-            .get_node_plugin_and::<DiagGaussian, _, _>(self.address, |p| p.var())
-            .unwrap();
+        let dim = self.dim();
+        let var = dispatch!(PyReader, &self.tree, tree => {
+            tree.get_node_plugin_and::<DiagGaussian, _, _>(self.address, |p| p.var())
+        })
+        .unwrap();
         let py_mean = Array1::from_shape_vec((dim,), var).unwrap();
         let gil = pyo3::Python::acquire_gil();
         let py = gil.python();
@@ -185,7 +189,9 @@ impl PyNode {
         let gil = pyo3::Python::acquire_gil();
         let py = gil.python();
         let dict = PyDict::new(py);
-        match self.tree.get_node_label_summary(self.address) {
+        let summary =
+            dispatch!(PyReader, &self.tree, tree => tree.get_node_label_summary(self.address));
+        match summary {
             Some(s) => {
                 dict.set_item("errors", s.errors)?;
                 dict.set_item("nones", s.nones)?;
@@ -196,3 +202,9 @@ impl PyNode {
         }
     }
 }
+
+impl PyNode {
+    fn dim(&self) -> usize {
+        dispatch!(PyReader, &self.tree, tree => tree.parameters().point_cloud.dim())
+    }
+}
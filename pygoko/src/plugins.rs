@@ -1,10 +1,10 @@
 use goko::plugins::discrete::prelude::*;
-use goko::*;
 use numpy::PyArray1;
-use pointcloud::*;
 use pyo3::prelude::*;
 use pyo3::types::PyDict;
 
+use crate::metric::*;
+
 /*
 pub #[derive(Debug)]
 struct PyBucketProbs {
@@ -24,42 +24,73 @@ impl PyBucketProbs {
 
 #[pyclass(unsendable)]
 pub struct PyBayesCategoricalTracker {
-    pub hkl: BayesCategoricalTracker<DefaultLabeledCloud<L2>>,
-    pub tree: CoverTreeReader<DefaultLabeledCloud<L2>>,
+    pub tracker: PyTracker,
 }
 
 #[pymethods]
 impl PyBayesCategoricalTracker {
     pub fn push(&mut self, point: &PyArray1<f32>) {
-        let results = self
-            .tree
-            .path(&point.readonly().as_slice().unwrap())
-            .unwrap();
-        self.hkl.add_path(results);
+        dispatch!(PyTracker, &mut self.tracker, tracker => {
+            let results = tracker
+                .tree
+                .path(&point.readonly().as_slice().unwrap())
+                .unwrap();
+            tracker.hkl.add_path(results);
+        })
+    }
+
+    pub fn add_path(&mut self, point: &PyArray1<f32>) {
+        self.push(point)
+    }
+
+    pub fn sequence_len(&self) -> usize {
+        dispatch!(PyTracker, &self.tracker, tracker => tracker.hkl.sequence_len())
+    }
+
+    pub fn reset(&mut self) {
+        dispatch!(PyTracker, &mut self.tracker, tracker => tracker.hkl.reset())
     }
 
     pub fn print(&self) {
-        println!("{:#?}", self.hkl);
+        dispatch!(PyTracker, &self.tracker, tracker => println!("{:#?}", tracker.hkl))
     }
 
     pub fn probs(&self, node_address: (i32, usize)) -> Option<(Vec<((i32, usize), f64)>, f64)> {
-        self.hkl.prob_vector(node_address)
+        dispatch!(PyTracker, &self.tracker, tracker => tracker.hkl.prob_vector(node_address))
     }
 
     pub fn evidence(&self, node_address: (i32, usize)) -> Option<(Vec<((i32, usize), f64)>, f64)> {
-        self.hkl.evidence_prob_vector(node_address)
+        dispatch!(PyTracker, &self.tracker, tracker => tracker.hkl.evidence_prob_vector(node_address))
     }
 
     pub fn all_kl(&self) -> Vec<(f64, (i32, usize))> {
-        self.hkl.all_node_kl()
+        dispatch!(PyTracker, &self.tracker, tracker => tracker.hkl.all_node_kl())
     }
 
     pub fn kl_div(&self) -> f64 {
-        self.hkl.kl_div()
+        dispatch!(PyTracker, &self.tracker, tracker => tracker.hkl.kl_div())
+    }
+
+    pub fn marginal_aic(&self) -> f64 {
+        dispatch!(PyTracker, &self.tracker, tracker => tracker.hkl.marginal_aic())
     }
 
     pub fn stats(&self) -> PyResult<PyObject> {
-        let stats = self.hkl.kl_div_stats();
+        self.kl_div_stats(None)
+    }
+
+    pub fn kl_div_stats(&self, weighting: Option<&str>) -> PyResult<PyObject> {
+        let weighting = match weighting {
+            None | Some("fraction") => CoverageWeighting::Fraction,
+            Some("ln_fraction") => CoverageWeighting::LnFraction,
+            Some(other) => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "unknown weighting {}, expected fraction or ln_fraction",
+                    other
+                )))
+            }
+        };
+        let stats = dispatch!(PyTracker, &self.tracker, tracker => tracker.hkl.kl_div_stats_weighted(weighting));
         let gil = pyo3::Python::acquire_gil();
         let py = gil.python();
         let dict = PyDict::new(py);
@@ -68,6 +99,24 @@ impl PyBayesCategoricalTracker {
         dict.set_item("nz_count", stats.nz_count)?;
         dict.set_item("moment1_nz", stats.moment1_nz)?;
         dict.set_item("moment2_nz", stats.moment2_nz)?;
+        dict.set_item("weighted_moment1_nz", stats.weighted_moment1_nz)?;
+        dict.set_item("weighted_moment2_nz", stats.weighted_moment2_nz)?;
+        dict.set_item("weight_nz", stats.weight_nz)?;
+        dict.set_item("sequence_len", stats.sequence_len)?;
+        Ok(dict.into())
+    }
+
+    pub fn goodness_of_fit(&self) -> PyResult<PyObject> {
+        let stats = dispatch!(PyTracker, &self.tracker, tracker => tracker.hkl.goodness_of_fit());
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        let dict = PyDict::new(py);
+        dict.set_item("chi_squared", stats.chi_squared)?;
+        dict.set_item("chi_squared_p_value", stats.chi_squared_p_value)?;
+        dict.set_item("g_statistic", stats.g_statistic)?;
+        dict.set_item("g_test_p_value", stats.g_test_p_value)?;
+        dict.set_item("degrees_of_freedom", stats.degrees_of_freedom)?;
+        dict.set_item("node_count", stats.node_count)?;
         dict.set_item("sequence_len", stats.sequence_len)?;
         Ok(dict.into())
     }
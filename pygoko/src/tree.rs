@@ -17,21 +17,23 @@
 * under the License.
 */
 
-use ndarray::Array1;
+use ndarray::{Array1, Array2};
 use numpy::{IntoPyArray, PyArray1, PyArray2};
 use pyo3::prelude::*;
 use pyo3::types::PyDict;
 use rand::prelude::*;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use goko::query_interface::BulkInterface;
+use goko::utils::{linkage_matrix, load_tree_with_plugins, save_tree_with_plugins};
 use goko::*;
 use pointcloud::loaders::labeled_ram_from_yaml;
 use pointcloud::*;
 
 use crate::layer::*;
+use crate::metric::*;
 use crate::node::*;
 use crate::plugins::*;
 use goko::plugins::discrete::prelude::*;
@@ -40,9 +42,9 @@ use goko::plugins::gaussians::*;
 #[pyclass(unsendable)]
 pub struct CoverTree {
     builder: Option<CoverTreeBuilder>,
-    temp_point_cloud: Option<Arc<DefaultLabeledCloud<L2>>>,
-    writer: Option<CoverTreeWriter<DefaultLabeledCloud<L2>>>,
-    metric: String,
+    yaml_path: Option<PathBuf>,
+    writer: Option<PyWriter>,
+    metric: MetricKind,
 }
 
 #[pymethods]
@@ -51,9 +53,9 @@ impl CoverTree {
     fn new() -> PyResult<CoverTree> {
         Ok(CoverTree {
             builder: Some(CoverTreeBuilder::new()),
-            temp_point_cloud: None,
+            yaml_path: None,
             writer: None,
-            metric: "DefaultLabeledCloud<L2>".to_string(),
+            metric: MetricKind::L2,
         })
     }
     pub fn set_scale_base(&mut self, x: f32) {
@@ -88,17 +90,30 @@ impl CoverTree {
         };
     }
 
+    /// Reads the build parameters from the yaml file, the data it points to is read by `fit` with the metric of
+    /// the tree.
     pub fn load_yaml_config(&mut self, file_name: String) -> PyResult<()> {
         let path = Path::new(&file_name);
-        let point_cloud = Arc::new(labeled_ram_from_yaml::<_, L2>(&path).unwrap());
         let builder = CoverTreeBuilder::from_yaml(&path);
         self.builder = Some(builder);
-        self.temp_point_cloud = Some(point_cloud);
+        self.yaml_path = Some(path.to_path_buf());
         Ok(())
     }
 
-    pub fn set_metric(&mut self, metric_name: String) {
-        self.metric = metric_name;
+    /// Sets the metric the tree is built with, `"l2"` (the default) or `"cosine"`. The cosine metric is the angle
+    /// between the points, so the distances the tree gives back are in radians.
+    pub fn set_metric(&mut self, metric_name: &str) -> PyResult<()> {
+        let metric = MetricKind::parse(metric_name)?;
+        match &self.builder {
+            Some(_) => self.metric = metric,
+            None => panic!("Set too late"),
+        };
+        Ok(())
+    }
+
+    /// The name of the metric of the tree.
+    pub fn metric(&self) -> &'static str {
+        self.metric.name()
     }
 
     pub fn fit(
@@ -106,35 +121,46 @@ impl CoverTree {
         data: Option<&PyArray2<f32>>,
         labels: Option<&PyArray1<i64>>,
     ) -> PyResult<()> {
-        let point_cloud = if let Some(data) = data {
-            let len = data.shape()[0];
-            let data_dim = data.shape()[1];
-            let my_labels: Vec<i64> = match labels {
-                Some(labels) => Vec::from(labels.readonly().as_slice().unwrap()),
-                None => vec![0; len],
-            };
-            Arc::new(DefaultLabeledCloud::<L2>::new_simple(
-                Vec::from(data.readonly().as_slice().unwrap()),
-                data_dim,
-                my_labels,
-            ))
-        } else {
-            if let Some(point_cloud) = self.temp_point_cloud.take() {
-                point_cloud
-            } else {
-                panic!("No known point_cloud");
-            }
-        };
-
-        let builder = self.builder.take();
-        self.writer = Some(builder.unwrap().build(point_cloud).unwrap());
-        let writer = self.writer.as_mut().unwrap();
-        writer.generate_summaries();
-        writer.add_plugin::<GokoDiagGaussian>(GokoDiagGaussian::singletons());
-        writer.add_plugin::<GokoDirichlet>(GokoDirichlet {});
+        let builder = self.builder.take().unwrap();
+        let yaml_path = self.yaml_path.take();
+        self.writer = Some(match self.metric {
+            MetricKind::L2 => PyWriter::L2(fit_tree(builder, data, labels, yaml_path)),
+            MetricKind::Cosine => PyWriter::Cosine(fit_tree(builder, data, labels, yaml_path)),
+        });
         Ok(())
     }
 
+    /// Saves the tree with its plugins, the data isn't saved with it so keep that around to load the tree again.
+    /// Neither is the metric, pass the same one to `load`.
+    pub fn save(&self, path: String) -> PyResult<()> {
+        dispatch!(PyWriter, self.writer.as_ref().unwrap(), writer => {
+            save_tree_with_plugins(&path, writer, &default_plugin_codecs())
+        })
+        .map_err(|e| pyo3::exceptions::PyIOError::new_err(format!("{}", e)))
+    }
+
+    /// Loads a tree saved with `save`, the data, labels and metric have to be the ones the tree was fit with. The
+    /// saved plugins are put back as they were, the ones the file doesn't have are computed.
+    #[staticmethod]
+    pub fn load(
+        path: String,
+        data: &PyArray2<f32>,
+        labels: Option<&PyArray1<i64>>,
+        metric: Option<&str>,
+    ) -> PyResult<CoverTree> {
+        let metric = MetricKind::parse(metric.unwrap_or("l2"))?;
+        let writer = match metric {
+            MetricKind::L2 => PyWriter::L2(load_tree(&path, data, labels)?),
+            MetricKind::Cosine => PyWriter::Cosine(load_tree(&path, data, labels)?),
+        };
+        Ok(CoverTree {
+            builder: None,
+            yaml_path: None,
+            writer: Some(writer),
+            metric,
+        })
+    }
+
     /*
     pub fn attach_svds(&mut self, min_point_count: usize, max_point_count: usize, tau: f32) {
         let writer = self.writer.as_mut().unwrap();
@@ -148,41 +174,42 @@ impl CoverTree {
 
     pub fn data_point(&self, point_index: usize) -> PyResult<Option<Py<PyArray1<f32>>>> {
         let reader = self.writer.as_ref().unwrap().reader();
-        let dim = reader.parameters().point_cloud.dim();
-        Ok(match reader.parameters().point_cloud.point(point_index) {
-            Err(_) => None,
-            Ok(point) => {
-                let py_point =
-                    Array1::from_shape_vec((dim,), point.dense_iter().collect()).unwrap();
-                let gil = pyo3::Python::acquire_gil();
-                let py = gil.python();
-                Some(py_point.into_pyarray(py).to_owned())
-            }
-        })
+        let point: Option<Vec<f32>> = dispatch!(PyReader, &reader, tree => {
+            let point_cloud = &tree.parameters().point_cloud;
+            point_cloud.point(point_index).ok().map(|p| p.dense_iter().collect())
+        });
+        Ok(point.map(|point| {
+            let py_point = Array1::from_shape_vec((point.len(),), point).unwrap();
+            let gil = pyo3::Python::acquire_gil();
+            let py = gil.python();
+            py_point.into_pyarray(py).to_owned()
+        }))
     }
 
     //pub fn layers(&self) ->
     pub fn top_scale(&self) -> Option<i32> {
         self.writer
             .as_ref()
-            .map(|w| w.reader().scale_range().end - 1)
+            .map(|w| dispatch!(PyWriter, w, w => w.reader().scale_range().end - 1))
     }
 
     pub fn bottom_scale(&self) -> Option<i32> {
-        self.writer.as_ref().map(|w| w.reader().scale_range().start)
+        self.writer
+            .as_ref()
+            .map(|w| dispatch!(PyWriter, w, w => w.reader().scale_range().start))
     }
 
     pub fn scale_base(&self) -> Option<f32> {
         self.writer
             .as_ref()
-            .map(|w| w.reader().parameters().scale_base)
+            .map(|w| dispatch!(PyWriter, w, w => w.reader().parameters().scale_base))
     }
 
     pub fn layers(&self) -> PyResult<IterLayers> {
         let reader = self.writer.as_ref().unwrap().reader();
-        let scale_indexes = reader.layers().map(|(si, _)| si).collect();
+        let scale_indexes =
+            dispatch!(PyReader, &reader, tree => tree.layers().map(|(si, _)| si).collect());
         Ok(IterLayers {
-            parameters: Arc::clone(reader.parameters()),
             tree: reader,
             scale_indexes,
             index: 0,
@@ -192,7 +219,6 @@ impl CoverTree {
     pub fn layer(&self, scale_index: i32) -> PyResult<PyLayer> {
         let reader = self.writer.as_ref().unwrap().reader();
         Ok(PyLayer {
-            parameters: Arc::clone(reader.parameters()),
             tree: reader,
             scale_index,
         })
@@ -201,9 +227,8 @@ impl CoverTree {
     pub fn node(&self, address: (i32, usize)) -> PyResult<PyNode> {
         let reader = self.writer.as_ref().unwrap().reader();
         // Check node exists
-        reader.get_node_and(address, |_| true).unwrap();
+        dispatch!(PyReader, &reader, tree => tree.get_node_and(address, |_| true).unwrap());
         Ok(PyNode {
-            parameters: Arc::clone(reader.parameters()),
             address,
             tree: reader,
         })
@@ -211,112 +236,196 @@ impl CoverTree {
 
     pub fn root(&self) -> PyResult<PyNode> {
         let reader = self.writer.as_ref().unwrap().reader();
-        self.node(reader.root_address())
+        self.node(dispatch!(PyReader, &reader, tree => tree.root_address()))
+    }
+
+    /// The `k` nearest neighbors of the point. Gives `(distance, index)` pairs by default, drop the distances with
+    /// `return_distance=False` and add the labels the tree was fit with by passing `return_labels=True`. Raises a
+    /// `ValueError` if the point isn't the dimension of the tree.
+    pub fn knn(
+        &self,
+        point: &PyArray1<f32>,
+        k: usize,
+        return_distance: Option<bool>,
+        return_labels: Option<bool>,
+    ) -> PyResult<PyObject> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        dispatch!(PyReader, &reader, tree => {
+            let point_cloud = &tree.parameters().point_cloud;
+            check_dim(point_cloud.dim(), point.len())?;
+            let results = tree
+                .knn(&point.readonly().as_slice().unwrap(), k)
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("{}", e)))?;
+            let gil = pyo3::Python::acquire_gil();
+            Ok(knn_to_py(
+                gil.python(),
+                point_cloud,
+                results,
+                return_distance.unwrap_or(true),
+                return_labels.unwrap_or(false),
+            ))
+        })
     }
 
-    pub fn knn(&self, point: &PyArray1<f32>, k: usize) -> Vec<(f32, usize)> {
+    /// The `k` nearest neighbors of each row of `points`, as a list of what `knn` gives. The queries run in parallel
+    /// with the GIL released. Raises a `ValueError` if the rows aren't the dimension of the tree.
+    pub fn knn_batch(
+        &self,
+        points: &PyArray2<f32>,
+        k: usize,
+        return_distance: Option<bool>,
+        return_labels: Option<bool>,
+    ) -> PyResult<Vec<PyObject>> {
         let reader = self.writer.as_ref().unwrap().reader();
-        reader
-            .knn(&point.readonly().as_slice().unwrap(), k)
-            .unwrap()
+        let dim = points.shape()[1];
+        let data: Vec<f32> = points.readonly().as_array().iter().cloned().collect();
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        let return_distance = return_distance.unwrap_or(true);
+        let return_labels = return_labels.unwrap_or(false);
+        dispatch!(PyReader, reader, tree => {
+            let point_cloud = Arc::clone(&tree.parameters().point_cloud);
+            check_dim(point_cloud.dim(), dim)?;
+            let results = py.allow_threads(move || {
+                let bulk = BulkInterface::new(tree);
+                let points: Vec<&[f32]> = data.chunks(dim).collect();
+                bulk.knn(&points, k)
+            });
+            results
+                .into_iter()
+                .map(|r| {
+                    r.map(|r| knn_to_py(py, &point_cloud, r, return_distance, return_labels))
+                        .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("{}", e)))
+                })
+                .collect()
+        })
+    }
+
+    /// The `k` nearest neighbors of every point the tree was fit on, not counting the point itself, as the
+    /// `(indptr, indices, distances)` arrays of a CSR matrix. Pass them to `scipy.sparse.csr_matrix((distances,
+    /// indices, indptr))`.
+    pub fn knn_graph(
+        &self,
+        k: usize,
+    ) -> PyResult<(Py<PyArray1<usize>>, Py<PyArray1<usize>>, Py<PyArray1<f32>>)> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        let graph = py
+            .allow_threads(
+                move || dispatch!(PyReader, reader, tree => BulkInterface::new(tree).knn_graph(k)),
+            )
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("{}", e)))?;
+        Ok((
+            Array1::from(graph.offsets).into_pyarray(py).to_owned(),
+            Array1::from(graph.indices).into_pyarray(py).to_owned(),
+            Array1::from(graph.distances).into_pyarray(py).to_owned(),
+        ))
     }
 
     pub fn routing_knn(&self, point: &PyArray1<f32>, k: usize) -> Vec<(f32, usize)> {
         let reader = self.writer.as_ref().unwrap().reader();
-        reader
-            .routing_knn(&point.readonly().as_slice().unwrap(), k)
-            .unwrap()
+        dispatch!(PyReader, &reader, tree => {
+            tree.routing_knn(&point.readonly().as_slice().unwrap(), k)
+                .unwrap()
+        })
     }
 
     pub fn known_path(&self, point_index: usize) -> Vec<(f32, (i32, usize))> {
         let reader = self.writer.as_ref().unwrap().reader();
-        reader.known_path(point_index).unwrap()
+        dispatch!(PyReader, &reader, tree => tree.known_path(point_index).unwrap())
     }
 
     pub fn index_depths(&self, point_indexes: Vec<usize>, tau: Option<f32>) -> Vec<(usize, usize)> {
         let reader = self.writer.as_ref().unwrap().reader();
-        let bulk = BulkInterface::new(reader);
         let tau = tau.unwrap_or(0.00001);
-        bulk.known_path_and(&point_indexes, |reader, path| {
-            if let Ok(path) = path {
-                let mut homogenity_depth = path.len();
-                for (i, (_d, a)) in path.iter().enumerate() {
-                    let summ = reader.get_node_label_summary(*a).unwrap();
-                    if summ.summary.items.len() == 1 {
-                        homogenity_depth = i;
-                        break;
-                    }
-                    let sum = summ.summary.items.iter().map(|(_, c)| c).sum::<usize>() as f32;
-                    let max = *summ.summary.items.iter().map(|(_, c)| c).max().unwrap() as f32;
-                    if 1.0 - max / sum < tau {
-                        homogenity_depth = i;
-                        break;
+        dispatch!(PyReader, reader, tree => {
+            let bulk = BulkInterface::new(tree);
+            bulk.known_path_and(&point_indexes, |reader, path| {
+                if let Ok(path) = path {
+                    let mut homogenity_depth = path.len();
+                    for (i, (_d, a)) in path.iter().enumerate() {
+                        let summ = reader.get_node_label_summary(*a).unwrap();
+                        if summ.summary.items.len() == 1 {
+                            homogenity_depth = i;
+                            break;
+                        }
+                        let sum = summ.summary.items.iter().map(|(_, c)| c).sum::<usize>() as f32;
+                        let max = *summ.summary.items.iter().map(|(_, c)| c).max().unwrap() as f32;
+                        if 1.0 - max / sum < tau {
+                            homogenity_depth = i;
+                            break;
+                        }
                     }
+                    (path.len(), homogenity_depth)
+                } else {
+                    (0, 0)
                 }
-                (path.len(), homogenity_depth)
-            } else {
-                (0, 0)
-            }
+            })
         })
     }
 
     pub fn point_depths(&self, points: &PyArray2<f32>, tau: Option<f32>) -> Vec<(usize, usize)> {
         let reader = self.writer.as_ref().unwrap().reader();
-        let bulk = BulkInterface::new(reader);
         let tau = tau.unwrap_or(0.00001);
 
-        bulk.array_map_with_reader(points.readonly().as_array(), |reader, point| {
-            if let Ok(path) = reader.path(point) {
-                let mut homogenity_depth = path.len();
-                for (i, (_d, a)) in path.iter().enumerate() {
-                    let summ = reader.get_node_label_summary(*a).unwrap();
-                    if summ.summary.items.len() == 1 {
-                        homogenity_depth = i;
-                        break;
-                    }
-                    let sum = summ.summary.items.iter().map(|(_, c)| c).sum::<usize>() as f32;
-                    let max = *summ.summary.items.iter().map(|(_, c)| c).max().unwrap() as f32;
-                    if 1.0 - max / sum < tau {
-                        homogenity_depth = i;
-                        break;
+        dispatch!(PyReader, reader, tree => {
+            let bulk = BulkInterface::new(tree);
+            bulk.array_map_with_reader(points.readonly().as_array(), |reader, point| {
+                if let Ok(path) = reader.path(point) {
+                    let mut homogenity_depth = path.len();
+                    for (i, (_d, a)) in path.iter().enumerate() {
+                        let summ = reader.get_node_label_summary(*a).unwrap();
+                        if summ.summary.items.len() == 1 {
+                            homogenity_depth = i;
+                            break;
+                        }
+                        let sum = summ.summary.items.iter().map(|(_, c)| c).sum::<usize>() as f32;
+                        let max = *summ.summary.items.iter().map(|(_, c)| c).max().unwrap() as f32;
+                        if 1.0 - max / sum < tau {
+                            homogenity_depth = i;
+                            break;
+                        }
                     }
+                    (path.len(), homogenity_depth)
+                } else {
+                    (0, 0)
                 }
-                (path.len(), homogenity_depth)
-            } else {
-                (0, 0)
-            }
+            })
         })
     }
 
     pub fn path(&self, point: &PyArray1<f32>) -> Vec<(f32, (i32, usize))> {
         let reader = self.writer.as_ref().unwrap().reader();
-        reader.path(&point.readonly().as_slice().unwrap()).unwrap()
+        dispatch!(PyReader, &reader, tree => {
+            tree.path(&point.readonly().as_slice().unwrap()).unwrap()
+        })
     }
 
     pub fn sample(&self) -> PyResult<(Py<PyArray1<f32>>, Option<PyObject>)> {
         let reader = self.writer.as_ref().unwrap().reader();
         let mut rng = SmallRng::from_entropy();
-        let mut parent_addr = reader.root_address();
-
-        while let Some(pat) = reader
-            .get_node_plugin_and::<Dirichlet, _, _>(parent_addr, |p| p.sample(&mut rng))
-            .unwrap()
-        {
-            parent_addr = pat;
-        }
+        let (sample, summary) = dispatch!(PyReader, &reader, tree => {
+            let mut parent_addr = tree.root_address();
+            while let Some(pat) = tree
+                .get_node_plugin_and::<Dirichlet, _, _>(parent_addr, |p| p.sample(&mut rng))
+                .unwrap()
+            {
+                parent_addr = pat;
+            }
+            let sample = tree
+                .get_node_plugin_and::<DiagGaussian, _, _>(parent_addr, |p| p.sample(&mut rng))
+                .unwrap();
+            (sample, tree.get_node_label_summary(parent_addr))
+        });
         let gil = pyo3::Python::acquire_gil();
         let py = gil.python();
-        let vec = reader
-            .get_node_plugin_and::<DiagGaussian, _, _>(parent_addr, |p| p.sample(&mut rng))
-            .map(|m| {
-                Array1::from_shape_vec((m.len(),), m)
-                    .unwrap()
-                    .into_pyarray(py)
-                    .to_owned()
-            })
-            .unwrap();
+        let vec = Array1::from_shape_vec((sample.len(),), sample)
+            .unwrap()
+            .into_pyarray(py)
+            .to_owned();
         let dict = PyDict::new(py);
-        let summ = match reader.get_node_label_summary(parent_addr) {
+        let summ = match summary {
             Some(s) => {
                 dict.set_item("errors", s.errors)?;
                 dict.set_item("nones", s.nones)?;
@@ -328,23 +437,76 @@ impl CoverTree {
         Ok((vec, summ))
     }
 
-    pub fn kl_div_dirichlet(
-        &self,
-        size: u64,
-        prior_weight: Option<f64>,
-        observation_weight: Option<f64>,
-    ) -> PyBayesCategoricalTracker {
-        let writer = self.writer.as_ref().unwrap();
-
-        PyBayesCategoricalTracker {
-            hkl: BayesCategoricalTracker::new(
-                prior_weight.unwrap_or(1.0),
-                observation_weight.unwrap_or(1.0),
-                size as usize,
-                writer.reader(),
-            ),
-            tree: writer.reader(),
+    /// The tree as a scipy linkage matrix, for `scipy.cluster.hierarchy.dendrogram`. Returns the matrix and the
+    /// point index of each observation.
+    pub fn linkage(&self) -> (Py<PyArray2<f64>>, Py<PyArray1<usize>>) {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let linkage = dispatch!(PyReader, &reader, tree => linkage_matrix(tree));
+        let rows: Vec<f64> = linkage.rows.iter().flat_map(|r| r.iter().copied()).collect();
+        let matrix = Array2::from_shape_vec((linkage.rows.len(), 4), rows).unwrap();
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        (
+            matrix.into_pyarray(py).to_owned(),
+            Array1::from(linkage.point_indexes).into_pyarray(py).to_owned(),
+        )
+    }
+
+    /// The tree's dendrogram as a Newick string, the leaves are named by point index.
+    pub fn newick(&self) -> String {
+        let reader = self.writer.as_ref().unwrap().reader();
+        dispatch!(PyReader, &reader, tree => linkage_matrix(tree).to_newick())
+    }
+
+    /// Node, leaf and singleton counts of the tree and its layers, and the parameters it was built with, as a dict.
+    /// The layers are a list of dicts, starting from the one that holds the root.
+    pub fn tree_stats(&self) -> PyResult<PyObject> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let stats = dispatch!(PyReader, &reader, tree => tree.tree_stats());
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        let dict = PyDict::new(py);
+        dict.set_item("point_count", stats.point_count)?;
+        dict.set_item("node_count", stats.node_count)?;
+        dict.set_item("leaf_count", stats.leaf_count)?;
+        dict.set_item("singleton_count", stats.singleton_count)?;
+        dict.set_item("mean_children", stats.mean_children)?;
+        dict.set_item("max_children", stats.max_children)?;
+        let mut layers: Vec<PyObject> = Vec::with_capacity(stats.layers.len());
+        for layer in &stats.layers {
+            let layer_dict = PyDict::new(py);
+            layer_dict.set_item("scale_index", layer.scale_index)?;
+            layer_dict.set_item("node_count", layer.node_count)?;
+            layer_dict.set_item("leaf_count", layer.leaf_count)?;
+            layer_dict.set_item("singleton_count", layer.singleton_count)?;
+            layer_dict.set_item("mean_radius", layer.mean_radius)?;
+            layers.push(layer_dict.into());
         }
+        dict.set_item("layers", layers)?;
+        let parameters = PyDict::new(py);
+        parameters.set_item("scale_base", stats.parameters.scale_base)?;
+        parameters.set_item("leaf_cutoff", stats.parameters.leaf_cutoff)?;
+        parameters.set_item("min_res_index", stats.parameters.min_res_index)?;
+        parameters.set_item("use_singletons", stats.parameters.use_singletons)?;
+        parameters.set_item(
+            "partition_type",
+            format!("{:?}", stats.parameters.partition_type),
+        )?;
+        parameters.set_item("rng_seed", stats.parameters.rng_seed)?;
+        dict.set_item("parameters", parameters)?;
+        Ok(dict.into())
+    }
+
+    pub fn kl_div_dirichlet(&self, size: u64, decay: Option<f64>) -> PyBayesCategoricalTracker {
+        let tracker = match self.writer.as_ref().unwrap().reader() {
+            PyReader::L2(tree) => PyTracker::L2(tracked(tree, size, decay)),
+            PyReader::Cosine(tree) => PyTracker::Cosine(tracked(tree, size, decay)),
+        };
+        PyBayesCategoricalTracker { tracker }
+    }
+
+    pub fn tracker(&self, window_size: Option<u64>, decay: Option<f64>) -> PyBayesCategoricalTracker {
+        self.kl_div_dirichlet(window_size.unwrap_or(0), decay)
     }
 
     pub fn kl_div_dirichlet_baseline(
@@ -362,7 +524,129 @@ impl CoverTree {
         trainer.set_sequence_len(sequence_len);
         trainer.set_num_sequences(num_sequences);
         trainer.set_sample_rate(sample_rate);
-        let baseline = trainer.train(reader).unwrap();
+        let baseline = dispatch!(PyReader, reader, tree => trainer.train(tree).unwrap());
         PyKLDivergenceBaseline { baseline }
     }
 }
+
+fn numpy_point_cloud<M: Metric<[f32]>>(
+    data: &PyArray2<f32>,
+    labels: Option<&PyArray1<i64>>,
+) -> Arc<DefaultLabeledCloud<M>> {
+    let len = data.shape()[0];
+    let data_dim = data.shape()[1];
+    let my_labels: Vec<i64> = match labels {
+        Some(labels) => Vec::from(labels.readonly().as_slice().unwrap()),
+        None => vec![0; len],
+    };
+    Arc::new(DefaultLabeledCloud::<M>::new_simple(
+        Vec::from(data.readonly().as_slice().unwrap()),
+        data_dim,
+        my_labels,
+    ))
+}
+
+/// Builds the tree on the numpy data, or on the data of the yaml file the builder was read from.
+fn fit_tree<M: Metric<[f32]>>(
+    builder: CoverTreeBuilder,
+    data: Option<&PyArray2<f32>>,
+    labels: Option<&PyArray1<i64>>,
+    yaml_path: Option<PathBuf>,
+) -> CoverTreeWriter<DefaultLabeledCloud<M>> {
+    let point_cloud = if let Some(data) = data {
+        numpy_point_cloud(data, labels)
+    } else {
+        if let Some(path) = yaml_path {
+            Arc::new(labeled_ram_from_yaml::<_, M>(&path).unwrap())
+        } else {
+            panic!("No known point_cloud");
+        }
+    };
+    let mut writer = builder.build(point_cloud).unwrap();
+    attach_default_plugins(&mut writer);
+    writer
+}
+
+fn load_tree<M: Metric<[f32]>>(
+    path: &str,
+    data: &PyArray2<f32>,
+    labels: Option<&PyArray1<i64>>,
+) -> PyResult<CoverTreeWriter<DefaultLabeledCloud<M>>> {
+    let point_cloud = numpy_point_cloud(data, labels);
+    let mut writer = load_tree_with_plugins(path, point_cloud, &default_plugin_codecs())
+        .map_err(|e| pyo3::exceptions::PyIOError::new_err(format!("{}", e)))?;
+    attach_missing_plugins(&mut writer);
+    Ok(writer)
+}
+
+fn tracked<D: PointCloud>(tree: CoverTreeReader<D>, size: u64, decay: Option<f64>) -> Tracked<D> {
+    let mut hkl = BayesCategoricalTracker::new(size as usize, tree.clone());
+    if let Some(gamma) = decay {
+        hkl.set_decay(gamma);
+    }
+    Tracked { hkl, tree }
+}
+
+fn attach_default_plugins<D: PointCloud>(writer: &mut CoverTreeWriter<D>) {
+    writer.generate_summaries();
+    writer.add_plugin::<GokoDiagGaussian>(GokoDiagGaussian::singletons());
+    writer.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+}
+
+/// The default plugins that are saved with the tree. The label summaries aren't serializable, they're cheap to
+/// count again.
+fn default_plugin_codecs<D: PointCloud>() -> PluginCodecs<D> {
+    PluginCodecs::new()
+        .register::<GokoDiagGaussian>("diag_gaussian")
+        .register::<GokoDirichlet>("dirichlet")
+}
+
+/// Attaches the default plugins a loaded tree doesn't have, a file saved without them has none.
+fn attach_missing_plugins<D: PointCloud>(writer: &mut CoverTreeWriter<D>) {
+    writer.generate_summaries();
+    if !writer.reader().plugin_attached::<GokoDiagGaussian>() {
+        writer.add_plugin::<GokoDiagGaussian>(GokoDiagGaussian::singletons());
+    }
+    if !writer.reader().plugin_attached::<GokoDirichlet>() {
+        writer.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+    }
+}
+
+/// Errors if a query isn't the dimension of the tree, a dimension of 0 would have nothing to chunk the rows by.
+fn check_dim(expected: usize, dim: usize) -> PyResult<()> {
+    if dim != expected {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "the points have dimension {}, the tree has dimension {}",
+            dim, expected
+        )));
+    }
+    Ok(())
+}
+
+fn knn_to_py<M: Metric<[f32]>>(
+    py: Python,
+    point_cloud: &DefaultLabeledCloud<M>,
+    results: Vec<(f32, usize)>,
+    return_distance: bool,
+    return_labels: bool,
+) -> PyObject {
+    let label = |i: usize| point_cloud.label(i).ok().flatten().cloned();
+    match (return_distance, return_labels) {
+        (true, false) => results.into_py(py),
+        (false, false) => results
+            .iter()
+            .map(|(_, i)| *i)
+            .collect::<Vec<usize>>()
+            .into_py(py),
+        (true, true) => results
+            .iter()
+            .map(|(d, i)| (*d, *i, label(*i)))
+            .collect::<Vec<(f32, usize, Option<i64>)>>()
+            .into_py(py),
+        (false, true) => results
+            .iter()
+            .map(|(_, i)| (*i, label(*i)))
+            .collect::<Vec<(usize, Option<i64>)>>()
+            .into_py(py),
+    }
+}
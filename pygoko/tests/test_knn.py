@@ -0,0 +1,85 @@
+import pygoko
+
+import numpy as np
+
+
+def test_labeled_knn():
+    data = np.array([[0.499], [0.48], [-0.49], [0.0]], dtype=np.float32)
+    labels = np.array([0, 0, 1, 1], dtype=np.int64)
+
+    tree = pygoko.CoverTree()
+    tree.set_scale_base(2)
+    tree.set_leaf_cutoff(0)
+    tree.fit(data, labels)
+
+    pairs = tree.knn(data[0], 2)
+    indexes = tree.knn(data[0], 2, return_distance=False)
+    labeled = tree.knn(data[0], 2, return_labels=True)
+    assert indexes == [i for _, i in pairs]
+    assert [(d, i) for d, i, _ in labeled] == pairs
+    assert all(label == labels[i] for _, i, label in labeled)
+
+    batch = tree.knn_batch(data, 2, return_labels=True)
+    assert len(batch) == len(data)
+    for point, result in zip(data, batch):
+        assert result == tree.knn(point, 2, return_labels=True)
+
+
+def test_knn_graph():
+    data = np.array([[0.499], [0.48], [-0.49], [0.0]], dtype=np.float32)
+
+    tree = pygoko.CoverTree()
+    tree.set_scale_base(2)
+    tree.set_leaf_cutoff(0)
+    tree.fit(data)
+
+    indptr, indices, distances = tree.knn_graph(2)
+    assert list(indptr) == [0, 2, 4, 6, 8]
+    for i, point in enumerate(data):
+        row = list(zip(distances[indptr[i]:indptr[i + 1]], indices[indptr[i]:indptr[i + 1]]))
+        expected = [(d, j) for d, j in tree.knn(point, 3) if j != i][:2]
+        assert [j for _, j in row] == [j for _, j in expected]
+
+
+def test_cosine_metric():
+    data = np.array([[1.0, 0.0], [0.0, 2.0], [-3.0, 0.0], [1.0, 1.0]], dtype=np.float32)
+
+    tree = pygoko.CoverTree()
+    tree.set_metric("cosine")
+    tree.set_scale_base(2)
+    tree.set_leaf_cutoff(0)
+    tree.fit(data)
+    assert tree.metric() == "cosine"
+
+    distances = dict((i, d) for d, i in tree.knn(data[0], 4))
+    assert abs(distances[0]) < 1e-2
+    assert abs(distances[1] - np.pi / 2) < 1e-3
+    assert abs(distances[2] - np.pi) < 1e-3
+    assert abs(distances[3] - np.pi / 4) < 1e-3
+
+    try:
+        pygoko.CoverTree().set_metric("manhattan")
+        assert False
+    except ValueError:
+        pass
+
+
+def test_knn_checks_the_dimension():
+    data = np.array([[0.499], [0.48], [-0.49], [0.0]], dtype=np.float32)
+
+    tree = pygoko.CoverTree()
+    tree.set_scale_base(2)
+    tree.set_leaf_cutoff(0)
+    tree.fit(data)
+
+    for points in [np.zeros((2, 0), dtype=np.float32), np.zeros((2, 3), dtype=np.float32)]:
+        try:
+            tree.knn_batch(points, 2)
+            assert False
+        except ValueError:
+            pass
+    try:
+        tree.knn(np.zeros(0, dtype=np.float32), 2)
+        assert False
+    except ValueError:
+        pass
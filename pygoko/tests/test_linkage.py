@@ -0,0 +1,19 @@
+import pygoko
+
+import numpy as np
+
+
+def test_linkage_is_a_dendrogram():
+    data = np.array([[0.499], [0.49], [0.48], [-0.49], [0.0]], dtype=np.float32)
+
+    tree = pygoko.CoverTree()
+    tree.set_scale_base(2)
+    tree.set_leaf_cutoff(0)
+    tree.fit(data)
+
+    matrix, point_indexes = tree.linkage()
+    assert matrix.shape == (len(data) - 1, 4)
+    assert sorted(point_indexes) == list(range(len(data)))
+    assert np.all(np.diff(matrix[:, 2]) >= 0)
+    assert matrix[-1, 3] == len(data)
+    assert tree.newick().endswith(";")
@@ -0,0 +1,46 @@
+import os
+import tempfile
+
+import pygoko
+
+import numpy as np
+
+
+def test_save_load_round_trip():
+    data = np.array([[0.499], [0.48], [-0.49], [0.0]], dtype=np.float32)
+
+    tree = pygoko.CoverTree()
+    tree.set_scale_base(2)
+    tree.set_leaf_cutoff(0)
+    tree.fit(data)
+
+    with tempfile.TemporaryDirectory() as tmp:
+        path = os.path.join(tmp, "tree.dat")
+        tree.save(path)
+        loaded = pygoko.CoverTree.load(path, data)
+
+    assert loaded.top_scale() == tree.top_scale()
+    assert loaded.bottom_scale() == tree.bottom_scale()
+    for point in data:
+        assert loaded.knn(point, 2) == tree.knn(point, 2)
+        assert loaded.path(point) == tree.path(point)
+
+
+def test_plugins_are_saved():
+    data = np.array([[0.499], [0.48], [-0.49], [0.0]], dtype=np.float32)
+
+    tree = pygoko.CoverTree()
+    tree.set_scale_base(2)
+    tree.set_leaf_cutoff(0)
+    tree.fit(data)
+
+    with tempfile.TemporaryDirectory() as tmp:
+        path = os.path.join(tmp, "tree.dat")
+        tree.save(path)
+        loaded = pygoko.CoverTree.load(path, data)
+
+    root = tree.root()
+    loaded_root = loaded.root()
+    assert loaded_root.children_probs() == root.children_probs()
+    assert np.array_equal(loaded_root.cover_mean(), root.cover_mean())
+    assert np.array_equal(loaded_root.cover_diag_var(), root.cover_diag_var())
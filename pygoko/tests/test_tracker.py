@@ -0,0 +1,30 @@
+import pygoko
+
+import numpy as np
+
+
+def test_tracker_stats():
+    data = np.array([[0.499], [0.48], [-0.49], [0.0]], dtype=np.float32)
+
+    tree = pygoko.CoverTree()
+    tree.set_scale_base(2)
+    tree.set_leaf_cutoff(0)
+    tree.fit(data)
+
+    tracker = tree.tracker(3)
+    assert tracker.kl_div() == 0.0
+    assert tracker.marginal_aic() == 0.0
+    for point in [data[0], data[0], data[1], data[0]]:
+        tracker.add_path(point)
+
+    assert tracker.sequence_len() == 3
+    assert tracker.kl_div() >= 0.0
+    assert tracker.marginal_aic() > 0.0
+    stats = tracker.kl_div_stats()
+    assert stats["sequence_len"] == 3
+    assert set(stats.keys()) >= {"max", "min", "nz_count", "moment1_nz", "moment2_nz"}
+    assert tracker.kl_div_stats("ln_fraction")["sequence_len"] == 3
+    fit = tracker.goodness_of_fit()
+    assert fit["sequence_len"] == 3
+    assert 0.0 <= fit["chi_squared_p_value"] <= 1.0
+    assert 0.0 <= fit["g_test_p_value"] <= 1.0
@@ -0,0 +1,20 @@
+import pygoko
+
+import numpy as np
+
+
+def test_tree_stats():
+    data = np.array([[0.499], [0.49], [0.48], [-0.49], [0.0]], dtype=np.float32)
+
+    tree = pygoko.CoverTree()
+    tree.set_scale_base(2)
+    tree.set_leaf_cutoff(0)
+    tree.fit(data)
+
+    stats = tree.tree_stats()
+    assert stats["point_count"] == len(data)
+    assert stats["leaf_count"] + stats["singleton_count"] == len(data)
+    assert sum(layer["node_count"] for layer in stats["layers"]) == stats["node_count"]
+    assert stats["layers"][0]["scale_index"] == tree.top_scale()
+    assert stats["parameters"]["scale_base"] == 2
+    assert stats["parameters"]["leaf_cutoff"] == 0
@@ -5,14 +5,14 @@ extern crate serve_goko;
 use serve_goko::parsers::MsgPackDense;
 use serve_goko::http::*;
 use serve_goko::core::*;
+use serve_goko::config::ServerConfig;
 use std::sync::Arc;
 use goko::plugins::discrete::prelude::GokoDirichlet;
 use hyper::Server;
 use pointcloud::loaders::labeled_ram_from_yaml;
 use pointcloud::label_sources::SmallIntLabels;
 use pointcloud::data_sources::DataRam;
-use log::LevelFilter;
-use env_logger::Builder;
+use tracing::{info, Level};
 
 fn build_tree() -> CoverTreeWriter<SimpleLabeledCloud<DataRam<L2>, SmallIntLabels>> {
     let file_name = "../data/ember_complex_test.yml";
@@ -27,21 +27,34 @@ fn build_tree() -> CoverTreeWriter<SimpleLabeledCloud<DataRam<L2>, SmallIntLabel
 
 #[tokio::main]
 pub async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut builder = Builder::new();
-    builder.filter_level(LevelFilter::Info).init();
+    tracing_subscriber::fmt().with_max_level(Level::INFO).init();
 
     let mut ct_writer = build_tree();
-    ct_writer.add_plugin::<GokoDirichlet>(GokoDirichlet {});
+    ct_writer.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
     ct_writer.generate_summaries();
-    let goko_server = MakeGokoHttp::<_,MsgPackDense>::new(Arc::new(CoreWriter::new(ct_writer)));
+    let config = ServerConfig::default().with_flush_path("trackers.json");
+    let core = Arc::new(CoreWriter::from_config(ct_writer, &config));
+    core.add_trackers(&config.trackers.window_sizes).await?;
+    if config.persistence.restore {
+        let restored = core.restore_trackers().await?;
+        info!(trackers = restored.trackers_restored, "Restored the trackers");
+    }
+    if let Some(interval_secs) = config.persistence.snapshot_interval_secs {
+        core.schedule_snapshots(interval_secs);
+    }
+    if let Some(baseline) = &config.baseline {
+        core.schedule_baselines(baseline.clone());
+    }
+    let goko_server = MakeGokoHttp::<_,MsgPackDense>::from_config(Arc::clone(&core), &config);
 
-    let addr = ([127, 0, 0, 1], 3030).into();
+    let addr = config.address;
 
-    let server = Server::bind(&addr).serve(goko_server);
+    let server = Server::bind(&addr).serve(goko_server).with_graceful_shutdown(shutdown_signal());
 
     println!("Listening on http://{}", addr);
 
     server.await?;
+    core.shutdown().await?;
 
     Ok(())
 }
@@ -5,12 +5,13 @@ use std::path::Path;
 extern crate serve_goko;
 use serve_goko::parsers::MsgPackDense;
 use serve_goko::http::*;
+use serve_goko::grpc::GokoGrpc;
 use serve_goko::core::*;
+use serve_goko::config::ServerConfig;
 use std::sync::Arc;
 use goko::plugins::discrete::prelude::GokoDirichlet;
 use hyper::Server;
-use log::LevelFilter;
-use env_logger::Builder;
+use tracing::{info, Level};
 
 fn build_tree() -> CoverTreeWriter<DefaultLabeledCloud<L2>> {
     let file_name = "../data/mnist_complex.yml";
@@ -24,21 +25,41 @@ fn build_tree() -> CoverTreeWriter<DefaultLabeledCloud<L2>> {
 
 #[tokio::main(worker_threads = 12)]
 pub async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut builder = Builder::new();
-
-    builder.filter_level(LevelFilter::Info).init();
+    tracing_subscriber::fmt().with_max_level(Level::INFO).init();
     let mut ct_writer = build_tree();
-    ct_writer.add_plugin::<GokoDirichlet>(GokoDirichlet {});
+    ct_writer.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
     ct_writer.generate_summaries();
-    let goko_server = MakeGokoHttp::<_,MsgPackDense>::new(Arc::new(CoreWriter::new(ct_writer)));
+    let config = ServerConfig::default()
+        .with_address(([127, 0, 0, 1], 3031))
+        .with_flush_path("trackers.json")
+        .with_grpc_address(([127, 0, 0, 1], 3041));
+    let core = Arc::new(CoreWriter::from_config(ct_writer, &config));
+    core.add_trackers(&config.trackers.window_sizes).await?;
+    if config.persistence.restore {
+        let restored = core.restore_trackers().await?;
+        info!(trackers = restored.trackers_restored, "Restored the trackers");
+    }
+    if let Some(interval_secs) = config.persistence.snapshot_interval_secs {
+        core.schedule_snapshots(interval_secs);
+    }
+    if let Some(baseline) = &config.baseline {
+        core.schedule_baselines(baseline.clone());
+    }
+    let goko_server = MakeGokoHttp::<_,MsgPackDense>::from_config(Arc::clone(&core), &config);
+    if let Some(grpc_address) = config.grpc_address {
+        let grpc = GokoGrpc::from_config(Arc::clone(&core), &config).into_service();
+        tokio::spawn(tonic::transport::Server::builder().add_service(grpc).serve(grpc_address));
+        info!(address = %grpc_address, "Serving gRPC");
+    }
 
-    let addr = ([127, 0, 0, 1], 3031).into();
+    let addr = config.address;
 
-    let server = Server::bind(&addr).serve(goko_server);
+    let server = Server::bind(&addr).serve(goko_server).with_graceful_shutdown(shutdown_signal());
 
     println!("Listening on http://{}", addr);
 
     server.await?;
+    core.shutdown().await?;
 
     Ok(())
 }
@@ -0,0 +1,189 @@
+use pointcloud::*;
+use crate::core::*;
+use crate::errors::InternalServiceError;
+use goko::errors::GokoError;
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::ops::Deref;
+
+use super::{RestoreTrackerRequest, SnapshotRequest, TrackerSnapshot, TrackingRequest, TrackingRequestChoice, TrackingResponse};
+
+/// Send a `POST` request to `/admin/flush` for this
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct FlushRequest;
+
+/// Request: [`FlushRequest`]
+#[derive(Deserialize, Serialize)]
+pub struct FlushResponse {
+    /// The number of trackers whose state was captured
+    pub trackers_flushed: usize,
+    /// Where the state was written, `None` if no flush path is configured
+    pub path: Option<String>,
+}
+
+/// The trackers under a single tracker name, `None` is the default tracker.
+#[derive(Deserialize, Serialize)]
+pub struct NamedTrackerSnapshots {
+    pub tracker_name: Option<String>,
+    pub trackers: Vec<TrackerSnapshot>,
+}
+
+/// What is written to the flush path.
+#[derive(Deserialize, Serialize)]
+pub struct FlushedTrackers {
+    pub trackers: Vec<NamedTrackerSnapshots>,
+}
+
+fn snapshot_request<T>(tracker_name: Option<String>) -> TrackingRequest<T> {
+    TrackingRequest {
+        tracker_name,
+        request: TrackingRequestChoice::Snapshot(SnapshotRequest),
+    }
+}
+
+/// The windows of every tracker of the reader, leaving out the transient ones unless asked for them. The tracker
+/// workers handle their messages in order, so once the snapshot comes back every tracking request that was sent
+/// before it has been applied.
+pub(crate) async fn tracker_snapshots<D, T>(reader: &CoreReader<D, T>, with_transient: bool) -> Result<Vec<NamedTrackerSnapshots>, InternalServiceError>
+where
+    D: PointCloud,
+    T: Send + 'static,
+{
+    let mut trackers = Vec::new();
+    if let TrackingResponse::Snapshot(s) = reader.main_tracker.message(snapshot_request(None)).await? {
+        trackers.push(NamedTrackerSnapshots {
+            tracker_name: None,
+            trackers: s.trackers,
+        });
+    }
+    for (tracker_name, tracker) in reader.trackers.read().await.iter() {
+        if !with_transient && reader.transient_trackers.contains(tracker_name) {
+            continue;
+        }
+        let tracker_name = Some(tracker_name.clone());
+        if let TrackingResponse::Snapshot(s) = tracker.message(snapshot_request(tracker_name.clone())).await? {
+            trackers.push(NamedTrackerSnapshots {
+                tracker_name,
+                trackers: s.trackers,
+            });
+        }
+    }
+    Ok(trackers)
+}
+
+/// Replays the snapshots onto the reader's trackers, see [`RestoreRequest::process`]. The counts are returned
+/// without a path.
+pub(crate) async fn replay_trackers<D, T>(reader: &CoreReader<D, T>, snapshots: Vec<NamedTrackerSnapshots>, with_transient: bool) -> Result<RestoreResponse, InternalServiceError>
+where
+    D: PointCloud,
+    T: Deref<Target = D::Point> + Send + Sync + 'static,
+{
+    let mut response = RestoreResponse::default();
+    for named in snapshots {
+        if let Some(tracker_name) = &named.tracker_name {
+            if !with_transient && reader.transient_trackers.contains(tracker_name) {
+                continue;
+            }
+            let mut trackers = reader.trackers.write().await;
+            if !trackers.contains_key(tracker_name) {
+                let tracker = reader.named_tracker(tracker_name).await?;
+                trackers.insert(tracker_name.clone(), tracker);
+            }
+        }
+        for snapshot in named.trackers {
+            let request = TrackingRequest {
+                tracker_name: named.tracker_name.clone(),
+                request: TrackingRequestChoice::RestoreTracker(RestoreTrackerRequest { snapshot }),
+            };
+            let restored = match &named.tracker_name {
+                Some(tracker_name) => match reader.trackers.read().await.get(tracker_name) {
+                    Some(tracker) => tracker.message(request).await?,
+                    None => continue,
+                },
+                None => reader.main_tracker.message(request).await?,
+            };
+            if let TrackingResponse::RestoreTracker(r) = restored {
+                response.trackers_restored += 1;
+                response.paths_restored += r.restored;
+                response.paths_skipped += r.skipped;
+            }
+        }
+    }
+    Ok(response)
+}
+
+impl FlushRequest {
+    /// See [`tracker_snapshots`] for which tracking requests make it into the flush.
+    pub async fn process<D, T>(self, reader: &CoreReader<D, T>) -> Result<FlushResponse, InternalServiceError>
+    where
+        D: PointCloud,
+        T: Send + 'static,
+    {
+        let trackers = tracker_snapshots(reader, false).await?;
+        let trackers_flushed = trackers.iter().map(|t| t.trackers.len()).sum();
+
+        let path = match &reader.flush_path {
+            Some(flush_path) => {
+                // Written next to the flush path and moved over it, so a crash mid-write leaves the last flush intact
+                let partial_path = flush_path.with_extension("partial");
+                let file = File::create(&partial_path).map_err(GokoError::from)?;
+                let mut writer = io::BufWriter::new(file);
+                serde_json::to_writer(&mut writer, &FlushedTrackers { trackers })
+                    .map_err(|e| GokoError::from(io::Error::from(e)))?;
+                // On disk before the rename, or a crash right after it can leave an empty flush behind
+                writer.flush().map_err(GokoError::from)?;
+                writer.get_ref().sync_all().map_err(GokoError::from)?;
+                fs::rename(&partial_path, flush_path).map_err(GokoError::from)?;
+                Some(flush_path.to_string_lossy().to_string())
+            }
+            None => None,
+        };
+        Ok(FlushResponse {
+            trackers_flushed,
+            path,
+        })
+    }
+}
+
+/// Replays the trackers from the flush path, see [`crate::core::CoreWriter::restore_trackers`].
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct RestoreRequest;
+
+/// Request: [`RestoreRequest`]
+#[derive(Deserialize, Serialize, Default)]
+pub struct RestoreResponse {
+    /// The number of tracker windows that were restored
+    pub trackers_restored: usize,
+    /// The number of paths replayed onto them
+    pub paths_restored: usize,
+    /// The number of paths that went through nodes that aren't in the tree, these are dropped
+    pub paths_skipped: usize,
+    /// Where the state was read from, `None` if no flush path is configured or nothing was flushed to it yet
+    pub path: Option<String>,
+}
+
+impl RestoreRequest {
+    /// Each window in the flush is restored onto a fresh tracker, replacing the tracker with the same name and
+    /// window. Named trackers that don't exist are created and the transient ones are skipped. The evidence, counters
+    /// and decay come back as they were flushed if the tree has every node they're on, otherwise the paths are
+    /// replayed, see [`crate::api::RestoreTrackerRequest`]. The unique visitors and segments come back empty.
+    pub async fn process<D, T>(self, reader: &CoreReader<D, T>) -> Result<RestoreResponse, InternalServiceError>
+    where
+        D: PointCloud,
+        T: Deref<Target = D::Point> + Send + Sync + 'static,
+    {
+        let flush_path = match &reader.flush_path {
+            Some(flush_path) if flush_path.exists() => flush_path,
+            _ => return Ok(RestoreResponse::default()),
+        };
+        let file = File::open(flush_path).map_err(GokoError::from)?;
+        let flushed: FlushedTrackers = serde_json::from_reader(io::BufReader::new(file))
+            .map_err(|e| GokoError::from(io::Error::from(e)))?;
+
+        let mut response = replay_trackers(reader, flushed.trackers, false).await?;
+        response.path = Some(flush_path.to_string_lossy().to_string());
+        Ok(response)
+    }
+}
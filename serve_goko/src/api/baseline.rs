@@ -0,0 +1,108 @@
+use pointcloud::*;
+use crate::core::*;
+use goko::errors::GokoError;
+use goko::plugins::discrete::baseline::KLDivergenceBaseline;
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Where the baselines of the trackers came from, shared between the core, its readers and the scheduler.
+#[derive(Default)]
+pub(crate) struct BaselineState {
+    interval_secs: Option<u64>,
+    num_sequences: usize,
+    /// When the baseline in use was made and the epoch of the tree it was trained on
+    current: Option<(Instant, u64)>,
+    /// The baseline in use, named trackers added after it was made start with it
+    baseline: Option<KLDivergenceBaseline>,
+    recomputations: u64,
+    running: Option<(Instant, Arc<AtomicUsize>)>,
+    last_error: Option<String>,
+}
+
+impl BaselineState {
+    pub(crate) fn schedule(&mut self, interval_secs: u64, num_sequences: usize) {
+        self.interval_secs = Some(interval_secs);
+        self.num_sequences = num_sequences;
+    }
+
+    /// A recomputation has started, the trainer counts its finished sequences with the returned counter.
+    pub(crate) fn start(&mut self) -> Arc<AtomicUsize> {
+        let progress = Arc::new(AtomicUsize::new(0));
+        self.running = Some((Instant::now(), Arc::clone(&progress)));
+        progress
+    }
+
+    /// A baseline trained on the tree at `epoch` was swapped in, either by the scheduler or by hand.
+    pub(crate) fn finish(&mut self, epoch: u64, baseline: KLDivergenceBaseline) {
+        if self.running.take().is_some() {
+            self.recomputations += 1;
+            self.last_error = None;
+        }
+        self.current = Some((Instant::now(), epoch));
+        self.baseline = Some(baseline);
+    }
+
+    /// The baseline in use, if there is one.
+    pub(crate) fn baseline(&self) -> Option<KLDivergenceBaseline> {
+        self.baseline.clone()
+    }
+
+    pub(crate) fn fail(&mut self, error: String) {
+        self.running = None;
+        self.last_error = Some(error);
+    }
+}
+
+/// Send a `GET` request to `/track/baseline/status` for this
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct BaselineStatusRequest;
+
+/// Request: [`BaselineStatusRequest`]
+#[derive(Deserialize, Serialize)]
+pub struct BaselineStatusResponse {
+    /// The seconds between recomputations, `None` if the baselines aren't recomputed in the background
+    pub interval_secs: Option<u64>,
+    /// Seconds since the baseline in use was swapped in, `None` if the trackers don't have one
+    pub age_secs: Option<f64>,
+    /// The epoch of the tree the baseline in use was trained on
+    pub epoch: Option<u64>,
+    /// The number of background recomputations that finished
+    pub recomputations: u64,
+    /// Whether a recomputation is running right now
+    pub running: bool,
+    /// Seconds since the running recomputation started
+    pub running_secs: Option<f64>,
+    /// The sequences of the running recomputation that are done
+    pub sequences_done: usize,
+    /// The sequences a recomputation samples
+    pub num_sequences: usize,
+    /// Why the last recomputation failed, cleared once one succeeds
+    pub last_error: Option<String>,
+}
+
+impl BaselineStatusRequest {
+    pub fn process<D: PointCloud, T: Send + 'static>(self, reader: &CoreReader<D, T>) -> Result<BaselineStatusResponse, GokoError> {
+        let state = reader.baseline_state.lock().unwrap();
+        let (running_secs, sequences_done) = match &state.running {
+            Some((started, progress)) => (
+                Some(started.elapsed().as_secs_f64()),
+                progress.load(atomic::Ordering::Relaxed),
+            ),
+            None => (None, 0),
+        };
+        Ok(BaselineStatusResponse {
+            interval_secs: state.interval_secs,
+            age_secs: state.current.map(|(made, _)| made.elapsed().as_secs_f64()),
+            epoch: state.current.map(|(_, epoch)| epoch),
+            recomputations: state.recomputations,
+            running: state.running.is_some(),
+            running_secs,
+            sequences_done,
+            num_sequences: state.num_sequences,
+            last_error: state.last_error.clone(),
+        })
+    }
+}
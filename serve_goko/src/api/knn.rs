@@ -5,20 +5,29 @@ use serde::{Deserialize, Serialize};
 use std::ops::Deref;
 
 use goko::errors::GokoError;
+use goko::QueryBudget;
 
-use super::NamedDistance;
+use super::{NamedDistance, PointFields};
 
 /// Response: [`KnnResponse`]
 #[derive(Deserialize, Serialize)]
 pub struct KnnRequest<T> {
     pub k: usize,
     pub point: T,
+    #[serde(default)]
+    pub fields: PointFields,
+    /// Limits on the nodes the query opens, the default has none
+    #[serde(default)]
+    pub budget: QueryBudget,
 }
 
 /// Request: [`KnnRequest`]
 #[derive(Deserialize, Serialize)]
 pub struct KnnResponse {
     pub knn: Vec<NamedDistance>,
+    /// If the query ran out of budget, the neighbors are real points but there may be closer ones
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
 }
 
 impl<T> KnnRequest<T> {
@@ -27,19 +36,15 @@ impl<T> KnnRequest<T> {
         D: PointCloud, 
         T: Deref<Target = D::Point> + Send + Sync,
     {
-        let knn = reader.tree.knn(&self.point, self.k)?;
+        let knn = reader.tree.knn_budgeted(&self.point, self.k, &self.budget)?;
         let pc = &reader.tree.parameters().point_cloud;
         let resp: Result<Vec<NamedDistance>, GokoError> = knn
+            .result
             .iter()
-            .map(|(distance, pi)| {
-                Ok(NamedDistance {
-                    name: pc.name(*pi)?,
-                    distance: *distance,
-                })
-            })
+            .map(|(distance, pi)| self.fields.named_distance(pc.as_ref(), *pi, *distance))
             .collect();
 
-        Ok(KnnResponse { knn: resp? })
+        Ok(KnnResponse { knn: resp?, truncated: knn.truncated })
     }
 }
 
@@ -48,12 +53,20 @@ impl<T> KnnRequest<T> {
 pub struct RoutingKnnRequest<T> {
     pub k: usize,
     pub point: T,
+    #[serde(default)]
+    pub fields: PointFields,
+    /// Limits on the nodes the query opens, the default has none
+    #[serde(default)]
+    pub budget: QueryBudget,
 }
 
 /// Request: [`RoutingKnnRequest`]
 #[derive(Deserialize, Serialize)]
 pub struct RoutingKnnResponse {
     pub routing_knn: Vec<NamedDistance>,
+    /// If the query ran out of budget, see [`KnnResponse::truncated`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
 }
 
 impl<T> RoutingKnnRequest<T> {
@@ -62,18 +75,75 @@ impl<T> RoutingKnnRequest<T> {
         D: PointCloud, 
         T: Deref<Target = D::Point> + Send + Sync,
     {
-        let knn = reader.tree.routing_knn(&self.point, self.k)?;
+        let knn = reader.tree.routing_knn_budgeted(&self.point, self.k, &self.budget)?;
         let pc = &reader.tree.parameters().point_cloud;
         let resp: Result<Vec<NamedDistance>, GokoError> = knn
+            .result
             .iter()
-            .map(|(distance, pi)| {
-                Ok(NamedDistance {
-                    name: pc.name(*pi)?,
-                    distance: *distance,
-                })
-            })
+            .map(|(distance, pi)| self.fields.named_distance(pc.as_ref(), *pi, *distance))
             .collect();
 
-        Ok(RoutingKnnResponse { routing_knn: resp? })
+        Ok(RoutingKnnResponse { routing_knn: resp?, truncated: knn.truncated })
+    }
+}
+/// Response: [`KnnResponse`]
+#[derive(Deserialize, Serialize)]
+pub struct KnnByNameRequest {
+    pub k: usize,
+    pub name: String,
+    #[serde(default)]
+    pub fields: PointFields,
+    /// Limits on the nodes the query opens, the default has none
+    #[serde(default)]
+    pub budget: QueryBudget,
+}
+
+impl KnnByNameRequest {
+    pub fn process<D, T>(self, reader: &mut CoreReader<D, T>) -> Result<KnnResponse, GokoError>
+    where
+        D: PointCloud,
+        T: Deref<Target = D::Point> + Send + Sync,
+    {
+        let pc = &reader.tree.parameters().point_cloud;
+        let point = pc.point(pc.index(&self.name)?)?;
+        let knn = reader.tree.knn_budgeted(&point, self.k, &self.budget)?;
+        let resp: Result<Vec<NamedDistance>, GokoError> = knn
+            .result
+            .iter()
+            .map(|(distance, pi)| self.fields.named_distance(pc.as_ref(), *pi, *distance))
+            .collect();
+
+        Ok(KnnResponse { knn: resp?, truncated: knn.truncated })
+    }
+}
+
+/// Response: [`KnnResponse`]
+#[derive(Deserialize, Serialize)]
+pub struct KnnByIdRequest {
+    pub k: usize,
+    pub id: ExternalId,
+    #[serde(default)]
+    pub fields: PointFields,
+    /// Limits on the nodes the query opens, the default has none
+    #[serde(default)]
+    pub budget: QueryBudget,
+}
+
+impl KnnByIdRequest {
+    pub fn process<D, T>(self, reader: &mut CoreReader<D, T>) -> Result<KnnResponse, GokoError>
+    where
+        D: PointCloud,
+        T: Deref<Target = D::Point> + Send + Sync,
+    {
+        let pc = &reader.tree.parameters().point_cloud;
+        let point = pc.point(pc.external_index(&self.id)?)?;
+        let knn = reader.tree.knn_budgeted(&point, self.k, &self.budget)?;
+        let resp: Result<Vec<NamedDistance>, GokoError> = knn
+            .result
+            .iter()
+            .map(|(distance, pi)| self.fields.named_distance(pc.as_ref(), *pi, *distance))
+            .collect();
+
+        Ok(KnnResponse { knn: resp?, truncated: knn.truncated })
     }
 }
\ No newline at end of file
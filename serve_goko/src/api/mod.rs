@@ -1,19 +1,30 @@
 use std::ops::Deref;
-use pointcloud::{PointCloud, SummaryCounter, Summary};
+use pointcloud::{ExternalId, PointCloud, SummaryCounter, Summary};
 use crate::errors::InternalServiceError;
 use crate::core::CoreReader;
+use goko::errors::GokoError;
+use std::io;
+use std::time::Instant;
 
 use serde::{Deserialize, Serialize};
 //use std::convert::Infallible;
 
+mod admin;
+mod baseline;
 mod parameters;
 mod path;
 mod knn;
+mod registry;
 mod tracker;
+mod tree_stats;
 
+pub use admin::*;
+pub use baseline::*;
 pub use parameters::*;
 pub use path::*;
+pub use registry::*;
 pub use tracker::*;
+pub use tree_stats::*;
 pub use knn::*;
 
 /// A summary for a small number of categories.
@@ -23,6 +34,11 @@ pub enum GokoRequest<T> {
     /// 
     /// Response: [`ParametersResponse`]
     Parameters(ParametersRequest),
+    /// The node, leaf and singleton counts of the tree and its layers, with the parameters it was built with. With
+    /// the HTTP server, send a `GET` request to `/stats`.
+    ///
+    /// Response: [`TreeStatsResponse`]
+    TreeStats(TreeStatsRequest),
     /// With the HTTP server, send a `GET` request to `/knn?k=5` with a set of features in the body for this query, 
     /// will return with the response with the nearest 5 routing nbrs. 
     /// 
@@ -42,13 +58,97 @@ pub enum GokoRequest<T> {
     /// 
     /// Response: [`PathResponse`]
     Path(PathRequest<T>),
+    /// Knn of a point that's already in the tree, by its name. With the HTTP server send a `GET` request to
+    /// `/knn_by_name?name=NAME&k=5`, the fields can be picked like the regular knn.
+    ///
+    /// Response: [`KnnResponse`]
+    KnnByName(KnnByNameRequest),
+    /// The path of a point that's already in the tree, by its name. With the HTTP server send a `GET` request to
+    /// `/known_path_by_name?name=NAME`.
+    ///
+    /// Response: [`PathResponse`]
+    KnownPathByName(KnownPathByNameRequest),
+    /// Knn of a point that's already in the tree, by its external id. With the HTTP server send a `GET` request to
+    /// `/knn_by_id?id=ID&k=5`, ids that parse as a `u64` are looked up as integers. The fields can be picked like
+    /// the regular knn. Only works if the server's point cloud has external ids.
+    ///
+    /// Response: [`KnnResponse`]
+    KnnById(KnnByIdRequest),
+    /// The path of a point that's already in the tree, by its external id. With the HTTP server send a `GET`
+    /// request to `/known_path_by_id?id=ID`.
+    ///
+    /// Response: [`PathResponse`]
+    KnownPathById(KnownPathByIdRequest),
     /// The queries to manipulate the trackers, all under /track/
     /// 
     /// See : [`TrackingRequest`]
     Tracking(TrackingRequest<T>),
+    /// Drains the trackers and writes their state to the configured flush path, send a `POST` request to `/admin/flush`.
+    /// This is also done on shutdown.
+    /// 
+    /// Response: [`FlushResponse`]
+    Flush(FlushRequest),
+    /// The stats of every window of every tracker, captured at one logical point so they can be compared with
+    /// each other. Send a `GET` request to `/track/snapshot`.
+    ///
+    /// Response: [`StatsSnapshotResponse`]
+    StatsSnapshot(StatsSnapshotRequest),
+    /// How old the baseline of the trackers is and how far along its background recomputation is, send a `GET`
+    /// request to `/track/baseline/status`. See [`crate::core::CoreWriter::schedule_baselines`].
+    ///
+    /// Response: [`BaselineStatusResponse`]
+    BaselineStatus(BaselineStatusRequest),
+    /// Loads a tree and serves it next to the main one, under `/tree/NAME/`. Every route of the main tree works
+    /// on a registered tree, for example `/tree/NAME/knn?k=5` and `/tree/NAME/track/point`. Send a `POST` request
+    /// to `/admin/trees/register?name=NAME&path=PATH`. The loader opens whatever path the caller sends, see
+    /// [`RegisterTreeRequest`].
+    ///
+    /// Response: [`RegisterTreeResponse`]
+    RegisterTree(RegisterTreeRequest),
+    /// Stops serving a registered tree, send a `POST` request to `/admin/trees/unregister?name=NAME`.
+    ///
+    /// Response: [`UnregisterTreeResponse`]
+    UnregisterTree(UnregisterTreeRequest),
+    /// Swaps a tree for a new version of it without a restart, send a `POST` request to
+    /// `/admin/trees/reload?path=PATH&migrate_trackers=true`. Add `name=NAME` to reload a registered tree instead of
+    /// the main one.
+    ///
+    /// Response: [`ReloadTreeResponse`]
+    ReloadTree(ReloadTreeRequest),
+    /// The registered trees, send a `GET` request to `/admin/trees`.
+    ///
+    /// Response: [`ListTreesResponse`]
+    ListTrees(ListTreesRequest),
     /// The catch-all for errors
     Unknown(String, u16),
 }
+impl<T> GokoRequest<T> {
+    /// The name the request is counted under in the [`crate::core::Metrics`], tracking requests are counted by
+    /// what they ask the tracker to do.
+    pub fn request_type(&self) -> &'static str {
+        match self {
+            GokoRequest::Parameters(_) => "parameters",
+            GokoRequest::TreeStats(_) => "tree_stats",
+            GokoRequest::Knn(_) => "knn",
+            GokoRequest::RoutingKnn(_) => "routing_knn",
+            GokoRequest::Path(_) => "path",
+            GokoRequest::KnnByName(_) => "knn_by_name",
+            GokoRequest::KnownPathByName(_) => "known_path_by_name",
+            GokoRequest::KnnById(_) => "knn_by_id",
+            GokoRequest::KnownPathById(_) => "known_path_by_id",
+            GokoRequest::Tracking(p) => p.request.request_type(),
+            GokoRequest::Flush(_) => "flush",
+            GokoRequest::StatsSnapshot(_) => "stats_snapshot",
+            GokoRequest::BaselineStatus(_) => "baseline_status",
+            GokoRequest::RegisterTree(_) => "register_tree",
+            GokoRequest::UnregisterTree(_) => "unregister_tree",
+            GokoRequest::ReloadTree(_) => "reload_tree",
+            GokoRequest::ListTrees(_) => "list_trees",
+            GokoRequest::Unknown(..) => "unknown",
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct TrackingRequest<T> {
     pub tracker_name: Option<String>,
@@ -60,6 +160,9 @@ pub struct TrackingRequest<T> {
 pub enum TrackingRequestChoice<T> {
     /// Track a point, send a `POST` request to `/track/point?tracker_name=TRACKER_NAME` with a set of features in the body for this query. 
     /// Omit the `TRACKER_NAME` query to use the default. You
+    /// can add `query_id=QUERY_ID`, any string, to count the query towards the unique visitors of the trackers that
+    /// have them. Add `segment=SEGMENT`, a label or metadata value of the point, to break its stats out with
+    /// [`TrackingRequestChoice::SegmentStats`].
     /// 
     /// See the chosen body parser for how to encode the body.
     /// 
@@ -69,8 +172,23 @@ pub enum TrackingRequestChoice<T> {
     /// 
     /// Response: [`TrackPathResponse`]
     TrackPath(TrackPathRequest),
+    /// Track a batch of points in order, send a `POST` request to `/track/bulk?tracker_name=TRACKER_NAME` with the
+    /// batch in the body. The paths are computed in parallel before they're added to the tracker, so this is the
+    /// way to backfill a tracker. Omit the `TRACKER_NAME` query to use the default. Add `segment=SEGMENT` if the
+    /// whole batch is in one segment. The batch can have at most [`crate::http::QueryLimits::max_bulk_points`]
+    /// points.
+    ///
+    /// See the chosen body parser for how to encode the body.
+    ///
+    /// Response: [`TrackBulkResponse`]
+    TrackBulk(TrackBulkRequest<T>),
+    /// Unsupported for HTTP, the [`CoreReader`] turns a [`TrackingRequestChoice::TrackBulk`] into this
+    ///
+    /// Response: [`TrackBulkResponse`]
+    TrackBulkPaths(TrackBulkPathsRequest),
     /// Add a tracker, send a `POST` request to `/track/add?window_size=WINDOW_SIZE&tracker_name=TRACKER_NAME` with a set of features in the body for this query. 
     /// Omit the `TRACKER_NAME` query to use the default.
+    /// Add `unique_visitors=true` to also estimate the distinct query ids that go through each node.
     /// 
     /// Response: [`AddTrackerResponse`]
     AddTracker(AddTrackerRequest),
@@ -79,24 +197,149 @@ pub enum TrackingRequestChoice<T> {
     /// 
     /// Response: [`CurrentStatsResponse`]
     CurrentStats(CurrentStatsRequest),
+    /// Change the window of a tracker, send a `POST` request to `/track/resize?window_size=WINDOW_SIZE&tracker_name=TRACKER_NAME`.
+    /// If the tracker has more than one window pick the one to change with `old_window_size=OLD_WINDOW_SIZE`.
+    /// Shrinking the window drops the oldest points.
+    ///
+    /// Response: [`ResizeTrackerResponse`]
+    ResizeTracker(ResizeTrackerRequest),
+    /// Clear the evidence of a tracker, send a `POST` request to `/track/reset?tracker_name=TRACKER_NAME`.
+    /// Add `window_size=WINDOW_SIZE` to only clear one of its windows.
+    ///
+    /// Response: [`ResetTrackerResponse`]
+    ResetTracker(ResetTrackerRequest),
+    /// Get the nodes that drifted most, send a `GET` request to `/track/top_drift?n=N&window_size=WINDOW_SIZE&tracker_name=TRACKER_NAME`.
+    /// `n` defaults to 20. If the tracker has only one window you can omit `WINDOW_SIZE`. The first request to a
+    /// tracker computes the divergence of every node, after that they're kept up to date as points are tracked.
+    ///
+    /// Response: [`TopDriftResponse`]
+    TopDrift(TopDriftRequest),
+    /// Get the drift rolled up to a coarser scale, send a `GET` request to
+    /// `/track/drift_rollup?scale_index=SCALE_INDEX&window_size=WINDOW_SIZE&tracker_name=TRACKER_NAME`.
+    /// The divergence of every node is added to its ancestor at `SCALE_INDEX`, so the response has a handful of
+    /// regions instead of every node. If the tracker has only one window you can omit `WINDOW_SIZE`.
+    ///
+    /// Response: [`DriftRollupResponse`]
+    DriftRollup(DriftRollupRequest),
+    /// Get the stats of the points tracked with a segment, send a `GET` request to
+    /// `/track/segment_stats?window_size=WINDOW_SIZE&segment=SEGMENT&tracker_name=TRACKER_NAME`.
+    /// Omit `SEGMENT` for every segment, the tracker keeps at most [`MAX_SEGMENTS`]. `weighting=ln_fraction` works
+    /// like it does for the stats. Resetting all the windows of a tracker forgets its segments.
+    ///
+    /// Response: [`SegmentStatsResponse`]
+    SegmentStats(SegmentStatsRequest),
+    /// Unsupported for HTTP, see [`GokoRequest::StatsSnapshot`]
+    ///
+    /// Response: [`WindowStatsResponse`]
+    WindowStats(WindowStatsRequest),
+    /// Unsupported for HTTP, see [`GokoRequest::Flush`]
+    /// 
+    /// Response: [`SnapshotResponse`]
+    Snapshot(SnapshotRequest),
+    /// Unsupported for HTTP, see [`crate::core::CoreWriter::set_baseline`]
+    ///
+    /// Response: [`SetBaselineResponse`]
+    SetBaseline(SetBaselineRequest),
+    /// Unsupported for HTTP, see [`crate::core::CoreWriter::restore_trackers`]
+    ///
+    /// Response: [`RestoreTrackerResponse`]
+    RestoreTracker(RestoreTrackerRequest),
+}
+
+impl<T> TrackingRequestChoice<T> {
+    /// The name the request is counted under in the [`crate::core::Metrics`].
+    pub fn request_type(&self) -> &'static str {
+        use TrackingRequestChoice::*;
+        match self {
+            TrackPoint(_) => "track_point",
+            TrackPath(_) => "track_path",
+            TrackBulk(_) => "track_bulk",
+            TrackBulkPaths(_) => "track_bulk",
+            AddTracker(_) => "add_tracker",
+            CurrentStats(_) => "current_stats",
+            ResizeTracker(_) => "resize_tracker",
+            ResetTracker(_) => "reset_tracker",
+            TopDrift(_) => "top_drift",
+            DriftRollup(_) => "drift_rollup",
+            SegmentStats(_) => "segment_stats",
+            WindowStats(_) => "window_stats",
+            Snapshot(_) => "snapshot",
+            SetBaseline(_) => "set_baseline",
+            RestoreTracker(_) => "restore_tracker",
+        }
+    }
 }
 
 /// The response one gets back from the core server loop.
+///
+/// The HTTP server adds the epoch of the tree that answered the query to every response, both as a `goko-epoch`
+/// header and as an `epoch` field in the JSON body. The epoch is bumped every time the tree is refreshed, see
+/// [`crate::core::CoreWriter::refresh`]. Pass `require_epoch=N` in the query of any request to have it
+/// rejected with a 409 and a [`StaleEpochResponse`] if the tree is no longer at epoch `N`.
 #[derive(Deserialize, Serialize)]
 pub enum GokoResponse<L: Summary> {
     Parameters(ParametersResponse),
+    TreeStats(TreeStatsResponse),
     Knn(KnnResponse),
     RoutingKnn(RoutingKnnResponse),
     Path(PathResponse<L>),
     Tracking(TrackingResponse),
+    Flush(FlushResponse),
+    StatsSnapshot(StatsSnapshotResponse),
+    BaselineStatus(BaselineStatusResponse),
+    RegisterTree(RegisterTreeResponse),
+    UnregisterTree(UnregisterTreeResponse),
+    ReloadTree(ReloadTreeResponse),
+    ListTrees(ListTreesResponse),
+    StaleEpoch(StaleEpochResponse),
+    PayloadTooLarge(PayloadTooLargeResponse),
     Unknown(String, u16),
 }
 
+impl<L: Summary> GokoResponse<L> {
+    /// The length of the path the query walked down the tree, for the responses that have one.
+    pub fn depth(&self) -> Option<usize> {
+        match self {
+            GokoResponse::Path(p) => Some(p.path.len()),
+            _ => None,
+        }
+    }
+}
+
+/// The response to a request whose `require_epoch` doesn't match the tree's current epoch.
+#[derive(Deserialize, Serialize)]
+pub struct StaleEpochResponse {
+    /// The epoch the request asked for
+    pub required_epoch: u64,
+    /// The epoch the tree is at
+    pub epoch: u64,
+}
+
+/// The 413 response to a request whose body is over the server's limit, see
+/// [`crate::http::QueryLimits::max_body_bytes`].
+#[derive(Deserialize, Serialize)]
+pub struct PayloadTooLargeResponse {
+    /// What went wrong
+    pub error: String,
+    /// The largest body the server accepts, in bytes
+    pub max_body_bytes: usize,
+}
+
 #[derive(Deserialize, Serialize)]
 pub enum TrackingResponse {
     TrackPath(TrackPathResponse),
+    TrackBulk(TrackBulkResponse),
     AddTracker(AddTrackerResponse),
     CurrentStats(CurrentStatsResponse),
+    ResizeTracker(ResizeTrackerResponse),
+    ResetTracker(ResetTrackerResponse),
+    TopDrift(TopDriftResponse),
+    DriftRollup(DriftRollupResponse),
+    SegmentStats(SegmentStatsResponse),
+    WindowStats(WindowStatsResponse),
+    Snapshot(SnapshotResponse),
+    SetBaseline(SetBaselineResponse),
+    RestoreTracker(RestoreTrackerResponse),
     Unknown(Option<String>,Option<usize>),
 }
 
@@ -104,9 +347,65 @@ pub enum TrackingResponse {
 #[derive(Deserialize, Serialize)]
 pub struct NamedDistance {
     /// The name of the point we're refering to
-    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
     /// Distance to that point
     pub distance: f32,
+    /// The label of the point, if it was asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<serde_json::Value>,
+    /// The metadata of the point, if it was asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    /// The external id of the point, if the point cloud has them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<ExternalId>,
+}
+
+/// The per-point fields that are returned with KNN type queries. With the HTTP server pass `fields=name,label,metadata`
+/// in the query, any subset works. If it is omitted only the name is returned.
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct PointFields {
+    pub name: bool,
+    pub label: bool,
+    pub metadata: bool,
+}
+
+impl Default for PointFields {
+    fn default() -> Self {
+        PointFields {
+            name: true,
+            label: false,
+            metadata: false,
+        }
+    }
+}
+
+impl PointFields {
+    pub(crate) fn named_distance<D: PointCloud>(&self, point_cloud: &D, pi: usize, distance: f32) -> Result<NamedDistance, GokoError> {
+        let name = if self.name {
+            Some(point_cloud.name(pi)?)
+        } else {
+            None
+        };
+        let label = if self.label {
+            Some(serde_json::to_value(point_cloud.label(pi)?).map_err(|e| GokoError::from(io::Error::from(e)))?)
+        } else {
+            None
+        };
+        let metadata = if self.metadata {
+            Some(serde_json::to_value(point_cloud.metadata(pi)?).map_err(|e| GokoError::from(io::Error::from(e)))?)
+        } else {
+            None
+        };
+        Ok(NamedDistance {
+            name,
+            distance,
+            label,
+            metadata,
+            external_id: point_cloud.external_id(pi)?,
+        })
+    }
 }
 
 /// Response for queries that include distances to nodes, usually in a vec
@@ -119,23 +418,60 @@ pub struct NodeDistance<L: Summary + Clone> {
     /// The distance to the central node
     pub distance: f32,
     pub label_summary: Option<SummaryCounter<L>>,
+    /// The external id of the center point, if the point cloud has them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<ExternalId>,
 }
 
 impl<D: PointCloud, P> CoreReader<D, P>
 where P: Deref<Target = D::Point> + Send + Sync + 'static {
+    /// Answers the request and counts it in the [`crate::core::Metrics`].
     pub async fn process(&mut self, request: GokoRequest<P>) -> Result<GokoResponse<D::LabelSummary>,InternalServiceError> {
+        let request_type = request.request_type();
+        let start = Instant::now();
+        let response = self.process_uncounted(request).await;
+        self.metrics.record(request_type, start.elapsed(), response.is_ok());
+        response
+    }
+
+    async fn process_uncounted(&mut self, request: GokoRequest<P>) -> Result<GokoResponse<D::LabelSummary>,InternalServiceError> {
         match request {
             GokoRequest::Parameters(p) => p.process(self).map(|p| GokoResponse::Parameters(p)).map_err(|e| e.into()),
+            GokoRequest::TreeStats(p) => p.process(self).map(|p| GokoResponse::TreeStats(p)).map_err(|e| e.into()),
             GokoRequest::Knn(p) => p.process(self).map(|p| GokoResponse::Knn(p)).map_err(|e| e.into()),
             GokoRequest::RoutingKnn(p) => p.process(self).map(|p| GokoResponse::RoutingKnn(p)).map_err(|e| e.into()),
             GokoRequest::Path(p) => p.process(self).map(|p| GokoResponse::Path(p)).map_err(|e| e.into()),
+            GokoRequest::KnnByName(p) => p.process(self).map(|p| GokoResponse::Knn(p)).map_err(|e| e.into()),
+            GokoRequest::KnownPathByName(p) => p.process(self).map(|p| GokoResponse::Path(p)).map_err(|e| e.into()),
+            GokoRequest::KnnById(p) => p.process(self).map(|p| GokoResponse::Knn(p)).map_err(|e| e.into()),
+            GokoRequest::KnownPathById(p) => p.process(self).map(|p| GokoResponse::Path(p)).map_err(|e| e.into()),
+            GokoRequest::Flush(p) => p.process(self).await.map(|p| GokoResponse::Flush(p)),
+            GokoRequest::StatsSnapshot(p) => p.process(self).await.map(|p| GokoResponse::StatsSnapshot(p)),
+            GokoRequest::BaselineStatus(p) => p.process(self).map(|p| GokoResponse::BaselineStatus(p)).map_err(|e| e.into()),
+            GokoRequest::RegisterTree(p) => p.process(self).await.map(|p| GokoResponse::RegisterTree(p)),
+            GokoRequest::UnregisterTree(p) => p.process(self).await.map(|p| GokoResponse::UnregisterTree(p)),
+            GokoRequest::ReloadTree(p) => p.process(self).await.map(|p| GokoResponse::ReloadTree(p)),
+            GokoRequest::ListTrees(p) => p.process(self).await.map(|p| GokoResponse::ListTrees(p)),
             GokoRequest::Unknown(response_string, status) => {
                 Ok(GokoResponse::Unknown(response_string, status))
             },
             GokoRequest::Tracking(p) => {
+                // The paths of a whole batch take a while, so they're computed before the request reaches the tracker
+                let request = match p.request {
+                    TrackingRequestChoice::TrackBulk(req) => TrackingRequestChoice::TrackBulkPaths(req.paths(self.tree.clone()).await?),
+                    request => request,
+                };
+                let p = TrackingRequest {
+                    tracker_name: p.tracker_name,
+                    request,
+                };
                 if let Some(tracker_name) = &p.tracker_name {
                     if let TrackingRequestChoice::AddTracker(_) = p.request {
-                        self.trackers.write().await.entry(tracker_name.clone()).or_insert_with(|| TrackerWorker::operator(self.tree.clone()));
+                        let mut trackers = self.trackers.write().await;
+                        if !trackers.contains_key(tracker_name) {
+                            let tracker = self.named_tracker(tracker_name).await?;
+                            trackers.insert(tracker_name.clone(), tracker);
+                        }
                     }
                     match self.trackers.read().await.get(tracker_name) {
                         Some(t) => t.message(p).await.map(|r| GokoResponse::Tracking(r)),
@@ -22,7 +22,7 @@ pub struct ParametersResponse {
     pub use_singletons: bool,
     /// The partition type of the tree
     pub partition_type: PartitionType,
-    /// This should be replaced by a logging solution
+    /// Above 1 a progress bar is drawn while building. Everything else is logged through `tracing`
     pub verbosity: u32,
     /// The seed to use for deterministic trees. This is xor-ed with the point index to create a seed for `rand::rngs::SmallRng`.
     pub rng_seed: Option<u64>,
@@ -4,6 +4,7 @@ use serde::{Deserialize, Serialize};
 use std::ops::Deref;
 
 use goko::errors::GokoError;
+use goko::NodeAddress;
 use crate::core::*;
 use super::NodeDistance;
 
@@ -25,21 +26,69 @@ impl<T> PathRequest<T> {
         D: PointCloud, 
         T: Deref<Target = D::Point> + Send + Sync,
     {
-        let knn = reader.tree.path(&self.point)?;
-        let pc = &reader.tree.parameters().point_cloud;
-        
-        let resp: Result<Vec<NodeDistance<D::LabelSummary>>, GokoError> = knn
-            .iter()
-            .map(|(distance, (layer, pi))| {
-                let label_summary = reader.tree.get_node_label_summary((*layer, *pi)).map(|s| (*s).clone());
-                Ok(NodeDistance {
-                    name: pc.name(*pi)?,
-                    layer: *layer,
-                    distance: *distance,
-                    label_summary,
-                })
-            })
-            .collect();
-        Ok(PathResponse { path: resp? })
+        let path = reader.tree.path(&self.point)?;
+        path_response(reader, &path)
+    }
+}
+
+/// The path of a point in the tree, by the point's name. This is the path the point was inserted along.
+///
+/// Response: [`PathResponse`]
+#[derive(Deserialize, Serialize)]
+pub struct KnownPathByNameRequest {
+    pub name: String,
+}
+
+impl KnownPathByNameRequest {
+    pub fn process<D, T>(self, reader: &mut CoreReader<D, T>) -> Result<PathResponse<D::LabelSummary>, GokoError>
+    where
+        D: PointCloud,
+        T: Deref<Target = D::Point> + Send + Sync,
+    {
+        let index = reader.tree.parameters().point_cloud.index(&self.name)?;
+        let path = reader.tree.known_path(index)?;
+        path_response(reader, &path)
+    }
+}
+
+/// The path of a point in the tree, by the point's external id.
+///
+/// Response: [`PathResponse`]
+#[derive(Deserialize, Serialize)]
+pub struct KnownPathByIdRequest {
+    pub id: ExternalId,
+}
+
+impl KnownPathByIdRequest {
+    pub fn process<D, T>(self, reader: &mut CoreReader<D, T>) -> Result<PathResponse<D::LabelSummary>, GokoError>
+    where
+        D: PointCloud,
+        T: Deref<Target = D::Point> + Send + Sync,
+    {
+        let index = reader.tree.parameters().point_cloud.external_index(&self.id)?;
+        let path = reader.tree.known_path(index)?;
+        path_response(reader, &path)
     }
+}
+
+fn path_response<D, T>(reader: &CoreReader<D, T>, path: &[(f32, NodeAddress)]) -> Result<PathResponse<D::LabelSummary>, GokoError>
+where
+    D: PointCloud,
+    T: Deref<Target = D::Point> + Send + Sync,
+{
+    let pc = &reader.tree.parameters().point_cloud;
+    let resp: Result<Vec<NodeDistance<D::LabelSummary>>, GokoError> = path
+        .iter()
+        .map(|(distance, (layer, pi))| {
+            let label_summary = reader.tree.get_node_label_summary((*layer, *pi)).map(|s| (*s).clone());
+            Ok(NodeDistance {
+                name: pc.name(*pi)?,
+                layer: *layer,
+                distance: *distance,
+                label_summary,
+                external_id: pc.external_id(*pi)?,
+            })
+        })
+        .collect();
+    Ok(PathResponse { path: resp? })
 }
\ No newline at end of file
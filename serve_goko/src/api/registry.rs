@@ -0,0 +1,244 @@
+use pointcloud::*;
+use crate::core::*;
+use crate::errors::InternalServiceError;
+use goko::errors::GokoError;
+use goko::CoverTreeWriter;
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use super::admin::{replay_trackers, tracker_snapshots};
+use super::RestoreResponse;
+
+/// Loads a tree with the server's tree loader and serves it under `/tree/NAME/`. Send a `POST` request to
+/// `/admin/trees/register?name=NAME&path=PATH` with the path percent encoded, it's decoded and handed to the loader.
+/// See [`crate::core::CoreWriter::set_tree_loader`].
+///
+/// Anyone who can reach this route can have the server open any path it can read, the same goes for
+/// `/admin/trees/reload`. Keep the admin routes away from untrusted callers, or give the server a loader that
+/// refuses paths outside of the directory of its trees.
+#[derive(Deserialize, Serialize)]
+pub struct RegisterTreeRequest {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// Request: [`RegisterTreeRequest`]
+#[derive(Deserialize, Serialize)]
+pub struct RegisterTreeResponse {
+    /// False if there's already a tree with the name, it's left alone
+    pub success: bool,
+    /// The number of nodes in the tree that was loaded
+    pub node_count: usize,
+}
+
+/// Stops serving a registered tree, its trackers are dropped. Send a `POST` request to
+/// `/admin/trees/unregister?name=NAME`.
+#[derive(Deserialize, Serialize)]
+pub struct UnregisterTreeRequest {
+    pub name: String,
+}
+
+/// Request: [`UnregisterTreeRequest`]
+#[derive(Deserialize, Serialize)]
+pub struct UnregisterTreeResponse {
+    /// False if there was no tree with the name
+    pub success: bool,
+}
+
+/// Replaces a tree with a new one from the tree loader, without dropping a connection. Send a `POST` request to
+/// `/admin/trees/reload?path=PATH` for the main tree, add `name=NAME` for a registered tree. With
+/// `migrate_trackers=true` the windows of the trackers are replayed onto the new tree, paths through nodes that
+/// aren't in it are dropped. Otherwise the trackers start over with the same windows.
+///
+/// Requests keep going to the old tree while the new one loads and its trackers are replayed. The trackers are read
+/// once they're drained, then the new tree and its trackers are swapped in behind the same handle and the old tree is
+/// dropped. Tracking that reaches the old tree after its trackers were read is lost, and of two reloads of the same
+/// tree at once the last one to finish is served.
+#[derive(Deserialize, Serialize)]
+pub struct ReloadTreeRequest {
+    pub name: Option<String>,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub migrate_trackers: bool,
+}
+
+/// Request: [`ReloadTreeRequest`]
+#[derive(Deserialize, Serialize)]
+pub struct ReloadTreeResponse {
+    /// False if there's no registered tree with the name, nothing was loaded
+    pub success: bool,
+    /// The number of nodes in the new tree
+    pub node_count: usize,
+    /// What happened to the trackers, the paths are only counted when they're migrated
+    pub trackers: RestoreResponse,
+}
+
+/// Lists the registered trees, send a `GET` request to `/admin/trees`.
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct ListTreesRequest;
+
+/// A registered tree.
+#[derive(Deserialize, Serialize)]
+pub struct RegisteredTree {
+    pub name: String,
+    pub node_count: usize,
+    pub point_count: usize,
+    /// The current epoch of the tree
+    pub epoch: u64,
+}
+
+/// Request: [`ListTreesRequest`]
+#[derive(Deserialize, Serialize)]
+pub struct ListTreesResponse {
+    /// Sorted by name
+    pub trees: Vec<RegisteredTree>,
+}
+
+/// Runs the reader's tree loader on the blocking pool, the other trees are served in the meantime.
+async fn load_tree<D, T>(reader: &CoreReader<D, T>, path: PathBuf) -> Result<CoverTreeWriter<D>, InternalServiceError>
+where
+    D: PointCloud,
+    T: Send + 'static,
+{
+    let loader = match &reader.tree_loader {
+        Some(loader) => Arc::clone(loader),
+        None => {
+            return Err(GokoError::from(io::Error::new(io::ErrorKind::Other, "The server has no tree loader")).into());
+        }
+    };
+    let writer = tokio::task::spawn_blocking(move || loader(&path))
+        .await
+        .map_err(|e| GokoError::from(io::Error::new(io::ErrorKind::Other, e.to_string())))??;
+    Ok(writer)
+}
+
+impl RegisterTreeRequest {
+    pub async fn process<D, T>(self, reader: &CoreReader<D, T>) -> Result<RegisterTreeResponse, InternalServiceError>
+    where
+        D: PointCloud,
+        T: Deref<Target = D::Point> + Send + Sync + 'static,
+    {
+        if reader.tenants.read().await.contains_key(&self.name) {
+            return Ok(RegisterTreeResponse {
+                success: false,
+                node_count: 0,
+            });
+        }
+        let writer = load_tree(reader, self.path).await?;
+        let node_count = writer.reader().node_count();
+        let success = reader.register_tenant(self.name, writer).await;
+        Ok(RegisterTreeResponse {
+            success,
+            node_count: if success { node_count } else { 0 },
+        })
+    }
+}
+
+impl UnregisterTreeRequest {
+    pub async fn process<D, T>(self, reader: &CoreReader<D, T>) -> Result<UnregisterTreeResponse, InternalServiceError>
+    where
+        D: PointCloud,
+        T: Send + 'static,
+    {
+        Ok(UnregisterTreeResponse {
+            success: reader.tenants.write().await.remove(&self.name).is_some(),
+        })
+    }
+}
+
+impl ListTreesRequest {
+    pub async fn process<D, T>(self, reader: &CoreReader<D, T>) -> Result<ListTreesResponse, InternalServiceError>
+    where
+        D: PointCloud,
+        T: Send + 'static,
+    {
+        let mut trees: Vec<RegisteredTree> = reader
+            .tenants
+            .read()
+            .await
+            .iter()
+            .map(|(name, tenant)| {
+                let tree = tenant.reader().tree;
+                RegisteredTree {
+                    name: name.clone(),
+                    node_count: tree.node_count(),
+                    point_count: tree.parameters().point_cloud.len(),
+                    epoch: tree.epoch(),
+                }
+            })
+            .collect();
+        trees.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(ListTreesResponse { trees })
+    }
+}
+
+/// The new tree with the trackers of the old one, their paths are only replayed when they're migrated. Nothing is
+/// locked while the trackers are read and replayed.
+async fn swap_trackers<D, T>(old_reader: &CoreReader<D, T>, writer: CoverTreeWriter<D>, migrate_trackers: bool) -> Result<(ServedTree<D, T>, RestoreResponse), InternalServiceError>
+where
+    D: PointCloud,
+    T: Deref<Target = D::Point> + Send + Sync + 'static,
+{
+    let served = ServedTree::new(writer);
+    let mut snapshots = tracker_snapshots(old_reader, true).await?;
+    if !migrate_trackers {
+        snapshots.iter_mut().flat_map(|named| named.trackers.iter_mut()).for_each(|t| {
+            t.paths.clear();
+            t.state = None;
+        });
+    }
+    let new_reader = old_reader.with_served(&served);
+    let trackers = replay_trackers(&new_reader, snapshots, true).await?;
+    new_reader.send_last_baseline().await?;
+    Ok((served, trackers))
+}
+
+impl ReloadTreeRequest {
+    /// The old tree is looked up once the new one is loaded, and a registered tree is only swapped if it's still
+    /// registered when its trackers are replayed.
+    pub async fn process<D, T>(self, reader: &CoreReader<D, T>) -> Result<ReloadTreeResponse, InternalServiceError>
+    where
+        D: PointCloud,
+        T: Deref<Target = D::Point> + Send + Sync + 'static,
+    {
+        let missing = ReloadTreeResponse {
+            success: false,
+            node_count: 0,
+            trackers: RestoreResponse::default(),
+        };
+        if let Some(name) = &self.name {
+            if !reader.tenants.read().await.contains_key(name) {
+                return Ok(missing);
+            }
+        }
+        let writer = load_tree(reader, self.path).await?;
+        let node_count = writer.reader().node_count();
+        let old_reader = match &self.name {
+            Some(name) => match reader.tenant(name).await {
+                Some(tenant) => tenant,
+                None => return Ok(missing),
+            },
+            None => reader.with_served(&reader.served.lock().unwrap()),
+        };
+        let (served, trackers) = swap_trackers(&old_reader, writer, self.migrate_trackers).await?;
+        match &self.name {
+            Some(name) => {
+                let tenants = reader.tenants.read().await;
+                match tenants.get(name) {
+                    Some(tenant) if Arc::ptr_eq(&tenant.served, &old_reader.served) => old_reader.swap_served(served),
+                    _ => return Ok(missing),
+                }
+            }
+            None => old_reader.swap_served(served),
+        }
+        Ok(ReloadTreeResponse {
+            success: true,
+            node_count,
+            trackers,
+        })
+    }
+}
@@ -1,18 +1,34 @@
 use pointcloud::*;
 use goko::{NodeAddress, CoverTreeReader};
-use goko::plugins::discrete::tracker::BayesCategoricalTracker;
+use goko::query_interface::BulkInterface;
+use goko::plugins::discrete::baseline::{KLDivergenceBaseline, KLDivergenceScores};
+use goko::plugins::discrete::tracker::{BayesCategoricalTracker, CoverageWeighting, GoodnessOfFitStats, TrackerState};
+use goko::plugins::discrete::visitors::{NodeVisitors, DEFAULT_VISITOR_PRECISION};
 use crate::core::internal_service::*;
 use goko::errors::GokoError;
+use std::io;
 use std::ops::Deref;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::core::{CoreReader, TrackerGauges, WindowGauge};
+use crate::errors::InternalServiceError;
+use futures::future::join_all;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use super::{TrackingRequest, TrackingRequestChoice, TrackingResponse};
 
 #[derive(Deserialize, Serialize)]
 pub struct TrackPointRequest<T> {
     pub point: T,
+    /// Identifies the query for the unique visitor counts, see [`AddTrackerRequest::unique_visitors`]
+    #[serde(default)]
+    pub query_id: Option<u64>,
+    /// The segment the point belongs to, a label or metadata value. The point is also tracked by the segment's
+    /// own trackers, see [`SegmentStatsRequest`]
+    #[serde(default)]
+    pub segment: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -25,18 +41,135 @@ pub struct TrackPathResponse {
     pub success: bool,
 }
 
+/// The points of a bulk request, either the points themselves or the indexes of points that are in the tree.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+pub enum BulkPoints<T> {
+    Indexes(Vec<usize>),
+    Points(Vec<T>),
+}
+
+impl<T> BulkPoints<T> {
+    pub fn len(&self) -> usize {
+        match self {
+            BulkPoints::Indexes(indexes) => indexes.len(),
+            BulkPoints::Points(points) => points.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TrackBulkRequest<T> {
+    pub points: BulkPoints<T>,
+    /// The segment all the points belong to, see [`TrackPointRequest::segment`]
+    #[serde(default)]
+    pub segment: Option<String>,
+}
+
+impl<T> TrackBulkRequest<T> {
+    fn compute_paths<D>(self, tree: CoverTreeReader<D>) -> TrackBulkPathsRequest
+    where
+        D: PointCloud,
+        T: Deref<Target = D::Point> + Send + Sync,
+    {
+        let start = Instant::now();
+        let bulk = BulkInterface::new(tree);
+        let paths = match &self.points {
+            BulkPoints::Indexes(indexes) => bulk.known_path(indexes),
+            BulkPoints::Points(points) => bulk.path(points),
+        };
+        TrackBulkPathsRequest {
+            paths: paths.into_iter().map(|path| path.ok()).collect(),
+            segment: self.segment,
+            path_micros: start.elapsed().as_micros() as u64,
+        }
+    }
+
+    /// Computes the paths on tokio's blocking threads, a large batch would hold up the worker it ran on.
+    pub(crate) async fn paths<D>(self, tree: CoverTreeReader<D>) -> Result<TrackBulkPathsRequest, GokoError>
+    where
+        D: PointCloud,
+        T: Deref<Target = D::Point> + Send + Sync + 'static,
+    {
+        tokio::task::spawn_blocking(move || self.compute_paths(tree))
+            .await
+            .map_err(|e| GokoError::from(io::Error::new(io::ErrorKind::Other, e.to_string())))
+    }
+}
+
+/// The paths of a [`TrackBulkRequest`], in the order the points were sent. The points whose path couldn't be
+/// computed are `None`.
+#[derive(Deserialize, Serialize)]
+pub struct TrackBulkPathsRequest {
+    pub paths: Vec<Option<Vec<(f32, NodeAddress)>>>,
+    /// The segment all the points belong to, see [`TrackPointRequest::segment`]
+    #[serde(default)]
+    pub segment: Option<String>,
+    /// Time spent computing the paths, in microseconds
+    pub path_micros: u64,
+}
+
+/// What a bulk request did. Points whose path couldn't be computed are skipped and counted in `failed`, the rest are
+/// applied in the order they were sent.
+#[derive(Deserialize, Serialize)]
+pub struct TrackBulkResponse {
+    pub success: bool,
+    pub tracked: usize,
+    pub failed: usize,
+    /// Time spent computing the paths, in microseconds
+    pub path_micros: u64,
+    /// Time spent adding the paths to the trackers, in microseconds
+    pub apply_micros: u64,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct AddTrackerRequest {
     pub window_size: usize,
+    /// Estimate the distinct queries that go through each node too, from the query ids of the tracked points.
+    /// These counts aren't windowed.
+    #[serde(default)]
+    pub unique_visitors: bool,
 }
 #[derive(Deserialize, Serialize)]
 pub struct AddTrackerResponse {
     pub success: bool,
 }
 
+/// Changes the window of a tracker. If the named tracker only has one window you can leave out `old_window_size`.
+#[derive(Deserialize, Serialize)]
+pub struct ResizeTrackerRequest {
+    pub window_size: usize,
+    #[serde(default)]
+    pub old_window_size: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ResizeTrackerResponse {
+    pub success: bool,
+}
+
+/// Clears the evidence of a tracker. Leave out the window size to clear all of them.
+#[derive(Deserialize, Serialize)]
+pub struct ResetTrackerRequest {
+    #[serde(default)]
+    pub window_size: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ResetTrackerResponse {
+    pub success: bool,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct CurrentStatsRequest {
     pub window_size: usize,
+    /// How the weighted stats weight each node, defaults to the coverage fraction
+    #[serde(default)]
+    pub weighting: CoverageWeighting,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -47,13 +180,271 @@ pub struct CurrentStatsResponse {
     pub nz_count: u64,
     pub moment1_nz: f64,
     pub moment2_nz: f64,
+    pub weighted_moment1_nz: f64,
+    pub weighted_moment2_nz: f64,
+    pub weight_nz: f64,
     pub sequence_len: usize,
+    /// The z-scores and percentiles of the stats against the tracker's baseline, only there if a baseline was
+    /// attached with [`crate::core::CoreWriter::set_baseline`] or trained by
+    /// [`crate::core::CoreWriter::schedule_baselines`]
+    #[serde(default)]
+    pub scores: Option<KLDivergenceScores>,
+    /// The chi-squared and G-test of the window against the tree, with their p-values. Not there while the
+    /// tree's priors are still being attached
+    #[serde(default)]
+    pub goodness_of_fit: Option<GoodnessOfFitStats>,
+    /// The visits to each node with the estimated distinct query ids among them, only there if the tracker was
+    /// added with [`AddTrackerRequest::unique_visitors`]
+    #[serde(default)]
+    pub visitors: Option<Vec<NodeVisitors>>,
+}
+
+/// The most segments a tracker keeps. Points in a segment past this are still tracked, but not by segment.
+pub const MAX_SEGMENTS: usize = 256;
+
+/// Asks for the stats of the points that were tracked with a segment. Each segment has its own trackers with the
+/// same windows as the tracker, so a window of 100 has the stats of the last 100 points in that segment. Leave out
+/// `segment` for all of them.
+#[derive(Deserialize, Serialize)]
+pub struct SegmentStatsRequest {
+    pub window_size: usize,
+    #[serde(default)]
+    pub segment: Option<String>,
+    #[serde(default)]
+    pub weighting: CoverageWeighting,
+}
+
+/// The stats of each segment that has been tracked, keyed by the segment.
+#[derive(Deserialize, Serialize)]
+pub struct SegmentStatsResponse {
+    pub segments: BTreeMap<String, CurrentStatsResponse>,
+}
+
+/// Asks for the nodes that drifted most. If the named tracker only has one window you can leave out `window_size`.
+#[derive(Deserialize, Serialize)]
+pub struct TopDriftRequest {
+    #[serde(default)]
+    pub window_size: Option<usize>,
+    pub n: usize,
+}
+
+/// A node that drifted, with enough about it to show on a dashboard.
+#[derive(Deserialize, Serialize)]
+pub struct DriftingNode {
+    pub address: NodeAddress,
+    /// The scale of the node's layer, `scale_base^scale_index`
+    pub scale: f32,
+    /// The KL divergence between the node's prior and posterior
+    pub kl_div: f64,
+    /// The number of points the node covers
+    pub coverage: usize,
+    /// The summary of the labels the node covers, if the point cloud has labels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label_summary: Option<serde_json::Value>,
+}
+
+/// The nodes are sorted by divergence, largest first. This is empty while the priors are being attached.
+#[derive(Deserialize, Serialize)]
+pub struct TopDriftResponse {
+    pub nodes: Vec<DriftingNode>,
+}
+
+/// Asks for the divergence of the nodes added up at their ancestors at `scale_index`, a coarse view of where the
+/// drift is. If the named tracker only has one window you can leave out `window_size`.
+#[derive(Deserialize, Serialize)]
+pub struct DriftRollupRequest {
+    #[serde(default)]
+    pub window_size: Option<usize>,
+    pub scale_index: i32,
+}
+
+/// The ancestors are sorted by their summed divergence, largest first. This is empty while the priors are being
+/// attached.
+#[derive(Deserialize, Serialize)]
+pub struct DriftRollupResponse {
+    pub scale_index: i32,
+    pub nodes: Vec<DriftingNode>,
+}
+
+/// Asks a tracker worker for the stats of all of its windows at once.
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct WindowStatsRequest {
+    #[serde(default)]
+    pub weighting: CoverageWeighting,
+}
+
+/// The stats of one window of a tracker.
+#[derive(Deserialize, Serialize)]
+pub struct WindowStats {
+    pub window_size: usize,
+    pub stats: CurrentStatsResponse,
+}
+
+/// The windows are sorted by size.
+#[derive(Deserialize, Serialize)]
+pub struct WindowStatsResponse {
+    pub windows: Vec<WindowStats>,
+}
+
+/// Send a `GET` request to `/track/snapshot` for this, `weighting=ln_fraction` works like it does for the stats.
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct StatsSnapshotRequest {
+    #[serde(default)]
+    pub weighting: CoverageWeighting,
+}
+
+/// The stats of every window of every tracker, captured at one logical point.
+///
+/// Request: [`StatsSnapshotRequest`]
+#[derive(Deserialize, Serialize)]
+pub struct StatsSnapshotResponse {
+    /// The windows of the default tracker
+    pub default_tracker: Vec<WindowStats>,
+    /// The windows of the named trackers, keyed by the tracker name
+    pub trackers: BTreeMap<String, Vec<WindowStats>>,
+}
+
+impl StatsSnapshotRequest {
+    /// The stats requests are queued on every tracker worker while holding the write lock on the named trackers.
+    /// Tracking requests for a named tracker hold the read lock until they're answered, so none of them can land
+    /// between two of our requests. Each worker handles its queue in order, so every tracking request sent before
+    /// the snapshot is in it and none sent after are.
+    pub async fn process<D, T>(self, reader: &CoreReader<D, T>) -> Result<StatsSnapshotResponse, InternalServiceError>
+    where
+        D: PointCloud,
+        T: Send + 'static,
+    {
+        let window_stats_request = |tracker_name: Option<String>| TrackingRequest {
+            tracker_name,
+            request: TrackingRequestChoice::WindowStats(WindowStatsRequest {
+                weighting: self.weighting,
+            }),
+        };
+        let (default_response, named_responses) = {
+            let trackers = reader.trackers.write().await;
+            let default_response = reader.main_tracker.message(window_stats_request(None));
+            let named_responses: Vec<_> = trackers
+                .iter()
+                .map(|(tracker_name, tracker)| {
+                    (tracker_name.clone(), tracker.message(window_stats_request(Some(tracker_name.clone()))))
+                })
+                .collect();
+            (default_response, named_responses)
+        };
+
+        let default_tracker = match default_response.await? {
+            TrackingResponse::WindowStats(s) => s.windows,
+            _ => Vec::new(),
+        };
+        let (names, responses): (Vec<String>, Vec<_>) = named_responses.into_iter().unzip();
+        let mut trackers = BTreeMap::new();
+        for (tracker_name, response) in names.into_iter().zip(join_all(responses).await) {
+            if let TrackingResponse::WindowStats(s) = response? {
+                trackers.insert(tracker_name, s.windows);
+            }
+        }
+        Ok(StatsSnapshotResponse {
+            default_tracker,
+            trackers,
+        })
+    }
 }
 
+/// Asks a tracker worker for the windows of all of its trackers. Used by the flush.
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct SnapshotRequest;
+
+/// The state of a single tracker, enough to replay it onto a fresh tracker.
+#[derive(Deserialize, Serialize)]
+pub struct TrackerSnapshot {
+    pub window_size: usize,
+    pub sequence_len: usize,
+    pub paths: Vec<Vec<(f32, NodeAddress)>>,
+    /// The evidence, counters and decay that go with the paths. Without it, as in the flushes of older servers, the
+    /// paths are replayed and a tracker with an unlimited window comes back empty.
+    #[serde(default)]
+    pub state: Option<TrackerState>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SnapshotResponse {
+    pub trackers: Vec<TrackerSnapshot>,
+}
+
+/// Replays a snapshot onto a fresh tracker with the snapshot's window, replacing the tracker with that window if
+/// there is one. If every node the snapshot's state is on is in the tree the state is restored as it was. Otherwise
+/// the paths are replayed with the snapshot's decay, and the paths through nodes that aren't in the tree are skipped,
+/// they come from an older tree. Used by [`crate::api::RestoreRequest`].
+#[derive(Deserialize, Serialize)]
+pub struct RestoreTrackerRequest {
+    pub snapshot: TrackerSnapshot,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RestoreTrackerResponse {
+    /// The number of paths replayed onto the tracker
+    pub restored: usize,
+    /// The number of paths that were skipped
+    pub skipped: usize,
+}
+
+/// Attaches a baseline to every window of a tracker, including the ones added later. Their stats are then scored
+/// against it, see [`CurrentStatsResponse::scores`].
+#[derive(Deserialize, Serialize)]
+pub struct SetBaselineRequest {
+    pub baseline: KLDivergenceBaseline,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SetBaselineResponse {
+    pub success: bool,
+}
+
+fn current_stats<D: PointCloud>(tracker: &BayesCategoricalTracker<D>, weighting: CoverageWeighting) -> CurrentStatsResponse {
+    let stats = tracker.kl_div_stats_weighted(weighting);
+    CurrentStatsResponse {
+        kl_div: tracker.kl_div(),
+        max: stats.max,
+        min: stats.min,
+        nz_count: stats.nz_count,
+        moment1_nz: stats.moment1_nz,
+        moment2_nz: stats.moment2_nz,
+        weighted_moment1_nz: stats.weighted_moment1_nz,
+        weighted_moment2_nz: stats.weighted_moment2_nz,
+        weight_nz: stats.weight_nz,
+        sequence_len: stats.sequence_len,
+        scores: tracker.baseline().map(|baseline| baseline.score(&stats)),
+        goodness_of_fit: tracker.try_goodness_of_fit(),
+        visitors: tracker.node_visitors(),
+    }
+}
+
+fn drifting_node<D: PointCloud>(reader: &CoverTreeReader<D>, kl_div: f64, address: NodeAddress) -> Result<DriftingNode, GokoError> {
+    let label_summary = match reader.get_node_label_summary(address) {
+        Some(summary) => Some(serde_json::to_value(summary.as_ref()).map_err(|e| GokoError::from(io::Error::from(e)))?),
+        None => None,
+    };
+    Ok(DriftingNode {
+        address,
+        scale: reader.scale(address.0),
+        kl_div,
+        coverage: reader.get_node_and(address, |n| n.coverage_count()).unwrap_or(0),
+        label_summary,
+    })
+}
+
+/// The most often a tracker worker that's tracking points publishes its windows to the [`TrackerGauges`].
+pub const GAUGE_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct TrackerWorker<D: PointCloud> {
     reader: CoverTreeReader<D>,
     trackers: HashMap<usize, BayesCategoricalTracker<D>>,
+    segments: HashMap<String, HashMap<usize, BayesCategoricalTracker<D>>>,
+    baseline: Option<Arc<KLDivergenceBaseline>>,
+    tracker_name: Option<String>,
+    gauges: Arc<TrackerGauges>,
+    gauges_published: Instant,
+    gauges_stale: bool,
 }
 
 impl<D: PointCloud> TrackerWorker<D> {
@@ -61,26 +452,106 @@ impl<D: PointCloud> TrackerWorker<D> {
         TrackerWorker {
             reader,
             trackers: HashMap::new(),
+            segments: HashMap::new(),
+            baseline: None,
+            tracker_name: None,
+            gauges: Arc::default(),
+            gauges_published: Instant::now(),
+            gauges_stale: false,
         }
     }
 
-    pub(crate) fn operator<T: Deref<Target = D::Point> + Send + Sync + 'static>(reader: CoverTreeReader<D>) -> InternalServiceOperator<TrackingRequest<T>, TrackingResponse> {
-        let worker = TrackerWorker {
-            reader,
-            trackers: HashMap::new(),
-        };
-        InternalServiceOperator::new(worker)
+    /// Publishes the stats of every window to the gauges `/metrics` reads.
+    fn publish_gauges(&mut self) {
+        let mut windows: Vec<WindowGauge> = self.trackers.iter().map(|(window_size, tracker)| {
+            let stats = tracker.kl_div_stats();
+            WindowGauge {
+                window_size: *window_size,
+                kl_div: tracker.kl_div(),
+                max_kl_div: stats.max,
+                sequence_len: stats.sequence_len,
+            }
+        }).collect();
+        windows.sort_by_key(|w| w.window_size);
+        self.gauges.publish(self.tracker_name.as_deref(), windows);
+        self.gauges_published = Instant::now();
+        self.gauges_stale = false;
     }
-}
 
-impl<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync> InternalService<TrackingRequest<T>, TrackingResponse> for TrackerWorker<D> {
-    fn process(&mut self, request: TrackingRequest<T>) -> Result<TrackingResponse, GokoError> {
+    fn new_tracker(&self, window_size: usize) -> BayesCategoricalTracker<D> {
+        let mut tracker = BayesCategoricalTracker::new(window_size, self.reader.clone());
+        if let Some(baseline) = &self.baseline {
+            tracker.set_baseline(Arc::clone(baseline));
+        }
+        tracker
+    }
+
+    /// Adds the path to the segment's trackers, making them if this is the segment's first point.
+    fn track_segment(&mut self, segment: &str, path: &[(f32, NodeAddress)]) {
+        if !self.segments.contains_key(segment) {
+            if self.segments.len() >= MAX_SEGMENTS {
+                return;
+            }
+            let trackers = self.trackers.keys().map(|window_size| (*window_size, self.new_tracker(*window_size))).collect();
+            self.segments.insert(segment.to_string(), trackers);
+        }
+        if let Some(trackers) = self.segments.get_mut(segment) {
+            for tracker in trackers.values_mut() {
+                tracker.add_path(path.to_vec());
+            }
+        }
+    }
+
+    fn track_bulk_paths(&mut self, req: TrackBulkPathsRequest) -> TrackingResponse {
+        let start = Instant::now();
+        let mut tracked = 0;
+        let mut failed = 0;
+        for path in req.paths {
+            match path {
+                Some(path) => {
+                    for tracker in self.trackers.values_mut() {
+                        tracker.add_path(path.clone());
+                    }
+                    if let Some(segment) = &req.segment {
+                        self.track_segment(segment, &path);
+                    }
+                    tracked += 1;
+                }
+                None => failed += 1,
+            }
+        }
+        TrackingResponse::TrackBulk(TrackBulkResponse {
+            success: !self.trackers.is_empty(),
+            tracked,
+            failed,
+            path_micros: req.path_micros,
+            apply_micros: start.elapsed().as_micros() as u64,
+        })
+    }
+
+    /// A worker for the tracker with this name, `None` for the default tracker, that publishes its windows to the
+    /// gauges.
+    pub(crate) fn operator<T: Deref<Target = D::Point> + Send + Sync + 'static>(reader: CoverTreeReader<D>, tracker_name: Option<String>, gauges: Arc<TrackerGauges>) -> InternalServiceOperator<TrackingRequest<T>, TrackingResponse> {
+        InternalServiceOperator::new(TrackerWorker {
+            tracker_name,
+            gauges,
+            ..TrackerWorker::new(reader)
+        })
+    }
+
+    fn respond<T: Deref<Target = D::Point> + Send + Sync>(&mut self, request: TrackingRequest<T>) -> Result<TrackingResponse, GokoError> {
         use TrackingRequestChoice::*;
         match request.request {
             TrackPoint(req) => {
                 let path = self.reader.path(&req.point)?;
                 for tracker in self.trackers.values_mut() {
-                    tracker.add_path(path.clone());
+                    match req.query_id {
+                        Some(query_id) => tracker.add_path_with_id(path.clone(), query_id),
+                        None => tracker.add_path(path.clone()),
+                    }
+                }
+                if let Some(segment) = &req.segment {
+                    self.track_segment(segment, &path);
                 }
 
                 Ok(TrackingResponse::TrackPath(TrackPathResponse {
@@ -95,35 +566,232 @@ impl<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync> InternalService<T
                     success: true,
                 }))
             }
+            TrackBulk(req) => {
+                let req = req.compute_paths(self.reader.clone());
+                Ok(self.track_bulk_paths(req))
+            }
+            TrackBulkPaths(req) => Ok(self.track_bulk_paths(req)),
             AddTracker(req) => {
                 if self.trackers.contains_key(&req.window_size) {
                     Ok(TrackingResponse::AddTracker(AddTrackerResponse {
                         success: false,
                     }))
                 } else {
-                    self.trackers.insert(req.window_size, BayesCategoricalTracker::new(req.window_size, self.reader.clone()));
+                    let mut tracker = self.new_tracker(req.window_size);
+                    if req.unique_visitors {
+                        tracker.enable_unique_visitors(DEFAULT_VISITOR_PRECISION);
+                    }
+                    self.trackers.insert(req.window_size, tracker);
+                    let segment_names: Vec<String> = self.segments.keys().cloned().collect();
+                    for segment in segment_names {
+                        let tracker = self.new_tracker(req.window_size);
+                        if let Some(trackers) = self.segments.get_mut(&segment) {
+                            trackers.insert(req.window_size, tracker);
+                        }
+                    }
                     Ok(TrackingResponse::AddTracker(AddTrackerResponse {
                         success: true,
                     }))
                 }
             }
+            ResizeTracker(req) => {
+                let old_window_size = match req.old_window_size {
+                    Some(old_window_size) => Some(old_window_size),
+                    None if self.trackers.len() == 1 => self.trackers.keys().next().cloned(),
+                    None => None,
+                };
+                match old_window_size {
+                    Some(old_window_size) if self.trackers.contains_key(&old_window_size) => {
+                        if old_window_size != req.window_size && self.trackers.contains_key(&req.window_size) {
+                            return Ok(TrackingResponse::ResizeTracker(ResizeTrackerResponse {
+                                success: false,
+                            }));
+                        }
+                        let mut tracker = self.trackers.remove(&old_window_size).unwrap();
+                        tracker.set_window_size(req.window_size);
+                        self.trackers.insert(req.window_size, tracker);
+                        for trackers in self.segments.values_mut() {
+                            if let Some(mut tracker) = trackers.remove(&old_window_size) {
+                                tracker.set_window_size(req.window_size);
+                                trackers.insert(req.window_size, tracker);
+                            }
+                        }
+                        Ok(TrackingResponse::ResizeTracker(ResizeTrackerResponse {
+                            success: true,
+                        }))
+                    }
+                    _ => Ok(TrackingResponse::Unknown(request.tracker_name.clone(), old_window_size)),
+                }
+            }
+            ResetTracker(req) => {
+                match req.window_size {
+                    Some(window_size) => match self.trackers.get_mut(&window_size) {
+                        Some(tracker) => {
+                            tracker.reset();
+                            for trackers in self.segments.values_mut() {
+                                trackers.get_mut(&window_size).into_iter().for_each(|tracker| tracker.reset());
+                            }
+                        }
+                        None => return Ok(TrackingResponse::Unknown(request.tracker_name.clone(), Some(window_size))),
+                    },
+                    None => {
+                        self.trackers.values_mut().for_each(|tracker| tracker.reset());
+                        self.segments.clear();
+                    }
+                }
+                Ok(TrackingResponse::ResetTracker(ResetTrackerResponse {
+                    success: true,
+                }))
+            }
             CurrentStats(req) => {
                 if let Some(tracker) = self.trackers.get(&req.window_size) {
-                    let stats = tracker.kl_div_stats();
-                    let kl_div = tracker.kl_div();
-                    Ok(TrackingResponse::CurrentStats(CurrentStatsResponse {
-                        kl_div,
-                        max: stats.max,
-                        min: stats.min,
-                        nz_count: stats.nz_count,
-                        moment1_nz: stats.moment1_nz,
-                        moment2_nz: stats.moment2_nz,
-                        sequence_len: stats.sequence_len,
-                    }))
+                    Ok(TrackingResponse::CurrentStats(current_stats(tracker, req.weighting)))
                 } else {
                     Ok(TrackingResponse::Unknown(request.tracker_name.clone(),Some(req.window_size)))
                 }
             }
+            TopDrift(req) => {
+                let window_size = match req.window_size {
+                    Some(window_size) => Some(window_size),
+                    None if self.trackers.len() == 1 => self.trackers.keys().next().cloned(),
+                    None => None,
+                };
+                match window_size.and_then(|window_size| self.trackers.get_mut(&window_size)) {
+                    Some(tracker) => {
+                        // Only the trackers someone asked about pay for keeping their divergences up to date
+                        tracker.enable_top_drift();
+                        let nodes = tracker
+                            .top_drift(req.n)
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|(kl_div, address)| drifting_node(&self.reader, kl_div, address))
+                            .collect::<Result<Vec<DriftingNode>, GokoError>>()?;
+                        Ok(TrackingResponse::TopDrift(TopDriftResponse { nodes }))
+                    }
+                    None => Ok(TrackingResponse::Unknown(request.tracker_name.clone(), window_size)),
+                }
+            }
+            DriftRollup(req) => {
+                let window_size = match req.window_size {
+                    Some(window_size) => Some(window_size),
+                    None if self.trackers.len() == 1 => self.trackers.keys().next().cloned(),
+                    None => None,
+                };
+                match window_size.and_then(|window_size| self.trackers.get(&window_size)) {
+                    Some(tracker) => {
+                        let nodes = tracker
+                            .rollup_kl(req.scale_index)
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|(kl_div, address)| drifting_node(&self.reader, kl_div, address))
+                            .collect::<Result<Vec<DriftingNode>, GokoError>>()?;
+                        Ok(TrackingResponse::DriftRollup(DriftRollupResponse {
+                            scale_index: req.scale_index,
+                            nodes,
+                        }))
+                    }
+                    None => Ok(TrackingResponse::Unknown(request.tracker_name.clone(), window_size)),
+                }
+            }
+            SegmentStats(req) => {
+                if !self.trackers.contains_key(&req.window_size) {
+                    return Ok(TrackingResponse::Unknown(request.tracker_name.clone(), Some(req.window_size)));
+                }
+                let segments = self.segments.iter().filter(|(segment, _)| {
+                    req.segment.as_ref().map_or(true, |s| s == *segment)
+                }).filter_map(|(segment, trackers)| {
+                    trackers.get(&req.window_size).map(|tracker| (segment.clone(), current_stats(tracker, req.weighting)))
+                }).collect();
+                Ok(TrackingResponse::SegmentStats(SegmentStatsResponse { segments }))
+            }
+            WindowStats(req) => {
+                let mut windows: Vec<WindowStats> = self.trackers.iter().map(|(window_size, tracker)| {
+                    WindowStats {
+                        window_size: *window_size,
+                        stats: current_stats(tracker, req.weighting),
+                    }
+                }).collect();
+                windows.sort_by_key(|w| w.window_size);
+                Ok(TrackingResponse::WindowStats(WindowStatsResponse { windows }))
+            }
+            Snapshot(_) => {
+                let trackers = self.trackers.values().map(|tracker| {
+                    TrackerSnapshot {
+                        window_size: tracker.window_size(),
+                        sequence_len: tracker.sequence_len(),
+                        paths: tracker.sequence_queue().iter().cloned().collect(),
+                        state: Some(tracker.state()),
+                    }
+                }).collect();
+                Ok(TrackingResponse::Snapshot(SnapshotResponse { trackers }))
+            }
+            RestoreTracker(req) => {
+                let snapshot = req.snapshot;
+                let mut tracker = self.new_tracker(snapshot.window_size);
+                let in_tree = |address: &NodeAddress| self.reader.get_node_and(*address, |_| ()).is_some();
+                let all_in_tree = snapshot.paths.iter().all(|path| !path.is_empty() && path.iter().all(|(_, address)| in_tree(address)));
+                let decay = snapshot.state.as_ref().and_then(|state| state.decay());
+                let state = snapshot.state.filter(|state| all_in_tree && state.addresses().all(|address| in_tree(&address)));
+                let exact = match state {
+                    Some(state) => tracker.restore_state(snapshot.paths.clone(), state),
+                    None => false,
+                };
+                let mut restored = 0;
+                let mut skipped = 0;
+                if exact {
+                    restored = snapshot.paths.len();
+                } else {
+                    if let Some(gamma) = decay {
+                        tracker.set_decay(gamma);
+                    }
+                    for path in snapshot.paths {
+                        if !path.is_empty() && path.iter().all(|(_, address)| in_tree(address)) {
+                            tracker.add_path(path);
+                            restored += 1;
+                        } else {
+                            skipped += 1;
+                        }
+                    }
+                }
+                self.trackers.insert(snapshot.window_size, tracker);
+                Ok(TrackingResponse::RestoreTracker(RestoreTrackerResponse {
+                    restored,
+                    skipped,
+                }))
+            }
+            SetBaseline(req) => {
+                let baseline = Arc::new(req.baseline);
+                for tracker in self.trackers.values_mut().chain(self.segments.values_mut().flat_map(|t| t.values_mut())) {
+                    tracker.set_baseline(Arc::clone(&baseline));
+                }
+                self.baseline = Some(baseline);
+                Ok(TrackingResponse::SetBaseline(SetBaselineResponse {
+                    success: true,
+                }))
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+impl<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync> InternalService<TrackingRequest<T>, TrackingResponse> for TrackerWorker<D> {
+    /// The windows are published right after they change shape or are snapshotted. Tracking only marks them stale,
+    /// they're published if the last time was [`GAUGE_INTERVAL`] ago or when the worker goes idle.
+    fn process(&mut self, request: TrackingRequest<T>) -> Result<TrackingResponse, GokoError> {
+        use TrackingRequestChoice::*;
+        let reshaped = matches!(request.request, AddTracker(_) | ResizeTracker(_) | ResetTracker(_) | RestoreTracker(_) | WindowStats(_));
+        let tracked = matches!(request.request, TrackPoint(_) | TrackPath(_) | TrackBulk(_) | TrackBulkPaths(_));
+        let response = self.respond(request);
+        if reshaped || (tracked && self.gauges_published.elapsed() >= GAUGE_INTERVAL) {
+            self.publish_gauges();
+        } else if tracked {
+            self.gauges_stale = true;
+        }
+        response
+    }
+
+    fn idle(&mut self) {
+        if self.gauges_stale {
+            self.publish_gauges();
+        }
+    }
+}
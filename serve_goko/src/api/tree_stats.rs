@@ -0,0 +1,19 @@
+use pointcloud::*;
+
+use serde::{Deserialize, Serialize};
+use crate::core::*;
+use goko::errors::GokoError;
+use goko::TreeStats;
+
+/// Send a `GET` request to `/stats` for this
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct TreeStatsRequest;
+
+/// Response to a tree stats request, see [`goko::CoverTreeReader::tree_stats`]
+pub type TreeStatsResponse = TreeStats;
+
+impl TreeStatsRequest {
+    pub fn process<D: PointCloud, T: Send + 'static>(self, reader: &mut CoreReader<D, T>) -> Result<TreeStatsResponse, GokoError> {
+        Ok(reader.tree.tree_stats())
+    }
+}
@@ -0,0 +1,548 @@
+//! # Client
+//!
+//! An async client for the HTTP server. Points are sent as msgpack (see [`crate::parsers::MsgPackDense`]) and
+//! the JSON responses are parsed back into the types in [`crate::api`].
+//!
+//! Connections are pooled by the underlying hyper client, so make one [`GokoClient`] and clone it around.
+//! If you run several replicas of the same tree, [`GokoReplicas`] sends each query to all of them and returns
+//! the answer from the newest tree.
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, Uri};
+use pointcloud::{ExternalId, Summary};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+use crate::api::*;
+use crate::errors::GokoClientError;
+use crate::http::{percent_encode, LatencyByDepthResponse};
+use futures::future::join_all;
+
+/// A client for a single goko server.
+#[derive(Clone)]
+pub struct GokoClient {
+    client: Client<HttpConnector>,
+    base_uri: String,
+    retries: usize,
+    retry_delay: Duration,
+    require_epoch: Option<u64>,
+}
+
+impl GokoClient {
+    /// Creates a client for the server at `base_uri`, for example `http://127.0.0.1:3030`.
+    /// By default this retries failed connections and 5xx responses twice, 100ms apart. See [`Self::set_retries`].
+    pub fn new(base_uri: &str) -> GokoClient {
+        GokoClient {
+            client: Client::new(),
+            base_uri: base_uri.trim_end_matches('/').to_string(),
+            retries: 2,
+            retry_delay: Duration::from_millis(100),
+            require_epoch: None,
+        }
+    }
+
+    /// How many times to retry a request after a connection error or a 5xx response. The POST requests change the
+    /// server's state, so they're only retried when the connection couldn't be made, as the server never saw them.
+    pub fn set_retries(&mut self, retries: usize) -> &mut Self {
+        self.retries = retries;
+        self
+    }
+
+    /// How long to wait between retries.
+    pub fn set_retry_delay(&mut self, retry_delay: Duration) -> &mut Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Only accept answers from this epoch of the tree, the server responds to anything else with a 409 which
+    /// comes back as a [`GokoClientError::ServerError`]. See [`GokoResponse`].
+    pub fn set_require_epoch(&mut self, require_epoch: Option<u64>) -> &mut Self {
+        self.require_epoch = require_epoch;
+        self
+    }
+
+    /// The server this client talks to.
+    pub fn base_uri(&self) -> &str {
+        &self.base_uri
+    }
+
+    /// A client for a tree registered on this server, it sends everything under `/tree/NAME/`. See
+    /// [`GokoRequest::RegisterTree`].
+    pub fn tree(&self, name: &str) -> GokoClient {
+        let mut client = self.clone();
+        client.base_uri = format!("{}/tree/{}", self.base_uri, percent_encode(name));
+        client
+    }
+
+    fn encode_point(point: &[f32]) -> Result<Vec<u8>, GokoClientError> {
+        rmp_serde::to_vec(point).map_err(|e| GokoClientError::parse(Box::new(e)))
+    }
+
+    fn tracker_query(tracker_name: Option<&str>) -> String {
+        match tracker_name {
+            Some(tracker_name) => format!("tracker_name={}", percent_encode(tracker_name)),
+            None => String::new(),
+        }
+    }
+
+    async fn send<R: DeserializeOwned>(
+        &self,
+        method: Method,
+        path_and_query: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<R, GokoClientError> {
+        self.send_with_epoch(method, path_and_query, body)
+            .await
+            .map(|(response, _epoch)| response)
+    }
+
+    /// Sends the request and returns the response with the epoch of the tree that answered it, from the
+    /// `goko-epoch` header.
+    async fn send_with_epoch<R: DeserializeOwned>(
+        &self,
+        method: Method,
+        path_and_query: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<(R, Option<u64>), GokoClientError> {
+        let path_and_query = match self.require_epoch {
+            Some(epoch) if path_and_query.contains('?') => {
+                format!("{}&require_epoch={}", path_and_query, epoch)
+            }
+            Some(epoch) => format!("{}?require_epoch={}", path_and_query, epoch),
+            None => path_and_query.to_string(),
+        };
+        let uri: Uri = format!("{}{}", self.base_uri, path_and_query)
+            .parse()
+            .map_err(|e| GokoClientError::parse(Box::new(e)))?;
+        let mut attempt = 0;
+        loop {
+            let request = Request::builder()
+                .method(method.clone())
+                .uri(uri.clone())
+                .body(body.clone().map(Body::from).unwrap_or_else(Body::empty))
+                .map_err(|e| GokoClientError::parse(Box::new(e)))?;
+            match self.client.request(request).await {
+                Ok(response) => {
+                    let status = response.status();
+                    let epoch = response
+                        .headers()
+                        .get("goko-epoch")
+                        .and_then(|epoch| epoch.to_str().ok())
+                        .and_then(|epoch| epoch.parse::<u64>().ok());
+                    let bytes = hyper::body::to_bytes(response.into_body()).await?;
+                    if status.is_success() {
+                        return serde_json::from_slice(&bytes)
+                            .map(|response| (response, epoch))
+                            .map_err(|e| GokoClientError::parse(Box::new(e)));
+                    } else if !status.is_server_error() || !method.is_idempotent() || attempt >= self.retries {
+                        return Err(GokoClientError::ServerError(
+                            status.as_u16(),
+                            String::from_utf8_lossy(&bytes).to_string(),
+                        ));
+                    }
+                }
+                Err(e) => {
+                    if attempt >= self.retries || !(method.is_idempotent() || e.is_connect()) {
+                        return Err(e.into());
+                    }
+                }
+            }
+            attempt += 1;
+            tokio::time::sleep(self.retry_delay).await;
+        }
+    }
+
+    /// See [`GokoRequest::Parameters`]
+    pub async fn parameters(&self) -> Result<ParametersResponse, GokoClientError> {
+        self.send(Method::GET, "/", None).await
+    }
+
+    /// See [`GokoRequest::TreeStats`]
+    pub async fn tree_stats(&self) -> Result<TreeStatsResponse, GokoClientError> {
+        self.send(Method::GET, "/stats", None).await
+    }
+
+    /// See [`GokoRequest::Knn`]
+    pub async fn knn(&self, point: &[f32], k: usize) -> Result<KnnResponse, GokoClientError> {
+        let body = Self::encode_point(point)?;
+        self.send(Method::GET, &format!("/knn?k={}", k), Some(body))
+            .await
+    }
+
+    /// See [`GokoRequest::RoutingKnn`]
+    pub async fn routing_knn(
+        &self,
+        point: &[f32],
+        k: usize,
+    ) -> Result<RoutingKnnResponse, GokoClientError> {
+        let body = Self::encode_point(point)?;
+        self.send(Method::GET, &format!("/routing_knn?k={}", k), Some(body))
+            .await
+    }
+
+    /// See [`GokoRequest::Path`]. The label summary type has to match the server's point cloud.
+    pub async fn path<L: Summary + DeserializeOwned>(
+        &self,
+        point: &[f32],
+    ) -> Result<PathResponse<L>, GokoClientError> {
+        let body = Self::encode_point(point)?;
+        self.send(Method::GET, "/path", Some(body)).await
+    }
+
+    /// See [`GokoRequest::KnnByName`], the name is percent encoded.
+    pub async fn knn_by_name(&self, name: &str, k: usize) -> Result<KnnResponse, GokoClientError> {
+        self.send(Method::GET, &format!("/knn_by_name?name={}&k={}", percent_encode(name), k), None)
+            .await
+    }
+
+    /// See [`GokoRequest::KnownPathByName`]. The label summary type has to match the server's point cloud.
+    pub async fn known_path_by_name<L: Summary + DeserializeOwned>(
+        &self,
+        name: &str,
+    ) -> Result<PathResponse<L>, GokoClientError> {
+        self.send(Method::GET, &format!("/known_path_by_name?name={}", percent_encode(name)), None)
+            .await
+    }
+
+    /// See [`GokoRequest::KnnById`], string ids are percent encoded.
+    pub async fn knn_by_id(&self, id: &ExternalId, k: usize) -> Result<KnnResponse, GokoClientError> {
+        self.send(Method::GET, &format!("/knn_by_id?id={}&k={}", percent_encode(&id.to_string()), k), None)
+            .await
+    }
+
+    /// See [`GokoRequest::KnownPathById`]. The label summary type has to match the server's point cloud.
+    pub async fn known_path_by_id<L: Summary + DeserializeOwned>(
+        &self,
+        id: &ExternalId,
+    ) -> Result<PathResponse<L>, GokoClientError> {
+        self.send(Method::GET, &format!("/known_path_by_id?id={}", percent_encode(&id.to_string())), None)
+            .await
+    }
+
+    /// See [`TrackingRequestChoice::TrackPoint`]
+    pub async fn track_point(
+        &self,
+        point: &[f32],
+        tracker_name: Option<&str>,
+    ) -> Result<TrackingResponse, GokoClientError> {
+        let body = Self::encode_point(point)?;
+        let query = Self::tracker_query(tracker_name);
+        self.send(Method::POST, &format!("/track/point?{}", query), Some(body))
+            .await
+    }
+
+    /// See [`TrackingRequestChoice::TrackPoint`], the query id is percent encoded.
+    pub async fn track_point_with_id(
+        &self,
+        point: &[f32],
+        query_id: &str,
+        tracker_name: Option<&str>,
+    ) -> Result<TrackingResponse, GokoClientError> {
+        let body = Self::encode_point(point)?;
+        let query = Self::tracker_query(tracker_name);
+        self.send(
+            Method::POST,
+            &format!("/track/point?query_id={}&{}", percent_encode(query_id), query),
+            Some(body),
+        )
+        .await
+    }
+
+    /// See [`TrackingRequestChoice::TrackPoint`], the segment is percent encoded.
+    pub async fn track_point_in_segment(
+        &self,
+        point: &[f32],
+        segment: &str,
+        tracker_name: Option<&str>,
+    ) -> Result<TrackingResponse, GokoClientError> {
+        let body = Self::encode_point(point)?;
+        let query = Self::tracker_query(tracker_name);
+        self.send(
+            Method::POST,
+            &format!("/track/point?segment={}&{}", percent_encode(segment), query),
+            Some(body),
+        )
+        .await
+    }
+
+    /// See [`TrackingRequestChoice::TrackBulk`]
+    pub async fn track_bulk(
+        &self,
+        points: &BulkPoints<Vec<f32>>,
+        tracker_name: Option<&str>,
+    ) -> Result<TrackingResponse, GokoClientError> {
+        let body = rmp_serde::to_vec(points).map_err(|e| GokoClientError::parse(Box::new(e)))?;
+        let query = Self::tracker_query(tracker_name);
+        self.send(Method::POST, &format!("/track/bulk?{}", query), Some(body))
+            .await
+    }
+
+    /// See [`TrackingRequestChoice::AddTracker`]
+    pub async fn add_tracker(
+        &self,
+        window_size: usize,
+        tracker_name: Option<&str>,
+    ) -> Result<TrackingResponse, GokoClientError> {
+        let query = Self::tracker_query(tracker_name);
+        self.send(
+            Method::POST,
+            &format!("/track/add?window_size={}&{}", window_size, query),
+            None,
+        )
+        .await
+    }
+
+    /// See [`TrackingRequestChoice::AddTracker`], the tracker also counts the unique visitors of each node.
+    pub async fn add_unique_visitor_tracker(
+        &self,
+        window_size: usize,
+        tracker_name: Option<&str>,
+    ) -> Result<TrackingResponse, GokoClientError> {
+        let query = Self::tracker_query(tracker_name);
+        self.send(
+            Method::POST,
+            &format!(
+                "/track/add?window_size={}&unique_visitors=true&{}",
+                window_size, query
+            ),
+            None,
+        )
+        .await
+    }
+
+    /// See [`TrackingRequestChoice::CurrentStats`]
+    pub async fn tracker_stats(
+        &self,
+        window_size: usize,
+        tracker_name: Option<&str>,
+    ) -> Result<TrackingResponse, GokoClientError> {
+        let query = Self::tracker_query(tracker_name);
+        self.send(
+            Method::GET,
+            &format!("/track/stats?window_size={}&{}", window_size, query),
+            None,
+        )
+        .await
+    }
+
+    /// See [`TrackingRequestChoice::SegmentStats`]
+    pub async fn segment_stats(
+        &self,
+        window_size: usize,
+        segment: Option<&str>,
+        tracker_name: Option<&str>,
+    ) -> Result<TrackingResponse, GokoClientError> {
+        let mut query = format!("window_size={}&{}", window_size, Self::tracker_query(tracker_name));
+        if let Some(segment) = segment {
+            query.push_str(&format!("&segment={}", percent_encode(segment)));
+        }
+        self.send(Method::GET, &format!("/track/segment_stats?{}", query), None)
+            .await
+    }
+
+    /// See [`TrackingRequestChoice::ResizeTracker`]
+    pub async fn resize_tracker(
+        &self,
+        window_size: usize,
+        old_window_size: Option<usize>,
+        tracker_name: Option<&str>,
+    ) -> Result<TrackingResponse, GokoClientError> {
+        let mut query = format!("window_size={}&{}", window_size, Self::tracker_query(tracker_name));
+        if let Some(old_window_size) = old_window_size {
+            query.push_str(&format!("&old_window_size={}", old_window_size));
+        }
+        self.send(Method::POST, &format!("/track/resize?{}", query), None)
+            .await
+    }
+
+    /// See [`TrackingRequestChoice::ResetTracker`]
+    pub async fn reset_tracker(
+        &self,
+        window_size: Option<usize>,
+        tracker_name: Option<&str>,
+    ) -> Result<TrackingResponse, GokoClientError> {
+        let mut query = Self::tracker_query(tracker_name);
+        if let Some(window_size) = window_size {
+            query.push_str(&format!("&window_size={}", window_size));
+        }
+        self.send(Method::POST, &format!("/track/reset?{}", query), None)
+            .await
+    }
+
+    /// See [`TrackingRequestChoice::TopDrift`]
+    pub async fn top_drift(
+        &self,
+        n: usize,
+        window_size: Option<usize>,
+        tracker_name: Option<&str>,
+    ) -> Result<TrackingResponse, GokoClientError> {
+        let mut query = format!("n={}&{}", n, Self::tracker_query(tracker_name));
+        if let Some(window_size) = window_size {
+            query.push_str(&format!("&window_size={}", window_size));
+        }
+        self.send(Method::GET, &format!("/track/top_drift?{}", query), None)
+            .await
+    }
+
+    /// See [`TrackingRequestChoice::DriftRollup`]
+    pub async fn drift_rollup(
+        &self,
+        scale_index: i32,
+        window_size: Option<usize>,
+        tracker_name: Option<&str>,
+    ) -> Result<TrackingResponse, GokoClientError> {
+        let mut query = format!(
+            "scale_index={}&{}",
+            scale_index,
+            Self::tracker_query(tracker_name)
+        );
+        if let Some(window_size) = window_size {
+            query.push_str(&format!("&window_size={}", window_size));
+        }
+        self.send(Method::GET, &format!("/track/drift_rollup?{}", query), None)
+            .await
+    }
+
+    /// The server's query latencies by path length, see [`crate::http::LatencyByDepth`]
+    pub async fn latency_by_depth(&self) -> Result<LatencyByDepthResponse, GokoClientError> {
+        self.send(Method::GET, "/latency_by_depth", None).await
+    }
+
+    /// See [`GokoRequest::StatsSnapshot`]
+    pub async fn tracker_snapshot(&self) -> Result<StatsSnapshotResponse, GokoClientError> {
+        self.send(Method::GET, "/track/snapshot", None).await
+    }
+
+    /// See [`GokoRequest::BaselineStatus`]
+    pub async fn baseline_status(&self) -> Result<BaselineStatusResponse, GokoClientError> {
+        self.send(Method::GET, "/track/baseline/status", None).await
+    }
+
+    /// See [`GokoRequest::Flush`]
+    pub async fn flush(&self) -> Result<FlushResponse, GokoClientError> {
+        self.send(Method::POST, "/admin/flush", None).await
+    }
+
+    /// See [`GokoRequest::RegisterTree`], the path is percent encoded.
+    pub async fn register_tree(&self, name: &str, path: &str) -> Result<RegisterTreeResponse, GokoClientError> {
+        self.send(
+            Method::POST,
+            &format!("/admin/trees/register?name={}&path={}", percent_encode(name), percent_encode(path)),
+            None,
+        )
+        .await
+    }
+
+    /// See [`GokoRequest::UnregisterTree`]
+    pub async fn unregister_tree(&self, name: &str) -> Result<UnregisterTreeResponse, GokoClientError> {
+        self.send(Method::POST, &format!("/admin/trees/unregister?name={}", percent_encode(name)), None)
+            .await
+    }
+
+    /// See [`GokoRequest::ReloadTree`], leave out the name to reload the main tree.
+    pub async fn reload_tree(
+        &self,
+        name: Option<&str>,
+        path: &str,
+        migrate_trackers: bool,
+    ) -> Result<ReloadTreeResponse, GokoClientError> {
+        let mut query = format!("path={}&migrate_trackers={}", percent_encode(path), migrate_trackers);
+        if let Some(name) = name {
+            query.push_str(&format!("&name={}", percent_encode(name)));
+        }
+        self.send(Method::POST, &format!("/admin/trees/reload?{}", query), None)
+            .await
+    }
+
+    /// See [`GokoRequest::ListTrees`]
+    pub async fn trees(&self) -> Result<ListTreesResponse, GokoClientError> {
+        self.send(Method::GET, "/admin/trees", None).await
+    }
+}
+
+/// Sends read queries to several replicas of the same tree at once and returns the successful response from the
+/// replica with the newest tree, the one with the highest epoch. A replica that's still on an older tree answers
+/// from it until it's reloaded, so this waits for all of them. Tracking requests are stateful, so send those to a
+/// single [`GokoClient`].
+#[derive(Clone)]
+pub struct GokoReplicas {
+    replicas: Vec<GokoClient>,
+}
+
+impl GokoReplicas {
+    /// Creates a client for each of the replicas.
+    pub fn new(base_uris: &[&str]) -> GokoReplicas {
+        GokoReplicas {
+            replicas: base_uris.iter().map(|uri| GokoClient::new(uri)).collect(),
+        }
+    }
+
+    /// Uses pre-configured clients for the replicas.
+    pub fn from_clients(replicas: Vec<GokoClient>) -> GokoReplicas {
+        GokoReplicas { replicas }
+    }
+
+    /// The clients for each replica
+    pub fn replicas(&self) -> &[GokoClient] {
+        &self.replicas
+    }
+
+    /// Sends the GET request to every replica and picks the successful response with the highest epoch, the
+    /// first replica's on ties. If they all fail this returns the last error.
+    async fn fan_out<R: DeserializeOwned>(
+        &self,
+        path_and_query: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<R, GokoClientError> {
+        if self.replicas.is_empty() {
+            return Err(GokoClientError::MalformedQuery("No replicas to query"));
+        }
+        let responses = join_all(
+            self.replicas
+                .iter()
+                .map(|c| c.send_with_epoch::<R>(Method::GET, path_and_query, body.clone())),
+        )
+        .await;
+        let mut newest: Option<(R, Option<u64>)> = None;
+        let mut last_error = None;
+        for response in responses {
+            match response {
+                Ok((response, epoch)) => match &newest {
+                    Some((_, newest_epoch)) if epoch <= *newest_epoch => {}
+                    _ => newest = Some((response, epoch)),
+                },
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match (newest, last_error) {
+            (Some((response, _epoch)), _) => Ok(response),
+            (None, Some(e)) => Err(e),
+            (None, None) => Err(GokoClientError::MalformedQuery("No replicas to query")),
+        }
+    }
+
+    /// See [`GokoClient::knn`]
+    pub async fn knn(&self, point: &[f32], k: usize) -> Result<KnnResponse, GokoClientError> {
+        let body = GokoClient::encode_point(point)?;
+        self.fan_out(&format!("/knn?k={}", k), Some(body)).await
+    }
+
+    /// See [`GokoClient::routing_knn`]
+    pub async fn routing_knn(
+        &self,
+        point: &[f32],
+        k: usize,
+    ) -> Result<RoutingKnnResponse, GokoClientError> {
+        let body = GokoClient::encode_point(point)?;
+        self.fan_out(&format!("/routing_knn?k={}", k), Some(body)).await
+    }
+
+    /// See [`GokoClient::path`]
+    pub async fn path<L: Summary + DeserializeOwned>(
+        &self,
+        point: &[f32],
+    ) -> Result<PathResponse<L>, GokoClientError> {
+        let body = GokoClient::encode_point(point)?;
+        self.fan_out("/path", Some(body)).await
+    }
+}
@@ -0,0 +1,181 @@
+//! # Configuration
+//!
+//! Everything about how the server runs, in one place. Load it from a YAML or TOML file and override what you need:
+//!
+//! ```yaml
+//! address: 0.0.0.0:3030
+//! grpc_address: 0.0.0.0:3040
+//! flush_path: trackers.json
+//! persistence:
+//!   snapshot_interval_secs: 300
+//!   restore: true
+//! limits:
+//!   max_k: 100
+//! trackers:
+//!   window_sizes: [100, 1000]
+//! baseline:
+//!   interval_secs: 3600
+//!   sequence_len: 1000
+//! ```
+//!
+//! Every field is optional, missing ones are filled in from [`ServerConfig::default`]. Unknown keys are an error, so
+//! a misspelled field doesn't silently fall back to its default. The body parser is the type parameter of
+//! [`crate::http::MakeGokoHttp`], and TLS is terminated by whatever you put in front of the server, so neither is
+//! configured here.
+
+use serde::{Deserialize, Serialize};
+use std::fs::read_to_string;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::http::QueryLimits;
+
+/// The trackers that are on the default tracker from the start.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrackerConfig {
+    /// The window sizes of the trackers to add, see [`crate::core::CoreWriter::add_trackers`]
+    pub window_sizes: Vec<usize>,
+}
+
+/// How the tracker state outlives the server. It's written to the flush path, so these do nothing without one.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PersistenceConfig {
+    /// Seconds between the snapshots of the trackers, on top of the flushes on shutdown and at `/admin/flush`.
+    /// See [`crate::core::CoreWriter::schedule_snapshots`]
+    pub snapshot_interval_secs: Option<u64>,
+    /// Replays the trackers from the flush path on startup, see [`crate::core::CoreWriter::restore_trackers`]
+    pub restore: bool,
+    /// The named trackers that are left out of the snapshots and the restore, they start over with the server. The
+    /// names are matched exactly, there are no patterns, and the default tracker is always kept
+    pub transient_trackers: Vec<String>,
+}
+
+/// How the baselines of the trackers are recomputed in the background, see
+/// [`crate::core::CoreWriter::schedule_baselines`]. The fields other than the interval are passed on to a
+/// `DirichletBaseline`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BaselineConfig {
+    /// Seconds between the end of one recomputation and the start of the next
+    pub interval_secs: u64,
+    /// The length of the sampled sequences, set this to the largest window size. 0 samples the whole training set
+    pub sequence_len: usize,
+    /// The number of sequences sampled from the training set
+    pub num_sequences: usize,
+    /// The stats are taken every this many points along a sequence and interpolated in between
+    pub sample_rate: usize,
+}
+
+impl Default for BaselineConfig {
+    fn default() -> Self {
+        BaselineConfig {
+            interval_secs: 3600,
+            sequence_len: 0,
+            num_sequences: 8,
+            sample_rate: 100,
+        }
+    }
+}
+
+/// The server's configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The address to bind to
+    pub address: SocketAddr,
+    /// Limits and defaults for the query parameters
+    pub limits: QueryLimits,
+    /// The trackers to start with
+    pub trackers: TrackerConfig,
+    /// Where the tracker state is written on a flush, see [`crate::core::CoreWriter::set_flush_path`]
+    pub flush_path: Option<PathBuf>,
+    /// The periodic snapshots and restore of the tracker state
+    pub persistence: PersistenceConfig,
+    /// The background baseline recomputation, if any
+    pub baseline: Option<BaselineConfig>,
+    /// The address to serve gRPC on, if any, see [`crate::grpc`]
+    pub grpc_address: Option<SocketAddr>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            address: ([127, 0, 0, 1], 3030).into(),
+            limits: QueryLimits::default(),
+            trackers: TrackerConfig::default(),
+            flush_path: None,
+            persistence: PersistenceConfig::default(),
+            baseline: None,
+            grpc_address: None,
+        }
+    }
+}
+
+fn invalid_data<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl ServerConfig {
+    /// Loads the config from a YAML file.
+    pub fn from_yaml<P: AsRef<Path>>(path: P) -> io::Result<ServerConfig> {
+        serde_yaml::from_str(&read_to_string(path)?).map_err(invalid_data)
+    }
+
+    /// Loads the config from a TOML file.
+    pub fn from_toml<P: AsRef<Path>>(path: P) -> io::Result<ServerConfig> {
+        toml::from_str(&read_to_string(path)?).map_err(invalid_data)
+    }
+
+    /// Loads the config from a file, picking the format from the extension. Anything that isn't `.toml` is read as YAML.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<ServerConfig> {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("toml") => ServerConfig::from_toml(path),
+            _ => ServerConfig::from_yaml(path),
+        }
+    }
+
+    /// Overrides the address.
+    pub fn with_address<A: Into<SocketAddr>>(mut self, address: A) -> Self {
+        self.address = address.into();
+        self
+    }
+
+    /// Overrides the query limits.
+    pub fn with_limits(mut self, limits: QueryLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Overrides the starting trackers.
+    pub fn with_tracker_window_sizes(mut self, window_sizes: Vec<usize>) -> Self {
+        self.trackers.window_sizes = window_sizes;
+        self
+    }
+
+    /// Overrides the flush path.
+    pub fn with_flush_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.flush_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Overrides the snapshots and restore of the tracker state.
+    pub fn with_persistence(mut self, persistence: PersistenceConfig) -> Self {
+        self.persistence = persistence;
+        self
+    }
+
+    /// Serves gRPC on this address too.
+    pub fn with_grpc_address<A: Into<SocketAddr>>(mut self, address: A) -> Self {
+        self.grpc_address = Some(address.into());
+        self
+    }
+
+    /// Overrides the background baseline recomputation.
+    pub fn with_baseline(mut self, baseline: BaselineConfig) -> Self {
+        self.baseline = Some(baseline);
+        self
+    }
+}
@@ -5,6 +5,7 @@ use core::task::Context;
 use std::future::Future;
 use std::pin::Pin;
 use std::task::Poll;
+use std::time::Duration;
 
 use crate::errors::*;
 
@@ -74,8 +75,14 @@ impl<T> Future for ResponseFuture<T> {
     }
 }
 
+/// How long a service waits for a request before it's told it's idle, see [`InternalService::idle`].
+pub(crate) const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
 pub trait InternalService<T, S>: Send {
     fn process(&mut self, request: T) -> Result<S, GokoError>;
+
+    /// Called when no request came in for [`IDLE_INTERVAL`], and again after every interval it stays idle.
+    fn idle(&mut self) {}
 }
 
 #[derive(Clone)]
@@ -91,12 +98,18 @@ impl<T: Send + 'static, S: Send + 'static> InternalServiceOperator<T, S> {
         let (request_snd, mut request_rcv): (CoreRequestSender<T,S>, CoreRequestReciever<T,S>) =
             mpsc::unbounded_channel();
         tokio::spawn(async move {
-            while let Some(mut msg) = request_rcv.recv().await {
-                if let Some(request) = msg.request() {
-                    let response = server.process(request);
-                    msg.respond(response);
-                } else {
-                    msg.error(InternalServiceError::DoubleRead)
+            loop {
+                match tokio::time::timeout(IDLE_INTERVAL, request_rcv.recv()).await {
+                    Ok(Some(mut msg)) => {
+                        if let Some(request) = msg.request() {
+                            let response = server.process(request);
+                            msg.respond(response);
+                        } else {
+                            msg.error(InternalServiceError::DoubleRead)
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(_) => server.idle(),
                 }
             }
         });
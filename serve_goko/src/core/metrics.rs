@@ -0,0 +1,257 @@
+//! # Metrics
+//!
+//! The counters behind the server's `GET /metrics`. The requests are counted as they go through
+//! [`CoreReader::process`], so both the HTTP and gRPC servers feed them. The size of the tree is read when the
+//! metrics are scraped. The KL divergences of the trackers are the ones the tracker workers last published to their
+//! [`TrackerGauges`], so a scrape doesn't queue anything on the workers.
+
+use pointcloud::PointCloud;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::CoreReader;
+
+/// The upper bounds of the latency buckets, in microseconds. Anything slower than the last one lands in an overflow
+/// bucket.
+pub const LATENCY_BUCKETS_US: [u64; 12] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 1_000_000,
+];
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Histogram {
+    /// One count per bound in `LATENCY_BUCKETS_US`, then the overflow
+    buckets: [u64; LATENCY_BUCKETS_US.len() + 1],
+    count: u64,
+    sum_us: u64,
+}
+
+impl Histogram {
+    pub(crate) fn record(&mut self, latency_us: u64) {
+        let bucket = LATENCY_BUCKETS_US
+            .iter()
+            .position(|b| latency_us <= *b)
+            .unwrap_or(LATENCY_BUCKETS_US.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum_us += latency_us;
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub(crate) fn sum_us(&self) -> u64 {
+        self.sum_us
+    }
+
+    /// One count per bound in [`LATENCY_BUCKETS_US`], then the overflow, not cumulative.
+    pub(crate) fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// Writes the histogram in Prometheus' text format, in seconds. The labels go in as they are, `a="b",c="d"`.
+    pub(crate) fn write_prometheus(&self, text: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            cumulative += count;
+            let le = match LATENCY_BUCKETS_US.get(i) {
+                Some(le_us) => (*le_us as f64 / 1.0e6).to_string(),
+                None => "+Inf".to_string(),
+            };
+            text.push_str(&format!(
+                "{}_bucket{{{},le=\"{}\"}} {}\n",
+                name, labels, le, cumulative
+            ));
+        }
+        text.push_str(&format!(
+            "{}_sum{{{}}} {}\n",
+            name,
+            labels,
+            self.sum_us as f64 / 1.0e6
+        ));
+        text.push_str(&format!("{}_count{{{}}} {}\n", name, labels, self.count));
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct RequestCounter {
+    errors: u64,
+    latency: Histogram,
+}
+
+/// Counts of the requests served by a [`super::CoreWriter`] and its readers, by the request type, with a latency
+/// histogram per type. The types are the ones from [`crate::GokoRequest::request_type`]. One set is shared by the
+/// writer and every reader made from it, get it with [`super::CoreWriter::metrics`].
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<&'static str, RequestCounter>>,
+}
+
+impl Metrics {
+    pub(crate) fn record(&self, request_type: &'static str, latency: Duration, success: bool) {
+        let mut requests = self.requests.lock().unwrap();
+        let counter = requests.entry(request_type).or_default();
+        counter.latency.record(latency.as_micros() as u64);
+        if !success {
+            counter.errors += 1;
+        }
+    }
+
+    /// The number of requests of this type that were served, including the ones that failed.
+    pub fn request_count(&self, request_type: &str) -> u64 {
+        self.requests
+            .lock()
+            .unwrap()
+            .get(request_type)
+            .map(|c| c.latency.count())
+            .unwrap_or(0)
+    }
+
+    /// The number of requests of this type that failed with an internal error.
+    pub fn error_count(&self, request_type: &str) -> u64 {
+        self.requests
+            .lock()
+            .unwrap()
+            .get(request_type)
+            .map(|c| c.errors)
+            .unwrap_or(0)
+    }
+
+    fn write_prometheus(&self, text: &mut String) {
+        let requests = self.requests.lock().unwrap().clone();
+        text.push_str("# HELP goko_requests_total Requests that reached the tree, by type.\n");
+        text.push_str("# TYPE goko_requests_total counter\n");
+        for (request_type, counter) in &requests {
+            text.push_str(&format!(
+                "goko_requests_total{{type=\"{}\"}} {}\n",
+                request_type,
+                counter.latency.count()
+            ));
+        }
+        text.push_str("# HELP goko_request_errors_total Requests that failed with an internal error, by type.\n");
+        text.push_str("# TYPE goko_request_errors_total counter\n");
+        for (request_type, counter) in &requests {
+            text.push_str(&format!(
+                "goko_request_errors_total{{type=\"{}\"}} {}\n",
+                request_type, counter.errors
+            ));
+        }
+        text.push_str(
+            "# HELP goko_request_latency_seconds Time spent answering a request, by type.\n",
+        );
+        text.push_str("# TYPE goko_request_latency_seconds histogram\n");
+        for (request_type, counter) in &requests {
+            counter.latency.write_prometheus(
+                text,
+                "goko_request_latency_seconds",
+                &format!("type=\"{}\"", request_type),
+            );
+        }
+    }
+}
+
+/// The stats of one tracker window that `/metrics` reports.
+#[derive(Debug, Clone)]
+pub struct WindowGauge {
+    pub window_size: usize,
+    pub kl_div: f64,
+    pub max_kl_div: f64,
+    pub sequence_len: usize,
+}
+
+/// The window stats of every tracker of a tree, as the tracker workers last published them. A worker publishes right
+/// after its windows are added, resized, reset or restored and whenever its stats are snapshotted. While it's
+/// tracking it publishes at most once every [`crate::api::GAUGE_INTERVAL`], and once more when it goes idle, so the
+/// gauges lag the trackers by about that much.
+#[derive(Debug, Default)]
+pub struct TrackerGauges {
+    trackers: Mutex<BTreeMap<Option<String>, Vec<WindowGauge>>>,
+}
+
+impl TrackerGauges {
+    /// Replaces the windows of a tracker, `None` is the default tracker.
+    pub(crate) fn publish(&self, tracker_name: Option<&str>, windows: Vec<WindowGauge>) {
+        self.trackers
+            .lock()
+            .unwrap()
+            .insert(tracker_name.map(|name| name.to_string()), windows);
+    }
+
+    /// The windows of each tracker, the default tracker first.
+    pub fn snapshot(&self) -> BTreeMap<Option<String>, Vec<WindowGauge>> {
+        self.trackers.lock().unwrap().clone()
+    }
+}
+
+/// Tracker names can come from gRPC as any string.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_tracker_gauges(
+    text: &mut String,
+    metric: &str,
+    trackers: &[(String, Vec<WindowGauge>)],
+    value: impl Fn(&WindowGauge) -> f64,
+) {
+    for (tracker_name, windows) in trackers {
+        for window in windows.iter() {
+            text.push_str(&format!(
+                "{}{{tracker=\"{}\",window_size=\"{}\"}} {}\n",
+                metric,
+                tracker_name,
+                window.window_size,
+                value(window)
+            ));
+        }
+    }
+}
+
+impl<D: PointCloud, T: Send + 'static> CoreReader<D, T> {
+    /// The request counters, the size of the tree and the stats of every tracker window in Prometheus' text
+    /// format. The trackers are read from their [`TrackerGauges`], the default tracker has an empty `tracker` label.
+    pub fn prometheus_text(&self) -> String {
+        let mut text = String::new();
+        self.metrics.write_prometheus(&mut text);
+
+        text.push_str("# HELP goko_tree_nodes The number of nodes in the tree.\n");
+        text.push_str("# TYPE goko_tree_nodes gauge\n");
+        text.push_str(&format!("goko_tree_nodes {}\n", self.tree.node_count()));
+        text.push_str("# HELP goko_tree_points The number of points in the tree's point cloud.\n");
+        text.push_str("# TYPE goko_tree_points gauge\n");
+        text.push_str(&format!(
+            "goko_tree_points {}\n",
+            self.tree.parameters().point_cloud.len()
+        ));
+        text.push_str("# HELP goko_tree_epoch The number of times the tree was refreshed.\n");
+        text.push_str("# TYPE goko_tree_epoch gauge\n");
+        text.push_str(&format!("goko_tree_epoch {}\n", self.epoch()));
+
+        let trackers: Vec<(String, Vec<WindowGauge>)> = self
+            .gauges
+            .snapshot()
+            .into_iter()
+            .map(|(name, windows)| (name.map(|n| escape_label(&n)).unwrap_or_default(), windows))
+            .collect();
+        text.push_str("# HELP goko_tracker_kl_div The KL divergence of a tracker window.\n");
+        text.push_str("# TYPE goko_tracker_kl_div gauge\n");
+        write_tracker_gauges(&mut text, "goko_tracker_kl_div", &trackers, |w| w.kl_div);
+        text.push_str("# HELP goko_tracker_max_kl_div The largest KL divergence of a node in a tracker window.\n");
+        text.push_str("# TYPE goko_tracker_max_kl_div gauge\n");
+        write_tracker_gauges(&mut text, "goko_tracker_max_kl_div", &trackers, |w| {
+            w.max_kl_div
+        });
+        text.push_str(
+            "# HELP goko_tracker_sequence_len The number of points in a tracker window.\n",
+        );
+        text.push_str("# TYPE goko_tracker_sequence_len gauge\n");
+        write_tracker_gauges(&mut text, "goko_tracker_sequence_len", &trackers, |w| {
+            w.sequence_len as f64
+        });
+        text
+    }
+}
@@ -1,38 +1,289 @@
 use pointcloud::PointCloud;
 use goko::{CoverTreeReader,CoverTreeWriter};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::sync::RwLock;
-use std::collections::HashMap;
+use tokio::task::JoinHandle;
+use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
 
 pub(crate) mod internal_service;
 use internal_service::InternalServiceOperator;
-use crate::api::{TrackerWorker, TrackingRequest, TrackingResponse};
+mod metrics;
+pub use metrics::{Metrics, TrackerGauges, WindowGauge, LATENCY_BUCKETS_US};
+pub(crate) use metrics::Histogram;
+use crate::api::{AddTrackerRequest, BaselineState, FlushRequest, FlushResponse, RestoreRequest, RestoreResponse, SetBaselineRequest, TrackerWorker, TrackingRequest, TrackingRequestChoice, TrackingResponse};
+use goko::plugins::discrete::baseline::{DirichletBaseline, KLDivergenceBaseline};
+use crate::config::{BaselineConfig, ServerConfig};
+use crate::errors::InternalServiceError;
+use goko::errors::GokoError;
 
 
-pub struct CoreWriter<D: PointCloud, T: Send + 'static> {
+/// Loads the trees registered at `/admin/trees/register` from the path in the request, see
+/// [`CoreWriter::set_tree_loader`]. Add the plugins the server needs, like `GokoDirichlet`, before returning the tree.
+pub type TreeLoader<D> = Arc<dyn Fn(&Path) -> Result<CoverTreeWriter<D>, GokoError> + Send + Sync>;
+
+/// The named trees a core serves next to its own, each with its own trackers.
+type Tenants<D, T> = Arc<RwLock<HashMap<String, Arc<CoreWriter<D, T>>>>>;
+
+/// The tree a core serves and its trackers. A reload swaps a new one in behind the same handle, so the old tree is
+/// dropped once the requests that were on it finish. See [`crate::api::ReloadTreeRequest`].
+pub(crate) struct ServedTree<D: PointCloud, T: Send + 'static> {
     pub(crate) tree: CoverTreeWriter<D>,
-    pub(crate) trackers: Arc<RwLock<HashMap<String,InternalServiceOperator<TrackingRequest<T>, TrackingResponse>>>>,
-    pub(crate) main_tracker: Arc<InternalServiceOperator<TrackingRequest<T>, TrackingResponse>>,
+    pub(crate) trackers: Arc<RwLock<HashMap<String, TrackerOperator<T>>>>,
+    pub(crate) main_tracker: Arc<TrackerOperator<T>>,
+    pub(crate) gauges: Arc<TrackerGauges>,
+}
+
+impl<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync> ServedTree<D, T> {
+    pub(crate) fn new(writer: CoverTreeWriter<D>) -> Self {
+        let gauges = Arc::new(TrackerGauges::default());
+        ServedTree {
+            trackers: Arc::new(RwLock::new(HashMap::new())),
+            main_tracker: Arc::new(TrackerWorker::operator(writer.reader(), None, Arc::clone(&gauges))),
+            gauges,
+            tree: writer,
+        }
+    }
+}
+
+type Served<D, T> = Arc<Mutex<ServedTree<D, T>>>;
+
+pub struct CoreWriter<D: PointCloud, T: Send + 'static> {
+    pub(crate) served: Served<D, T>,
+    pub(crate) flush_path: Option<PathBuf>,
+    pub(crate) transient_trackers: HashSet<String>,
+    pub(crate) baseline_state: Arc<Mutex<BaselineState>>,
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) tenants: Tenants<D, T>,
+    pub(crate) tree_loader: Option<TreeLoader<D>>,
+}
+
+type TrackerOperator<T> = InternalServiceOperator<TrackingRequest<T>, TrackingResponse>;
+
+/// Swaps the baseline into the default tracker and, if `trackers` is given, into the named trackers too.
+async fn send_baseline<T: Send + 'static>(
+    baseline: KLDivergenceBaseline,
+    main_tracker: &TrackerOperator<T>,
+    trackers: Option<&RwLock<HashMap<String, TrackerOperator<T>>>>,
+) -> Result<(), InternalServiceError> {
+    let set_baseline = |tracker_name: Option<String>, baseline: KLDivergenceBaseline| TrackingRequest {
+        tracker_name,
+        request: TrackingRequestChoice::SetBaseline(SetBaselineRequest { baseline }),
+    };
+    if let Some(trackers) = trackers {
+        for (tracker_name, tracker) in trackers.read().await.iter() {
+            tracker.message(set_baseline(Some(tracker_name.clone()), baseline.clone())).await?;
+        }
+    }
+    main_tracker.message(set_baseline(None, baseline)).await?;
+    Ok(())
 }
 
 impl<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync> CoreWriter<D,T> {
     pub fn new(writer: CoverTreeWriter<D>) -> Self {
-        let trackers = Arc::new(RwLock::new(HashMap::new()));
-        let main_tracker = Arc::new(TrackerWorker::operator(writer.reader()));
         CoreWriter {
-            trackers,
-            main_tracker,
-            tree: writer,
+            served: Arc::new(Mutex::new(ServedTree::new(writer))),
+            flush_path: None,
+            transient_trackers: HashSet::new(),
+            baseline_state: Arc::new(Mutex::new(BaselineState::default())),
+            metrics: Arc::new(Metrics::default()),
+            tenants: Arc::new(RwLock::new(HashMap::new())),
+            tree_loader: None,
         }
     }
 
+    /// Creates the core with the flush path and transient trackers from the config. The trackers in the config are
+    /// added with [`CoreWriter::add_trackers`], and restored with [`CoreWriter::restore_trackers`], as those have
+    /// to talk to the tracker workers.
+    pub fn from_config(writer: CoverTreeWriter<D>, config: &ServerConfig) -> Self {
+        let mut core = CoreWriter::new(writer);
+        core.flush_path = config.flush_path.clone();
+        core.transient_trackers = config.persistence.transient_trackers.iter().cloned().collect();
+        core
+    }
+
+    /// Adds trackers with these window sizes to the default tracker.
+    pub async fn add_trackers(&self, window_sizes: &[usize]) -> Result<(), InternalServiceError> {
+        let main_tracker = self.reader().main_tracker;
+        for window_size in window_sizes {
+            let request = TrackingRequest {
+                tracker_name: None,
+                request: TrackingRequestChoice::AddTracker(AddTrackerRequest {
+                    window_size: *window_size,
+                    unique_visitors: false,
+                }),
+            };
+            main_tracker.message(request).await?;
+        }
+        Ok(())
+    }
+
+    /// Scores the stats of the default tracker's windows against this baseline, see
+    /// [`crate::api::CurrentStatsResponse::scores`]. Train it with a `DirichletBaseline` at the tracker's window size.
+    pub async fn set_baseline(&self, baseline: KLDivergenceBaseline) -> Result<(), InternalServiceError> {
+        send_baseline(baseline.clone(), &self.reader().main_tracker, None).await?;
+        self.baseline_state.lock().unwrap().finish(self.epoch(), baseline);
+        Ok(())
+    }
+
+    /// The baseline the trackers are scored against, the last one set with [`CoreWriter::set_baseline`] or
+    /// recomputed by [`CoreWriter::schedule_baselines`].
+    pub fn baseline(&self) -> Option<KLDivergenceBaseline> {
+        self.baseline_state.lock().unwrap().baseline()
+    }
+
+    /// Retrains the baseline of every tracker in the background, the first run starts right away and the next
+    /// one `interval_secs` after the previous one finished. The baselines are trained on the tree as it is when
+    /// the run starts, so they keep up with refreshes and reloads of the main tree. Each tracker swaps its baseline in between two queries, and
+    /// the named trackers added later start with the last baseline.
+    ///
+    /// The status is served at `/track/baseline/status`, see [`crate::api::BaselineStatusResponse`]. Call `abort`
+    /// on the returned handle to stop the schedule.
+    pub fn schedule_baselines(&self, config: BaselineConfig) -> JoinHandle<()> {
+        let base = self.reader();
+        let baseline_state = Arc::clone(&self.baseline_state);
+        baseline_state.lock().unwrap().schedule(config.interval_secs, config.num_sequences);
+
+        let mut trainer = DirichletBaseline::default();
+        trainer.set_sequence_len(config.sequence_len);
+        trainer.set_num_sequences(config.num_sequences);
+        trainer.set_sample_rate(config.sample_rate.max(1));
+        let trainer = Arc::new(trainer);
+
+        tokio::spawn(async move {
+            loop {
+                // Resolved on every run, so a reloaded main tree gets its baselines from its own tree and trackers
+                let replaced = base.replaced();
+                let current = replaced.as_ref().unwrap_or(&base);
+                let progress = baseline_state.lock().unwrap().start();
+                let epoch = current.epoch();
+                let (trainer, tree) = (Arc::clone(&trainer), current.tree.clone());
+                let trained = tokio::task::spawn_blocking(move || trainer.train_with_progress(tree, &progress)).await;
+                let result = match trained {
+                    Ok(Ok(baseline)) => send_baseline(baseline.clone(), &current.main_tracker, Some(&current.trackers)).await
+                        .map(|()| baseline)
+                        .map_err(|e| e.to_string()),
+                    Ok(Err(e)) => Err(e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                };
+                match result {
+                    Ok(baseline) => {
+                        info!(epoch, "recomputed the tracker baselines");
+                        baseline_state.lock().unwrap().finish(epoch, baseline);
+                    }
+                    Err(e) => {
+                        warn!(error = %e, "failed to recompute the tracker baselines");
+                        baseline_state.lock().unwrap().fail(e);
+                    }
+                }
+                tokio::time::sleep(Duration::from_secs(config.interval_secs)).await;
+            }
+        })
+    }
+
+    /// Where the tracker state is written on a flush, either from `/admin/flush` or on shutdown.
+    /// Set this before handing the writer to the server, readers copy it when they're created.
+    pub fn set_flush_path<P: AsRef<Path>>(&mut self, path: P) {
+        self.flush_path = Some(path.as_ref().to_path_buf());
+    }
+
+    /// Named trackers that are left out of the flushes and the restore, by their exact names. Set this before handing
+    /// the writer to the server, readers copy it when they're created.
+    pub fn set_transient_trackers<I: IntoIterator<Item = String>>(&mut self, tracker_names: I) {
+        self.transient_trackers = tracker_names.into_iter().collect();
+    }
+
+    /// How the trees registered at `/admin/trees/register` are loaded. Without a loader the server only takes trees
+    /// registered with [`CoreWriter::register_tree`]. Set this before handing the writer to the server, readers copy
+    /// it when they're created.
+    pub fn set_tree_loader(&mut self, loader: TreeLoader<D>) {
+        self.tree_loader = Some(loader);
+    }
+
+    /// Serves the tree under `/tree/NAME/`, with every route the main tree has and its own trackers. Returns false,
+    /// and drops the tree, if there's already a tree with the name. The requests to it are counted in the same
+    /// [`Metrics`]. The trackers of registered trees aren't flushed.
+    pub async fn register_tree(&self, name: &str, writer: CoverTreeWriter<D>) -> bool {
+        self.reader().register_tenant(name.to_string(), writer).await
+    }
+
+    /// Stops serving a registered tree, returns false if there was no tree with the name.
+    pub async fn unregister_tree(&self, name: &str) -> bool {
+        self.tenants.write().await.remove(name).is_some()
+    }
+
+    /// The names of the registered trees.
+    pub async fn tree_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.tenants.read().await.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Replays the trackers from the last flush, call this on startup before serving. A flush made with another
+    /// tree can be restored, the paths through nodes this tree doesn't have are dropped. See
+    /// [`RestoreRequest::process`] for what isn't restored.
+    pub async fn restore_trackers(&self) -> Result<RestoreResponse, InternalServiceError> {
+        RestoreRequest.process(&self.reader()).await
+    }
+
+    /// Flushes the trackers to the flush path every `interval_secs` in the background, so that a crash loses at
+    /// most that much. If the main tree was reloaded the trackers of the tree that's being served are flushed. Call
+    /// `abort` on the returned handle to stop the schedule.
+    pub fn schedule_snapshots(&self, interval_secs: u64) -> JoinHandle<()> {
+        let base = self.reader();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(interval_secs)).await;
+                let replaced = base.replaced();
+                match FlushRequest.process(replaced.as_ref().unwrap_or(&base)).await {
+                    Ok(flushed) => info!(trackers = flushed.trackers_flushed, "snapshotted the trackers"),
+                    Err(e) => warn!(error = %e, "failed to snapshot the trackers"),
+                }
+            }
+        })
+    }
+
+    /// Drains the tracker queues and flushes their state. Call this after the server has stopped accepting
+    /// connections, see [`crate::http::shutdown_signal`]. If the main tree was reloaded the trackers of the tree
+    /// that's being served are flushed.
+    pub async fn shutdown(&self) -> Result<FlushResponse, InternalServiceError> {
+        FlushRequest.process(&self.reader()).await
+    }
+
+    /// Publishes any changes made to the tree to the readers and bumps the tree's epoch.
+    pub fn refresh(&mut self) {
+        self.served.lock().unwrap().tree.refresh();
+    }
+
+    /// The current epoch of the tree, see [`goko::CoverTreeParameters::epoch`].
+    pub fn epoch(&self) -> u64 {
+        self.served.lock().unwrap().tree.epoch()
+    }
+
+    /// The request counters, shared by the writer and every reader made from it. They're served at `/metrics`.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// A reader of the tree that's being served, the last one reloaded if it was, see
+    /// [`crate::api::ReloadTreeRequest`].
     pub fn reader(&self) -> CoreReader<D,T> {
-        let tree = self.tree.reader();
+        let served = self.served.lock().unwrap();
         CoreReader {
-            trackers: Arc::clone(&self.trackers),
-            main_tracker: Arc::clone(&self.main_tracker),
-            tree,
+            tree: served.tree.reader(),
+            trackers: Arc::clone(&served.trackers),
+            main_tracker: Arc::clone(&served.main_tracker),
+            gauges: Arc::clone(&served.gauges),
+            served: Arc::clone(&self.served),
+            flush_path: self.flush_path.clone(),
+            transient_trackers: self.transient_trackers.clone(),
+            baseline_state: Arc::clone(&self.baseline_state),
+            metrics: Arc::clone(&self.metrics),
+            tenants: Arc::clone(&self.tenants),
+            tree_loader: self.tree_loader.clone(),
         }
     }
 }
@@ -41,5 +292,98 @@ pub struct CoreReader<D: PointCloud, T: Send + 'static> {
     pub(crate) tree: CoverTreeReader<D>,
     pub(crate) trackers: Arc<RwLock<HashMap<String,InternalServiceOperator<TrackingRequest<T>, TrackingResponse>>>>,
     pub(crate) main_tracker: Arc<InternalServiceOperator<TrackingRequest<T>, TrackingResponse>>,
+    pub(crate) gauges: Arc<TrackerGauges>,
+    pub(crate) flush_path: Option<PathBuf>,
+    pub(crate) transient_trackers: HashSet<String>,
+    pub(crate) baseline_state: Arc<Mutex<BaselineState>>,
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) tenants: Tenants<D, T>,
+    pub(crate) tree_loader: Option<TreeLoader<D>>,
+    pub(crate) served: Served<D, T>,
+}
+
+impl<D: PointCloud, T: Send + 'static> CoreReader<D,T> {
+    /// The current epoch of the tree, see [`goko::CoverTreeParameters::epoch`].
+    pub fn epoch(&self) -> u64 {
+        self.tree.epoch()
+    }
 }
 
+impl<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync> CoreReader<D,T> {
+    /// A reader of a registered tree, see [`CoreWriter::register_tree`].
+    pub async fn tenant(&self, name: &str) -> Option<CoreReader<D,T>> {
+        self.tenants.read().await.get(name).map(|tenant| tenant.reader())
+    }
+
+    /// A reader of the tree that replaced this reader's one, `None` if it wasn't reloaded since this reader was
+    /// made. Readers that live across requests swap themselves for this, so they let go of the old tree.
+    pub fn replaced(&self) -> Option<CoreReader<D,T>> {
+        let served = self.served.lock().unwrap();
+        if Arc::ptr_eq(&served.main_tracker, &self.main_tracker) {
+            None
+        } else {
+            Some(self.with_served(&served))
+        }
+    }
+
+    /// A reader of another tree and its trackers. Everything else is shared with this one, so the admin requests it
+    /// answers act on the same registry and the baseline status carries over. Fresh trackers only get the baseline
+    /// from [`CoreReader::send_last_baseline`].
+    pub(crate) fn with_served(&self, served: &ServedTree<D, T>) -> CoreReader<D,T> {
+        CoreReader {
+            tree: served.tree.reader(),
+            trackers: Arc::clone(&served.trackers),
+            main_tracker: Arc::clone(&served.main_tracker),
+            gauges: Arc::clone(&served.gauges),
+            flush_path: self.flush_path.clone(),
+            transient_trackers: self.transient_trackers.clone(),
+            baseline_state: Arc::clone(&self.baseline_state),
+            metrics: Arc::clone(&self.metrics),
+            tenants: Arc::clone(&self.tenants),
+            tree_loader: self.tree_loader.clone(),
+            served: Arc::clone(&self.served),
+        }
+    }
+
+    /// Swaps the tree this reader was made from, and its trackers, for another one behind the same handle. Every
+    /// reader of the handle picks it up on their next request.
+    pub(crate) fn swap_served(&self, served: ServedTree<D, T>) {
+        let old = std::mem::replace(&mut *self.served.lock().unwrap(), served);
+        // The old tree is dropped outside of the lock
+        drop(old);
+    }
+
+    /// A named tracker for this reader's tree. Named trackers are made on demand, so they catch up with the baseline
+    /// the others have.
+    pub(crate) async fn named_tracker(&self, tracker_name: &str) -> Result<TrackerOperator<T>, InternalServiceError> {
+        let tracker = TrackerWorker::operator(self.tree.clone(), Some(tracker_name.to_string()), Arc::clone(&self.gauges));
+        let baseline = self.baseline_state.lock().unwrap().baseline();
+        if let Some(baseline) = baseline {
+            tracker.message(TrackingRequest {
+                tracker_name: Some(tracker_name.to_string()),
+                request: TrackingRequestChoice::SetBaseline(SetBaselineRequest { baseline }),
+            }).await?;
+        }
+        Ok(tracker)
+    }
+
+    /// Swaps the last baseline into every tracker, for trackers that were made after it was set.
+    pub(crate) async fn send_last_baseline(&self) -> Result<(), InternalServiceError> {
+        let baseline = self.baseline_state.lock().unwrap().baseline();
+        match baseline {
+            Some(baseline) => send_baseline(baseline, &self.main_tracker, Some(&self.trackers)).await,
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) async fn register_tenant(&self, name: String, writer: CoverTreeWriter<D>) -> bool {
+        let mut tenants = self.tenants.write().await;
+        if tenants.contains_key(&name) {
+            return false;
+        }
+        let mut tenant = CoreWriter::new(writer);
+        tenant.metrics = Arc::clone(&self.metrics);
+        tenants.insert(name, Arc::new(tenant));
+        true
+    }
+}
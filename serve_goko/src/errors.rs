@@ -74,6 +74,9 @@ pub enum GokoClientError {
     Http(hyper::Error),
     Parse(Box<dyn std::error::Error + Send + Sync>),
     MissingBody,
+    ServerError(u16, String),
+    LimitExceeded(String),
+    PayloadTooLarge(usize),
 }
 
 impl GokoClientError {
@@ -114,6 +117,9 @@ impl fmt::Display for GokoClientError {
             GokoClientError::Http(ref se) => fmt::Display::fmt(se, f),
             GokoClientError::Parse(ref se) => fmt::Display::fmt(se, f),
             GokoClientError::MissingBody => f.pad("Body Missing"),
+            GokoClientError::ServerError(status, ref body) => write!(f, "Server responded with {}: {}", status, body),
+            GokoClientError::LimitExceeded(ref se) => fmt::Display::fmt(se, f),
+            GokoClientError::PayloadTooLarge(limit) => write!(f, "Body is larger than the maximum of {} bytes", limit),
         }
     }
 }
@@ -126,6 +132,9 @@ impl fmt::Debug for GokoClientError {
             GokoClientError::Http(ref se) => write!(f, "Http({:?})", se),
             GokoClientError::Parse(ref se) => write!(f, "Underlying({:?})", se),
             GokoClientError::MissingBody => f.pad("MissingBody"),
+            GokoClientError::ServerError(status, ref body) => write!(f, "ServerError({:?}, {:?})", status, body),
+            GokoClientError::LimitExceeded(ref se) => write!(f, "LimitExceeded({:?})", se),
+            GokoClientError::PayloadTooLarge(limit) => write!(f, "PayloadTooLarge({:?})", limit),
         }
     }
 }
@@ -138,6 +147,9 @@ impl Error for GokoClientError {
             GokoClientError::Parse(ref se) => se.source(),
             GokoClientError::MalformedQuery(_) => None,
             GokoClientError::MissingBody => None,
+            GokoClientError::ServerError(..) => None,
+            GokoClientError::LimitExceeded(_) => None,
+            GokoClientError::PayloadTooLarge(_) => None,
         }
     }
 }
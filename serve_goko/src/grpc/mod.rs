@@ -0,0 +1,278 @@
+//! # gRPC
+//!
+//! The same queries as the HTTP server, over gRPC with the messages in `protos/goko.proto`. The points are plain
+//! `repeated float`s, so this serves trees over `[f32]` point clouds. It shares the trackers with the HTTP server
+//! when both are made from the same [`CoreWriter`]:
+//!
+//! ```ignore
+//! let grpc = GokoGrpc::from_config(Arc::clone(&core), &config).into_service();
+//! tonic::transport::Server::builder().add_service(grpc).serve(addr).await?;
+//! ```
+//!
+//! The knn type queries are held to the same [`QueryLimits`] as the HTTP server's, a `k` of 0 is the default `k`.
+//!
+//! `TrackStream` tracks a stream of points in order and sends back the stats of one window after each point, so a
+//! client can follow the drift of a sequence without polling.
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::Stream;
+use pointcloud::*;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::api::*;
+use crate::config::ServerConfig;
+use crate::core::*;
+use crate::errors::{GokoClientError, InternalServiceError};
+use crate::http::QueryLimits;
+use crate::GokoResponse;
+
+/// The generated messages and service traits
+pub mod proto {
+    tonic::include_proto!("goko");
+}
+
+use proto::goko_server::{Goko, GokoServer};
+
+/// The gRPC service, see the [module docs](self).
+pub struct GokoGrpc<D: PointCloud> {
+    writer: Arc<CoreWriter<D, Vec<f32>>>,
+    limits: QueryLimits,
+}
+
+impl<D: PointCloud<Point = [f32]>> GokoGrpc<D> {
+    /// Serves the trees and trackers of the core, with the default [`QueryLimits`]. Make the HTTP server from the
+    /// same core to share the trackers with it.
+    pub fn new(writer: Arc<CoreWriter<D, Vec<f32>>>) -> GokoGrpc<D> {
+        GokoGrpc {
+            writer,
+            limits: QueryLimits::default(),
+        }
+    }
+
+    /// Creates the service with the query limits from the config.
+    pub fn from_config(writer: Arc<CoreWriter<D, Vec<f32>>>, config: &ServerConfig) -> GokoGrpc<D> {
+        GokoGrpc::new(writer).with_limits(config.limits)
+    }
+
+    /// Sets the limits on the query parameters, see [`QueryLimits`].
+    pub fn with_limits(mut self, limits: QueryLimits) -> GokoGrpc<D> {
+        self.limits = limits;
+        self
+    }
+
+    /// The `k` of a knn type request checked against the limits, 0 is the default.
+    fn k(&self, k: u32) -> Result<usize, Status> {
+        let k = if k == 0 { None } else { Some(k as usize) };
+        self.limits.k(k).map_err(|e| match e {
+            GokoClientError::LimitExceeded(message) => Status::invalid_argument(message),
+            e => Status::internal(e.to_string()),
+        })
+    }
+
+    /// Wraps this up for `tonic::transport::Server::add_service`.
+    pub fn into_service(self) -> GokoServer<GokoGrpc<D>> {
+        GokoServer::new(self)
+    }
+}
+
+fn internal(e: InternalServiceError) -> Status {
+    Status::internal(e.to_string())
+}
+
+fn tracker_name(name: String) -> Option<String> {
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+fn neighbors(knn: Vec<NamedDistance>) -> proto::KnnResponse {
+    let to_json = |v: Option<serde_json::Value>| v.map(|v| v.to_string()).unwrap_or_default();
+    proto::KnnResponse {
+        knn: knn.into_iter().map(|n| proto::Neighbor {
+            name: n.name.unwrap_or_default(),
+            distance: n.distance,
+            label_json: to_json(n.label),
+            metadata_json: to_json(n.metadata),
+        }).collect(),
+    }
+}
+
+fn stats(stats: CurrentStatsResponse) -> proto::CurrentStatsResponse {
+    proto::CurrentStatsResponse {
+        kl_div: stats.kl_div,
+        max: stats.max,
+        min: stats.min,
+        nz_count: stats.nz_count,
+        moment1_nz: stats.moment1_nz,
+        moment2_nz: stats.moment2_nz,
+        weighted_moment1_nz: stats.weighted_moment1_nz,
+        weighted_moment2_nz: stats.weighted_moment2_nz,
+        weight_nz: stats.weight_nz,
+        sequence_len: stats.sequence_len as u64,
+    }
+}
+
+fn unexpected<L: Summary>(response: GokoResponse<L>) -> Status {
+    match response {
+        GokoResponse::Tracking(TrackingResponse::Unknown(tracker_name, window_size)) => Status::not_found(format!("No tracker {:?} with window {:?}", tracker_name, window_size)),
+        GokoResponse::Unknown(message, _) => Status::invalid_argument(message),
+        _ => Status::internal("Unexpected response"),
+    }
+}
+
+fn point_fields(fields: Option<proto::PointFields>) -> PointFields {
+    let fields = fields.unwrap_or_default();
+    PointFields {
+        name: true,
+        label: fields.label,
+        metadata: fields.metadata,
+    }
+}
+
+async fn track_point<D: PointCloud<Point = [f32]>>(reader: &mut CoreReader<D, Vec<f32>>, tracker_name: Option<String>, request: TrackPointRequest<Vec<f32>>) -> Result<bool, Status> {
+    let request = crate::GokoRequest::Tracking(TrackingRequest {
+        tracker_name,
+        request: TrackingRequestChoice::TrackPoint(request),
+    });
+    match reader.process(request).await.map_err(internal)? {
+        GokoResponse::Tracking(TrackingResponse::TrackPath(r)) => Ok(r.success),
+        other => Err(unexpected(other)),
+    }
+}
+
+async fn current_stats<D: PointCloud<Point = [f32]>>(reader: &mut CoreReader<D, Vec<f32>>, tracker_name: Option<String>, window_size: usize) -> Result<proto::CurrentStatsResponse, Status> {
+    let request = crate::GokoRequest::Tracking(TrackingRequest {
+        tracker_name,
+        request: TrackingRequestChoice::CurrentStats(CurrentStatsRequest {
+            window_size,
+            weighting: Default::default(),
+        }),
+    });
+    match reader.process(request).await.map_err(internal)? {
+        GokoResponse::Tracking(TrackingResponse::CurrentStats(r)) => Ok(stats(r)),
+        other => Err(unexpected(other)),
+    }
+}
+
+type StatsStream = Pin<Box<dyn Stream<Item = Result<proto::CurrentStatsResponse, Status>> + Send + Sync + 'static>>;
+
+#[tonic::async_trait]
+impl<D: PointCloud<Point = [f32]>> Goko for GokoGrpc<D> {
+    async fn knn(&self, request: Request<proto::KnnRequest>) -> Result<Response<proto::KnnResponse>, Status> {
+        let request = request.into_inner();
+        let mut reader = self.writer.reader();
+        let knn = crate::GokoRequest::Knn(KnnRequest {
+            k: self.k(request.k)?,
+            point: request.point,
+            fields: point_fields(request.fields),
+            budget: self.limits.budget(),
+        });
+        match reader.process(knn).await.map_err(internal)? {
+            GokoResponse::Knn(knn) => Ok(Response::new(neighbors(knn.knn))),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn routing_knn(&self, request: Request<proto::KnnRequest>) -> Result<Response<proto::KnnResponse>, Status> {
+        let request = request.into_inner();
+        let mut reader = self.writer.reader();
+        let knn = crate::GokoRequest::RoutingKnn(RoutingKnnRequest {
+            k: self.k(request.k)?,
+            point: request.point,
+            fields: point_fields(request.fields),
+            budget: self.limits.budget(),
+        });
+        match reader.process(knn).await.map_err(internal)? {
+            GokoResponse::RoutingKnn(knn) => Ok(Response::new(neighbors(knn.routing_knn))),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn path(&self, request: Request<proto::PathRequest>) -> Result<Response<proto::PathResponse>, Status> {
+        let mut reader = self.writer.reader();
+        let path = crate::GokoRequest::Path(PathRequest { point: request.into_inner().point });
+        let path = match reader.process(path).await.map_err(internal)? {
+            GokoResponse::Path(path) => path,
+            other => return Err(unexpected(other)),
+        };
+        Ok(Response::new(proto::PathResponse {
+            path: path.path.into_iter().map(|n| proto::PathNode {
+                name: n.name,
+                layer: n.layer,
+                distance: n.distance,
+                label_summary_json: n.label_summary.map(|s| serde_json::to_string(&s).unwrap_or_default()).unwrap_or_default(),
+            }).collect(),
+        }))
+    }
+
+    async fn add_tracker(&self, request: Request<proto::AddTrackerRequest>) -> Result<Response<proto::TrackPointResponse>, Status> {
+        let request = request.into_inner();
+        let mut reader = self.writer.reader();
+        let tracking = crate::GokoRequest::Tracking(TrackingRequest {
+            tracker_name: tracker_name(request.tracker_name),
+            request: TrackingRequestChoice::AddTracker(AddTrackerRequest {
+                window_size: request.window_size as usize,
+                unique_visitors: request.unique_visitors,
+            }),
+        });
+        match reader.process(tracking).await.map_err(internal)? {
+            GokoResponse::Tracking(TrackingResponse::AddTracker(r)) => Ok(Response::new(proto::TrackPointResponse { success: r.success })),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn track_point(&self, request: Request<proto::TrackPointRequest>) -> Result<Response<proto::TrackPointResponse>, Status> {
+        let request = request.into_inner();
+        let mut reader = self.writer.reader();
+        let track = TrackPointRequest {
+            point: request.point,
+            query_id: if request.has_query_id { Some(request.query_id) } else { None },
+            segment: if request.segment.is_empty() { None } else { Some(request.segment) },
+        };
+        let success = track_point(&mut reader, tracker_name(request.tracker_name), track).await?;
+        Ok(Response::new(proto::TrackPointResponse { success }))
+    }
+
+    async fn current_stats(&self, request: Request<proto::CurrentStatsRequest>) -> Result<Response<proto::CurrentStatsResponse>, Status> {
+        let request = request.into_inner();
+        let mut reader = self.writer.reader();
+        let stats = current_stats(&mut reader, tracker_name(request.tracker_name), request.window_size as usize).await?;
+        Ok(Response::new(stats))
+    }
+
+    type TrackStreamStream = StatsStream;
+
+    async fn track_stream(&self, request: Request<Streaming<proto::TrackStreamRequest>>) -> Result<Response<Self::TrackStreamStream>, Status> {
+        let mut points = request.into_inner();
+        let mut reader = self.writer.reader();
+        let (snd, rcv) = mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Some(point) = points.next().await {
+                let result = match point {
+                    Ok(point) => {
+                        let name = tracker_name(point.tracker_name);
+                        let track = TrackPointRequest { point: point.point, query_id: None, segment: None };
+                        match track_point(&mut reader, name.clone(), track).await {
+                            Ok(_) => current_stats(&mut reader, name, point.window_size as usize).await,
+                            Err(e) => Err(e),
+                        }
+                    }
+                    Err(e) => Err(e),
+                };
+                let failed = result.is_err();
+                // The client hung up
+                if snd.send(result).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rcv)) as StatsStream))
+    }
+}
@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::RejectionMetrics;
+use crate::core::{Histogram, LATENCY_BUCKETS_US};
+
+/// Latency histograms of the queries that walk a path down the tree, keyed by the length of that path. If the slow
+/// queries pile up at one depth they're running into a particular region of the tree. One set is shared by all the
+/// connections of a [`crate::http::MakeGokoHttp`]. It's served as JSON at `GET /latency_by_depth` and in
+/// Prometheus' format at `GET /metrics`.
+#[derive(Debug, Default)]
+pub struct LatencyByDepth {
+    histograms: Mutex<BTreeMap<usize, Histogram>>,
+}
+
+/// A latency bucket, not cumulative.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyBucket {
+    /// The upper bound of the bucket in microseconds, `None` for the overflow bucket
+    pub le_us: Option<u64>,
+    /// The number of queries in the bucket
+    pub count: u64,
+}
+
+/// The latencies of the queries that reached one depth.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepthLatency {
+    /// The length of the path the queries walked
+    pub depth: usize,
+    /// The number of queries
+    pub count: u64,
+    /// Their mean latency in microseconds
+    pub mean_us: f64,
+    /// The histogram of their latencies
+    pub buckets: Vec<LatencyBucket>,
+}
+
+/// The body of `GET /latency_by_depth`, sorted by depth.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyByDepthResponse {
+    /// One entry per depth that has seen a query
+    pub depths: Vec<DepthLatency>,
+}
+
+impl LatencyByDepth {
+    pub(crate) fn record(&self, depth: usize, latency: Duration) {
+        self.histograms
+            .lock()
+            .unwrap()
+            .entry(depth)
+            .or_default()
+            .record(latency.as_micros() as u64);
+    }
+
+    /// A copy of the histograms.
+    pub fn snapshot(&self) -> LatencyByDepthResponse {
+        let histograms = self.histograms.lock().unwrap();
+        let depths = histograms
+            .iter()
+            .map(|(depth, h)| DepthLatency {
+                depth: *depth,
+                count: h.count(),
+                mean_us: h.sum_us() as f64 / h.count().max(1) as f64,
+                buckets: h
+                    .buckets()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, count)| LatencyBucket {
+                        le_us: LATENCY_BUCKETS_US.get(i).cloned(),
+                        count: *count,
+                    })
+                    .collect(),
+            })
+            .collect();
+        LatencyByDepthResponse { depths }
+    }
+}
+
+/// Writes the server's metrics in Prometheus' text format.
+pub(crate) fn prometheus_text(latency: &LatencyByDepth, rejections: &RejectionMetrics) -> String {
+    let mut text = String::new();
+    text.push_str("# HELP goko_query_latency_seconds Latency of the queries that walk a path, by path length.\n");
+    text.push_str("# TYPE goko_query_latency_seconds histogram\n");
+    for depth in latency.snapshot().depths {
+        let mut cumulative = 0;
+        for bucket in &depth.buckets {
+            cumulative += bucket.count;
+            let le = match bucket.le_us {
+                Some(le_us) => (le_us as f64 / 1.0e6).to_string(),
+                None => "+Inf".to_string(),
+            };
+            text.push_str(&format!(
+                "goko_query_latency_seconds_bucket{{depth=\"{}\",le=\"{}\"}} {}\n",
+                depth.depth, le, cumulative
+            ));
+        }
+        text.push_str(&format!(
+            "goko_query_latency_seconds_sum{{depth=\"{}\"}} {}\n",
+            depth.depth,
+            depth.mean_us * depth.count as f64 / 1.0e6
+        ));
+        text.push_str(&format!(
+            "goko_query_latency_seconds_count{{depth=\"{}\"}} {}\n",
+            depth.depth, depth.count
+        ));
+    }
+
+    let counts = rejections.counts();
+    text.push_str("# HELP goko_rejected_requests_total Requests turned away before they reached the tree.\n");
+    text.push_str("# TYPE goko_rejected_requests_total counter\n");
+    for (reason, count) in &[
+        ("payload_too_large", counts.payload_too_large),
+        ("limit_exceeded", counts.limit_exceeded),
+        ("malformed_query", counts.malformed_query),
+    ] {
+        text.push_str(&format!(
+            "goko_rejected_requests_total{{reason=\"{}\"}} {}\n",
+            reason, count
+        ));
+    }
+    text
+}
@@ -0,0 +1,134 @@
+use crate::errors::GokoClientError;
+use goko::QueryBudget;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Limits on the query parameters, and the values used when a parameter is missing.
+/// A query that asks for more than the limit is rejected with a 422, a body that's too large with a 413.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueryLimits {
+    /// The largest `k` a knn type query can ask for
+    pub max_k: usize,
+    /// The `k` used when the query doesn't give one
+    pub default_k: usize,
+    /// The largest radius a range query can ask for
+    pub max_radius: f32,
+    /// The radius used when a range query doesn't give one
+    pub default_radius: f32,
+    /// The largest request body the server reads, in bytes. The body is rejected as soon as it's known to be larger,
+    /// either from its `content-length` or while it streams in, so it's never buffered in full.
+    pub max_body_bytes: usize,
+    /// The most nodes a knn type query opens up before it gives up and returns what it has, flagged `truncated`
+    pub max_query_nodes: Option<usize>,
+    /// The longest a knn type query runs for before it gives up and returns what it has, flagged `truncated`
+    pub max_query_millis: Option<u64>,
+    /// The most points a bulk tracking request can have
+    pub max_bulk_points: usize,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        QueryLimits {
+            max_k: 1000,
+            default_k: 10,
+            max_radius: f32::MAX,
+            default_radius: 1.0,
+            max_body_bytes: 16 * 1024 * 1024,
+            max_query_nodes: None,
+            max_query_millis: None,
+            max_bulk_points: 100_000,
+        }
+    }
+}
+
+impl QueryLimits {
+    /// Checks a requested `k` against the limits, filling in the default if it's missing.
+    pub fn k(&self, k: Option<usize>) -> Result<usize, GokoClientError> {
+        match k {
+            None => Ok(self.default_k),
+            Some(k) if k > self.max_k => Err(GokoClientError::LimitExceeded(format!(
+                "k={} is larger than the maximum of {}",
+                k, self.max_k
+            ))),
+            Some(k) => Ok(k),
+        }
+    }
+
+    /// Checks the number of points in a bulk request against the limits.
+    pub fn bulk_points(&self, len: usize) -> Result<(), GokoClientError> {
+        if len > self.max_bulk_points {
+            Err(GokoClientError::LimitExceeded(format!(
+                "{} points is more than the maximum of {}",
+                len, self.max_bulk_points
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The budget knn type queries run on, see [`goko::QueryBudget`].
+    pub fn budget(&self) -> QueryBudget {
+        QueryBudget {
+            max_nodes: self.max_query_nodes,
+            max_time: self.max_query_millis.map(Duration::from_millis),
+        }
+    }
+
+    /// Checks a requested radius against the limits, filling in the default if it's missing.
+    pub fn radius(&self, radius: Option<f32>) -> Result<f32, GokoClientError> {
+        match radius {
+            None => Ok(self.default_radius),
+            Some(r) if r.is_nan() || r > self.max_radius => {
+                Err(GokoClientError::LimitExceeded(format!(
+                    "radius={} is larger than the maximum of {}",
+                    r, self.max_radius
+                )))
+            }
+            Some(r) => Ok(r),
+        }
+    }
+}
+
+/// Counts of the requests that were turned away before they reached the tree. One set of counters is shared by all
+/// the connections of a [`crate::http::MakeGokoHttp`], get it with [`crate::http::MakeGokoHttp::rejections`].
+#[derive(Debug, Default)]
+pub struct RejectionMetrics {
+    payload_too_large: AtomicU64,
+    limit_exceeded: AtomicU64,
+    malformed_query: AtomicU64,
+}
+
+/// A copy of the [`RejectionMetrics`] counters at one moment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RejectionCounts {
+    /// Requests rejected with a 413 because their body was over [`QueryLimits::max_body_bytes`]
+    pub payload_too_large: u64,
+    /// Requests rejected with a 422 because a parameter was over its limit
+    pub limit_exceeded: u64,
+    /// Requests rejected because their query couldn't be parsed
+    pub malformed_query: u64,
+}
+
+impl RejectionMetrics {
+    /// Counts the rejection of a request that failed with this error. Errors that aren't rejections are ignored.
+    pub(crate) fn record(&self, error: &GokoClientError) {
+        let counter = match error {
+            GokoClientError::PayloadTooLarge(_) => &self.payload_too_large,
+            GokoClientError::LimitExceeded(_) => &self.limit_exceeded,
+            GokoClientError::MalformedQuery(_) => &self.malformed_query,
+            _ => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Reads the counters.
+    pub fn counts(&self) -> RejectionCounts {
+        RejectionCounts {
+            payload_too_large: self.payload_too_large.load(Ordering::Relaxed),
+            limit_exceeded: self.limit_exceeded.load(Ordering::Relaxed),
+            malformed_query: self.malformed_query.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -13,13 +13,17 @@ use std::task::Poll;
 use std::marker::PhantomData;
 use std::ops::Deref;
 
-use super::GokoHttp;
+use super::{GokoHttp, LatencyByDepth, QueryLimits, RejectionMetrics};
 use crate::parsers::{PointParser, PointBuffer};
 use crate::core::*;
+use crate::config::ServerConfig;
 
 pub struct MakeGokoHttp<D: PointCloud, P: PointParser> {
     writer: Arc<CoreWriter<D, P::Point>>,
     parser: PhantomData<P>,
+    limits: QueryLimits,
+    rejections: Arc<RejectionMetrics>,
+    latency: Arc<LatencyByDepth>,
 }
 
 impl<D, P> MakeGokoHttp<D, P>
@@ -32,8 +36,32 @@ where
         MakeGokoHttp { 
             writer,
             parser: PhantomData,
+            limits: QueryLimits::default(),
+            rejections: Arc::new(RejectionMetrics::default()),
+            latency: Arc::new(LatencyByDepth::default()),
         }
     }
+
+    /// Creates the service with the query limits from the config.
+    pub fn from_config(writer: Arc<CoreWriter<D, P::Point>>, config: &ServerConfig) -> MakeGokoHttp<D, P> {
+        MakeGokoHttp::new(writer).with_limits(config.limits)
+    }
+
+    /// Sets the limits on the query parameters, see [`QueryLimits`].
+    pub fn with_limits(mut self, limits: QueryLimits) -> MakeGokoHttp<D, P> {
+        self.limits = limits;
+        self
+    }
+
+    /// The counts of the requests turned away by the limits, shared by every connection this makes.
+    pub fn rejections(&self) -> Arc<RejectionMetrics> {
+        Arc::clone(&self.rejections)
+    }
+
+    /// The latency histograms of the path queries, shared by every connection this makes.
+    pub fn latency_by_depth(&self) -> Arc<LatencyByDepth> {
+        Arc::clone(&self.latency)
+    }
 }
 
 impl<D, T, P> Service<T> for MakeGokoHttp<D, P>
@@ -52,7 +80,7 @@ where
 
     fn call(&mut self, _: T) -> Self::Future {
         let reader = self.writer.reader();
-        let parser = PointBuffer::<P>::new();
-        future::ready(Ok(GokoHttp::new(reader, parser)))
+        let parser = PointBuffer::<P>::new(self.limits.max_body_bytes);
+        future::ready(Ok(GokoHttp::new(reader, parser, self.limits, Arc::clone(&self.rejections), Arc::clone(&self.latency))))
     }
 }
\ No newline at end of file
@@ -1,7 +1,16 @@
+mod latency;
+mod limits;
 mod maker;
 mod message;
+mod query;
 mod service;
+mod shutdown;
 
 pub use service::GokoHttp;
 pub use message::ResponseFuture;
-pub use maker::MakeGokoHttp;
\ No newline at end of file
+pub use maker::MakeGokoHttp;
+pub use limits::{QueryLimits, RejectionCounts, RejectionMetrics};
+pub use latency::{LatencyByDepth, LatencyByDepthResponse, DepthLatency, LatencyBucket};
+pub use crate::core::LATENCY_BUCKETS_US;
+pub(crate) use query::{percent_decode, percent_encode};
+pub use shutdown::shutdown_signal;
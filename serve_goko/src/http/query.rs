@@ -0,0 +1,43 @@
+//! Percent encoding for the values of query strings. The client encodes every name and path it puts in a query and
+//! the server decodes them, so a value can hold `&`, `=`, spaces and anything else outside of the unreserved
+//! characters of RFC 3986.
+
+use crate::errors::GokoClientError;
+
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~')
+}
+
+/// Encodes everything but the unreserved characters as `%XX`, byte by byte.
+pub(crate) fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if is_unreserved(byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Decodes the `%XX` escapes of a query value. A `+` is left as it is, the client never sends one for a space.
+pub(crate) fn percent_decode(value: &str) -> Result<String, GokoClientError> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let byte = bytes.get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or(GokoClientError::MalformedQuery("Invalid percent escape in the query."))?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| GokoClientError::MalformedQuery("The query isn't UTF-8 once decoded."))
+}
@@ -20,12 +20,23 @@ use std::ops::Deref;
 use regex::Regex;
 use lazy_static::lazy_static;
 use super::message::*;
+use super::{LatencyByDepth, QueryLimits, RejectionMetrics};
+use super::latency::prometheus_text;
+use super::percent_decode;
 use crate::errors::InternalServiceError;
 use crate::PointParser;
 use crate::parsers::PointBuffer;
 use crate::errors::*;
 use crate::api::*;
 use crate::core::*;
+use goko::plugins::discrete::tracker::CoverageWeighting;
+use std::time::Instant;
+use fxhash::FxHasher64;
+use std::hash::Hasher;
+use tracing::{debug, field, info_span, warn, Instrument};
+
+/// Every request gets an id from this, it's on the request's span so that the logs of one request can be pulled out.
+static NEXT_REQUEST_ID: atomic::AtomicU64 = atomic::AtomicU64::new(0);
 
 
 pub struct GokoHttp<D: PointCloud, P: PointParser> {
@@ -37,27 +48,145 @@ pub struct GokoHttp<D: PointCloud, P: PointParser> {
 }
 
 
-fn parse_knn_query(uri: &Uri) -> usize {
+fn parse_knn_query(uri: &Uri, limits: &QueryLimits) -> Result<usize, GokoClientError> {
     lazy_static! {
         static ref RE: Regex = Regex::new(r"k=(?P<k>\d+)").unwrap();
     }
 
+    let k = match uri.query().map(|s| RE.captures(s)).flatten() {
+        Some(caps) => Some(
+            caps["k"]
+                .parse::<usize>()
+                .map_err(|_| GokoClientError::MalformedQuery("Unable to parse k."))?,
+        ),
+        None => None,
+    };
+    limits.k(k)
+}
+
+fn parse_fields_query(uri: &Uri) -> PointFields {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"fields=(?P<fields>[\w,]+)").unwrap();
+    }
+
     match uri.query().map(|s| RE.captures(s)).flatten() {
-        Some(caps) => caps["k"].parse::<usize>().unwrap(),
-        None => 10,
+        Some(caps) => {
+            let mut fields = PointFields {
+                name: false,
+                label: false,
+                metadata: false,
+            };
+            for field in caps["fields"].split(',') {
+                match field {
+                    "name" => fields.name = true,
+                    "label" => fields.label = true,
+                    "metadata" => fields.metadata = true,
+                    _ => (),
+                }
+            }
+            fields
+        }
+        None => PointFields::default(),
     }
 }
 
-fn parse_tracker_query(uri: &Uri) -> (Option<String>, Option<usize>) {
+fn parse_name_query(uri: &Uri) -> Result<String, GokoClientError> {
     lazy_static! {
-        static ref RE_TRACKER: Regex = Regex::new(r"tracker_name=(?P<tracker_name>\w+)").unwrap();
+        static ref RE: Regex = Regex::new(r"\bname=(?P<name>[^&]+)").unwrap();
     }
+
+    match uri.query().map(|s| RE.captures(s)).flatten() {
+        Some(caps) => percent_decode(&caps["name"]),
+        None => Err(GokoClientError::MalformedQuery("Unable to parse name.")),
+    }
+}
+
+fn parse_id_query(uri: &Uri) -> Result<ExternalId, GokoClientError> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"\bid=(?P<id>[^&]+)").unwrap();
+    }
+
+    match uri.query().map(|s| RE.captures(s)).flatten() {
+        Some(caps) => Ok(ExternalId::parse(&percent_decode(&caps["id"])?)),
+        None => Err(GokoClientError::MalformedQuery("Unable to parse id.")),
+    }
+}
+
+fn parse_path_query(uri: &Uri) -> Result<String, GokoClientError> {
     lazy_static! {
-        static ref RE_WINDOW: Regex = Regex::new(r"window_size=(?P<window_size>\d+)").unwrap();
+        static ref RE: Regex = Regex::new(r"\bpath=(?P<path>[^&]+)").unwrap();
+    }
+
+    match uri.query().map(|s| RE.captures(s)).flatten() {
+        Some(caps) => percent_decode(&caps["path"]),
+        None => Err(GokoClientError::MalformedQuery("Unable to parse path.")),
+    }
+}
+
+fn parse_migrate_trackers_query(uri: &Uri) -> bool {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"migrate_trackers=(?P<migrate_trackers>\w+)").unwrap();
+    }
+
+    match uri.query().map(|s| RE.captures(s)).flatten() {
+        Some(caps) => &caps["migrate_trackers"] == "true",
+        None => false,
+    }
+}
+
+/// Splits `/tree/NAME/rest?query` into the tree's name and the request to `/rest?query` it's for. The name is percent
+/// decoded, one that doesn't decode is left as it is and matches no tree.
+fn tenant_request(mut request: Request<Body>) -> (Option<String>, Request<Body>) {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^/tree/(?P<name>[^/]+)(?P<rest>/.*)?$").unwrap();
+    }
+
+    let (name, path_and_query) = match RE.captures(request.uri().path()) {
+        Some(caps) => {
+            let rest = caps.name("rest").map(|m| m.as_str()).unwrap_or("/");
+            let path_and_query = match request.uri().query() {
+                Some(query) => format!("{}?{}", rest, query),
+                None => rest.to_string(),
+            };
+            let name = percent_decode(&caps["name"]).unwrap_or_else(|_| caps["name"].to_string());
+            (name, path_and_query)
+        }
+        None => return (None, request),
+    };
+    match path_and_query.parse::<Uri>() {
+        Ok(uri) => {
+            *request.uri_mut() = uri;
+            (Some(name), request)
+        }
+        Err(_) => (None, request),
+    }
+}
+
+fn parse_weighting_query(uri: &Uri) -> CoverageWeighting {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"weighting=(?P<weighting>\w+)").unwrap();
+    }
+
+    match uri.query().map(|s| RE.captures(s)).flatten() {
+        Some(caps) => match &caps["weighting"] {
+            "ln_fraction" => CoverageWeighting::LnFraction,
+            _ => CoverageWeighting::Fraction,
+        },
+        None => CoverageWeighting::default(),
+    }
+}
+
+/// The tracker name can be any string the tracker was added under, it's percent decoded.
+fn parse_tracker_query(uri: &Uri) -> Result<(Option<String>, Option<usize>), GokoClientError> {
+    lazy_static! {
+        static ref RE_TRACKER: Regex = Regex::new(r"\btracker_name=(?P<tracker_name>[^&]+)").unwrap();
+    }
+    lazy_static! {
+        static ref RE_WINDOW: Regex = Regex::new(r"\bwindow_size=(?P<window_size>\d+)").unwrap();
     }
 
     let tracker_name = match uri.query().map(|s| RE_TRACKER.captures(s)).flatten() {
-        Some(caps) => caps["tracker_name"].parse::<String>().ok(),
+        Some(caps) => Some(percent_decode(&caps["tracker_name"])?),
         None => None,
     };
 
@@ -65,35 +194,134 @@ fn parse_tracker_query(uri: &Uri) -> (Option<String>, Option<usize>) {
         Some(caps) => caps["window_size"].parse::<usize>().ok(),
         None => None,
     };
-    (tracker_name, window_size)
+    Ok((tracker_name, window_size))
+}
+
+fn parse_old_window_query(uri: &Uri) -> Option<usize> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"old_window_size=(?P<window_size>\d+)").unwrap();
+    }
+
+    match uri.query().map(|s| RE.captures(s)).flatten() {
+        Some(caps) => caps["window_size"].parse::<usize>().ok(),
+        None => None,
+    }
+}
+
+fn parse_unique_visitors_query(uri: &Uri) -> bool {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"unique_visitors=(?P<unique_visitors>\w+)").unwrap();
+    }
+
+    match uri.query().map(|s| RE.captures(s)).flatten() {
+        Some(caps) => &caps["unique_visitors"] == "true",
+        None => false,
+    }
 }
 
-pub(crate) async fn parse_http<P: PointParser>(request: Request<Body>, parser: &mut PointBuffer<P>) -> Result<GokoRequest<P::Point>, GokoClientError> {
+fn parse_top_drift_query(uri: &Uri) -> usize {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"\bn=(?P<n>\d+)").unwrap();
+    }
+
+    match uri.query().map(|s| RE.captures(s)).flatten() {
+        Some(caps) => caps["n"].parse::<usize>().unwrap_or(20),
+        None => 20,
+    }
+}
+
+fn parse_scale_index_query(uri: &Uri) -> Result<i32, GokoClientError> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"scale_index=(?P<scale_index>-?\d+)").unwrap();
+    }
+
+    match uri.query().map(|s| RE.captures(s)).flatten() {
+        Some(caps) => caps["scale_index"]
+            .parse::<i32>()
+            .map_err(|_| GokoClientError::MalformedQuery("Unable to parse scale_index.")),
+        None => Err(GokoClientError::MalformedQuery("Unable to parse scale_index.")),
+    }
+}
+
+/// The segment is percent decoded.
+fn parse_segment_query(uri: &Uri) -> Result<Option<String>, GokoClientError> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"\bsegment=(?P<segment>[^&]+)").unwrap();
+    }
+
+    match uri.query().map(|s| RE.captures(s)).flatten() {
+        Some(caps) => percent_decode(&caps["segment"]).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// The query id can be any string, it's percent decoded and hashed down to the id the sketches use. The hash is
+/// fixed, so an id maps to the same sketch id across restarts and releases and the flushed sketches stay valid.
+fn parse_query_id_query(uri: &Uri) -> Result<Option<u64>, GokoClientError> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"query_id=(?P<query_id>[^&]+)").unwrap();
+    }
+
+    match uri.query().map(|s| RE.captures(s)).flatten() {
+        Some(caps) => {
+            let mut hasher = FxHasher64::default();
+            hasher.write(percent_decode(&caps["query_id"])?.as_bytes());
+            Ok(Some(hasher.finish()))
+        }
+        None => Ok(None),
+    }
+}
+
+pub(crate) async fn parse_http<P: PointParser>(request: Request<Body>, parser: &mut PointBuffer<P>, limits: &QueryLimits) -> Result<GokoRequest<P::Point>, GokoClientError> {
     match (request.method(), request.uri().path()) {
         // Serve some instructions at /
         (&Method::GET, "/") => Ok(GokoRequest::Parameters(ParametersRequest)),
+        (&Method::GET, "/stats") => Ok(GokoRequest::TreeStats(TreeStatsRequest)),
         (&Method::GET, "/knn") => {
-            let k = parse_knn_query(request.uri());
+            let k = parse_knn_query(request.uri(), limits)?;
+            let fields = parse_fields_query(request.uri());
             let point = parser.point(request).await?;
-            Ok(GokoRequest::Knn(KnnRequest { point, k }))
+            Ok(GokoRequest::Knn(KnnRequest { point, k, fields, budget: limits.budget() }))
         }
         (&Method::GET, "/routing_knn") => {
-            let k = parse_knn_query(request.uri());
+            let k = parse_knn_query(request.uri(), limits)?;
+            let fields = parse_fields_query(request.uri());
             let point = parser.point(request).await?;
-            Ok(GokoRequest::RoutingKnn(RoutingKnnRequest { point, k }))
+            Ok(GokoRequest::RoutingKnn(RoutingKnnRequest { point, k, fields, budget: limits.budget() }))
 
         }
+        (&Method::GET, "/knn_by_name") => {
+            let k = parse_knn_query(request.uri(), limits)?;
+            let fields = parse_fields_query(request.uri());
+            let name = parse_name_query(request.uri())?;
+            Ok(GokoRequest::KnnByName(KnnByNameRequest { name, k, fields, budget: limits.budget() }))
+        }
+        (&Method::GET, "/known_path_by_name") => {
+            let name = parse_name_query(request.uri())?;
+            Ok(GokoRequest::KnownPathByName(KnownPathByNameRequest { name }))
+        }
+        (&Method::GET, "/knn_by_id") => {
+            let k = parse_knn_query(request.uri(), limits)?;
+            let fields = parse_fields_query(request.uri());
+            let id = parse_id_query(request.uri())?;
+            Ok(GokoRequest::KnnById(KnnByIdRequest { id, k, fields, budget: limits.budget() }))
+        }
+        (&Method::GET, "/known_path_by_id") => {
+            let id = parse_id_query(request.uri())?;
+            Ok(GokoRequest::KnownPathById(KnownPathByIdRequest { id }))
+        }
         (&Method::GET, "/path") => {
             let point = parser.point(request).await?;
             Ok(GokoRequest::Path(PathRequest { point }))
 
         }
         (&Method::POST, "/track/add") => {
-            let (tracker_name, window_size) = parse_tracker_query(request.uri());
+            let (tracker_name, window_size) = parse_tracker_query(request.uri())?;
             if let Some(window_size) = window_size {
                 let request = TrackingRequestChoice::AddTracker(
                     AddTrackerRequest {
                         window_size,
+                        unique_visitors: parse_unique_visitors_query(request.uri()),
                     }
                 );
                 let tracking_request = TrackingRequest {
@@ -106,11 +334,32 @@ pub(crate) async fn parse_http<P: PointParser>(request: Request<Body>, parser: &
             }
         }
         (&Method::POST, "/track/point") => {
-            let (tracker_name, _window_size) = parse_tracker_query(request.uri());
+            let (tracker_name, _window_size) = parse_tracker_query(request.uri())?;
+            let query_id = parse_query_id_query(request.uri())?;
+            let segment = parse_segment_query(request.uri())?;
             let point = parser.point(request).await?;
             let request = TrackingRequestChoice::TrackPoint(
                 TrackPointRequest {
                     point,
+                    query_id,
+                    segment,
+                }
+            );
+            let tracking_request = TrackingRequest {
+                tracker_name,
+                request,
+            };
+            Ok(GokoRequest::Tracking(tracking_request))
+        }
+        (&Method::POST, "/track/bulk") => {
+            let (tracker_name, _window_size) = parse_tracker_query(request.uri())?;
+            let segment = parse_segment_query(request.uri())?;
+            let points = parser.points(request).await?;
+            limits.bulk_points(points.len())?;
+            let request = TrackingRequestChoice::TrackBulk(
+                TrackBulkRequest {
+                    points,
+                    segment,
                 }
             );
             let tracking_request = TrackingRequest {
@@ -120,11 +369,49 @@ pub(crate) async fn parse_http<P: PointParser>(request: Request<Body>, parser: &
             Ok(GokoRequest::Tracking(tracking_request))
         }
         (&Method::GET, "/track/stats") => {
-            let (tracker_name, window_size) = parse_tracker_query(request.uri());
+            let (tracker_name, window_size) = parse_tracker_query(request.uri())?;
             if let Some(window_size) = window_size {
                 let request = TrackingRequestChoice::CurrentStats(
                     CurrentStatsRequest {
                         window_size,
+                        weighting: parse_weighting_query(request.uri()),
+                    }
+                );
+                let tracking_request = TrackingRequest {
+                    tracker_name,
+                    request,
+                };
+                Ok(GokoRequest::Tracking(tracking_request))
+            } else {
+                Err(GokoClientError::MalformedQuery("Unable to parse window_size."))
+            }
+        }
+        (&Method::GET, "/track/segment_stats") => {
+            let (tracker_name, window_size) = parse_tracker_query(request.uri())?;
+            if let Some(window_size) = window_size {
+                let request = TrackingRequestChoice::SegmentStats(
+                    SegmentStatsRequest {
+                        window_size,
+                        segment: parse_segment_query(request.uri())?,
+                        weighting: parse_weighting_query(request.uri()),
+                    }
+                );
+                let tracking_request = TrackingRequest {
+                    tracker_name,
+                    request,
+                };
+                Ok(GokoRequest::Tracking(tracking_request))
+            } else {
+                Err(GokoClientError::MalformedQuery("Unable to parse window_size."))
+            }
+        }
+        (&Method::POST, "/track/resize") => {
+            let (tracker_name, window_size) = parse_tracker_query(request.uri())?;
+            if let Some(window_size) = window_size {
+                let request = TrackingRequestChoice::ResizeTracker(
+                    ResizeTrackerRequest {
+                        window_size,
+                        old_window_size: parse_old_window_query(request.uri()),
                     }
                 );
                 let tracking_request = TrackingRequest {
@@ -136,19 +423,144 @@ pub(crate) async fn parse_http<P: PointParser>(request: Request<Body>, parser: &
                 Err(GokoClientError::MalformedQuery("Unable to parse window_size."))
             }
         }
+        (&Method::POST, "/track/reset") => {
+            let (tracker_name, window_size) = parse_tracker_query(request.uri())?;
+            let request = TrackingRequestChoice::ResetTracker(
+                ResetTrackerRequest {
+                    window_size,
+                }
+            );
+            let tracking_request = TrackingRequest {
+                tracker_name,
+                request,
+            };
+            Ok(GokoRequest::Tracking(tracking_request))
+        }
+        (&Method::GET, "/track/top_drift") => {
+            let (tracker_name, window_size) = parse_tracker_query(request.uri())?;
+            let request = TrackingRequestChoice::TopDrift(
+                TopDriftRequest {
+                    window_size,
+                    n: parse_top_drift_query(request.uri()),
+                }
+            );
+            let tracking_request = TrackingRequest {
+                tracker_name,
+                request,
+            };
+            Ok(GokoRequest::Tracking(tracking_request))
+        }
+        (&Method::GET, "/track/drift_rollup") => {
+            let (tracker_name, window_size) = parse_tracker_query(request.uri())?;
+            let request = TrackingRequestChoice::DriftRollup(
+                DriftRollupRequest {
+                    window_size,
+                    scale_index: parse_scale_index_query(request.uri())?,
+                }
+            );
+            let tracking_request = TrackingRequest {
+                tracker_name,
+                request,
+            };
+            Ok(GokoRequest::Tracking(tracking_request))
+        }
+        (&Method::GET, "/track/snapshot") => {
+            Ok(GokoRequest::StatsSnapshot(StatsSnapshotRequest {
+                weighting: parse_weighting_query(request.uri()),
+            }))
+        }
+        (&Method::GET, "/track/baseline/status") => Ok(GokoRequest::BaselineStatus(BaselineStatusRequest)),
+        (&Method::POST, "/admin/flush") => Ok(GokoRequest::Flush(FlushRequest)),
+        (&Method::GET, "/admin/trees") => Ok(GokoRequest::ListTrees(ListTreesRequest)),
+        (&Method::POST, "/admin/trees/register") => {
+            let name = parse_name_query(request.uri())?;
+            let path = parse_path_query(request.uri())?;
+            Ok(GokoRequest::RegisterTree(RegisterTreeRequest { name, path: path.into() }))
+        }
+        (&Method::POST, "/admin/trees/reload") => {
+            let name = parse_name_query(request.uri()).ok();
+            let path = parse_path_query(request.uri())?;
+            Ok(GokoRequest::ReloadTree(ReloadTreeRequest {
+                name,
+                path: path.into(),
+                migrate_trackers: parse_migrate_trackers_query(request.uri()),
+            }))
+        }
+        (&Method::POST, "/admin/trees/unregister") => {
+            let name = parse_name_query(request.uri())?;
+            Ok(GokoRequest::UnregisterTree(UnregisterTreeRequest { name }))
+        }
         // The 404 Not Found route...
         _ => Ok(GokoRequest::Unknown(String::new(), 404)),
     }
 }
 
-pub(crate) fn into_http<L: Summary + Serialize>(response: GokoResponse<L>) -> Result<Response<Body>, GokoClientError> {
-    let mut builder = http::response::Builder::new();
+/// The routes about the server itself rather than the tree. These are answered by the HTTP service directly.
+fn metrics_response<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync + 'static>(request: &Request<Body>, reader: &CoreReader<D, T>, latency: &LatencyByDepth, rejections: &RejectionMetrics) -> Option<Response<Body>> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            let mut text = prometheus_text(latency, rejections);
+            text.push_str(&reader.prometheus_text());
+            Some(
+                http::response::Builder::new()
+                    .header("content-type", "text/plain; version=0.0.4")
+                    .body(Body::from(text))
+                    .unwrap(),
+            )
+        }
+        (&Method::GET, "/latency_by_depth") => Some(
+            http::response::Builder::new()
+                .body(Body::from(serde_json::to_string(&latency.snapshot()).unwrap()))
+                .unwrap(),
+        ),
+        _ => None,
+    }
+}
+
+fn parse_epoch_query(uri: &Uri) -> Option<u64> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"require_epoch=(?P<epoch>\d+)").unwrap();
+    }
+
+    match uri.query().map(|s| RE.captures(s)).flatten() {
+        Some(caps) => caps["epoch"].parse::<u64>().ok(),
+        None => None,
+    }
+}
+
+/// Serializes the response and stamps it with the epoch, the JSON objects get an `epoch` field.
+fn epoch_json<R: Serialize>(response: &R, epoch: u64) -> String {
+    let mut value = serde_json::to_value(response).unwrap();
+    if let serde_json::Value::Object(map) = &mut value {
+        map.insert("epoch".to_string(), epoch.into());
+    }
+    value.to_string()
+}
+
+pub(crate) fn into_http<L: Summary + Serialize>(response: GokoResponse<L>, epoch: u64) -> Result<Response<Body>, GokoClientError> {
+    let mut builder = http::response::Builder::new().header("goko-epoch", epoch);
     let json_str = match response {
-        GokoResponse::Parameters(p) => serde_json::to_string(&p).unwrap(),
-        GokoResponse::Knn(p) => serde_json::to_string(&p).unwrap(),
-        GokoResponse::RoutingKnn(p) => serde_json::to_string(&p).unwrap(),
-        GokoResponse::Path(p) => serde_json::to_string(&p).unwrap(),
-        GokoResponse::Tracking(p) => serde_json::to_string(&p).unwrap(),
+        GokoResponse::Parameters(p) => epoch_json(&p, epoch),
+        GokoResponse::TreeStats(p) => epoch_json(&p, epoch),
+        GokoResponse::Knn(p) => epoch_json(&p, epoch),
+        GokoResponse::RoutingKnn(p) => epoch_json(&p, epoch),
+        GokoResponse::Path(p) => epoch_json(&p, epoch),
+        GokoResponse::Tracking(p) => epoch_json(&p, epoch),
+        GokoResponse::Flush(p) => epoch_json(&p, epoch),
+        GokoResponse::StatsSnapshot(p) => epoch_json(&p, epoch),
+        GokoResponse::BaselineStatus(p) => epoch_json(&p, epoch),
+        GokoResponse::RegisterTree(p) => epoch_json(&p, epoch),
+        GokoResponse::UnregisterTree(p) => epoch_json(&p, epoch),
+        GokoResponse::ReloadTree(p) => epoch_json(&p, epoch),
+        GokoResponse::ListTrees(p) => epoch_json(&p, epoch),
+        GokoResponse::StaleEpoch(p) => {
+            builder = builder.status(409);
+            serde_json::to_string(&p).unwrap()
+        }
+        GokoResponse::PayloadTooLarge(p) => {
+            builder = builder.status(413);
+            serde_json::to_string(&p).unwrap()
+        }
         GokoResponse::Unknown(response_string, status) => {
             builder = builder.status(status);
             response_string
@@ -164,27 +576,85 @@ where
     P::Point: Deref<Target = D::Point> + Send + Sync + 'static,
     D::LabelSummary: Serialize,
 {
-    pub(crate) fn new(mut reader: CoreReader<D, P::Point>, mut parser: PointBuffer<P>) -> GokoHttp<D, P> {
+    pub(crate) fn new(mut reader: CoreReader<D, P::Point>, mut parser: PointBuffer<P>, limits: QueryLimits, rejections: Arc<RejectionMetrics>, latency: Arc<LatencyByDepth>) -> GokoHttp<D, P> {
         let (request_snd, mut request_rcv): (HttpRequestSender, HttpRequestReciever) =
             mpsc::unbounded_channel();
         tokio::spawn(async move {
             while let Some(mut msg) = request_rcv.recv().await {
                 if let Some(hyper_request) = msg.request() {
-                    let goko_request = parse_http(hyper_request, &mut parser).await;
-                    let response = match goko_request {
-                        Ok(r) => reader.process(r).await.map_err(|e| e.into()),
-                        Err(e) => {
-                            if let GokoClientError::MalformedQuery(s) = e {
-                                Ok(GokoResponse::Unknown(s.to_string(), 404))
-                            } else {
-                                Err(e)
+                    // Let go of the main tree if it was reloaded since the last request
+                    if let Some(current) = reader.replaced() {
+                        reader = current;
+                    }
+                    if let Some(response) = metrics_response(&hyper_request, &reader, &latency, &rejections) {
+                        msg.respond(Ok(response));
+                        continue;
+                    }
+                    let span = info_span!(
+                        "request",
+                        request_id = NEXT_REQUEST_ID.fetch_add(1, atomic::Ordering::Relaxed),
+                        method = %hyper_request.method(),
+                        path = hyper_request.uri().path(),
+                        epoch = field::Empty,
+                        status = field::Empty,
+                        latency_us = field::Empty,
+                    );
+                    let start = Instant::now();
+                    let (tree_name, hyper_request) = tenant_request(hyper_request);
+                    let mut tenant = match &tree_name {
+                        Some(tree_name) => match reader.tenant(tree_name).await {
+                            Some(tenant) => Some(tenant),
+                            None => {
+                                span.record("status", &404);
+                                let missing = GokoResponse::<D::LabelSummary>::Unknown(format!("No tree named {}", tree_name), 404);
+                                msg.respond(into_http(missing, reader.epoch()));
+                                continue;
                             }
                         },
+                        None => None,
                     };
-                    match response {
-                        Ok(resp) => msg.respond(into_http(resp)),
-                        Err(e) => msg.respond(Err(e)),
+                    let target = tenant.as_mut().unwrap_or(&mut reader);
+                    let required_epoch = parse_epoch_query(hyper_request.uri());
+                    let epoch = target.epoch();
+                    span.record("epoch", &epoch);
+                    let goko_request = parse_http(hyper_request, &mut parser, &limits).instrument(span.clone()).await;
+                    if let Err(e) = &goko_request {
+                        rejections.record(e);
+                    }
+                    let response = match goko_request {
+                        Ok(_) if required_epoch.map(|e| e != epoch).unwrap_or(false) => {
+                            Ok(GokoResponse::StaleEpoch(StaleEpochResponse {
+                                required_epoch: required_epoch.unwrap(),
+                                epoch,
+                            }))
+                        }
+                        Ok(r) => target.process(r).instrument(span.clone()).await.map_err(|e| e.into()),
+                        Err(GokoClientError::MalformedQuery(s)) => Ok(GokoResponse::Unknown(s.to_string(), 404)),
+                        Err(GokoClientError::LimitExceeded(s)) => Ok(GokoResponse::Unknown(s, 422)),
+                        Err(e @ GokoClientError::PayloadTooLarge(_)) => {
+                            span.in_scope(|| warn!(error = %e, "Rejected request body"));
+                            Ok(GokoResponse::PayloadTooLarge(PayloadTooLargeResponse {
+                                error: e.to_string(),
+                                max_body_bytes: limits.max_body_bytes,
+                            }))
+                        }
+                        Err(e) => Err(e),
                     };
+                    let depth = response.as_ref().ok().and_then(|resp| resp.depth());
+                    let response = response.and_then(|resp| into_http(resp, epoch));
+                    match &response {
+                        Ok(resp) => {
+                            span.record("status", &resp.status().as_u16());
+                        }
+                        Err(e) => span.in_scope(|| warn!(error = %e, "Request failed")),
+                    }
+                    let elapsed = start.elapsed();
+                    if let Some(depth) = depth {
+                        latency.record(depth, elapsed);
+                    }
+                    span.record("latency_us", &(elapsed.as_micros() as u64));
+                    span.in_scope(|| debug!("Request finished"));
+                    msg.respond(response);
                 } else {
                     msg.error(GokoClientError::Underlying(InternalServiceError::DoubleRead))
                 }
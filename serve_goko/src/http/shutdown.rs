@@ -0,0 +1,21 @@
+use tracing::info;
+
+/// Resolves when the process is sent a SIGTERM or a ctrl-c. Pass this to hyper's `with_graceful_shutdown` so that
+/// the server stops accepting connections and finishes the in-flight requests, then call
+/// [`crate::core::CoreWriter::shutdown`] to drain and flush the trackers.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut sigterm = signal(SignalKind::terminate()).expect("Unable to install the SIGTERM handler");
+        tokio::select! {
+            _ = sigterm.recv() => info!("Recieved SIGTERM, shutting down"),
+            _ = tokio::signal::ctrl_c() => info!("Recieved ctrl-c, shutting down"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.expect("Unable to install the ctrl-c handler");
+        info!("Recieved ctrl-c, shutting down");
+    }
+}
@@ -3,9 +3,17 @@
 //! # A server for Goko
 //! 
 //! 
-//! See [`GokoRequest`] for documentation of how to query the HTTP server. 
-//mod client;
-//pub use client::*;
+//! See [`GokoRequest`] for documentation of how to query the HTTP server. The same queries are served over gRPC
+//! by [`grpc::GokoGrpc`].
+//!
+//! Logging goes through `tracing`. Each HTTP request runs in a `request` span with a `request_id`, its `status`
+//! and `latency_us`, and the tree build has its own spans. Install whichever subscriber you like, the examples use
+//! `tracing_subscriber::fmt`.
+//!
+//! `GET /metrics` serves the request counts and latencies by request type, the size of the tree and the KL
+//! divergence of every tracker window in Prometheus' text format, see [`core::Metrics`].
+pub mod client;
+pub mod config;
 pub mod parsers;
 pub mod errors;
 
@@ -15,4 +23,5 @@ pub use api::GokoResponse;
 pub use parsers::PointParser;
 
 pub mod http;
+pub mod grpc;
 pub mod core;
\ No newline at end of file
@@ -13,6 +13,7 @@ use std::marker::PhantomData;
 use serde::Serialize;
 use hyper::body::HttpBody;
 use pin_project::pin_project;
+use crate::api::BulkPoints;
 
 mod msgpack_dense;
 pub use msgpack_dense::MsgPackDense;
@@ -20,10 +21,15 @@ pub use msgpack_dense::MsgPackDense;
 pub trait PointParser: Send + 'static {
     type Point: Serialize + Send + Sync + Debug + 'static;
     fn parse(body_buffer: &[u8], scratch_buffer: &mut Vec<u8>, request: &Request<Body>) -> Result<Self::Point, GokoClientError>;
+    /// Parses the body of a bulk request, either a list of points or a list of indexes of points in the tree.
+    fn parse_bulk(body_buffer: &[u8], scratch_buffer: &mut Vec<u8>, request: &Request<Body>) -> Result<BulkPoints<Self::Point>, GokoClientError>;
 }
 
+type ParseFn<T> = fn(&[u8], &mut Vec<u8>, &Request<Body>) -> Result<T, GokoClientError>;
+
 #[pin_project]
 pub(crate) struct PointBuffer<P: PointParser> {
+    max_body_bytes: usize,
     body_buffer: Vec<u8>,
     point_buffer: Vec<u8>,
     request: Request<Body>,
@@ -31,8 +37,10 @@ pub(crate) struct PointBuffer<P: PointParser> {
 }
 
 impl<P: PointParser> PointBuffer<P> {
-    pub(crate) fn new() -> Self {
+    /// A buffer that refuses bodies larger than `max_body_bytes`.
+    pub(crate) fn new(max_body_bytes: usize) -> Self {
         PointBuffer {
+            max_body_bytes,
             body_buffer: Vec::with_capacity(8*1024),
             point_buffer: Vec::with_capacity(8*1024),
             request: Request::default(),
@@ -45,8 +53,15 @@ impl<P: PointParser> PointBuffer<P> {
         self.point_buffer.clear();
     }
 
-    pub(crate) fn poll_point(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<P::Point, GokoClientError>> {
+    pub(crate) fn poll_parse<T>(self: Pin<&mut Self>, cx: &mut Context, parse: ParseFn<T>) -> Poll<Result<T, GokoClientError>> {
         let this = self.project();
+        let max_body_bytes = *this.max_body_bytes;
+        // A body that says up front it's too large is turned away before we read any of it
+        if this.request.body().size_hint().lower() > max_body_bytes as u64 {
+            this.body_buffer.clear();
+            *this.request = Request::default();
+            return Poll::Ready(Err(GokoClientError::PayloadTooLarge(max_body_bytes)))
+        }
         let mut body = this.request.body_mut();
         loop {
             let new_bytes = match Pin::new(&mut body).poll_data(cx) {
@@ -56,6 +71,13 @@ impl<P: PointParser> PointBuffer<P> {
             if let Some(new_bytes) = new_bytes {
                 match new_bytes {
                     Ok(new_bytes) => {
+                        if this.body_buffer.len() + new_bytes.len() > max_body_bytes {
+                            // Dropping the body stops the read, hyper closes the connection rather than drain it
+                            this.body_buffer.clear();
+                            this.point_buffer.clear();
+                            *this.request = Request::default();
+                            return Poll::Ready(Err(GokoClientError::PayloadTooLarge(max_body_bytes)))
+                        }
                         this.body_buffer.extend_from_slice(&new_bytes);
                     }
                     Err(e) => {
@@ -73,7 +95,7 @@ impl<P: PointParser> PointBuffer<P> {
             }
 
             if body.is_end_stream() {
-                let point_res = P::parse(this.body_buffer, this.point_buffer, this.request);
+                let point_res = parse(this.body_buffer, this.point_buffer, this.request);
                 this.body_buffer.clear();
                 this.point_buffer.clear();
                 *this.request = Request::default();
@@ -82,27 +104,42 @@ impl<P: PointParser> PointBuffer<P> {
         }
     }
 
-    pub(crate) fn point(&mut self, req: Request<Body>) -> PointFuture<'_, P> 
+    pub(crate) fn point(&mut self, req: Request<Body>) -> PointFuture<'_, P, P::Point> 
+    where 
+    Self: Unpin + Sized,
+    {
+        self.switch(req);
+        PointFuture{
+            req: self,
+            parse: P::parse,
+        }
+    }
+
+    /// Reads the body of a bulk request, see [`PointParser::parse_bulk`].
+    pub(crate) fn points(&mut self, req: Request<Body>) -> PointFuture<'_, P, BulkPoints<P::Point>> 
     where 
     Self: Unpin + Sized,
     {
         self.switch(req);
         PointFuture{
             req: self,
+            parse: P::parse_bulk,
         }
     }
 }
 
 #[pin_project]
-/// Future that resolves to the next data chunk from `Body`
-pub(crate) struct PointFuture<'a, P: PointParser> { 
+/// Future that resolves to the parsed body
+pub(crate) struct PointFuture<'a, P: PointParser, T> { 
     req: &'a mut PointBuffer<P>,
+    parse: ParseFn<T>,
 }
 
-impl<'a, P: PointParser> Future for PointFuture<'a, P> {
-    type Output = Result<P::Point, GokoClientError>;
+impl<'a, P: PointParser, T> Future for PointFuture<'a, P, T> {
+    type Output = Result<T, GokoClientError>;
 
     fn poll(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
-        Pin::new(&mut *self.req).poll_point(ctx)
+        let parse = self.parse;
+        Pin::new(&mut *self.req).poll_parse(ctx, parse)
     }
 }
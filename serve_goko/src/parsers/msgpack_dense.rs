@@ -10,8 +10,9 @@ use flate2::read::{DeflateDecoder, ZlibDecoder};
 use rmp_serde;
 use std::io::Read;
 use crate::PointParser;
-use log::trace;
+use tracing::trace;
 use crate::errors::*;
+use crate::api::BulkPoints;
 
 pub trait ParserService: Send + Sync + 'static {
     type Point;
@@ -39,9 +40,9 @@ impl<R: Read> Read for Readers<R> {
 }
 
 
-impl PointParser for MsgPackDense {
-    type Point = Vec<f32>;
-    fn parse(body_buffer: &[u8], scratch_buffer: &mut Vec<u8>, request: &Request<Body>) -> Result<Self::Point, GokoClientError> {
+impl MsgPackDense {
+    /// Inflates the body into the scratch buffer, by the content type.
+    fn decompress(body_buffer: &[u8], scratch_buffer: &mut Vec<u8>, request: &Request<Body>) -> Result<(), GokoClientError> {
         scratch_buffer.clear();
         let mut reader = match request.headers().get(CONTENT_TYPE) {
             Some(typestr) => {
@@ -62,12 +63,29 @@ impl PointParser for MsgPackDense {
         };
         reader.read_to_end(scratch_buffer).map_err(|e| GokoClientError::parse(Box::new(e)))?;
         if scratch_buffer.len() > 0 {
-            let point: Vec<f32> =
-                rmp_serde::from_read_ref(scratch_buffer).map_err(|e| GokoClientError::Parse(Box::new(e)))?;
-            trace!("Initial Buffer len: {}, Scratch Buffer Len: {}, Final point lenght: {}", body_buffer.len(), scratch_buffer.len(), point.len());
-            Ok(point)
+            Ok(())
         } else {
             Err(GokoClientError::MissingBody)
         }
     }
-}
\ No newline at end of file
+}
+
+impl PointParser for MsgPackDense {
+    type Point = Vec<f32>;
+    fn parse(body_buffer: &[u8], scratch_buffer: &mut Vec<u8>, request: &Request<Body>) -> Result<Self::Point, GokoClientError> {
+        Self::decompress(body_buffer, scratch_buffer, request)?;
+        let point: Vec<f32> =
+            rmp_serde::from_read_ref(scratch_buffer).map_err(|e| GokoClientError::Parse(Box::new(e)))?;
+        trace!("Initial Buffer len: {}, Scratch Buffer Len: {}, Final point lenght: {}", body_buffer.len(), scratch_buffer.len(), point.len());
+        Ok(point)
+    }
+
+    /// A msgpack array of points, each an array of floats, or a msgpack array of point indexes.
+    fn parse_bulk(body_buffer: &[u8], scratch_buffer: &mut Vec<u8>, request: &Request<Body>) -> Result<BulkPoints<Self::Point>, GokoClientError> {
+        Self::decompress(body_buffer, scratch_buffer, request)?;
+        let points: BulkPoints<Vec<f32>> =
+            rmp_serde::from_read_ref(scratch_buffer).map_err(|e| GokoClientError::Parse(Box::new(e)))?;
+        trace!("Initial Buffer len: {}, Scratch Buffer Len: {}, Bulk point count: {}", body_buffer.len(), scratch_buffer.len(), points.len());
+        Ok(points)
+    }
+}
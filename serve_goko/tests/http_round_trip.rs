@@ -0,0 +1,1413 @@
+//! End to end tests of the HTTP server. Each test builds a small tree, serves it on an ephemeral port and talks to
+//! it with the client.
+//!
+//! The harness is generic over the body parser, so a new parser only needs a `TestServer::start::<NewParser>()`
+//! next to the msgpack ones.
+
+use goko::errors::GokoError;
+use goko::plugins::discrete::prelude::{DirichletBaseline, GokoDirichlet};
+use goko::{CoverTreeBuilder, CoverTreeWriter};
+use hyper::Server;
+use pointcloud::summaries::CategorySummary;
+use pointcloud::*;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serve_goko::api::*;
+use serve_goko::client::{GokoClient, GokoReplicas};
+use serve_goko::config::{BaselineConfig, ServerConfig};
+use serve_goko::core::*;
+use serve_goko::errors::GokoClientError;
+use serve_goko::grpc::proto as grpc;
+use serve_goko::grpc::proto::goko_client::GokoClient as GrpcClient;
+use serve_goko::grpc::GokoGrpc;
+use serve_goko::http::*;
+use serve_goko::parsers::{MsgPackDense, PointParser};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+const DIM: usize = 5;
+const COUNT: usize = 200;
+
+fn build_tree() -> CoverTreeWriter<DefaultLabeledCloud<L2>> {
+    build_tree_with_offset(0.0)
+}
+
+/// The test tree with every coordinate of its points moved by `offset`.
+fn build_tree_with_offset(offset: f32) -> CoverTreeWriter<DefaultLabeledCloud<L2>> {
+    build_tree_on(build_cloud(offset))
+}
+
+/// The points of the test tree with every coordinate moved by `offset`.
+fn build_cloud(offset: f32) -> DefaultLabeledCloud<L2> {
+    let mut rng = SmallRng::seed_from_u64(0);
+    let data: Vec<f32> = (0..COUNT * DIM)
+        .map(|_| rng.gen::<f32>() + offset)
+        .collect();
+    let labels: Vec<i64> = (0..COUNT).map(|i| (i % 3) as i64).collect();
+    DefaultLabeledCloud::<L2>::new_simple(data, DIM, labels)
+}
+
+fn build_tree_on<D: PointCloud>(point_cloud: D) -> CoverTreeWriter<D> {
+    let mut builder = CoverTreeBuilder::new();
+    builder
+        .set_scale_base(2.0)
+        .set_leaf_cutoff(5)
+        .set_min_res_index(-10)
+        .set_use_singletons(true)
+        .set_verbosity(0)
+        .set_rng_seed(0);
+    let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
+    tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+    tree.generate_summaries();
+    tree
+}
+
+/// The tree loader of the test servers, every registered tree is a copy of the main one. It refuses paths with a
+/// `%` in them, they're the ones the server didn't decode.
+fn load_tree(path: &Path) -> Result<CoverTreeWriter<DefaultLabeledCloud<L2>>, GokoError> {
+    if path.to_string_lossy().contains('%') {
+        return Err(GokoError::IoError(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{:?} wasn't decoded", path),
+        )));
+    }
+    Ok(build_tree())
+}
+
+/// The baseline attached to the default tracker of a test server.
+#[derive(Clone, Copy, PartialEq)]
+enum TestBaseline {
+    None,
+    /// Trained on the tree before the server starts
+    Trained,
+    /// Recomputed in the background with [`CoreWriter::schedule_baselines`]
+    Scheduled,
+}
+
+/// A server running in the background of the test's runtime. Drop it or call `stop` when you're done.
+struct TestServer {
+    client: GokoClient,
+    rejections: Arc<RejectionMetrics>,
+    shutdown: Option<oneshot::Sender<()>>,
+    handle: JoinHandle<()>,
+}
+
+impl TestServer {
+    async fn start<P>() -> TestServer
+    where
+        P: PointParser + 'static,
+        P::Point: Deref<Target = [f32]> + Send + Sync + 'static,
+    {
+        TestServer::start_with_limits::<P>(QueryLimits::default()).await
+    }
+
+    async fn start_with_limits<P>(limits: QueryLimits) -> TestServer
+    where
+        P: PointParser + 'static,
+        P::Point: Deref<Target = [f32]> + Send + Sync + 'static,
+    {
+        TestServer::start_with::<P>(limits, TestBaseline::None).await
+    }
+
+    /// Starts a server, with the baseline attached to the default tracker.
+    async fn start_with<P>(limits: QueryLimits, baseline: TestBaseline) -> TestServer
+    where
+        P: PointParser + 'static,
+        P::Point: Deref<Target = [f32]> + Send + Sync + 'static,
+    {
+        TestServer::start_with_tree::<P>(build_tree(), limits, baseline).await
+    }
+
+    /// Starts a server for the tree.
+    async fn start_with_tree<P>(
+        tree: CoverTreeWriter<DefaultLabeledCloud<L2>>,
+        limits: QueryLimits,
+        baseline: TestBaseline,
+    ) -> TestServer
+    where
+        P: PointParser + 'static,
+        P::Point: Deref<Target = [f32]> + Send + Sync + 'static,
+    {
+        let loader: TreeLoader<DefaultLabeledCloud<L2>> = Arc::new(load_tree);
+        TestServer::start_with_loader::<P, _>(tree, Some(loader), limits, baseline).await
+    }
+
+    /// Starts a server for a tree on any cloud, registered trees are loaded with the loader.
+    async fn start_with_loader<P, D>(
+        tree: CoverTreeWriter<D>,
+        loader: Option<TreeLoader<D>>,
+        limits: QueryLimits,
+        baseline: TestBaseline,
+    ) -> TestServer
+    where
+        P: PointParser + 'static,
+        P::Point: Deref<Target = [f32]> + Send + Sync + 'static,
+        D: PointCloud<Point = [f32]>,
+    {
+        let trained = if baseline == TestBaseline::Trained {
+            let mut trainer = DirichletBaseline::default();
+            trainer.set_sequence_len(10);
+            trainer.set_sample_rate(1);
+            Some(trainer.train(tree.reader()).unwrap())
+        } else {
+            None
+        };
+        let mut core = CoreWriter::new(tree);
+        if let Some(loader) = loader {
+            core.set_tree_loader(loader);
+        }
+        let core = Arc::new(core);
+        core.add_trackers(&[10]).await.unwrap();
+        if let Some(trained) = trained {
+            core.set_baseline(trained).await.unwrap();
+        }
+        if baseline == TestBaseline::Scheduled {
+            core.schedule_baselines(BaselineConfig {
+                interval_secs: 3600,
+                sequence_len: 10,
+                num_sequences: 2,
+                sample_rate: 1,
+            });
+        }
+        let goko_server = MakeGokoHttp::<_, P>::new(Arc::clone(&core)).with_limits(limits);
+        let rejections = goko_server.rejections();
+
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(goko_server);
+        let address = server.local_addr();
+        let server = server.with_graceful_shutdown(async {
+            shutdown_rx.await.ok();
+        });
+        let handle = tokio::spawn(async move {
+            server.await.unwrap();
+            core.shutdown().await.unwrap();
+        });
+
+        TestServer {
+            client: GokoClient::new(&format!("http://{}", address)),
+            rejections,
+            shutdown: Some(shutdown),
+            handle,
+        }
+    }
+
+    async fn stop(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+        (&mut self.handle).await.unwrap();
+    }
+}
+
+fn query_point() -> Vec<f32> {
+    vec![0.5; DIM]
+}
+
+async fn knn_round_trip(server: &TestServer) {
+    let knn = server.client.knn(&query_point(), 5).await.unwrap();
+    assert_eq!(knn.knn.len(), 5);
+    assert!(knn.knn.windows(2).all(|w| w[0].distance <= w[1].distance));
+    assert!(knn.knn.iter().all(|n| n.name.is_some()));
+
+    let by_name = server
+        .client
+        .knn_by_name(knn.knn[0].name.as_ref().unwrap(), 1)
+        .await
+        .unwrap();
+    assert_eq!(by_name.knn[0].distance, 0.0);
+}
+
+async fn path_round_trip(server: &TestServer) {
+    let path = server
+        .client
+        .path::<CategorySummary>(&query_point())
+        .await
+        .unwrap();
+    assert!(!path.path.is_empty());
+    let root = &path.path[0];
+    assert_eq!(root.label_summary.as_ref().map(|s| s.count()), Some(COUNT));
+}
+
+async fn tracking_round_trip(server: &TestServer) {
+    for _ in 0..3 {
+        match server
+            .client
+            .track_point(&query_point(), None)
+            .await
+            .unwrap()
+        {
+            TrackingResponse::TrackPath(resp) => assert!(resp.success),
+            _ => panic!("Expected a TrackPath response"),
+        }
+    }
+    match server.client.tracker_stats(10, None).await.unwrap() {
+        TrackingResponse::CurrentStats(stats) => {
+            assert_eq!(stats.sequence_len, 3);
+            assert!(stats.kl_div >= 0.0);
+            let fit = stats
+                .goodness_of_fit
+                .expect("the tree's priors should be attached");
+            assert_eq!(fit.sequence_len, 3);
+            let chi_squared_p_value = fit.chi_squared_p_value.unwrap();
+            let g_test_p_value = fit.g_test_p_value.unwrap();
+            assert!((0.0..=1.0).contains(&chi_squared_p_value));
+            assert!((0.0..=1.0).contains(&g_test_p_value));
+        }
+        _ => panic!("Expected a CurrentStats response"),
+    }
+    match server.client.reset_tracker(Some(10), None).await.unwrap() {
+        TrackingResponse::ResetTracker(_) => {}
+        _ => panic!("Expected a ResetTracker response"),
+    }
+    match server.client.tracker_stats(10, None).await.unwrap() {
+        TrackingResponse::CurrentStats(stats) => assert_eq!(stats.sequence_len, 0),
+        _ => panic!("Expected a CurrentStats response"),
+    }
+}
+
+async fn snapshot_round_trip(server: &TestServer) {
+    server.client.add_tracker(20, Some("other")).await.unwrap();
+    for tracker_name in &[None, Some("other")] {
+        server
+            .client
+            .track_point(&query_point(), *tracker_name)
+            .await
+            .unwrap();
+    }
+    let snapshot = server.client.tracker_snapshot().await.unwrap();
+    assert_eq!(snapshot.default_tracker.len(), 1);
+    assert_eq!(snapshot.default_tracker[0].window_size, 10);
+    assert_eq!(snapshot.default_tracker[0].stats.sequence_len, 1);
+    let other = &snapshot.trackers["other"];
+    assert_eq!(other.len(), 1);
+    assert_eq!(other[0].window_size, 20);
+    assert_eq!(other[0].stats.sequence_len, 1);
+}
+
+#[tokio::test]
+async fn msgpack_parameters() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    let parameters = server.client.parameters().await.unwrap();
+    assert_eq!(parameters.leaf_cutoff, 5);
+    assert_eq!(parameters.min_res_index, -10);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn msgpack_tree_stats() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    let stats = server.client.tree_stats().await.unwrap();
+    assert_eq!(stats.point_count, COUNT);
+    assert_eq!(stats.leaf_count + stats.singleton_count, COUNT);
+    assert_eq!(
+        stats.layers.iter().map(|l| l.node_count).sum::<usize>(),
+        stats.node_count
+    );
+    assert_eq!(stats.parameters.leaf_cutoff, 5);
+    assert_eq!(stats.parameters.rng_seed, Some(0));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn msgpack_knn() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    knn_round_trip(&server).await;
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn msgpack_path() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    path_round_trip(&server).await;
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn msgpack_tracking() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    tracking_round_trip(&server).await;
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn msgpack_tracker_scores() {
+    let server =
+        TestServer::start_with::<MsgPackDense>(QueryLimits::default(), TestBaseline::Trained).await;
+    for _ in 0..3 {
+        server
+            .client
+            .track_point(&query_point(), None)
+            .await
+            .unwrap();
+    }
+    match server.client.tracker_stats(10, None).await.unwrap() {
+        TrackingResponse::CurrentStats(stats) => {
+            let scores = stats.scores.expect("the baseline should score the stats");
+            assert_eq!(scores.sequence_len, 3);
+            assert!(scores.moment1_nz.percentile >= 0.0 && scores.moment1_nz.percentile <= 100.0);
+        }
+        _ => panic!("Expected a CurrentStats response"),
+    }
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn msgpack_baseline_status() {
+    let server =
+        TestServer::start_with::<MsgPackDense>(QueryLimits::default(), TestBaseline::Trained).await;
+    let status = server.client.baseline_status().await.unwrap();
+    assert!(status.age_secs.is_some());
+    assert_eq!(status.interval_secs, None);
+    assert_eq!(status.recomputations, 0);
+    server.stop().await;
+
+    let server =
+        TestServer::start_with::<MsgPackDense>(QueryLimits::default(), TestBaseline::Scheduled)
+            .await;
+    let mut status = server.client.baseline_status().await.unwrap();
+    for _ in 0..100 {
+        if status.recomputations > 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        status = server.client.baseline_status().await.unwrap();
+    }
+    assert_eq!(status.recomputations, 1);
+    assert_eq!(status.interval_secs, Some(3600));
+    assert_eq!(status.num_sequences, 2);
+    assert!(!status.running);
+    assert!(status.last_error.is_none());
+    assert!(status.epoch.is_some());
+
+    server
+        .client
+        .track_point(&query_point(), None)
+        .await
+        .unwrap();
+    match server.client.tracker_stats(10, None).await.unwrap() {
+        TrackingResponse::CurrentStats(stats) => assert!(stats.scores.is_some()),
+        _ => panic!("Expected a CurrentStats response"),
+    }
+
+    // A tracker added after the recomputation starts with its baseline
+    server.client.add_tracker(10, Some("late")).await.unwrap();
+    server
+        .client
+        .track_point(&query_point(), Some("late"))
+        .await
+        .unwrap();
+    match server.client.tracker_stats(10, Some("late")).await.unwrap() {
+        TrackingResponse::CurrentStats(stats) => assert!(stats.scores.is_some()),
+        _ => panic!("Expected a CurrentStats response"),
+    }
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn msgpack_track_bulk() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    let points = BulkPoints::Points(vec![query_point(); 4]);
+    match server.client.track_bulk(&points, None).await.unwrap() {
+        TrackingResponse::TrackBulk(resp) => {
+            assert!(resp.success);
+            assert_eq!(resp.tracked, 4);
+            assert_eq!(resp.failed, 0);
+        }
+        _ => panic!("Expected a TrackBulk response"),
+    }
+    let indexes = BulkPoints::Indexes(vec![0, 1, 2, COUNT + 10]);
+    match server.client.track_bulk(&indexes, None).await.unwrap() {
+        TrackingResponse::TrackBulk(resp) => {
+            assert_eq!(resp.tracked, 3);
+            assert_eq!(resp.failed, 1);
+        }
+        _ => panic!("Expected a TrackBulk response"),
+    }
+    match server.client.tracker_stats(10, None).await.unwrap() {
+        TrackingResponse::CurrentStats(stats) => assert_eq!(stats.sequence_len, 7),
+        _ => panic!("Expected a CurrentStats response"),
+    }
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn oversized_bulk_is_rejected() {
+    let limits = QueryLimits {
+        max_bulk_points: 3,
+        ..QueryLimits::default()
+    };
+    let server = TestServer::start_with_limits::<MsgPackDense>(limits).await;
+    let points = BulkPoints::Points(vec![query_point(); 4]);
+    match server.client.track_bulk(&points, None).await {
+        Err(GokoClientError::ServerError(status, _)) => assert_eq!(status, 422),
+        _ => panic!("Expected the batch to be rejected"),
+    }
+    assert_eq!(server.rejections.counts().limit_exceeded, 1);
+    let points = BulkPoints::Points(vec![query_point(); 3]);
+    match server.client.track_bulk(&points, None).await.unwrap() {
+        TrackingResponse::TrackBulk(resp) => assert_eq!(resp.tracked, 3),
+        _ => panic!("Expected a TrackBulk response"),
+    }
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn msgpack_unique_visitors() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    server
+        .client
+        .add_unique_visitor_tracker(10, Some("visits"))
+        .await
+        .unwrap();
+    for query_id in &["a", "b", "a", "b", "a"] {
+        server
+            .client
+            .track_point_with_id(&query_point(), query_id, Some("visits"))
+            .await
+            .unwrap();
+    }
+    match server
+        .client
+        .tracker_stats(10, Some("visits"))
+        .await
+        .unwrap()
+    {
+        TrackingResponse::CurrentStats(stats) => {
+            let visitors = stats.visitors.expect("the tracker should count visitors");
+            assert!(!visitors.is_empty());
+            for node in visitors {
+                assert_eq!(node.count, 5);
+                assert!((node.distinct - 2.0).abs() < 0.1);
+            }
+        }
+        _ => panic!("Expected a CurrentStats response"),
+    }
+    match server.client.tracker_stats(10, None).await.unwrap() {
+        TrackingResponse::CurrentStats(stats) => assert!(stats.visitors.is_none()),
+        _ => panic!("Expected a CurrentStats response"),
+    }
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn msgpack_top_drift() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    for _ in 0..3 {
+        server
+            .client
+            .track_point(&query_point(), None)
+            .await
+            .unwrap();
+    }
+    let nodes = match server.client.top_drift(2, None, None).await.unwrap() {
+        TrackingResponse::TopDrift(response) => response.nodes,
+        _ => panic!("Expected a TopDrift response"),
+    };
+    assert!(!nodes.is_empty());
+    assert!(nodes.len() <= 2);
+    assert!(nodes.windows(2).all(|w| w[0].kl_div >= w[1].kl_div));
+    assert!(nodes.iter().all(|n| n.kl_div > 0.0 && n.coverage > 0));
+
+    // Later points are folded into the divergences as they're tracked
+    server
+        .client
+        .track_point(&query_point(), None)
+        .await
+        .unwrap();
+    match server.client.top_drift(2, Some(10), None).await.unwrap() {
+        TrackingResponse::TopDrift(response) => {
+            assert!(response.nodes[0].kl_div > nodes[0].kl_div);
+        }
+        _ => panic!("Expected a TopDrift response"),
+    }
+    match server.client.top_drift(2, Some(3), None).await.unwrap() {
+        TrackingResponse::Unknown(_, window_size) => assert_eq!(window_size, Some(3)),
+        _ => panic!("Expected an Unknown response"),
+    }
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn msgpack_drift_rollup() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    for _ in 0..3 {
+        server
+            .client
+            .track_point(&query_point(), None)
+            .await
+            .unwrap();
+    }
+    let path = server
+        .client
+        .path::<CategorySummary>(&query_point())
+        .await
+        .unwrap()
+        .path;
+    let total: f64 = match server.client.top_drift(1000, None, None).await.unwrap() {
+        TrackingResponse::TopDrift(response) => response.nodes.iter().map(|n| n.kl_div).sum(),
+        _ => panic!("Expected a TopDrift response"),
+    };
+
+    // Everything rolls up into the root at the root's scale
+    match server
+        .client
+        .drift_rollup(path[0].layer, None, None)
+        .await
+        .unwrap()
+    {
+        TrackingResponse::DriftRollup(response) => {
+            assert_eq!(response.scale_index, path[0].layer);
+            assert_eq!(response.nodes.len(), 1);
+            assert!((response.nodes[0].kl_div - total).abs() < 1.0e-6);
+        }
+        _ => panic!("Expected a DriftRollup response"),
+    }
+    match server
+        .client
+        .drift_rollup(path[1].layer, Some(10), None)
+        .await
+        .unwrap()
+    {
+        TrackingResponse::DriftRollup(response) => {
+            let rolled_up: f64 = response.nodes.iter().map(|n| n.kl_div).sum();
+            assert!((rolled_up - total).abs() < 1.0e-6);
+            assert!(response
+                .nodes
+                .windows(2)
+                .all(|w| w[0].kl_div >= w[1].kl_div));
+        }
+        _ => panic!("Expected a DriftRollup response"),
+    }
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn msgpack_segment_stats() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    for segment in &["gold", "gold", "silver"] {
+        server
+            .client
+            .track_point_in_segment(&query_point(), segment, None)
+            .await
+            .unwrap();
+    }
+    server
+        .client
+        .track_point(&query_point(), None)
+        .await
+        .unwrap();
+    match server.client.segment_stats(10, None, None).await.unwrap() {
+        TrackingResponse::SegmentStats(stats) => {
+            assert_eq!(stats.segments.len(), 2);
+            assert_eq!(stats.segments["gold"].sequence_len, 2);
+            assert_eq!(stats.segments["silver"].sequence_len, 1);
+        }
+        _ => panic!("Expected a SegmentStats response"),
+    }
+    match server
+        .client
+        .segment_stats(10, Some("gold"), None)
+        .await
+        .unwrap()
+    {
+        TrackingResponse::SegmentStats(stats) => {
+            assert_eq!(stats.segments.len(), 1);
+            assert!(stats.segments["gold"].nz_count > 0);
+        }
+        _ => panic!("Expected a SegmentStats response"),
+    }
+    match server.client.tracker_stats(10, None).await.unwrap() {
+        TrackingResponse::CurrentStats(stats) => assert_eq!(stats.sequence_len, 4),
+        _ => panic!("Expected a CurrentStats response"),
+    }
+
+    server.client.reset_tracker(None, None).await.unwrap();
+    match server.client.segment_stats(10, None, None).await.unwrap() {
+        TrackingResponse::SegmentStats(stats) => assert!(stats.segments.is_empty()),
+        _ => panic!("Expected a SegmentStats response"),
+    }
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn msgpack_tracker_snapshot() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    snapshot_round_trip(&server).await;
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn msgpack_latency_by_depth() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    path_round_trip(&server).await;
+    let latency = server.client.latency_by_depth().await.unwrap();
+    assert_eq!(latency.depths.len(), 1);
+    assert_eq!(latency.depths[0].count, 1);
+    assert!(latency.depths[0].depth > 0);
+
+    let hyper_client = hyper::Client::new();
+    let uri = format!("{}/metrics", server.client.base_uri())
+        .parse()
+        .unwrap();
+    let response = hyper_client.get(uri).await.unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let metrics = String::from_utf8(body.to_vec()).unwrap();
+    assert!(metrics.contains("goko_query_latency_seconds_count"));
+    assert!(metrics.contains("goko_rejected_requests_total"));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn metrics_count_requests() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    knn_round_trip(&server).await;
+    for _ in 0..3 {
+        server
+            .client
+            .track_point(&query_point(), None)
+            .await
+            .unwrap();
+    }
+    // The tracker gauges are the stats the workers last published, a snapshot publishes them right away
+    server.client.tracker_snapshot().await.unwrap();
+
+    let hyper_client = hyper::Client::new();
+    let uri = format!("{}/metrics", server.client.base_uri())
+        .parse()
+        .unwrap();
+    let response = hyper_client.get(uri).await.unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let metrics = String::from_utf8(body.to_vec()).unwrap();
+    let lines: Vec<&str> = metrics.lines().collect();
+    assert!(lines.contains(&"goko_requests_total{type=\"knn\"} 1"));
+    assert!(lines.contains(&"goko_requests_total{type=\"knn_by_name\"} 1"));
+    assert!(lines.contains(&"goko_requests_total{type=\"track_point\"} 3"));
+    assert!(lines.contains(&"goko_request_latency_seconds_count{type=\"track_point\"} 3"));
+    assert!(lines.contains(&"goko_tracker_sequence_len{tracker=\"\",window_size=\"10\"} 3"));
+    assert!(lines.contains(&format!("goko_tree_points {}", COUNT).as_str()));
+    assert!(metrics.contains("goko_tracker_kl_div{tracker=\"\",window_size=\"10\"}"));
+    assert!(metrics.contains("goko_tree_nodes "));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn trackers_survive_a_restart() {
+    let flush_path =
+        std::env::temp_dir().join(format!("goko_trackers_{}.json", std::process::id()));
+    let track_point = |tracker_name: Option<&str>| {
+        GokoRequest::Tracking(TrackingRequest {
+            tracker_name: tracker_name.map(|s| s.to_string()),
+            request: TrackingRequestChoice::TrackPoint(TrackPointRequest {
+                point: query_point(),
+                query_id: None,
+                segment: None,
+            }),
+        })
+    };
+
+    let mut core = CoreWriter::<_, Vec<f32>>::new(build_tree());
+    core.set_flush_path(&flush_path);
+    core.set_transient_trackers(vec!["scratch".to_string()]);
+    // The unlimited window doesn't keep its paths, it comes back from the flushed evidence
+    core.add_trackers(&[0, 10]).await.unwrap();
+    let mut reader = core.reader();
+    for tracker_name in &["other", "scratch"] {
+        let add = GokoRequest::Tracking(TrackingRequest {
+            tracker_name: Some(tracker_name.to_string()),
+            request: TrackingRequestChoice::AddTracker(AddTrackerRequest {
+                window_size: 20,
+                unique_visitors: false,
+            }),
+        });
+        reader.process(add).await.unwrap();
+    }
+    for _ in 0..3 {
+        for tracker_name in &[None, Some("other"), Some("scratch")] {
+            reader.process(track_point(*tracker_name)).await.unwrap();
+        }
+    }
+    let before = StatsSnapshotRequest {
+        weighting: Default::default(),
+    }
+    .process(&core.reader())
+    .await
+    .unwrap();
+    let flushed = core.shutdown().await.unwrap();
+    assert_eq!(flushed.trackers_flushed, 3);
+
+    let mut trainer = DirichletBaseline::default();
+    trainer.set_sequence_len(10);
+    trainer.set_sample_rate(1);
+    let tree = build_tree();
+    let baseline = trainer.train(tree.reader()).unwrap();
+    let mut core = CoreWriter::<_, Vec<f32>>::new(tree);
+    core.set_flush_path(&flush_path);
+    core.set_baseline(baseline).await.unwrap();
+    let restored = core.restore_trackers().await.unwrap();
+    assert_eq!(restored.trackers_restored, 3);
+    assert_eq!(restored.paths_restored, 6);
+    assert_eq!(restored.paths_skipped, 0);
+
+    let snapshot = StatsSnapshotRequest {
+        weighting: Default::default(),
+    }
+    .process(&core.reader())
+    .await
+    .unwrap();
+    assert_eq!(snapshot.default_tracker.len(), 2);
+    assert_eq!(snapshot.default_tracker[0].window_size, 0);
+    assert_eq!(snapshot.default_tracker[0].stats.sequence_len, 3);
+    assert!(
+        (snapshot.default_tracker[0].stats.kl_div - before.default_tracker[0].stats.kl_div).abs()
+            < 1.0e-9
+    );
+    assert_eq!(snapshot.default_tracker[1].window_size, 10);
+    assert_eq!(snapshot.default_tracker[1].stats.sequence_len, 3);
+    assert_eq!(snapshot.trackers.len(), 1);
+    assert_eq!(snapshot.trackers["other"][0].stats.sequence_len, 3);
+    // The named tracker is made by the restore, after the baseline was set
+    assert!(snapshot.trackers["other"][0].stats.scores.is_some());
+    std::fs::remove_file(&flush_path).ok();
+}
+
+#[tokio::test]
+async fn registered_trees_are_served() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    let registered = server
+        .client
+        .register_tree("small", "small.yml")
+        .await
+        .unwrap();
+    assert!(registered.success);
+    assert!(registered.node_count > 0);
+    assert!(
+        !server
+            .client
+            .register_tree("small", "small.yml")
+            .await
+            .unwrap()
+            .success
+    );
+    let trees = server.client.trees().await.unwrap();
+    assert_eq!(trees.trees.len(), 1);
+    assert_eq!(trees.trees[0].name, "small");
+    assert_eq!(trees.trees[0].point_count, COUNT);
+
+    let small = server.client.tree("small");
+    let knn = small.knn(&query_point(), 5).await.unwrap();
+    assert_eq!(knn.knn.len(), 5);
+    small.add_tracker(10, None).await.unwrap();
+    small.track_point(&query_point(), None).await.unwrap();
+    match small.tracker_stats(10, None).await.unwrap() {
+        TrackingResponse::CurrentStats(stats) => assert_eq!(stats.sequence_len, 1),
+        _ => panic!("Expected a CurrentStats response"),
+    }
+    // The main tree's trackers don't see the registered tree's points
+    match server.client.tracker_stats(10, None).await.unwrap() {
+        TrackingResponse::CurrentStats(stats) => assert_eq!(stats.sequence_len, 0),
+        _ => panic!("Expected a CurrentStats response"),
+    }
+
+    assert!(server
+        .client
+        .tree("missing")
+        .knn(&query_point(), 5)
+        .await
+        .is_err());
+    assert!(
+        server
+            .client
+            .unregister_tree("small")
+            .await
+            .unwrap()
+            .success
+    );
+    assert!(small.knn(&query_point(), 5).await.is_err());
+    assert!(server.client.trees().await.unwrap().trees.is_empty());
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn registered_paths_are_percent_encoded() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    let registered = server
+        .client
+        .register_tree("spaced", "trees/a tree & more=1.yml")
+        .await
+        .unwrap();
+    assert!(registered.success);
+    let reloaded = server
+        .client
+        .reload_tree(Some("spaced"), "trees/a tree & more=2.yml", false)
+        .await
+        .unwrap();
+    assert!(reloaded.success);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn named_points_are_served() {
+    let path = std::env::temp_dir().join(format!("goko_names_{}.csv", std::process::id()));
+    let mut names = "index,name\n".to_string();
+    for i in 0..COUNT {
+        names.push_str(&format!("{},point {} & 50%\n", i, i));
+    }
+    std::fs::write(&path, names).unwrap();
+    let names = pointcloud::loaders::open_name_csv(&path, 1).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let tree = build_tree_on(SimpleNamedCloud::new(build_cloud(0.0), names));
+    let server = TestServer::start_with_loader::<MsgPackDense, _>(
+        tree,
+        None,
+        QueryLimits::default(),
+        TestBaseline::None,
+    )
+    .await;
+    let knn = server.client.knn(&query_point(), 5).await.unwrap();
+    assert!(knn
+        .knn
+        .iter()
+        .all(|n| n.name.as_ref().unwrap().ends_with(" & 50%")));
+
+    let by_name = server.client.knn_by_name("point 7 & 50%", 1).await.unwrap();
+    assert_eq!(by_name.knn[0].distance, 0.0);
+    assert_eq!(by_name.knn[0].name.as_deref(), Some("point 7 & 50%"));
+    let path = server
+        .client
+        .known_path_by_name::<CategorySummary>("point 7 & 50%")
+        .await
+        .unwrap();
+    assert!(!path.path.is_empty());
+    assert!(server.client.knn_by_name("point 7", 1).await.is_err());
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn query_values_are_percent_encoded() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    let segment = "eu-west & 50%";
+    for _ in 0..2 {
+        server
+            .client
+            .track_point_in_segment(&query_point(), segment, None)
+            .await
+            .unwrap();
+    }
+    match server
+        .client
+        .segment_stats(10, Some(segment), None)
+        .await
+        .unwrap()
+    {
+        TrackingResponse::SegmentStats(stats) => {
+            assert_eq!(stats.segments.len(), 1);
+            assert_eq!(stats.segments[segment].sequence_len, 2);
+        }
+        _ => panic!("Expected a SegmentStats response"),
+    }
+
+    let tracker_name = "team-a/b & 50%";
+    server
+        .client
+        .add_tracker(10, Some(tracker_name))
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        server
+            .client
+            .track_point(&query_point(), Some(tracker_name))
+            .await
+            .unwrap();
+    }
+    match server
+        .client
+        .tracker_stats(10, Some(tracker_name))
+        .await
+        .unwrap()
+    {
+        TrackingResponse::CurrentStats(stats) => assert_eq!(stats.sequence_len, 3),
+        _ => panic!("Expected a CurrentStats response"),
+    }
+    match server.client.tracker_stats(10, None).await.unwrap() {
+        TrackingResponse::CurrentStats(stats) => assert_eq!(stats.sequence_len, 2),
+        _ => panic!("Expected a CurrentStats response"),
+    }
+
+    let registered = server
+        .client
+        .register_tree("a tree & more", "small.yml")
+        .await
+        .unwrap();
+    assert!(registered.success);
+    let trees = server.client.trees().await.unwrap();
+    assert_eq!(trees.trees[0].name, "a tree & more");
+    let knn = server
+        .client
+        .tree("a tree & more")
+        .knn(&query_point(), 5)
+        .await
+        .unwrap();
+    assert_eq!(knn.knn.len(), 5);
+    assert!(
+        server
+            .client
+            .unregister_tree("a tree & more")
+            .await
+            .unwrap()
+            .success
+    );
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn reloads_keep_the_trackers() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    for _ in 0..3 {
+        server
+            .client
+            .track_point(&query_point(), None)
+            .await
+            .unwrap();
+    }
+    let reloaded = server
+        .client
+        .reload_tree(None, "new.yml", true)
+        .await
+        .unwrap();
+    assert!(reloaded.success);
+    assert_eq!(reloaded.trackers.trackers_restored, 1);
+    // The loader builds the same tree, so every path is still in it
+    assert_eq!(reloaded.trackers.paths_restored, 3);
+    assert_eq!(reloaded.trackers.paths_skipped, 0);
+    match server.client.tracker_stats(10, None).await.unwrap() {
+        TrackingResponse::CurrentStats(stats) => assert_eq!(stats.sequence_len, 3),
+        _ => panic!("Expected a CurrentStats response"),
+    }
+    knn_round_trip(&server).await;
+
+    let reloaded = server
+        .client
+        .reload_tree(None, "new.yml", false)
+        .await
+        .unwrap();
+    assert_eq!(reloaded.trackers.trackers_restored, 1);
+    assert_eq!(reloaded.trackers.paths_restored, 0);
+    match server.client.tracker_stats(10, None).await.unwrap() {
+        TrackingResponse::CurrentStats(stats) => assert_eq!(stats.sequence_len, 0),
+        _ => panic!("Expected a CurrentStats response"),
+    }
+
+    let missing = server
+        .client
+        .reload_tree(Some("missing"), "new.yml", true)
+        .await
+        .unwrap();
+    assert!(!missing.success);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn reloads_swap_the_served_tree() {
+    let mut core = CoreWriter::<_, Vec<f32>>::new(build_tree());
+    let loader: TreeLoader<DefaultLabeledCloud<L2>> =
+        Arc::new(|_path: &Path| Ok(build_tree_with_offset(10.0)));
+    core.set_tree_loader(loader);
+    let old_reader = core.reader();
+    let root_distance = |response: GokoResponse<_>| match response {
+        GokoResponse::Path(path) => path.path[0].distance,
+        _ => panic!("Expected a Path response"),
+    };
+    let path = || {
+        GokoRequest::Path(PathRequest {
+            point: query_point(),
+        })
+    };
+    let before = root_distance(core.reader().process(path()).await.unwrap());
+    assert!(old_reader.replaced().is_none());
+
+    let reloaded = ReloadTreeRequest {
+        name: None,
+        path: "new.yml".into(),
+        migrate_trackers: false,
+    }
+    .process(&old_reader)
+    .await
+    .unwrap();
+    assert!(reloaded.success);
+    // The writer serves the new tree, and the readers made before the reload move over to it
+    let after = root_distance(core.reader().process(path()).await.unwrap());
+    assert!(after > before + 5.0);
+    assert!(core.reader().replaced().is_none());
+    let mut replaced = old_reader.replaced().unwrap();
+    assert_eq!(
+        root_distance(replaced.process(path()).await.unwrap()),
+        after
+    );
+}
+
+#[tokio::test]
+async fn reloads_keep_the_baseline() {
+    let server =
+        TestServer::start_with::<MsgPackDense>(QueryLimits::default(), TestBaseline::Trained).await;
+    let reloaded = server
+        .client
+        .reload_tree(None, "new.yml", true)
+        .await
+        .unwrap();
+    assert!(reloaded.success);
+    // The baseline status is shared with the reloaded tree, and its trackers are scored against the baseline
+    let status = server.client.baseline_status().await.unwrap();
+    assert!(status.age_secs.is_some());
+    server
+        .client
+        .track_point(&query_point(), None)
+        .await
+        .unwrap();
+    match server.client.tracker_stats(10, None).await.unwrap() {
+        TrackingResponse::CurrentStats(stats) => assert!(stats.scores.is_some()),
+        _ => panic!("Expected a CurrentStats response"),
+    }
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn oversized_body_is_rejected() {
+    let limits = QueryLimits {
+        max_body_bytes: 64,
+        ..QueryLimits::default()
+    };
+    let server = TestServer::start_with_limits::<MsgPackDense>(limits).await;
+    let hyper_client = hyper::Client::new();
+    let request = hyper::Request::post(format!("{}/track/point", server.client.base_uri()))
+        .body(hyper::Body::from(vec![0u8; 1024]))
+        .unwrap();
+    let response = hyper_client.request(request).await.unwrap();
+    assert_eq!(response.status().as_u16(), 413);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let rejection: PayloadTooLargeResponse = serde_json::from_slice(&body).unwrap();
+    assert_eq!(rejection.max_body_bytes, 64);
+    assert_eq!(server.rejections.counts().payload_too_large, 1);
+
+    // Small bodies still get through
+    knn_round_trip(&server).await;
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn query_budget_truncates_knn() {
+    let limits = QueryLimits {
+        max_query_nodes: Some(1),
+        ..QueryLimits::default()
+    };
+    let server = TestServer::start_with_limits::<MsgPackDense>(limits).await;
+    let knn = server.client.knn(&query_point(), 5).await.unwrap();
+    assert!(knn.truncated);
+    assert!(knn.knn.windows(2).all(|w| w[0].distance <= w[1].distance));
+
+    let full = TestServer::start::<MsgPackDense>().await;
+    let knn = full.client.knn(&query_point(), 5).await.unwrap();
+    assert!(!knn.truncated);
+    full.stop().await;
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn unknown_route_is_rejected() {
+    let server = TestServer::start::<MsgPackDense>().await;
+    let hyper_client = hyper::Client::new();
+    let uri = format!("{}/not_a_route", server.client.base_uri())
+        .parse()
+        .unwrap();
+    let response = hyper_client.get(uri).await.unwrap();
+    assert!(response.status().is_client_error());
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn replicas_answer_from_the_newest_tree() {
+    let old = TestServer::start::<MsgPackDense>().await;
+    let mut tree = build_tree_with_offset(10.0);
+    tree.refresh();
+    tree.refresh();
+    let new = TestServer::start_with_tree::<MsgPackDense>(
+        tree,
+        QueryLimits::default(),
+        TestBaseline::None,
+    )
+    .await;
+
+    // Nothing listens on the last one, it fails and the others still answer
+    let mut down = GokoClient::new("http://127.0.0.1:1");
+    down.set_retries(0);
+    let replicas = GokoReplicas::from_clients(vec![old.client.clone(), new.client.clone(), down]);
+    let newest = new.client.knn(&query_point(), 3).await.unwrap();
+    let oldest = old.client.knn(&query_point(), 3).await.unwrap();
+    let knn = replicas.knn(&query_point(), 3).await.unwrap();
+    let distances = |knn: &KnnResponse| knn.knn.iter().map(|n| n.distance).collect::<Vec<f32>>();
+    assert_eq!(distances(&knn), distances(&newest));
+    assert_ne!(distances(&knn), distances(&oldest));
+
+    let mut down = GokoClient::new("http://127.0.0.1:1");
+    down.set_retries(0);
+    assert!(GokoReplicas::from_clients(vec![down])
+        .knn(&query_point(), 3)
+        .await
+        .is_err());
+    old.stop().await;
+    new.stop().await;
+}
+
+/// A gRPC server running in the background of the test's runtime, with the same tree as the HTTP ones.
+struct TestGrpcServer {
+    client: GrpcClient<tonic::transport::Channel>,
+    shutdown: Option<oneshot::Sender<()>>,
+    handle: JoinHandle<()>,
+}
+
+impl TestGrpcServer {
+    async fn start(limits: QueryLimits) -> TestGrpcServer {
+        let core = Arc::new(CoreWriter::new(build_tree()));
+        core.add_trackers(&[10]).await.unwrap();
+        let service = GokoGrpc::new(Arc::clone(&core))
+            .with_limits(limits)
+            .into_service();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let incoming = Box::pin(futures::stream::unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        }));
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming_shutdown(incoming, async {
+                    shutdown_rx.await.ok();
+                })
+                .await
+                .unwrap();
+            core.shutdown().await.unwrap();
+        });
+
+        let client = GrpcClient::connect(format!("http://{}", address))
+            .await
+            .unwrap();
+        TestGrpcServer {
+            client,
+            shutdown: Some(shutdown),
+            handle,
+        }
+    }
+
+    async fn stop(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+        (&mut self.handle).await.unwrap();
+    }
+}
+
+fn grpc_knn_request(k: u32) -> grpc::KnnRequest {
+    grpc::KnnRequest {
+        point: query_point(),
+        k,
+        fields: Some(grpc::PointFields {
+            label: true,
+            metadata: false,
+        }),
+    }
+}
+
+#[tokio::test]
+async fn grpc_knn() {
+    let mut server = TestGrpcServer::start(QueryLimits::default()).await;
+    let knn = server
+        .client
+        .knn(grpc_knn_request(5))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(knn.knn.len(), 5);
+    assert!(knn.knn.windows(2).all(|w| w[0].distance <= w[1].distance));
+    assert!(knn.knn.iter().all(|n| !n.name.is_empty()));
+    assert!(knn.knn.iter().all(|n| !n.label_json.is_empty()));
+    assert!(knn.knn.iter().all(|n| n.metadata_json.is_empty()));
+
+    let http = TestServer::start::<MsgPackDense>().await;
+    let expected = http.client.knn(&query_point(), 5).await.unwrap();
+    let distances: Vec<f32> = knn.knn.iter().map(|n| n.distance).collect();
+    let expected: Vec<f32> = expected.knn.iter().map(|n| n.distance).collect();
+    assert_eq!(distances, expected);
+
+    // A k of 0 is the default k
+    let knn = server
+        .client
+        .knn(grpc_knn_request(0))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(knn.knn.len(), QueryLimits::default().default_k);
+    http.stop().await;
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn grpc_routing_knn() {
+    let mut server = TestGrpcServer::start(QueryLimits::default()).await;
+    let knn = server
+        .client
+        .routing_knn(grpc_knn_request(3))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!knn.knn.is_empty());
+    assert!(knn.knn.len() <= 3);
+    assert!(knn.knn.windows(2).all(|w| w[0].distance <= w[1].distance));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn grpc_path() {
+    let mut server = TestGrpcServer::start(QueryLimits::default()).await;
+    let path = server
+        .client
+        .path(grpc::PathRequest {
+            point: query_point(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!path.path.is_empty());
+    assert!(path.path.windows(2).all(|w| w[0].layer >= w[1].layer));
+    assert!(!path.path[0].label_summary_json.is_empty());
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn grpc_tracking() {
+    let mut server = TestGrpcServer::start(QueryLimits::default()).await;
+    let added = server
+        .client
+        .add_tracker(grpc::AddTrackerRequest {
+            tracker_name: "other".to_string(),
+            window_size: 20,
+            unique_visitors: false,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(added.success);
+
+    for tracker_name in &["", "other"] {
+        for _ in 0..3 {
+            let tracked = server
+                .client
+                .track_point(grpc::TrackPointRequest {
+                    tracker_name: tracker_name.to_string(),
+                    point: query_point(),
+                    query_id: 0,
+                    has_query_id: false,
+                    segment: String::new(),
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(tracked.success);
+        }
+    }
+    for (tracker_name, window_size) in &[("", 10), ("other", 20)] {
+        let stats = server
+            .client
+            .current_stats(grpc::CurrentStatsRequest {
+                tracker_name: tracker_name.to_string(),
+                window_size: *window_size,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(stats.sequence_len, 3);
+        assert!(stats.kl_div >= 0.0);
+    }
+
+    let missing = server
+        .client
+        .current_stats(grpc::CurrentStatsRequest {
+            tracker_name: "missing".to_string(),
+            window_size: 10,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(missing.code(), tonic::Code::NotFound);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn grpc_track_stream() {
+    let mut server = TestGrpcServer::start(QueryLimits::default()).await;
+    let points = (0..4).map(|_| grpc::TrackStreamRequest {
+        tracker_name: String::new(),
+        point: query_point(),
+        window_size: 10,
+    });
+    let mut stats = server
+        .client
+        .track_stream(futures::stream::iter(points))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut sequence_lens = Vec::new();
+    while let Some(stats) = stats.message().await.unwrap() {
+        sequence_lens.push(stats.sequence_len);
+    }
+    assert_eq!(sequence_lens, vec![1, 2, 3, 4]);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn grpc_knn_is_limited() {
+    let limits = QueryLimits {
+        max_k: 10,
+        ..QueryLimits::default()
+    };
+    let mut server = TestGrpcServer::start(limits).await;
+    let rejected = server.client.knn(grpc_knn_request(11)).await.unwrap_err();
+    assert_eq!(rejected.code(), tonic::Code::InvalidArgument);
+    let rejected = server
+        .client
+        .routing_knn(grpc_knn_request(11))
+        .await
+        .unwrap_err();
+    assert_eq!(rejected.code(), tonic::Code::InvalidArgument);
+    let knn = server
+        .client
+        .knn(grpc_knn_request(10))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(knn.knn.len(), 10);
+    server.stop().await;
+}
+
+#[test]
+fn config_rejects_unknown_keys() {
+    let path = std::env::temp_dir().join(format!("goko_config_{}.yaml", std::process::id()));
+    std::fs::write(&path, "address: 0.0.0.0:3030\nlimits:\n  max_k: 100\n").unwrap();
+    let config = ServerConfig::load(&path).unwrap();
+    assert_eq!(config.limits.max_k, 100);
+    for unknown in &[
+        "tls:\n  cert_path: cert.pem\n  key_path: key.pem\n",
+        "parser: MsgPackDense\n",
+        "limits:\n  max_kk: 100\n",
+    ] {
+        std::fs::write(&path, unknown).unwrap();
+        let error = ServerConfig::load(&path).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+    std::fs::remove_file(&path).unwrap();
+}